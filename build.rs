@@ -0,0 +1,15 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    // the gRPC service code is generated from the protobuf definitions
+    #[cfg(feature = "grpc")]
+    {
+        // a vendored protoc, so the build doesn't need one installed
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        let include = protoc_bin_vendored::include_path()?;
+        tonic_prost_build::configure().compile_protos(
+            &["proto/x328_events.proto".into()],
+            &["proto".into(), include],
+        )?;
+    }
+    Ok(())
+}
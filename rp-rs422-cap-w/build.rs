@@ -0,0 +1,32 @@
+//! Puts `memory.x` on the linker search path, and checks that the CYW43 firmware blobs, which
+//! aren't part of the repository, are in place.
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const BLOBS: [&str; 2] = [
+    "cyw43-firmware/43439A0.bin",
+    "cyw43-firmware/43439A0_clm.bin",
+];
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    for blob in BLOBS {
+        println!("cargo:rerun-if-changed={blob}");
+        if !Path::new(blob).exists() {
+            panic!("{blob} is missing, see cyw43-firmware/README.md");
+        }
+    }
+    println!("cargo:rerun-if-env-changed=WIFI_NETWORK");
+    println!("cargo:rerun-if-env-changed=WIFI_PASSWORD");
+    println!("cargo:rerun-if-env-changed=CAPTURE_UDP_DEST");
+}
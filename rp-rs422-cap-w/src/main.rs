@@ -0,0 +1,275 @@
+//! Capture firmware for the Pico W, which streams the framed capture records over Wi-Fi
+//! instead of USB, so the tap only needs power.
+//!
+//! UART 0 (RX on GP1) receives from the bus controller and UART 1 (RX on GP5) from the nodes,
+//! as on the Pico build, at 9600 baud 7E1. The records are the ones of the vendor USB
+//! interface, see `serial_pcap_core::framed`. The firmware serves them to one client at a
+//! time on TCP port 4222, read with `serial-pcap --wifi HOST:4222`, or when built with
+//! `CAPTURE_UDP_DEST=ADDR:PORT` sends them as UDP datagrams to that address, read with
+//! `serial-pcap --wifi-udp`.
+//!
+//! The network is joined with the `WIFI_NETWORK` and `WIFI_PASSWORD` given at build time,
+//! the address is from DHCP. The LED is lit while a TCP client is connected.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
+use embassy_executor::Spawner;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack, StackResources};
+use embassy_rp::bind_interrupts;
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::peripherals::{DMA_CH0, PIO0, UART0, UART1};
+use embassy_rp::pio::{self, Pio};
+use embassy_rp::uart::{self, BufferedUartRx};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use panic_halt as _;
+use rand_core::RngCore;
+use serial_pcap_core::framed::{RecordQueue, FLAG_BREAK, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP};
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+    UART0_IRQ => uart::BufferedInterruptHandler<UART0>;
+    UART1_IRQ => uart::BufferedInterruptHandler<UART1>;
+});
+
+const WIFI_NETWORK: &str = env!("WIFI_NETWORK");
+const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
+/// Send UDP datagrams to this "a.b.c.d:port" instead of serving TCP clients
+const UDP_DEST: Option<&str> = option_env!("CAPTURE_UDP_DEST");
+const TCP_PORT: u16 = 4222;
+
+const BAUD: u32 = 9600;
+/// Microseconds per UART frame: start bit, 7 data bits, parity and stop bit
+const FRAME_US: u32 = 10 * 1_000_000 / BAUD;
+/// The largest transfer to the host, also the UDP datagram size
+const PACKET_LEN: usize = 1024;
+const QUEUE_LEN: usize = 8192;
+
+/// The records waiting to be sent
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<RecordQueue<QUEUE_LEN>>> =
+    Mutex::new(RefCell::new(RecordQueue::new(PACKET_LEN)));
+/// Records were queued
+static QUEUED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let mut config = uart::Config::default();
+    config.baudrate = BAUD;
+    config.data_bits = uart::DataBits::DataBits7;
+    config.parity = uart::Parity::ParityEven;
+    config.stop_bits = uart::StopBits::STOP1;
+    static CTRL_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    static NODE_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    let ctrl = BufferedUartRx::new(p.UART0, Irqs, p.PIN_1, CTRL_BUF.init([0; 256]), config);
+    let node = BufferedUartRx::new(p.UART1, Irqs, p.PIN_5, NODE_BUF.init([0; 256]), config);
+    spawner.must_spawn(ctrl_task(ctrl));
+    spawner.must_spawn(node_task(node));
+
+    let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
+    let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
+    let pwr = Output::new(p.PIN_23, Level::Low);
+    let cs = Output::new(p.PIN_25, Level::High);
+    let mut pio = Pio::new(p.PIO0, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        DEFAULT_CLOCK_DIVIDER,
+        pio.irq0,
+        cs,
+        p.PIN_24,
+        p.PIN_29,
+        p.DMA_CH0,
+    );
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let (net_device, mut control, runner) =
+        cyw43::new(STATE.init(cyw43::State::new()), pwr, spi, fw).await;
+    spawner.must_spawn(cyw43_task(runner));
+    control.init(clm).await;
+    // the latency matters more than the power draw
+    control
+        .set_power_management(cyw43::PowerManagementMode::None)
+        .await;
+
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(
+        net_device,
+        embassy_net::Config::dhcpv4(Default::default()),
+        RESOURCES.init(StackResources::new()),
+        RoscRng.next_u64(),
+    );
+    spawner.must_spawn(net_task(runner));
+
+    while control
+        .join(
+            WIFI_NETWORK,
+            cyw43::JoinOptions::new(WIFI_PASSWORD.as_bytes()),
+        )
+        .await
+        .is_err()
+    {
+        Timer::after(Duration::from_secs(1)).await;
+    }
+    stack.wait_config_up().await;
+
+    match UDP_DEST.map(parse_endpoint) {
+        Some(Some(dest)) => send_datagrams(stack, dest).await,
+        Some(None) => panic!("CAPTURE_UDP_DEST isn't an \"a.b.c.d:port\" address"),
+        None => serve_clients(stack, &mut control).await,
+    }
+}
+
+#[embassy_executor::task]
+async fn cyw43_task(
+    runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn ctrl_task(rx: BufferedUartRx<'static, UART0>) -> ! {
+    capture(rx, FLAG_CTRL).await
+}
+
+#[embassy_executor::task]
+async fn node_task(rx: BufferedUartRx<'static, UART1>) -> ! {
+    capture(rx, 0).await
+}
+
+/// Queue the data received on one side of the bus, `side` is FLAG_CTRL for the controller
+async fn capture(mut rx: impl Read<Error = uart::Error>, side: u8) -> ! {
+    let mut buf = [0u8; 64];
+    // when the last byte was received
+    let mut last: Option<u32> = None;
+    loop {
+        let res = rx.read(&mut buf).await;
+        let now = Instant::now().as_micros() as u32;
+        match res {
+            Ok(len) => {
+                let first = now.wrapping_sub(FRAME_US * (len as u32).saturating_sub(1));
+                // more than two frame times without data starts a new chunk
+                let chunk = match last.replace(now) {
+                    Some(prev) if first.wrapping_sub(prev) <= 2 * FRAME_US => 0,
+                    _ => FLAG_CHUNK,
+                };
+                push(side | chunk, first, &buf[..len]);
+            }
+            Err(uart::Error::Overrun) => push(side | FLAG_DROP, now, &[]),
+            Err(uart::Error::Break) => push(side | FLAG_BREAK, now, &[]),
+            // a byte with a parity or framing error is dropped
+            Err(_) => {}
+        }
+    }
+}
+
+fn push(flags: u8, time: u32, data: &[u8]) {
+    QUEUE.lock(|q| q.borrow_mut().push(flags, time, data));
+    QUEUED.signal(());
+}
+
+/// Copy the next transfer of whole records from the queue into `buf`
+async fn next_packet(buf: &mut [u8; PACKET_LEN]) -> usize {
+    loop {
+        let len = QUEUE.lock(|q| {
+            let q = q.borrow();
+            let packet = q.packet();
+            buf[..packet.len()].copy_from_slice(packet);
+            packet.len()
+        });
+        if len > 0 {
+            return len;
+        }
+        QUEUED.wait().await;
+    }
+}
+
+/// Remove the records which have been sent
+fn consume(len: usize) {
+    QUEUE.lock(|q| q.borrow_mut().consume(len));
+}
+
+/// Accept one client at a time and stream the records to it until it disconnects
+async fn serve_clients(stack: Stack<'static>, control: &mut cyw43::Control<'static>) -> ! {
+    static RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    static TX_BUF: StaticCell<[u8; 4096]> = StaticCell::new();
+    static PACKET: StaticCell<[u8; PACKET_LEN]> = StaticCell::new();
+    let rx_buf = RX_BUF.init([0; 256]);
+    let tx_buf = TX_BUF.init([0; 4096]);
+    let packet = PACKET.init([0; PACKET_LEN]);
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buf[..], &mut tx_buf[..]);
+        // a client which doesn't read is dropped, so the next one can connect
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        socket.set_nodelay(true);
+        if socket.accept(TCP_PORT).await.is_err() {
+            continue;
+        }
+        // the records from before the client connected are stale
+        QUEUE.lock(|q| q.borrow_mut().clear());
+        control.gpio_set(0, true).await;
+        loop {
+            let len = next_packet(packet).await;
+            if socket.write_all(&packet[..len]).await.is_err() {
+                break;
+            }
+            consume(len);
+        }
+        socket.abort();
+        let _ = socket.flush().await;
+        control.gpio_set(0, false).await;
+    }
+}
+
+/// Send the records as UDP datagrams to `dest`, a lost datagram isn't resent
+async fn send_datagrams(stack: Stack<'static>, dest: IpEndpoint) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    static TX_BUF: StaticCell<[u8; 4 * PACKET_LEN]> = StaticCell::new();
+    static PACKET: StaticCell<[u8; PACKET_LEN]> = StaticCell::new();
+    let tx_buf = TX_BUF.init([0; 4 * PACKET_LEN]);
+    let packet = PACKET.init([0; PACKET_LEN]);
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, tx_buf);
+    socket.bind(0).unwrap();
+    loop {
+        let len = next_packet(packet).await;
+        // a failed send is like a lost datagram
+        let _ = socket.send_to(&packet[..len], dest).await;
+        consume(len);
+    }
+}
+
+/// Parse "a.b.c.d:port"
+fn parse_endpoint(addr: &str) -> Option<IpEndpoint> {
+    let (ip, port) = addr.split_once(':')?;
+    let mut octets = [0u8; 4];
+    let mut parts = ip.split('.');
+    for octet in &mut octets {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    let [a, b, c, d] = octets;
+    Some(IpEndpoint::new(
+        IpAddress::Ipv4(Ipv4Address::new(a, b, c, d)),
+        port.parse().ok()?,
+    ))
+}
@@ -0,0 +1,381 @@
+//! Firmware settings, stored in the last two sectors of the flash.
+//!
+//! Every save programs the settings into the next free 256 byte page, so a sector is only
+//! erased once every 16 saves. When a sector is full the other one is erased and used, and
+//! the newest record is found from its sequence number at startup. The previous record is
+//! kept until the new one is written, so an interrupted save falls back to the old settings.
+//! The records from before the node addresses were added are still read, with the default
+//! addresses. The framing byte was unused in the older records, which read as bit 7 framing.
+
+use core::fmt;
+
+use fugit::RateExtU32;
+use rp2040_hal::rom_data;
+use rp2040_hal::uart;
+use serial_pcap_core::nodes::NodeAddresses;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// How the muxed stream to the host is framed, see `serial_pcap_core::slip`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// The ctrl bytes have bit 7 set, for 7 bit data
+    Bit7,
+    /// SLIP frames, for 8 bit data
+    Slip,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+    /// RGB LED brightness
+    pub brightness: u8,
+    /// UART 0 receives from the bus controller and UART 1 from the nodes, instead of
+    /// the other way around
+    pub swap_channels: bool,
+    /// The X3.28 addresses of the mirrored nodes
+    pub nodes: NodeAddresses,
+    pub framing: Framing,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            baud: 9600,
+            data_bits: 7,
+            parity: Parity::Even,
+            stop_bits: 1,
+            brightness: 50,
+            swap_channels: false,
+            nodes: NodeAddresses::DEFAULT,
+            framing: Framing::Bit7,
+        }
+    }
+}
+
+impl Settings {
+    pub fn uart_config(&self) -> uart::UartConfig {
+        let data_bits = match self.data_bits {
+            5 => uart::DataBits::Five,
+            6 => uart::DataBits::Six,
+            7 => uart::DataBits::Seven,
+            _ => uart::DataBits::Eight,
+        };
+        let parity = match self.parity {
+            Parity::None => None,
+            Parity::Even => Some(uart::Parity::Even),
+            Parity::Odd => Some(uart::Parity::Odd),
+        };
+        let stop_bits = match self.stop_bits {
+            2 => uart::StopBits::Two,
+            _ => uart::StopBits::One,
+        };
+        uart::UartConfig::new(self.baud.Hz(), data_bits, parity, stop_bits)
+    }
+
+    /// Change one setting, from a `set <name> <value>` command.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        match name {
+            "baud" => {
+                self.baud = value.parse().map_err(|_| "invalid baud rate")?;
+                if !(300..=1_000_000).contains(&self.baud) {
+                    return Err("baud rate out of range");
+                }
+            }
+            "databits" => match value.parse() {
+                Ok(bits @ 5..=8) => self.data_bits = bits,
+                _ => return Err("data bits must be 5-8"),
+            },
+            "parity" => {
+                self.parity = match value {
+                    "none" => Parity::None,
+                    "even" => Parity::Even,
+                    "odd" => Parity::Odd,
+                    _ => return Err("parity must be none, even or odd"),
+                }
+            }
+            "stopbits" => match value.parse() {
+                Ok(bits @ 1..=2) => self.stop_bits = bits,
+                _ => return Err("stop bits must be 1 or 2"),
+            },
+            "brightness" => self.brightness = value.parse().map_err(|_| "invalid brightness")?,
+            "swap" => {
+                self.swap_channels = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err("swap must be on or off"),
+                }
+            }
+            "framing" => {
+                self.framing = match value {
+                    "bit7" => Framing::Bit7,
+                    "slip" => Framing::Slip,
+                    _ => return Err("framing must be bit7 or slip"),
+                }
+            }
+            _ if NodeAddresses::NAMES.contains(&name) => {
+                self.nodes.set(name, value).map_err(|e| e.as_str())?
+            }
+            _ => return Err("unknown setting"),
+        }
+        Ok(())
+    }
+
+    fn encode(&self, seq: u32) -> [u8; RECORD_LEN] {
+        let mut rec = [0u8; RECORD_LEN];
+        rec[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        rec[4..8].copy_from_slice(&seq.to_le_bytes());
+        rec[8..12].copy_from_slice(&self.baud.to_le_bytes());
+        rec[12] = self.data_bits;
+        rec[13] = self.parity as u8;
+        rec[14] = self.stop_bits;
+        rec[15] = self.brightness;
+        rec[16] = self.swap_channels as u8;
+        rec[17..21].copy_from_slice(&self.nodes.to_bytes());
+        rec[21] = self.framing as u8;
+        let sum = checksum(&rec[..RECORD_LEN - 4]);
+        rec[RECORD_LEN - 4..].copy_from_slice(&sum.to_le_bytes());
+        rec
+    }
+
+    /// Returns the settings and the sequence number of a valid record.
+    fn decode(rec: &[u8]) -> Option<(Self, u32)> {
+        let word = |pos: usize| u32::from_le_bytes(rec[pos..pos + 4].try_into().unwrap());
+        let len = match word(0) {
+            RECORD_MAGIC => RECORD_LEN,
+            RECORD_MAGIC_V1 => RECORD_LEN_V1,
+            _ => return None,
+        };
+        if word(len - 4) != checksum(&rec[..len - 4]) {
+            return None;
+        }
+        let nodes = match len {
+            RECORD_LEN => NodeAddresses::from_bytes(rec[17..21].try_into().unwrap())?,
+            _ => NodeAddresses::DEFAULT,
+        };
+        let framing = match (len, rec[21]) {
+            (RECORD_LEN, 1) => Framing::Slip,
+            (RECORD_LEN, 2..) => return None,
+            _ => Framing::Bit7,
+        };
+        let parity = match rec[13] {
+            0 => Parity::None,
+            1 => Parity::Even,
+            2 => Parity::Odd,
+            _ => return None,
+        };
+        let settings = Self {
+            baud: word(8),
+            data_bits: rec[12],
+            parity,
+            stop_bits: rec[14],
+            brightness: rec[15],
+            swap_channels: rec[16] != 0,
+            nodes,
+            framing,
+        };
+        Some((settings, word(4)))
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => "none",
+            Parity::Even => "even",
+            Parity::Odd => "odd",
+        };
+        let framing = match self.framing {
+            Framing::Bit7 => "bit7",
+            Framing::Slip => "slip",
+        };
+        write!(
+            f,
+            "baud {} databits {} parity {parity} stopbits {} brightness {} swap {} {} framing {framing}",
+            self.baud,
+            self.data_bits,
+            self.stop_bits,
+            self.brightness,
+            if self.swap_channels { "on" } else { "off" },
+            self.nodes
+        )
+    }
+}
+
+/// FNV-1a
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |h, &b| {
+        (h ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+const RECORD_MAGIC: u32 = 0x3247_4643;
+const RECORD_LEN: usize = 28;
+/// The records without the node addresses
+const RECORD_MAGIC_V1: u32 = 0x4746_4e43;
+const RECORD_LEN_V1: usize = 24;
+
+const XIP_BASE: u32 = 0x1000_0000;
+const FLASH_SIZE: u32 = 2048 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: u32 = 256;
+const PAGES_PER_SECTOR: u32 = SECTOR_SIZE / PAGE_SIZE;
+/// Flash offset of the two config sectors, which are excluded from the FLASH region in memory.x
+const CONFIG_OFFSET: u32 = FLASH_SIZE - 2 * SECTOR_SIZE;
+
+/// Location of the settings record in the flash
+#[derive(Copy, Clone)]
+struct Slot {
+    sector: u32,
+    page: u32,
+}
+
+impl Slot {
+    fn offset(self) -> u32 {
+        CONFIG_OFFSET + self.sector * SECTOR_SIZE + self.page * PAGE_SIZE
+    }
+
+    fn read(self) -> &'static [u8] {
+        let addr = (XIP_BASE + self.offset()) as *const u8;
+        // SAFETY: the config sectors are always mapped, and only modified by ConfigStore::save
+        unsafe { core::slice::from_raw_parts(addr, PAGE_SIZE as usize) }
+    }
+
+    fn is_erased(self) -> bool {
+        self.read().iter().all(|&b| b == 0xff)
+    }
+}
+
+pub struct ConfigStore {
+    /// The newest record
+    current: Option<(Slot, u32)>,
+}
+
+impl ConfigStore {
+    /// Find the newest stored settings, or the defaults if there are none.
+    pub fn load() -> (Self, Settings) {
+        let mut newest = None;
+        for sector in 0..2 {
+            for page in 0..PAGES_PER_SECTOR {
+                let slot = Slot { sector, page };
+                if let Some((settings, seq)) = Settings::decode(slot.read()) {
+                    if newest.is_none_or(|(_, _, s)| seq > s) {
+                        newest = Some((slot, settings, seq));
+                    }
+                }
+            }
+        }
+        let settings = newest.map(|(_, s, _)| s).unwrap_or_default();
+        let current = newest.map(|(slot, _, seq)| (slot, seq));
+        (Self { current }, settings)
+    }
+
+    /// Write the settings to the flash. The interrupts are disabled while the flash is
+    /// written, which takes up to 50 ms when a sector has to be erased.
+    pub fn save(&mut self, settings: &Settings) {
+        let (slot, seq) = match self.current {
+            Some((slot, seq)) => {
+                let next = Slot {
+                    page: slot.page + 1,
+                    ..slot
+                };
+                if next.page < PAGES_PER_SECTOR && next.is_erased() {
+                    (next, seq + 1)
+                } else {
+                    let other = Slot {
+                        sector: 1 - slot.sector,
+                        page: 0,
+                    };
+                    flash::erase_sector(other.offset());
+                    (other, seq + 1)
+                }
+            }
+            None => {
+                let first = Slot { sector: 0, page: 0 };
+                flash::erase_sector(first.offset());
+                (first, 0)
+            }
+        };
+        let mut page = [0xffu8; PAGE_SIZE as usize];
+        page[..RECORD_LEN].copy_from_slice(&settings.encode(seq));
+        flash::program_page(slot.offset(), &page);
+        self.current = Some((slot, seq));
+    }
+}
+
+mod flash {
+    use super::*;
+
+    /// The ROM functions are looked up before the XIP is disabled, since the lookup runs from flash
+    struct RomFunctions {
+        connect_internal_flash: unsafe extern "C" fn(),
+        flash_exit_xip: unsafe extern "C" fn(),
+        flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+        flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+        flash_flush_cache: unsafe extern "C" fn(),
+    }
+
+    impl RomFunctions {
+        fn lookup() -> Self {
+            Self {
+                connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+                flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+                flash_range_erase: rom_data::flash_range_erase::ptr(),
+                flash_range_program: rom_data::flash_range_program::ptr(),
+                flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+            }
+        }
+    }
+
+    enum Op {
+        Erase,
+        Program(*const u8),
+    }
+
+    pub fn erase_sector(offset: u32) {
+        run(Op::Erase, offset, SECTOR_SIZE as usize);
+    }
+
+    pub fn program_page(offset: u32, data: &[u8; PAGE_SIZE as usize]) {
+        run(Op::Program(data.as_ptr()), offset, data.len());
+    }
+
+    fn run(op: Op, offset: u32, len: usize) {
+        let rom = RomFunctions::lookup();
+        // The boot2 stage is copied to RAM, so it can restore the fast XIP mode afterwards
+        let mut boot2 = [0u32; 64];
+        // SAFETY: the boot2 stage is the first 256 bytes of the flash
+        unsafe {
+            core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), 64);
+        }
+        cortex_m::interrupt::free(|_| unsafe {
+            flash_op(&rom, &op, offset, len, boot2.as_ptr());
+        });
+    }
+
+    /// Everything the flash is being written must run from RAM, nothing can be read from
+    /// the flash until the XIP mode is restored.
+    #[inline(never)]
+    #[link_section = ".data.ram_func"]
+    unsafe fn flash_op(rom: &RomFunctions, op: &Op, offset: u32, len: usize, boot2: *const u32) {
+        (rom.connect_internal_flash)();
+        (rom.flash_exit_xip)();
+        match *op {
+            // 64 KiB block erase command, which isn't used for a single sector
+            Op::Erase => (rom.flash_range_erase)(offset, len, 1 << 16, 0xd8),
+            Op::Program(data) => (rom.flash_range_program)(offset, data, len),
+        }
+        (rom.flash_flush_cache)();
+        // Call boot2 to re-enter the XIP mode, with the thumb mode bit set
+        let boot2: extern "C" fn() = core::mem::transmute(boot2 as usize | 1);
+        boot2();
+    }
+}
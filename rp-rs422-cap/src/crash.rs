@@ -0,0 +1,105 @@
+use core::fmt;
+use core::panic::PanicInfo;
+
+use rp_pico::pac;
+
+// The watchdog scratch registers survive watchdog and software resets, but not power-on.
+// Scratch 4-7 are used by the bootrom, so the counters are kept in scratch 0-3.
+/// Stored in scratch 0 when the counters are valid
+const MAGIC: u32 = 0x5243_4150;
+/// Stored in scratch 0 by the panic handler, before waiting for the watchdog reset
+const PANIC_MAGIC: u32 = 0x5243_5041;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResetCause {
+    #[default]
+    PowerOn,
+    /// The watchdog wasn't fed, the firmware hung
+    Watchdog,
+    /// The watchdog reset the device after a panic
+    Panic,
+    /// Reset by the debugger or the bootrom, e.g. after flashing
+    Other,
+}
+
+impl fmt::Display for ResetCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResetCause::PowerOn => "power-on",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Panic => "panic",
+            ResetCause::Other => "other",
+        })
+    }
+}
+
+/// Reset cause and counters since the last power-on
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CrashCounters {
+    pub cause: ResetCause,
+    pub boots: u32,
+    pub watchdog_resets: u32,
+    pub panics: u32,
+}
+
+impl CrashCounters {
+    /// Determine the cause of the last reset and update the counters in the scratch registers.
+    /// Call this once at startup, before the watchdog is started.
+    pub fn update(wd: &pac::WATCHDOG) -> Self {
+        let magic = wd.scratch0.read().bits();
+        let mut counters = Self::default();
+        if magic == MAGIC || magic == PANIC_MAGIC {
+            counters.boots = wd.scratch1.read().bits();
+            counters.watchdog_resets = wd.scratch2.read().bits();
+            counters.panics = wd.scratch3.read().bits();
+            counters.cause = if magic == PANIC_MAGIC {
+                ResetCause::Panic
+            } else if wd.reason.read().timer().bit_is_set() {
+                counters.watchdog_resets = counters.watchdog_resets.wrapping_add(1);
+                ResetCause::Watchdog
+            } else {
+                ResetCause::Other
+            };
+        }
+        counters.boots = counters.boots.wrapping_add(1);
+
+        // SAFETY: the scratch registers are plain storage
+        unsafe {
+            wd.scratch1.write(|w| w.bits(counters.boots));
+            wd.scratch2.write(|w| w.bits(counters.watchdog_resets));
+            wd.scratch3.write(|w| w.bits(counters.panics));
+            wd.scratch0.write(|w| w.bits(MAGIC));
+        }
+        counters
+    }
+}
+
+impl fmt::Display for CrashCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Reset cause {}, boot {}, {} watchdog resets, {} panics",
+            self.cause, self.boots, self.watchdog_resets, self.panics
+        )
+    }
+}
+
+/// Count the panic and wait for the watchdog to reset the device.
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    #[cfg(feature = "defmt")]
+    defmt::error!("{}", defmt::Display2Format(_info));
+    // SAFETY: nothing else runs with the interrupts disabled
+    let wd = unsafe { &*pac::WATCHDOG::ptr() };
+    if wd.scratch0.read().bits() == MAGIC {
+        let panics = wd.scratch3.read().bits().wrapping_add(1);
+        unsafe {
+            wd.scratch3.write(|w| w.bits(panics));
+            wd.scratch0.write(|w| w.bits(PANIC_MAGIC));
+        }
+    }
+    loop {
+        cortex_m::asm::nop();
+    }
+}
@@ -0,0 +1,113 @@
+//! Diagnostic logging over RTT with defmt, enabled by the `defmt` feature. The log macros
+//! compile to nothing without the feature, so the capture data path is unaffected.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(feature = "defmt")]
+use defmt_rtt as _;
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "defmt")]
+        defmt::debug!($($arg)*);
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "defmt")]
+        defmt::info!($($arg)*);
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "defmt")]
+        defmt::warn!($($arg)*);
+    };
+}
+
+pub(crate) use {log_debug, log_info, log_warn};
+
+// microseconds since boot
+#[cfg(feature = "defmt")]
+defmt::timestamp!("{=u32:us}", {
+    // SAFETY: read only access to the free running timer
+    unsafe { (*rp_pico::pac::TIMER::ptr()).timerawl.read().bits() }
+});
+
+/// Receive counters for one side of the bus. The counters are only updated from the
+/// UART interrupt, thumbv6 has no atomic read-modify-write.
+/// A snapshot of the `UartStats` counters
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct UartCounts {
+    pub bytes: u32,
+    pub overruns: u32,
+    pub breaks: u32,
+    pub usb_drops: u32,
+}
+
+pub struct UartStats {
+    bytes: AtomicU32,
+    overruns: AtomicU32,
+    /// Break conditions on the line
+    breaks: AtomicU32,
+    /// Data which didn't fit in the USB buffer
+    usb_drops: AtomicU32,
+}
+
+impl UartStats {
+    pub const fn new() -> Self {
+        Self {
+            bytes: AtomicU32::new(0),
+            overruns: AtomicU32::new(0),
+            breaks: AtomicU32::new(0),
+            usb_drops: AtomicU32::new(0),
+        }
+    }
+
+    fn incr(counter: &AtomicU32, n: u32) {
+        counter.store(
+            counter.load(Ordering::Relaxed).wrapping_add(n),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn received(&self, len: usize) {
+        Self::incr(&self.bytes, len as u32);
+    }
+
+    pub fn overrun(&self) {
+        Self::incr(&self.overruns, 1);
+    }
+
+    pub fn line_break(&self) {
+        Self::incr(&self.breaks, 1);
+    }
+
+    pub fn usb_drop(&self) {
+        Self::incr(&self.usb_drops, 1);
+    }
+
+    pub fn counts(&self) -> UartCounts {
+        UartCounts {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            breaks: self.breaks.load(Ordering::Relaxed),
+            usb_drops: self.usb_drops.load(Ordering::Relaxed),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub fn log(&self, name: &str) {
+        let c = self.counts();
+        log_info!(
+            "{=str}: {=u32} bytes, {=u32} overruns, {=u32} breaks, {=u32} USB drops",
+            name,
+            c.bytes,
+            c.overruns,
+            c.breaks,
+            c.usb_drops
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use core::fmt;
 use core::fmt::Write;
 
 use arrayvec::ArrayString;
@@ -9,6 +10,8 @@ use embedded_graphics::primitives::{PrimitiveStyle, Rectangle, StyledDrawable};
 use embedded_graphics::text::{Alignment, Text};
 use enumflags2::BitFlags;
 
+use crate::crash::CrashCounters;
+use crate::diag::UartCounts;
 use rp_rs422_cap::picodisplay;
 use rp_rs422_cap::x328_bus::iobox::{CommandBit, InputBit, OutputBit};
 
@@ -23,6 +26,15 @@ pub enum Info {
     IoboxOutputs(BitFlags<OutputBit>),
     PolEncVal(i32),
     DeclEncVal(i32),
+    Resets(CrashCounters),
+    /// The bus load in the last second
+    BusLoad(BusLoad),
+    /// The receive counters of the node and controller UARTs
+    UartStats(UartCounts, UartCounts),
+    /// The X3.28 errors since boot
+    BusErrors(ErrorCounts),
+    /// The latest X3.28 error
+    LastError(BusError),
     #[default]
     END,
 }
@@ -34,6 +46,241 @@ impl Info {
         // https://doc.rust-lang.org/reference/items/enumerations.html#pointer-casting
         (unsafe { *(self as *const Self as *const u8) }) as usize
     }
+
+    /// How soon the info is expected to be updated. Info which is only set once doesn't age,
+    /// and neither do the counters of the device.
+    fn staleness(&self) -> Option<Staleness> {
+        let (aging, old) = match self {
+            // the encoders are polled all the time while the antenna is controlled
+            Info::PolEncVal(_) | Info::DeclEncVal(_) => (2, 5),
+            Info::IoboxCmd(_) | Info::IoboxInputs(_) | Info::IoboxOutputs(_) => (5, 30),
+            Info::PolarSpeedCmd(_) => (5, 30),
+            // the stow pins are only read now and then
+            Info::StowPressEast(_) | Info::StowPressWest(_) => (120, 600),
+            Info::LastError(_) => (10, 60),
+            Info::Resets(_)
+            | Info::BusLoad(_)
+            | Info::UartStats(..)
+            | Info::BusErrors(_)
+            | Info::END => return None,
+        };
+        Some(Staleness { aging, old })
+    }
+
+    /// The page, the first row and the number of rows of the info
+    fn layout(&self) -> Option<(Page, i32, i32)> {
+        let layout = match self {
+            Info::IoboxCmd(_) => (Page::Iobox, 0, 5),
+            Info::IoboxInputs(_) => (Page::Iobox, 5, 7),
+            Info::IoboxOutputs(_) => (Page::Iobox, 12, 5),
+            Info::PolEncVal(_) => (Page::Encoders, 0, 2),
+            Info::DeclEncVal(_) => (Page::Encoders, 2, 2),
+            Info::PolarSpeedCmd(_) => (Page::Encoders, 4, 1),
+            Info::StowPressEast(_) => (Page::Encoders, 5, 1),
+            Info::StowPressWest(_) => (Page::Encoders, 6, 1),
+            Info::UartStats(..) => (Page::Statistics, STATS_ROW, 4),
+            Info::Resets(_) => (Page::Errors, 0, 1),
+            Info::BusErrors(_) => (Page::Errors, 1, 3),
+            Info::LastError(_) => (Page::Errors, 5, 2),
+            Info::BusLoad(_) | Info::END => return None,
+        };
+        Some(layout)
+    }
+}
+
+/// The seconds after an update when an item is shown as aging, and as old
+#[derive(Copy, Clone)]
+struct Staleness {
+    aging: Age,
+    old: Age,
+}
+
+impl Staleness {
+    fn style(self, elapsed: Age) -> ItemStyle {
+        match elapsed {
+            e if e >= self.old => ItemStyle::Old,
+            e if e >= self.aging => ItemStyle::Aging,
+            _ => ItemStyle::Current,
+        }
+    }
+}
+
+/// An encoder value in hundredths of a degree, shown as e.g. "-12.07°"
+#[derive(Copy, Clone)]
+struct Degrees(i32);
+
+impl fmt::Display for Degrees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the sign is written separately, so it isn't lost for values between -1° and 0°
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{:02}\u{b0}", abs / 100, abs % 100)
+    }
+}
+
+/// The rate of change of an encoder value, over the seconds between updates
+#[derive(Copy, Clone, Default)]
+struct EncoderRate {
+    value: Option<(i32, Age)>,
+    /// Hundredths of a degree per second
+    rate: i32,
+}
+
+impl EncoderRate {
+    fn update(&mut self, value: i32, age: Age) {
+        match self.value {
+            Some((prev, prev_age)) if age > prev_age => {
+                self.rate = (value - prev) / (age - prev_age);
+                self.value = Some((value, age));
+            }
+            Some(_) => {}
+            None => self.value = Some((value, age)),
+        }
+    }
+}
+
+impl fmt::Display for EncoderRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.rate {
+            0 => return write!(f, "  = still"),
+            r if r > 0 => '^',
+            _ => 'v',
+        };
+        let sign = if self.rate > 0 { "+" } else { "" };
+        write!(f, "  {arrow} {sign}{}/s", Degrees(self.rate))
+    }
+}
+
+/// Counts the X3.28 errors by kind
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub timeouts: u16,
+    pub failed: u16,
+    pub unexpected: u16,
+}
+
+impl ErrorCounts {
+    pub const fn new() -> Self {
+        Self {
+            timeouts: 0,
+            failed: 0,
+            unexpected: 0,
+        }
+    }
+
+    pub fn count(&mut self, kind: ErrorKind) {
+        let counter = match kind {
+            ErrorKind::Timeout => &mut self.timeouts,
+            ErrorKind::Failed => &mut self.failed,
+            ErrorKind::Unexpected => &mut self.unexpected,
+        };
+        *counter = counter.saturating_add(1);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// A command without a response
+    Timeout,
+    /// A response with a bad checksum or an error from the node
+    Failed,
+    /// Data from a node which wasn't asked for
+    Unexpected,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BusError {
+    pub kind: ErrorKind,
+    /// The node address and parameter of the command
+    pub cmd: Option<(u8, i16)>,
+}
+
+/// The X3.28 traffic in one second
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct BusLoad {
+    /// Commands which got a response
+    pub transactions: u16,
+    /// Timeouts, unexpected transmissions and failed commands
+    pub errors: u16,
+}
+
+/// Bars in the bus load graph, one per second
+const GRAPH_LEN: usize = 45;
+const BAR_WIDTH: i32 = DISP_WIDTH / GRAPH_LEN as i32;
+
+/// The bus load of the last `GRAPH_LEN` seconds
+struct LoadHistory {
+    samples: [BusLoad; GRAPH_LEN],
+    /// The index of the oldest sample
+    next: usize,
+}
+
+impl Default for LoadHistory {
+    fn default() -> Self {
+        Self {
+            samples: [BusLoad::default(); GRAPH_LEN],
+            next: 0,
+        }
+    }
+}
+
+impl LoadHistory {
+    fn push(&mut self, load: BusLoad) {
+        self.samples[self.next] = load;
+        self.next = (self.next + 1) % GRAPH_LEN;
+    }
+
+    fn last(&self) -> BusLoad {
+        self.samples[(self.next + GRAPH_LEN - 1) % GRAPH_LEN]
+    }
+
+    /// The samples, oldest first
+    fn iter(&self) -> impl Iterator<Item = &BusLoad> {
+        self.samples[self.next..]
+            .iter()
+            .chain(&self.samples[..self.next])
+    }
+
+    /// The top of the graph, a multiple of 10 events per second above the highest bar
+    fn scale(&self) -> u16 {
+        let max = self
+            .iter()
+            .map(|s| s.transactions.saturating_add(s.errors))
+            .max()
+            .unwrap_or(0);
+        (max / 10 + 1).saturating_mul(10)
+    }
+}
+
+/// The first row of the UART counters on the statistics page, below the bus load numbers
+const STATS_ROW: i32 = 3;
+/// The first row of the bus load graph, below the UART counters
+const GRAPH_ROW: i32 = STATS_ROW + 4;
+
+/// What the display shows, cycled with the X and Y buttons
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub enum Page {
+    /// The IoBox command, input and output bits
+    #[default]
+    Iobox,
+    /// The encoder values, the polar speed command and the stow pin pressures
+    Encoders,
+    /// The bus load and the UART counters
+    Statistics,
+    /// The reset cause and the X3.28 errors
+    Errors,
+}
+
+impl Page {
+    const ALL: [Page; 4] = [Page::Iobox, Page::Encoders, Page::Statistics, Page::Errors];
+
+    fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    fn prev(self) -> Self {
+        Self::ALL[(self as usize + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
 }
 
 pub struct DisplayUpdates {
@@ -76,6 +323,10 @@ impl DisplayUpdates {
 pub struct BusDisplay {
     screen: picodisplay::Screen,
     on_screen: [ScreenItem; INFO_CNT],
+    page: Page,
+    load: LoadHistory,
+    pol_rate: EncoderRate,
+    decl_rate: EncoderRate,
 }
 
 pub type Age = i32;
@@ -126,6 +377,7 @@ impl ItemStyle {
 struct Row(i32);
 
 const DISP_WIDTH: i32 = 135;
+const DISP_HEIGHT: i32 = 240;
 
 impl Row {
     fn top_left(self, x: i32) -> Point {
@@ -142,13 +394,17 @@ impl Row {
 }
 
 impl BusDisplay {
-    const FONT: &'static MonoFont<'static> = &mono_font::ascii::FONT_7X14;
+    const FONT: &'static MonoFont<'static> = &mono_font::iso_8859_1::FONT_7X14;
     const ROW_HEIGHT: i32 = Self::FONT.character_size.height as i32;
 
     pub fn new(screen: picodisplay::Screen) -> Self {
         Self {
             screen,
             on_screen: Default::default(),
+            page: Page::default(),
+            load: LoadHistory::default(),
+            pol_rate: EncoderRate::default(),
+            decl_rate: EncoderRate::default(),
         }
     }
 
@@ -156,25 +412,54 @@ impl BusDisplay {
     pub fn redraw(&mut self) {
         self.screen.clear(RgbColor::BLUE).unwrap();
         for i in 0..self.on_screen.len() {
+            // the areas are from the last time the info was drawn, maybe on another page
+            self.on_screen[i].area = Rectangle::zero();
             self.draw_info(i)
         }
+        if self.page == Page::Statistics {
+            self.draw_load_graph();
+        }
+    }
+
+    pub fn next_page(&mut self) {
+        self.page = self.page.next();
+        self.redraw();
+    }
+
+    pub fn prev_page(&mut self) {
+        self.page = self.page.prev();
+        self.redraw();
     }
 
     pub fn check_age(&mut self, current_age: i32) {
         for idx in 0..self.on_screen.len() {
             let i = &mut self.on_screen[idx];
-            match (current_age - i.info_age, i.style) {
-                (-1, _) => continue,
-                (0, ItemStyle::Current) | (1, ItemStyle::Aging) | (_, ItemStyle::Old) => continue,
-                (0, _) => i.style = ItemStyle::Current,
-                (1, _) => i.style = ItemStyle::Aging,
-                (_, _) => i.style = ItemStyle::Old,
+            let Some(staleness) = i.info.staleness() else {
+                continue;
+            };
+            // the info is updated with the age of the next second
+            let style = staleness.style(current_age - i.info_age + 1);
+            if style == i.style {
+                continue;
             }
+            i.style = style;
             self.draw_info(idx);
         }
     }
 
     pub fn update_info(&mut self, info: Info, age: Age) {
+        if let Info::BusLoad(load) = info {
+            self.load.push(load);
+            if self.page == Page::Statistics {
+                self.draw_load_graph();
+            }
+            return;
+        }
+        match info {
+            Info::PolEncVal(v) => self.pol_rate.update(v, age),
+            Info::DeclEncVal(v) => self.decl_rate.update(v, age),
+            _ => {}
+        }
         let info_idx = info.discriminant();
         self.on_screen[info_idx].info = info;
         self.on_screen[info_idx].style = ItemStyle::Current;
@@ -183,49 +468,60 @@ impl BusDisplay {
     }
 
     fn draw_info(&mut self, info_idx: usize) {
-        let mut buf = ArrayString::<100>::new();
-        let mut row;
         let info = &self.on_screen[info_idx].info;
-
-        let _write_res = match info {
-            Info::StowPressEast(p) => {
-                row = 0;
-                write!(&mut buf, "Stow east {p}")
-            }
-            Info::StowPressWest(p) => {
-                row = 1;
-                write!(&mut buf, "Stow west {p}")
-            }
-            Info::PolarSpeedCmd(s) => {
-                row = 2;
-                write!(&mut buf, "Pol speed cmd {s}")
-            }
-            Info::DeclEncVal(v) => {
-                row = 3;
-                write!(&mut buf, "Decl enc: {}.{}", v / 100, v % 100)
-            }
-            Info::PolEncVal(v) => {
-                row = 4;
-                write!(&mut buf, "Pol enc: {}.{}", v / 100, v % 100)
-            }
-            Info::IoboxCmd(c) => {
-                row = 5;
-                c.iter().try_for_each(|b| writeln!(buf, "c {b:?}"))
-            }
-            Info::IoboxInputs(i) => {
-                row = 9;
-                i.iter().try_for_each(|b| writeln!(buf, "i {b:?}"))
-            }
-            Info::IoboxOutputs(o) => {
-                row = 15;
-                o.iter().try_for_each(|b| writeln!(buf, "o {b:?}"))
-            }
-            Info::END => return,
+        let Some((page, mut row, rows)) = info.layout() else {
+            return;
         };
+        // the info is drawn when its page is shown again
+        if page != self.page {
+            return;
+        }
+        let mut buf = ArrayString::<200>::new();
+
+        let _write_res =
+            match info {
+                Info::StowPressEast(p) => write!(&mut buf, "Stow east {p}"),
+                Info::StowPressWest(p) => write!(&mut buf, "Stow west {p}"),
+                Info::PolarSpeedCmd(s) => write!(&mut buf, "Pol speed cmd {s}"),
+                Info::PolEncVal(v) => write!(&mut buf, "Pol {}\n{}", Degrees(*v), self.pol_rate),
+                Info::DeclEncVal(v) => write!(&mut buf, "Dec {}\n{}", Degrees(*v), self.decl_rate),
+                Info::IoboxCmd(c) => c.iter().try_for_each(|b| writeln!(buf, "c {b:?}")),
+                Info::IoboxInputs(i) => i.iter().try_for_each(|b| writeln!(buf, "i {b:?}")),
+                Info::IoboxOutputs(o) => o.iter().try_for_each(|b| writeln!(buf, "o {b:?}")),
+                Info::Resets(c) => write!(
+                    &mut buf,
+                    "Rst {} wd {} pn {}",
+                    c.cause, c.watchdog_resets, c.panics
+                ),
+                Info::UartStats(node, ctrl) => [("node", node), ("ctrl", ctrl)]
+                    .iter()
+                    .try_for_each(|(name, c)| {
+                        writeln!(buf, "{name} rx {}", c.bytes)?;
+                        writeln!(
+                            buf,
+                            " ovr {} brk {} drp {}",
+                            c.overruns, c.breaks, c.usb_drops
+                        )
+                    }),
+                Info::BusErrors(e) => write!(
+                    &mut buf,
+                    "Timeouts {}\nFailed {}\nUnexpected {}",
+                    e.timeouts, e.failed, e.unexpected
+                ),
+                Info::LastError(e) => match e.cmd {
+                    Some((node, param)) => {
+                        write!(&mut buf, "Last {:?}\nnode {node} param {param}", e.kind)
+                    }
+                    None => write!(&mut buf, "Last {:?}", e.kind),
+                },
+                Info::BusLoad(_) | Info::END => return,
+            };
 
         let top_left = Row(row).top_left(0);
 
-        for line in buf.lines().filter(|l| !l.is_empty()) {
+        // the lines which don't fit in the rows of the info are left out
+        let lines = buf.lines().filter(|l| !l.is_empty()).take(rows as usize);
+        for line in lines {
             self.write_row(
                 Row(row),
                 line,
@@ -251,6 +547,54 @@ impl BusDisplay {
         }
     }
 
+    /// The numbers of the last second, and a bar per second with the errors in red on top of
+    /// the transactions in green
+    fn draw_load_graph(&mut self) {
+        let last = self.load.last();
+        let scale = self.load.scale();
+        let mut buf = ArrayString::<32>::new();
+        let _ = write!(buf, "{} trans/s", last.transactions);
+        self.write_row(Row(0), &buf, ItemStyle::CURR_STYLE);
+        buf.clear();
+        let _ = write!(buf, "{} errors/s", last.errors);
+        let style = match last.errors {
+            0 => ItemStyle::CURR_STYLE,
+            _ => ItemStyle::OLD_STYLE,
+        };
+        self.write_row(Row(1), &buf, style);
+        buf.clear();
+        let _ = write!(buf, "top {scale}/s");
+        self.write_row(Row(2), &buf, ItemStyle::CURR_STYLE);
+
+        let top = Row(GRAPH_ROW).top_left(0).y;
+        let bottom = DISP_HEIGHT - 1;
+        let height = bottom - top;
+        let bar_height = |n: u16| i32::from(n) * height / i32::from(scale);
+        for (i, sample) in self.load.iter().copied().enumerate() {
+            let x = i as i32 * BAR_WIDTH;
+            let errors = bar_height(sample.errors);
+            let total = bar_height(sample.transactions.saturating_add(sample.errors));
+            // the column is cleared above the bar only, so the graph doesn't flicker
+            let bar = |y0, y1, color| {
+                (y1 > y0).then(|| {
+                    Rectangle::with_corners(
+                        Point::new(x, y0 + 1),
+                        Point::new(x + BAR_WIDTH - 2, y1),
+                    )
+                    .into_styled(PrimitiveStyle::with_fill(color))
+                })
+            };
+            let parts = [
+                bar(top - 1, bottom - total, Rgb565::BLUE),
+                bar(bottom - total, bottom - errors, Rgb565::GREEN),
+                bar(bottom - errors, bottom, Rgb565::RED),
+            ];
+            for part in parts.into_iter().flatten() {
+                let _ = part.draw(&mut self.screen);
+            }
+        }
+    }
+
     fn clear_area(&mut self, rect: Rectangle) {
         if !rect.is_zero_sized() {
             let _ = rect.draw_styled(&PrimitiveStyle::with_fill(Rgb565::BLUE), &mut self.screen);
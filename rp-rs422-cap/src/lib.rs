@@ -1,3 +1,5 @@
 #![no_std]
+pub mod config;
 pub mod picodisplay;
+pub mod sdlog;
 pub mod x328_bus;
@@ -3,7 +3,7 @@
 #![allow(unused_must_use)]
 
 use core::fmt::Write;
-use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 
 use arrayvec::ArrayString;
@@ -30,7 +30,13 @@ type UartDev<D, P> = uart::UartPeripheral<
 type Uart0 = UartDev<pac::UART0, gpio::bank0::Gpio1>;
 type Uart1 = UartDev<pac::UART1, gpio::bank0::Gpio5>;
 
+mod crash;
+mod diag;
 mod disp_info;
+mod pio_uart;
+mod sdcard;
+mod usb_capture;
+mod usb_mux;
 
 #[rtic::app(device = pac, dispatchers = [TIMER_IRQ_1, TIMER_IRQ_2])]
 mod app {
@@ -39,59 +45,97 @@ mod app {
 
     use embedded_graphics::pixelcolor::Rgb888;
     use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
+    use embedded_hal::watchdog::{Watchdog, WatchdogEnable};
     use hal::clocks::ClockSource;
-    use panic_probe as _;
+    use hal::pio::{SM0, SM1};
     use rp2040_hal::gpio::{FunctionSio, FunctionSioOutput, SioOutput};
-    use rp2040_monotonic::{
-        fugit::Duration,
-        fugit::RateExtU32, // For .kHz() conversion funcs
-        Rp2040Monotonic,
-    };
+    use rp2040_monotonic::{fugit::Duration, fugit::ExtU32, Rp2040Monotonic};
     use rp_pico::hal::{gpio::bank0::Gpio25, pac, pwm, sio::Sio, Clock};
     use rp_pico::XOSC_CRYSTAL_FREQ;
     use x328_proto::scanner;
     use x328_proto::scanner::ControllerEvent;
 
+    use serial_pcap_core::mux::{BREAK_BYTE, CTRL_BIT, DROP_BYTE};
+    use serial_pcap_core::USB_VID_PID;
+
+    use rp_rs422_cap::config::{ConfigStore, Settings};
+    use rp_rs422_cap::sdlog::{SdBuffer, SD_BUF_LEN};
     use rp_rs422_cap::x328_bus::{FieldBus, UartBuf, UpdateEvent};
     use rp_rs422_cap::{create_picodisplay, make_buttons, picodisplay::PicoDisplay};
 
-    use crate::disp_info::{DisplayUpdates, Info};
+    use crate::crash::CrashCounters;
+    use crate::diag::{log_debug, log_info, log_warn};
+    use crate::disp_info::{BusError, BusLoad, DisplayUpdates, ErrorCounts, ErrorKind, Info};
+    use crate::pio_uart::PioUart;
+    use crate::sdcard::{self, SdLogger};
+    use crate::usb_capture::{
+        CaptureClass, FLAG_BREAK, FLAG_BUS2, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP, FLAG_TRIGGER,
+        TRIGGER_SOURCE_BUTTON,
+    };
+    use crate::usb_mux::{first_byte_time, ChunkTimer, MuxedPort};
 
     use super::*;
 
     const MONO_NUM: u32 = 1;
     const MONO_DENOM: u32 = 1000000;
     const ONE_SEC_TICKS: u64 = 1000000;
+    /// The device is reset if the idle task doesn't run for this long
+    const WATCHDOG_PERIOD_US: u32 = 2_000_000;
 
     #[monotonic(binds = TIMER_IRQ_0, default = true)]
     type Rp2040Mono = Rp2040Monotonic;
 
     #[shared]
     struct Shared {
-        usb_serial: SerialPort<'static, hal::usb::UsbBus>,
+        usb_serial: MuxedPort,
         usb_serial2: SerialPort<'static, hal::usb::UsbBus>,
+        /// The muxed stream from the PIO UARTs on the second bus
+        usb_serial3: MuxedPort,
+        /// Framed capture records on the vendor bulk interface
+        usb_capture: CaptureClass,
         x328_scanner: scanner::Scanner,
+        sd_buf: SdBuffer,
         display_updates: DisplayUpdates,
+        /// The X3.28 transactions and errors since the last heartbeat
+        bus_load: BusLoad,
     }
 
     #[local]
     struct Local {
         buttons: Buttons,
         picodisplay: disp_info::BusDisplay,
+        watchdog: hal::watchdog::Watchdog,
+        crash_counters: CrashCounters,
         led: gpio::Pin<Gpio25, FunctionSioOutput, gpio::PullDown>,
         usb_device: UsbDevice<'static, hal::usb::UsbBus>,
         uart0: Uart0,
         uart1: Uart1,
+        side0: BusSide,
+        side1: BusSide,
+        pio_uart0: PioUart<SM0>,
+        pio_uart1: PioUart<SM1>,
+        bus2_sides: (BusSide, BusSide),
+        rgb: picodisplay::RGB,
+        config_store: ConfigStore,
+        settings: Settings,
+        fb: FieldBus,
+        sd_logger: Option<SdLogger>,
         pin_gp9: gpio::Pin<gpio::bank0::Gpio9, FunctionSio<SioOutput>, PullNone>,
     }
 
     #[init(local=[
         usb_bus_uninit: MaybeUninit<UsbBusAllocator<hal::usb::UsbBus>> = MaybeUninit::uninit(),
-        display_updates: DisplayUpdates = DisplayUpdates::new(),
     ])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
         let mut pac = ctx.device;
 
+        let crash_counters = CrashCounters::update(&pac.WATCHDOG);
+        let (config_store, settings) = ConfigStore::load();
+        let fb = FieldBus::new(&settings.nodes);
+        usb_mux::set_frame_time(&settings);
+        log_info!("{}", defmt::Display2Format(&crash_counters));
+        log_info!("Settings: {}", defmt::Display2Format(&settings));
+
         // Configure the clocks, watchdog - The default is to generate a 125 MHz system clock
         let mut watchdog = hal::watchdog::Watchdog::new(pac.WATCHDOG);
 
@@ -107,8 +151,8 @@ mod app {
         .ok()
         .unwrap();
 
-        let delay =
-            &mut cortex_m::delay::Delay::new(ctx.core.SYST, clocks.system_clock.get_freq().to_Hz());
+        let mut delay =
+            cortex_m::delay::Delay::new(ctx.core.SYST, clocks.system_clock.get_freq().to_Hz());
         // Init LED pin
         let sio = Sio::new(pac.SIO);
         let rp_pins = rp_pico::Pins::new(
@@ -127,10 +171,10 @@ mod app {
 
         let mut rgb =
             picodisplay::RGB::new(rp_pins.gpio6, rp_pins.gpio7, rp_pins.gpio8, pwm_rg, pwm_b);
-        rgb.set_brightness(50);
+        rgb.set_brightness(settings.brightness);
         rgb.set_color(Rgb888::GREEN);
 
-        let picodisplay = create_picodisplay!(rp_pins, pac, delay);
+        let picodisplay = create_picodisplay!(rp_pins, pac, &mut delay);
         let mut picodisplay = disp_info::BusDisplay::new(picodisplay.screen);
 
         let buttons = make_buttons!(rp_pins);
@@ -141,15 +185,46 @@ mod app {
         let uart0 = uart_setup(
             rp_pins.gpio1,
             pac.UART0,
+            settings.uart_config(),
             &clocks.peripheral_clock,
             &mut pac.RESETS,
         );
         let uart1 = uart_setup(
             rp_pins.gpio5,
             pac.UART1,
+            settings.uart_config(),
             &clocks.peripheral_clock,
             &mut pac.RESETS,
         );
+        let (side0, side1) = match settings.swap_channels {
+            false => (BusSide::Node, BusSide::Ctrl),
+            true => (BusSide::Ctrl, BusSide::Node),
+        };
+        // The PIO UARTs on the second bus, GP2 is wired like UART 0 and GP3 like UART 1
+        let (pio_uart0, pio_uart1) = pio_uart::pio_uarts(
+            pac.PIO0,
+            rp_pins.gpio2,
+            rp_pins.gpio3,
+            &settings,
+            clocks.system_clock.freq(),
+            &mut pac.RESETS,
+        );
+
+        // The SD card is optional, the capture is logged to it when there is no host
+        let sd_spi = sdcard::sd_spi(
+            pac.SPI1,
+            rp_pins.gpio26,
+            rp_pins.gpio27,
+            rp_pins.gpio28,
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+        );
+        let sd_cs = rp_pins.gpio22.into_push_pull_output();
+        let sd_logger = SdLogger::new(sd_spi, sd_cs, delay, clocks.peripheral_clock.freq());
+        if let Some(_logger) = &sd_logger {
+            log_info!("Logging to {=str} on the SD card", _logger.file_name());
+            SD_ACTIVE.store(true, Ordering::Relaxed);
+        }
 
         // Set up the USB driver
         let usb_bus_uninit = ctx.local.usb_bus_uninit;
@@ -165,10 +240,12 @@ mod app {
 
         // Set up the USB Communications Class Device driver
         let usb_serial2 = SerialPort::new(usb_bus);
-        let usb_serial = SerialPort::new(usb_bus);
+        let usb_serial = MuxedPort::new(SerialPort::new(usb_bus), settings.framing);
+        let usb_serial3 = MuxedPort::new(SerialPort::new(usb_bus), settings.framing);
+        let usb_capture = CaptureClass::new(usb_bus);
 
         // Create a USB device with a fake VID and PID
-        let usb_device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        let usb_device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(USB_VID_PID.0, USB_VID_PID.1))
             .manufacturer("Fake company")
             .product("Serial port")
             .serial_number("TEST")
@@ -181,22 +258,42 @@ mod app {
         heartbeat::spawn().unwrap();
 
         picodisplay.redraw();
+        let mut display_updates = DisplayUpdates::new();
+        display_updates.set_info(Info::Resets(crash_counters));
+
+        watchdog.start(WATCHDOG_PERIOD_US.micros());
 
         // Return resources and timer
         (
             Shared {
                 usb_serial,
                 usb_serial2,
+                usb_serial3,
+                usb_capture,
                 x328_scanner: Default::default(),
-                display_updates: DisplayUpdates::new(),
+                sd_buf: SdBuffer::new(),
+                display_updates,
+                bus_load: BusLoad::default(),
             },
             Local {
                 buttons,
                 picodisplay,
+                watchdog,
+                crash_counters,
                 led,
                 usb_device,
                 uart0,
                 uart1,
+                side0,
+                side1,
+                pio_uart0,
+                pio_uart1,
+                bus2_sides: (side0, side1),
+                rgb,
+                config_store,
+                settings,
+                fb,
+                sd_logger,
                 pin_gp9,
             },
             init::Monotonics(monotonic),
@@ -206,6 +303,7 @@ mod app {
     fn uart_setup<D, P>(
         pin: gpio::Pin<P, gpio::FunctionNull, gpio::PullDown>,
         dev: D,
+        uart_config: uart::UartConfig,
         peripheral_clock: &hal::clocks::PeripheralClock,
         resets: &mut pac::RESETS,
     ) -> UartDev<D, P>
@@ -214,12 +312,6 @@ mod app {
         P: gpio::PinId + uart::ValidPinIdRx<D> + gpio::ValidFunction<gpio::FunctionUart>,
     {
         let rx_pin = pin.into_pull_type().into_function::<gpio::FunctionUart>();
-        let uart_config = uart::UartConfig::new(
-            9600.Hz(),
-            uart::DataBits::Seven,
-            Some(uart::Parity::Even),
-            uart::StopBits::One,
-        );
         // TODO: uart config should be Clone, and new() should take it by reference
         let mut uart = uart::UartPeripheral::new(dev, uart::Pins::default().rx(rx_pin), resets)
             .enable(uart_config, peripheral_clock.freq())
@@ -229,10 +321,19 @@ mod app {
         uart
     }
 
-    #[idle(local = [picodisplay], shared = [display_updates])]
+    #[idle(local = [picodisplay, watchdog], shared = [display_updates])]
     fn idle(mut ctx: idle::Context) -> ! {
         let disp = ctx.local.picodisplay;
         loop {
+            ctx.local.watchdog.feed();
+            if PREV_PAGE.load(Ordering::Relaxed) {
+                PREV_PAGE.store(false, Ordering::Relaxed);
+                disp.prev_page();
+            }
+            if NEXT_PAGE.load(Ordering::Relaxed) {
+                NEXT_PAGE.store(false, Ordering::Relaxed);
+                disp.next_page();
+            }
             let age = SECONDS.load(Ordering::SeqCst);
             let info = ctx.shared.display_updates.lock(|u| u.next_change());
             if let Some(update) = info {
@@ -243,12 +344,30 @@ mod app {
     }
     static SECONDS: AtomicI32 = AtomicI32::new(0);
 
-    #[task(local = [led])]
-    fn heartbeat(ctx: heartbeat::Context) {
+    #[task(local = [led], shared = [display_updates, bus_load])]
+    fn heartbeat(mut ctx: heartbeat::Context) {
         // Flicker the built-in LED
         _ = ctx.local.led.toggle();
         let age = SECONDS.load(Ordering::SeqCst);
         SECONDS.store(age + 1, Ordering::SeqCst);
+        if SD_ACTIVE.load(Ordering::Relaxed) {
+            let _ = sd_writer::spawn();
+        }
+        if age % 10 == 0 {
+            BusSide::Node.stats().log("node");
+            BusSide::Ctrl.stats().log("ctrl");
+            pio_uart::STATS[0].log("gp2");
+            pio_uart::STATS[1].log("gp3");
+        }
+        let load = ctx.shared.bus_load.lock(core::mem::take);
+        let uart_stats = Info::UartStats(
+            BusSide::Node.stats().counts(),
+            BusSide::Ctrl.stats().counts(),
+        );
+        ctx.shared.display_updates.lock(|u| {
+            u.set_info(Info::BusLoad(load));
+            u.set_info(uart_stats);
+        });
 
         // Re-spawn this task after 1 second
         let one_second = Duration::<u64, MONO_NUM, MONO_DENOM>::from_ticks(ONE_SEC_TICKS);
@@ -258,10 +377,11 @@ mod app {
     #[task(
         capacity = 1,
         priority = 2,
-        shared = [ usb_serial2, display_updates ],
+        shared = [ usb_serial2, display_updates, bus_load ],
         local = [
             ctrl_ev: ControllerEvent = ControllerEvent::NodeTimeout,
-            fb: FieldBus = FieldBus::new(),
+            errors: ErrorCounts = ErrorCounts::new(),
+            fb,
         ])]
     fn x328_event_handler(mut ctx: x328_event_handler::Context, ev: scanner::Event) {
         use scanner::{ControllerEvent, Event, NodeEvent};
@@ -269,6 +389,35 @@ mod app {
         let fb = ctx.local.fb;
         let ctrl_ev = ctx.local.ctrl_ev;
         let mut update_event = None;
+        // the node and parameter of the last command
+        let cmd = match ctrl_ev {
+            ControllerEvent::Read(a, p) | ControllerEvent::Write(a, p, _) => Some((**a, **p)),
+            ControllerEvent::NodeTimeout => None,
+        };
+        let error_kind = match &ev {
+            Event::Ctrl(ControllerEvent::NodeTimeout) => cmd.map(|_| ErrorKind::Timeout),
+            Event::Ctrl(_) => None,
+            Event::Node(NodeEvent::Write(Ok(_)) | NodeEvent::Read(Ok(_))) => {
+                ctx.shared
+                    .bus_load
+                    .lock(|l| l.transactions = l.transactions.saturating_add(1));
+                None
+            }
+            Event::Node(NodeEvent::UnexpectedTransmission) => Some(ErrorKind::Unexpected),
+            Event::Node(_) => Some(ErrorKind::Failed),
+        };
+        if let Some(kind) = error_kind {
+            ctx.shared
+                .bus_load
+                .lock(|l| l.errors = l.errors.saturating_add(1));
+            let errors = ctx.local.errors;
+            errors.count(kind);
+            let cmd = cmd.filter(|_| kind != ErrorKind::Unexpected);
+            ctx.shared.display_updates.lock(|disp| {
+                disp.set_info(Info::BusErrors(*errors));
+                disp.set_info(Info::LastError(BusError { kind, cmd }));
+            });
+        }
         match ev {
             Event::Ctrl(ev) => {
                 if matches!(ev, ControllerEvent::NodeTimeout) {
@@ -294,11 +443,14 @@ mod app {
                     update_event = fb.update_parameter(*a, *p, v);
                     write!(msg, "Node {} read ok {} == {}", **a, **p, *v);
                 }
-                (NodeEvent::UnexpectedTransmission, _) => {}
+                (NodeEvent::UnexpectedTransmission, _) => {
+                    log_warn!("Unexpected transmission");
+                }
                 _ => {}
             },
         }
         if !msg.is_empty() {
+            log_debug!("{=str}", msg.as_str());
             msg.push_str("\r\n");
 
             ctx.shared.usb_serial2.lock(|serial| {
@@ -322,7 +474,7 @@ mod app {
         }
     }
 
-    #[task(local = [last_trig_time: i32 = 0, pin_gp9], shared = [usb_serial, usb_serial2])]
+    #[task(local = [last_trig_time: i32 = 0, pin_gp9], shared = [usb_serial, usb_serial2, usb_capture])]
     fn meas_trigger(ctx: meas_trigger::Context) {
         let prev_trig = ctx.local.last_trig_time;
         let mut usb_events = ctx.shared.usb_serial2;
@@ -335,10 +487,10 @@ mod app {
         }
         trig_pin.set_high();
         *prev_trig = now;
-        usb_bytes.lock(|usb| {
-            usb.write(b"\n");
-            usb.flush();
-        });
+        usb_bytes.lock(|usb| usb.write_trigger());
+        let time = monotonics::now().ticks() as u32;
+        let mut capture = ctx.shared.usb_capture;
+        capture.lock(|c| c.push(FLAG_TRIGGER, time, TRIGGER_SOURCE_BUTTON));
         usb_events.lock(|usb| {
             usb.write(b"Trigger event\r\n");
             usb.flush();
@@ -346,85 +498,378 @@ mod app {
         trig_pin.set_low();
     }
 
-    // Received from x3.28 node
-    #[task(binds = UART0_IRQ, priority = 2, local = [uart0, buf: UartBuf = UartBuf::new()], shared = [usb_serial, x328_scanner])]
-    fn uart0_irq(mut ctx: uart0_irq::Context) {
+    #[task(binds = UART0_IRQ, priority = 2, local = [uart0, side0, buf: UartBuf = UartBuf::new(), drop_pending: bool = false, timer: ChunkTimer = ChunkTimer::new()], shared = [usb_serial, usb_capture, x328_scanner, sd_buf])]
+    fn uart0_irq(ctx: uart0_irq::Context) {
         let uart: &mut Uart0 = ctx.local.uart0;
-        let buf = ctx.local.buf;
-        ctx.shared.usb_serial.lock(|serial: &mut SerialPort<_>| {
-            let tail = buf.tail_slice(1);
-            let len = match uart.read_raw(tail) {
-                Ok(len) => len,
-                Err(nb::Error::WouldBlock) => 0,
-                Err(nb::Error::Other(uart::ReadError { discarded, .. })) => discarded.len(),
-            };
-            let _ = serial.write(&tail[0..len]);
-            let _ = serial.flush();
-            buf.incr_len(len);
-        });
-        ctx.shared.x328_scanner.lock(|s| {
-            let (consumed, event) = s.recv_from_node(buf);
-            buf.consume(consumed);
-            if let Some(event) = event {
-                let _ = x328_event_handler::spawn(event.into());
-            }
-        });
+        let side = *ctx.local.side0;
+        let (len, overrun, line_break) = read_uart(uart, side, ctx.local.buf);
+        uart_received(
+            side,
+            len,
+            overrun,
+            line_break,
+            ctx.local.buf,
+            ctx.local.drop_pending,
+            ctx.local.timer,
+            ctx.shared.usb_serial,
+            ctx.shared.usb_capture,
+            ctx.shared.x328_scanner,
+            ctx.shared.sd_buf,
+        );
     }
 
-    // Received from bus controller
-    #[task(binds = UART1_IRQ, priority = 2, local = [uart1, buf: UartBuf = UartBuf::new()], shared = [usb_serial, x328_scanner])]
-    fn uart1_irq(mut ctx: uart1_irq::Context) {
+    #[task(binds = UART1_IRQ, priority = 2, local = [uart1, side1, buf: UartBuf = UartBuf::new(), drop_pending: bool = false, timer: ChunkTimer = ChunkTimer::new()], shared = [usb_serial, usb_capture, x328_scanner, sd_buf])]
+    fn uart1_irq(ctx: uart1_irq::Context) {
         let uart: &mut Uart1 = ctx.local.uart1;
-        let buf = ctx.local.buf;
-        let tail = buf.tail_slice(1);
-        let len = match uart.read_raw(tail) {
-            Ok(len) => len,
-            Err(nb::Error::WouldBlock) => 0,
-            Err(nb::Error::Other(uart::ReadError { discarded, .. })) => discarded.len(),
+        let side = *ctx.local.side1;
+        let (len, overrun, line_break) = read_uart(uart, side, ctx.local.buf);
+        uart_received(
+            side,
+            len,
+            overrun,
+            line_break,
+            ctx.local.buf,
+            ctx.local.drop_pending,
+            ctx.local.timer,
+            ctx.shared.usb_serial,
+            ctx.shared.usb_capture,
+            ctx.shared.x328_scanner,
+            ctx.shared.sd_buf,
+        );
+    }
+
+    #[task(binds = PIO0_IRQ_0, priority = 2, local = [pio_uart0, pio_uart1, bus2_sides, drop_pending: [bool; 2] = [false; 2], timers: [ChunkTimer; 2] = [ChunkTimer::new(), ChunkTimer::new()]], shared = [usb_serial3, usb_capture])]
+    fn pio0_irq(ctx: pio0_irq::Context) {
+        let now = monotonics::now().ticks() as u32;
+        let (side0, side1) = *ctx.local.bus2_sides;
+        let [drop0, drop1] = ctx.local.drop_pending;
+        let [timer0, timer1] = ctx.local.timers;
+        let mut buf = [0u8; 8];
+        let uart0 = ctx.local.pio_uart0;
+        let uart1 = ctx.local.pio_uart1;
+        (ctx.shared.usb_serial3, ctx.shared.usb_capture).lock(|port, capture| {
+            let (len, overrun) = uart0.read(&mut buf);
+            let (stats, data) = (uart0.stats(), &buf[..len]);
+            bus2_received(
+                side0, stats, data, overrun, drop0, timer0, now, port, capture,
+            );
+            let (len, overrun) = uart1.read(&mut buf);
+            let (stats, data) = (uart1.stats(), &buf[..len]);
+            bus2_received(
+                side1, stats, data, overrun, drop1, timer1, now, port, capture,
+            );
+        });
+    }
+
+    /// Forward the data from a PIO UART to the host, in the same muxed format as the first bus.
+    /// The second bus isn't decoded or logged to the SD card.
+    #[allow(clippy::too_many_arguments)]
+    fn bus2_received(
+        side: BusSide,
+        stats: &diag::UartStats,
+        data: &[u8],
+        overrun: bool,
+        drop_pending: &mut bool,
+        timer: &mut ChunkTimer,
+        now: u32,
+        port: &mut MuxedPort,
+        capture: &mut CaptureClass,
+    ) {
+        stats.received(data.len());
+        if overrun {
+            stats.overrun();
+            *drop_pending = true;
+        }
+        let was_dropping = *drop_pending;
+        let ch_bit = match side {
+            BusSide::Node => 0,
+            BusSide::Ctrl => CTRL_BIT,
         };
-        let tail = &mut tail[0..len];
-        for b in tail.iter_mut() {
-            *b |= 0x80; // set bit 8 high to indicate uart 1
+        let chunk = timer.received(now, data.len());
+        if !data.is_empty() || overrun {
+            let flags = capture_flags(side, FLAG_BUS2, overrun, chunk.is_some());
+            capture.push(flags, first_byte_time(now, data.len()), data);
+        }
+        if let Some(chunk) = &chunk {
+            port.write_chunk_start(chunk, ch_bit);
+        }
+        port.forward(data, ch_bit, drop_pending);
+        if *drop_pending && !was_dropping {
+            stats.usb_drop();
+        }
+    }
+
+    /// Flags for a framed capture record
+    fn capture_flags(side: BusSide, bus: u8, overrun: bool, chunk_start: bool) -> u8 {
+        let mut flags = bus;
+        if let BusSide::Ctrl = side {
+            flags |= FLAG_CTRL;
+        }
+        if overrun {
+            flags |= FLAG_DROP;
+        }
+        if chunk_start {
+            flags |= FLAG_CHUNK;
+        }
+        flags
+    }
+
+    /// Read the received data into the tail of `buf`, returns the number of bytes read,
+    /// whether the UART overran and whether there was a break on the line after the data.
+    fn read_uart<D, P>(
+        uart: &mut UartDev<D, P>,
+        side: BusSide,
+        buf: &mut UartBuf,
+    ) -> (usize, bool, bool)
+    where
+        D: uart::UartDevice,
+        P: gpio::PinId + uart::ValidPinIdRx<D> + gpio::ValidFunction<gpio::FunctionUart>,
+    {
+        match uart.read_raw(buf.tail_slice(1)) {
+            Ok(len) => (len, false, false),
+            Err(nb::Error::WouldBlock) => (0, false, false),
+            Err(nb::Error::Other(uart::ReadError {
+                err_type,
+                discarded,
+            })) => {
+                let overrun = matches!(err_type, uart::ReadErrorType::Overrun);
+                if overrun {
+                    side.stats().overrun();
+                }
+                let line_break = matches!(err_type, uart::ReadErrorType::Break);
+                if line_break {
+                    side.stats().line_break();
+                }
+                (discarded.len(), overrun, line_break)
+            }
         }
+    }
 
-        ctx.shared.usb_serial.lock(|serial: &mut SerialPort<_>| {
-            let _ = serial.write(tail);
-            let _ = serial.flush();
+    /// Forward the `len` bytes just read into `buf` to the host, and pass them to the X3.28 scanner.
+    /// A break after the data is forwarded as a break record, and discards the partial frame
+    /// in the scanner buffer.
+    #[allow(clippy::too_many_arguments)]
+    fn uart_received(
+        side: BusSide,
+        len: usize,
+        overrun: bool,
+        line_break: bool,
+        buf: &mut UartBuf,
+        drop_pending: &mut bool,
+        timer: &mut ChunkTimer,
+        mut usb_serial: impl rtic::Mutex<T = MuxedPort>,
+        mut usb_capture: impl rtic::Mutex<T = CaptureClass>,
+        mut x328_scanner: impl rtic::Mutex<T = scanner::Scanner>,
+        mut sd_buf: impl rtic::Mutex<T = SdBuffer>,
+    ) {
+        let now = monotonics::now().ticks();
+        let tail = &mut buf.tail_slice(1)[0..len];
+        let stats = side.stats();
+        stats.received(len);
+        *drop_pending |= overrun;
+        let was_dropping = *drop_pending;
+        let drop_byte = match side {
+            BusSide::Node => DROP_BYTE,
+            BusSide::Ctrl => DROP_BYTE | CTRL_BIT,
+        };
+        let chunk = timer.received(now as u32, len);
+        if len > 0 || overrun {
+            let flags = capture_flags(side, 0, overrun, chunk.is_some());
+            let time = first_byte_time(now as u32, len);
+            usb_capture.lock(|c| c.push(flags, time, tail));
+        }
+        if line_break {
+            let flags = capture_flags(side, 0, false, false) | FLAG_BREAK;
+            usb_capture.lock(|c| c.push(flags, now as u32, &[]));
+        }
+        let ch_bit = drop_byte & CTRL_BIT;
+        usb_serial.lock(|port| {
+            if let Some(chunk) = &chunk {
+                port.write_chunk_start(chunk, ch_bit);
+            }
+            port.forward(tail, ch_bit, drop_pending);
+            if line_break {
+                port.write_break(ch_bit, drop_pending);
+            }
         });
-        for b in tail.iter_mut() {
-            *b &= 0x7f; // clear bit 8 again
+        // the SD card log has the bit 7 format of the muxed stream
+        let break_byte = BREAK_BYTE | ch_bit;
+        if let BusSide::Ctrl = side {
+            for b in tail.iter_mut() {
+                *b |= CTRL_BIT; // set bit 8 high to indicate the controller
+            }
+        }
+        if SD_ACTIVE.load(Ordering::Relaxed) && !HOST_ATTACHED.load(Ordering::Relaxed) {
+            if len > 0 {
+                sd_buf.lock(|sd| sd.push(now, tail, drop_byte));
+            }
+            if line_break {
+                sd_buf.lock(|sd| sd.push(now, &[break_byte], drop_byte));
+            }
+        }
+        if let BusSide::Ctrl = side {
+            for b in tail.iter_mut() {
+                *b &= !CTRL_BIT; // clear bit 8 again
+            }
+        }
+        if *drop_pending && !was_dropping {
+            stats.usb_drop();
         }
         buf.incr_len(len);
+        let _discarded = buf.take_overflow();
+        if _discarded > 0 {
+            log_warn!("Scanner buffer full, {=usize} bytes discarded", _discarded);
+        }
 
-        ctx.shared.x328_scanner.lock(|s| {
-            let (consumed, event) = s.recv_from_ctrl(buf);
+        x328_scanner.lock(|s| {
+            let (consumed, event) = match side {
+                BusSide::Node => {
+                    let (consumed, event) = s.recv_from_node(buf);
+                    (consumed, event.map(scanner::Event::from))
+                }
+                BusSide::Ctrl => {
+                    let (consumed, event) = s.recv_from_ctrl(buf);
+                    (consumed, event.map(scanner::Event::from))
+                }
+            };
             buf.consume(consumed);
             if let Some(event) = event {
-                let _ = x328_event_handler::spawn(event.into());
+                let _ = x328_event_handler::spawn(event);
             }
         });
+        if line_break {
+            buf.consume(buf.len());
+        }
     }
 
     #[task(
     binds = USBCTRL_IRQ,
     priority=3,
-    local = [usb_device],
-    shared = [usb_serial, usb_serial2],
+    local = [usb_device, crash_counters, dtr: bool = false, usb_state: UsbDeviceState = UsbDeviceState::Default, line: ArrayString<64> = ArrayString::new_const()],
+    shared = [usb_serial, usb_serial2, usb_serial3, usb_capture],
     )]
     fn usb_irq(ctx: usb_irq::Context) {
         let usb_device: &mut UsbDevice<_> = ctx.local.usb_device;
 
         let serial = ctx.shared.usb_serial;
         let usb_serial2 = ctx.shared.usb_serial2;
+        let usb_serial3 = ctx.shared.usb_serial3;
+        let usb_capture = ctx.shared.usb_capture;
         // Poll the USB driver with all of our supported USB Classes
         let mut ready = false;
-        (serial, usb_serial2).lock(|ser1: &mut SerialPort<_>, ser2| {
-            ready = usb_device.poll(&mut [ser2, ser1]);
-            if ready {
-                let mut buf = [0u8; 0];
-                ser1.read(&mut buf);
-                ser2.read(&mut buf);
+        (serial, usb_serial2, usb_serial3, usb_capture).lock(
+            |ser1: &mut MuxedPort, ser2, ser3, capture| {
+                ready = usb_device.poll(&mut [ser2, &mut ser1.serial, &mut ser3.serial, capture]);
+                // the rest of a timing record is sent as soon as there is room for it
+                ser1.write_pending();
+                ser3.write_pending();
+                let state = usb_device.state();
+                if state != *ctx.local.usb_state {
+                    *ctx.local.usb_state = state;
+                    HOST_ATTACHED.store(state == UsbDeviceState::Configured, Ordering::Relaxed);
+                    log_info!(
+                        "USB {=str}",
+                        match state {
+                            UsbDeviceState::Default => "default",
+                            UsbDeviceState::Addressed => "addressed",
+                            UsbDeviceState::Configured => "configured",
+                            UsbDeviceState::Suspend => "suspended",
+                        }
+                    );
+                }
+                if ready {
+                    let mut buf = [0u8; 0];
+                    ser1.serial.read(&mut buf);
+                    ser3.serial.read(&mut buf);
+                    let mut buf = [0u8; 64];
+                    if let Ok(len) = ser2.read(&mut buf) {
+                        command_input(ctx.local.line, &buf[..len]);
+                    }
+                }
+                // Report the crash counters when a terminal opens the event port
+                let dtr = ser2.dtr();
+                // 1200 baud touch: the host opens the port at 1200 baud and closes it
+                if dtr != *ctx.local.dtr {
+                    log_debug!("Event port DTR {=bool}", dtr);
+                }
+                if !dtr && *ctx.local.dtr && ser2.line_coding().data_rate() == 1200 {
+                    reboot_to_bootloader();
+                }
+                if dtr && !*ctx.local.dtr {
+                    let mut msg = ArrayString::<100>::new();
+                    let _ = write!(msg, "{}\r\n", ctx.local.crash_counters);
+                    ser2.write(msg.as_bytes());
+                    ser2.flush();
+                }
+                *ctx.local.dtr = dtr;
+            },
+        );
+    }
+
+    /// Write the buffered data to the SD card
+    #[task(shared = [sd_buf], local = [sd_logger, chunk: [u8; SD_BUF_LEN] = [0; SD_BUF_LEN]])]
+    fn sd_writer(mut ctx: sd_writer::Context) {
+        let chunk = ctx.local.chunk;
+        let len = ctx.shared.sd_buf.lock(|sd| sd.take(chunk));
+        let Some(logger) = ctx.local.sd_logger else {
+            return;
+        };
+        if len > 0 && logger.write(&chunk[..len]).is_err() {
+            log_warn!("SD card write failed, logging stopped");
+            SD_ACTIVE.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Collect the command lines from the event port
+    fn command_input(line: &mut ArrayString<64>, data: &[u8]) {
+        for &b in data {
+            match b {
+                b'\r' | b'\n' => {
+                    if !line.is_empty() {
+                        let _ = command::spawn(*line);
+                        line.clear();
+                    }
+                }
+                _ => {
+                    // overlong lines are truncated, and rejected as invalid commands
+                    let _ = line.try_push(b as char);
+                }
+            }
+        }
+    }
+
+    /// Handle a command line from the host. The settings commands are
+    /// `config`, `set <name> <value>`, `save` and `reboot`, and `bootsel` reboots into the
+    /// RP2040 bootloader for firmware updates.
+    #[task(capacity = 1, shared = [usb_serial2], local = [config_store, settings, rgb])]
+    fn command(mut ctx: command::Context, line: ArrayString<64>) {
+        let settings = ctx.local.settings;
+        let mut reply = ArrayString::<160>::new();
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("config"), None, None) => write!(reply, "{settings}"),
+            (Some("set"), Some(name), Some(value)) => match settings.set(name, value) {
+                Ok(()) => {
+                    ctx.local.rgb.set_brightness(settings.brightness);
+                    write!(reply, "{settings}")
+                }
+                Err(e) => write!(reply, "Error: {e}"),
+            },
+            (Some("save"), None, None) => {
+                ctx.local.config_store.save(settings);
+                write!(
+                    reply,
+                    "Saved, the UART settings, framing and node addresses are used after a reboot"
+                )
             }
+            (Some("reboot"), None, None) => cortex_m::peripheral::SCB::sys_reset(),
+            (Some("bootsel"), None, None) => reboot_to_bootloader(),
+            _ => write!(reply, "Unknown command: {line}"),
+        };
+        reply.push_str("\r\n");
+        ctx.shared.usb_serial2.lock(|serial| {
+            serial.write(reply.as_bytes());
+            serial.flush();
         });
     }
 
@@ -433,12 +878,49 @@ mod app {
         let b = ctx.local.buttons;
         use core::sync::atomic::Ordering;
         b.clear_interrupts();
-        if b.x.is_low().unwrap() {
-            let x = BTN_X_CTR.load(Ordering::Relaxed);
-            BTN_X_CTR.store(x + 1, Ordering::Relaxed);
+        if b.a.is_low().unwrap() {
             meas_trigger::spawn();
         }
+        if b.x.is_low().unwrap() {
+            PREV_PAGE.store(true, Ordering::Relaxed);
+        }
+        if b.y.is_low().unwrap() {
+            NEXT_PAGE.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Reboot into the USB mass storage bootloader, as if BOOTSEL was held during reset
+fn reboot_to_bootloader() -> ! {
+    hal::rom_data::reset_to_usb_boot(0, 0);
+    // not reached, the ROM function resets the chip
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+
+/// Which side of the bus a UART receives from
+#[derive(Copy, Clone)]
+enum BusSide {
+    Node,
+    Ctrl,
+}
+
+impl BusSide {
+    fn stats(self) -> &'static diag::UartStats {
+        static NODE: diag::UartStats = diag::UartStats::new();
+        static CTRL: diag::UartStats = diag::UartStats::new();
+        match self {
+            BusSide::Node => &NODE,
+            BusSide::Ctrl => &CTRL,
+        }
     }
 }
 
-static BTN_X_CTR: AtomicU32 = AtomicU32::new(0);
+/// The X and Y buttons ask the idle task to show the previous or the next display page
+static PREV_PAGE: AtomicBool = AtomicBool::new(false);
+static NEXT_PAGE: AtomicBool = AtomicBool::new(false);
+/// The USB device is configured by a host
+static HOST_ATTACHED: AtomicBool = AtomicBool::new(false);
+/// There is an SD card to log to when there is no host
+static SD_ACTIVE: AtomicBool = AtomicBool::new(false);
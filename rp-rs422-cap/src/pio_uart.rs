@@ -0,0 +1,139 @@
+//! UART receivers in PIO0, for tapping a second bus. The RP2040 only has two hardware
+//! UARTs, so the two lines of the second bus are sampled by PIO state machines, on GP2
+//! and GP3, with the same line settings as the hardware UARTs.
+
+use pio::{InSource, JmpCondition, WaitSource};
+use rp2040_hal::gpio::{bank0, FunctionPio0, Pin, PullNone};
+use rp2040_hal::pio::{Buffers, PIOBuilder, PIOExt, Rx, ShiftDirection, SM0, SM1};
+use rp_pico::hal::{gpio, pac};
+
+use rp_rs422_cap::config::{Parity, Settings};
+
+use crate::diag::UartStats;
+
+type PioRx<SM> = Rx<(pac::PIO0, SM)>;
+
+/// Receive counters for GP2 and GP3
+pub static STATS: [UartStats; 2] = [UartStats::new(), UartStats::new()];
+
+/// One PIO UART receiver
+pub struct PioUart<SM: rp2040_hal::pio::StateMachineIndex> {
+    rx: PioRx<SM>,
+    index: u8,
+    /// Bits per frame after the start bit, including the parity bit
+    frame_bits: u8,
+    data_mask: u8,
+}
+
+impl<SM: rp2040_hal::pio::StateMachineIndex> PioUart<SM> {
+    /// Read the received bytes into `buf`, returns the number of bytes read, and whether
+    /// bytes were lost because the FIFO was full.
+    pub fn read(&mut self, buf: &mut [u8]) -> (usize, bool) {
+        let mut len = 0;
+        while len < buf.len() {
+            let Some(word) = self.rx.read() else {
+                break;
+            };
+            // the bits are shifted in from the left, LSB first
+            buf[len] = (word >> (32 - self.frame_bits as u32)) as u8 & self.data_mask;
+            len += 1;
+        }
+        (len, take_overrun(self.index))
+    }
+
+    pub fn stats(&self) -> &'static UartStats {
+        &STATS[self.index as usize]
+    }
+}
+
+/// The state machine sets its RX stall flag when a push is dropped because the FIFO is full.
+fn take_overrun(index: u8) -> bool {
+    // SAFETY: the FDEBUG flags are write-1-to-clear, and each flag belongs to one receiver
+    let pio = unsafe { &*pac::PIO0::ptr() };
+    let mask = 1 << index;
+    if pio.fdebug.read().rxstall().bits() & mask != 0 {
+        pio.fdebug.write(|w| unsafe { w.rxstall().bits(mask) });
+        true
+    } else {
+        false
+    }
+}
+
+/// The UART receive program, `frame_bits` is the number of bits to shift in after the
+/// start bit. Frames without a stop bit are discarded.
+fn uart_rx_program(frame_bits: u8) -> pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }> {
+    let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
+    let mut start = a.label();
+    let mut bitloop = a.label();
+    let mut good_stop = a.label();
+    a.bind(&mut start);
+    a.wait(0, WaitSource::PIN, 0, false);
+    // 8 cycles per bit, wait until the middle of the first data bit
+    a.set_with_delay(pio::SetDestination::X, frame_bits - 1, 10);
+    a.bind(&mut bitloop);
+    a.r#in(InSource::PINS, 1);
+    a.jmp_with_delay(JmpCondition::XDecNonZero, &mut bitloop, 6);
+    a.jmp(JmpCondition::PinHigh, &mut good_stop);
+    // framing error or break, wait for the line to go idle
+    a.wait(1, WaitSource::PIN, 0, false);
+    a.jmp(JmpCondition::Always, &mut start);
+    a.bind(&mut good_stop);
+    // non-blocking, so a full FIFO drops the byte and sets the stall flag
+    a.push(false, false);
+    a.assemble_program()
+}
+
+/// Start the PIO UART receivers on GP2 and GP3, with the line settings from `settings`.
+/// The RX not empty interrupts are routed to PIO0_IRQ_0.
+#[allow(clippy::type_complexity)]
+pub fn pio_uarts(
+    pio0: pac::PIO0,
+    gp2: Pin<bank0::Gpio2, gpio::FunctionNull, gpio::PullDown>,
+    gp3: Pin<bank0::Gpio3, gpio::FunctionNull, gpio::PullDown>,
+    settings: &Settings,
+    system_freq: fugit::HertzU32,
+    resets: &mut pac::RESETS,
+) -> (PioUart<SM0>, PioUart<SM1>) {
+    let gp2: Pin<_, FunctionPio0, PullNone> = gp2.into_pull_type().into_function();
+    let gp3: Pin<_, FunctionPio0, PullNone> = gp3.into_pull_type().into_function();
+    let frame_bits = settings.data_bits + u8::from(settings.parity != Parity::None);
+    let data_mask = ((1u16 << settings.data_bits) - 1) as u8;
+
+    let (mut pio, sm0, sm1, _, _) = pio0.split(resets);
+    let program = pio.install(&uart_rx_program(frame_bits)).unwrap();
+    // clock divisor in 1/256ths, for 8 PIO cycles per bit
+    let div = (system_freq.to_Hz() as u64 * 256 / (8 * settings.baud as u64)) as u32;
+    let (int, frac) = ((div >> 8) as u16, div as u8);
+
+    // SAFETY: both state machines run the same program, which is never uninstalled
+    let program2 = unsafe { program.share() };
+    let builder = |program, pin: u8| {
+        PIOBuilder::from_program(program)
+            .in_pin_base(pin)
+            .jmp_pin(pin)
+            .in_shift_direction(ShiftDirection::Right)
+            .autopush(false)
+            .buffers(Buffers::OnlyRx)
+            .clock_divisor_fixed_point(int, frac)
+    };
+    let (sm0, rx0, _) = builder(program, gp2.id().num).build(sm0);
+    let (sm1, rx1, _) = builder(program2, gp3.id().num).build(sm1);
+    rx0.enable_rx_not_empty_interrupt(rp2040_hal::pio::PioIRQ::Irq0);
+    rx1.enable_rx_not_empty_interrupt(rp2040_hal::pio::PioIRQ::Irq0);
+    sm0.start();
+    sm1.start();
+    (
+        PioUart {
+            rx: rx0,
+            index: 0,
+            frame_bits,
+            data_mask,
+        },
+        PioUart {
+            rx: rx1,
+            index: 1,
+            frame_bits,
+            data_mask,
+        },
+    )
+}
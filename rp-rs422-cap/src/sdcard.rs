@@ -0,0 +1,133 @@
+use embedded_hal::spi::MODE_0;
+use embedded_sdmmc::{
+    Directory, File, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager,
+};
+use fugit::RateExtU32;
+use rp2040_hal::gpio::{FunctionSioOutput, FunctionSpi, Pin, PullDown};
+use rp_pico::hal::gpio::bank0::{Gpio22, Gpio26, Gpio27, Gpio28};
+use rp_pico::hal::{self, pac, spi};
+
+use rp_rs422_cap::sdlog::SD_LOG_MAGIC;
+
+type SdSpi = spi::Spi<
+    spi::Enabled,
+    pac::SPI1,
+    (
+        Pin<Gpio27, FunctionSpi, PullDown>,
+        Pin<Gpio28, FunctionSpi, PullDown>,
+        Pin<Gpio26, FunctionSpi, PullDown>,
+    ),
+    8,
+>;
+type SdCs = Pin<Gpio22, FunctionSioOutput, PullDown>;
+type Volumes = VolumeManager<SdCard<SdSpi, SdCs, cortex_m::delay::Delay>, NoClock>;
+
+/// There is no real time clock, the files get a fixed timestamp
+pub struct NoClock;
+
+impl TimeSource for NoClock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp::from_calendar(2023, 1, 1, 0, 0, 0).unwrap()
+    }
+}
+
+/// Log file on an SD card on SPI1, SCK on GP26, MOSI on GP27, MISO on GP28 and CS on GP22.
+pub struct SdLogger {
+    volumes: Volumes,
+    dir: Directory,
+    file: File,
+    name: [u8; 12],
+}
+
+impl SdLogger {
+    /// Create the next CAPnnnnn.LOG file on the card, returns None if there is no card.
+    pub fn new(
+        spi: SdSpi,
+        cs: SdCs,
+        delay: cortex_m::delay::Delay,
+        peripheral_freq: fugit::HertzU32,
+    ) -> Option<Self> {
+        let card = SdCard::new(spi, cs, delay);
+        card.num_bytes().ok()?;
+        // the card is identified at 400 kHz
+        card.spi(|spi| spi.set_baudrate(peripheral_freq, 16.MHz()));
+        let mut volumes = VolumeManager::new(card, NoClock);
+        let volume = volumes.open_volume(VolumeIdx(0)).ok()?;
+        let dir = volumes.open_root_dir(volume).ok()?;
+
+        let mut last = 0;
+        volumes
+            .iterate_dir(dir, |entry| {
+                let n = core::str::from_utf8(entry.name.base_name())
+                    .ok()
+                    .and_then(|b| b.strip_prefix("CAP"))
+                    .and_then(|n| n.parse().ok());
+                if entry.name.extension() == b"LOG" {
+                    last = last.max(n.unwrap_or(0));
+                }
+            })
+            .ok()?;
+        let mut name = *b"CAP00000.LOG";
+        let mut n: u32 = last + 1;
+        for digit in name[3..8].iter_mut().rev() {
+            *digit = b'0' + (n % 10) as u8;
+            n /= 10;
+        }
+        let file = volumes
+            .open_file_in_dir(
+                dir,
+                core::str::from_utf8(&name).ok()?,
+                Mode::ReadWriteCreate,
+            )
+            .ok()?;
+        volumes.write(file, SD_LOG_MAGIC).ok()?;
+        let mut logger = Self {
+            volumes,
+            dir,
+            file,
+            name,
+        };
+        logger.sync().ok()?;
+        Some(logger)
+    }
+
+    pub fn file_name(&self) -> &str {
+        core::str::from_utf8(&self.name).unwrap_or_default()
+    }
+
+    pub fn write(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(), embedded_sdmmc::Error<embedded_sdmmc::SdCardError>> {
+        self.volumes.write(self.file, data)?;
+        self.sync()
+    }
+
+    /// The file size in the directory is only updated when the file is closed, so it's
+    /// reopened after every write to keep the data if the power is lost.
+    fn sync(&mut self) -> Result<(), embedded_sdmmc::Error<embedded_sdmmc::SdCardError>> {
+        self.volumes.close_file(self.file)?;
+        let name = core::str::from_utf8(&self.name).unwrap_or_default();
+        self.file = self
+            .volumes
+            .open_file_in_dir(self.dir, name, Mode::ReadWriteAppend)?;
+        Ok(())
+    }
+}
+
+/// Set up SPI1 for the SD card, at the 400 kHz identification speed.
+pub fn sd_spi(
+    dev: pac::SPI1,
+    sck: Pin<Gpio26, hal::gpio::FunctionNull, PullDown>,
+    mosi: Pin<Gpio27, hal::gpio::FunctionNull, PullDown>,
+    miso: Pin<Gpio28, hal::gpio::FunctionNull, PullDown>,
+    resets: &mut pac::RESETS,
+    peripheral_freq: fugit::HertzU32,
+) -> SdSpi {
+    let pins = (
+        mosi.into_function::<FunctionSpi>(),
+        miso.into_function::<FunctionSpi>(),
+        sck.into_function::<FunctionSpi>(),
+    );
+    spi::Spi::new(dev, pins).init(resets, peripheral_freq, 400.kHz(), MODE_0)
+}
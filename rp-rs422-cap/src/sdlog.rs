@@ -0,0 +1,57 @@
+//! Buffering of the captured data for the SD card log, which is written when no host is
+//! attached. The log file format is in `serial_pcap_core::sdlog`, records of a u64
+//! microsecond timestamp, a length byte and the muxed stream bytes.
+
+pub use serial_pcap_core::sdlog::SD_LOG_MAGIC;
+use serial_pcap_core::sdlog::{encode_header, HEADER_LEN};
+
+pub const SD_BUF_LEN: usize = 4096;
+
+/// Records which are waiting to be written to the SD card
+pub struct SdBuffer {
+    buf: [u8; SD_BUF_LEN],
+    len: usize,
+    /// The drop bytes to put in front of the next data from each side, after the
+    /// buffer was full
+    pending_drops: [Option<u8>; 2],
+}
+
+impl SdBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; SD_BUF_LEN],
+            len: 0,
+            pending_drops: [None; 2],
+        }
+    }
+
+    /// Append a record with `data` from the muxed stream. If it doesn't fit, `drop_byte` is
+    /// logged before the next data from the same side instead.
+    pub fn push(&mut self, micros: u64, data: &[u8], drop_byte: u8) {
+        let side = (drop_byte >> 7) as usize;
+        let drop = self.pending_drops[side];
+        let data_len = data.len() + drop.is_some() as usize;
+        if data_len > u8::MAX as usize || self.len + HEADER_LEN + data_len > SD_BUF_LEN {
+            self.pending_drops[side] = Some(drop_byte);
+            return;
+        }
+        let rec = &mut self.buf[self.len..self.len + HEADER_LEN + data_len];
+        rec[..HEADER_LEN].copy_from_slice(&encode_header(micros, data_len as u8));
+        let mut payload = &mut rec[HEADER_LEN..];
+        if let Some(drop) = drop {
+            payload[0] = drop;
+            payload = &mut payload[1..];
+        }
+        payload.copy_from_slice(data);
+        self.len += HEADER_LEN + data_len;
+        self.pending_drops[side] = None;
+    }
+
+    /// Move the buffered records to `out`, returns the number of bytes.
+    pub fn take(&mut self, out: &mut [u8; SD_BUF_LEN]) -> usize {
+        let len = self.len;
+        out[..len].copy_from_slice(&self.buf[..len]);
+        self.len = 0;
+        len
+    }
+}
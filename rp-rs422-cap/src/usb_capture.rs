@@ -0,0 +1,72 @@
+//! Vendor specific USB interface with a bulk IN endpoint, which carries the captured data as
+//! framed records. Unlike the muxed stream on the CDC port, the records hold the channel,
+//! loss and chunk flags and a timestamp for every block of data, and 8 bit data.
+//!
+//! The record format is in `serial_pcap_core::framed`. The records are never split between
+//! USB packets, so the host can parse each packet on its own.
+
+use rp_pico::hal;
+use usb_device::class_prelude::*;
+
+use serial_pcap_core::framed::RecordQueue;
+pub use serial_pcap_core::framed::{
+    FLAG_BREAK, FLAG_BUS2, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP, FLAG_TRIGGER, TRIGGER_SOURCE_BUTTON,
+};
+
+const PACKET_LEN: usize = 64;
+const QUEUE_LEN: usize = 1024;
+
+pub struct CaptureClass {
+    iface: InterfaceNumber,
+    ep: EndpointIn<'static, hal::usb::UsbBus>,
+    /// Records waiting to be sent
+    queue: RecordQueue<QUEUE_LEN>,
+}
+
+impl CaptureClass {
+    pub fn new(alloc: &'static UsbBusAllocator<hal::usb::UsbBus>) -> Self {
+        Self {
+            iface: alloc.interface(),
+            ep: alloc.bulk(PACKET_LEN as u16),
+            queue: RecordQueue::new(PACKET_LEN),
+        }
+    }
+
+    /// Queue the data with the flags for the channel, `time` is when the first byte was
+    /// received. Data which doesn't fit in the queue is reported with FLAG_DROP in the next
+    /// record from the channel.
+    pub fn push(&mut self, flags: u8, time: u32, data: &[u8]) {
+        self.queue.push(flags, time, data);
+        self.send();
+    }
+
+    /// Send as many whole records as fit in a USB packet
+    fn send(&mut self) {
+        let packet = self.queue.packet();
+        // the endpoint is busy until the previous packet is sent
+        if !packet.is_empty() && self.ep.write(packet).is_ok() {
+            self.queue.consume(packet.len());
+        }
+    }
+}
+
+impl UsbClass<hal::usb::UsbBus> for CaptureClass {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.iface, 0xff, 0, 0)?;
+        writer.endpoint(&self.ep)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.queue.clear();
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep.address() {
+            self.send();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! The muxed stream to the host, in the format of `serial_pcap_core::mux`, or SLIP framed in
+//! the format of `serial_pcap_core::slip` with `set framing slip`. In the bit 7 format the
+//! controller bytes have bit 8 set, the node bytes have it cleared, and the control bytes are
+//! inserted by the firmware.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use arrayvec::ArrayVec;
+use rp_pico::hal;
+use usbd_serial::SerialPort;
+
+use rp_rs422_cap::config::{Framing, Parity, Settings};
+use serial_pcap_core::mux::{ChunkTimes, BREAK_BYTE, DROP_BYTE, TRIG_BYTE};
+use serial_pcap_core::slip::{Frame, MAX_DATA_LEN, MAX_FRAME_LEN};
+
+/// Time to receive one frame on the bus, set from the UART settings at startup
+static FRAME_US: AtomicU32 = AtomicU32::new(1042);
+
+pub fn set_frame_time(settings: &Settings) {
+    let bits = 1
+        + settings.data_bits as u32
+        + u32::from(settings.parity != Parity::None)
+        + settings.stop_bits as u32;
+    FRAME_US.store(bits * 1_000_000 / settings.baud, Ordering::Relaxed);
+}
+
+/// A USB serial port carrying the muxed stream. The timing records and SLIP frames are kept
+/// intact, if one doesn't fit in the USB buffer the rest of it is sent before any more data.
+pub struct MuxedPort {
+    pub serial: SerialPort<'static, hal::usb::UsbBus>,
+    framing: Framing,
+    pending: ArrayVec<u8, MAX_FRAME_LEN>,
+}
+
+impl MuxedPort {
+    pub fn new(serial: SerialPort<'static, hal::usb::UsbBus>, framing: Framing) -> Self {
+        Self {
+            serial,
+            framing,
+            pending: ArrayVec::new(),
+        }
+    }
+
+    /// Send the rest of a timing record or frame, returns true when there is nothing left of it.
+    pub fn write_pending(&mut self) -> bool {
+        if !self.pending.is_empty() {
+            let n = self.serial.write(&self.pending).unwrap_or(0);
+            self.pending.drain(..n);
+            let _ = self.serial.flush();
+        }
+        self.pending.is_empty()
+    }
+
+    /// Write the data after any pending record, returns the number of bytes written.
+    fn write(&mut self, data: &[u8]) -> usize {
+        if !self.write_pending() {
+            return 0;
+        }
+        self.serial.write(data).unwrap_or(0)
+    }
+
+    /// Write the bytes as a whole, the part which doesn't fit in the USB buffer is sent later.
+    /// Returns false if they were lost, because the previous record is still pending.
+    fn write_whole(&mut self, bytes: &[u8]) -> bool {
+        let n = self.write(bytes);
+        if n == 0 && !self.pending.is_empty() {
+            return false;
+        }
+        self.pending
+            .try_extend_from_slice(&bytes[n..])
+            .unwrap_or_default();
+        true
+    }
+
+    /// Encode and send a SLIP frame, returns false if it was lost.
+    fn write_frame(&mut self, frame: Frame, ch_bit: u8) -> bool {
+        let mut bytes = ArrayVec::<u8, MAX_FRAME_LEN>::new();
+        frame.encode(ch_bit, |b| bytes.push(b));
+        self.write_whole(&bytes)
+    }
+
+    /// Send a timing record for the chunk, before the data in it. `ch_bit` is `CTRL_BIT` for
+    /// the controller and 0 for the node, also in the SLIP frames.
+    pub fn write_chunk_start(&mut self, chunk: &ChunkTimes, ch_bit: u8) {
+        // if the record is lost, the following data will be dropped too
+        match self.framing {
+            Framing::Bit7 => self.write_whole(&chunk.encode(ch_bit)),
+            Framing::Slip => self.write_frame(Frame::Time(*chunk), ch_bit),
+        };
+    }
+
+    /// Write the data to the host. If the data doesn't fit, `drop_pending` is set and a
+    /// drop is sent before the next data, so the host knows data was lost.
+    pub fn forward(&mut self, data: &[u8], ch_bit: u8, drop_pending: &mut bool) {
+        let sent = match self.framing {
+            Framing::Bit7 => {
+                if *drop_pending && self.write(&[DROP_BYTE | ch_bit]) == 1 {
+                    *drop_pending = false;
+                }
+                let mut tagged = [0u8; MAX_DATA_LEN];
+                data.chunks(MAX_DATA_LEN).all(|part| {
+                    let tagged = &mut tagged[..part.len()];
+                    for (t, &b) in tagged.iter_mut().zip(part) {
+                        *t = b | ch_bit;
+                    }
+                    self.write(tagged) == tagged.len()
+                })
+            }
+            Framing::Slip => {
+                if *drop_pending && self.write_frame(Frame::Drop, ch_bit) {
+                    *drop_pending = false;
+                }
+                data.chunks(MAX_DATA_LEN)
+                    .all(|part| self.write_frame(Frame::Data(part), ch_bit))
+            }
+        };
+        if !sent {
+            *drop_pending = true;
+        }
+        let _ = self.serial.flush();
+    }
+
+    /// Report a break on the line after the data forwarded before it.
+    pub fn write_break(&mut self, ch_bit: u8, drop_pending: &mut bool) {
+        match self.framing {
+            Framing::Bit7 => self.forward(&[BREAK_BYTE], ch_bit, drop_pending),
+            Framing::Slip => {
+                if !self.write_frame(Frame::Break, ch_bit) {
+                    *drop_pending = true;
+                }
+                let _ = self.serial.flush();
+            }
+        }
+    }
+
+    /// Send a measurement trigger, it's lost if the USB buffer is full.
+    pub fn write_trigger(&mut self) {
+        match self.framing {
+            Framing::Bit7 => {
+                self.write(&[TRIG_BYTE]);
+            }
+            Framing::Slip => {
+                self.write_frame(Frame::Trigger, 0);
+            }
+        }
+        let _ = self.serial.flush();
+    }
+}
+
+/// Timer value when the first of the `len` bytes read at `now` was received
+pub fn first_byte_time(now: u32, len: usize) -> u32 {
+    let frame = FRAME_US.load(Ordering::Relaxed);
+    now.wrapping_sub(frame.wrapping_mul(len.saturating_sub(1) as u32))
+}
+
+/// Finds the gaps in the data from one side of the bus.
+pub struct ChunkTimer {
+    /// Timer value when the last byte was received
+    last: Option<u32>,
+}
+
+impl ChunkTimer {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Called with the `len` bytes read at `now`, returns the chunk timing if they start a
+    /// new chunk.
+    pub fn received(&mut self, now: u32, len: usize) -> Option<ChunkTimes> {
+        if len == 0 {
+            return None;
+        }
+        let frame = FRAME_US.load(Ordering::Relaxed);
+        let first = first_byte_time(now, len);
+        let prev = self.last.replace(now);
+        // more than two frame times without data starts a new chunk
+        if prev.is_some_and(|prev| first.wrapping_sub(prev) <= 2 * frame) {
+            return None;
+        }
+        Some(ChunkTimes {
+            first,
+            prev_last: prev.unwrap_or(first),
+        })
+    }
+}
@@ -0,0 +1,4 @@
+pub use serial_pcap_core::x328_bus::*;
+
+/// Holds the longest X3.28 frame, a write command of 18 bytes
+pub type UartBuf = serial_pcap_core::uart_buf::UartBuf<20>;
@@ -0,0 +1,163 @@
+//! Consistent Overhead Byte Stuffing, for framing binary records on a byte stream.
+//!
+//! The encoding removes the zero bytes from a record, so a zero can delimit the records. Each
+//! run of up to 254 non-zero bytes is prefixed with its length plus one, and a length below
+//! 255 means the run was followed by a zero, except at the end of the record. The overhead is
+//! one byte per 254 bytes of data, see [`max_encoded_len`], much less than for the escaping
+//! of SLIP in the worst case.
+
+use core::fmt;
+
+/// Ends an encoded record
+pub const DELIMITER: u8 = 0;
+
+/// The most bytes of an encoded record with `len` bytes of data, without the delimiter
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encode the data into `out`, which must hold [`max_encoded_len`] bytes, returns the
+/// encoded length. The [`DELIMITER`] isn't included.
+pub fn encode(data: &[u8], out: &mut [u8]) -> usize {
+    assert!(out.len() >= max_encoded_len(data.len()));
+    let mut code_pos = 0;
+    let mut pos = 1;
+    for (i, &b) in data.iter().enumerate() {
+        if b != 0 {
+            out[pos] = b;
+            pos += 1;
+        }
+        // a full run at the end of the data needs no empty run after it
+        if b == 0 || (pos - code_pos == 0xff && i + 1 < data.len()) {
+            out[code_pos] = (pos - code_pos) as u8;
+            code_pos = pos;
+            pos += 1;
+        }
+    }
+    out[code_pos] = (pos - code_pos) as u8;
+    pos
+}
+
+/// The encoded record is invalid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CobsError {
+    /// A zero byte inside the record
+    ZeroByte,
+    /// A run length goes past the end of the record
+    Truncated,
+    /// The decoded record doesn't fit in the buffer
+    TooLong,
+}
+
+impl fmt::Display for CobsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl CobsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CobsError::ZeroByte => "zero byte in a COBS record",
+            CobsError::Truncated => "truncated COBS record",
+            CobsError::TooLong => "COBS record too long",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CobsError {}
+
+/// Decode a record without the delimiter into `out`, returns the decoded length. The decoded
+/// record is never longer than the encoded one.
+pub fn decode(record: &[u8], out: &mut [u8]) -> Result<usize, CobsError> {
+    let mut len = 0;
+    let mut rest = record;
+    while let Some((&code, tail)) = rest.split_first() {
+        let run = (code as usize).checked_sub(1).ok_or(CobsError::ZeroByte)?;
+        let data = tail.get(..run).ok_or(CobsError::Truncated)?;
+        if data.contains(&0) {
+            return Err(CobsError::ZeroByte);
+        }
+        rest = &tail[run..];
+        let zero = code != 0xff && !rest.is_empty();
+        let end = len + run + usize::from(zero);
+        let out = out.get_mut(len..end).ok_or(CobsError::TooLong)?;
+        out[..run].copy_from_slice(data);
+        if zero {
+            out[run] = 0;
+        }
+        len = end;
+    }
+    Ok(len)
+}
+
+/// Collects the encoded records from a byte stream, up to `N` encoded bytes each, and decodes
+/// them in place.
+#[derive(Debug, Clone)]
+pub struct CobsDecoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for CobsDecoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> CobsDecoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Feed one byte, returns the decoded record or the error when it ends a record. Empty
+    /// records, e.g. repeated delimiters, are skipped.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], CobsError>> {
+        if byte != DELIMITER {
+            match self.buf.get_mut(self.len) {
+                Some(b) => {
+                    *b = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(CobsError::TooLong));
+        }
+        if len == 0 {
+            return None;
+        }
+        Some(decode_in_place(&mut self.buf[..len]).map(|n| &self.buf[..n]))
+    }
+}
+
+/// Decode a record in its buffer, the decoded bytes are never ahead of the encoded ones.
+fn decode_in_place(buf: &mut [u8]) -> Result<usize, CobsError> {
+    let (mut read, mut write) = (0, 0);
+    while let Some(&code) = buf.get(read) {
+        let run = (code as usize).checked_sub(1).ok_or(CobsError::ZeroByte)?;
+        let data = read + 1..read + 1 + run;
+        match buf.get(data.clone()) {
+            None => return Err(CobsError::Truncated),
+            Some(data) if data.contains(&0) => return Err(CobsError::ZeroByte),
+            Some(_) => {}
+        }
+        read = data.end;
+        buf.copy_within(data, write);
+        write += run;
+        if code != 0xff && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
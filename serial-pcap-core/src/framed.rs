@@ -0,0 +1,162 @@
+//! Framed capture records, sent by the capture device on its vendor USB bulk interface, or
+//! over the network by the Wi-Fi firmware.
+//!
+//! Every record has a 6 byte header: the payload length, the flags and the device time in
+//! microseconds when the first byte was received, as a little endian u32, followed by the
+//! payload. The records are never split between USB packets or UDP datagrams.
+
+use core::fmt;
+
+/// The data is from the controller side of the bus
+pub const FLAG_CTRL: u8 = 0x01;
+/// The data is from the second bus
+pub const FLAG_BUS2: u8 = 0x02;
+/// Data from the same channel was lost before this record
+pub const FLAG_DROP: u8 = 0x04;
+/// The first byte starts a new chunk, after a gap on the bus
+pub const FLAG_CHUNK: u8 = 0x08;
+/// The record is a measurement trigger, not bus data. The payload names the source of the
+/// trigger, e.g. [`TRIGGER_SOURCE_BUTTON`], older firmware sends a single trigger byte.
+pub const FLAG_TRIGGER: u8 = 0x10;
+/// The source of the triggers fired with the A button of the display
+pub const TRIGGER_SOURCE_BUTTON: &[u8] = b"button A";
+/// A break condition on the line at the record time, the payload is empty
+pub const FLAG_BREAK: u8 = 0x20;
+
+pub const HEADER_LEN: usize = 6;
+
+/// The header of a record
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// The payload length
+    pub len: u8,
+    pub flags: u8,
+    /// Device time of the first byte in microseconds
+    pub time: u32,
+}
+
+impl RecordHeader {
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = self.len;
+        header[1] = self.flags;
+        header[2..].copy_from_slice(&self.time.to_le_bytes());
+        header
+    }
+}
+
+/// The packet ends in the middle of a record
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Truncated;
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Truncated capture record.")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Truncated {}
+
+/// Split the first record off a USB packet, returns the header, the payload and the rest
+/// of the packet.
+pub fn split_record(packet: &[u8]) -> Result<(RecordHeader, &[u8], &[u8]), Truncated> {
+    if packet.len() < HEADER_LEN || packet.len() < HEADER_LEN + packet[0] as usize {
+        return Err(Truncated);
+    }
+    let header = RecordHeader {
+        len: packet[0],
+        flags: packet[1],
+        time: u32::from_le_bytes([packet[2], packet[3], packet[4], packet[5]]),
+    };
+    let (payload, rest) = packet[HEADER_LEN..].split_at(header.len as usize);
+    Ok((header, payload, rest))
+}
+
+/// The records waiting to be sent by the capture device, `N` bytes of them. The transfers
+/// to the host, USB packets or UDP datagrams of at most `packet_len` bytes, hold only whole
+/// records.
+pub struct RecordQueue<const N: usize> {
+    data: [u8; N],
+    len: usize,
+    packet_len: usize,
+    /// Data was lost on the channel, indexed by the ctrl and bus flags
+    dropped: [bool; 4],
+}
+
+impl<const N: usize> RecordQueue<N> {
+    /// A queue for transfers of `packet_len` bytes, which must hold a header and some data
+    pub const fn new(packet_len: usize) -> Self {
+        assert!(packet_len > HEADER_LEN);
+        Self {
+            data: [0; N],
+            len: 0,
+            packet_len,
+            dropped: [false; 4],
+        }
+    }
+
+    /// Queue the data with the flags for the channel, `time` is when the first byte was
+    /// received. Data which doesn't fit in the queue is reported with FLAG_DROP in the next
+    /// record from the channel.
+    pub fn push(&mut self, flags: u8, time: u32, data: &[u8]) {
+        let channel = (flags & (FLAG_CTRL | FLAG_BUS2)) as usize;
+        // a loss is reported with the next data from the channel
+        if flags & FLAG_DROP != 0 {
+            self.dropped[channel] = true;
+        }
+        let mut flags = flags & !FLAG_DROP;
+        let max_payload = (self.packet_len - HEADER_LEN).min(u8::MAX as usize);
+        for payload in data.chunks(max_payload) {
+            let record_len = HEADER_LEN + payload.len();
+            if N - self.len < record_len {
+                self.dropped[channel] = true;
+                break;
+            }
+            if self.dropped[channel] {
+                flags |= FLAG_DROP;
+                self.dropped[channel] = false;
+            }
+            let header = RecordHeader {
+                len: payload.len() as u8,
+                flags,
+                time,
+            };
+            self.data[self.len..self.len + HEADER_LEN].copy_from_slice(&header.encode());
+            self.data[self.len + HEADER_LEN..self.len + record_len].copy_from_slice(payload);
+            self.len += record_len;
+            // only the first record starts the chunk
+            flags &= !(FLAG_CHUNK | FLAG_DROP);
+        }
+    }
+
+    /// As many whole records from the front of the queue as fit in a transfer, empty if
+    /// the queue is empty. Remove them with [`consume`](Self::consume) when they are sent.
+    pub fn packet(&self) -> &[u8] {
+        let mut len = 0;
+        while len < self.len {
+            let record_len = HEADER_LEN + self.data[len] as usize;
+            if len + record_len > self.packet_len {
+                break;
+            }
+            len += record_len;
+        }
+        &self.data[..len]
+    }
+
+    /// Remove `len` bytes of sent records from the front of the queue
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.len);
+        self.data.copy_within(len..self.len, 0);
+        self.len -= len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Drop the queued records, e.g. when the host disconnects
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
@@ -0,0 +1,24 @@
+//! The wire formats between the `rp-rs422-cap` capture device and the `serial-pcap` host
+//! tools: the muxed stream on the USB serial port, bit 7 tagged or SLIP framed, the framed
+//! records on the vendor USB interface and the SD card log, the UART receive buffer of the
+//! firmware and the addresses of the mirrored bus nodes, and with the `x328` feature the
+//! mirrors of the nodes.
+//!
+//! The crate is `no_std` without allocations, so the firmware and the host use the same
+//! encoding, and the same COBS and SLIP codecs for framing records on a byte stream. The `std`
+//! feature implements `std::error::Error` for the error types.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod cobs;
+pub mod framed;
+pub mod mux;
+pub mod nodes;
+pub mod sdlog;
+pub mod slip;
+pub mod uart_buf;
+#[cfg(feature = "x328")]
+pub mod x328_bus;
+
+/// The USB vendor and product id of the capture device
+pub const USB_VID_PID: (u16, u16) = (0x16c0, 0x27dd);
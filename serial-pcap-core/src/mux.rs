@@ -0,0 +1,66 @@
+//! The muxed stream, sent by the capture device on its first USB serial port.
+//!
+//! The 7 bit data from both sides of the bus is sent as it's received, the ctrl bytes with
+//! bit 7 set and the node bytes with it cleared. The control bytes below are inserted by the
+//! device.
+
+/// Set on the bytes from the bus controller
+pub const CTRL_BIT: u8 = 0x80;
+/// A measurement trigger, belongs to the channel of the surrounding data
+pub const TRIG_BYTE: u8 = b'\n';
+/// Sent in place of data which was lost, with [`CTRL_BIT`] set for the controller
+pub const DROP_BYTE: u8 = 0x1a;
+/// A break condition on the line, with [`CTRL_BIT`] set for the controller
+pub const BREAK_BYTE: u8 = 0x1d;
+/// Starts a chunk timing record, see [`ChunkTimes`]
+pub const TIME_BYTE: u8 = 0x1c;
+/// Length of the chunk timing record, including the [`TIME_BYTE`]
+pub const TIME_RECORD_LEN: usize = 9;
+/// The device timestamps wrap around at 2^28 µs
+pub const DEVICE_TIME_MASK: u32 = (1 << 28) - 1;
+
+/// Timing of a chunk of back-to-back bytes, measured by the capture device.
+///
+/// The device sends a timing record before the first byte after a gap: [`TIME_BYTE`], with
+/// [`CTRL_BIT`] set for the ctrl channel, followed by the two times as four 7 bit groups
+/// each, least significant first. The times are in microseconds and wrap at
+/// [`DEVICE_TIME_MASK`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChunkTimes {
+    /// When the first byte of the chunk was received
+    pub first: u32,
+    /// When the last byte of the previous chunk on the channel was received
+    pub prev_last: u32,
+}
+
+impl ChunkTimes {
+    /// The timing record, `ch_bit` is [`CTRL_BIT`] for the ctrl channel and 0 for the node
+    pub fn encode(&self, ch_bit: u8) -> [u8; TIME_RECORD_LEN] {
+        let mut record = [0u8; TIME_RECORD_LEN];
+        record[0] = TIME_BYTE | ch_bit;
+        for (i, time) in [self.first, self.prev_last].into_iter().enumerate() {
+            for (j, b) in record[1 + 4 * i..5 + 4 * i].iter_mut().enumerate() {
+                *b = (time >> (7 * j)) as u8 & 0x7f;
+            }
+        }
+        record
+    }
+
+    pub fn decode(record: &[u8; TIME_RECORD_LEN]) -> Self {
+        let time = |groups: &[u8]| {
+            groups
+                .iter()
+                .rev()
+                .fold(0, |t, &b| (t << 7) | (b & 0x7f) as u32)
+        };
+        Self {
+            first: time(&record[1..5]),
+            prev_last: time(&record[5..9]),
+        }
+    }
+
+    /// Microseconds the channel was idle before the chunk
+    pub fn gap_micros(&self) -> u32 {
+        self.first.wrapping_sub(self.prev_last) & DEVICE_TIME_MASK
+    }
+}
@@ -0,0 +1,132 @@
+//! The X3.28 addresses of the bus nodes which the firmware and the host mirror.
+//!
+//! The same hardware is used on buses with different addressing, so the addresses are
+//! configured: stored with the firmware settings and set with `set <node> <address>` on the
+//! device, or given as `node=address` pairs, e.g. `iobox=31,polenc=12`, to the host tools.
+
+use core::fmt;
+use core::str::FromStr;
+
+/// The address of each mirrored node
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NodeAddresses {
+    pub iobox: u8,
+    /// The polar axis encoder
+    pub pol_enc: u8,
+    /// The declination axis encoder
+    pub decl_enc: u8,
+    /// The polar axis drive
+    pub pol_drive: u8,
+}
+
+impl Default for NodeAddresses {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The node name isn't known or the address isn't 0-99
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeAddressError {
+    UnknownNode,
+    InvalidAddress,
+}
+
+impl fmt::Display for NodeAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl NodeAddressError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeAddressError::UnknownNode => "unknown node",
+            NodeAddressError::InvalidAddress => "node addresses must be 0-99",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NodeAddressError {}
+
+impl NodeAddresses {
+    /// The addresses on the bus of the 25 m telescope
+    pub const DEFAULT: Self = Self {
+        iobox: 31,
+        pol_enc: 12,
+        decl_enc: 22,
+        pol_drive: 11,
+    };
+
+    /// The node names, in the order of `to_bytes`
+    pub const NAMES: [&'static str; 4] = ["iobox", "polenc", "declenc", "poldrive"];
+
+    fn addr_mut(&mut self, name: &str) -> Option<&mut u8> {
+        match name {
+            "iobox" => Some(&mut self.iobox),
+            "polenc" => Some(&mut self.pol_enc),
+            "declenc" => Some(&mut self.decl_enc),
+            "poldrive" => Some(&mut self.pol_drive),
+            _ => None,
+        }
+    }
+
+    /// Change the address of the named node
+    pub fn set(&mut self, name: &str, addr: &str) -> Result<(), NodeAddressError> {
+        let node = self.addr_mut(name).ok_or(NodeAddressError::UnknownNode)?;
+        match addr.parse() {
+            Ok(addr @ 0..=99) => *node = addr,
+            _ => return Err(NodeAddressError::InvalidAddress),
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        [self.iobox, self.pol_enc, self.decl_enc, self.pol_drive]
+    }
+
+    /// None if an address is out of range
+    pub fn from_bytes(bytes: [u8; 4]) -> Option<Self> {
+        if bytes.iter().any(|&a| a > 99) {
+            return None;
+        }
+        Some(Self {
+            iobox: bytes[0],
+            pol_enc: bytes[1],
+            decl_enc: bytes[2],
+            pol_drive: bytes[3],
+        })
+    }
+}
+
+/// Comma separated `node=address` pairs, the nodes which aren't listed keep their default
+/// address
+impl FromStr for NodeAddresses {
+    type Err = NodeAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut addresses = Self::DEFAULT;
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, addr) = pair
+                .split_once('=')
+                .ok_or(NodeAddressError::InvalidAddress)?;
+            addresses.set(name.trim(), addr.trim())?;
+        }
+        Ok(addresses)
+    }
+}
+
+/// e.g. `iobox 31 polenc 12 declenc 22 poldrive 11`
+impl fmt::Display for NodeAddresses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs = self.to_bytes();
+        for (i, (name, addr)) in Self::NAMES.iter().zip(addrs).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{name} {addr}")?;
+        }
+        Ok(())
+    }
+}
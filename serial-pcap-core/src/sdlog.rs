@@ -0,0 +1,24 @@
+//! The log files written to an SD card by the capture device, when it runs without a host.
+//!
+//! The file starts with [`SD_LOG_MAGIC`], followed by records of a little endian u64
+//! timestamp in microseconds since the device booted, a length byte, and that many bytes
+//! of the muxed stream, in the same format as sent over USB.
+
+pub const SD_LOG_MAGIC: &[u8; 8] = b"RSCAPLG1";
+
+/// The record header, timestamp and length
+pub const HEADER_LEN: usize = 9;
+
+pub fn encode_header(micros: u64, len: u8) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&micros.to_le_bytes());
+    header[8] = len;
+    header
+}
+
+/// The timestamp and the data length
+pub fn decode_header(header: &[u8; HEADER_LEN]) -> (u64, u8) {
+    let mut micros = [0u8; 8];
+    micros.copy_from_slice(&header[..8]);
+    (u64::from_le_bytes(micros), header[8])
+}
@@ -0,0 +1,216 @@
+//! The SLIP framed variant of the muxed stream, for buses with 8 bit data.
+//!
+//! The bit 7 channel tagging of the [`mux`](crate::mux) stream leaves only 7 bits for the data,
+//! so with `set framing slip` the capture device sends SLIP frames instead. Each frame is
+//! [`END`], a header byte, the payload and [`END`], with the [`END`] and [`ESC`] bytes in
+//! the header and payload escaped. The header is the frame kind, with
+//! [`CTRL_BIT`](crate::mux::CTRL_BIT) set for the controller side of the bus. Empty frames
+//! are ignored, so the receiver syncs on the next [`END`] after line noise or a lost byte.
+
+use core::fmt;
+
+use crate::mux::{ChunkTimes, CTRL_BIT};
+
+/// Ends a frame, and starts the next one
+pub const END: u8 = 0xc0;
+/// Starts an escape sequence
+pub const ESC: u8 = 0xdb;
+/// [`ESC`] [`ESC_END`] is an [`END`] byte in the frame
+pub const ESC_END: u8 = 0xdc;
+/// [`ESC`] [`ESC_ESC`] is an [`ESC`] byte in the frame
+pub const ESC_ESC: u8 = 0xdd;
+
+/// The payload is bus data
+pub const KIND_DATA: u8 = 0x01;
+/// Data from the channel was lost before the next data, the payload is empty
+pub const KIND_DROP: u8 = 0x02;
+/// A break condition on the line after the data, the payload is empty
+pub const KIND_BREAK: u8 = 0x03;
+/// The timing of the next chunk of data, the two times of [`ChunkTimes`] as little endian u32
+pub const KIND_TIME: u8 = 0x04;
+/// A measurement trigger, the payload is empty. Triggers don't belong to a channel, and are
+/// sent without [`CTRL_BIT`].
+pub const KIND_TRIGGER: u8 = 0x05;
+
+/// The most data bytes in one frame, longer data is sent in several frames
+pub const MAX_DATA_LEN: usize = 32;
+/// The most bytes of an encoded frame, with every byte escaped
+pub const MAX_FRAME_LEN: usize = 2 + 2 * (1 + MAX_DATA_LEN);
+
+/// A frame of the stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Frame<'a> {
+    Data(&'a [u8]),
+    Drop,
+    Break,
+    Time(ChunkTimes),
+    Trigger,
+}
+
+impl<'a> Frame<'a> {
+    /// The frame kind, without the channel
+    pub fn kind(&self) -> u8 {
+        match self {
+            Frame::Data(_) => KIND_DATA,
+            Frame::Drop => KIND_DROP,
+            Frame::Break => KIND_BREAK,
+            Frame::Time(_) => KIND_TIME,
+            Frame::Trigger => KIND_TRIGGER,
+        }
+    }
+
+    /// Encode the frame, `ch_bit` is [`CTRL_BIT`] for the ctrl channel and 0 for the node.
+    /// The bytes are passed to `push`, at most [`MAX_FRAME_LEN`] of them when the data is
+    /// at most [`MAX_DATA_LEN`] bytes.
+    pub fn encode(&self, ch_bit: u8, mut push: impl FnMut(u8)) {
+        let times;
+        let payload = match self {
+            Frame::Data(data) => data,
+            Frame::Time(chunk) => {
+                times = time_payload(chunk);
+                &times[..]
+            }
+            _ => &[][..],
+        };
+        push(END);
+        for &b in [self.kind() | ch_bit].iter().chain(payload) {
+            match b {
+                END => [ESC, ESC_END].into_iter().for_each(&mut push),
+                ESC => [ESC, ESC_ESC].into_iter().for_each(&mut push),
+                b => push(b),
+            }
+        }
+        push(END);
+    }
+
+    /// Decode the header and payload of a received frame, returns the frame and whether it's
+    /// from the ctrl channel.
+    pub fn decode(frame: &'a [u8]) -> Result<(Self, bool), FrameError> {
+        let (&header, payload) = frame.split_first().ok_or(FrameError::BadLength)?;
+        let ctrl = header & CTRL_BIT != 0;
+        let frame = match (header & !CTRL_BIT, payload.len()) {
+            (KIND_DATA, _) => Frame::Data(payload),
+            (KIND_DROP, 0) => Frame::Drop,
+            (KIND_BREAK, 0) => Frame::Break,
+            (KIND_TIME, 8) => {
+                let word = |i: usize| u32::from_le_bytes(payload[i..i + 4].try_into().unwrap());
+                Frame::Time(ChunkTimes {
+                    first: word(0),
+                    prev_last: word(4),
+                })
+            }
+            (KIND_TRIGGER, 0) => Frame::Trigger,
+            (KIND_DROP | KIND_BREAK | KIND_TIME | KIND_TRIGGER, _) => {
+                return Err(FrameError::BadLength)
+            }
+            _ => return Err(FrameError::UnknownKind),
+        };
+        Ok((frame, ctrl))
+    }
+}
+
+fn time_payload(chunk: &ChunkTimes) -> [u8; 8] {
+    let mut payload = [0u8; 8];
+    payload[..4].copy_from_slice(&chunk.first.to_le_bytes());
+    payload[4..].copy_from_slice(&chunk.prev_last.to_le_bytes());
+    payload
+}
+
+/// A received frame which can't be decoded, it's skipped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// Longer than a frame from the device, the rest of it is skipped
+    TooLong,
+    /// [`ESC`] followed by something else than [`ESC_END`] or [`ESC_ESC`]
+    BadEscape,
+    UnknownKind,
+    /// The payload length doesn't match the frame kind
+    BadLength,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FrameError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameError::TooLong => "frame too long",
+            FrameError::BadEscape => "invalid escape sequence",
+            FrameError::UnknownKind => "unknown frame kind",
+            FrameError::BadLength => "invalid frame length",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+/// Collects the bytes of the stream into frames
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    buf: [u8; 1 + MAX_DATA_LEN],
+    len: usize,
+    escaped: bool,
+    error: Option<FrameError>,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; 1 + MAX_DATA_LEN],
+            len: 0,
+            escaped: false,
+            error: None,
+        }
+    }
+
+    /// Feed one byte, returns the frame or the error when it ends a frame.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<(Frame<'_>, bool), FrameError>> {
+        if byte == END {
+            let len = core::mem::take(&mut self.len);
+            let error = self
+                .error
+                .take()
+                .or(self.escaped.then_some(FrameError::BadEscape));
+            self.escaped = false;
+            return match (error, len) {
+                (Some(err), _) => Some(Err(err)),
+                (None, 0) => None,
+                (None, len) => Some(Frame::decode(&self.buf[..len])),
+            };
+        }
+        if self.error.is_some() {
+            return None;
+        }
+        let byte = match (core::mem::take(&mut self.escaped), byte) {
+            (false, ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (false, b) => b,
+            (true, ESC_END) => END,
+            (true, ESC_ESC) => ESC,
+            (true, _) => {
+                self.error = Some(FrameError::BadEscape);
+                return None;
+            }
+        };
+        match self.buf.get_mut(self.len) {
+            Some(b) => {
+                *b = byte;
+                self.len += 1;
+            }
+            None => self.error = Some(FrameError::TooLong),
+        }
+        None
+    }
+}
@@ -0,0 +1,107 @@
+//! A fixed size receive buffer, which the UART data is read into and the protocol scanner
+//! consumes from.
+
+use core::ops::Deref;
+
+/// A buffer of the last `N` received bytes, which derefs to the unconsumed data.
+///
+/// The data is read directly into [`UartBuf::tail_slice`] and committed with
+/// [`UartBuf::incr_len`]. When there isn't room for new data, the oldest unconsumed bytes
+/// are discarded and counted, see [`UartBuf::take_overflow`].
+pub struct UartBuf<const N: usize> {
+    len: usize,
+    read_pos: usize,
+    /// Bytes discarded since the last `take_overflow`
+    overflow: usize,
+    data: [u8; N],
+}
+
+impl<const N: usize> Default for UartBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for UartBuf<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data[self.read_pos..self.read_pos + self.len]
+    }
+}
+
+impl<const N: usize> UartBuf<N> {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            read_pos: 0,
+            overflow: 0,
+            data: [0; N],
+        }
+    }
+
+    /// The free space after the data, at least `min_cap` bytes (limited to `N`). The oldest
+    /// data is discarded if there isn't enough free space.
+    pub fn tail_slice(&mut self, min_cap: usize) -> &mut [u8] {
+        let min_cap = min_cap.min(N);
+        if self.is_empty() {
+            self.read_pos = 0;
+        }
+        let spare_cap = N - self.len;
+        if spare_cap < min_cap {
+            self.overflow += min_cap - spare_cap;
+            self.consume(min_cap - spare_cap);
+        }
+        if self.tail_capacity() < min_cap {
+            self.data
+                .copy_within(self.read_pos..self.read_pos + self.len, 0);
+            self.read_pos = 0;
+        }
+        let wr_pos = self.read_pos + self.len;
+        &mut self.data[wr_pos..]
+    }
+
+    fn tail_capacity(&self) -> usize {
+        N - (self.read_pos + self.len)
+    }
+
+    /// Commit `new` bytes written to the tail slice
+    pub fn incr_len(&mut self, new: usize) {
+        let new = self.tail_capacity().min(new);
+        self.len += new;
+    }
+
+    /// Append the data. If it doesn't fit, the oldest data is discarded, including the
+    /// start of `data` if it is longer than the buffer.
+    pub fn write(&mut self, data: &[u8]) {
+        let excess = data.len().saturating_sub(N);
+        self.overflow += excess;
+        let data = &data[excess..];
+        self.tail_slice(data.len())[..data.len()].copy_from_slice(data);
+        self.incr_len(data.len());
+    }
+
+    /// Remove `len` bytes from the start of the data
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.len);
+        self.read_pos += len;
+        self.len -= len;
+    }
+
+    /// The number of bytes discarded to make room for new data since the last call
+    pub fn take_overflow(&mut self) -> usize {
+        core::mem::take(&mut self.overflow)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
@@ -0,0 +1,32 @@
+use crate::x328_bus::{NodeMirror, UpdateEvent};
+use x328_proto::{Address, Parameter, Value};
+
+/// The drive of the polar axis
+#[derive(Debug)]
+pub struct PolarDrive {
+    addr: Address,
+    pub speed_cmd: u16,
+}
+
+impl PolarDrive {
+    pub const fn new(addr: Address) -> Self {
+        Self { addr, speed_cmd: 0 }
+    }
+}
+
+impl NodeMirror for PolarDrive {
+    fn addr(&self) -> Address {
+        self.addr
+    }
+
+    fn update_parameter(&mut self, p: Parameter, v: Value) -> Option<UpdateEvent> {
+        match *p {
+            118 => {
+                self.speed_cmd = *v as u16;
+                UpdateEvent::PolarSpeedCmd(self.speed_cmd)
+            }
+            _ => return None,
+        }
+        .into()
+    }
+}
@@ -1,31 +1,30 @@
 use crate::x328_bus::{NodeMirror, UpdateEvent};
 use core::marker::PhantomData;
-use x328_proto::{addr, Address, Parameter, Value};
+use x328_proto::{Address, Parameter, Value};
 
 pub struct Polar;
 pub struct Declination;
 
 pub struct Encoder<Pos> {
+    addr: Address,
     value: i32, // 100-dels grader
     _pos: PhantomData<Pos>,
 }
 
 impl<Pos> Encoder<Pos> {
-    pub const fn new() -> Self {
+    pub const fn new(addr: Address) -> Self {
         Self {
+            addr,
             value: 0,
             _pos: PhantomData,
         }
     }
 }
-impl<Pos> Default for Encoder<Pos> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
 
 impl NodeMirror for Encoder<Polar> {
-    const ADDR: Address = addr(12);
+    fn addr(&self) -> Address {
+        self.addr
+    }
 
     fn update_parameter(&mut self, p: Parameter, v: Value) -> Option<UpdateEvent> {
         match *p {
@@ -40,7 +39,9 @@ impl NodeMirror for Encoder<Polar> {
 }
 
 impl NodeMirror for Encoder<Declination> {
-    const ADDR: Address = addr(22);
+    fn addr(&self) -> Address {
+        self.addr
+    }
 
     fn update_parameter(&mut self, p: Parameter, v: Value) -> Option<UpdateEvent> {
         match *p {
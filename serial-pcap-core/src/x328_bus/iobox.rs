@@ -1,6 +1,6 @@
 use crate::x328_bus::{NodeMirror, UpdateEvent};
 use enumflags2::{bitflags, BitFlags};
-use x328_proto::{addr, Address, Parameter, Value};
+use x328_proto::{Address, Parameter, Value};
 
 #[bitflags]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -72,8 +72,9 @@ pub enum InputBit {
     EastStowLocked = 1 << 0,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct IoBox {
+    addr: Address,
     pub inputs: BitFlags<InputBit>,
     pub outputs: BitFlags<OutputBit>,
     pub cmd_reg: BitFlags<CommandBit>,
@@ -82,8 +83,9 @@ pub struct IoBox {
 }
 
 impl IoBox {
-    pub const fn new() -> Self {
+    pub const fn new(addr: Address) -> Self {
         Self {
+            addr,
             inputs: BitFlags::EMPTY,
             outputs: BitFlags::EMPTY,
             cmd_reg: BitFlags::EMPTY,
@@ -94,7 +96,9 @@ impl IoBox {
 }
 
 impl NodeMirror for IoBox {
-    const ADDR: Address = addr(31);
+    fn addr(&self) -> Address {
+        self.addr
+    }
 
     fn update_parameter(&mut self, p: Parameter, v: Value) -> Option<UpdateEvent> {
         match *p {
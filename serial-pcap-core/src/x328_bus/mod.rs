@@ -0,0 +1,78 @@
+//! Mirrors of the X3.28 bus nodes, which follow the node parameters from the commands and
+//! responses on the bus. The capture firmware shows them on its display and the host tools
+//! keep them with the other parameters of the nodes. Enabled by the `x328` feature.
+
+use enumflags2::BitFlags;
+
+use crate::nodes::NodeAddresses;
+use crate::x328_bus::drives::PolarDrive;
+use crate::x328_bus::encoders::{Declination, Encoder, Polar};
+use crate::x328_bus::iobox::{CommandBit, InputBit, OutputBit};
+use iobox::IoBox;
+use x328_proto::{addr, Address, Parameter, Value};
+
+pub mod drives;
+pub mod encoders;
+pub mod iobox;
+
+// Tracks all the nodes on the bus in the 25m
+pub struct FieldBus {
+    pub iobox: IoBox,
+    pub pol_enc: Encoder<Polar>,
+    pub decl_enc: Encoder<Declination>,
+    pub pol_drv: PolarDrive,
+}
+
+/// A change of a mirrored node
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UpdateEvent {
+    StowPress(u16, u16),
+    IoboxInputs(BitFlags<InputBit>),
+    IoboxCmd(BitFlags<CommandBit>),
+    IoboxOutputs(BitFlags<OutputBit>),
+    PolarSpeedCmd(u16),
+    PolarEncoder(i32),
+    DeclinationEncoder(i32),
+}
+
+impl FieldBus {
+    pub const fn new(addrs: &NodeAddresses) -> Self {
+        Self {
+            iobox: IoBox::new(addr(addrs.iobox)),
+            pol_enc: Encoder::new(addr(addrs.pol_enc)),
+            decl_enc: Encoder::new(addr(addrs.decl_enc)),
+            pol_drv: PolarDrive::new(addr(addrs.pol_drive)),
+        }
+    }
+
+    /// The mirrors of the nodes, a new node is added here
+    pub fn nodes(&mut self) -> [&mut dyn NodeMirror; 4] {
+        [
+            &mut self.iobox,
+            &mut self.pol_enc,
+            &mut self.decl_enc,
+            &mut self.pol_drv,
+        ]
+    }
+
+    pub fn update_parameter(&mut self, a: Address, p: Parameter, v: Value) -> Option<UpdateEvent> {
+        update_nodes(&mut self.nodes(), a, p, v)
+    }
+}
+
+/// Update the mirror of the node at address `a`, if there is one
+pub fn update_nodes(
+    nodes: &mut [&mut dyn NodeMirror],
+    a: Address,
+    p: Parameter,
+    v: Value,
+) -> Option<UpdateEvent> {
+    let node = nodes.iter_mut().find(|n| n.addr() == a)?;
+    node.update_parameter(p, v)
+}
+
+/// Follows the parameters of a node from the commands and responses on the bus
+pub trait NodeMirror {
+    fn addr(&self) -> Address;
+    fn update_parameter(&mut self, p: Parameter, v: Value) -> Option<UpdateEvent>;
+}
@@ -0,0 +1,99 @@
+use proptest::prelude::*;
+use serial_pcap_core::cobs::{decode, encode, max_encoded_len, CobsDecoder, CobsError, DELIMITER};
+
+fn encoded(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0; max_encoded_len(data.len())];
+    let len = encode(data, &mut out);
+    out.truncate(len);
+    out
+}
+
+/// The records in the stream, decoded by a CobsDecoder
+fn decode_stream<const N: usize>(stream: &[u8]) -> Vec<Result<Vec<u8>, CobsError>> {
+    let mut decoder = CobsDecoder::<N>::new();
+    stream
+        .iter()
+        .filter_map(|&b| Some(decoder.feed(b)?.map(|r| r.to_vec())))
+        .collect()
+}
+
+#[test]
+fn test_known_encodings() {
+    let run = |r: std::ops::RangeInclusive<u8>| r.collect::<Vec<_>>();
+    let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        (vec![], vec![1]),
+        (vec![0], vec![1, 1]),
+        (vec![0, 0], vec![1, 1, 1]),
+        (vec![0, 0x11, 0], vec![1, 2, 0x11, 1]),
+        (vec![0x11, 0x22, 0, 0x33], vec![3, 0x11, 0x22, 2, 0x33]),
+        (
+            vec![0x11, 0x22, 0x33, 0x44],
+            vec![5, 0x11, 0x22, 0x33, 0x44],
+        ),
+        (run(1..=254), [vec![0xff], run(1..=254)].concat()),
+        (run(0..=254), [vec![1, 0xff], run(1..=254)].concat()),
+        (
+            run(1..=255),
+            [vec![0xff], run(1..=254), vec![2, 0xff]].concat(),
+        ),
+    ];
+    for (data, expected) in cases {
+        assert_eq!(encoded(&data), expected, "encoding {data:02x?}");
+        let mut out = vec![0; expected.len()];
+        let len = decode(&expected, &mut out).unwrap();
+        assert_eq!(out[..len], data);
+    }
+}
+
+#[test]
+fn test_invalid_records() {
+    let mut out = [0; 8];
+    assert_eq!(decode(&[3, 0x11], &mut out), Err(CobsError::Truncated));
+    assert_eq!(decode(&[3, 0x11, 0], &mut out), Err(CobsError::ZeroByte));
+    assert_eq!(decode(&[2, 0x11, 0], &mut out), Err(CobsError::ZeroByte));
+    assert_eq!(
+        decode(&[9, 1, 2, 3, 4, 5, 6, 7, 8], &mut out[..4]),
+        Err(CobsError::TooLong)
+    );
+}
+
+#[test]
+fn test_stream_resync() {
+    let mut stream = vec![0x11, 0x22]; // the end of a record, before the first delimiter
+    stream.push(DELIMITER);
+    stream.extend(encoded(b"\x01\x00\x02"));
+    stream.extend([DELIMITER, DELIMITER]);
+    stream.extend([5, 0x11]); // cut off
+    stream.push(DELIMITER);
+    stream.extend(encoded(&[0x33; 20])); // longer than the buffer
+    stream.push(DELIMITER);
+    stream.extend(encoded(b"ok"));
+    stream.push(DELIMITER);
+    assert_eq!(
+        decode_stream::<16>(&stream),
+        [
+            Err(CobsError::Truncated),
+            Ok(b"\x01\x00\x02".to_vec()),
+            Err(CobsError::Truncated),
+            Err(CobsError::TooLong),
+            Ok(b"ok".to_vec()),
+        ]
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_round_trip(records in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..600), 1..4)) {
+        let mut stream = vec![];
+        for record in &records {
+            let encoded = encoded(record);
+            prop_assert!(encoded.len() <= max_encoded_len(record.len()));
+            prop_assert!(!encoded.contains(&DELIMITER));
+            stream.extend(encoded);
+            stream.push(DELIMITER);
+        }
+        let decoded = decode_stream::<1024>(&stream);
+        let expected: Vec<_> = records.into_iter().map(Ok).collect();
+        prop_assert_eq!(decoded, expected);
+    }
+}
@@ -0,0 +1,41 @@
+use serial_pcap_core::nodes::{NodeAddressError, NodeAddresses};
+
+#[test]
+fn test_parse() {
+    let addrs: NodeAddresses = "iobox=30, poldrive=5".parse().unwrap();
+    assert_eq!(
+        addrs,
+        NodeAddresses {
+            iobox: 30,
+            pol_drive: 5,
+            ..NodeAddresses::DEFAULT
+        }
+    );
+    assert_eq!(
+        addrs.to_string(),
+        "iobox 30 polenc 12 declenc 22 poldrive 5"
+    );
+    assert_eq!("".parse(), Ok(NodeAddresses::DEFAULT));
+    assert_eq!(
+        "motor=3".parse::<NodeAddresses>(),
+        Err(NodeAddressError::UnknownNode)
+    );
+    assert_eq!(
+        "iobox=100".parse::<NodeAddresses>(),
+        Err(NodeAddressError::InvalidAddress)
+    );
+    assert_eq!(
+        "iobox".parse::<NodeAddresses>(),
+        Err(NodeAddressError::InvalidAddress)
+    );
+}
+
+#[test]
+fn test_bytes() {
+    let mut addrs = NodeAddresses::DEFAULT;
+    addrs.set("declenc", "7").unwrap();
+    let bytes = addrs.to_bytes();
+    assert_eq!(bytes, [31, 12, 7, 11]);
+    assert_eq!(NodeAddresses::from_bytes(bytes), Some(addrs));
+    assert_eq!(NodeAddresses::from_bytes([31, 12, 0xff, 11]), None);
+}
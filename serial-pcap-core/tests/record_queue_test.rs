@@ -0,0 +1,64 @@
+use serial_pcap_core::framed::{
+    split_record, RecordHeader, RecordQueue, FLAG_BUS2, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP,
+    HEADER_LEN,
+};
+
+/// The headers and payloads of the records in a packet
+fn records(mut packet: &[u8]) -> Vec<(RecordHeader, Vec<u8>)> {
+    let mut records = vec![];
+    while !packet.is_empty() {
+        let (header, payload, rest) = split_record(packet).unwrap();
+        records.push((header, payload.to_vec()));
+        packet = rest;
+    }
+    records
+}
+
+#[test]
+fn test_whole_records() {
+    let mut queue = RecordQueue::<256>::new(32);
+    assert!(queue.packet().is_empty());
+    // split in records of at most 26 bytes
+    queue.push(FLAG_CTRL | FLAG_CHUNK, 100, &[1; 40]);
+    queue.push(0, 200, b"abc");
+    let packet = queue.packet();
+    assert_eq!(packet.len(), HEADER_LEN + 26);
+    let first = records(packet);
+    assert_eq!(first[0].0.flags, FLAG_CTRL | FLAG_CHUNK);
+    assert_eq!(first[0].0.time, 100);
+    queue.consume(packet.len());
+
+    // the rest of the data and the next record fit in one packet
+    let rest = records(queue.packet());
+    assert_eq!(rest.len(), 2);
+    assert_eq!(rest[0].0.flags, FLAG_CTRL);
+    assert_eq!(rest[0].1, [1; 14]);
+    assert_eq!((rest[1].0.time, &rest[1].1[..]), (200, &b"abc"[..]));
+    queue.consume(queue.packet().len());
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_large_packets() {
+    // the payload length is a byte
+    let mut queue = RecordQueue::<1024>::new(1400);
+    queue.push(0, 0, &[7; 300]);
+    let records = records(queue.packet());
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].1.len(), 255);
+    assert_eq!(records[1].1.len(), 45);
+}
+
+#[test]
+fn test_drop_flag() {
+    let mut queue = RecordQueue::<50>::new(64);
+    queue.push(FLAG_BUS2, 1, &[0; 30]);
+    // doesn't fit, reported with the next record from the channel
+    queue.push(FLAG_BUS2, 2, &[0; 10]);
+    queue.push(0, 3, b"node");
+    queue.consume(HEADER_LEN + 30);
+    queue.push(FLAG_BUS2, 4, b"more");
+    let records = records(queue.packet());
+    let flags: Vec<_> = records.iter().map(|(h, _)| (h.time, h.flags)).collect();
+    assert_eq!(flags, [(3, 0), (4, FLAG_BUS2 | FLAG_DROP)]);
+}
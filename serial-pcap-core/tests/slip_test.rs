@@ -0,0 +1,75 @@
+use proptest::prelude::*;
+use serial_pcap_core::mux::{ChunkTimes, CTRL_BIT};
+use serial_pcap_core::slip::{Frame, FrameDecoder, FrameError, END, ESC, MAX_FRAME_LEN};
+
+fn encode(frame: Frame, ch_bit: u8) -> Vec<u8> {
+    let mut out = vec![];
+    frame.encode(ch_bit, |b| out.push(b));
+    out
+}
+
+/// The frames in the stream, with the data copied out of the decoder
+fn decode(stream: &[u8]) -> Vec<Result<(String, bool), FrameError>> {
+    let mut decoder = FrameDecoder::new();
+    stream
+        .iter()
+        .filter_map(|&b| {
+            let frame = decoder.feed(b)?;
+            Some(frame.map(|(frame, ctrl)| (format!("{frame:?}"), ctrl)))
+        })
+        .collect()
+}
+
+#[test]
+fn test_escaped_data() {
+    let stream = encode(Frame::Data(&[0x41, END, ESC, 0xff]), CTRL_BIT);
+    assert_eq!(stream, [END, 0x81, 0x41, ESC, 0xdc, ESC, 0xdd, 0xff, END]);
+    let frames = decode(&stream);
+    assert_eq!(
+        frames,
+        [Ok((
+            format!("{:?}", Frame::Data(&[0x41, END, ESC, 0xff])),
+            true
+        ))]
+    );
+}
+
+#[test]
+fn test_resync_after_errors() {
+    let chunk = ChunkTimes {
+        first: 0x0123_4567,
+        prev_last: 0x0fff_ffff,
+    };
+    let mut stream = vec![0x12, 0x34]; // the tail of a frame, before the first END
+    stream.extend(encode(Frame::Time(chunk), 0));
+    stream.extend([END, ESC, 0x41, END]);
+    stream.extend([END, 0x7f, END]);
+    stream.extend([END, 0x02, 0x00, END]);
+    stream.extend([END, 0x01]);
+    stream.extend([0x55; 40]);
+    stream.extend(encode(Frame::Trigger, 0));
+    let frames = decode(&stream);
+    assert_eq!(
+        frames,
+        [
+            Err(FrameError::UnknownKind),
+            Ok((format!("{:?}", Frame::Time(chunk)), false)),
+            Err(FrameError::BadEscape),
+            Err(FrameError::UnknownKind),
+            Err(FrameError::BadLength),
+            Err(FrameError::TooLong),
+            Ok((format!("{:?}", Frame::Trigger), false)),
+        ]
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_data_round_trip(data in prop::collection::vec(any::<u8>(), 0..=32), ctrl: bool) {
+        let ch_bit = if ctrl { CTRL_BIT } else { 0 };
+        let stream = encode(Frame::Data(&data), ch_bit);
+        prop_assert!(stream.len() <= MAX_FRAME_LEN);
+        prop_assert_eq!(stream.iter().filter(|&&b| b == END).count(), 2);
+        prop_assert_eq!(decode(&stream), vec![Ok((format!("{:?}", Frame::Data(&data)), ctrl))]);
+    }
+}
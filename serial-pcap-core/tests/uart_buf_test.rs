@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+use serial_pcap_core::uart_buf::UartBuf;
+
+#[test]
+fn test_write_consume() {
+    let mut buf = UartBuf::<8>::new();
+    assert!(buf.is_empty());
+    buf.write(b"abc");
+    buf.write(b"de");
+    assert_eq!(&*buf, b"abcde");
+    buf.consume(2);
+    assert_eq!(&*buf, b"cde");
+    buf.consume(10);
+    assert!(buf.is_empty());
+    assert_eq!(buf.take_overflow(), 0);
+}
+
+#[test]
+fn test_compaction() {
+    // the data is moved to the start when the tail is too short
+    let mut buf = UartBuf::<8>::new();
+    buf.write(b"abcdef");
+    buf.consume(4);
+    buf.write(b"ghij");
+    assert_eq!(&*buf, b"efghij");
+    assert_eq!(buf.take_overflow(), 0);
+}
+
+#[test]
+fn test_overflow() {
+    let mut buf = UartBuf::<8>::new();
+    buf.write(b"abcdef");
+    buf.write(b"ghij");
+    assert_eq!(&*buf, b"cdefghij");
+    assert_eq!(buf.take_overflow(), 2);
+    assert_eq!(buf.take_overflow(), 0);
+
+    // only the end of data longer than the buffer is kept
+    buf.write(b"0123456789");
+    assert_eq!(&*buf, b"23456789");
+    assert_eq!(buf.take_overflow(), 10);
+}
+
+#[test]
+fn test_tail_slice() {
+    let mut buf = UartBuf::<8>::new();
+    buf.write(b"abcde");
+    buf.consume(1);
+    let tail = buf.tail_slice(1);
+    assert_eq!(tail.len(), 3);
+    tail[..2].copy_from_slice(b"fg");
+    buf.incr_len(2);
+    assert_eq!(&*buf, b"bcdefg");
+    // more than the tail, but not more than the free space
+    assert_eq!(buf.tail_slice(2).len(), 2);
+    assert_eq!(&*buf, b"bcdefg");
+    // committing more than the tail is limited
+    buf.incr_len(5);
+    assert_eq!(buf.len(), 8);
+    assert_eq!(buf.take_overflow(), 0);
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Write(Vec<u8>),
+    Read { min_cap: usize, data: Vec<u8> },
+    Consume(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..24).prop_map(Op::Write),
+        (0..24usize, prop::collection::vec(any::<u8>(), 0..24))
+            .prop_map(|(min_cap, data)| Op::Read { min_cap, data }),
+        (0..24usize).prop_map(Op::Consume),
+    ]
+}
+
+/// The buffer as a queue which drops the oldest bytes
+struct Model {
+    bytes: VecDeque<u8>,
+    overflow: usize,
+}
+
+impl Model {
+    fn make_room(&mut self, n: usize) {
+        let n = n.min(16);
+        while 16 - self.bytes.len() < n {
+            self.bytes.pop_front();
+            self.overflow += 1;
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_matches_queue(ops in prop::collection::vec(op(), 0..64)) {
+        let mut buf = UartBuf::<16>::new();
+        let mut model = Model { bytes: VecDeque::new(), overflow: 0 };
+        for op in ops {
+            match op {
+                Op::Write(data) => {
+                    buf.write(&data);
+                    let excess = data.len().saturating_sub(16);
+                    model.overflow += excess;
+                    model.make_room(data.len());
+                    model.bytes.extend(&data[excess..]);
+                }
+                Op::Read { min_cap, data } => {
+                    // like a UART read into the tail
+                    let tail = buf.tail_slice(min_cap);
+                    prop_assert!(tail.len() >= min_cap.min(16));
+                    let n = data.len().min(tail.len());
+                    tail[..n].copy_from_slice(&data[..n]);
+                    buf.incr_len(n);
+                    model.make_room(min_cap);
+                    model.bytes.extend(&data[..n]);
+                }
+                Op::Consume(n) => {
+                    buf.consume(n);
+                    let n = n.min(model.bytes.len());
+                    model.bytes.drain(..n);
+                }
+            }
+            prop_assert_eq!(&*buf, model.bytes.make_contiguous());
+            prop_assert_eq!(buf.len(), model.bytes.len());
+            prop_assert_eq!(buf.take_overflow(), std::mem::take(&mut model.overflow));
+        }
+    }
+}
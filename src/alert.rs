@@ -0,0 +1,360 @@
+//! Alert rules evaluated on the decoded bus traffic, to use the tap as a simple bus monitor.
+//!
+//! The rules are read from a text file with a rule per line, e.g.
+//! ```text
+//! # stow pressure too high
+//! addr 31 param 401 > 180 for 5s
+//! addr 31 param 402 <= 10
+//! no response from addr 12 for 30s
+//! ```
+//! A value rule fires when the values read or written for the parameter have been outside the
+//! limit for the time given, or at once without `for`, and the comparison is one of `>`, `>=`,
+//! `<`, `<=`, `==` and `!=`. A response rule fires when the node hasn't responded, with a value
+//! or EOT or NAK, for the time given. A rule fires once, and again after the condition has
+//! cleared.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::decode::{BusEvent, Transaction, X328Decoder};
+use crate::{parse_duration, SerialPacket};
+
+/// The comparison of a value rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn holds(self, value: i32, limit: i32) -> bool {
+        match self {
+            Comparison::Greater => value > limit,
+            Comparison::GreaterOrEqual => value >= limit,
+            Comparison::Less => value < limit,
+            Comparison::LessOrEqual => value <= limit,
+            Comparison::Equal => value == limit,
+            Comparison::NotEqual => value != limit,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            _ => bail!("Unknown comparison '{s}', expected >, >=, <, <=, == or !=."),
+        })
+    }
+}
+
+/// An alert rule, a line of the rules file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// The parameter value compared to a limit, for at least `duration`
+    Value {
+        addr: u8,
+        param: i16,
+        cmp: Comparison,
+        limit: i32,
+        duration: Duration,
+    },
+    /// No response from the node for `duration`
+    NoResponse { addr: u8, duration: Duration },
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    /// Parse "addr A param P CMP LIMIT [for TIME]" or "no response from addr A for TIME"
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        let number = |word: &str, what: &str| -> Result<i64> {
+            word.parse()
+                .with_context(|| format!("Invalid {what} '{word}'."))
+        };
+        let addr = |word: &str| -> Result<u8> {
+            let addr = number(word, "node address")?;
+            u8::try_from(addr)
+                .ok()
+                .filter(|a| *a < 100)
+                .with_context(|| format!("Invalid node address '{word}'."))
+        };
+        match words[..] {
+            ["addr", a, "param", p, cmp, limit, ref rest @ ..] => {
+                let duration = match rest {
+                    [] => Duration::ZERO,
+                    ["for", time] => parse_duration(time)?,
+                    _ => bail!("Expected 'for TIME' after the limit."),
+                };
+                Ok(Rule::Value {
+                    addr: addr(a)?,
+                    param: i16::try_from(number(p, "parameter")?)
+                        .with_context(|| format!("Invalid parameter '{p}'."))?,
+                    cmp: cmp.parse()?,
+                    limit: i32::try_from(number(limit, "limit")?)
+                        .with_context(|| format!("Invalid limit '{limit}'."))?,
+                    duration,
+                })
+            }
+            ["no", "response", "from", "addr", a, "for", time] => Ok(Rule::NoResponse {
+                addr: addr(a)?,
+                duration: parse_duration(time)?,
+            }),
+            _ => bail!(
+                "Expected 'addr A param P CMP LIMIT [for TIME]' or \
+                 'no response from addr A for TIME'."
+            ),
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Value {
+                addr,
+                param,
+                cmp,
+                limit,
+                duration,
+            } => {
+                write!(f, "addr {addr} param {param} {} {limit}", cmp.as_str())?;
+                match duration.is_zero() {
+                    true => Ok(()),
+                    false => write!(f, " for {duration:?}"),
+                }
+            }
+            Rule::NoResponse { addr, duration } => {
+                write!(f, "no response from addr {addr} for {duration:?}")
+            }
+        }
+    }
+}
+
+/// The rules of a rules file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlertRules {
+    pub rules: Vec<Rule>,
+}
+
+impl AlertRules {
+    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let text = std::fs::read_to_string(filename)
+            .with_context(|| format!("Failed to read {}.", filename.display()))?;
+        text.parse()
+            .with_context(|| format!("Failed to parse {}.", filename.display()))
+    }
+}
+
+impl FromStr for AlertRules {
+    type Err = anyhow::Error;
+
+    /// A rule per line, empty lines and the text after a # are ignored
+    fn from_str(text: &str) -> Result<Self> {
+        let mut rules = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                rules.push(line.parse().with_context(|| format!("Line {}", n + 1))?);
+            }
+        }
+        Ok(Self { rules })
+    }
+}
+
+/// A fired rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub time: DateTime<Utc>,
+    pub rule: Rule,
+    /// The value which fired a value rule
+    pub value: Option<i32>,
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{} (value {value})", self.rule),
+            None => write!(f, "{}", self.rule),
+        }
+    }
+}
+
+/// The evaluation state of a rule
+#[derive(Debug, Default)]
+struct RuleState {
+    /// When the condition started to hold
+    since: Option<DateTime<Utc>>,
+    /// The last value of a value rule
+    value: Option<i32>,
+    fired: bool,
+}
+
+/// Evaluates the rules on the decoded bus events
+pub struct AlertMonitor {
+    rules: Vec<(Rule, RuleState)>,
+    /// The time of the last response of each node
+    responses: HashMap<u8, DateTime<Utc>>,
+    /// The time of the first evaluation, when the nodes are first expected to respond
+    start: Option<DateTime<Utc>>,
+    alerts: u64,
+}
+
+impl AlertMonitor {
+    pub fn new(rules: AlertRules) -> Self {
+        Self {
+            rules: rules
+                .rules
+                .into_iter()
+                .map(|r| (r, RuleState::default()))
+                .collect(),
+            responses: HashMap::new(),
+            start: None,
+            alerts: 0,
+        }
+    }
+
+    /// The number of alerts so far
+    pub fn alerts(&self) -> u64 {
+        self.alerts
+    }
+
+    /// Evaluate the rules on a decoder event, and at its time
+    pub fn handle_event(&mut self, event: &BusEvent, mut on_alert: impl FnMut(Alert)) {
+        let time = event.time();
+        if let BusEvent::Transaction(t) = event {
+            self.responses.insert(*t.cmd.addr(), t.resp_time);
+            self.transaction(t, &mut on_alert);
+        }
+        self.check(time, on_alert);
+    }
+
+    fn transaction(&mut self, t: &Transaction, on_alert: &mut impl FnMut(Alert)) {
+        let Ok(value) = t.result else {
+            return;
+        };
+        let (a, p, value) = (*t.cmd.addr(), *t.cmd.param(), *value);
+        for (rule, state) in &mut self.rules {
+            let Rule::Value {
+                addr,
+                param,
+                cmp,
+                limit,
+                ..
+            } = rule
+            else {
+                continue;
+            };
+            if (*addr, *param) != (a, p) {
+                continue;
+            }
+            if !cmp.holds(value, *limit) {
+                *state = RuleState::default();
+                continue;
+            }
+            state.since.get_or_insert(t.resp_time);
+            state.value = Some(value);
+        }
+        self.fire(t.resp_time, on_alert);
+    }
+
+    /// Evaluate the rules at `time`, e.g. periodically while the bus is silent
+    pub fn check(&mut self, time: DateTime<Utc>, mut on_alert: impl FnMut(Alert)) {
+        let start = *self.start.get_or_insert(time);
+        for (rule, state) in &mut self.rules {
+            if let Rule::NoResponse { addr, .. } = rule {
+                let last = self.responses.get(addr).copied().unwrap_or(start);
+                if state.since != Some(last) {
+                    // a new response, the rule may fire again
+                    *state = RuleState {
+                        since: Some(last),
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+        self.fire(time, &mut on_alert);
+    }
+
+    /// Fire the rules whose condition has held for long enough
+    fn fire(&mut self, time: DateTime<Utc>, on_alert: &mut impl FnMut(Alert)) {
+        for (rule, state) in &mut self.rules {
+            let duration = match rule {
+                Rule::Value { duration, .. } | Rule::NoResponse { duration, .. } => *duration,
+            };
+            let Some(since) = state.since else {
+                continue;
+            };
+            let held = (time - since).to_std().unwrap_or_default();
+            if state.fired || held < duration {
+                continue;
+            }
+            state.fired = true;
+            self.alerts += 1;
+            on_alert(Alert {
+                time,
+                rule: rule.clone(),
+                value: state.value,
+            });
+        }
+    }
+}
+
+/// Evaluate the rules on the packets received on `rx`, until the sender is dropped. The rules
+/// are also checked every second at the time from `now`, so a silent bus is noticed.
+pub fn monitor(
+    rules: AlertRules,
+    rx: Receiver<SerialPacket>,
+    now: impl Fn() -> DateTime<Utc>,
+    mut on_alert: impl FnMut(Alert),
+) {
+    let mut monitor = AlertMonitor::new(rules);
+    let mut decoders: BTreeMap<u8, X328Decoder> = BTreeMap::new();
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(pkt) => {
+                let mut events = vec![];
+                decoders
+                    .entry(pkt.bus)
+                    .or_default()
+                    .feed(&pkt, |e| events.push(e));
+                for event in events {
+                    monitor.handle_event(&event, &mut on_alert);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => monitor.check(now(), &mut on_alert),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::{
+    copy_to_arena, parse_record, CaptureRecord, RecordRef, SerialPacket, UartTxChannel,
+    LINKTYPE_IPV4,
+};
+
+/// Larger records than this are treated as a corrupt stream
+const MAX_RECORD_LEN: usize = 64 * 1024;
+
+/// Async version of [`SerialPacketReader`](crate::SerialPacketReader), for pcap streams
+/// arriving over a socket or a pipe.
+pub struct AsyncSerialPacketReader<R: AsyncRead + Unpin> {
+    reader: R,
+    big_endian: bool,
+    nanosecond_timestamps: bool,
+    record: Vec<u8>,
+    arena: BytesMut,
+    ctrl_buf: BytesMut,
+    node_buf: BytesMut,
+}
+
+impl<R: AsyncRead + Unpin> AsyncSerialPacketReader<R> {
+    /// Read the pcap file header from `reader`.
+    pub async fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 24];
+        reader
+            .read_exact(&mut header)
+            .await
+            .context("Failed to read the pcap header.")?;
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, nanosecond_timestamps) = match magic {
+            0xa1b2c3d4 => (false, false),
+            0xa1b23c4d => (false, true),
+            0xd4c3b2a1 => (true, false),
+            0x4d3cb2a1 => (true, true),
+            _ => bail!("Not a pcap stream, magic number {magic:#x}."),
+        };
+        let this = Self {
+            reader,
+            big_endian,
+            nanosecond_timestamps,
+            record: vec![],
+            arena: BytesMut::new(),
+            ctrl_buf: BytesMut::new(),
+            node_buf: BytesMut::new(),
+        };
+        let linktype = this.u32_at(&header, 20);
+        if linktype != LINKTYPE_IPV4 {
+            bail!("Unsupported pcap link type {linktype}.");
+        }
+        Ok(this)
+    }
+
+    fn u32_at(&self, buf: &[u8], pos: usize) -> u32 {
+        let bytes = buf[pos..pos + 4].try_into().unwrap();
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Read the next UART data packet, skipping any markers and triggers.
+    pub async fn next_packet(&mut self) -> Result<Option<SerialPacket>> {
+        loop {
+            match self.next_record().await? {
+                Some(CaptureRecord::Packet(pkt)) => return Ok(Some(pkt)),
+                Some(CaptureRecord::Marker(_) | CaptureRecord::Trigger(_)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Read the next packet, marker or trigger.
+    pub async fn next_record(&mut self) -> Result<Option<CaptureRecord>> {
+        let Some(time) = self.read_record().await? else {
+            return Ok(None);
+        };
+        let record = match parse_record(&self.record, time)? {
+            RecordRef::Packet(pkt) => CaptureRecord::Packet(SerialPacket {
+                bus: pkt.bus,
+                ch: pkt.ch,
+                data: copy_to_arena(&mut self.arena, pkt.data),
+                time: pkt.time,
+            }),
+            RecordRef::Marker(marker) => CaptureRecord::Marker(marker),
+            RecordRef::Trigger(trigger) => CaptureRecord::Trigger(trigger),
+        };
+        Ok(Some(record))
+    }
+
+    /// Read the next packet, marker or trigger, without copying the packet data.
+    pub async fn next_record_ref(&mut self) -> Result<Option<RecordRef<'_>>> {
+        match self.read_record().await? {
+            Some(time) => parse_record(&self.record, time).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read up to `max_len` bytes of the data on `ch`, reading packets until there is some.
+    /// Returns no data at the end of the stream.
+    pub async fn read_bytes(&mut self, ch: UartTxChannel, max_len: usize) -> Result<BytesMut> {
+        while self.get_buffer(ch).is_empty() && self.extend_one_pkt().await? {}
+        let buf = self.get_buffer(ch);
+        let len = max_len.min(buf.len());
+        Ok(buf.split_to(len))
+    }
+
+    /// The data on `ch` as a byte stream, the other channel is buffered meanwhile.
+    pub fn reader(&mut self, ch: UartTxChannel) -> impl AsyncRead + '_
+    where
+        R: Send,
+    {
+        ChannelReader {
+            ch,
+            state: ReadState::Idle(self),
+        }
+    }
+
+    fn get_buffer(&mut self, ch: UartTxChannel) -> &mut BytesMut {
+        match ch {
+            UartTxChannel::Ctrl => &mut self.ctrl_buf,
+            UartTxChannel::Node => &mut self.node_buf,
+        }
+    }
+
+    async fn extend_one_pkt(&mut self) -> Result<bool> {
+        let Some(time) = self.read_record().await? else {
+            return Ok(false);
+        };
+        let pkt = match parse_record(&self.record, time)? {
+            RecordRef::Packet(pkt) => pkt,
+            RecordRef::Marker(_) | RecordRef::Trigger(_) => return Ok(true),
+        };
+        // the byte stream readers only follow the first bus
+        if pkt.bus != 0 {
+            return Ok(true);
+        }
+        let buf = match pkt.ch {
+            UartTxChannel::Ctrl => &mut self.ctrl_buf,
+            UartTxChannel::Node => &mut self.node_buf,
+        };
+        buf.extend_from_slice(pkt.data);
+        Ok(true)
+    }
+
+    /// Read the next pcap record into `self.record` and return its timestamp.
+    async fn read_record(&mut self) -> Result<Option<DateTime<Utc>>> {
+        let mut header = [0u8; 16];
+        // A clean end of stream is only allowed between records
+        let mut len = 0;
+        while len < header.len() {
+            let n = self.reader.read(&mut header[len..]).await?;
+            if n == 0 {
+                if len == 0 {
+                    return Ok(None);
+                }
+                bail!("Truncated pcap record header.");
+            }
+            len += n;
+        }
+        let secs = self.u32_at(&header, 0);
+        let subsec = self.u32_at(&header, 4);
+        let incl_len = self.u32_at(&header, 8) as usize;
+        if incl_len > MAX_RECORD_LEN {
+            bail!("Pcap record length {incl_len} is too large.");
+        }
+        let nanos = match self.nanosecond_timestamps {
+            true if subsec < 1_000_000_000 => subsec,
+            false if subsec < 1_000_000 => subsec * 1000,
+            _ => bail!("Invalid pcap record timestamp fraction {subsec}."),
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::new(secs.into(), nanos);
+
+        self.record.resize(incl_len, 0);
+        self.reader
+            .read_exact(&mut self.record)
+            .await
+            .context("Truncated pcap record.")?;
+        Ok(Some(time.into()))
+    }
+}
+
+type ReadFuture<'a, R> = Pin<
+    Box<dyn Future<Output = (&'a mut AsyncSerialPacketReader<R>, Result<BytesMut>)> + Send + 'a>,
+>;
+
+enum ReadState<'a, R: AsyncRead + Unpin> {
+    Idle(&'a mut AsyncSerialPacketReader<R>),
+    Reading(ReadFuture<'a, R>),
+    /// Only while switching states
+    Empty,
+}
+
+struct ChannelReader<'a, R: AsyncRead + Unpin> {
+    ch: UartTxChannel,
+    state: ReadState<'a, R>,
+}
+
+impl<R: AsyncRead + Unpin + Send> AsyncRead for ChannelReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let ch = self.ch;
+        loop {
+            match std::mem::replace(&mut self.state, ReadState::Empty) {
+                ReadState::Idle(reader) => {
+                    let max_len = buf.remaining();
+                    self.state = ReadState::Reading(Box::pin(async move {
+                        let data = reader.read_bytes(ch, max_len).await;
+                        (reader, data)
+                    }));
+                }
+                ReadState::Reading(mut read) => match read.as_mut().poll(cx) {
+                    Poll::Ready((reader, data)) => {
+                        self.state = ReadState::Idle(reader);
+                        let data = data.map_err(std::io::Error::other)?;
+                        buf.put_slice(&data);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Pending => {
+                        self.state = ReadState::Reading(read);
+                        return Poll::Pending;
+                    }
+                },
+                ReadState::Empty => unreachable!("the read state is always restored"),
+            }
+        }
+    }
+}
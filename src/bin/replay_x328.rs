@@ -1,157 +1,503 @@
-#![allow(dead_code)]
+use std::io::IsTerminal;
+use std::process::ExitCode;
 
-use anyhow::{bail, Context, Result};
-use bytes::BytesMut;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use tracing::{error, error_span, info, warn, Level, Span};
+use tracing_subscriber::layer::SubscriberExt;
 
-use x328_proto::scanner::{ControllerEvent, NodeEvent};
+use x328_proto::master::Error as X328Error;
 use x328_proto::{Address, Parameter, Value};
 
-use serial_pcap::{SerialPacketReader, UartTxChannel, TRIG_BYTE};
-
-#[derive(Copy, Clone, Debug)]
-enum BusCommand {
-    Read {
-        addr: Address,
-        param: Parameter,
-    },
-    Write {
-        addr: Address,
-        param: Parameter,
-        value: Value,
-    },
-}
+use serial_pcap::alert::{AlertMonitor, AlertRules};
+use serial_pcap::completions::DocCommand;
+use serial_pcap::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use serial_pcap::latency::{LatencySamples, LatencyStats};
+use serial_pcap::names::NameMap;
+use serial_pcap::pace::{Pacer, ReplaySpeed};
+use serial_pcap::segments::SegmentReader;
+use serial_pcap::step::{decode_steps, Breakpoint, Stepper};
+use serial_pcap::{CaptureRecord, MarkerKind};
 
-struct DataWithTrigger {
-    data: BytesMut,
+/// "param@addr", or the name from the mapping file
+fn describe(names: &NameMap, a: Address, p: Parameter) -> String {
+    match names.is_empty() {
+        true => format!("{p:?}@{a:?}"),
+        false => names.label(*a, *p),
+    }
 }
 
-impl DataWithTrigger {
-    fn new(data: BytesMut) -> Self {
-        Self { data }
+fn describe_value(names: &NameMap, a: Address, p: Parameter, v: Value) -> String {
+    match names.is_empty() {
+        true => format!("{v:?}"),
+        false => names.value(*a, *p, *v),
     }
-    fn as_slice(&self) -> &[u8] {
-        self.data
-            .as_ref()
-            .split(|&b| b == TRIG_BYTE)
-            .next()
-            .unwrap()
+}
+
+fn describe_cmd(names: &NameMap, cmd: BusCommand) -> String {
+    match cmd {
+        BusCommand::Read { addr, param } => format!("read of {}", describe(names, addr, param)),
+        BusCommand::Write { addr, param, value } => format!(
+            "write of {} to {}",
+            describe_value(names, addr, param, value),
+            describe(names, addr, param)
+        ),
     }
-    fn is_empty(&self) -> bool {
-        self.data.is_empty()
+}
+
+/// Counts of the decoded events, for the summary and the exit code
+#[derive(Default)]
+struct Summary {
+    transactions: u64,
+    /// Commands answered with EOT or NAK
+    failed: u64,
+    /// Invalid responses, e.g. with a bad checksum or garbled, the x328-proto ProtocolError
+    protocol_errors: u64,
+    timeouts: u64,
+    /// Timeouts, unexpected transmissions, mismatched responses and collisions
+    violations: u64,
+    /// Corrupt records, skipped data and packets missing from the capture
+    decode_errors: u64,
+    /// The fired alert rules, when evaluating the rules
+    alerts: Option<u64>,
+}
+
+impl Summary {
+    fn count(&mut self, event: &BusEvent) {
+        match event {
+            BusEvent::Transaction(t) => {
+                self.transactions += 1;
+                match t.result {
+                    Ok(_) => (),
+                    Err(X328Error::ProtocolError) => self.protocol_errors += 1,
+                    Err(_) => self.failed += 1,
+                }
+            }
+            BusEvent::Timeout { .. } => {
+                self.timeouts += 1;
+                self.violations += 1;
+            }
+            BusEvent::UnexpectedTransmission { .. }
+            | BusEvent::Mismatch { .. }
+            | BusEvent::Collision { .. } => self.violations += 1,
+            BusEvent::Trigger { .. } | BusEvent::Break { .. } => (),
+        }
     }
-    fn consume(&mut self, len: usize) -> BytesMut {
-        self.data.split_to(len)
+
+    /// 3 for decode errors, 4 for protocol errors, 5 for protocol violations and 6 for alerts,
+    /// the first one which occurred in this order
+    fn exit_code(&self) -> u8 {
+        match self {
+            s if s.decode_errors > 0 => 3,
+            s if s.protocol_errors > 0 => 4,
+            s if s.violations > 0 => 5,
+            s if s.alerts.is_some_and(|a| a > 0) => 6,
+            _ => 0,
+        }
     }
-    fn check_trigger(&mut self) -> bool {
-        let Some(trig_pos) = self.data.iter().position(|&b| b == TRIG_BYTE) else {
-            return false;
+
+    /// A single line of key=value pairs, with the alerts when evaluating the rules
+    fn line(&self) -> String {
+        let alerts = match self.alerts {
+            Some(alerts) => format!(" alerts={alerts}"),
+            None => String::new(),
         };
-        let tail = self.data.split_off(trig_pos).split_off(1);
-        self.data.unsplit(tail);
-        true
+        format!(
+            "summary transactions={} failed={} protocol_errors={} timeouts={} violations={} \
+             decode_errors={}{alerts} exit_code={}",
+            self.transactions,
+            self.failed,
+            self.protocol_errors,
+            self.timeouts,
+            self.violations,
+            self.decode_errors,
+            self.exit_code()
+        )
     }
 }
 
-fn parse_x328_uart<R: std::io::Read>(uart_reader: &mut SerialPacketReader<R>) -> Result<()> {
-    let pkt_iter = uart_reader;
+/// Splits the analysis into segments at the trigger events
+struct Segments {
+    /// The decoder resets at each trigger, so protocol errors before it don't affect the decoding after it
+    reset_scanner: bool,
+    count: usize,
+    /// The span of the current segment, the parent of the events in it. The spans are at the
+    /// ERROR level, so they aren't filtered out from under the warnings and errors.
+    span: Span,
+}
 
-    let mut scanner = x328_proto::scanner::Scanner::new();
-    let mut ctrl_event = None;
-    let mut ctrl_time: DateTime<Utc> = DateTime::default();
-    'next_packet: loop {
-        let Some(pkt) = pkt_iter.next().transpose()? else {
-            return Ok(());
-        };
-        let mut data = DataWithTrigger::new(pkt.data);
-
-        match pkt.ch {
-            UartTxChannel::Ctrl => {
-                while !data.is_empty() {
-                    let slice = data.as_slice();
-                    if slice.is_empty() {
-                        if data.check_trigger() {
-                            println!("Trigger event");
-                            continue;
-                        }
-                        panic!("Empty data slice without trigger.")
-                    }
-                    let (consumed, event) = scanner.recv_from_ctrl(slice);
-                    let consumed = data.consume(consumed);
-                    ctrl_event = event;
-                    ctrl_time = pkt.time;
-                    if ctrl_event.is_none() {
-                        if data.check_trigger() {
-                            println!("Trigger event");
-                            continue;
-                        }
-                        println!("Consumed without event {consumed:?}");
-                        println!("Trailing data in ctrl packet. {:?}", data.as_slice());
-                        continue 'next_packet;
-                    }
-                }
+fn log_event(names: &NameMap, segments: &mut Segments, event: BusEvent) {
+    let _segment = segments.span.clone().entered();
+    match event {
+        BusEvent::Transaction(t) => {
+            let (a, p) = (t.cmd.addr(), t.cmd.param());
+            let _transaction = error_span!(
+                "transaction",
+                cmd = %describe_cmd(names, t.cmd),
+                cmd_time = %t.cmd_time
+            )
+            .entered();
+            let resp_time = t.resp_time;
+            match (t.cmd, t.result) {
+                (BusCommand::Read { .. }, Ok(val)) => info!(
+                    %resp_time,
+                    "Read {} => {}",
+                    describe(names, a, p),
+                    describe_value(names, a, p, val)
+                ),
+                (BusCommand::Write { value, .. }, Ok(_)) => info!(
+                    %resp_time,
+                    "Write ok {} to {}",
+                    describe_value(names, a, p, value),
+                    describe(names, a, p)
+                ),
+                (_, Err(e @ X328Error::ProtocolError)) => error!(%resp_time, "Failed => {e:?}"),
+                (_, Err(e)) => warn!(%resp_time, "Failed => {e:?}"),
             }
-            UartTxChannel::Node => {
-                while !data.is_empty() {
-                    let slice = data.as_slice();
-                    if slice.is_empty() {
-                        if data.check_trigger() {
-                            println!("Trigger event");
-                            continue;
-                        }
-                        panic!("Empty data slice without trigger.");
-                    }
-                    let (consumed, event) = scanner.recv_from_node(slice);
-                    let consumed = data.consume(consumed);
-                    if let Some(event) = event {
-                        print!("cmd time: {ctrl_time} ");
-                        print!("resp time {} ", pkt.time);
-                        match event {
-                            NodeEvent::Write(r) => {
-                                let Some(ControllerEvent::Write(a, p, v)) = ctrl_event.take()
-                                else {
-                                    bail!("Expected write from controller")
-                                };
-                                println!("Write ok {v:?} to {p:?}@{a:?} => {r:?}");
-                            }
-                            NodeEvent::Read(Ok(val)) => {
-                                let Some(ControllerEvent::Read(a, p)) = ctrl_event.take() else {
-                                    bail!("Expected read from controller")
-                                };
-                                println!("Read {p:?}@{a:?} => {val:?}");
-                            }
-                            NodeEvent::UnexpectedTransmission => {
-                                println!("Unexpected data on node tx channel {consumed:?}");
-                                continue 'next_packet;
-                            }
-                            _ => {}
-                        }
-                    } else {
-                        if data.check_trigger() {
-                            println!("Trigger event");
-                            continue;
-                        }
-                        println!("Not enough data in node ch packet.");
-                        continue 'next_packet;
+        }
+        BusEvent::Timeout { cmd, time } => match cmd {
+            Some(cmd) => {
+                let _transaction =
+                    error_span!("transaction", cmd = %describe_cmd(names, cmd)).entered();
+                warn!(%time, "Timeout, no response");
+            }
+            None => warn!(%time, "Timeout"),
+        },
+        BusEvent::UnexpectedTransmission { time } => {
+            warn!(%time, "Unexpected data on node tx channel")
+        }
+        BusEvent::Mismatch { time } => {
+            warn!(%time, "Node response doesn't match the command")
+        }
+        BusEvent::Collision { kind, time } => match kind {
+            Collision::Echo { ch } => warn!(%time, "Echo on the {ch:?} channel"),
+            Collision::SecondMaster { .. } => warn!(%time, "Second bus master"),
+            Collision::Overlap { ch } => {
+                warn!(%time, "Overlapping transmission on the {ch:?} channel")
+            }
+        },
+        BusEvent::Break { ch, time } => info!(%time, "Break on the {ch:?} channel"),
+        BusEvent::Trigger { time } => {
+            if !segments.reset_scanner {
+                info!(%time, "Trigger event");
+                return;
+            }
+            segments.count += 1;
+            segments.span =
+                error_span!(parent: None, "segment", n = segments.count, trigger = %time);
+            info!(parent: &segments.span, "Scanner reset");
+        }
+    }
+}
+
+fn parse_x328_uart(
+    uart_reader: &mut SegmentReader,
+    names: &NameMap,
+    reset_on_trigger: bool,
+    baud: Option<u32>,
+    pacer: &mut Pacer,
+    summary: &mut Summary,
+    on_event: &mut dyn FnMut(&BusEvent) -> Result<()>,
+) -> Result<()> {
+    let mut segments = Segments {
+        reset_scanner: reset_on_trigger,
+        count: 0,
+        span: Span::none(),
+    };
+    let mut decoder = X328Decoder::new().with_reset_on_trigger(reset_on_trigger);
+    if let Some(baud) = baud {
+        decoder = decoder.with_baud(baud);
+    }
+    let mut boundaries = 0;
+    while let Some(record) = uart_reader.next_record()? {
+        pacer.wait(record.time());
+        if let Some(b) = uart_reader.boundaries().get(boundaries) {
+            boundaries += 1;
+            let gap = b.gap().to_std().unwrap_or_default();
+            info!(
+                parent: &segments.span,
+                time = %b.next_start,
+                "Continued in {} after {gap:.1?}",
+                b.next.display()
+            );
+        }
+        for gap in uart_reader.take_sequence_gaps() {
+            warn!(parent: &segments.span, time = %gap.time, "Sequence gap: {gap}");
+        }
+        match &record {
+            CaptureRecord::Marker(m) if m.kind != MarkerKind::Break => {
+                info!(parent: &segments.span, time = %m.time, "Marker: {m}")
+            }
+            _ => {
+                let mut hook_result = Ok(());
+                decoder.feed_record(&record, |event| {
+                    summary.count(&event);
+                    log_event(names, &mut segments, event.clone());
+                    if hook_result.is_ok() {
+                        hook_result = on_event(&event);
                     }
-                }
+                });
+                hook_result?;
             }
         }
     }
+    Ok(())
 }
 
+/// Decode the X3.28 traffic in a capture
+///
+/// The exit code is 3 for decode errors, i.e. corrupt records, skipped data or sequence gaps,
+/// 4 for protocol errors, i.e. invalid node responses such as a bad checksum, 5 for protocol
+/// violations, i.e. timeouts, unexpected transmissions, mismatched responses and collisions,
+/// and 6 for alerts with --rules. With several kinds, the first one in this order is returned.
 #[derive(Parser, Debug)]
+#[clap(
+    name = "replay_x328",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct CmdlineOpts {
-    /// The pcap filename to read the UART data from
-    pcap_file: String,
+    #[clap(subcommand)]
+    command: Option<DocCommand>,
+
+    /// The pcap files to read the UART data from, or - to read it from stdin. Several files,
+    /// e.g. the segments of a ring capture, are read in turn as one capture, and a glob
+    /// pattern like 'bus.*.pcap' is replaced by the matching files sorted by name.
+    #[clap(required = true, value_name = "PCAP_FILE")]
+    pcap_files: Vec<String>,
+
+    /// Show the decoded transactions in an interactive terminal UI
+    #[clap(long)]
+    tui: bool,
+
+    /// Parameter name mapping file (TOML or CSV)
+    #[clap(long, value_name = "FILE")]
+    names: Option<String>,
+
+    /// Reset the protocol scanner at each trigger event, and number the segments between them
+    #[clap(long, conflicts_with_all = ["tui", "step"])]
+    reset_on_trigger: bool,
+
+    /// Report the transmissions which overlap in time as collisions, with the byte times
+    /// estimated from this baud rate of the bus
+    #[clap(long)]
+    baud: Option<u32>,
+
+    /// Start the replay at this time, skipping the packets and markers before it
+    #[clap(long, value_name = "RFC3339")]
+    from: Option<DateTime<Utc>>,
+
+    /// Replay at the recorded speed with "realtime", scaled by a factor, e.g. "0.5x" or
+    /// "10x", or as fast as possible with "max"
+    #[clap(
+        long,
+        value_name = "SPEED",
+        default_value = "max",
+        conflicts_with = "step"
+    )]
+    speed: ReplaySpeed,
+
+    /// Skip corrupt records in the capture instead of stopping at the first one
+    #[clap(long)]
+    resync: bool,
+
+    /// Skip the records which aren't serial-pcap packets, e.g. in a capture merged with other
+    /// traffic, and reassemble fragmented packets
+    #[clap(long)]
+    lenient: bool,
+
+    /// Read the capture through a memory map, which is faster for large captures. Not for a
+    /// capture which is still being written.
+    #[clap(long)]
+    mmap: bool,
+
+    /// Step through the decoded transactions in a terminal UI, and inspect the raw bytes
+    #[clap(long, conflicts_with = "tui")]
+    step: bool,
+
+    /// Stop at the commands to this node, or to one of its parameters, when continuing in
+    /// the step mode. Can be repeated.
+    #[clap(long = "break", value_name = "ADDR[:PARAM]", requires = "step")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Print the response time percentiles and a histogram of each node before the summary
+    #[clap(long, conflicts_with_all = ["tui", "step"])]
+    latency: bool,
+
+    /// Write the response time of each transaction to this CSV file
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["tui", "step"])]
+    latency_samples: Option<String>,
+
+    /// Evaluate the alert rules in this file, log the alerts and exit with code 6 if any fired
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["tui", "step"])]
+    rules: Option<String>,
+
+    /// Call the on_transaction function of this Rhai script for each transaction
+    #[cfg(feature = "script")]
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["tui", "step"])]
+    script: Option<String>,
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = CmdlineOpts::parse();
+    if let Some(doc) = args.command {
+        doc.run(CmdlineOpts::command());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut uart_reader = SegmentReader::new(&args.pcap_files)?;
+    if args.mmap {
+        uart_reader = uart_reader.with_mmap();
+    }
+    if args.resync {
+        uart_reader = uart_reader.with_resync();
+    }
+    if args.lenient {
+        uart_reader = uart_reader.with_lenient();
+    }
+    if let Some(time) = args.from {
+        uart_reader.skip_to(time)?;
+    }
+    let names = match &args.names {
+        Some(filename) => NameMap::from_file(filename)?,
+        None => NameMap::default(),
+    };
+    if args.tui {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut pacer = Pacer::new(args.speed);
+        std::thread::spawn(move || loop {
+            // a read error ends the stream, the view shows it
+            let record = match uart_reader.next_record().transpose() {
+                Some(Ok(record)) => record,
+                Some(Err(e)) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                }
+                None => break,
+            };
+            pacer.wait(record.time());
+            if tx.send(Ok(record)).is_err() {
+                break;
+            }
+        });
+        return serial_pcap::tui::run(rx, names, None).map(|_| ExitCode::SUCCESS);
+    }
+    if args.step {
+        let mut stepper = Stepper::new(decode_steps(uart_reader)?);
+        for bp in args.breakpoints {
+            stepper.toggle_breakpoint(bp);
+        }
+        return serial_pcap::tui::run_stepper(stepper, names).map(|_| ExitCode::SUCCESS);
+    }
+    // the terminal UIs don't log, it would garble the screen
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stdout)
+        .with_ansi(std::io::stdout().is_terminal())
+        .with_max_level(Level::TRACE)
+        .without_time()
+        .with_target(false)
+        .finish()
+        .with(serial_pcap::logging::env_filter(Level::INFO)?);
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    let mut latency = args.latency.then(LatencyStats::default);
+    let mut latency_samples = match &args.latency_samples {
+        Some(filename) => {
+            let file = std::fs::File::create(filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            Some(LatencySamples::new(std::io::BufWriter::new(file))?)
+        }
+        None => None,
+    };
+    let mut alerts = match &args.rules {
+        Some(filename) => Some(AlertMonitor::new(AlertRules::from_file(filename)?)),
+        None => None,
+    };
+    #[cfg(feature = "script")]
+    let mut script = match &args.script {
+        Some(filename) => Some(serial_pcap::script::TransactionScript::load(filename)?),
+        None => None,
+    };
+    // a failing script ends the replay with its error, it isn't a problem of the capture
+    #[cfg_attr(not(feature = "script"), allow(unused_mut))]
+    let mut script_failed = false;
+    let mut on_event = |event: &BusEvent| -> Result<()> {
+        if let Some(alerts) = &mut alerts {
+            alerts.handle_event(event, |alert| warn!(time = %alert.time, "Alert: {alert}"));
+        }
+        if let BusEvent::Transaction(t) = event {
+            if let Some(latency) = &mut latency {
+                latency.add(t);
+            }
+            if let Some(samples) = &mut latency_samples {
+                samples.add(t)?;
+            }
+        }
+        #[cfg(feature = "script")]
+        if let (Some(script), BusEvent::Transaction(t)) = (&mut script, event) {
+            script
+                .transaction(t)
+                .inspect_err(|_| script_failed = true)?;
+        }
+        Ok(())
+    };
 
-    let filename = &args.pcap_file;
-    let file = std::fs::File::open(filename).context("Failed to open {filename}.")?;
-    let mut uart_reader = SerialPacketReader::new(file)?;
-    parse_x328_uart(&mut uart_reader)
+    let mut summary = Summary::default();
+    // a corrupt record ends the replay, but the summary is still printed
+    match parse_x328_uart(
+        &mut uart_reader,
+        &names,
+        args.reset_on_trigger,
+        args.baud,
+        &mut Pacer::new(args.speed),
+        &mut summary,
+        &mut on_event,
+    ) {
+        Err(e) if script_failed => return Err(e),
+        Err(e) => {
+            error!("{e:#}");
+            summary.decode_errors += 1;
+        }
+        Ok(()) => (),
+    }
+    #[cfg(feature = "script")]
+    if let Some(script) = &mut script {
+        script.end()?;
+    }
+    summary.alerts = alerts.map(|a| a.alerts());
+    if let Some(samples) = latency_samples {
+        samples.finish()?;
+    }
+    if let Some(stats) = uart_reader.resync_stats().filter(|s| s.gaps > 0) {
+        warn!(
+            "Skipped {} bytes of corrupt data in {} places.",
+            stats.skipped_bytes, stats.gaps
+        );
+        summary.decode_errors += stats.gaps;
+    }
+    summary.decode_errors += uart_reader.sequence_gaps();
+    if uart_reader.sequence_gaps() > 0 {
+        warn!(
+            "Found {} gaps in the packet sequence numbers, packets were lost or reordered after the capture.",
+            uart_reader.sequence_gaps()
+        );
+    }
+    if let Some(stats) = uart_reader.lenient_stats() {
+        if stats.foreign > 0 || stats.incomplete > 0 {
+            warn!(
+                "Skipped {} foreign records and {} incomplete fragmented packets.",
+                stats.foreign, stats.incomplete
+            );
+        }
+        if stats.reassembled > 0 {
+            info!("Reassembled {} fragmented packets.", stats.reassembled);
+        }
+        summary.decode_errors += stats.incomplete;
+    }
+    if let Some(latency) = &mut latency {
+        latency.write_report(std::io::stdout().lock())?;
+    }
+    println!("{}", summary.line());
+    Ok(ExitCode::from(summary.exit_code()))
 }
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use clap::{CommandFactory, Parser};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tokio_serial::SerialStream;
+use x328_proto::master::SendData;
+use x328_proto::{addr, param, value, Master};
+
+use serial_pcap::completions::DocCommand;
+use serial_pcap::open_async_uart;
+use serial_pcap::sim::SimNode;
+
+/// Simulate an X3.28 bus controller and nodes on two serial ports
+#[derive(Parser, Debug)]
+#[clap(
+    name = "simulate",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct CmdlineOpts {
+    #[clap(subcommand)]
+    command: Option<DocCommand>,
+
+    /// The TOML scenario file describing the bus
+    #[clap(required = true)]
+    scenario: Option<PathBuf>,
+
+    /// Serial port for the bus controller, overrides the scenario file
+    #[clap(long, value_name = "SERIAL_PORT")]
+    ctrl: Option<String>,
+
+    /// Serial port for the bus nodes, overrides the scenario file
+    #[clap(long, value_name = "SERIAL_PORT")]
+    node: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    #[serde(default)]
+    bus: BusConfig,
+    #[serde(default, rename = "node")]
+    nodes: Vec<NodeConfig>,
+    #[serde(default)]
+    commands: Vec<Cmd>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct BusConfig {
+    ctrl: Option<String>,
+    node: Option<String>,
+    /// Number of times the command list is repeated, 0 means forever
+    cycles: usize,
+    command_interval_ms: u64,
+    response_timeout_ms: u64,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            ctrl: None,
+            node: None,
+            cycles: 1,
+            command_interval_ms: 10,
+            response_timeout_ms: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct NodeConfig {
+    address: u8,
+    #[serde(default)]
+    response_delay_ms: u64,
+    /// Parameter values, keyed by parameter number
+    #[serde(default)]
+    parameters: HashMap<String, i32>,
+    #[serde(default)]
+    errors: ErrorInjection,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ErrorInjection {
+    /// Parameters that fail with NAK on read or write
+    #[serde(default)]
+    fail: Vec<i16>,
+    /// Skip every Nth reply, 0 disables
+    #[serde(default)]
+    no_reply_every: usize,
+    /// Corrupt the checksum of every Nth reply, 0 disables
+    #[serde(default)]
+    corrupt_every: usize,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Cmd {
+    Read(u8, i16),
+    Write(u8, i16, i32),
+}
+
+impl Scenario {
+    fn from_file(filename: &PathBuf) -> Result<Self> {
+        let text = std::fs::read_to_string(filename)
+            .with_context(|| format!("Failed to read scenario {}.", filename.display()))?;
+        toml::from_str(&text).context("Failed to parse scenario.")
+    }
+}
+
+struct BusController {
+    master: Master,
+    response_timeout: Duration,
+}
+
+impl BusController {
+    fn new(response_timeout: Duration) -> Self {
+        BusController {
+            master: Master::new(),
+            response_timeout,
+        }
+    }
+
+    async fn next(&mut self, cmd: Cmd, uart: &mut SerialStream) -> Result<()> {
+        let timeout = self.response_timeout;
+        match cmd {
+            Cmd::Read(a, p) => {
+                let read = self.master.read_parameter(addr(a), param(p));
+                match Self::master_trx(read, uart, timeout).await {
+                    Ok(Ok(v)) => println!("Read {p}@{a} => {}", *v),
+                    Ok(Err(e)) => println!("Read {p}@{a} failed: {e}"),
+                    Err(e) => println!("Read {p}@{a}: {e:#}"),
+                }
+            }
+            Cmd::Write(a, p, v) => {
+                let write = self.master.write_parameter(addr(a), param(p), value(v));
+                match Self::master_trx(write, uart, timeout).await {
+                    Ok(Ok(())) => println!("Write {v} to {p}@{a} ok"),
+                    Ok(Err(e)) => println!("Write {v} to {p}@{a} failed: {e}"),
+                    Err(e) => println!("Write {v} to {p}@{a}: {e:#}"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // this doesn't take `self` since send is borrowed from self.master
+    async fn master_trx<R>(
+        mut send: impl SendData<Response = R>,
+        uart: &mut SerialStream,
+        response_timeout: Duration,
+    ) -> Result<Result<R, x328_proto::master::Error>> {
+        uart.write_all(send.get_data())
+            .await
+            .context("Ctrl UART write failed")?;
+
+        let recv = send.data_sent();
+        let mut buf = BytesMut::with_capacity(40);
+        loop {
+            buf.clear();
+            timeout(response_timeout, uart.read_buf(&mut buf))
+                .await
+                .context("Ctrl UART read timeout")?
+                .context("Ctrl UART read error")?;
+            if let Some(response) = recv.receive_data(buf.as_ref()) {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+struct Node {
+    sim: SimNode,
+    response_delay: Duration,
+}
+
+impl Node {
+    fn new(config: NodeConfig) -> Result<Self> {
+        let values = config
+            .parameters
+            .into_iter()
+            .map(|(p, v)| Ok((p.parse().context("Invalid parameter number")?, v)))
+            .collect::<Result<Vec<_>>>()?;
+        let errors = config.errors;
+        let sim = errors.fail.into_iter().fold(
+            SimNode::new(config.address)
+                .with_values(values)
+                .with_no_reply_every(errors.no_reply_every)
+                .with_corrupt_every(errors.corrupt_every),
+            SimNode::with_failing,
+        );
+        Ok(Self {
+            sim,
+            response_delay: Duration::from_millis(config.response_delay_ms),
+        })
+    }
+}
+
+async fn nodes_chat(mut uart: SerialStream, mut nodes: Vec<Node>) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(40);
+    loop {
+        buf.clear();
+        uart.read_buf(&mut buf)
+            .await
+            .context("Node UART read failed")?;
+
+        for node in nodes.iter_mut() {
+            let Some(reply) = node.sim.receive(buf.as_ref()) else {
+                continue;
+            };
+            tokio::time::sleep(node.response_delay).await;
+            uart.write_all(&reply)
+                .await
+                .context("Node UART write failed")?;
+        }
+    }
+}
+
+async fn chat(mut ctrl: SerialStream, node: SerialStream, scenario: Scenario) -> Result<()> {
+    let bus = scenario.bus;
+    let nodes = scenario
+        .nodes
+        .into_iter()
+        .map(Node::new)
+        .collect::<Result<Vec<_>>>()?;
+    let node_handle: abort_on_drop::ChildTask<_> = tokio::spawn(nodes_chat(node, nodes)).into();
+
+    let mut controller = BusController::new(Duration::from_millis(bus.response_timeout_ms));
+    let cycles = match bus.cycles {
+        0 => usize::MAX,
+        n => n,
+    };
+    let commands = scenario.commands.iter().cycle();
+    for cmd in commands.take(scenario.commands.len().saturating_mul(cycles)) {
+        controller.next(*cmd, &mut ctrl).await?;
+        tokio::time::sleep(Duration::from_millis(bus.command_interval_ms)).await;
+        if node_handle.is_finished() {
+            return node_handle
+                .await
+                .context("Error in node task join handle.")?
+                .context("Node task terminated unexpectedly");
+        }
+    }
+    // Stop the node UART reader
+    node_handle.abort();
+    let _ = node_handle.await;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = CmdlineOpts::parse();
+    if let Some(doc) = args.command {
+        doc.run(CmdlineOpts::command());
+        return Ok(());
+    }
+    let mut scenario = Scenario::from_file(args.scenario.as_ref().unwrap())?;
+
+    let (Some(ctrl), Some(node)) = (
+        args.ctrl.or(scenario.bus.ctrl.take()),
+        args.node.or(scenario.bus.node.take()),
+    ) else {
+        bail!("Both the ctrl and node serial ports must be given.");
+    };
+    let ctrl_uart = open_async_uart(&ctrl)?;
+    let node_uart = open_async_uart(&node)?;
+
+    chat(ctrl_uart, node_uart, scenario).await
+}
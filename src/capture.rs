@@ -0,0 +1,430 @@
+//! The capture sources, which read the UART data from a serial port or any other byte stream
+//! and queue it for the recorder.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UdpSocket;
+use tracing::{info, trace, warn};
+
+use crate::clock::{CaptureClock, DeviceClock};
+use crate::framed::{parse_packet, split_stream, FramedRecord};
+use crate::pool::BufferPool;
+use crate::queue::QueueSender;
+use crate::{
+    Marker, MarkerKind, MuxFraming, MuxedStreamDecoder, Trigger, UartData, UartTxChannel, TRIG_BYTE,
+};
+
+/// A block of data read from a UART
+#[derive(Debug)]
+pub struct UartRead {
+    pub bus: u8,
+    pub ch_name: UartTxChannel,
+    pub data: BytesMut,
+    pub time_received: std::time::SystemTime,
+    /// The capture device marked this as the start of a chunk, after a gap on the bus
+    pub chunk_start: bool,
+    /// A trigger or a line break at `time_received` instead of data, `data` is empty
+    pub event: Option<UartEvent>,
+}
+
+/// An event from the capture device, which is recorded as a trigger or a marker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UartEvent {
+    /// A measurement trigger, from the named source, e.g. [`Trigger::DEVICE`]
+    Trigger(String),
+    /// A break condition on the line
+    Break,
+}
+
+impl UartRead {
+    fn event(
+        bus: u8,
+        ch_name: UartTxChannel,
+        event: UartEvent,
+        time_received: std::time::SystemTime,
+    ) -> Self {
+        Self {
+            bus,
+            ch_name,
+            data: BytesMut::new(),
+            time_received,
+            chunk_start: false,
+            event: Some(event),
+        }
+    }
+}
+
+/// Number of lost data blocks and bytes for one channel
+#[derive(Default, Debug)]
+struct DropCounter {
+    events: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// A loss which hasn't been written to the pcap file yet
+struct PendingDrop {
+    ch: UartTxChannel,
+    reason: &'static str,
+    events: u64,
+    bytes: u64,
+    time: std::time::SystemTime,
+}
+
+/// Accounting of all data lost during the capture
+#[derive(Default)]
+pub struct DropStats {
+    clock: CaptureClock,
+    ctrl: DropCounter,
+    node: DropCounter,
+    pending: Mutex<Vec<PendingDrop>>,
+}
+
+impl DropStats {
+    pub fn new(clock: CaptureClock) -> Self {
+        Self {
+            clock,
+            ..Default::default()
+        }
+    }
+
+    /// Record lost data, `bytes` is zero if the amount is unknown.
+    pub fn record(&self, ch: UartTxChannel, bytes: usize, reason: &'static str) {
+        let counter = self.counter(ch);
+        counter.events.fetch_add(1, Ordering::Relaxed);
+        counter.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let mut pending = self.pending.lock().unwrap();
+        // Merge repeated losses, so a sustained overflow doesn't queue a marker per read
+        match pending.last_mut() {
+            Some(last) if last.ch == ch && last.reason == reason => {
+                last.events += 1;
+                last.bytes += bytes as u64;
+            }
+            _ => pending.push(PendingDrop {
+                ch,
+                reason,
+                events: 1,
+                bytes: bytes as u64,
+                time: self.clock.now(),
+            }),
+        }
+    }
+
+    fn counter(&self, ch: UartTxChannel) -> &DropCounter {
+        match ch {
+            UartTxChannel::Ctrl => &self.ctrl,
+            UartTxChannel::Node => &self.node,
+        }
+    }
+
+    /// The number of losses on the channel, and the bytes lost
+    pub fn channel_losses(&self, ch: UartTxChannel) -> (u64, u64) {
+        let counter = self.counter(ch);
+        (
+            counter.events.load(Ordering::Relaxed),
+            counter.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn total_events(&self) -> u64 {
+        self.ctrl.events.load(Ordering::Relaxed) + self.node.events.load(Ordering::Relaxed)
+    }
+
+    /// Markers for the losses since the last call
+    pub fn take_markers(&self) -> Vec<Marker> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        pending
+            .into_iter()
+            .map(|d| {
+                let label = match d.bytes {
+                    0 => format!("{} ({}x)", d.reason, d.events),
+                    bytes => format!("{} ({}x, {bytes} bytes)", d.reason, d.events),
+                };
+                Marker {
+                    kind: MarkerKind::Drop,
+                    ch: Some(d.ch),
+                    label,
+                    time: d.time.into(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn summary(&self) -> String {
+        let count = |c: &DropCounter| {
+            let events = c.events.load(Ordering::Relaxed);
+            let bytes = c.bytes.load(Ordering::Relaxed);
+            format!("{events} losses, {bytes} bytes")
+        };
+        format!("ctrl: {}, node: {}", count(&self.ctrl), count(&self.node))
+    }
+}
+
+/// Sends the UART data to the recorder, and accounts for any data which is lost on the way
+#[derive(Clone)]
+pub struct UartSink {
+    pub tx: QueueSender<UartRead>,
+    pub drops: Arc<DropStats>,
+    pub clock: CaptureClock,
+    /// The read buffers, which the recorder returns after use
+    pub pool: BufferPool,
+}
+
+impl UartSink {
+    pub async fn send(&self, data: UartRead) -> Result<()> {
+        if let Some(evicted) = self.tx.send(data).await? {
+            self.drops
+                .record(evicted.ch_name, evicted.data.len(), "queue overflow");
+        }
+        Ok(())
+    }
+}
+
+/// Read one side of a bus from a UART, `bus` and `ch_name` are what it is recorded as.
+#[tracing::instrument(skip(uart, tx))]
+pub async fn read_uart<R: AsyncRead + Unpin>(
+    mut uart: R,
+    bus: u8,
+    ch_name: UartTxChannel,
+    tx: UartSink,
+) -> Result<()> {
+    let mut buf = tx.pool.get();
+    loop {
+        match uart.read_buf(&mut buf).await {
+            Ok(0) => {
+                info!("Zero length read");
+                bail!("Read from {ch_name:?} returned 0 bytes.");
+            }
+            Ok(len) => {
+                trace!("Received {len} bytes.");
+                tx.send(UartRead {
+                    bus,
+                    ch_name,
+                    data: std::mem::replace(&mut buf, tx.pool.get()),
+                    chunk_start: false,
+                    time_received: tx.clock.now(),
+                    event: None,
+                })
+                .await?;
+            }
+            err => {
+                info!("UART read returned with error {err:?}");
+                tx.drops.record(ch_name, 0, "read error");
+                err.with_context(|| format!("Read error from UART '{ch_name:?}'."))?;
+            }
+        }
+    }
+}
+
+/// Read the muxed stream from a capture device, `bus` is the bus it is recorded as.
+pub async fn read_muxed_uart<R: AsyncRead + Unpin>(
+    mut uart: R,
+    bus: u8,
+    framing: MuxFraming,
+    tx: UartSink,
+) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(1);
+    let mut decoder = MuxedStreamDecoder::with_framing(framing);
+    // the 8 bit data of the SLIP framed stream can hold any byte, its triggers are frames
+    let trig_byte = (framing == MuxFraming::Bit7).then_some(TRIG_BYTE);
+    let mut device_clock = DeviceClock::new();
+    // the drop markers only name the channel, so the reason tells the buses apart
+    let (overrun, read_error) = match bus {
+        0 => ("device overrun", "read error"),
+        _ => ("bus 1 device overrun", "bus 1 read error"),
+    };
+    loop {
+        buf.reserve(1);
+        match uart.read_buf(&mut buf).await {
+            Ok(0) => {
+                info!("Zero length read");
+                bail!("Read from muxed uart returned 0 bytes.");
+            }
+            Ok(_len) => {
+                let time_received = tx.clock.now();
+                // trace!("Received {_len} bytes.");
+                for UartData {
+                    ch,
+                    mut data,
+                    drops,
+                    chunk,
+                    line_break,
+                    trigger,
+                } in decoder.feed(&buf)
+                {
+                    // the capture device couldn't forward all the data
+                    for _ in 0..drops {
+                        tx.drops.record(ch, 0, overrun);
+                    }
+                    // the device measures when the chunk started, without the USB latency
+                    let mut time = match chunk {
+                        Some(chunk) => device_clock.capture_time(chunk.first, time_received),
+                        None => time_received,
+                    };
+                    let mut chunk_start = chunk.is_some();
+                    // the triggers are recorded as markers, between the data around them
+                    while let Some(pos) = data.iter().position(|&b| Some(b) == trig_byte) {
+                        info!("Trigger found in data stream");
+                        let before = data.split_to(pos);
+                        let _ = data.split_to(1);
+                        if !before.is_empty() {
+                            tx.send(UartRead {
+                                bus,
+                                ch_name: ch,
+                                data: before,
+                                time_received: time,
+                                chunk_start,
+                                event: None,
+                            })
+                            .await?;
+                            chunk_start = false;
+                        }
+                        // the trigger byte has no timestamp of its own, so it and the data
+                        // after it get the receive time
+                        time = time.max(time_received);
+                        let trigger = UartEvent::Trigger(Trigger::DEVICE.into());
+                        tx.send(UartRead::event(bus, ch, trigger, time)).await?;
+                    }
+                    if !data.is_empty() {
+                        tx.send(UartRead {
+                            bus,
+                            ch_name: ch,
+                            data,
+                            time_received: time,
+                            chunk_start,
+                            event: None,
+                        })
+                        .await?;
+                    }
+                    if line_break {
+                        let time = time.max(time_received);
+                        tx.send(UartRead::event(bus, ch, UartEvent::Break, time))
+                            .await?;
+                    }
+                    if trigger {
+                        info!("Trigger frame received");
+                        let trigger = UartEvent::Trigger(Trigger::DEVICE.into());
+                        let time = time.max(time_received);
+                        tx.send(UartRead::event(bus, ch, trigger, time)).await?;
+                    }
+                }
+                buf.clear();
+            }
+            err => {
+                info!("UART read returned with error {err:?}");
+                tx.drops.record(UartTxChannel::Ctrl, 0, read_error);
+                tx.drops.record(UartTxChannel::Node, 0, read_error);
+                err.with_context(|| "Read error from muxed UART.".to_string())?;
+            }
+        }
+    }
+}
+
+/// Queue the framed records from the capture device, `time_received` is when they arrived
+async fn send_records(
+    tx: &UartSink,
+    device_clock: &mut DeviceClock,
+    records: Vec<FramedRecord>,
+    time_received: std::time::SystemTime,
+) -> Result<()> {
+    for rec in records {
+        let (bus, ch) = (rec.bus(), rec.ch());
+        let time = device_clock.capture_time(rec.time, time_received);
+        if rec.is_trigger() {
+            info!("Trigger found in data stream");
+            // older firmware sends a trigger byte instead of the name of the source
+            let source = match &rec.data[..] {
+                [] | [TRIG_BYTE] => Trigger::DEVICE.into(),
+                name => String::from_utf8_lossy(name).into_owned(),
+            };
+            tx.send(UartRead::event(bus, ch, UartEvent::Trigger(source), time))
+                .await?;
+            continue;
+        }
+        if rec.is_break() {
+            tx.send(UartRead::event(bus, ch, UartEvent::Break, time))
+                .await?;
+            continue;
+        }
+        if rec.dropped() {
+            let reason = match bus {
+                0 => "device overrun",
+                _ => "bus 1 device overrun",
+            };
+            tx.drops.record(ch, 0, reason);
+        }
+        if rec.data.is_empty() {
+            continue;
+        }
+        tx.send(UartRead {
+            bus,
+            ch_name: ch,
+            time_received: time,
+            chunk_start: rec.chunk_start(),
+            data: rec.data,
+            event: None,
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "usb")]
+/// Read the framed capture records from the vendor USB interface of the capture device.
+pub async fn read_usb(tx: UartSink) -> Result<()> {
+    let mut usb = crate::usb::UsbCapture::open()?;
+    let mut device_clock = DeviceClock::new();
+    loop {
+        let records = usb.next_records().await?;
+        let time_received = tx.clock.now();
+        send_records(&tx, &mut device_clock, records, time_received).await?;
+    }
+}
+
+#[cfg(not(feature = "usb"))]
+pub async fn read_usb(_tx: UartSink) -> Result<()> {
+    bail!("serial-pcap was built without the usb feature.")
+}
+
+/// Read the framed capture records streamed by the Pico W firmware over a TCP connection.
+pub async fn read_framed_stream<R: AsyncRead + Unpin>(mut stream: R, tx: UartSink) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(4096);
+    let mut device_clock = DeviceClock::new();
+    loop {
+        buf.reserve(1);
+        match stream.read_buf(&mut buf).await {
+            Ok(0) => bail!("The capture device closed the connection."),
+            Ok(_) => {
+                let time_received = tx.clock.now();
+                let records = split_stream(&mut buf);
+                send_records(&tx, &mut device_clock, records, time_received).await?;
+            }
+            Err(e) => {
+                tx.drops.record(UartTxChannel::Ctrl, 0, "read error");
+                tx.drops.record(UartTxChannel::Node, 0, "read error");
+                return Err(e).context("Read error from the capture device connection.");
+            }
+        }
+    }
+}
+
+/// Read the framed capture records sent by the Pico W firmware as UDP datagrams. A lost
+/// datagram isn't detected.
+pub async fn read_framed_datagrams(socket: UdpSocket, tx: UartSink) -> Result<()> {
+    let mut buf = [0u8; 2048];
+    let mut device_clock = DeviceClock::new();
+    loop {
+        let (len, peer) = socket
+            .recv_from(&mut buf)
+            .await
+            .context("Failed to receive from the capture device.")?;
+        let time_received = tx.clock.now();
+        match parse_packet(&buf[..len]) {
+            Ok(records) => send_records(&tx, &mut device_clock, records, time_received).await?,
+            Err(e) => warn!("Ignoring a datagram from {peer}: {e}"),
+        }
+    }
+}
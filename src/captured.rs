@@ -0,0 +1,152 @@
+//! Capture of an application's own serial traffic, by wrapping its stream.
+//!
+//! An application which talks to a bus through e.g. a [`SerialStream`](tokio_serial::SerialStream)
+//! can capture the traffic without a second port or a tap on the line, by using a
+//! [`CapturedStream`] in place of the stream:
+//!
+//! ```no_run
+//! # use serial_pcap::{captured::CapturedStream, SerialPacketWriter};
+//! # fn open(port: tokio_serial::SerialStream) -> anyhow::Result<()> {
+//! let port = CapturedStream::new(port, SerialPacketWriter::new_file("bus.pcap")?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+use crate::{SerialPacketWriter, UartTxChannel};
+
+/// Writes everything read from and written to the wrapped stream to a capture. The stream is
+/// used as is, and an error writing the capture is logged once, without failing the stream.
+pub struct CapturedStream<S, W: Write> {
+    stream: S,
+    writer: SerialPacketWriter<W>,
+    /// The channel of the written data, the read data is from the other one
+    written: UartTxChannel,
+    /// A capture error has been logged
+    failed: bool,
+}
+
+impl<S, W: Write> CapturedStream<S, W> {
+    /// Capture the stream of a bus controller, the written data is the ctrl channel and the
+    /// read data the node channel.
+    pub fn new(stream: S, writer: SerialPacketWriter<W>) -> Self {
+        Self {
+            stream,
+            writer,
+            written: UartTxChannel::Ctrl,
+            failed: false,
+        }
+    }
+
+    /// Capture the written data as `ch` instead, e.g. [`UartTxChannel::Node`] for the stream of
+    /// a node.
+    pub fn with_written_channel(mut self, ch: UartTxChannel) -> Self {
+        self.written = ch;
+        self
+    }
+
+    fn read_channel(&self) -> UartTxChannel {
+        match self.written {
+            UartTxChannel::Ctrl => UartTxChannel::Node,
+            UartTxChannel::Node => UartTxChannel::Ctrl,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// The capture, e.g. to write markers between the data
+    pub fn writer_mut(&mut self) -> &mut SerialPacketWriter<W> {
+        &mut self.writer
+    }
+
+    /// Flush the capture and return the stream and the capture writer.
+    pub fn into_inner(mut self) -> (S, SerialPacketWriter<W>) {
+        self.flush_capture();
+        (self.stream, self.writer)
+    }
+
+    fn capture(&mut self, data: &[u8], ch: UartTxChannel) {
+        if data.is_empty() {
+            return;
+        }
+        let res = self.writer.write_packet_time(data, ch, SystemTime::now());
+        self.check(res);
+    }
+
+    fn flush_capture(&mut self) {
+        let res = self.writer.flush();
+        self.check(res);
+    }
+
+    fn check(&mut self, res: anyhow::Result<()>) {
+        if let Err(e) = res {
+            if !self.failed {
+                warn!("Failed to write the captured stream: {e:#}");
+                self.failed = true;
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin, W: Write + Unpin> AsyncRead for CapturedStream<S, W> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let start = buf.filled().len();
+        let res = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            let ch = this.read_channel();
+            this.capture(&buf.filled()[start..], ch);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin, W: Write + Unpin> AsyncWrite for CapturedStream<S, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.capture(&buf[..n], this.written);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.stream).poll_flush(cx);
+        if res.is_ready() {
+            this.flush_capture();
+        }
+        res
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.stream).poll_shutdown(cx);
+        if res.is_ready() {
+            this.flush_capture();
+        }
+        res
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Marker, MarkerKind};
+
+/// Correction of the system clock, e.g. from a comparison against a reference clock
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ClockModel {
+    /// Seconds added to all timestamps
+    pub offset: f64,
+    /// Rate error of the system clock in parts per million, positive if it runs fast
+    pub drift_ppm: f64,
+}
+
+/// Timestamps for the captured data.
+///
+/// The time is measured with the monotonic clock, anchored to a single reading
+/// of the wall clock when the capture starts, so the time between packets stays
+/// accurate when NTP steps the system clock during the capture. A [`ClockModel`]
+/// and the correction from a PPS source are applied on top of that.
+#[derive(Debug, Clone)]
+pub struct CaptureClock {
+    wall: SystemTime,
+    mono: Instant,
+    model: ClockModel,
+    /// Correction from the PPS source in nanoseconds, shared by all copies of the clock
+    pps_correction: Arc<AtomicI64>,
+}
+
+impl Default for CaptureClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `t` shifted by `secs`, which may be negative
+fn add_secs(t: SystemTime, secs: f64) -> SystemTime {
+    match secs >= 0.0 {
+        true => t + Duration::from_secs_f64(secs),
+        false => t - Duration::from_secs_f64(-secs),
+    }
+}
+
+/// Seconds from `b` to `a`
+fn secs_between(a: SystemTime, b: SystemTime) -> f64 {
+    match a.duration_since(b) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+impl CaptureClock {
+    pub fn new() -> Self {
+        Self::with_model(ClockModel::default())
+    }
+
+    pub fn with_model(model: ClockModel) -> Self {
+        Self {
+            mono: Instant::now(),
+            wall: SystemTime::now(),
+            model,
+            pps_correction: Arc::default(),
+        }
+    }
+
+    /// The current capture time
+    pub fn now(&self) -> SystemTime {
+        let pps = self.pps_correction.load(Ordering::Relaxed) as f64 * 1e-9;
+        add_secs(self.modelled_now(), pps)
+    }
+
+    /// The capture time without the PPS correction
+    fn modelled_now(&self) -> SystemTime {
+        let elapsed = self.mono.elapsed().as_secs_f64();
+        let secs = self.model.offset + elapsed * (1.0 - self.model.drift_ppm * 1e-6);
+        add_secs(self.wall, secs)
+    }
+
+    /// The wall clock time at the start of the capture
+    pub fn anchor(&self) -> SystemTime {
+        self.wall
+    }
+
+    /// How far the system clock has moved away from the capture clock since the start,
+    /// in seconds, positive if the system clock is ahead.
+    pub fn drift(&self) -> f64 {
+        secs_between(SystemTime::now(), self.now())
+    }
+
+    /// Align the capture clock to a pulse which marks the start of a second.
+    ///
+    /// `pulse` is the system clock time of the pulse, and the capture time is corrected
+    /// so the pulse falls on the nearest whole second. Returns the correction in seconds.
+    pub fn apply_pps(&self, pulse: SystemTime) -> f64 {
+        let since_pulse = secs_between(SystemTime::now(), pulse);
+        let at_pulse = add_secs(self.modelled_now(), -since_pulse);
+        let secs = secs_between(at_pulse, SystemTime::UNIX_EPOCH);
+        let correction = secs.round() - secs;
+        self.pps_correction
+            .store((correction * 1e9) as i64, Ordering::Relaxed);
+        correction
+    }
+
+    /// Marker which records the anchor in the capture file
+    pub fn anchor_marker(&self) -> Marker {
+        let mut label = "monotonic clock anchored to the system clock".to_string();
+        if self.model != ClockModel::default() {
+            label += &format!(
+                ", offset {:+.6} s, drift {:+.3} ppm",
+                self.model.offset, self.model.drift_ppm
+            );
+        }
+        Marker {
+            kind: MarkerKind::Clock,
+            ch: None,
+            label,
+            time: self.wall.into(),
+        }
+    }
+}
+
+/// How long an estimate of the device clock offset is used, before the next one replaces it
+const DEVICE_CLOCK_WINDOW: Duration = Duration::from_secs(10);
+
+/// Converts the chunk times measured by the capture device to capture time.
+///
+/// The offset between the clocks is taken from the reception with the least USB latency,
+/// i.e. the smallest difference between the capture time when the data was received and the
+/// device time, and is re-estimated every 10 s to follow the drift between the clocks.
+#[derive(Debug, Default)]
+pub struct DeviceClock {
+    /// Capture time at device time zero, and when it was estimated
+    offset: Option<(SystemTime, SystemTime)>,
+    /// The estimate for the next window
+    next_offset: Option<SystemTime>,
+}
+
+impl DeviceClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The capture time of `device_time`, for data received at `received`.
+    pub fn capture_time(&mut self, device_time: u32, received: SystemTime) -> SystemTime {
+        let device_time = Duration::from_micros((device_time & crate::DEVICE_TIME_MASK) as u64);
+        let wrap = (crate::DEVICE_TIME_MASK as f64 + 1.0) * 1e-6;
+        let candidate = received - device_time;
+        // the device time wraps around, move the estimates by whole wraps to match
+        let unwrap = |t: SystemTime| {
+            let wraps = (secs_between(candidate, t) / wrap).round();
+            add_secs(t, wraps * wrap)
+        };
+        let (offset, since) = self
+            .offset
+            .map_or((candidate, received), |(o, since)| (unwrap(o), since));
+        let next = self
+            .next_offset
+            .map_or(candidate, |n| unwrap(n).min(candidate));
+        let offset = offset.min(candidate);
+        if secs_between(received, since) > DEVICE_CLOCK_WINDOW.as_secs_f64() {
+            self.offset = Some((next, received));
+            self.next_offset = None;
+        } else {
+            self.offset = Some((offset, since));
+            self.next_offset = Some(next);
+        }
+        self.offset.unwrap().0 + device_time
+    }
+}
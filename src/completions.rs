@@ -0,0 +1,334 @@
+//! Shell completion scripts and man pages, generated from the command line definitions of the
+//! binaries.
+//!
+//! The completions offer the subcommands, the options and the values of the options with a fixed
+//! set of values, and fall back to file names for the other arguments. The zsh script uses the
+//! bash completion through `bashcompinit`.
+
+use std::fmt::Write as _;
+
+use clap::{Arg, Command, Subcommand, ValueEnum};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The subcommands of every binary which print its completions and man page
+#[derive(Subcommand, Debug)]
+pub enum DocCommand {
+    /// Print the shell completion script, e.g. to /usr/share/bash-completion/completions/
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page in roff, e.g. to /usr/local/share/man/man1/
+    Man,
+}
+
+impl DocCommand {
+    /// Print the output for `cmd`, the command line of the binary
+    pub fn run(&self, cmd: Command) {
+        match self {
+            Self::Completions { shell } => print!("{}", generate(*shell, cmd)),
+            Self::Man => print!("{}", man_page(cmd)),
+        }
+    }
+}
+
+/// The completion script of `cmd` for `shell`
+pub fn generate(shell: Shell, mut cmd: Command) -> String {
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(&cmd),
+        Shell::Zsh => format!(
+            "#compdef {name}\n\nautoload -U bashcompinit && bashcompinit\n\n{}",
+            bash(&cmd),
+            name = cmd.get_name()
+        ),
+        Shell::Fish => fish(&cmd),
+    }
+}
+
+/// The man page of `cmd` in roff, with a section for each subcommand
+pub fn man_page(mut cmd: Command) -> String {
+    cmd.build();
+    let name = cmd.get_name().to_string();
+    let mut out = String::new();
+    let source = match cmd.get_version() {
+        Some(version) => format!("{name} {version}"),
+        None => name.clone(),
+    };
+    writeln!(
+        out,
+        ".TH {} 1 \"\" \"{source}\"",
+        roff(&name.to_uppercase())
+    )
+    .unwrap();
+    writeln!(out, ".SH NAME").unwrap();
+    match cmd.get_about() {
+        Some(about) => writeln!(out, "{} \\- {}", roff(&name), roff(&about.to_string())),
+        None => writeln!(out, "{}", roff(&name)),
+    }
+    .unwrap();
+    writeln!(out, ".SH SYNOPSIS").unwrap();
+    writeln!(out, "{}", usage(&mut cmd)).unwrap();
+    if let Some(about) = cmd.get_long_about() {
+        writeln!(out, ".SH DESCRIPTION\n{}", roff(&about.to_string())).unwrap();
+    }
+    writeln!(out, ".SH OPTIONS").unwrap();
+    man_options(&mut out, &cmd);
+    let subcommands: Vec<_> = visible_subcommands(&cmd).collect();
+    if !subcommands.is_empty() {
+        writeln!(out, ".SH SUBCOMMANDS").unwrap();
+        for sub in subcommands {
+            man_subcommand(&mut out, &name, sub);
+        }
+    }
+    out
+}
+
+fn man_subcommand(out: &mut String, parent: &str, cmd: &Command) {
+    let path = format!("{parent} {}", cmd.get_name());
+    writeln!(out, ".SS {}", roff(&path)).unwrap();
+    if let Some(about) = cmd.get_about() {
+        writeln!(out, "{}", roff(&about.to_string())).unwrap();
+    }
+    // the usage of a built subcommand starts with the parent command
+    writeln!(out, ".PP\n{}", usage(&mut cmd.clone())).unwrap();
+    man_options(out, cmd);
+    for sub in visible_subcommands(cmd) {
+        man_subcommand(out, &path, sub);
+    }
+}
+
+fn man_options(out: &mut String, cmd: &Command) {
+    for arg in visible_args(cmd) {
+        let mut names = vec![];
+        if let Some(short) = arg.get_short() {
+            names.push(format!("\\fB\\-{short}\\fR"));
+        }
+        if let Some(long) = arg.get_long() {
+            names.push(format!("\\fB\\-\\-{}\\fR", roff(long)));
+        }
+        let mut item = names.join(", ");
+        if arg.is_positional() || arg.get_action().takes_values() {
+            write!(item, " \\fI{}\\fR", roff(&value_name(arg))).unwrap();
+        }
+        writeln!(out, ".TP\n{}", item.trim_start()).unwrap();
+        let mut help = arg.get_help().map(|h| h.to_string()).unwrap_or_default();
+        let values = values(arg);
+        if !values.is_empty() {
+            write!(help, " [possible values: {}]", values.join(", ")).unwrap();
+        }
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy())
+            .collect();
+        if !defaults.is_empty() && arg.get_action().takes_values() {
+            write!(help, " [default: {}]", defaults.join(",")).unwrap();
+        }
+        if !help.is_empty() {
+            writeln!(out, "{}", roff(help.trim_start())).unwrap();
+        }
+    }
+}
+
+/// The usage lines without the "Usage: " prefix, in roff
+fn usage(cmd: &mut Command) -> String {
+    let usage = cmd.render_usage().to_string();
+    let usage = usage.strip_prefix("Usage: ").unwrap_or(&usage);
+    usage
+        .lines()
+        .map(|line| roff(line.trim()))
+        .collect::<Vec<_>>()
+        .join("\n.br\n")
+}
+
+/// e.g. `<PCAP_FILE>` for a positional argument and `READS` for an option
+fn value_name(arg: &Arg) -> String {
+    let name = match arg.get_value_names() {
+        Some(names) => names
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => arg.get_id().to_string().to_uppercase(),
+    };
+    match arg.is_positional() {
+        true => format!("<{name}>"),
+        false => name,
+    }
+}
+
+/// Escape the text for roff
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    text.lines()
+        .map(|line| match line.starts_with(['.', '\'']) {
+            true => format!("\\&{line}"),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn visible_args(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|a| !a.is_hide_set())
+}
+
+fn visible_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands()
+        .filter(|c| !c.is_hide_set() && c.get_name() != "help")
+}
+
+/// The options of `arg`, e.g. `-o --output`
+fn flags(arg: &Arg) -> Vec<String> {
+    let short = arg.get_short().map(|s| format!("-{s}"));
+    let long = arg.get_long().map(|l| format!("--{l}"));
+    short.into_iter().chain(long).collect()
+}
+
+/// The values of an option with a fixed set of values
+fn values(arg: &Arg) -> Vec<String> {
+    match arg.get_action().takes_values() {
+        true => arg
+            .get_possible_values()
+            .iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| v.get_name().to_string())
+            .collect(),
+        false => vec![],
+    }
+}
+
+/// A shell function name for the command
+fn function_name(path: &str) -> String {
+    let name: String = path
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("_{name}")
+}
+
+/// Each command and subcommand with its path, e.g. "serial-pcap device dfu"
+fn commands(cmd: &Command) -> Vec<(String, &Command)> {
+    fn walk<'a>(path: String, cmd: &'a Command, out: &mut Vec<(String, &'a Command)>) {
+        for sub in visible_subcommands(cmd) {
+            out.push((format!("{path} {}", sub.get_name()), sub));
+            walk(format!("{path} {}", sub.get_name()), sub, out);
+        }
+    }
+    let mut out = vec![(cmd.get_name().to_string(), cmd)];
+    walk(cmd.get_name().to_string(), cmd, &mut out);
+    out
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let function = function_name(name);
+    let mut out = String::new();
+    writeln!(out, "{function}() {{").unwrap();
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"").unwrap();
+    writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"").unwrap();
+    writeln!(out, "    local cmd=\"{name}\" opts=\"\" i").unwrap();
+    // follow the subcommands on the command line
+    writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do").unwrap();
+    writeln!(out, "        case \"${{cmd}} ${{COMP_WORDS[i]}}\" in").unwrap();
+    let commands = commands(cmd);
+    for (path, _) in &commands[1..] {
+        writeln!(out, "            \"{path}\") cmd=\"{path}\" ;;").unwrap();
+    }
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    case \"${{cmd}}\" in").unwrap();
+    for (path, cmd) in &commands {
+        writeln!(out, "        \"{path}\")").unwrap();
+        let with_values: Vec<_> = visible_args(cmd)
+            .filter(|a| !a.is_positional() && !values(a).is_empty())
+            .collect();
+        if !with_values.is_empty() {
+            writeln!(out, "            case \"${{prev}}\" in").unwrap();
+            for arg in with_values {
+                writeln!(
+                    out,
+                    "                {})\n                    COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))\n                    return ;;",
+                    flags(arg).join("|"),
+                    values(arg).join(" ")
+                )
+                .unwrap();
+            }
+            writeln!(out, "            esac").unwrap();
+        }
+        let words: Vec<_> = visible_args(cmd)
+            .flat_map(flags)
+            .chain(visible_subcommands(cmd).map(|c| c.get_name().to_string()))
+            .collect();
+        writeln!(out, "            opts=\"{}\" ;;", words.join(" ")).unwrap();
+    }
+    writeln!(out, "    esac").unwrap();
+    writeln!(
+        out,
+        "    COMPREPLY=($(compgen -W \"${{opts}}\" -- \"${{cur}}\"))"
+    )
+    .unwrap();
+    writeln!(out, "}}").unwrap();
+    // file names when nothing matches
+    writeln!(out, "complete -o default -F {function} {name}").unwrap();
+    out
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut out = String::new();
+    for (path, sub) in commands(cmd) {
+        // the words after the command name
+        let words: Vec<_> = path.split(' ').skip(1).collect();
+        let condition = match words.last() {
+            None => "__fish_use_subcommand".to_string(),
+            Some(last) => format!("__fish_seen_subcommand_from {last}"),
+        };
+        for child in visible_subcommands(sub) {
+            write!(
+                out,
+                "complete -c {name} -n \"{condition}\" -f -a {}",
+                child.get_name()
+            )
+            .unwrap();
+            if let Some(about) = child.get_about() {
+                write!(out, " -d '{}'", fish_quote(&about.to_string())).unwrap();
+            }
+            out.push('\n');
+        }
+        for arg in visible_args(sub).filter(|a| !a.is_positional()) {
+            write!(out, "complete -c {name} -n \"{condition}\"").unwrap();
+            if let Some(short) = arg.get_short() {
+                write!(out, " -s {short}").unwrap();
+            }
+            if let Some(long) = arg.get_long() {
+                write!(out, " -l {long}").unwrap();
+            }
+            let values = values(arg);
+            if !values.is_empty() {
+                write!(out, " -x -a '{}'", values.join(" ")).unwrap();
+            } else if arg.get_action().takes_values() {
+                out.push_str(" -r");
+            }
+            if let Some(help) = arg.get_help() {
+                write!(out, " -d '{}'", fish_quote(&help.to_string())).unwrap();
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn fish_quote(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
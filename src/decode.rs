@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::master::Error as X328Error;
+use x328_proto::scanner::{ControllerEvent, NodeEvent, Scanner};
+use x328_proto::{Address, Parameter, Value};
+
+use crate::{CaptureRecord, Marker, MarkerKind, SerialPacket, Trigger, UartTxChannel};
+
+/// A command sent by the bus controller
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BusCommand {
+    Read {
+        addr: Address,
+        param: Parameter,
+    },
+    Write {
+        addr: Address,
+        param: Parameter,
+        value: Value,
+    },
+}
+
+impl BusCommand {
+    pub fn addr(&self) -> Address {
+        match self {
+            BusCommand::Read { addr, .. } | BusCommand::Write { addr, .. } => *addr,
+        }
+    }
+
+    pub fn param(&self) -> Parameter {
+        match self {
+            BusCommand::Read { param, .. } | BusCommand::Write { param, .. } => *param,
+        }
+    }
+}
+
+/// A bus controller command together with the node response
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub cmd: BusCommand,
+    pub cmd_time: DateTime<Utc>,
+    pub resp_time: DateTime<Utc>,
+    /// The value read, or the value written for successful write commands
+    pub result: Result<Value, X328Error>,
+}
+
+impl Transaction {
+    pub fn latency(&self) -> chrono::Duration {
+        self.resp_time - self.cmd_time
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BusEvent {
+    Transaction(Transaction),
+    /// The controller sent a new command without receiving a response to `cmd`
+    Timeout {
+        cmd: Option<BusCommand>,
+        time: DateTime<Utc>,
+    },
+    /// A node transmitted data without a preceding command
+    UnexpectedTransmission { time: DateTime<Utc> },
+    /// The node response doesn't match the controller command
+    Mismatch { time: DateTime<Utc> },
+    Trigger { time: DateTime<Utc> },
+    /// A break condition on the line, the partial frame received before it is discarded
+    Break {
+        ch: UartTxChannel,
+        time: DateTime<Utc>,
+    },
+    /// Traffic from more than one source on a channel
+    Collision {
+        kind: Collision,
+        time: DateTime<Utc>,
+    },
+}
+
+impl BusEvent {
+    /// The time of the response, or of the event if there is no response
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            BusEvent::Transaction(t) => t.resp_time,
+            BusEvent::Timeout { time, .. }
+            | BusEvent::UnexpectedTransmission { time }
+            | BusEvent::Mismatch { time }
+            | BusEvent::Trigger { time }
+            | BusEvent::Break { time, .. }
+            | BusEvent::Collision { time, .. } => *time,
+        }
+    }
+
+    /// The command the event is about, if it is known
+    pub fn command(&self) -> Option<BusCommand> {
+        match self {
+            BusEvent::Transaction(t) => Some(t.cmd),
+            BusEvent::Timeout { cmd, .. } => *cmd,
+            BusEvent::Collision {
+                kind: Collision::SecondMaster { cmd },
+                ..
+            } => *cmd,
+            _ => None,
+        }
+    }
+
+    /// Failed transactions, timeouts and protocol errors
+    pub fn is_error(&self) -> bool {
+        match self {
+            BusEvent::Transaction(t) => t.result.is_err(),
+            BusEvent::Trigger { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Collision {
+    /// The data on `ch` is a copy of the data on the other channel, e.g. from line echo
+    Echo { ch: UartTxChannel },
+    /// The controller sent a new command too soon after `cmd` for it to be a timeout,
+    /// e.g. because there is a second bus master
+    SecondMaster { cmd: Option<BusCommand> },
+    /// `ch` started transmitting while the other channel was still sending, judged from the
+    /// packet times and the transmission time of the bytes at the baud rate
+    Overlap { ch: UartTxChannel },
+}
+
+/// Bytes which appear on the other channel within this time are treated as an echo
+const ECHO_WINDOW: Duration = Duration::milliseconds(3);
+/// Bits per character with the start, parity and stop bits of 7E1
+const CHAR_BITS: i64 = 10;
+/// Number of bytes kept for the echo detection
+const ECHO_HISTORY: usize = 64;
+
+/// Recently received bytes on one channel, with the packet times
+#[derive(Default)]
+struct EchoHistory {
+    bytes: VecDeque<(u8, DateTime<Utc>)>,
+    /// The last byte on this channel was an echo
+    echoing: bool,
+    /// The estimated end of the last byte sent on this channel, not counting echoes
+    end: Option<DateTime<Utc>>,
+    /// An overlap with the other channel has been reported for the current transmission
+    overlapping: bool,
+}
+
+/// Push-based X3.28 decoder for a stream of [`SerialPacket`]s.
+///
+/// Data that doesn't form a complete frame is kept until the next packet
+/// on the same channel, so frames can be split across packets. Only the packets
+/// from bus 0 are decoded.
+///
+/// Bytes which are copies of the data on the other channel, and commands sent
+/// too close together to come from a single bus master, are reported as
+/// [`BusEvent::Collision`] instead of being passed to the protocol scanner.
+/// With a known baud rate, so are transmissions which start while the other channel
+/// is still sending, see [`with_baud`](Self::with_baud).
+pub struct X328Decoder {
+    scanner: Scanner,
+    ctrl_buf: Vec<u8>,
+    node_buf: Vec<u8>,
+    pending: Option<(BusCommand, DateTime<Utc>)>,
+    ctrl_history: EchoHistory,
+    node_history: EchoHistory,
+    min_timeout: Duration,
+    /// Transmission time of one byte, for estimating the time of each byte in a packet
+    byte_time: Duration,
+    detect_overlap: bool,
+    reset_on_trigger: bool,
+}
+
+impl Default for X328Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl X328Decoder {
+    pub fn new() -> Self {
+        Self {
+            scanner: Scanner::new(),
+            ctrl_buf: vec![],
+            node_buf: vec![],
+            pending: None,
+            ctrl_history: EchoHistory::default(),
+            node_history: EchoHistory::default(),
+            min_timeout: Duration::milliseconds(20),
+            byte_time: Duration::microseconds(1_000_000 * CHAR_BITS / 9600),
+            detect_overlap: false,
+            reset_on_trigger: false,
+        }
+    }
+
+    /// Commands sent within `min_timeout` of the previous unanswered command are
+    /// reported as a [`Collision::SecondMaster`] instead of a timeout. Defaults to 20 ms.
+    pub fn with_min_timeout(min_timeout: std::time::Duration) -> Self {
+        Self {
+            min_timeout: Duration::from_std(min_timeout).unwrap_or(Duration::max_value()),
+            ..Self::new()
+        }
+    }
+
+    /// Estimate the time of each byte from the baud rate of the bus instead of assuming
+    /// 9600 baud, and report a [`Collision::Overlap`] when a channel starts sending before
+    /// the last byte on the other channel has been sent. Without the actual baud rate,
+    /// e.g. for a capture over ptys, the byte times are too unreliable for that.
+    pub fn with_baud(mut self, baud: u32) -> Self {
+        self.byte_time = Duration::microseconds(1_000_000 * CHAR_BITS / i64::from(baud.max(1)));
+        self.detect_overlap = true;
+        self
+    }
+
+    /// Start over with a fresh protocol scanner at each trigger event, so a confused decoder
+    /// state before the trigger doesn't spoil the decoding after it.
+    pub fn with_reset_on_trigger(mut self, reset: bool) -> Self {
+        self.reset_on_trigger = reset;
+        self
+    }
+
+    /// Decode the data in `pkt`, calling `on_event` for every bus event found.
+    pub fn feed(&mut self, pkt: &SerialPacket, mut on_event: impl FnMut(BusEvent)) {
+        if pkt.bus != 0 {
+            return;
+        }
+        self.push_bytes(pkt, &mut on_event);
+        self.scan(pkt, &mut on_event);
+    }
+
+    /// Decode a packet read from a capture, or report a trigger or a break marker as a
+    /// [`BusEvent::Trigger`] or [`BusEvent::Break`]. The other markers are ignored. The
+    /// markers don't record the bus, so they are taken to be from bus 0.
+    pub fn feed_record(&mut self, record: &CaptureRecord, mut on_event: impl FnMut(BusEvent)) {
+        match record {
+            CaptureRecord::Packet(pkt) => self.feed(pkt, on_event),
+            CaptureRecord::Trigger(Trigger { bus: 0, time, .. }) => {
+                self.trigger(*time, &mut on_event)
+            }
+            CaptureRecord::Trigger(_) => {}
+            CaptureRecord::Marker(Marker {
+                kind: MarkerKind::Break,
+                ch: Some(ch),
+                time,
+                ..
+            }) => self.line_break(*ch, *time, on_event),
+            CaptureRecord::Marker(_) => {}
+        }
+    }
+
+    /// Report a break on the line of `ch`, and discard the partial frame received on it.
+    pub fn line_break(
+        &mut self,
+        ch: UartTxChannel,
+        time: DateTime<Utc>,
+        mut on_event: impl FnMut(BusEvent),
+    ) {
+        match ch {
+            UartTxChannel::Ctrl => self.ctrl_buf.clear(),
+            UartTxChannel::Node => self.node_buf.clear(),
+        }
+        on_event(BusEvent::Break { ch, time });
+    }
+
+    /// Report a measurement trigger, and start over if the decoder resets on triggers.
+    pub fn trigger(&mut self, time: DateTime<Utc>, mut on_event: impl FnMut(BusEvent)) {
+        on_event(BusEvent::Trigger { time });
+        if self.reset_on_trigger {
+            self.reset();
+        }
+    }
+
+    /// Forget the partial frames, the pending command and the echo history, as if the
+    /// decoding started over.
+    pub fn reset(&mut self) {
+        *self = Self {
+            reset_on_trigger: self.reset_on_trigger,
+            min_timeout: self.min_timeout,
+            byte_time: self.byte_time,
+            detect_overlap: self.detect_overlap,
+            ..Self::new()
+        };
+    }
+
+    /// Filter out the echoed bytes of `pkt` and append the rest to the channel buffer
+    fn push_bytes(&mut self, pkt: &SerialPacket, on_event: &mut impl FnMut(BusEvent)) {
+        let (history, other) = match pkt.ch {
+            UartTxChannel::Ctrl => (&mut self.ctrl_history, &mut self.node_history),
+            UartTxChannel::Node => (&mut self.node_history, &mut self.ctrl_history),
+        };
+        // the end of the other transmission, before the bytes of this packet clear the history
+        let other_end = other.end;
+        let mut first = None;
+        let mut byte_time = pkt.time;
+        for &b in &pkt.data[..] {
+            let time = byte_time;
+            byte_time += self.byte_time;
+            while matches!(other.bytes.front(), Some(&(_, t)) if time - t > ECHO_WINDOW) {
+                other.bytes.pop_front();
+            }
+            if matches!(other.bytes.front(), Some(&(o, _)) if o == b) {
+                other.bytes.pop_front();
+                if !history.echoing {
+                    let kind = Collision::Echo { ch: pkt.ch };
+                    on_event(BusEvent::Collision {
+                        kind,
+                        time: pkt.time,
+                    });
+                }
+                history.echoing = true;
+                continue;
+            }
+            // an echo is an unbroken copy, so the earlier bytes can't be echoed any more
+            history.echoing = false;
+            other.bytes.clear();
+            if history.bytes.len() == ECHO_HISTORY {
+                history.bytes.pop_front();
+            }
+            history.bytes.push_back((b, time));
+            history.end = Some(byte_time);
+            first.get_or_insert(time);
+            match pkt.ch {
+                UartTxChannel::Ctrl => self.ctrl_buf.push(b),
+                UartTxChannel::Node => self.node_buf.push(b),
+            }
+        }
+        let Some(first) = first.filter(|_| self.detect_overlap) else {
+            return;
+        };
+        // allow one byte time of slack for the timestamp jitter between the channels
+        if matches!(other_end, Some(end) if end - first > self.byte_time) {
+            if !history.overlapping {
+                let kind = Collision::Overlap { ch: pkt.ch };
+                on_event(BusEvent::Collision { kind, time: first });
+            }
+            history.overlapping = true;
+        } else {
+            history.overlapping = false;
+        }
+    }
+
+    /// Pass the buffered data of the packet channel to the scanner, until it needs more data
+    fn scan(&mut self, pkt: &SerialPacket, on_event: &mut impl FnMut(BusEvent)) {
+        loop {
+            let (empty, progress) = match pkt.ch {
+                UartTxChannel::Ctrl => {
+                    if self.ctrl_buf.is_empty() {
+                        return;
+                    }
+                    let (consumed, event) = self.scanner.recv_from_ctrl(&self.ctrl_buf);
+                    self.ctrl_buf.drain(..consumed);
+                    let progress = consumed != 0 || event.is_some();
+                    if let Some(event) = event.and_then(|e| self.ctrl_event(e, pkt.time)) {
+                        on_event(event);
+                    }
+                    (self.ctrl_buf.is_empty(), progress)
+                }
+                UartTxChannel::Node => {
+                    if self.node_buf.is_empty() {
+                        return;
+                    }
+                    let (consumed, event) = self.scanner.recv_from_node(&self.node_buf);
+                    self.node_buf.drain(..consumed);
+                    let progress = consumed != 0 || event.is_some();
+                    if let Some(event) = event {
+                        on_event(self.node_event(event, pkt.time));
+                    }
+                    (self.node_buf.is_empty(), progress)
+                }
+            };
+            if empty || !progress {
+                break;
+            }
+        }
+    }
+
+    fn ctrl_event(&mut self, event: ControllerEvent, time: DateTime<Utc>) -> Option<BusEvent> {
+        let cmd = match event {
+            ControllerEvent::Read(addr, param) => BusCommand::Read { addr, param },
+            ControllerEvent::Write(addr, param, value) => BusCommand::Write { addr, param, value },
+            ControllerEvent::NodeTimeout => {
+                let pending = self.pending.take();
+                let cmd = pending.map(|(cmd, _)| cmd);
+                if matches!(pending, Some((_, t)) if time - t < self.min_timeout) {
+                    let kind = Collision::SecondMaster { cmd };
+                    return Some(BusEvent::Collision { kind, time });
+                }
+                return Some(BusEvent::Timeout { cmd, time });
+            }
+        };
+        self.pending = Some((cmd, time));
+        None
+    }
+
+    fn node_event(&mut self, event: NodeEvent, resp_time: DateTime<Utc>) -> BusEvent {
+        let pending = self.pending.take();
+        let result = match (event, pending.map(|(cmd, _)| cmd)) {
+            (NodeEvent::Read(r), Some(BusCommand::Read { .. })) => r,
+            (NodeEvent::Write(r), Some(BusCommand::Write { value, .. })) => r.map(|_| value),
+            (NodeEvent::UnexpectedTransmission, _) => {
+                return BusEvent::UnexpectedTransmission { time: resp_time }
+            }
+            _ => return BusEvent::Mismatch { time: resp_time },
+        };
+        let (cmd, cmd_time) = pending.unwrap();
+        BusEvent::Transaction(Transaction {
+            cmd,
+            cmd_time,
+            resp_time,
+            result,
+        })
+    }
+}
@@ -0,0 +1,151 @@
+//! The USB serial ports of the capture device: the muxed stream of the bus data on the first port,
+//! a human readable log of the bus events on the second, and the muxed stream of the second bus
+//! on the third.
+//!
+//! The event log lines are timestamped with the capture clock when they are received, so they
+//! can be matched with the packets in the pcap file. The packets are timestamped by the device
+//! when the data arrived, so the event lines are a little later, by the USB latency and the time
+//! the device took to decode the transaction.
+
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_serial::{available_ports, SerialPortType};
+use tracing::info;
+
+use crate::clock::CaptureClock;
+use crate::framed::VID_PID;
+
+/// The serial ports of one capture device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePorts {
+    /// The muxed stream of the first bus
+    pub stream: String,
+    /// The event log, which also takes the settings commands
+    pub events: String,
+    /// The muxed stream of the second bus, with firmware which taps one
+    pub bus2: Option<String>,
+}
+
+impl DevicePorts {
+    /// Find the serial ports of the capture device by its USB VID:PID
+    pub fn find() -> Result<Self> {
+        let ports = available_ports().context("Failed to list the serial ports.")?;
+        let names = ports.into_iter().filter_map(|p| match p.port_type {
+            SerialPortType::UsbPort(usb) if (usb.vid, usb.pid) == VID_PID => Some(p.port_name),
+            _ => None,
+        });
+        Self::from_names(names)
+    }
+
+    /// The ports of the device from the names of its serial ports, in any order.
+    ///
+    /// The ports are numbered in the order of the USB interfaces, so the names are sorted with
+    /// the numbers compared by value, e.g. ttyACM2 before ttyACM10.
+    pub fn from_names(names: impl IntoIterator<Item = String>) -> Result<Self> {
+        let (vid, pid) = VID_PID;
+        let mut names: Vec<_> = names.into_iter().collect();
+        names.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+        let mut names = names.into_iter();
+        match (names.next(), names.next(), names.next(), names.len()) {
+            (Some(stream), Some(events), bus2, 0) => Ok(Self {
+                stream,
+                events,
+                bus2,
+            }),
+            (None, ..) => bail!("No capture device with the USB ID {vid:04x}:{pid:04x} found."),
+            (Some(port), None, ..) => bail!(
+                "Only one serial port of the capture device found ({port}), expected at least two."
+            ),
+            _ => bail!(
+                "Found serial ports of more than one capture device, \
+                select the ports of one with --muxed-stream --ctrl."
+            ),
+        }
+    }
+}
+
+/// A line of the event log, e.g. "Node 31 read ok 401 == 120"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLine {
+    /// The capture time when the start of the line was received
+    pub time: DateTime<Utc>,
+    pub text: String,
+}
+
+impl Display for EventLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.6f"),
+            self.text
+        )
+    }
+}
+
+/// Splits the event log into lines, which end with "\r\n" or "\n"
+#[derive(Debug, Default)]
+pub struct EventLineSplitter {
+    line: Vec<u8>,
+    /// When the first byte of `line` was received
+    start: Option<DateTime<Utc>>,
+}
+
+impl EventLineSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the bytes of one read at `time`, and get the complete lines. Empty lines are skipped.
+    pub fn feed(&mut self, data: &[u8], time: DateTime<Utc>) -> Vec<EventLine> {
+        let mut lines = vec![];
+        for &b in data {
+            match b {
+                b'\n' => {
+                    let text = String::from_utf8_lossy(&self.line).trim_end().to_string();
+                    self.line.clear();
+                    if let Some(time) = self.start.take().filter(|_| !text.is_empty()) {
+                        lines.push(EventLine { time, text });
+                    }
+                }
+                b => {
+                    self.start.get_or_insert(time);
+                    self.line.push(b);
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Read the event log of the capture device, and write the timestamped lines to `outputs`,
+/// e.g. stdout and a log file. The outputs are kept for the next port when the device is
+/// reconnected.
+pub async fn read_event_log<R: AsyncRead + Unpin>(
+    mut port: R,
+    clock: CaptureClock,
+    outputs: &mut [Box<dyn Write + Send>],
+) -> Result<()> {
+    let mut buf = [0; 256];
+    let mut splitter = EventLineSplitter::new();
+    loop {
+        let len = port
+            .read(&mut buf)
+            .await
+            .context("Read error from the event log port.")?;
+        if len == 0 {
+            info!("Zero length read");
+            bail!("Read from the event log port returned 0 bytes.");
+        }
+        for line in splitter.feed(&buf[..len], clock.now().into()) {
+            for out in outputs.iter_mut() {
+                writeln!(out, "{line}").context("Failed to write the event log.")?;
+                out.flush()?;
+            }
+        }
+    }
+}
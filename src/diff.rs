@@ -0,0 +1,233 @@
+//! Comparison of the X3.28 transactions in two captures, ignoring the timing.
+//!
+//! The transactions are aligned on the commands, so a command which is only in one of the
+//! captures shows up as added or removed, and a command which got a different response, or
+//! wrote a different value, as changed.
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use x328_proto::master::Error as X328Error;
+
+use crate::decode::{BusCommand, BusEvent, X328Decoder};
+use crate::names::NameMap;
+use crate::SerialPacket;
+
+/// Give up on captures with more differences than this, the comparison time and memory grow
+/// with the square of the number of differences.
+pub const MAX_DIFFERENCES: usize = 2000;
+
+/// The response to a command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The value read, or written
+    Ok(i32),
+    InvalidParameter,
+    CommandFailed,
+    ProtocolError,
+    Timeout,
+}
+
+impl From<&Result<x328_proto::Value, X328Error>> for Outcome {
+    fn from(result: &Result<x328_proto::Value, X328Error>) -> Self {
+        match result {
+            Ok(v) => Outcome::Ok(**v),
+            Err(X328Error::InvalidParameter) => Outcome::InvalidParameter,
+            Err(X328Error::CommandFailed) => Outcome::CommandFailed,
+            Err(X328Error::ProtocolError) => Outcome::ProtocolError,
+        }
+    }
+}
+
+/// A command and its outcome
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub cmd: BusCommand,
+    pub outcome: Outcome,
+    pub time: DateTime<Utc>,
+}
+
+impl Exchange {
+    /// The exchange of a transaction, or of a command which timed out
+    pub fn from_event(event: &BusEvent) -> Option<Self> {
+        match event {
+            BusEvent::Transaction(t) => Some(Exchange {
+                cmd: t.cmd,
+                outcome: (&t.result).into(),
+                time: t.cmd_time,
+            }),
+            BusEvent::Timeout {
+                cmd: Some(cmd),
+                time,
+            } => Some(Exchange {
+                cmd: *cmd,
+                outcome: Outcome::Timeout,
+                time: *time,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Both are reads or writes of the same parameter
+    fn same_command(&self, other: &Exchange) -> bool {
+        let is_read = |cmd| matches!(cmd, BusCommand::Read { .. });
+        is_read(self.cmd) == is_read(other.cmd)
+            && self.cmd.addr() == other.cmd.addr()
+            && self.cmd.param() == other.cmd.param()
+    }
+
+    /// e.g. "Read 23@21 => 120", with the names from `names`
+    pub fn describe(&self, names: &NameMap) -> String {
+        let (a, p) = (*self.cmd.addr(), *self.cmd.param());
+        let label = names.label(a, p);
+        let outcome = match self.outcome {
+            Outcome::Ok(v) => names.value(a, p, v),
+            other => other.to_string(),
+        };
+        match self.cmd {
+            BusCommand::Read { .. } => format!("Read  {label} => {outcome}"),
+            BusCommand::Write { value, .. } => {
+                let value = names.value(a, p, *value);
+                match self.outcome {
+                    Outcome::Ok(_) => format!("Write {label} = {value}"),
+                    _ => format!("Write {label} = {value} => {outcome}"),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Ok(v) => write!(f, "{v}"),
+            Outcome::InvalidParameter => f.write_str("invalid parameter"),
+            Outcome::CommandFailed => f.write_str("command failed"),
+            Outcome::ProtocolError => f.write_str("protocol error"),
+            Outcome::Timeout => f.write_str("timeout"),
+        }
+    }
+}
+
+/// Decode the commands and their outcomes from the packets
+pub fn exchanges(packets: impl Iterator<Item = Result<SerialPacket>>) -> Result<Vec<Exchange>> {
+    let mut decoder = X328Decoder::new();
+    let mut exchanges = vec![];
+    for pkt in packets {
+        decoder.feed(&pkt?, |event| {
+            exchanges.extend(Exchange::from_event(&event))
+        });
+    }
+    Ok(exchanges)
+}
+
+/// One step of the comparison, with the indexes of the exchanges
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffItem {
+    Same(usize, usize),
+    /// The same command, with a different value or outcome
+    Changed(usize, usize),
+    /// Only in the first capture
+    Removed(usize),
+    /// Only in the second capture
+    Added(usize),
+}
+
+/// Align the exchanges of two captures on the commands
+pub fn diff(a: &[Exchange], b: &[Exchange]) -> Result<Vec<DiffItem>> {
+    let prefix = a
+        .iter()
+        .zip(b)
+        .take_while(|(a, b)| a.same_command(b))
+        .count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a.same_command(b))
+        .count();
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut items: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let mut edits = vec![];
+    for (i, j) in myers(mid_a, mid_b)? {
+        items.push((prefix + i, prefix + j));
+    }
+    items.extend((0..suffix).map(|n| (a.len() - suffix + n, b.len() - suffix + n)));
+
+    // fill in the unmatched exchanges between the matched ones
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in items {
+        edits.extend((i..mi).map(DiffItem::Removed));
+        edits.extend((j..mj).map(DiffItem::Added));
+        let same = a[mi].cmd == b[mj].cmd && a[mi].outcome == b[mj].outcome;
+        edits.push(match same {
+            true => DiffItem::Same(mi, mj),
+            false => DiffItem::Changed(mi, mj),
+        });
+        (i, j) = (mi + 1, mj + 1);
+    }
+    edits.extend((i..a.len()).map(DiffItem::Removed));
+    edits.extend((j..b.len()).map(DiffItem::Added));
+    Ok(edits)
+}
+
+/// The matching pairs of a shortest edit script, with the algorithm by Eugene W. Myers
+fn myers(a: &[Exchange], b: &[Exchange]) -> Result<Vec<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // the v array after each number of edits, for finding the path back
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_DIFFERENCES {
+            bail!("The captures have more than {MAX_DIFFERENCES} differences.");
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (offset + k) as usize;
+            let mut x = match k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                true => v[idx + 1],
+                false => v[idx - 1] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize].same_command(&b[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // walk back from the end, collecting the diagonal moves
+    let mut pairs = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let (prev_x, prev_y) = match d {
+            0 => (0, 0),
+            _ => {
+                let at = |k: isize| v[(k + d) as usize];
+                let k = x - y;
+                let prev_k = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+                    true => k + 1,
+                    false => k - 1,
+                };
+                (at(prev_k), at(prev_k) - prev_k)
+            }
+        };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    pairs.reverse();
+    Ok(pairs)
+}
@@ -0,0 +1,66 @@
+//! Generation of a Wireshark Lua dissector which shows the node and parameter names from a
+//! name mapping file, so the captures can be read in Wireshark without looking up the numbers.
+//!
+//! The dissector decodes the X3.28 frames in the UART data packets, and checks the BCC of the
+//! write commands and read responses.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::names::NameMap;
+
+const TEMPLATE: &str = include_str!("../wireshark/x328-template.lua");
+
+/// The Lua source of the dissector, with the names from `names`
+pub fn generate(names: &NameMap) -> String {
+    let nodes: BTreeMap<_, _> = names.nodes().collect();
+    let mut params: BTreeMap<u8, BTreeMap<i16, _>> = BTreeMap::new();
+    for ((addr, param), info) in names.params() {
+        params.entry(addr).or_default().insert(param, info);
+    }
+
+    let mut tables = String::from("local nodes = {\n");
+    for (addr, name) in nodes {
+        writeln!(tables, "    [{addr}] = {},", lua_string(name)).unwrap();
+    }
+    tables.push_str("}\n\nlocal params = {\n");
+    for (addr, params) in params {
+        writeln!(tables, "    [{addr}] = {{").unwrap();
+        for (param, info) in params {
+            write!(
+                tables,
+                "        [{param}] = {{ name = {}",
+                lua_string(&info.name)
+            )
+            .unwrap();
+            if let Some(unit) = &info.unit {
+                write!(tables, ", unit = {}", lua_string(unit)).unwrap();
+            }
+            if let Some(description) = &info.description {
+                write!(tables, ", description = {}", lua_string(description)).unwrap();
+            }
+            tables.push_str(" },\n");
+        }
+        tables.push_str("    },\n");
+    }
+    tables.push('}');
+    TEMPLATE.replace("--@NAMES@", &tables)
+}
+
+/// A quoted Lua string literal
+pub fn lua_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            // the decimal escape can't be followed by a digit, so it's always three digits
+            c if c.is_ascii_control() => write!(quoted, "\\{:03}", c as u8).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
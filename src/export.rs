@@ -0,0 +1,89 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{SerialPacketReader, UartTxChannel};
+
+/// Logic analyzer UART export formats
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum AnalyzerFormat {
+    /// sigrok-cli UART decoder annotations, with sample numbers
+    Sigrok,
+    /// Saleae Logic 2 "Async Serial" analyzer CSV export
+    Saleae,
+}
+
+/// Settings for the byte timing reconstruction.
+///
+/// The pcap only holds a timestamp per packet, so the individual byte times
+/// are estimated from the baud rate, assuming back-to-back transmission.
+#[derive(Debug, Copy, Clone)]
+pub struct ExportTiming {
+    pub baud: u32,
+    /// Bits per character, including start, parity and stop bits
+    pub frame_bits: u32,
+    /// Sample rate for the sigrok sample numbers
+    pub samplerate: u64,
+}
+
+impl Default for ExportTiming {
+    fn default() -> Self {
+        Self {
+            baud: 9600,
+            frame_bits: 10, // 7E1
+            samplerate: 1_000_000,
+        }
+    }
+}
+
+impl ExportTiming {
+    fn byte_duration(&self) -> f64 {
+        self.frame_bits as f64 / self.baud as f64
+    }
+}
+
+pub fn channel_name(ch: UartTxChannel) -> &'static str {
+    match ch {
+        UartTxChannel::Ctrl => "ctrl",
+        UartTxChannel::Node => "node",
+    }
+}
+
+/// Convert all packets from `reader` to `format`, writing the result to `out`.
+pub fn export<R: std::io::Read>(
+    reader: &mut SerialPacketReader<R>,
+    mut out: impl Write,
+    format: AnalyzerFormat,
+    timing: ExportTiming,
+) -> Result<()> {
+    if format == AnalyzerFormat::Saleae {
+        writeln!(out, "name,type,start_time,duration,data")?;
+    }
+    let byte_duration = timing.byte_duration();
+    let mut start_time: Option<DateTime<Utc>> = None;
+    for pkt in reader {
+        let pkt = pkt?;
+        let start = *start_time.get_or_insert(pkt.time);
+        let pkt_offset = (pkt.time - start).num_nanoseconds().unwrap_or(0) as f64 / 1e9;
+        let name = channel_name(pkt.ch);
+        for (i, b) in pkt.data.iter().enumerate() {
+            let t = pkt_offset + i as f64 * byte_duration;
+            match format {
+                AnalyzerFormat::Sigrok => {
+                    let ss = (t * timing.samplerate as f64).round() as u64;
+                    let es = ((t + byte_duration) * timing.samplerate as f64).round() as u64;
+                    writeln!(out, "{ss}-{es} uart-{name}: {b:02X}")?;
+                }
+                AnalyzerFormat::Saleae => {
+                    writeln!(
+                        out,
+                        "\"{name}\",\"data\",{t:.9},{byte_duration:.9},0x{b:02X}"
+                    )?;
+                }
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! Extraction of the bytes of one channel as a plain byte stream, for tools which read the
+//! raw data of a serial port.
+//!
+//! The packet boundaries and times are lost in the byte stream, so they can be written to a
+//! CSV index with a line per packet: the offset of its first byte in the stream, its length
+//! and its time.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+
+use crate::{SerialPacketReader, UartTxChannel};
+
+/// The amount of data extracted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractStats {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Write the data of channel `ch` on `bus` to `out`, and a line per packet to `index`.
+pub fn extract<R: std::io::Read>(
+    reader: &mut SerialPacketReader<R>,
+    bus: u8,
+    ch: UartTxChannel,
+    mut out: impl Write,
+    mut index: Option<&mut dyn Write>,
+) -> Result<ExtractStats> {
+    let mut stats = ExtractStats::default();
+    if let Some(index) = &mut index {
+        writeln!(index, "offset,length,time")?;
+    }
+    for pkt in reader {
+        let pkt = pkt?;
+        if pkt.bus != bus || pkt.ch != ch {
+            continue;
+        }
+        out.write_all(&pkt.data)
+            .context("Failed to write the extracted data.")?;
+        if let Some(index) = &mut index {
+            let time = pkt.time.to_rfc3339_opts(SecondsFormat::Micros, true);
+            writeln!(index, "{},{},{time}", stats.bytes, pkt.data.len())
+                .context("Failed to write the index.")?;
+        }
+        stats.packets += 1;
+        stats.bytes += pkt.data.len() as u64;
+    }
+    out.flush()?;
+    if let Some(index) = &mut index {
+        index.flush()?;
+    }
+    Ok(stats)
+}
@@ -0,0 +1,143 @@
+//! The current values of the X3.28 node parameters, mirrored from the decoded bus traffic.
+//!
+//! The consumers of the decoded stream, e.g. the terminal UI, a web UI or MQTT, keep the values
+//! of all the nodes with a `FieldBusMirror` instead of their own bookkeeping, and subscribe to
+//! the changes. The nodes with a mirror in `serial_pcap_core::x328_bus`, at the addresses from
+//! `NodeAddresses`, also give the same `UpdateEvent`s as the capture firmware shows on its
+//! display.
+//!
+//! The values are taken from the successful transactions only, a write without a response
+//! doesn't change the mirror.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use chrono::{DateTime, Utc};
+use serial_pcap_core::nodes::NodeAddresses;
+use serial_pcap_core::x328_bus::{FieldBus, UpdateEvent};
+use x328_proto::{Address, Parameter, Value};
+
+use crate::decode::BusEvent;
+
+/// The last value of a parameter, and when it was seen on the bus
+#[derive(Debug, Copy, Clone)]
+pub struct ParamValue {
+    pub value: Value,
+    pub time: DateTime<Utc>,
+}
+
+/// A parameter which got a new value
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub addr: Address,
+    pub param: Parameter,
+    pub value: Value,
+    /// None for the first value seen
+    pub prev: Option<Value>,
+    pub time: DateTime<Utc>,
+    /// The update of the node mirror, for the nodes which have one
+    pub update: Option<UpdateEvent>,
+}
+
+struct Subscriber {
+    /// Only the changes of this node
+    addr: Option<Address>,
+    tx: Sender<Change>,
+}
+
+/// The parameter values of all the nodes on the bus
+pub struct FieldBusMirror {
+    nodes: FieldBus,
+    values: BTreeMap<(Address, Parameter), ParamValue>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl Default for FieldBusMirror {
+    fn default() -> Self {
+        Self::new(&NodeAddresses::DEFAULT)
+    }
+}
+
+impl FieldBusMirror {
+    /// `addrs` are the addresses of the nodes with a mirror
+    pub fn new(addrs: &NodeAddresses) -> Self {
+        Self {
+            nodes: FieldBus::new(addrs),
+            values: BTreeMap::new(),
+            subscribers: vec![],
+        }
+    }
+
+    /// Receive the changes of all the nodes. The subscription ends when the receiver is
+    /// dropped.
+    pub fn subscribe(&mut self) -> Receiver<Change> {
+        self.add_subscriber(None)
+    }
+
+    /// Receive the changes of the node at `addr`
+    pub fn subscribe_node(&mut self, addr: Address) -> Receiver<Change> {
+        self.add_subscriber(Some(addr))
+    }
+
+    fn add_subscriber(&mut self, addr: Option<Address>) -> Receiver<Change> {
+        let (tx, rx) = channel();
+        self.subscribers.push(Subscriber { addr, tx });
+        rx
+    }
+
+    /// Update the mirror from a decoded bus event
+    pub fn handle_event(&mut self, event: &BusEvent) {
+        if let BusEvent::Transaction(t) = event {
+            if let Ok(value) = t.result {
+                self.update(t.cmd.addr(), t.cmd.param(), value, t.resp_time);
+            }
+        }
+    }
+
+    /// Set a parameter value, and notify the subscribers if it changed
+    pub fn update(&mut self, addr: Address, param: Parameter, value: Value, time: DateTime<Utc>) {
+        let prev = self
+            .values
+            .insert((addr, param), ParamValue { value, time })
+            .map(|p| p.value);
+        let update = self.nodes.update_parameter(addr, param, value);
+        if prev.is_some_and(|prev| *prev == *value) {
+            return;
+        }
+        let change = Change {
+            addr,
+            param,
+            value,
+            prev,
+            time,
+            update,
+        };
+        self.subscribers.retain(|s| match s.addr {
+            Some(a) if a != addr => true,
+            // the receiver is gone
+            _ => s.tx.send(change.clone()).is_ok(),
+        });
+    }
+
+    pub fn value(&self, addr: Address, param: Parameter) -> Option<ParamValue> {
+        self.values.get(&(addr, param)).copied()
+    }
+
+    /// All the parameters seen, by address and parameter number
+    pub fn values(&self) -> impl Iterator<Item = (Address, Parameter, &ParamValue)> {
+        self.values.iter().map(|(&(a, p), v)| (a, p, v))
+    }
+
+    /// The parameters of the node at `addr`
+    pub fn node_values(&self, addr: Address) -> impl Iterator<Item = (Parameter, &ParamValue)> {
+        self.values
+            .range((addr, Parameter::new(0).unwrap())..)
+            .take_while(move |((a, _), _)| *a == addr)
+            .map(|(&(_, p), v)| (p, v))
+    }
+
+    /// The mirrors of the known nodes, e.g. the IoBox bits
+    pub fn nodes(&self) -> &FieldBus {
+        &self.nodes
+    }
+}
@@ -0,0 +1,103 @@
+//! Rewriting of captures from older versions in the current encoding.
+//!
+//! The reader accepts some quirks of older captures: the node channel on UDP port 1442
+//! instead of 1422, addresses which don't match the channel, a snaplen other than the one
+//! written now, packets longer than the current packet size and the measurement triggers as
+//! [`TRIG_BYTE`] in the data. A fixed up capture has the canonical ports and
+//! addresses, the current snaplen, and the triggers as trigger packets, so it reads the same in
+//! every tool.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use etherparse::{SlicedPacket, TransportSlice};
+use rpcap::read::PcapReader;
+
+use crate::{parse_record, RecordRef, SerialPacketWriter, Trigger, MAX_PACKET_LEN, TRIG_BYTE};
+
+/// What was changed in the capture
+#[derive(Debug, Default)]
+pub struct FixupReport {
+    /// Number of UART data packets written
+    pub packets: u64,
+    /// Number of markers copied
+    pub markers: u64,
+    /// Packets on the old node channel port 1442
+    pub legacy_ports: u64,
+    /// Trigger bytes moved to trigger packets
+    pub triggers: u64,
+    /// Packets too long for the current packet size, which were split
+    pub split: u64,
+    /// The snaplen of the original capture
+    pub snaplen: usize,
+}
+
+impl fmt::Display for FixupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets, {} markers, {} legacy ports, {} triggers moved to trigger packets, {} packets split",
+            self.packets, self.markers, self.legacy_ports, self.triggers, self.split
+        )?;
+        if self.snaplen != MAX_PACKET_LEN {
+            write!(f, ", snaplen {} -> {MAX_PACKET_LEN}", self.snaplen)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy the capture in `reader` to `writer` in the current encoding, with the original
+/// timestamps.
+pub fn fixup<R: Read, W: Write>(
+    reader: R,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<FixupReport> {
+    let (options, mut pcap_reader) =
+        PcapReader::new(reader).context("Failed to read the pcap header.")?;
+    let mut report = FixupReport {
+        snaplen: options.snaplen,
+        ..Default::default()
+    };
+    while let Some(pkt) = pcap_reader.next().context("Pcap read error")? {
+        if source_port(pkt.data) == Some(1442) {
+            report.legacy_ports += 1;
+        }
+        let time = pkt.time.into();
+        let pkt = match parse_record(pkt.data, time)? {
+            RecordRef::Packet(pkt) => pkt,
+            RecordRef::Marker(marker) => {
+                writer.write_marker(&marker)?;
+                report.markers += 1;
+                continue;
+            }
+            RecordRef::Trigger(trigger) => {
+                writer.write_trigger(&trigger)?;
+                continue;
+            }
+        };
+        let mut parts = pkt.data.split(|&b| b == TRIG_BYTE).peekable();
+        while let Some(part) = parts.next() {
+            if !part.is_empty() {
+                // 32 is the UDP header length, like in write_bus_packet_time
+                if part.len() > MAX_PACKET_LEN - 32 {
+                    report.split += 1;
+                }
+                writer.write_bus_packet_time(pkt.bus, part, pkt.ch, pkt.time.into())?;
+                report.packets += 1;
+            }
+            if parts.peek().is_some() {
+                writer.write_trigger(&Trigger::new(pkt.bus, Trigger::DEVICE, pkt.time))?;
+                report.triggers += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn source_port(data: &[u8]) -> Option<u16> {
+    match SlicedPacket::from_ip(data).ok()?.transport {
+        Some(TransportSlice::Udp(udp)) => Some(udp.source_port()),
+        _ => None,
+    }
+}
@@ -0,0 +1,99 @@
+//! Framed capture records, sent by the capture device on its vendor USB bulk interface, or by
+//! the Pico W firmware over TCP or UDP.
+//!
+//! Every record has a 6 byte header: the payload length, the flags and the device time in
+//! microseconds when the first byte was received, as a little endian u32, followed by the
+//! payload. The records are never split between USB packets or UDP datagrams.
+
+use anyhow::Result;
+use bytes::{Buf, BytesMut};
+use serial_pcap_core::framed::{split_record, RecordHeader, HEADER_LEN};
+
+use crate::UartTxChannel;
+
+/// The USB id of the capture firmware
+pub const VID_PID: (u16, u16) = serial_pcap_core::USB_VID_PID;
+
+pub use serial_pcap_core::framed::{
+    FLAG_BREAK, FLAG_BUS2, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP, FLAG_TRIGGER, TRIGGER_SOURCE_BUTTON,
+};
+
+/// One record from the capture device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramedRecord {
+    pub flags: u8,
+    /// Device time of the first byte in microseconds
+    pub time: u32,
+    pub data: BytesMut,
+}
+
+impl FramedRecord {
+    pub fn bus(&self) -> u8 {
+        u8::from(self.flags & FLAG_BUS2 != 0)
+    }
+
+    pub fn ch(&self) -> UartTxChannel {
+        match self.flags & FLAG_CTRL != 0 {
+            true => UartTxChannel::Ctrl,
+            false => UartTxChannel::Node,
+        }
+    }
+
+    pub fn dropped(&self) -> bool {
+        self.flags & FLAG_DROP != 0
+    }
+
+    pub fn chunk_start(&self) -> bool {
+        self.flags & FLAG_CHUNK != 0
+    }
+
+    pub fn is_trigger(&self) -> bool {
+        self.flags & FLAG_TRIGGER != 0
+    }
+
+    pub fn is_break(&self) -> bool {
+        self.flags & FLAG_BREAK != 0
+    }
+
+    /// Append the record to a USB packet, as sent by the capture device
+    pub fn encode(&self, packet: &mut Vec<u8>) {
+        let header = RecordHeader {
+            len: self.data.len() as u8,
+            flags: self.flags,
+            time: self.time,
+        };
+        packet.extend_from_slice(&header.encode());
+        packet.extend_from_slice(&self.data);
+    }
+}
+
+/// Split a USB packet from the capture device into records.
+pub fn parse_packet(mut packet: &[u8]) -> Result<Vec<FramedRecord>> {
+    let mut records = vec![];
+    while !packet.is_empty() {
+        let (header, payload, rest) = split_record(packet)?;
+        records.push(FramedRecord {
+            flags: header.flags,
+            time: header.time,
+            data: BytesMut::from(payload),
+        });
+        packet = rest;
+    }
+    Ok(records)
+}
+
+/// Split the whole records off the front of a TCP stream from the capture device, the start
+/// of a record which isn't complete yet is left in `buf`.
+pub fn split_stream(buf: &mut BytesMut) -> Vec<FramedRecord> {
+    let mut records = vec![];
+    while let Ok((header, _, _)) = split_record(buf) {
+        let mut data = buf.split_to(HEADER_LEN + header.len as usize);
+        data.advance(HEADER_LEN);
+        records.push(FramedRecord {
+            flags: header.flags,
+            time: header.time,
+            data,
+        });
+    }
+    records
+}
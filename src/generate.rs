@@ -0,0 +1,87 @@
+//! Generation of deterministic X3.28 test captures.
+//!
+//! A [`Scenario`] where the bus controller alternately reads parameter 23 and writes parameter
+//! 223 on each of the nodes in turn. The errors are injected on the whole bus, counting the
+//! replies of all the nodes.
+
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+
+use crate::sim::{write_exchange, Scenario, SimNode};
+use crate::SerialPacketWriter;
+
+/// The value of parameter 23 of every node
+pub const READ_VALUE: i32 = 33;
+
+/// What to generate
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Number of commands sent by the bus controller
+    pub transactions: usize,
+    /// The commands go to these addresses in turn, all of them have a node
+    pub nodes: Vec<u8>,
+    /// Corrupt the checksum of every Nth reply, 0 disables
+    pub corrupt_every: usize,
+    /// Leave out every Nth reply, 0 disables
+    pub no_reply_every: usize,
+    /// The time of the first command
+    pub start: DateTime<Utc>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            transactions: 100,
+            nodes: vec![21, 31],
+            corrupt_every: 0,
+            no_reply_every: 0,
+            start: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        }
+    }
+}
+
+/// Write the generated traffic to `writer`, returns the time after the last transaction
+pub fn generate<W: Write>(
+    opts: &GenerateOptions,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<DateTime<Utc>> {
+    if opts.nodes.is_empty() {
+        bail!("No node addresses to generate traffic for.");
+    }
+    if let Some(a) = opts.nodes.iter().find(|&&a| a > 99) {
+        bail!("Invalid node address {a}, the addresses are 0 to 99.");
+    }
+    let mut scenario = Scenario::new();
+    for &address in &opts.nodes {
+        let node = SimNode::new(address).with_values([(23, READ_VALUE), (223, 0)]);
+        scenario = scenario.with_node(node);
+    }
+    for i in 0..opts.transactions {
+        let address = opts.nodes[i % opts.nodes.len()];
+        scenario = match i % 2 {
+            0 => scenario.with_read(address, 23),
+            _ => scenario.with_write(address, 223, i as i32 % 1000),
+        };
+    }
+
+    let mut replies = 0usize;
+    let mut time = opts.start;
+    while let Some(mut exchange) = scenario.next_exchange() {
+        if exchange.reply.is_some() {
+            replies += 1;
+        }
+        let nth = |every: usize| every != 0 && replies.is_multiple_of(every);
+        if nth(opts.no_reply_every) {
+            exchange.reply = None;
+        }
+        if let Some(bcc) = exchange.reply.as_mut().and_then(|r| r.last_mut()) {
+            if nth(opts.corrupt_every) {
+                *bcc ^= 0x01;
+            }
+        }
+        time = write_exchange(writer, &exchange, time)?;
+    }
+    Ok(time)
+}
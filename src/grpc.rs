@@ -0,0 +1,186 @@
+//! gRPC service streaming the decoded bus traffic, defined in `proto/x328_events.proto`.
+//!
+//! Every subscriber gets the events decoded after it subscribed, optionally with the raw UART
+//! packets, until the capture stops. Subscribers which fall behind skip the events they missed.
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::mpsc::Receiver;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+use crate::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use crate::{CaptureRecord, SerialPacket, UartTxChannel};
+
+use proto::bus_events_server::{BusEvents, BusEventsServer};
+use proto::{event, transaction, Event, SubscribeRequest};
+
+/// The code generated from `proto/x328_events.proto`
+pub mod proto {
+    tonic::include_proto!("serial_pcap.v1");
+}
+
+/// Events kept for the subscribers which are behind
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+struct Service {
+    /// Weak, so the event streams end when the capture stops
+    events: broadcast::WeakSender<Event>,
+}
+
+#[tonic::async_trait]
+impl BusEvents for Service {
+    type SubscribeStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let SubscribeRequest { raw_frames, buses } = request.into_inner();
+        let events = self
+            .events
+            .upgrade()
+            .ok_or_else(|| Status::unavailable("The capture has stopped."))?
+            .subscribe();
+        let stream = BroadcastStream::new(events).filter_map(move |event| match event {
+            Ok(event) => {
+                let raw = matches!(event.event, Some(event::Event::Raw(_)));
+                let wanted =
+                    (raw_frames || !raw) && (buses.is_empty() || buses.contains(&event.bus));
+                wanted.then_some(Ok(event))
+            }
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                warn!("A gRPC subscriber missed {n} events.");
+                None
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Streams the events decoded from the packets in `records` to the gRPC clients connecting to
+/// `listener`, until the capture stops.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    records: Receiver<CaptureRecord>,
+) -> Result<()> {
+    let (events, _) = broadcast::channel(EVENT_QUEUE_SIZE);
+    let service = Service {
+        events: events.downgrade(),
+    };
+    let (stopped, on_stop) = oneshot::channel();
+    std::thread::Builder::new()
+        .name("grpc-decoder".into())
+        .spawn(move || {
+            decode(records, &events);
+            // dropping the sender ends the event streams
+            drop(events);
+            let _ = stopped.send(());
+        })?;
+    tonic::transport::Server::builder()
+        .add_service(BusEventsServer::new(service))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+            let _ = on_stop.await;
+        })
+        .await?;
+    Ok(())
+}
+
+fn decode(records: Receiver<CaptureRecord>, events: &broadcast::Sender<Event>) {
+    let mut decoders: BTreeMap<u8, X328Decoder> = BTreeMap::new();
+    for record in records {
+        let bus = match &record {
+            CaptureRecord::Packet(pkt) => {
+                // an error only means that there are no subscribers
+                let _ = events.send(raw_event(pkt));
+                pkt.bus
+            }
+            CaptureRecord::Trigger(trigger) => trigger.bus,
+            CaptureRecord::Marker(_) => continue,
+        };
+        let decoder = decoders.entry(bus).or_default();
+        decoder.feed_record(&record, |event| {
+            let _ = events.send(bus_event(bus, &event));
+        });
+    }
+}
+
+/// The message for a raw UART packet
+pub fn raw_event(pkt: &SerialPacket) -> Event {
+    Event {
+        time: Some(timestamp(pkt.time)),
+        bus: pkt.bus.into(),
+        event: Some(event::Event::Raw(proto::RawFrame {
+            bus: pkt.bus.into(),
+            channel: channel(pkt.ch).into(),
+            data: pkt.data.to_vec(),
+        })),
+    }
+}
+
+/// The message for a decoder event
+pub fn bus_event(bus: u8, event: &BusEvent) -> Event {
+    let message = match event {
+        BusEvent::Transaction(t) => event::Event::Transaction(proto::Transaction {
+            command: Some(command(&t.cmd)),
+            command_time: Some(timestamp(t.cmd_time)),
+            response_time: Some(timestamp(t.resp_time)),
+            result: Some(match &t.result {
+                Ok(value) => transaction::Result::Value(**value),
+                Err(e) => transaction::Result::Error(format!("{e:?}")),
+            }),
+        }),
+        BusEvent::Timeout { cmd, .. } => {
+            event::Event::Timeout(cmd.as_ref().map(command).unwrap_or_default())
+        }
+        BusEvent::UnexpectedTransmission { .. } => event::Event::UnexpectedTransmission(true),
+        BusEvent::Mismatch { .. } => event::Event::Mismatch(true),
+        BusEvent::Trigger { .. } => event::Event::Trigger(true),
+        BusEvent::Break { ch, .. } => event::Event::LineBreak(channel(*ch).into()),
+        BusEvent::Collision { kind, .. } => event::Event::Collision(
+            match kind {
+                Collision::Echo { .. } => "echo",
+                Collision::SecondMaster { .. } => "second master",
+                Collision::Overlap { .. } => "overlap",
+            }
+            .into(),
+        ),
+    };
+    Event {
+        time: Some(timestamp(event.time())),
+        bus: bus.into(),
+        event: Some(message),
+    }
+}
+
+fn command(cmd: &BusCommand) -> proto::Command {
+    proto::Command {
+        address: (*cmd.addr()).into(),
+        parameter: *cmd.param() as u32,
+        value: match cmd {
+            BusCommand::Write { value, .. } => Some(**value),
+            BusCommand::Read { .. } => None,
+        },
+    }
+}
+
+fn channel(ch: UartTxChannel) -> proto::Channel {
+    match ch {
+        UartTxChannel::Ctrl => proto::Channel::Ctrl,
+        UartTxChannel::Node => proto::Channel::Node,
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
@@ -0,0 +1,253 @@
+//! A harness for regression tests of the decoding, with recorded bus traffic as fixtures.
+//!
+//! A capture is replayed through the decoder into a transcript, one line per bus event with
+//! the time since the start of the capture, and compared with the expected transcript stored
+//! next to it. The transactions can also be re-enacted with the x328_proto bus controller and
+//! node state machines, to check that the simulated traffic decodes to the same outcomes.
+//!
+//! Everything is derived from the packet timestamps, so a replay gives the same result on
+//! every run.
+
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::master::SendData;
+use x328_proto::node::{Node, NodeState};
+use x328_proto::{addr, param, value, Master};
+
+use crate::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use crate::diff::{exchanges, Exchange, Outcome};
+use crate::names::NameMap;
+use crate::{CaptureRecord, SerialPacket, SerialPacketReader, UartTxChannel};
+
+/// Set this environment variable to write the transcripts instead of comparing them, after
+/// a change which is supposed to change the decoding
+pub const UPDATE_ENV: &str = "UPDATE_FIXTURES";
+
+/// The bus events decoded from a capture
+pub struct Replay {
+    events: Vec<BusEvent>,
+    /// The time of the first packet
+    start: DateTime<Utc>,
+}
+
+impl Replay {
+    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let file = std::fs::File::open(filename)
+            .with_context(|| format!("Failed to open {}.", filename.display()))?;
+        Self::new(file)
+    }
+
+    pub fn new(reader: impl Read) -> Result<Self> {
+        let mut reader = SerialPacketReader::new(reader)?;
+        let mut decoder = X328Decoder::new();
+        let mut events = vec![];
+        let mut start = None;
+        while let Some(record) = reader.next_record()? {
+            if let CaptureRecord::Packet(pkt) = &record {
+                start.get_or_insert(pkt.time);
+            }
+            decoder.feed_record(&record, |e| events.push(e));
+        }
+        Ok(Self {
+            events,
+            start: start.unwrap_or_default(),
+        })
+    }
+
+    pub fn events(&self) -> &[BusEvent] {
+        &self.events
+    }
+
+    /// The commands and their outcomes
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.events
+            .iter()
+            .filter_map(Exchange::from_event)
+            .collect()
+    }
+
+    /// One line per event, with the milliseconds since the first packet
+    pub fn transcript(&self) -> String {
+        let names = NameMap::default();
+        let mut out = String::new();
+        for event in &self.events {
+            let ms = (event.time() - self.start)
+                .num_microseconds()
+                .unwrap_or(i64::MAX);
+            let text = match (event, Exchange::from_event(event)) {
+                (BusEvent::Transaction(t), Some(ex)) => {
+                    let latency = t.latency().num_microseconds().unwrap_or(i64::MAX);
+                    format!(
+                        "{} ({:.3} ms)",
+                        ex.describe(&names),
+                        latency as f64 / 1000.0
+                    )
+                }
+                (_, Some(ex)) => ex.describe(&names),
+                (BusEvent::Timeout { .. }, None) => "timeout".into(),
+                (BusEvent::UnexpectedTransmission { .. }, _) => "unexpected transmission".into(),
+                (BusEvent::Mismatch { .. }, _) => "response doesn't match the command".into(),
+                (BusEvent::Trigger { .. }, _) => "trigger".into(),
+                (BusEvent::Break { ch, .. }, _) => format!("break on the {ch:?} channel"),
+                (BusEvent::Collision { kind, .. }, _) => match kind {
+                    Collision::Echo { ch } => format!("echo on the {ch:?} channel"),
+                    Collision::SecondMaster { .. } => "second bus master".into(),
+                    Collision::Overlap { ch } => {
+                        format!("overlapping transmission on the {ch:?} channel")
+                    }
+                },
+                (BusEvent::Transaction(_), None) => unreachable!(),
+            };
+            let _ = writeln!(out, "{:>10.3} {text}", ms as f64 / 1000.0);
+        }
+        out
+    }
+
+    /// Re-enact the exchanges with a simulated bus controller and node, which answers like
+    /// the node in the capture, and decode the simulated traffic.
+    pub fn simulate(&self) -> Result<Vec<Exchange>> {
+        let mut packets = vec![];
+        let mut time = self.start;
+        let mut push = |ch, data: Vec<u8>, time| {
+            packets.push(Ok(SerialPacket {
+                bus: 0,
+                ch,
+                data: data[..].into(),
+                time,
+            }))
+        };
+        for ex in self.exchanges() {
+            let (cmd, reply) = enact(&ex)?;
+            push(UartTxChannel::Ctrl, cmd, time);
+            if !reply.is_empty() {
+                push(
+                    UartTxChannel::Node,
+                    reply,
+                    time + Duration::milliseconds(10),
+                );
+            }
+            time += Duration::milliseconds(100);
+        }
+        // a last command, so a timeout at the end is detected
+        let mut master = Master::new();
+        let last = master.read_parameter(addr(0), param(0));
+        push(UartTxChannel::Ctrl, last.get_data().to_vec(), time);
+        exchanges(packets.into_iter())
+    }
+
+    /// Check that the simulated exchanges have the same commands and outcomes as the capture
+    pub fn check_simulation(&self) -> Result<()> {
+        let names = NameMap::default();
+        let (captured, simulated) = (self.exchanges(), self.simulate()?);
+        for (n, (c, s)) in captured.iter().zip(&simulated).enumerate() {
+            if c.cmd != s.cmd || c.outcome != s.outcome {
+                bail!(
+                    "Exchange {}: captured `{}`, simulated `{}`.",
+                    n + 1,
+                    c.describe(&names),
+                    s.describe(&names)
+                );
+            }
+        }
+        if captured.len() != simulated.len() {
+            bail!(
+                "{} exchanges captured, {} simulated.",
+                captured.len(),
+                simulated.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The command and the reply of the simulated node
+fn enact(ex: &Exchange) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut master = Master::new();
+    let cmd = match ex.cmd {
+        BusCommand::Read { addr, param } => master.read_parameter(addr, param).get_data().to_vec(),
+        BusCommand::Write { addr, param, value } => master
+            .write_parameter(addr, param, value)
+            .get_data()
+            .to_vec(),
+    };
+    let mut node = Node::new(ex.cmd.addr());
+    let token = node.reset();
+    let NodeState::ReceiveData(recv) = node.state(token) else {
+        unreachable!("a reset node receives")
+    };
+    let mut token = recv.receive_data(&cmd);
+    let mut reply = vec![];
+    loop {
+        token = match node.state(token) {
+            NodeState::ReceiveData(_) => break,
+            NodeState::SendData(send) => {
+                reply.extend_from_slice(send.send_data());
+                send.data_sent()
+            }
+            NodeState::ReadParameter(read) => match ex.outcome {
+                Outcome::Ok(v) => read.send_reply_ok(value(v)),
+                // a reply with a bad checksum
+                Outcome::ProtocolError => read.send_reply_ok(value(0)),
+                Outcome::InvalidParameter => read.send_invalid_parameter(),
+                Outcome::CommandFailed => read.send_read_failed(),
+                Outcome::Timeout => read.no_reply(),
+            },
+            NodeState::WriteParameter(write) => match ex.outcome {
+                Outcome::Ok(_) => write.write_ok(),
+                Outcome::CommandFailed => write.write_error(),
+                Outcome::Timeout => write.no_reply(),
+                Outcome::InvalidParameter | Outcome::ProtocolError => {
+                    bail!("Can't simulate a write with the outcome {}.", ex.outcome)
+                }
+            },
+        };
+    }
+    if ex.outcome == Outcome::ProtocolError {
+        if let Some(bcc) = reply.last_mut() {
+            *bcc ^= 0x01;
+        }
+    }
+    Ok((cmd, reply))
+}
+
+/// Compare the transcript of the capture with the expected one, in a file with the same name
+/// and the extension `.events`. With [`UPDATE_ENV`] set, the file is written instead.
+pub fn check_transcript(pcap: impl AsRef<Path>) -> Result<()> {
+    let pcap = pcap.as_ref();
+    let expected_file = pcap.with_extension("events");
+    let transcript = Replay::from_file(pcap)?.transcript();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        return std::fs::write(&expected_file, transcript)
+            .with_context(|| format!("Failed to write {}.", expected_file.display()));
+    }
+    let expected = std::fs::read_to_string(&expected_file).with_context(|| {
+        format!(
+            "Failed to read {}, set {UPDATE_ENV} to create it.",
+            expected_file.display()
+        )
+    })?;
+    for (n, (e, t)) in expected.lines().zip(transcript.lines()).enumerate() {
+        if e != t {
+            bail!(
+                "{}:{}: expected `{}`, decoded `{}`.",
+                expected_file.display(),
+                n + 1,
+                e.trim(),
+                t.trim()
+            );
+        }
+    }
+    let (e, t) = (expected.lines().count(), transcript.lines().count());
+    if e != t {
+        bail!(
+            "{}: expected {e} events, decoded {t}.",
+            expected_file.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+//! The timestamped hexdump logs of the serial sniffing tools, which a lot of old debugging
+//! data of the buses is kept in.
+//!
+//! A jpnevulator log, from `jpnevulator --read --timing-print`, has a line with the time and
+//! the device before the bytes read from each device:
+//!
+//! ```text
+//! 2024-05-02 12:15:00.012345: /dev/ttyUSB0
+//! 04 32 32 31 31 30 30 32 33 05
+//! ```
+//!
+//! An interceptty log has a line per byte, `>` for the data written to the device and `<` for
+//! the data read from it, with the time in front, e.g. added by piping it through `ts`:
+//!
+//! ```text
+//! 2024-05-02 12:15:00.012345 > 0x04
+//! ```
+//!
+//! The times are in the local time of the machine which wrote the log, without a time zone.
+//!
+//! A capture is written as a hexdump with a line per packet, for pasting into bug reports:
+//!
+//! ```text
+//! 2024-05-02T12:15:00.000 CTRL | 04 32 32 31 31 30 30 32 33 05
+//! 2024-05-02T12:15:00.012 NODE | 02 32 33 2B 30 30 30 33 33 03 44
+//! ```
+
+use std::io::{BufRead, Read, Write};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+
+use crate::export::channel_name;
+use crate::import::{parse_byte, AnalyzerByte};
+use crate::{SerialPacket, SerialPacketReader, UartTxChannel};
+
+/// Timestamped hexdump log formats
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum HexdumpFormat {
+    /// jpnevulator --read --timing-print, a block of hex bytes after each time and device
+    Jpnevulator,
+    /// interceptty, a line with the direction and the byte for each byte
+    Interceptty,
+}
+
+/// The devices of a jpnevulator log, or the directions of an interceptty log, which are the
+/// ctrl and the node channel. The ones not given are the first two seen in the log, in that
+/// order, and for interceptty `>` is ctrl and `<` is node.
+#[derive(Debug, Clone, Default)]
+pub struct HexdumpChannels {
+    pub ctrl: Option<String>,
+    pub node: Option<String>,
+}
+
+impl HexdumpChannels {
+    fn channel(&mut self, name: &str) -> Result<UartTxChannel> {
+        if self.ctrl.is_none() && self.node.as_deref() != Some(name) {
+            self.ctrl = Some(name.into());
+        } else if self.node.is_none() && self.ctrl.as_deref() != Some(name) {
+            self.node = Some(name.into());
+        }
+        if self.ctrl.as_deref() == Some(name) {
+            Ok(UartTxChannel::Ctrl)
+        } else if self.node.as_deref() == Some(name) {
+            Ok(UartTxChannel::Node)
+        } else {
+            bail!("Unknown device '{name}', the log has more than two.")
+        }
+    }
+}
+
+/// The characters of a hexdump log
+#[derive(Debug, Clone, PartialEq)]
+pub struct HexdumpLog {
+    /// The time of the first character
+    pub start: SystemTime,
+    /// The characters, with the time since `start`, sorted by time
+    pub bytes: Vec<AnalyzerByte>,
+}
+
+/// Read all characters from a hexdump log. The times are in `utc_offset`, or in the local
+/// time zone without one.
+pub fn read_hexdump(
+    input: impl BufRead,
+    format: HexdumpFormat,
+    mut channels: HexdumpChannels,
+    utc_offset: Option<FixedOffset>,
+) -> Result<HexdumpLog> {
+    if format == HexdumpFormat::Interceptty && channels.ctrl.is_none() && channels.node.is_none() {
+        channels.ctrl = Some(">".into());
+        channels.node = Some("<".into());
+    }
+    let to_utc = |naive: NaiveDateTime| -> Result<DateTime<Utc>> {
+        let time = match utc_offset {
+            Some(offset) => offset.from_local_datetime(&naive).single(),
+            None => Local.from_local_datetime(&naive).earliest().map(Into::into),
+        };
+        time.map(|t| t.with_timezone(&Utc))
+            .with_context(|| format!("The time {naive} doesn't exist in the local time zone."))
+    };
+    let mut times: Vec<(DateTime<Utc>, UartTxChannel, u8)> = vec![];
+    let mut current: Option<(DateTime<Utc>, UartTxChannel)> = None;
+    for (line_no, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut parse = || -> Result<()> {
+            match format {
+                HexdumpFormat::Jpnevulator => {
+                    if let Some((time, device)) = parse_time(&line) {
+                        current = Some((to_utc(time?)?, channels.channel(device.trim())?));
+                        return Ok(());
+                    }
+                    let (time, ch) = current.context("Data before the first time line.")?;
+                    // with --ascii, the characters follow after a wider gap
+                    let hex = line.trim_start().split("  ").next().unwrap_or_default();
+                    for byte in hex.split_whitespace() {
+                        times.push((time, ch, parse_byte(byte)?));
+                    }
+                }
+                HexdumpFormat::Interceptty => {
+                    let dir = line
+                        .find(['<', '>'])
+                        .context("Missing direction, < or >.")?;
+                    let (prefix, rest) = line.split_at(dir);
+                    let (name, rest) = rest.split_at(1);
+                    let ch = channels.channel(name)?;
+                    let time = match prefix.trim() {
+                        "" => current.context("Missing time.")?.0,
+                        prefix => match parse_time(prefix) {
+                            Some((time, _)) => to_utc(time?)?,
+                            None => bail!("Invalid time '{prefix}'."),
+                        },
+                    };
+                    current = Some((time, ch));
+                    // "0x41 (A)", the character in parentheses is optional
+                    let byte = rest.split_whitespace().next().context("Missing byte.")?;
+                    times.push((time, ch, parse_byte(byte)?));
+                }
+            }
+            Ok(())
+        };
+        parse().with_context(|| format!("Error on line {}.", line_no + 1))?;
+    }
+    times.sort_by_key(|(time, _, _)| *time);
+    let start = times.first().map_or(DateTime::<Utc>::UNIX_EPOCH, |t| t.0);
+    let bytes = times
+        .into_iter()
+        .map(|(time, ch, byte)| AnalyzerByte {
+            time: (time - start).to_std().unwrap_or_default().as_secs_f64(),
+            ch,
+            byte,
+        })
+        .collect();
+    Ok(HexdumpLog {
+        start: start.into(),
+        bytes,
+    })
+}
+
+/// Split a line starting with a "2024-05-02 12:15:00.012345" time into the time and the rest
+/// after it, without the colon after the time. `None` if it doesn't start with a date.
+fn parse_time(line: &str) -> Option<(Result<NaiveDateTime>, &str)> {
+    let line = line.trim_start();
+    let date = line.get(..10)?;
+    if !date.bytes().all(|b| b.is_ascii_digit() || b == b'-') || date.matches('-').count() != 2 {
+        return None;
+    }
+    let clock = line[10..].strip_prefix(' ')?;
+    let len = clock
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '.'))
+        .unwrap_or(clock.len());
+    let (time, rest) = line.split_at(11 + len);
+    let time = time.trim_end_matches(':');
+    let parsed = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.f")
+        .with_context(|| format!("Invalid time '{time}'."));
+    Some((parsed, rest))
+}
+
+/// How a capture is written as a hexdump
+#[derive(Debug, Copy, Clone)]
+pub struct HexdumpStyle {
+    /// Bytes per line, the rest of a longer packet goes on the following lines
+    pub width: usize,
+    /// Add the printable characters after the bytes
+    pub ascii: bool,
+    /// The time zone of the times, the local time zone without one
+    pub utc_offset: Option<FixedOffset>,
+}
+
+impl Default for HexdumpStyle {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            ascii: false,
+            utc_offset: None,
+        }
+    }
+}
+
+/// Write all packets from `reader` as a hexdump, with the time and the channel in front of
+/// each packet, `CTRL1` and `NODE1` for the second bus.
+pub fn write_hexdump<R: Read>(
+    reader: &mut SerialPacketReader<R>,
+    mut out: impl Write,
+    style: HexdumpStyle,
+) -> Result<()> {
+    for pkt in reader {
+        write_hexdump_packet(&mut out, &pkt?, style)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_hexdump_packet(
+    out: &mut impl Write,
+    pkt: &SerialPacket,
+    style: HexdumpStyle,
+) -> Result<()> {
+    const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+    let time = match style.utc_offset {
+        Some(offset) => pkt.time.with_timezone(&offset).format(TIME_FORMAT),
+        None => pkt.time.with_timezone(&Local).format(TIME_FORMAT),
+    };
+    let mut label = channel_name(pkt.ch).to_uppercase();
+    if pkt.bus != 0 {
+        label += &pkt.bus.to_string();
+    }
+    let prefix = format!("{time} {label:<4} | ");
+    for (i, line) in pkt.data.chunks(style.width.max(1)).enumerate() {
+        match i {
+            0 => write!(out, "{prefix}")?,
+            _ => write!(out, "{:1$}| ", "", prefix.len() - 2)?,
+        }
+        let hex: Vec<_> = line.iter().map(|b| format!("{b:02X}")).collect();
+        let hex = hex.join(" ");
+        if style.ascii {
+            let text: String = line
+                .iter()
+                .map(|&b| match b.is_ascii_graphic() || b == b' ' {
+                    true => b as char,
+                    false => '.',
+                })
+                .collect();
+            writeln!(out, "{hex:0$}  {text}", style.width.max(1) * 3 - 1)?;
+        } else {
+            writeln!(out, "{hex}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use std::io::BufRead;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+
+use crate::export::AnalyzerFormat;
+use crate::{SerialPacketWriter, UartTxChannel};
+
+/// A single decoded UART character from a logic analyzer export
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnalyzerByte {
+    /// Seconds since the start of the logic analyzer trace
+    pub time: f64,
+    pub ch: UartTxChannel,
+    pub byte: u8,
+}
+
+/// Maps the analyzer channel names to the pcap channels
+#[derive(Debug, Clone)]
+pub struct ChannelNames {
+    pub ctrl: String,
+    pub node: String,
+}
+
+impl Default for ChannelNames {
+    fn default() -> Self {
+        Self {
+            ctrl: "ctrl".into(),
+            node: "node".into(),
+        }
+    }
+}
+
+impl ChannelNames {
+    fn channel(&self, name: &str) -> Result<UartTxChannel> {
+        let name = name.trim().trim_start_matches("uart-");
+        if name == self.ctrl {
+            Ok(UartTxChannel::Ctrl)
+        } else if name == self.node {
+            Ok(UartTxChannel::Node)
+        } else {
+            bail!("Unknown channel name '{name}'.")
+        }
+    }
+}
+
+pub(crate) fn parse_byte(s: &str) -> Result<u8> {
+    let s = s.trim();
+    let byte = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u8::from_str_radix(hex, 16)
+    } else if s.len() == 2 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        u8::from_str_radix(s, 16) // sigrok hex annotation
+    } else {
+        s.parse()
+    };
+    byte.with_context(|| format!("Invalid data byte '{s}'."))
+}
+
+/// Read all UART characters from a logic analyzer export, sorted by time.
+///
+/// CSV files are matched on the column names, which covers the Saleae Logic 1 and 2 exports
+/// as well as plain `time,channel,byte` files.
+pub fn read_analyzer_export(
+    input: impl BufRead,
+    format: AnalyzerFormat,
+    names: &ChannelNames,
+    samplerate: u64,
+) -> Result<Vec<AnalyzerByte>> {
+    let mut bytes = match format {
+        AnalyzerFormat::Saleae => read_csv(input, names)?,
+        AnalyzerFormat::Sigrok => read_sigrok(input, names, samplerate)?,
+    };
+    bytes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(bytes)
+}
+
+fn read_csv(input: impl BufRead, names: &ChannelNames) -> Result<Vec<AnalyzerByte>> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV header.")?
+        .clone();
+    let column = |candidates: &[&str]| {
+        headers
+            .iter()
+            .position(|h| candidates.iter().any(|c| h.trim().eq_ignore_ascii_case(c)))
+            .with_context(|| format!("No {} column in CSV header.", candidates[0]))
+    };
+    let time_col = column(&["start_time", "time [s]", "time", "timestamp"])?;
+    let ch_col = column(&["name", "analyzer name", "channel"])?;
+    let data_col = column(&["data", "decoded protocol result", "value", "byte"])?;
+    let type_col = column(&["type"]).ok();
+
+    let mut bytes = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record.context("Failed to read CSV record.")?;
+        let field = |col: usize| record.get(col).unwrap_or_default();
+        // Saleae marks framing errors etc. with other types than "data"
+        if type_col.is_some_and(|col| field(col) != "data") {
+            continue;
+        }
+        let parse = || -> Result<AnalyzerByte> {
+            Ok(AnalyzerByte {
+                time: field(time_col)
+                    .trim()
+                    .parse()
+                    .context("Invalid timestamp.")?,
+                ch: names.channel(field(ch_col))?,
+                byte: parse_byte(field(data_col))?,
+            })
+        };
+        bytes.push(parse().with_context(|| format!("Error in CSV record {}.", line + 1))?);
+    }
+    Ok(bytes)
+}
+
+fn read_sigrok(
+    input: impl BufRead,
+    names: &ChannelNames,
+    samplerate: u64,
+) -> Result<Vec<AnalyzerByte>> {
+    let mut bytes = vec![];
+    for (line_no, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // "<start sample>-<end sample> <decoder>: <annotation>"
+        let parse = || -> Result<AnalyzerByte> {
+            let (samples, rest) = line.split_once(' ').context("Missing sample numbers.")?;
+            let (ss, _es) = samples.split_once('-').context("Invalid sample range.")?;
+            let (decoder, data) = rest.split_once(':').context("Missing annotation.")?;
+            let ss: u64 = ss.parse().context("Invalid sample number.")?;
+            Ok(AnalyzerByte {
+                time: ss as f64 / samplerate as f64,
+                ch: names.channel(decoder)?,
+                byte: parse_byte(data)?,
+            })
+        };
+        bytes.push(parse().with_context(|| format!("Error on line {}.", line_no + 1))?);
+    }
+    Ok(bytes)
+}
+
+/// Group the characters into packets and write them to the pcap.
+///
+/// A new packet is started when the channel changes, when the gap between two characters
+/// exceeds `max_gap`, or on an EOT character, the same way the capture tool does it.
+pub fn write_analyzer_bytes<W: std::io::Write>(
+    bytes: &[AnalyzerByte],
+    writer: &mut SerialPacketWriter<W>,
+    start_time: SystemTime,
+    max_gap: Duration,
+) -> Result<()> {
+    let max_gap = max_gap.as_secs_f64();
+    let mut packet: Vec<u8> = vec![];
+    let mut first = bytes.first().copied();
+    let mut prev_time = 0.0;
+    for b in bytes {
+        if let Some(p) = first.filter(|_| !packet.is_empty()) {
+            if p.ch != b.ch || b.time - prev_time > max_gap || b.byte == 0x04 {
+                write_packet(writer, &packet, p, start_time)?;
+                packet.clear();
+            }
+        }
+        if packet.is_empty() {
+            first = Some(*b);
+        }
+        packet.push(b.byte);
+        prev_time = b.time;
+    }
+    if let Some(p) = first.filter(|_| !packet.is_empty()) {
+        write_packet(writer, &packet, p, start_time)?;
+    }
+    Ok(())
+}
+
+fn write_packet<W: std::io::Write>(
+    writer: &mut SerialPacketWriter<W>,
+    data: &[u8],
+    first: AnalyzerByte,
+    start_time: SystemTime,
+) -> Result<()> {
+    let offset = Duration::try_from_secs_f64(first.time).context("Negative timestamp.")?;
+    writer.write_packet_time(data, first.ch, start_time + offset)
+}
+
+/// The most bytes in a packet of a raw byte stream without an index, so the packet times
+/// follow the byte rate
+pub const RAW_PACKET_LEN: usize = 64;
+
+/// How the times of the bytes of a raw byte stream are known
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawTiming {
+    /// The offset in the stream and the time of each packet, e.g. from the index written by
+    /// `serial-pcap extract`
+    Index(Vec<(usize, SystemTime)>),
+    /// The characters were sent back to back from `start`, `byte_duration` apart
+    Rate {
+        start: SystemTime,
+        byte_duration: Duration,
+    },
+}
+
+/// Read a CSV index of a raw byte stream, with the columns `offset` and `time` (RFC 3339). Any
+/// other columns, e.g. the `length` written by `serial-pcap extract`, are ignored.
+pub fn read_raw_index(input: impl BufRead) -> Result<RawTiming> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let headers = reader
+        .headers()
+        .context("Failed to read the index header.")?;
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .with_context(|| format!("The index has no '{name}' column."))
+    };
+    let (offset_col, time_col) = (column("offset")?, column("time")?);
+    let mut entries = vec![];
+    for (line_no, row) in reader.records().enumerate() {
+        let parse = || -> Result<(usize, SystemTime)> {
+            let row = row?;
+            let offset = row[offset_col]
+                .parse()
+                .with_context(|| format!("Invalid offset '{}'.", &row[offset_col]))?;
+            let time: chrono::DateTime<chrono::Utc> = row[time_col]
+                .parse()
+                .with_context(|| format!("Invalid time '{}'.", &row[time_col]))?;
+            Ok((offset, time.into()))
+        };
+        // the header is line 1
+        entries.push(parse().with_context(|| format!("Error on line {}.", line_no + 2))?);
+    }
+    Ok(RawTiming::Index(entries))
+}
+
+/// Write a raw byte stream to the pcap as packets from channel `ch` on `bus`.
+///
+/// With an index, each entry is a packet, up to the offset of the next one, and the offsets
+/// must start at 0 and increase. Without one, a packet is started at each EOT character, the
+/// same way the capture tool does it, and after [`RAW_PACKET_LEN`] bytes.
+pub fn write_raw_bytes<W: std::io::Write>(
+    data: &[u8],
+    timing: &RawTiming,
+    bus: u8,
+    ch: UartTxChannel,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<()> {
+    match timing {
+        RawTiming::Index(entries) => {
+            if entries.first().is_some_and(|(offset, _)| *offset != 0) {
+                bail!("The index doesn't start at offset 0.");
+            }
+            for (i, &(offset, time)) in entries.iter().enumerate() {
+                let end = entries.get(i + 1).map_or(data.len(), |(next, _)| *next);
+                let packet = data.get(offset..end).with_context(|| {
+                    format!(
+                        "Index entry {} is out of order or past the end of the data.",
+                        i + 1
+                    )
+                })?;
+                if !packet.is_empty() {
+                    writer.write_bus_packet_time(bus, packet, ch, time)?;
+                }
+            }
+        }
+        RawTiming::Rate {
+            start,
+            byte_duration,
+        } => {
+            let mut offset = 0;
+            while offset < data.len() {
+                let len = data[offset..]
+                    .iter()
+                    .take(RAW_PACKET_LEN)
+                    .skip(1)
+                    .position(|&b| b == 0x04)
+                    .map_or(RAW_PACKET_LEN.min(data.len() - offset), |eot| eot + 1);
+                let time = *start + *byte_duration * offset as u32;
+                writer.write_bus_packet_time(bus, &data[offset..offset + len], ch, time)?;
+                offset += len;
+            }
+        }
+    }
+    Ok(())
+}
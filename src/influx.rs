@@ -0,0 +1,128 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::decode::{BusEvent, Transaction, X328Decoder};
+use crate::names::NameMap;
+use crate::SerialPacket;
+
+/// Lines are POSTed to the HTTP endpoint in batches of about this size
+const HTTP_BATCH_SIZE: usize = 256 * 1024;
+
+/// Escape commas, equal signs and spaces in tag values
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Format a successful transaction as an Influx line protocol record,
+/// timestamped with the time of the node response.
+///
+/// The node and parameter names and the unit from `names` are added as tags when known.
+pub fn line(measurement: &str, t: &Transaction, names: &NameMap) -> Option<String> {
+    let value = *t.result.as_ref().ok()?;
+    let ns = t.resp_time.timestamp_nanos_opt()?;
+    let (addr, param) = (*t.cmd.addr(), *t.cmd.param());
+    let mut tags = format!("addr={addr},param={param}");
+    if let Some(node) = names.node(addr) {
+        tags += &format!(",node={}", escape_tag(node));
+    }
+    if let Some(info) = names.param(addr, param) {
+        tags += &format!(",name={}", escape_tag(&info.name));
+        if let Some(unit) = &info.unit {
+            tags += &format!(",unit={}", escape_tag(unit));
+        }
+    }
+    Some(format!("{measurement},{tags} value={}i {ns}\n", *value))
+}
+
+/// Writes the decoded X3.28 parameter values as Influx line protocol.
+pub struct InfluxWriter<W: Write> {
+    out: W,
+    measurement: String,
+    names: NameMap,
+    decoder: X328Decoder,
+}
+
+impl<W: Write> InfluxWriter<W> {
+    pub fn new(out: W, measurement: impl Into<String>, names: NameMap) -> Self {
+        Self {
+            out,
+            measurement: measurement.into(),
+            names,
+            decoder: X328Decoder::new(),
+        }
+    }
+
+    /// Decode the packet and write a record for each parameter value found.
+    pub fn feed(&mut self, pkt: &SerialPacket) -> Result<()> {
+        let mut lines = vec![];
+        self.decoder.feed(pkt, |e| {
+            if let BusEvent::Transaction(t) = e {
+                lines.extend(line(&self.measurement, &t, &self.names));
+            }
+        });
+        for line in lines {
+            // one write per line, so HttpSink only sees complete lines
+            self.out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// POSTs the written data to an InfluxDB write endpoint,
+/// e.g. `http://localhost:8086/api/v2/write?org=x&bucket=y&precision=ns`.
+pub struct HttpSink {
+    url: String,
+    token: Option<String>,
+    buf: Vec<u8>,
+}
+
+impl HttpSink {
+    pub fn new(url: impl Into<String>, token: Option<String>) -> Self {
+        Self {
+            url: url.into(),
+            token,
+            buf: Vec::with_capacity(HTTP_BATCH_SIZE),
+        }
+    }
+
+    fn post(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let mut req = ureq::post(&self.url).header("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &self.token {
+            req = req.header("Authorization", format!("Token {token}"));
+        }
+        req.send(self.buf.as_slice())
+            .with_context(|| format!("Failed to post data to {}.", self.url))?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Write for HttpSink {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= HTTP_BATCH_SIZE && self.buf.ends_with(b"\n") {
+            self.post().map_err(std::io::Error::other)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.post().map_err(std::io::Error::other)
+    }
+}
@@ -0,0 +1,159 @@
+//! Response times of the bus nodes, the time from a command to the response, as histograms and
+//! percentiles per node for chasing intermittently slow nodes.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::SecondsFormat;
+
+use crate::decode::Transaction;
+
+/// The upper bounds of the histogram buckets in ms, the last bucket has the rest
+pub const BUCKETS_MS: [f64; 9] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+/// The width of the longest bar of a histogram
+const BAR_WIDTH: u64 = 40;
+
+/// The response times of a node
+#[derive(Debug, Clone, Default)]
+pub struct NodeLatency {
+    /// In ms, sorted when the statistics are taken
+    samples: Vec<f64>,
+    sorted: bool,
+}
+
+impl NodeLatency {
+    pub fn add(&mut self, latency_ms: f64) {
+        self.samples.push(latency_ms);
+        self.sorted = false;
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn sorted(&mut self) -> &[f64] {
+        if !self.sorted {
+            self.samples.sort_by(f64::total_cmp);
+            self.sorted = true;
+        }
+        &self.samples
+    }
+
+    /// The nearest-rank percentile in ms, e.g. `percentile(99.0)`
+    pub fn percentile(&mut self, p: f64) -> Option<f64> {
+        let samples = self.sorted();
+        let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+        samples
+            .get(rank.clamp(1, samples.len().max(1)) - 1)
+            .copied()
+    }
+
+    pub fn min(&mut self) -> Option<f64> {
+        self.sorted().first().copied()
+    }
+
+    pub fn max(&mut self) -> Option<f64> {
+        self.sorted().last().copied()
+    }
+
+    /// The number of samples in each bucket of [`BUCKETS_MS`], and above the last bound
+    pub fn histogram(&self) -> [u64; BUCKETS_MS.len() + 1] {
+        let mut counts = [0; BUCKETS_MS.len() + 1];
+        for sample in &self.samples {
+            let bucket = BUCKETS_MS.iter().take_while(|b| sample >= b).count();
+            counts[bucket] += 1;
+        }
+        counts
+    }
+}
+
+/// The response times of the nodes, by address
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    nodes: BTreeMap<u8, NodeLatency>,
+}
+
+impl LatencyStats {
+    /// Add the response time of a transaction
+    pub fn add(&mut self, t: &Transaction) {
+        self.nodes
+            .entry(*t.cmd.addr())
+            .or_default()
+            .add(latency_ms(t));
+    }
+
+    pub fn node(&mut self, addr: u8) -> Option<&mut NodeLatency> {
+        self.nodes.get_mut(&addr)
+    }
+
+    /// Write a line with the percentiles of each node, followed by its histogram from the
+    /// fastest to the slowest response
+    pub fn write_report(&mut self, mut out: impl Write) -> Result<()> {
+        for (addr, node) in &mut self.nodes {
+            let ms = |v: Option<f64>| format!("{:.1}ms", v.unwrap_or_default());
+            writeln!(
+                out,
+                "latency addr={addr} count={} min={} p50={} p90={} p99={} max={}",
+                node.count(),
+                ms(node.min()),
+                ms(node.percentile(50.0)),
+                ms(node.percentile(90.0)),
+                ms(node.percentile(99.0)),
+                ms(node.max()),
+            )?;
+            let counts = node.histogram();
+            let most = counts.iter().copied().max().unwrap_or_default().max(1);
+            // the buckets from the fastest to the slowest response
+            let first = counts.iter().position(|c| *c > 0).unwrap_or_default();
+            let last = counts.iter().rposition(|c| *c > 0).unwrap_or_default();
+            for (n, count) in counts.iter().enumerate().take(last + 1).skip(first) {
+                let label = match n {
+                    0 => format!("< {} ms", BUCKETS_MS[0]),
+                    n if n == BUCKETS_MS.len() => format!(">= {} ms", BUCKETS_MS[n - 1]),
+                    n => format!("{}-{} ms", BUCKETS_MS[n - 1], BUCKETS_MS[n]),
+                };
+                // a bucket with samples gets at least one mark
+                let bar = (count * BAR_WIDTH).div_ceil(most) as usize;
+                writeln!(out, "  {label:>10} {count:>7} {}", "#".repeat(bar))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn latency_ms(t: &Transaction) -> f64 {
+    t.latency().num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0
+}
+
+/// Writes each response time as a CSV row, time,addr,param,latency_ms
+pub struct LatencySamples<W: Write> {
+    csv: csv::Writer<W>,
+}
+
+impl<W: Write> LatencySamples<W> {
+    pub fn new(out: W) -> Result<Self> {
+        let mut csv = csv::Writer::from_writer(out);
+        csv.write_record(["time", "addr", "param", "latency_ms"])?;
+        Ok(Self { csv })
+    }
+
+    /// Write the response time of a transaction, at the time of the command
+    pub fn add(&mut self, t: &Transaction) -> Result<()> {
+        self.csv.write_record([
+            t.cmd_time.to_rfc3339_opts(SecondsFormat::Micros, true),
+            (*t.cmd.addr()).to_string(),
+            (*t.cmd.param()).to_string(),
+            format!("{:.3}", latency_ms(t)),
+        ])?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.csv.flush()?;
+        self.csv
+            .into_inner()
+            .map_err(|e| anyhow::anyhow!("Failed to flush the latency samples: {e}"))
+    }
+}
@@ -0,0 +1,202 @@
+//! Reading of captures which were merged with other traffic, e.g. with `mergecap`.
+//!
+//! The records which aren't serial-pcap packets or markers are skipped and counted, and
+//! fragmented IPv4 packets are reassembled before they are decoded.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use etherparse::Ipv4HeaderSlice;
+use rpcap::CapturedPacket;
+
+use crate::{parse_record, SourceReader};
+
+/// Fragmented packets which are waiting for the rest of the fragments. When there are more,
+/// the oldest one is dropped.
+const MAX_PENDING: usize = 64;
+
+/// The records skipped by a lenient reader
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LenientStats {
+    /// Records which aren't serial-pcap packets, e.g. other protocols or ports, or truncated
+    pub foreign: u64,
+    /// Packets which were reassembled from fragments
+    pub reassembled: u64,
+    /// Fragmented packets which were dropped or never completed
+    pub incomplete: u64,
+}
+
+/// Source address, destination address, protocol and identification
+type FragmentKey = ([u8; 4], [u8; 4], u8, u16);
+
+#[derive(Default)]
+struct Fragments {
+    /// The IP header of the first fragment
+    header: Vec<u8>,
+    /// The payloads by their offset in bytes
+    parts: Vec<(usize, Vec<u8>)>,
+    /// The payload length, known from the last fragment
+    len: Option<usize>,
+    /// Arrival order, for dropping the oldest
+    seq: u64,
+}
+
+impl Fragments {
+    /// The whole IP packet, if all the fragments are there
+    fn assemble(&mut self) -> Option<Vec<u8>> {
+        let len = self.len?;
+        if self.header.is_empty() {
+            return None;
+        }
+        self.parts.sort_by_key(|(offset, _)| *offset);
+        let mut payload = Vec::with_capacity(len);
+        for (offset, data) in &self.parts {
+            // overlapping fragments only add their new bytes
+            if *offset > payload.len() {
+                return None;
+            }
+            let end = (offset + data.len()).min(len);
+            if end > payload.len() {
+                payload.extend_from_slice(&data[payload.len() - offset..end - offset]);
+            }
+        }
+        if payload.len() < len {
+            return None;
+        }
+        let mut packet = self.header.clone();
+        let total_len = u16::try_from(packet.len() + len).ok()?;
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+        // clear the fragment flags and offset, keeping don't fragment
+        packet[6] &= 0x40;
+        packet[7] = 0;
+        packet[10..12].fill(0);
+        let checksum = ipv4_checksum(&packet);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(&payload);
+        Some(packet)
+    }
+}
+
+pub(crate) fn ipv4_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|w| u32::from(u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+/// Filters the records of a capture down to the serial-pcap records
+#[derive(Default)]
+pub(crate) struct Lenient {
+    /// The record returned by the last read
+    buf: Vec<u8>,
+    pending: HashMap<FragmentKey, Fragments>,
+    seq: u64,
+    stats: LenientStats,
+}
+
+impl Lenient {
+    pub fn stats(&self) -> LenientStats {
+        LenientStats {
+            incomplete: self.stats.incomplete + self.pending.len() as u64,
+            ..self.stats
+        }
+    }
+
+    /// Read the next serial-pcap record from `reader`
+    pub fn next<R: std::io::Read>(
+        &mut self,
+        reader: &mut SourceReader<R>,
+    ) -> Result<Option<CapturedPacket<'_>>> {
+        loop {
+            let Some(pkt) = reader.next()? else {
+                return Ok(None);
+            };
+            let time = pkt.time;
+            // a truncated record can't be decoded
+            if pkt.orig_len != pkt.data.len() {
+                self.stats.foreign += 1;
+            } else if self.accept(pkt.data, time.into()) {
+                return Ok(Some(self.record(time)));
+            }
+        }
+    }
+
+    /// The record accepted by [`accept`](Self::accept)
+    pub fn record(&self, time: std::time::SystemTime) -> CapturedPacket<'_> {
+        CapturedPacket {
+            time,
+            data: &self.buf,
+            orig_len: self.buf.len(),
+        }
+    }
+
+    /// Check a record, returns true if it, or the packet it completes, is a serial-pcap record.
+    /// The record to return is then in `buf`.
+    pub fn accept(&mut self, data: &[u8], time: chrono::DateTime<chrono::Utc>) -> bool {
+        let Ok(ip) = Ipv4HeaderSlice::from_slice(data) else {
+            self.stats.foreign += 1;
+            return false;
+        };
+        let fragmented = ip.more_fragments() || ip.fragments_offset() != 0;
+        self.buf.clear();
+        match fragmented {
+            false => self.buf.extend_from_slice(data),
+            true => match self.add_fragment(&ip, data) {
+                Some(packet) => {
+                    self.stats.reassembled += 1;
+                    self.buf = packet;
+                }
+                None => return false,
+            },
+        }
+        if parse_record(&self.buf, time).is_err() {
+            self.stats.foreign += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Store a fragment, returns the whole packet if it was the missing one
+    fn add_fragment(&mut self, ip: &Ipv4HeaderSlice, data: &[u8]) -> Option<Vec<u8>> {
+        let key = (
+            ip.source(),
+            ip.destination(),
+            ip.protocol(),
+            ip.identification(),
+        );
+        let header_len = usize::from(ip.ihl()) * 4;
+        let end = usize::from(ip.total_len()).clamp(header_len, data.len());
+        let offset = usize::from(ip.fragments_offset()) * 8;
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, f)| f.seq)
+                .map(|(k, _)| *k);
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+                self.stats.incomplete += 1;
+            }
+        }
+        self.seq += 1;
+        let seq = self.seq;
+        let fragments = self.pending.entry(key).or_insert_with(|| Fragments {
+            seq,
+            ..Default::default()
+        });
+        if offset == 0 {
+            fragments.header = data[..header_len].to_vec();
+        }
+        if !ip.more_fragments() {
+            fragments.len = Some(offset + end - header_len);
+        }
+        fragments
+            .parts
+            .push((offset, data[header_len..end].to_vec()));
+        let packet = fragments.assemble()?;
+        self.pending.remove(&key);
+        Some(packet)
+    }
+}
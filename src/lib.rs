@@ -1,34 +1,329 @@
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::{BufWriter, SeekFrom};
 use std::path::Path;
 
 use anyhow::{bail, Context, Result};
-use arrayvec::ArrayVec;
 use bytes::{Buf, BytesMut};
 use chrono::Utc;
-use etherparse::{PacketBuilder, SlicedPacket, TransportSlice};
+use etherparse::{InternetSlice, PacketBuilder, SlicedPacket, TransportSlice};
 use rpcap::read::PcapReader;
 use rpcap::write::{PcapWriter, WriteOptions};
 use rpcap::CapturedPacket;
 use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, SerialStream, StopBits};
+use tracing::warn;
 
-const LINKTYPE_IPV4: u32 = 228; // https://www.tcpdump.org/linktypes.html
+pub mod alert;
+pub mod async_reader;
+pub mod capture;
+pub mod captured;
+pub mod clock;
+pub mod completions;
+pub mod decode;
+pub mod device;
+pub mod diff;
+pub mod dissector;
+pub mod export;
+pub mod extract;
+pub mod fieldbus;
+pub mod fixup;
+pub mod framed;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod harness;
+pub mod hexdump;
+pub mod import;
+pub mod influx;
+pub mod latency;
+pub mod lenient;
+pub mod logging;
+pub mod mirror;
+pub mod mmap;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod names;
+pub mod pace;
+mod pcapng;
+pub mod plot;
+#[cfg(feature = "wasm")]
+pub mod plugin;
+pub mod pool;
+#[cfg(target_os = "linux")]
+pub mod pps;
+#[cfg(unix)]
+pub mod pty;
+pub mod queue;
+pub mod reframe;
+pub mod remote;
+pub mod resync;
+pub mod ring;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod sdlog;
+pub mod segments;
+pub mod sequence;
+pub mod sim;
+pub mod status;
+pub mod step;
+#[cfg(unix)]
+pub mod systemd;
+pub mod tee;
+pub mod tshark;
+pub mod tui;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod validate;
+pub mod websocket;
+pub mod x328;
+
+pub(crate) const LINKTYPE_IPV4: u32 = 228; // https://www.tcpdump.org/linktypes.html
 const MAX_PACKET_LEN: usize = 200; // the maximum size of a packet in the pcap file
+/// The IPv4 header flag marking a packet which is continued in the next one on the channel. It's
+/// the reserved flag, which is never set in real traffic.
+const CONTINUED_FLAG: u8 = 0x80;
+const PCAP_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+const ARENA_SIZE: usize = 64 * 1024; // allocation size for the packet data read from pcap files
 
+/// Writes UART data to a pcap or pcapng file.
+///
+/// The output is buffered, call [`flush`](Self::flush) to make sure that the
+/// packets are written to the underlying writer.
 pub struct SerialPacketWriter<W: std::io::Write> {
-    pcap_writer: PcapWriter<W>,
+    output: PacketOutput<BufWriter<W>>,
+    /// Buffer for building the IP packets
+    packet_buf: Vec<u8>,
+    /// Number of packets written, including markers
+    packets: u64,
+    /// Bytes written to a pcap file, including the header
+    pcap_bytes: u64,
+    /// The maximum data length of a packet, longer writes are split
+    chunk_size: Option<usize>,
+    /// The sequence number of the last packet from each source address and port
+    sequence: HashMap<([u8; 4], u16), u16>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PacketOutput<W: std::io::Write> {
+    Pcap(PcapWriter<W>),
+    Pcapng(pcapng::PcapngWriter<W>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, clap::ValueEnum)]
 #[repr(u16)]
 pub enum UartTxChannel {
     Ctrl = 422,
     Node = 1422,
 }
 
-const CTRL: u16 = UartTxChannel::Ctrl as _;
-const NODE: u16 = UartTxChannel::Node as _;
+pub(crate) const CTRL: u16 = UartTxChannel::Ctrl as _;
+pub(crate) const NODE: u16 = UartTxChannel::Node as _;
+/// UDP port for the marker packets, which hold capture metadata instead of UART data
+pub(crate) const MARKER: u16 = 2422;
+/// UDP port for the trigger packets, from the address of the bus the trigger was recorded on
+pub(crate) const TRIGGER: u16 = 3422;
+
+pub use serial_pcap_core::mux::{
+    ChunkTimes, BREAK_BYTE, DEVICE_TIME_MASK, DROP_BYTE, TIME_BYTE, TRIG_BYTE,
+};
+use serial_pcap_core::mux::{CTRL_BIT, TIME_RECORD_LEN};
+use serial_pcap_core::slip::{Frame, FrameDecoder};
+/// The byte stream framing codecs, shared with the capture firmware
+pub use serial_pcap_core::{cobs, slip};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MarkerKind {
+    /// Data was lost at this point in the capture
+    Drop,
+    /// The wall clock time which the capture timestamps are anchored to
+    Clock,
+    /// Set by the operator during the capture, e.g. when a fault is observed
+    User,
+    /// No data was recorded from here until the next resume marker
+    Pause,
+    Resume,
+    /// Names the port a channel was captured from, when capturing more than two ports
+    Port,
+    /// A break condition on the line of the channel, often used to reset the bus
+    Break,
+    /// The capture device was disconnected, nothing was recorded until it was reconnected
+    Outage,
+    /// An alert rule fired, the label is the rule
+    Alert,
+}
+
+impl MarkerKind {
+    /// The name used in the marker payload, e.g. "trigger"
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MarkerKind::Drop => "drop",
+            MarkerKind::Clock => "clock",
+            MarkerKind::User => "user",
+            MarkerKind::Pause => "pause",
+            MarkerKind::Resume => "resume",
+            MarkerKind::Port => "port",
+            MarkerKind::Break => "break",
+            MarkerKind::Outage => "outage",
+            MarkerKind::Alert => "alert",
+        }
+    }
+}
+
+/// A capture event which isn't UART data, stored as a separate packet type in the pcap
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    /// The channel the marker applies to, if any
+    pub ch: Option<UartTxChannel>,
+    pub label: String,
+    pub time: chrono::DateTime<Utc>,
+}
+
+impl Marker {
+    /// A break condition on the line of `ch`, detected by the capture device of `bus`
+    pub fn line_break(bus: u8, ch: UartTxChannel, time: chrono::DateTime<Utc>) -> Self {
+        let label = match bus {
+            0 => "line break".to_string(),
+            bus => format!("bus {bus} line break"),
+        };
+        Self {
+            kind: MarkerKind::Break,
+            ch: Some(ch),
+            label,
+            time,
+        }
+    }
+
+    /// Marker payload, "<kind>[ <channel>]: <label>"
+    fn encode(&self) -> String {
+        let ch = match self.ch {
+            Some(UartTxChannel::Ctrl) => " ctrl",
+            Some(UartTxChannel::Node) => " node",
+            None => "",
+        };
+        format!("{}{ch}: {}", self.kind.as_str(), self.label)
+    }
+
+    pub(crate) fn decode(payload: &[u8], time: chrono::DateTime<Utc>) -> Result<Self> {
+        let text = std::str::from_utf8(payload).context("Marker payload isn't UTF-8.")?;
+        let (head, label) = text.split_once(": ").context("Invalid marker payload.")?;
+        let mut words = head.split(' ');
+        let kind = match words.next() {
+            Some("drop") => MarkerKind::Drop,
+            Some("clock") => MarkerKind::Clock,
+            Some("user") => MarkerKind::User,
+            Some("pause") => MarkerKind::Pause,
+            Some("resume") => MarkerKind::Resume,
+            Some("port") => MarkerKind::Port,
+            Some("break") => MarkerKind::Break,
+            Some("outage") => MarkerKind::Outage,
+            Some("alert") => MarkerKind::Alert,
+            kind => bail!("Unknown marker kind {kind:?}."),
+        };
+        let ch = match words.next() {
+            Some("ctrl") => Some(UartTxChannel::Ctrl),
+            Some("node") => Some(UartTxChannel::Node),
+            None => None,
+            Some(ch) => bail!("Unknown marker channel {ch}."),
+        };
+        Ok(Self {
+            kind,
+            ch,
+            label: label.to_string(),
+            time,
+        })
+    }
+}
+
+impl std::fmt::Display for Marker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+/// A measurement trigger, stored as its own packet type in the pcap, with the bus in the
+/// addresses like the data packets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    /// The bus of the capture device which recorded the trigger
+    pub bus: u8,
+    /// What fired the trigger, e.g. "button A" on the capture device
+    pub source: String,
+    pub label: String,
+    pub time: chrono::DateTime<Utc>,
+}
 
-pub const TRIG_BYTE: u8 = b'\n';
+impl Trigger {
+    /// The source of the triggers which don't name one, e.g. the trigger byte of the muxed stream
+    pub const DEVICE: &'static str = "device";
+
+    /// A measurement trigger from `source` on the capture device of `bus`
+    pub fn new(bus: u8, source: impl Into<String>, time: chrono::DateTime<Utc>) -> Self {
+        Self {
+            bus,
+            source: source.into(),
+            label: "measurement trigger".to_string(),
+            time,
+        }
+    }
+
+    /// Trigger payload, "<source>: <label>"
+    fn encode(&self) -> String {
+        format!("{}: {}", self.source, self.label)
+    }
+
+    pub(crate) fn decode(bus: u8, payload: &[u8], time: chrono::DateTime<Utc>) -> Result<Self> {
+        let text = std::str::from_utf8(payload).context("Trigger payload isn't UTF-8.")?;
+        let (source, label) = text.split_once(": ").context("Invalid trigger payload.")?;
+        Ok(Self {
+            bus,
+            source: source.to_string(),
+            label: label.to_string(),
+            time,
+        })
+    }
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+/// A packet read from a capture file
+#[derive(Debug, Clone)]
+pub enum CaptureRecord {
+    Packet(SerialPacket),
+    Marker(Marker),
+    Trigger(Trigger),
+}
+
+impl CaptureRecord {
+    pub fn time(&self) -> chrono::DateTime<Utc> {
+        match self {
+            CaptureRecord::Packet(pkt) => pkt.time,
+            CaptureRecord::Marker(marker) => marker.time,
+            CaptureRecord::Trigger(trigger) => trigger.time,
+        }
+    }
+}
+
+/// A UART data packet which borrows the data from the reader
+#[derive(Debug, Clone, Copy)]
+pub struct PacketRef<'a> {
+    pub bus: u8,
+    pub ch: UartTxChannel,
+    pub data: &'a [u8],
+    pub time: chrono::DateTime<Utc>,
+}
+
+/// A packet read from a capture file, see [`SerialPacketReader::next_record_ref`]
+#[derive(Debug, Clone)]
+pub enum RecordRef<'a> {
+    Packet(PacketRef<'a>),
+    Marker(Marker),
+    Trigger(Trigger),
+}
 
 impl SerialPacketWriter<File> {
     pub fn new_file(filename: impl AsRef<Path>) -> Result<Self> {
@@ -36,11 +331,38 @@ impl SerialPacketWriter<File> {
         let writer = File::create(filename).context("Failed to create pcap file {filename}")?;
         SerialPacketWriter::<File>::new(writer)
     }
+
+    /// Create a pcapng file, see [`new_pcapng`](Self::new_pcapng)
+    pub fn new_pcapng_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let writer = File::create(filename)
+            .with_context(|| format!("Failed to create pcapng file {}", filename.display()))?;
+        SerialPacketWriter::<File>::new_pcapng(writer)
+    }
+
+    /// Append to an existing capture file, or create it if it doesn't exist. A partial
+    /// record at the end, e.g. from a capture which was killed, is removed first.
+    pub fn append_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(filename)
+            .with_context(|| format!("Failed to open pcap file {}", filename.display()))?;
+        if file.metadata()?.len() == 0 {
+            return Self::new(file);
+        }
+        let (options, end) = seek_to_append(&mut file)?;
+        file.set_len(end)?;
+        Self::continue_with(file, options)
+    }
 }
 
 impl<W: std::io::Write> SerialPacketWriter<W> {
     pub fn new(writer: W) -> Result<Self> {
-        let pcap_writer = PcapWriter::new(
+        Self::with_options(
             writer,
             WriteOptions {
                 snaplen: MAX_PACKET_LEN, // maximum packet size in file
@@ -49,46 +371,370 @@ impl<W: std::io::Write> SerialPacketWriter<W> {
                 non_native_byte_order: false,
             },
         )
-        .context("Couldn't create PcapWriter.")?;
-        Ok(Self { pcap_writer })
+    }
+
+    /// Create a writer for rewriting the capture read by `reader`, e.g. in a filter. The
+    /// timestamp resolution and the snaplen are taken from the input file, so the packets
+    /// written with [`write_record`](Self::write_record) keep their exact timestamps.
+    pub fn new_like<R: std::io::Read>(writer: W, reader: &SerialPacketReader<R>) -> Result<Self> {
+        Self::with_options(
+            writer,
+            WriteOptions {
+                snaplen: reader.options.snaplen.max(MAX_PACKET_LEN),
+                ..reader.options
+            },
+        )
+        // the packets are split like by a new capture
+        .map(|w| w.with_chunk_size(Some(MAX_PACKET_LEN - 32)))
+    }
+
+    /// Create a writer with a larger maximum packet size than the default 200 bytes, so
+    /// longer writes aren't split, e.g. 65535 for the frames of a protocol with long messages.
+    pub fn new_with_snaplen(writer: W, snaplen: usize) -> Result<Self> {
+        if snaplen < MAX_PACKET_LEN {
+            bail!("The snaplen {snaplen} is less than the minimum {MAX_PACKET_LEN}.");
+        }
+        Self::with_options(
+            writer,
+            WriteOptions {
+                snaplen,
+                linktype: LINKTYPE_IPV4,
+                high_res_timestamps: false,
+                non_native_byte_order: false,
+            },
+        )
+    }
+
+    /// Split the data written with [`write_bus_packet_time`](Self::write_bus_packet_time) in
+    /// packets of at most `size` bytes. All but the last packet of a write are flagged as
+    /// continued, see [`SerialPacketReader::continued`]. With None a write is always a single
+    /// packet, and data which doesn't fit in the snaplen is an error.
+    ///
+    /// The default splits the data to fit in the snaplen.
+    pub fn with_chunk_size(mut self, size: Option<usize>) -> Self {
+        self.chunk_size = size.map(|size| size.max(1));
+        self
+    }
+
+    fn with_options(writer: W, options: WriteOptions) -> Result<Self> {
+        let pcap_writer = PcapWriter::new(BufWriter::new(writer), options)
+            .context("Couldn't create PcapWriter.")?;
+        Ok(Self {
+            output: PacketOutput::Pcap(pcap_writer),
+            packet_buf: vec![],
+            sequence: HashMap::new(),
+            packets: 0,
+            pcap_bytes: PCAP_HEADER_LEN,
+            // 32 is the UDP header length
+            chunk_size: Some(options.snaplen - 32),
+        })
+    }
+
+    /// Write pcapng instead of pcap, with an interface for each channel. The interfaces are
+    /// named after the channels, and can be described with
+    /// [`describe_channel`](Self::describe_channel).
+    pub fn new_pcapng(writer: W) -> Result<Self> {
+        let pcapng_writer = pcapng::PcapngWriter::new(BufWriter::new(writer), MAX_PACKET_LEN)?;
+        Ok(Self {
+            output: PacketOutput::Pcapng(pcapng_writer),
+            packet_buf: vec![],
+            sequence: HashMap::new(),
+            packets: 0,
+            pcap_bytes: 0,
+            chunk_size: Some(MAX_PACKET_LEN - 32),
+        })
+    }
+
+    /// Describe where the channel is captured from, e.g. "ctrl (/dev/ttyUSB0 9600 7E1)". The
+    /// description is stored in the pcapng interface of the channel, so it must be set
+    /// before the first packet from the channel. Plain pcap files have no place for it.
+    pub fn describe_channel(&mut self, bus: u8, ch: UartTxChannel, description: impl Into<String>) {
+        if let PacketOutput::Pcapng(w) = &mut self.output {
+            let iface = pcapng::Interface { bus, ch: Some(ch) };
+            w.describe(iface, description.into());
+        }
+    }
+
+    /// Append to an existing capture in `stream`, after checking that its linktype and
+    /// snaplen are the ones written by this writer. The packets are written after the last
+    /// complete record.
+    pub fn append(mut stream: W) -> Result<Self>
+    where
+        W: std::io::Read + std::io::Seek,
+    {
+        let (options, _) = seek_to_append(&mut stream)?;
+        Self::continue_with(stream, options)
+    }
+
+    /// Continue a capture at the current position in `writer`
+    fn continue_with(writer: W, options: WriteOptions) -> Result<Self> {
+        let pcap_writer = PcapWriter::append_unchecked(BufWriter::new(writer), options)
+            .context("Couldn't create PcapWriter.")?;
+        Ok(Self {
+            output: PacketOutput::Pcap(pcap_writer),
+            packet_buf: vec![],
+            sequence: HashMap::new(),
+            packets: 0,
+            pcap_bytes: 0,
+            chunk_size: Some(options.snaplen - 32),
+        })
     }
 
     pub fn write_packet(&mut self, data: &[u8], channel: UartTxChannel) -> Result<()> {
         self.write_packet_time(data, channel, std::time::SystemTime::now())
     }
 
+    /// Write the bytes written to the returned writer as packets from the channel, e.g. to
+    /// capture what existing code writes to a serial port. Each write is one packet with the
+    /// current time, and a flush flushes the capture file.
+    pub fn writer(&mut self, ch: UartTxChannel) -> impl std::io::Write + '_ {
+        WritePcapWriteImpl { writer: self, ch }
+    }
+
     pub fn write_packet_time(
         &mut self,
         data: &[u8],
         channel: UartTxChannel,
         time: std::time::SystemTime,
     ) -> Result<()> {
-        let (ip, ports) = match channel {
-            UartTxChannel::Ctrl => (([127, 0, 0, 1], [127, 0, 0, 2]), (CTRL, NODE)),
-            UartTxChannel::Node => (([127, 0, 0, 2], [127, 0, 0, 1]), (NODE, CTRL)),
+        self.write_bus_packet_time(0, data, channel, time)
+    }
+
+    /// Write data captured from one of the extra buses. The bus number is stored in
+    /// the third octet of the IP addresses, so bus 0 is the same as [`write_packet_time`].
+    ///
+    /// [`write_packet_time`]: Self::write_packet_time
+    pub fn write_bus_packet_time(
+        &mut self,
+        bus: u8,
+        data: &[u8],
+        channel: UartTxChannel,
+        time: std::time::SystemTime,
+    ) -> Result<()> {
+        let addrs = channel_addrs(bus, channel);
+        let iface = pcapng::Interface {
+            bus,
+            ch: Some(channel),
         };
+        let mut chunks = data
+            .chunks(self.chunk_size.unwrap_or(data.len().max(1)))
+            .peekable();
+        while let Some(chunk) = chunks.next() {
+            let continued = chunks.peek().is_some();
+            self.write_udp(chunk, addrs, iface, time, None, continued)?;
+        }
+        Ok(())
+    }
 
-        for data in data.chunks(MAX_PACKET_LEN - 32) {
-            // 32 is the UDP header length
-            let builder = PacketBuilder::ipv4(ip.0, ip.1, 254).udp(ports.0, ports.1);
-            let mut buf = ArrayVec::<u8, MAX_PACKET_LEN>::new();
-            builder
-                .write(&mut buf, data)
-                .context("Writing to packet memory buffer failed.")?;
-            self.pcap_writer
-                .write(&CapturedPacket {
-                    time,
-                    data: buf.as_slice(),
-                    orig_len: buf.len(),
-                })
-                .context("Failed to write packet to pcap file")?;
+    /// Write a packet or marker read from a capture, as a single packet with the original
+    /// timestamp. Unlike [`write_bus_packet_time`](Self::write_bus_packet_time) the data
+    /// isn't split, and a packet which doesn't fit in the snaplen is an error, so rewriting
+    /// a capture doesn't change its timing.
+    pub fn write_record(&mut self, record: &CaptureRecord) -> Result<()> {
+        match record {
+            CaptureRecord::Packet(pkt) => {
+                let addrs = channel_addrs(pkt.bus, pkt.ch);
+                let iface = pcapng::Interface {
+                    bus: pkt.bus,
+                    ch: Some(pkt.ch),
+                };
+                self.write_udp(&pkt.data, addrs, iface, pkt.time.into(), None, false)
+            }
+            CaptureRecord::Marker(marker) => self.write_marker(marker),
+            CaptureRecord::Trigger(trigger) => self.write_trigger(trigger),
+        }
+    }
+
+    /// Number of packets written by this writer, including the markers
+    pub fn packets_written(&self) -> u64 {
+        self.packets
+    }
+
+    /// Number of bytes written by this writer, including the file header but not what was
+    /// already in the file when appending
+    pub fn bytes_written(&self) -> u64 {
+        match &self.output {
+            PacketOutput::Pcap(_) => self.pcap_bytes,
+            PacketOutput::Pcapng(w) => w.bytes_written(),
+        }
+    }
+
+    /// Write any buffered packets to the underlying writer, and flush it.
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.output {
+            PacketOutput::Pcap(w) => w.flush(),
+            PacketOutput::Pcapng(w) => w.flush(),
+        }
+        .context("Failed to flush the pcap file.")
+    }
+
+    /// Flush the buffered packets and return the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        let writer = match self.output {
+            PacketOutput::Pcap(w) => w.take_writer(),
+            PacketOutput::Pcapng(w) => w.take_writer(),
+        };
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush the pcap file.")
+    }
+
+    /// Write a marker packet. Labels which don't fit in a packet are truncated.
+    pub fn write_marker(&mut self, marker: &Marker) -> Result<()> {
+        let mut payload = marker.encode();
+        let mut len = payload.len().min(MAX_PACKET_LEN - 32);
+        while !payload.is_char_boundary(len) {
+            len -= 1;
         }
+        payload.truncate(len);
+        let ip = ([127, 0, 0, 1], [127, 0, 0, 1]);
+        let iface = pcapng::Interface { bus: 0, ch: None };
+        let time = marker.time.into();
+        // pcapng readers show the marker as a packet comment, without the dissector
+        let comment = Some(payload.as_str());
+        self.write_udp(
+            payload.as_bytes(),
+            (ip, (MARKER, MARKER)),
+            iface,
+            time,
+            comment,
+            false,
+        )
+    }
+
+    /// Write a trigger packet, on the bus of the trigger
+    pub fn write_trigger(&mut self, trigger: &Trigger) -> Result<()> {
+        let payload = trigger.encode();
+        let ip = ([127, 0, trigger.bus, 1], [127, 0, trigger.bus, 1]);
+        // pcapng files have the triggers on the marker interface, of all the buses
+        let iface = pcapng::Interface { bus: 0, ch: None };
+        let comment = Some(payload.as_str());
+        self.write_udp(
+            payload.as_bytes(),
+            (ip, (TRIGGER, TRIGGER)),
+            iface,
+            trigger.time.into(),
+            comment,
+            false,
+        )
+    }
+
+    fn write_udp(
+        &mut self,
+        data: &[u8],
+        (ip, ports): (([u8; 4], [u8; 4]), (u16, u16)),
+        iface: pcapng::Interface,
+        time: std::time::SystemTime,
+        comment: Option<&str>,
+        continued: bool,
+    ) -> Result<()> {
+        let builder = PacketBuilder::ipv4(ip.0, ip.1, 254).udp(ports.0, ports.1);
+        let buf = &mut self.packet_buf;
+        buf.clear();
+        builder
+            .write(buf, data)
+            .context("Writing to packet memory buffer failed.")?;
+        // the identification is the sequence number on the channel, 0 is left for the packets
+        // without one
+        // the triggers are numbered with the markers, as the events of the capture
+        let key = match ports.0 {
+            TRIGGER => ([127, 0, 0, 1], MARKER),
+            port => (ip.0, port),
+        };
+        let seq = self.sequence.entry(key).or_insert(0);
+        *seq = sequence::next(*seq);
+        buf[4..6].copy_from_slice(&seq.to_be_bytes());
+        if continued {
+            buf[6] |= CONTINUED_FLAG;
+        }
+        buf[10..12].fill(0);
+        let checksum = lenient::ipv4_checksum(&buf[..20]);
+        buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+        let pcap_writer = match &mut self.output {
+            PacketOutput::Pcap(w) => w,
+            PacketOutput::Pcapng(w) if buf.len() <= MAX_PACKET_LEN => {
+                w.write(iface, time, buf, comment)?;
+                self.packets += 1;
+                return Ok(());
+            }
+            PacketOutput::Pcapng(_) => bail!("Packet of {} bytes is too long.", buf.len()),
+        };
+        let snaplen = pcap_writer.get_options().snaplen;
+        if buf.len() > snaplen {
+            bail!(
+                "Packet of {} bytes doesn't fit the snaplen {snaplen}.",
+                buf.len()
+            );
+        }
+        pcap_writer
+            .write(&CapturedPacket {
+                time,
+                data: buf.as_slice(),
+                orig_len: buf.len(),
+            })
+            .context("Failed to write packet to pcap file")?;
+        self.packets += 1;
+        self.pcap_bytes += PCAP_RECORD_HEADER_LEN + buf.len() as u64;
         Ok(())
     }
 }
 
+/// The IP addresses and UDP ports of a channel, with the bus in the third octet
+fn channel_addrs(bus: u8, channel: UartTxChannel) -> (([u8; 4], [u8; 4]), (u16, u16)) {
+    match channel {
+        UartTxChannel::Ctrl => (([127, 0, bus, 1], [127, 0, bus, 2]), (CTRL, NODE)),
+        UartTxChannel::Node => (([127, 0, bus, 2], [127, 0, bus, 1]), (NODE, CTRL)),
+    }
+}
+
+/// Check the pcap header in `stream`, and seek to the end of the last complete record.
+/// Returns the file options and the position.
+fn seek_to_append<S: std::io::Read + std::io::Seek>(stream: &mut S) -> Result<(WriteOptions, u64)> {
+    let len = stream.seek(SeekFrom::End(0))?;
+    stream.seek(SeekFrom::Start(0))?;
+    let (options, reader) =
+        PcapReader::new(&mut *stream).context("Failed to read the pcap header.")?;
+    drop(reader);
+    if options.linktype != LINKTYPE_IPV4 {
+        bail!(
+            "Can't append to a capture with linktype {}, expected {LINKTYPE_IPV4}.",
+            options.linktype
+        );
+    }
+    if options.snaplen < MAX_PACKET_LEN {
+        bail!(
+            "Can't append to a capture with snaplen {}, the minimum is {MAX_PACKET_LEN}.",
+            options.snaplen
+        );
+    }
+    // walk the record headers, the included length is at offset 8
+    let mut pos = 24;
+    let mut header = [0u8; 16];
+    loop {
+        stream.seek(SeekFrom::Start(pos))?;
+        if pos + 16 > len {
+            break;
+        }
+        stream.read_exact(&mut header)?;
+        let incl_len = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+        let incl_len = match options.non_native_byte_order {
+            true => incl_len.swap_bytes(),
+            false => incl_len,
+        };
+        if pos + 16 + incl_len as u64 > len {
+            break;
+        }
+        pos += 16 + incl_len as u64;
+    }
+    stream.seek(SeekFrom::Start(pos))?;
+    Ok((options, pos))
+}
+
 #[derive(Debug, Clone)]
 pub struct SerialPacket {
+    /// The bus the data was captured from, 0 unless the capture device taps several buses
+    pub bus: u8,
     pub ch: UartTxChannel,
     pub data: BytesMut,
     pub time: chrono::DateTime<Utc>,
@@ -102,25 +748,146 @@ impl<R: std::io::Read> Iterator for SerialPacketReader<R> {
     }
 }
 
+enum SourceReader<R: std::io::Read> {
+    Pcap(PcapReader<R>),
+    Resync(resync::ResyncReader<R>),
+}
+
+impl<R: std::io::Read> SourceReader<R> {
+    fn next(&mut self) -> Result<Option<CapturedPacket<'_>>> {
+        match self {
+            SourceReader::Pcap(r) => r.next().context("Pcap read error"),
+            SourceReader::Resync(r) => r.next_record(),
+        }
+    }
+}
+
+/// Where the reader gets the pcap records from
+struct RecordSource<R: std::io::Read> {
+    reader: SourceReader<R>,
+    /// The time of the record in `peek_buf`, which was read ahead by `skip_to`
+    peeked: Option<std::time::SystemTime>,
+    peek_buf: Vec<u8>,
+    /// Skips the foreign records, see [`SerialPacketReader::with_lenient`]
+    lenient: Option<lenient::Lenient>,
+}
+
+impl<R: std::io::Read> RecordSource<R> {
+    fn next(&mut self) -> Result<Option<CapturedPacket<'_>>> {
+        if let Some(time) = self.peeked.take() {
+            let accepted = match &mut self.lenient {
+                Some(lenient) => lenient.accept(&self.peek_buf, time.into()),
+                None => true,
+            };
+            if accepted {
+                return Ok(Some(match &self.lenient {
+                    Some(lenient) => lenient.record(time),
+                    None => CapturedPacket {
+                        time,
+                        data: &self.peek_buf,
+                        orig_len: self.peek_buf.len(),
+                    },
+                }));
+            }
+        }
+        match &mut self.lenient {
+            Some(lenient) => lenient.next(&mut self.reader),
+            None => self.reader.next(),
+        }
+    }
+
+    /// Discard the records before `time`, only looking at the record headers. The first
+    /// record at or after `time` is kept for the next read.
+    fn skip_to(&mut self, time: std::time::SystemTime) -> Result<()> {
+        if self.peeked.is_some_and(|t| t >= time) {
+            return Ok(());
+        }
+        self.peeked = None;
+        while let Some(pkt) = self.reader.next()? {
+            if pkt.time >= time {
+                self.peek_buf.clear();
+                self.peek_buf.extend_from_slice(pkt.data);
+                self.peeked = Some(pkt.time);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct SerialPacketReader<R: std::io::Read> {
-    pcap_reader: PcapReader<R>,
+    source: RecordSource<R>,
+    options: rpcap::FileOptions,
+    /// Backing storage for the data of the returned packets
+    arena: BytesMut,
     ctrl_buf: BytesMut,
     node_buf: BytesMut,
+    /// The IP header fields of the last record read
+    last: RecordHeader,
+    sequences: sequence::SequenceCheck,
     pub stream_time: std::time::SystemTime,
 }
 
 impl<R: std::io::Read> SerialPacketReader<R> {
     pub fn new(reader: R) -> Result<Self> {
+        let (options, pcap_reader) =
+            PcapReader::new(reader).context("Failed to create PcapReader.")?;
         Ok(Self {
-            pcap_reader: PcapReader::new(reader)
-                .context("Failed to create PcapReader.")?
-                .1,
+            source: RecordSource {
+                reader: SourceReader::Pcap(pcap_reader),
+                peeked: None,
+                peek_buf: vec![],
+                lenient: None,
+            },
+            options,
+            arena: BytesMut::new(),
             ctrl_buf: Default::default(),
             node_buf: Default::default(),
+            last: RecordHeader::default(),
+            sequences: Default::default(),
             stream_time: std::time::SystemTime::now(),
         })
     }
 
+    /// Skip the corrupt records instead of failing on them, see [`resync::ResyncReader`].
+    pub fn with_resync(mut self) -> Self {
+        self.source.reader = match self.source.reader {
+            SourceReader::Pcap(r) => {
+                SourceReader::Resync(resync::ResyncReader::new(r.take_reader(), self.options))
+            }
+            reader => reader,
+        };
+        self
+    }
+
+    /// The data skipped so far, None unless [`with_resync`](Self::with_resync) is used
+    pub fn resync_stats(&self) -> Option<resync::ResyncStats> {
+        match &self.source.reader {
+            SourceReader::Pcap(_) => None,
+            SourceReader::Resync(r) => Some(r.stats()),
+        }
+    }
+
+    /// Skip the records which aren't serial-pcap packets or markers instead of failing on them,
+    /// and reassemble fragmented IPv4 packets, e.g. for a capture merged with other traffic.
+    pub fn with_lenient(mut self) -> Self {
+        self.source.lenient.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// The records skipped so far, None unless [`with_lenient`](Self::with_lenient) is used
+    pub fn lenient_stats(&self) -> Option<lenient::LenientStats> {
+        self.source.lenient.as_ref().map(|l| l.stats())
+    }
+
+    /// Discard the packets and markers before `time`, so the next read returns the first
+    /// record at or after it. Only the record headers of the skipped records are read, which
+    /// is much faster than reading the packets when starting in the middle of a long capture.
+    pub fn skip_to(&mut self, time: chrono::DateTime<Utc>) -> Result<()> {
+        self.sequences.reset();
+        self.source.skip_to(time.into())
+    }
+
     pub fn read_bytes(&mut self, ch: UartTxChannel, max_len: usize) -> Result<BytesMut> {
         if self.get_buffer(ch).is_empty() {
             self.fill_buffer(ch)?;
@@ -130,28 +897,68 @@ impl<R: std::io::Read> SerialPacketReader<R> {
         Ok(buf.split_to(len))
     }
 
+    /// Read the next UART data packet, skipping any markers and triggers.
     pub fn next_packet(&mut self) -> Result<Option<SerialPacket>> {
-        let Some(pkt) = self.pcap_reader.next().context("Pcap read error")? else {
-            return Ok(None);
-        };
-        let time = chrono::DateTime::from(pkt.time);
-        assert_eq!(pkt.orig_len, pkt.data.len());
-        let pkt = SlicedPacket::from_ip(pkt.data).context("Failed to slice packet")?;
-        let Some(TransportSlice::Udp(udp_hdr)) = pkt.transport else {
-            bail!("Failed to find UDP header in pkt.")
-        };
-        let source_port = udp_hdr.source_port();
-        let ch = match source_port {
-            CTRL => UartTxChannel::Ctrl,
-            NODE => UartTxChannel::Node,
-            1442 => UartTxChannel::Node, // anyhow..
-            _ => bail!("Incorrect UDP source port {source_port}."),
-        };
-        Ok(Some(SerialPacket {
-            ch,
-            data: BytesMut::from(pkt.payload),
-            time,
-        }))
+        loop {
+            match self.next_record()? {
+                Some(CaptureRecord::Packet(pkt)) => return Ok(Some(pkt)),
+                Some(CaptureRecord::Marker(_) | CaptureRecord::Trigger(_)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Read the next packet, marker or trigger.
+    pub fn next_record(&mut self) -> Result<Option<CaptureRecord>> {
+        let record = match read_record(&mut self.source, &mut self.last, &mut self.sequences)? {
+            Some(RecordRef::Packet(pkt)) => CaptureRecord::Packet(SerialPacket {
+                bus: pkt.bus,
+                ch: pkt.ch,
+                data: copy_to_arena(&mut self.arena, pkt.data),
+                time: pkt.time,
+            }),
+            Some(RecordRef::Marker(marker)) => CaptureRecord::Marker(marker),
+            Some(RecordRef::Trigger(trigger)) => CaptureRecord::Trigger(trigger),
+            None => return Ok(None),
+        };
+        Ok(Some(record))
+    }
+
+    /// Read the next packet, marker or trigger, without copying the packet data.
+    ///
+    /// The returned data borrows the reader's packet buffer, which is reused for the next read.
+    pub fn next_record_ref(&mut self) -> Result<Option<RecordRef<'_>>> {
+        read_record(&mut self.source, &mut self.last, &mut self.sequences)
+    }
+
+    /// The data of the last packet read continues in the next packet of the channel, because the
+    /// writer split a longer write, see [`SerialPacketWriter::with_chunk_size`].
+    pub fn continued(&self) -> bool {
+        self.last.continued
+    }
+
+    /// The sequence number of the last packet or marker read. The writer numbers the records of
+    /// each channel from 1, and the markers separately, so a missing or reordered packet shows
+    /// up as a jump. None for the captures from older versions, which don't have them.
+    pub fn sequence(&self) -> Option<u16> {
+        self.last.sequence
+    }
+
+    /// The number of jumps in the sequence numbers so far, i.e. places where packets are
+    /// missing or out of order
+    pub fn sequence_gaps(&self) -> u64 {
+        self.sequences.count()
+    }
+
+    /// The jumps in the sequence numbers since the last call. Only the first 1024 are kept
+    /// between the calls, the rest are only counted.
+    pub fn take_sequence_gaps(&mut self) -> Vec<sequence::SequenceGap> {
+        self.sequences.take_gaps()
+    }
+
+    /// Re-frame the packets on idle times longer than `gap`, see [`reframe::FrameReader`].
+    pub fn frames(self, gap: std::time::Duration) -> reframe::FrameReader<Self> {
+        reframe::FrameReader::new(self, gap)
     }
 
     pub fn reader(&mut self, ch: UartTxChannel) -> impl std::io::Read + '_ {
@@ -171,22 +978,161 @@ impl<R: std::io::Read> SerialPacketReader<R> {
     }
 
     fn extend_one_pkt(&mut self) -> Result<bool> {
-        let Some(pkt) = self.next_packet()? else {
-            return Ok(false);
+        let pkt = match read_record(&mut self.source, &mut self.last, &mut self.sequences)? {
+            Some(RecordRef::Packet(pkt)) => pkt,
+            Some(RecordRef::Marker(_) | RecordRef::Trigger(_)) => return Ok(true),
+            None => return Ok(false),
         };
+        // the byte stream readers only follow the first bus
+        if pkt.bus != 0 {
+            return Ok(true);
+        }
         let buf = match pkt.ch {
             UartTxChannel::Ctrl => &mut self.ctrl_buf,
             UartTxChannel::Node => &mut self.node_buf,
         };
-        buf.unsplit(pkt.data);
+        buf.extend_from_slice(pkt.data);
         Ok(true)
     }
 }
 
-impl SerialPacketReader<File> {
-    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+/// The IP header fields which the writer uses besides the addresses
+#[derive(Debug, Default, Clone, Copy)]
+struct RecordHeader {
+    continued: bool,
+    sequence: Option<u16>,
+}
+
+impl RecordHeader {
+    fn parse(data: &[u8]) -> Self {
+        match data {
+            [version, _, _, _, id0, id1, flags, ..] if version >> 4 == 4 => Self {
+                continued: flags & CONTINUED_FLAG != 0,
+                sequence: Some(u16::from_be_bytes([*id0, *id1])).filter(|&seq| seq != 0),
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Parse the next pcap packet, borrowing the payload from the pcap reader. `header` is set to
+/// the header fields of the packet, and its sequence number is checked in `sequences`.
+fn read_record<'a, R: std::io::Read>(
+    source: &'a mut RecordSource<R>,
+    header: &mut RecordHeader,
+    sequences: &mut sequence::SequenceCheck,
+) -> Result<Option<RecordRef<'a>>> {
+    let Some(pkt) = source.next()? else {
+        return Ok(None);
+    };
+    assert_eq!(pkt.orig_len, pkt.data.len());
+    *header = RecordHeader::parse(pkt.data);
+    let record = parse_record(pkt.data, pkt.time.into())?;
+    if let Some(seq) = header.sequence {
+        sequences.check(seq, &record);
+    }
+    Ok(Some(record))
+}
+
+/// Decode the IPv4/UDP packet from a pcap record.
+pub(crate) fn parse_record(data: &[u8], time: chrono::DateTime<Utc>) -> Result<RecordRef<'_>> {
+    let pkt = SlicedPacket::from_ip(data).context("Failed to slice packet")?;
+    let Some(TransportSlice::Udp(udp_hdr)) = pkt.transport else {
+        bail!("Failed to find UDP header in pkt.")
+    };
+    let source_port = udp_hdr.source_port();
+    let bus = match &pkt.ip {
+        Some(InternetSlice::Ipv4(ip, _)) => ip.source()[2],
+        _ => 0,
+    };
+    let ch = match source_port {
+        CTRL => UartTxChannel::Ctrl,
+        NODE => UartTxChannel::Node,
+        1442 => UartTxChannel::Node, // anyhow..
+        MARKER => {
+            let marker = Marker::decode(pkt.payload, time)?;
+            return Ok(RecordRef::Marker(marker));
+        }
+        TRIGGER => {
+            let trigger = Trigger::decode(bus, pkt.payload, time)?;
+            return Ok(RecordRef::Trigger(trigger));
+        }
+        _ => bail!("Incorrect UDP source port {source_port}."),
+    };
+    Ok(RecordRef::Packet(PacketRef {
+        bus,
+        ch,
+        data: pkt.payload,
+        time,
+    }))
+}
+
+/// Copy `data` into the shared arena buffer, so that each packet doesn't need an allocation
+pub(crate) fn copy_to_arena(arena: &mut BytesMut, data: &[u8]) -> BytesMut {
+    if arena.capacity() < data.len() {
+        *arena = BytesMut::with_capacity(ARENA_SIZE.max(data.len()));
+    }
+    arena.extend_from_slice(data);
+    arena.split()
+}
+
+/// A capture file, or stdin when the file name is "-", e.g. at the end of a pipeline
+pub enum CaptureInput {
+    File(File),
+    Stdin(std::io::Stdin),
+    Mapped(mmap::MappedCapture),
+}
+
+impl CaptureInput {
+    pub fn open(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        if filename == Path::new("-") {
+            return Ok(Self::Stdin(std::io::stdin()));
+        }
+        let file = File::open(filename)
+            .with_context(|| format!("Failed to open {}.", filename.display()))?;
+        Ok(Self::File(file))
+    }
+
+    /// Like [`open`](Self::open), but the file is mapped into memory, see [`mmap`]. Stdin is
+    /// read as usual.
+    pub fn open_mapped(filename: impl AsRef<Path>) -> Result<Self> {
         let filename = filename.as_ref();
-        Self::new(File::open(filename).context("Failed to open {filename}")?)
+        if filename == Path::new("-") {
+            return Ok(Self::Stdin(std::io::stdin()));
+        }
+        Ok(Self::Mapped(mmap::MappedCapture::open(filename)?))
+    }
+}
+
+impl std::io::Read for CaptureInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            CaptureInput::File(f) => f.read(buf),
+            CaptureInput::Stdin(s) => s.read(buf),
+            CaptureInput::Mapped(m) => m.read(buf),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        match self {
+            CaptureInput::File(f) => f.read_exact(buf),
+            CaptureInput::Stdin(s) => s.read_exact(buf),
+            CaptureInput::Mapped(m) => m.read_exact(buf),
+        }
+    }
+}
+
+impl SerialPacketReader<CaptureInput> {
+    /// Read the capture file, or stdin if `filename` is "-"
+    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+        Self::new(CaptureInput::open(filename)?)
+    }
+
+    /// Read the capture file through a memory map, which is faster for a pass over a whole
+    /// large capture, see [`mmap`]. Stdin is read as usual.
+    pub fn from_file_mapped(filename: impl AsRef<Path>) -> Result<Self> {
+        Self::new(CaptureInput::open_mapped(filename)?)
     }
 }
 
@@ -198,18 +1144,305 @@ struct ReadPcapReadImpl<'a, R: std::io::Read> {
 impl<R: std::io::Read> std::io::Read for ReadPcapReadImpl<'_, R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         if let Err(e) = self.reader.fill_buffer(self.ch) {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+            return Err(std::io::Error::other(e));
         }
         self.reader.get_buffer(self.ch).reader().read(buf)
     }
 }
 
+struct WritePcapWriteImpl<'a, W: std::io::Write> {
+    writer: &'a mut SerialPacketWriter<W>,
+    ch: UartTxChannel,
+}
+
+impl<W: std::io::Write> std::io::Write for WritePcapWriteImpl<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.writer
+            .write_packet(buf, self.ch)
+            .map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush().map_err(std::io::Error::other)
+    }
+}
+
+/// UART line settings, written as e.g. "9600,7E1"
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UartSettings {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for UartSettings {
+    /// The X3.28 settings, 9600 baud 7E1
+    fn default() -> Self {
+        Self {
+            baud: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl std::str::FromStr for UartSettings {
+    type Err = anyhow::Error;
+
+    /// Parse "BAUD[,FORMAT]", where the format is e.g. 8N1, and defaults to 7E1.
+    fn from_str(s: &str) -> Result<Self> {
+        let (baud, format) = s.split_once(',').unwrap_or((s, "7E1"));
+        let baud = baud
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid baud rate '{baud}'."))?;
+        let &[data_bits, parity, stop_bits] = format.trim().as_bytes() else {
+            bail!("Invalid UART format '{format}', expected e.g. 8N1.");
+        };
+        Ok(Self {
+            baud,
+            data_bits: match data_bits {
+                b'5' => DataBits::Five,
+                b'6' => DataBits::Six,
+                b'7' => DataBits::Seven,
+                b'8' => DataBits::Eight,
+                _ => bail!("Invalid number of data bits in '{format}'."),
+            },
+            parity: match parity.to_ascii_uppercase() {
+                b'N' => Parity::None,
+                b'E' => Parity::Even,
+                b'O' => Parity::Odd,
+                _ => bail!("Invalid parity in '{format}'."),
+            },
+            stop_bits: match stop_bits {
+                b'1' => StopBits::One,
+                b'2' => StopBits::Two,
+                _ => bail!("Invalid number of stop bits in '{format}'."),
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for UartSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(f, "{} {data_bits}{parity}{stop_bits}", self.baud)
+    }
+}
+
 /// Open a tokio_serial UART with the correct settings for X3.28
 pub fn open_async_uart(uart: &str) -> Result<SerialStream> {
-    tokio_serial::new(uart, 9600)
-        .parity(Parity::Even)
-        .data_bits(DataBits::Seven)
-        .stop_bits(StopBits::One)
+    open_async_uart_with(uart, &UartSettings::default())
+}
+
+/// Open a tokio_serial UART with other settings, e.g. behind a converter which re-clocks
+pub fn open_async_uart_with(uart: &str, settings: &UartSettings) -> Result<SerialStream> {
+    tokio_serial::new(uart, settings.baud)
+        .parity(settings.parity)
+        .data_bits(settings.data_bits)
+        .stop_bits(settings.stop_bits)
         .open_native_async()
         .with_context(|| format!("Failed to open serial port {uart}."))
 }
+
+/// How the capture device frames the muxed stream, selected with `set framing` on the device
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MuxFraming {
+    /// The ctrl bytes have bit 7 set, for 7 bit data
+    #[default]
+    Bit7,
+    /// SLIP frames with a channel header, for 8 bit data
+    Slip,
+}
+
+/// Data for one channel, demultiplexed from the muxed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UartData {
+    pub ch: UartTxChannel,
+    /// The received bytes, with bit 7 cleared and including any trigger bytes in the bit 7
+    /// tagged stream
+    pub data: BytesMut,
+    /// Number of times the capture device reported lost data on the channel
+    pub drops: usize,
+    /// Set when the data starts a new chunk, after a gap on the bus
+    pub chunk: Option<ChunkTimes>,
+    /// The capture device detected a break on the line after the data
+    pub line_break: bool,
+    /// A measurement trigger after the data, from the SLIP framed stream
+    pub trigger: bool,
+}
+
+/// Splits the stream from a capture device in muxed mode into the two channels.
+///
+/// The ctrl bytes have bit 7 set and the node bytes have it cleared. [`TRIG_BYTE`] belongs to the
+/// channel of the surrounding data, and [`DROP_BYTE`] is counted and removed from the data.
+/// The chunk timing records are removed, and attached to the following data on their channel.
+/// A [`BREAK_BYTE`] ends the data before it, which gets the `line_break` flag.
+///
+/// The SLIP framed stream has one entry per data frame, and the drops, breaks and triggers as
+/// entries without data. Frames which can't be decoded are logged and skipped.
+#[derive(Debug, Default)]
+pub struct MuxedStreamDecoder {
+    framing: MuxFraming,
+    buf: BytesMut,
+    frames: FrameDecoder,
+    ctrl_chunk: Option<ChunkTimes>,
+    node_chunk: Option<ChunkTimes>,
+}
+
+impl MuxedStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_framing(framing: MuxFraming) -> Self {
+        Self {
+            framing,
+            ..Self::default()
+        }
+    }
+
+    /// Demultiplex the bytes, one entry per run of bytes from the same channel.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<UartData> {
+        match self.framing {
+            MuxFraming::Bit7 => self.feed_bit7(bytes),
+            MuxFraming::Slip => self.feed_slip(bytes),
+        }
+    }
+
+    fn feed_slip(&mut self, bytes: &[u8]) -> Vec<UartData> {
+        let mut out = vec![];
+        for &b in bytes {
+            let (frame, ctrl) = match self.frames.feed(b) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(err)) => {
+                    warn!("Skipped a frame of the muxed stream: {err}");
+                    continue;
+                }
+            };
+            let (ch, chunk) = match ctrl {
+                true => (UartTxChannel::Ctrl, &mut self.ctrl_chunk),
+                false => (UartTxChannel::Node, &mut self.node_chunk),
+            };
+            let mut entry = UartData {
+                ch,
+                data: BytesMut::new(),
+                drops: 0,
+                chunk: None,
+                line_break: false,
+                trigger: false,
+            };
+            match frame {
+                Frame::Data(data) => {
+                    entry.data.extend_from_slice(data);
+                    entry.chunk = chunk.take();
+                }
+                Frame::Drop => entry.drops = 1,
+                Frame::Break => entry.line_break = true,
+                Frame::Time(times) => {
+                    *chunk = Some(times);
+                    continue;
+                }
+                Frame::Trigger => entry.trigger = true,
+            }
+            out.push(entry);
+        }
+        out
+    }
+
+    fn feed_bit7(&mut self, bytes: &[u8]) -> Vec<UartData> {
+        self.buf.extend_from_slice(bytes);
+        let mut out = vec![];
+        // leading trigger bytes are held until the channel of the following data is known
+        while let Some(pos) = self.buf.iter().position(|&b| b != TRIG_BYTE) {
+            let byte = self.buf[pos];
+            let ch_bit = byte & CTRL_BIT;
+            let ch = match ch_bit == CTRL_BIT {
+                false => UartTxChannel::Node,
+                true => UartTxChannel::Ctrl,
+            };
+            let chunk = match ch {
+                UartTxChannel::Ctrl => &mut self.ctrl_chunk,
+                UartTxChannel::Node => &mut self.node_chunk,
+            };
+            if byte & !CTRL_BIT == TIME_BYTE {
+                if self.buf.len() < pos + TIME_RECORD_LEN {
+                    break; // wait for the rest of the record
+                }
+                let record = self.buf[pos..pos + TIME_RECORD_LEN].try_into().unwrap();
+                *chunk = Some(ChunkTimes::decode(record));
+                let tail = self.buf.split_off(pos);
+                self.buf.unsplit(BytesMut::from(&tail[TIME_RECORD_LEN..]));
+                continue;
+            }
+            let len = self
+                .buf
+                .iter()
+                .take_while(|&&b| {
+                    (b & CTRL_BIT == ch_bit || b == TRIG_BYTE)
+                        && b & !CTRL_BIT != TIME_BYTE
+                        && b & !CTRL_BIT != BREAK_BYTE
+                })
+                .count();
+            let mut data = self.buf.split_to(len);
+            let line_break = self.buf.first() == Some(&(BREAK_BYTE | ch_bit));
+            if line_break {
+                self.buf.advance(1);
+            }
+            data.iter_mut().for_each(|b| *b &= !CTRL_BIT);
+            let drops = data.iter().filter(|&&b| b == DROP_BYTE).count();
+            if drops > 0 {
+                data = data.into_iter().filter(|&b| b != DROP_BYTE).collect();
+            }
+            let chunk = chunk.take();
+            out.push(UartData {
+                ch,
+                data,
+                drops,
+                chunk,
+                line_break,
+                trigger: false,
+            });
+        }
+        out
+    }
+}
+
+/// Parse a duration like "90s", "15m" or "2h", a plain number is seconds
+pub fn parse_duration(arg: &str) -> Result<std::time::Duration> {
+    let (num, unit) = match arg.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => arg.split_at(pos),
+        None => (arg, "s"),
+    };
+    let scale = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => bail!("Unknown time unit '{unit}', expected ms, s, m, h or d."),
+    };
+    let num: f64 = num.trim().parse().context("Invalid duration.")?;
+    std::time::Duration::try_from_secs_f64(num * scale).context("Invalid duration.")
+}
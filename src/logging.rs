@@ -0,0 +1,19 @@
+//! The log filter shared by the binaries.
+//!
+//! `RUST_LOG` takes comma separated `target=level` directives, and a bare level for the targets
+//! which aren't listed, e.g. `RUST_LOG=warn,replay_x328=info`. Filtering on span fields isn't
+//! supported.
+
+use anyhow::{Context, Result};
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+
+/// The filter from `RUST_LOG`, or `default` for all the targets when it isn't set
+pub fn env_filter(default: Level) -> Result<Targets> {
+    match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => directives
+            .parse()
+            .with_context(|| format!("Invalid RUST_LOG directives {directives:?}.")),
+        _ => Ok(Targets::new().with_default(default)),
+    }
+}
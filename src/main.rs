@@ -1,165 +1,1107 @@
 #![allow(dead_code)]
 
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 use bytes::BytesMut;
-use clap::Parser;
-use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
 use tokio::time::timeout;
-use tokio_serial::SerialStream;
-use tracing::{info, trace, Level};
+use tracing::{info, trace, warn, Level};
+use tracing_subscriber::layer::SubscriberExt;
 
-use serial_pcap::{open_async_uart, SerialPacketWriter, UartTxChannel, TRIG_BYTE};
+use serial_pcap::alert::{Alert, AlertRules};
+use serial_pcap::capture::{
+    read_framed_datagrams, read_framed_stream, read_muxed_uart, read_uart, read_usb, DropStats,
+    UartEvent, UartRead, UartSink,
+};
+use serial_pcap::clock::{CaptureClock, ClockModel};
+use serial_pcap::completions::DocCommand;
+use serial_pcap::device::{read_event_log, DevicePorts};
+use serial_pcap::export::{export, AnalyzerFormat, ExportTiming};
+use serial_pcap::extract::extract;
+use serial_pcap::hexdump::{
+    read_hexdump, write_hexdump, HexdumpChannels, HexdumpFormat, HexdumpStyle,
+};
+use serial_pcap::import::{
+    read_analyzer_export, read_raw_index, write_analyzer_bytes, write_raw_bytes, ChannelNames,
+    RawTiming,
+};
+use serial_pcap::influx::{HttpSink, InfluxWriter};
+use serial_pcap::names::NameMap;
+use serial_pcap::plot::{collect_series, write_plot, ParamId, PlotFormat};
+use serial_pcap::pool::{self, BufferPool};
+use serial_pcap::queue::{self, OverflowPolicy, QueueReceiver};
+use serial_pcap::ring::{RingLimits, SegmentRing};
+use serial_pcap::sdlog::convert_sd_log;
+use serial_pcap::status::CaptureStatus;
+use serial_pcap::tee::Tee;
+use serial_pcap::{
+    open_async_uart, open_async_uart_with, parse_duration, CaptureInput, Marker, MarkerKind,
+    MuxFraming, SerialPacket, SerialPacketReader, SerialPacketWriter, Trigger, UartSettings,
+    UartTxChannel,
+};
 
+#[derive(Args, Debug)]
+struct ImportOpts {
+    /// The logic analyzer export file
+    input: String,
+
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+
+    #[clap(long, value_enum, default_value = "saleae")]
+    format: AnalyzerFormat,
+
+    /// Name of the analyzer channel with the bus controller data
+    #[clap(long, default_value = "ctrl")]
+    ctrl_name: String,
+
+    /// Name of the analyzer channel with the bus node data
+    #[clap(long, default_value = "node")]
+    node_name: String,
+
+    /// Sample rate for the sigrok sample numbers
+    #[clap(long, default_value_t = 1_000_000)]
+    samplerate: u64,
+
+    /// Wall clock time at the start of the trace, defaults to the input file modification time
+    #[clap(long, value_name = "RFC3339")]
+    start_time: Option<DateTime<Utc>>,
+
+    /// Idle time between characters that ends a packet, in milliseconds
+    #[clap(long, default_value_t = 5)]
+    gap_ms: u64,
+}
+
+/// Capture UART traffic to a pcap file
 #[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct CmdlineOpts {
-    #[clap(long, value_name = "SERIAL_PORT")]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    capture: CaptureOpts,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert a capture to a logic analyzer export format
+    Convert(ConvertOpts),
+    /// Write the bytes of one channel to a raw binary file
+    Extract(ExtractOpts),
+    /// Write a capture as a timestamped hexdump, e.g. for a bug report
+    Hexdump(HexdumpOpts),
+    /// Create a capture from a logic analyzer UART export
+    Import(ImportOpts),
+    /// Create a capture of one channel from a raw binary file
+    ImportRaw(ImportRawOpts),
+    /// Create a capture from a jpnevulator or interceptty hexdump log
+    ImportHexdump(ImportHexdumpOpts),
+    /// Write the decoded X3.28 parameter values as InfluxDB line protocol
+    Influx(InfluxOpts),
+    /// Chart the values of X3.28 parameters over the capture
+    Plot(PlotOpts),
+    /// Decode a capture with a WASM decoder plugin
+    #[cfg(feature = "wasm")]
+    DecodePlugin(DecodePluginOpts),
+    /// Receive the packets streamed by a capture running with --serve
+    Receive(ReceiveOpts),
+    /// Manage the capture device
+    Device(DeviceOpts),
+    /// Convert a log file from the capture device SD card to pcap
+    SdLog(SdLogOpts),
+    /// Check a capture for structural problems, exits with an error if any are found
+    Validate(ValidateOpts),
+    /// Compare the X3.28 transactions in two captures, exits with an error if they differ
+    Diff(DiffOpts),
+    /// Generate a Wireshark Lua dissector with the names from a parameter name mapping file
+    Dissector(DissectorOpts),
+    /// Rewrite a capture from an older version in the current encoding
+    Fixup(FixupOpts),
+    /// Write a capture of simulated X3.28 traffic, the same for the same options
+    GenerateTestPcap(GenerateOpts),
+    #[clap(flatten)]
+    Doc(DocCommand),
+}
+
+#[derive(Args, Debug)]
+struct GenerateOpts {
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+
+    /// Number of commands sent by the bus controller
+    #[clap(long, default_value_t = 100)]
+    transactions: usize,
+
+    /// Comma separated node addresses, the commands go to each of them in turn
+    #[clap(long, value_delimiter = ',', default_value = "21,31")]
+    nodes: Vec<u8>,
+
+    /// Corrupt the checksum of every Nth reply
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    corrupt_every: usize,
+
+    /// Leave out every Nth reply
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    no_reply_every: usize,
+
+    /// The time of the first command
+    #[clap(long, value_name = "RFC3339", default_value = "2023-11-14T22:13:20Z")]
+    start: DateTime<Utc>,
+}
+
+#[derive(Args, Debug)]
+struct FixupOpts {
+    /// The capture to rewrite, - for stdin
+    pcap_file: String,
+
+    /// The fixed up capture, will be overwritten if it exists
+    output: String,
+}
+
+#[derive(Args, Debug)]
+struct DissectorOpts {
+    /// Parameter name mapping file (TOML or CSV)
+    names: String,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct DiffOpts {
+    /// The capture from before the change
+    before: String,
+
+    /// The capture from after the change
+    after: String,
+
+    /// Print the transactions which are the same in both captures too
+    #[clap(long)]
+    all: bool,
+
+    /// Parameter name mapping file (TOML or CSV)
+    #[clap(long, value_name = "FILE")]
+    names: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ValidateOpts {
+    /// The pcap file to check, - for stdin
+    pcap_file: String,
+}
+
+#[derive(Args, Debug)]
+struct SdLogOpts {
+    /// The log file from the SD card
+    log_file: String,
+
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+
+    /// Wall clock time when the device booted, the timestamps start at the Unix epoch by default
+    #[clap(long, value_name = "RFC3339")]
+    start_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Args, Debug)]
+struct DeviceOpts {
+    #[clap(subcommand)]
+    command: DeviceCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum DeviceCommand {
+    /// Reboot the capture device into the RP2040 bootloader, for firmware updates
+    Dfu(DfuOpts),
+}
+
+#[derive(Args, Debug)]
+struct DfuOpts {
+    /// The command port of the capture device, i.e. the second USB serial port
+    port: String,
+
+    /// Open and close the port at 1200 baud instead of sending the bootsel command
+    #[clap(long)]
+    touch: bool,
+}
+
+#[derive(Args, Debug)]
+struct ReceiveOpts {
+    /// The capture server, as "host:port", or the address to listen on with --listen
+    server: String,
+
+    /// Listen for a capture running with --forward, instead of connecting to a capture server
+    #[clap(long)]
+    listen: bool,
+
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+}
+
+#[derive(Args, Debug)]
+struct ConvertOpts {
+    /// The pcap file to convert, - for stdin
+    pcap_file: String,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long)]
+    output: Option<String>,
+
+    #[clap(long, value_enum, default_value = "sigrok")]
+    format: AnalyzerFormat,
+
+    /// UART baud rate, used for estimating the individual byte times
+    #[clap(long, default_value_t = 9600)]
+    baud: u32,
+
+    /// Sample rate for the sigrok sample numbers
+    #[clap(long, default_value_t = 1_000_000)]
+    samplerate: u64,
+}
+
+#[derive(Args, Debug)]
+struct ExtractOpts {
+    /// The pcap file to extract from, - for stdin
+    pcap_file: String,
+
+    /// The channel to extract
+    #[clap(long, value_enum)]
+    channel: UartTxChannel,
+
+    /// The bus of the channel, for captures of two buses
+    #[clap(long, default_value_t = 0)]
+    bus: u8,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// Also write a CSV index with the offset in the output, the length and the time of each
+    /// packet
+    #[clap(long, value_name = "FILE")]
+    index: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct HexdumpOpts {
+    /// The pcap file to dump, - for stdin
+    pcap_file: String,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// Bytes per line
+    #[clap(long, default_value_t = 16)]
+    width: usize,
+
+    /// Add the printable characters after the bytes
+    #[clap(long)]
+    ascii: bool,
+
+    /// The time zone of the times, e.g. +02:00, defaults to the local time zone
+    #[clap(long, value_name = "OFFSET", allow_hyphen_values = true)]
+    utc_offset: Option<FixedOffset>,
+}
+
+#[derive(Args, Debug)]
+struct ImportRawOpts {
+    /// The raw binary file with the bytes of the channel
+    input: String,
+
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+
+    /// The channel of the bytes
+    #[clap(long, value_enum)]
+    channel: UartTxChannel,
+
+    /// The bus of the channel
+    #[clap(long, default_value_t = 0)]
+    bus: u8,
+
+    /// A CSV index with the offset and the time of each packet, like the one written by extract
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["baud", "start_time"])]
+    index: Option<String>,
+
+    /// Without an index, the bytes are timed as sent back to back at this baud rate, with 10
+    /// bits per character
+    #[clap(long, default_value_t = 9600)]
+    baud: u32,
+
+    /// Wall clock time of the first byte without an index, defaults to the input file
+    /// modification time
+    #[clap(long, value_name = "RFC3339")]
+    start_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Args, Debug)]
+struct ImportHexdumpOpts {
+    /// The hexdump log
+    input: String,
+
+    /// The pcap filename, will be overwritten if it exists
+    pcap_file: String,
+
+    #[clap(long, value_enum, default_value = "jpnevulator")]
+    format: HexdumpFormat,
+
+    /// The device, or the direction for interceptty, with the bus controller data. Defaults to
+    /// the first device in the log, and > for interceptty.
+    #[clap(long)]
+    ctrl_name: Option<String>,
+
+    /// The device, or the direction for interceptty, with the bus node data. Defaults to the
+    /// second device in the log, and < for interceptty.
+    #[clap(long)]
+    node_name: Option<String>,
+
+    /// The time zone of the times in the log, e.g. +02:00, defaults to the local time zone
+    #[clap(long, value_name = "OFFSET", allow_hyphen_values = true)]
+    utc_offset: Option<FixedOffset>,
+
+    /// Idle time between characters that ends a packet, in milliseconds
+    #[clap(long, default_value_t = 5)]
+    gap_ms: u64,
+}
+
+#[cfg(feature = "wasm")]
+#[derive(Args, Debug)]
+struct DecodePluginOpts {
+    /// The decoder plugin, a .wasm or .wat file
+    plugin: String,
+
+    /// The pcap file to decode, - for stdin
+    pcap_file: String,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct InfluxOpts {
+    /// The pcap file to decode, - for stdin
+    pcap_file: String,
+
+    /// Output file, defaults to stdout
+    #[clap(short, long, conflicts_with = "url")]
+    output: Option<String>,
+
+    /// POST the records to this InfluxDB write endpoint,
+    /// e.g. "http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET"
+    #[clap(long)]
+    url: Option<String>,
+
+    /// API token for the write endpoint
+    #[clap(long, env = "INFLUX_TOKEN", requires = "url")]
+    token: Option<String>,
+
+    #[clap(long, default_value = "x328")]
+    measurement: String,
+
+    /// Parameter name mapping file (TOML or CSV), adds name and unit tags
+    #[clap(long, value_name = "FILE")]
+    names: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct PlotOpts {
+    /// The pcap file to decode, - for stdin
+    pcap_file: String,
+
+    /// A parameter to chart, e.g. "31:401". Can be repeated, each gets its own panel.
+    #[clap(short, long = "param", value_name = "ADDR:PARAM", required = true)]
+    params: Vec<ParamId>,
+
+    /// Output file, defaults to stdout. The format is taken from the extension when not given.
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// The output format, SVG by default
+    #[clap(long, value_enum)]
+    format: Option<PlotFormat>,
+
+    /// Parameter name mapping file (TOML or CSV), for the names and units in the charts
+    #[clap(long, value_name = "FILE")]
+    names: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct CaptureOpts {
+    #[clap(long, value_name = "SERIAL_PORT", required_unless_present_any = ["pty", "usb", "port", "wifi", "wifi_udp", "device"])]
     /// One side of the UART
-    ctrl: String,
+    ctrl: Option<String>,
 
     /// The other side of the UART
     #[clap(long, value_name = "SERIAL_PORT")]
     node: Option<String>,
 
+    /// Line settings of the ctrl UART, e.g. "19200,8N1"
+    #[clap(long, value_name = "BAUD,FORMAT", default_value = "9600,7E1",
+        conflicts_with_all = ["muxed", "pty", "usb", "port", "wifi", "wifi_udp"])]
+    ctrl_settings: UartSettings,
+
+    /// Line settings of the node UART, if they differ from the ctrl UART, e.g. when the node
+    /// side is behind a converter which re-clocks the data
+    #[clap(long, value_name = "BAUD,FORMAT", conflicts_with_all = ["muxed", "pty", "usb", "port", "wifi", "wifi_udp"])]
+    node_settings: Option<UartSettings>,
+
     /// The ctrl and node bytes are received on the same UART, with the node bytes having MSB set high.
     #[clap(long = "muxed-stream")]
     muxed: bool,
 
+    /// The capture device's third serial port, with the muxed stream from the PIO UARTs
+    /// tapping a second bus. Recorded as bus 1 in the pcap.
+    #[clap(long, value_name = "SERIAL_PORT", requires = "muxed")]
+    bus2: Option<String>,
+
+    /// Framing of the muxed stream, as set with `set framing` on the capture device. SLIP
+    /// frames carry 8 bit data, e.g. from an 8N1 bus.
+    #[clap(long, value_enum, default_value = "bit7")]
+    muxed_framing: MuxFraming,
+
+    /// Show the decoded X3.28 traffic in a terminal UI while capturing
+    #[clap(long)]
+    tui: bool,
+
+    /// Run as a systemd notify service: report ready when the first packet is written, and
+    /// the byte counters in the service status
+    #[clap(long, conflicts_with = "tui")]
+    daemon: bool,
+
+    /// Parameter name mapping file (TOML or CSV), used by the terminal UI and the WebSocket
+    /// messages
+    #[clap(long, value_name = "FILE")]
+    names: Option<String>,
+
+    /// Publish the decoded X3.28 parameter values to this MQTT broker, as "host[:port]"
+    #[cfg(feature = "mqtt")]
+    #[clap(long, value_name = "BROKER")]
+    mqtt: Option<String>,
+
+    /// Topic prefix for the MQTT values, which are published to "<PREFIX>/<addr>/<param>"
+    #[cfg(feature = "mqtt")]
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        default_value = "serial-pcap",
+        requires = "mqtt"
+    )]
+    mqtt_topic: String,
+
+    /// Publish the MQTT values with the retain flag set
+    #[cfg(feature = "mqtt")]
+    #[clap(long, requires = "mqtt")]
+    mqtt_retain: bool,
+
+    /// Seconds to add to the capture timestamps, e.g. the measured offset of the system clock
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0.0,
+        allow_hyphen_values = true
+    )]
+    clock_offset: f64,
+
+    /// Rate error of the system clock in ppm, positive if it runs fast
+    #[clap(
+        long,
+        value_name = "PPM",
+        default_value_t = 0.0,
+        allow_hyphen_values = true
+    )]
+    clock_drift: f64,
+
+    /// Align the capture timestamps to the pulses from this PPS device, e.g. "/dev/pps0"
+    #[clap(long, value_name = "DEVICE")]
+    pps: Option<String>,
+
+    /// Stream the captured packets in pcap format to TCP clients connecting to this address,
+    /// e.g. "0.0.0.0:2422"
+    #[clap(long, value_name = "ADDR")]
+    serve: Option<String>,
+
+    /// Stream the captured packets in pcap format to the TCP server at this address, e.g. a
+    /// "serial-pcap receive --listen" on another machine. Reconnects when the connection fails.
+    #[clap(long, value_name = "HOST:PORT")]
+    forward: Option<String>,
+
+    /// Send the captured data as UDP datagrams, so Wireshark or tcpdump can capture it from a
+    /// network interface. The default 127.0.0.1 uses the addresses of the pcap packets on
+    /// loopback, another address gets all the datagrams.
+    #[clap(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "127.0.0.1"
+    )]
+    udp_mirror: Option<std::net::Ipv4Addr>,
+
+    /// Show the captured packets in tshark, which must be installed, while capturing
+    #[clap(long, conflicts_with_all = ["tui", "daemon"])]
+    tshark: bool,
+
+    /// Pass this argument to tshark, e.g. "-Xlua_script:x328-names.lua" to load the X3.28
+    /// dissector. Can be repeated.
+    #[clap(
+        long,
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        requires = "tshark"
+    )]
+    tshark_arg: Vec<String>,
+
+    /// Create two connected virtual serial ports and capture the traffic between them,
+    /// instead of opening real UARTs.
+    #[clap(long, conflicts_with_all = ["ctrl", "node", "muxed"])]
+    pty: bool,
+
+    /// Read the framed capture records from the capture device's vendor USB interface,
+    /// instead of the muxed stream on its serial port. Requires the usb feature.
+    #[clap(long, conflicts_with_all = ["ctrl", "node", "muxed", "pty"])]
+    usb: bool,
+
+    /// Capture a UART as its own channel, e.g. "--port meter=/dev/ttyUSB2@19200,8N1", the
+    /// line settings default to 9600,7E1. Can be repeated, the ports are recorded as the ctrl
+    /// and node channels of bus 0, 1, 2 and so on.
+    #[clap(long, value_name = "NAME=SERIAL_PORT[@BAUD,FORMAT]", value_parser = parse_port,
+        conflicts_with_all = ["ctrl", "node", "muxed", "pty", "usb"])]
+    port: Vec<PortArg>,
+
+    /// Read the framed capture records from the Pico W capture firmware over TCP, instead of
+    /// from USB, e.g. "--wifi 192.168.1.50:4222"
+    #[clap(long, value_name = "HOST:PORT",
+        conflicts_with_all = ["ctrl", "node", "muxed", "pty", "usb", "port"])]
+    wifi: Option<String>,
+
+    /// Receive the framed capture records from the Pico W capture firmware as UDP datagrams on
+    /// this address, e.g. "0.0.0.0:4222". Lost datagrams aren't detected.
+    #[clap(long, value_name = "ADDR",
+        conflicts_with_all = ["ctrl", "node", "muxed", "pty", "usb", "port", "wifi"])]
+    wifi_udp: Option<String>,
+
+    /// Find the capture device's USB serial ports by its VID:PID, record the muxed stream of the
+    /// first port, and the second bus from the third port if there is one. The event log of the
+    /// second port is printed with the capture timestamps, to match it with the packets.
+    #[clap(long, conflicts_with_all = ["ctrl", "node", "muxed", "bus2", "pty", "usb", "port", "wifi", "wifi_udp"])]
+    device: bool,
+
+    /// Also append the event log of --device to this file
+    #[clap(long, value_name = "FILE", requires = "device")]
+    event_log: Option<String>,
+
+    /// Max number of UART reads queued for writing to the pcap file
+    #[clap(long, value_name = "READS", default_value_t = 4096,
+           value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    queue_size: usize,
+
+    /// What to do when the queue is full, e.g. because the disk stalls
+    #[clap(long, value_enum, default_value = "block")]
+    overflow: OverflowPolicy,
+
+    /// Append to the pcap file if it exists, instead of overwriting it
+    #[clap(long)]
+    append: bool,
+
+    /// Also write the capture to this file, e.g. a pcapng copy of a pcap capture, or a copy on
+    /// another disk. Can be repeated. Each copy has the packets and markers of the capture file.
+    #[clap(long, value_name = "FILE")]
+    tee: Vec<String>,
+
+    /// Stop the capture after this time, e.g. "90s", "15m" or "2h", plain numbers are seconds
+    #[clap(long, value_name = "TIME", value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// Stop the capture when this many packets, including the markers, have been written
+    #[clap(long, value_name = "PACKETS")]
+    max_packets: Option<u64>,
+
+    /// Stop the capture when the file has grown by this many bytes, e.g. "500K", "20M" or "1G"
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Keep only the last SIZE bytes of the capture, e.g. "500M", in numbered segment files
+    /// next to the pcap file
+    #[clap(long, value_name = "SIZE", value_parser = parse_size,
+        conflicts_with_all = ["append", "max_size", "max_packets"])]
+    ring_size: Option<u64>,
+
+    /// Keep only the last TIME of the capture, e.g. "30m" or "7d", in numbered segment files
+    /// next to the pcap file
+    #[clap(long, value_name = "TIME", value_parser = parse_duration,
+        conflicts_with_all = ["append", "max_size", "max_packets"])]
+    ring_time: Option<Duration>,
+
+    /// Log a status line with the byte and packet counts and the file size at this interval,
+    /// 0 disables it. Not shown with --tui
+    #[clap(long, value_name = "TIME", value_parser = parse_duration, default_value = "60s")]
+    status_interval: Duration,
+
+    /// Answer GET /status requests on this address with the capture status as JSON, e.g.
+    /// "127.0.0.1:8422"
+    #[clap(long, value_name = "ADDR")]
+    http: Option<String>,
+
+    /// Stream the decoded transactions and the markers as JSON messages to WebSocket clients
+    /// connecting to this address, e.g. "0.0.0.0:8423"
+    #[clap(long, value_name = "ADDR")]
+    websocket: Option<String>,
+
+    /// Serve the decoded transactions, and on request the raw UART packets, to gRPC clients
+    /// connecting to this address, e.g. "0.0.0.0:8424"
+    #[cfg(feature = "grpc")]
+    #[clap(long, value_name = "ADDR")]
+    grpc: Option<String>,
+
+    /// Evaluate the alert rules in this file on the decoded traffic, and log and mark the
+    /// alerts in the capture
+    #[clap(long, value_name = "FILE")]
+    rules: Option<String>,
+
     /// The pcap filename, will be overwritten if it exists
-    pcap_file: String,
+    #[clap(required = true)]
+    pcap_file: Option<String>,
+}
+
+#[cfg(unix)]
+async fn read_pty(tx: UartSink) -> Result<()> {
+    let pty = serial_pcap::pty::PtyPair::new()?;
+    println!("ctrl: {}", pty.ctrl_path());
+    println!("node: {}", pty.node_path());
+    pty.run(async move |ch_name, data| {
+        tx.send(UartRead {
+            bus: 0,
+            ch_name,
+            data: BytesMut::from(data),
+            time_received: tx.clock.now(),
+            chunk_start: false,
+            event: None,
+        })
+        .await
+        .context("Stream recorder stopped.")
+    })
+    .await
 }
 
-#[derive(Debug)]
-struct UartData {
-    ch_name: UartTxChannel,
-    data: BytesMut,
-    time_received: std::time::SystemTime,
+#[cfg(not(unix))]
+async fn read_pty(_tx: UartSink) -> Result<()> {
+    bail!("Virtual serial ports are only supported on unix.")
 }
 
-#[tracing::instrument(skip(uart, tx))]
-async fn read_uart(
-    mut uart: SerialStream,
-    ch_name: UartTxChannel,
-    tx: UnboundedSender<UartData>,
+/// Write the buffered data as a packet, and pass it on to the sinks
+fn write_buffered<W: std::io::Write>(
+    writer: &mut SerialPacketWriter<W>,
+    tee: &mut Tee,
+    buf: &mut BytesMut,
+    bus: u8,
+    ch: UartTxChannel,
+    time: std::time::SystemTime,
 ) -> Result<()> {
-    let mut buf = BytesMut::with_capacity(1);
-    loop {
-        buf.reserve(1);
-        match uart.read_buf(&mut buf).await {
-            Ok(0) => {
-                info!("Zero length read");
-                bail!("Read from {ch_name:?} returned 0 bytes.");
-            }
-            Ok(len) => {
-                trace!("Received {len} bytes.");
-                tx.send(UartData {
-                    ch_name,
-                    data: buf.split(),
-                    time_received: std::time::SystemTime::now(),
-                })?;
-            }
-            err => {
-                info!("UART read returned with error {err:?}");
-                err.with_context(|| format!("Read error from UART '{ch_name:?}'."))?;
-            }
-        }
-    }
-}
-
-async fn read_muxed_uart(mut uart: SerialStream, tx: UnboundedSender<UartData>) -> Result<()> {
-    let mut buf = BytesMut::with_capacity(1);
-    'read: loop {
-        buf.reserve(1);
-        match uart.read_buf(&mut buf).await {
-            Ok(0) => {
-                info!("Zero length read");
-                bail!("Read from muxed uart returned 0 bytes.");
-            }
-            Ok(_len) => {
-                let time_received = std::time::SystemTime::now();
-                // trace!("Received {_len} bytes.");
-                while !buf.is_empty() {
-                    let Some(byte) = buf.iter().find(|&&b| b != TRIG_BYTE) else {
-                        continue 'read;
-                    };
-                    let ch = *byte & 0x80;
-                    let ch_name = match ch == 0x80 {
-                        false => UartTxChannel::Node,
-                        true => UartTxChannel::Ctrl,
-                    };
-
-                    // \n == Trigger event
-                    let l = buf
-                        .iter()
-                        .take_while(|&b| b & 0x80 == ch || *b == TRIG_BYTE)
-                        .count();
-                    let mut data = buf.split_to(l);
-                    if data.as_ref().contains(&TRIG_BYTE) {
-                        info!("Trigger found in data stream");
+    tokio::task::block_in_place(|| writer.write_bus_packet_time(bus, buf.as_ref(), ch, time))
+        .context("write_packet_time() returned an error.")?;
+    // the sinks get a copy, so the buffer is reused for the next packet
+    if !tee.is_empty() {
+        tee.send_packet(SerialPacket {
+            bus,
+            ch,
+            data: BytesMut::from(&buf[..]),
+            time: time.into(),
+        });
+    }
+    buf.clear();
+    Ok(())
+}
+
+/// Write a trigger, or a line break marker
+fn write_event<W: std::io::Write>(
+    writer: &mut SerialPacketWriter<W>,
+    tee: &mut Tee,
+    bus: u8,
+    ch: UartTxChannel,
+    event: UartEvent,
+    time: std::time::SystemTime,
+) -> Result<()> {
+    if let UartEvent::Trigger(source) = event {
+        let trigger = Trigger::new(bus, source, time.into());
+        info!("Trigger from {}", trigger.source);
+        tokio::task::block_in_place(|| {
+            writer.write_trigger(&trigger)?;
+            writer.flush()
+        })?;
+        tee.send_trigger(&trigger);
+        return Ok(());
+    }
+    let marker = Marker::line_break(bus, ch, time.into());
+    info!("Marker: {}", marker.label);
+    tokio::task::block_in_place(|| {
+        writer.write_marker(&marker)?;
+        writer.flush()
+    })?;
+    tee.send_marker(&marker);
+    Ok(())
+}
+
+fn write_drop_markers<W: std::io::Write>(
+    writer: &mut SerialPacketWriter<W>,
+    tee: &mut Tee,
+    drops: &DropStats,
+) -> Result<()> {
+    for marker in drops.take_markers() {
+        warn!("Data lost on {:?}: {}", marker.ch.unwrap(), marker.label);
+        tokio::task::block_in_place(|| writer.write_marker(&marker))
+            .context("write_marker() returned an error.")?;
+        tee.send_marker(&marker);
+    }
+    Ok(())
+}
+
+/// Stops the capture when the pcap file has reached the size or packet count limit
+#[derive(Default)]
+struct CaptureLimits {
+    max_packets: Option<u64>,
+    max_size: Option<u64>,
+    reached: Arc<tokio::sync::Notify>,
+}
+
+impl CaptureLimits {
+    fn is_reached<W: std::io::Write>(&self, writer: &SerialPacketWriter<W>) -> bool {
+        self.max_packets
+            .is_some_and(|max| writer.packets_written() >= max)
+            || self
+                .max_size
+                .is_some_and(|max| writer.bytes_written() >= max)
+    }
+}
+
+/// Input to the stream recorder, `Data(None)` when all the readers have stopped
+enum RecorderInput {
+    Data(Option<UartRead>),
+    Marker(Marker),
+}
+
+/// Where the stream recorder gets its input from
+struct RecorderInputs {
+    data: QueueReceiver<UartRead>,
+    marks: UnboundedReceiver<Marker>,
+    /// Takes back the read buffers once the data is copied out
+    pool: BufferPool,
+    /// A marker waiting for the data read before it
+    marker: Option<Marker>,
+    /// A read taken from the queue which is newer than the marker
+    read: Option<UartRead>,
+}
+
+impl RecorderInputs {
+    /// The next read or marker. The data and the markers come on separate channels, so the
+    /// reads queued before a marker are returned first, and a pause takes effect exactly at
+    /// the time of its marker.
+    async fn next(&mut self) -> RecorderInput {
+        loop {
+            if let Some(marker) = self.marker.take() {
+                let read = self.read.take().or_else(|| self.data.try_recv());
+                match read {
+                    Some(read) if read.time_received <= marker.time.into() => {
+                        self.marker = Some(marker);
+                        return RecorderInput::Data(Some(read));
+                    }
+                    read => {
+                        self.read = read;
+                        return RecorderInput::Marker(marker);
                     }
-                    data.iter_mut().for_each(|b| *b &= 0x7f); // clear bit 8
-                    tx.send(UartData {
-                        ch_name,
-                        data,
-                        time_received,
-                    })?;
                 }
             }
-            err => {
-                info!("UART read returned with error {err:?}");
-                err.with_context(|| "Read error from muxed UART.".to_string())?;
+            if let Some(read) = self.read.take() {
+                return RecorderInput::Data(Some(read));
+            }
+            tokio::select! {
+                biased;
+                Some(marker) = self.marks.recv() => self.marker = Some(marker),
+                msg = self.data.recv() => return RecorderInput::Data(msg),
             }
         }
     }
 }
 
 #[tracing::instrument(skip_all)]
-async fn record_streams<W: std::io::Write>(
-    mut writer: SerialPacketWriter<W>,
-    mut rx: UnboundedReceiver<UartData>,
+async fn record_streams(
+    mut writer: SerialPacketWriter<std::fs::File>,
+    mut ring: Option<SegmentRing>,
+    mut inputs: RecorderInputs,
+    mut tee: Tee,
+    drops: Arc<DropStats>,
+    limits: CaptureLimits,
 ) -> Result<()> {
+    let mut prev_bus = 0;
     let mut prev_ch = UartTxChannel::Node;
-    let mut buf = BytesMut::new();
+    let mut buf = BytesMut::with_capacity(pool::BUFFER_LEN);
     let mut time = std::time::SystemTime::now();
-    let read_timeout = Duration::from_millis(5);
+    // without chunk timing from the device, a 5 ms gap ends a packet
+    let mut read_timeout = Duration::from_millis(5);
+    let mut paused = false;
+    // bytes discarded while paused
+    let mut skipped = 0;
+    // the limit was reached, the rest is discarded until the readers have stopped
+    let mut full = false;
 
     trace!("Stream recorder running");
     loop {
-        let msg = if !buf.is_empty() {
-            let r = timeout(read_timeout, rx.recv()).await;
-            if r.is_err() || matches!(r, Ok(Some(UartData{ch_name, ref data, ..})) if ch_name != prev_ch || data[0] == 0x04 ) {
-                tokio::task::block_in_place(|| {
-                    writer.write_packet_time(buf.as_ref(), prev_ch, time)
-                })
-                .context("write_packet_time() returned an error.")?;
-                buf = BytesMut::new();
+        if !full && limits.is_reached(&writer) {
+            info!("Capture limit reached, stopping.");
+            tokio::task::block_in_place(|| writer.flush())?;
+            limits.reached.notify_one();
+            full = true;
+            buf.clear();
+        }
+        if let Some(ring) = &mut ring {
+            let now = std::time::Instant::now();
+            tokio::task::block_in_place(|| ring.rotate_if_due(&mut writer, now))?;
+        }
+        let next = inputs.next();
+        let input = if !buf.is_empty() {
+            let r = timeout(read_timeout, next).await;
+            if matches!(
+                r,
+                Err(_) | Ok(RecorderInput::Data(None) | RecorderInput::Marker(_))
+            ) || matches!(r, Ok(RecorderInput::Data(Some(UartRead{bus, ch_name, ref data, chunk_start, ref event, ..}))) if event.is_some() || ch_name != prev_ch || bus != prev_bus || chunk_start || data[0] == 0x04 )
+            {
+                write_buffered(&mut writer, &mut tee, &mut buf, prev_bus, prev_ch, time)?;
             }
             match r {
                 Ok(msg) => msg,
-                Err(_) => continue,
+                Err(_) => {
+                    // the bus is idle, make the data available to readers of the file
+                    tokio::task::block_in_place(|| writer.flush())?;
+                    continue;
+                }
             }
         } else {
-            rx.recv().await
+            next.await
+        };
+        let msg = match input {
+            RecorderInput::Data(msg) => msg,
+            RecorderInput::Marker(_) if full => continue,
+            RecorderInput::Marker(mut marker) => {
+                match marker.kind {
+                    MarkerKind::Pause => paused = true,
+                    MarkerKind::Resume => {
+                        paused = false;
+                        marker.label += &format!(", {skipped} bytes not recorded");
+                        skipped = 0;
+                    }
+                    _ => {}
+                }
+                info!("Marker: {}", marker.label);
+                tokio::task::block_in_place(|| {
+                    writer.write_marker(&marker)?;
+                    writer.flush()
+                })?;
+                tee.send_marker(&marker);
+                continue;
+            }
         };
+        // the lost data was queued before the data in msg
+        if !full {
+            write_drop_markers(&mut writer, &mut tee, &drops)?;
+        }
 
         // destructure the received message, or stop if the tx side is closed
-        let Some(UartData {
+        let Some(UartRead {
+            bus,
             ch_name,
             data,
             time_received,
+            chunk_start,
+            event,
         }) = msg
         else {
-            return Ok(());
+            return tokio::task::block_in_place(|| writer.flush());
         };
+        if full {
+            continue;
+        }
+        if let Some(event) = event {
+            write_event(&mut writer, &mut tee, bus, ch_name, event, time_received)?;
+            continue;
+        }
+        if paused {
+            skipped += data.len();
+            inputs.pool.put(data);
+            continue;
+        }
+        if chunk_start {
+            // the device marks the packet boundaries, the timeout only flushes when idle
+            read_timeout = Duration::from_millis(100);
+        }
         if buf.is_empty() {
             time = time_received;
+            prev_bus = bus;
             prev_ch = ch_name;
-            buf = data;
-        } else {
-            buf.unsplit(data);
+        }
+        buf.extend_from_slice(&data);
+        inputs.pool.put(data);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn start_pps(path: &str, clock: CaptureClock) -> Result<()> {
+    let pps = serial_pcap::pps::PpsSource::open(path)?;
+    std::thread::Builder::new()
+        .name("pps".into())
+        .spawn(move || {
+            if let Err(e) = pps.run(clock) {
+                warn!("PPS timestamping stopped: {e:#}");
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_pps(_path: &str, _clock: CaptureClock) -> Result<()> {
+    bail!("PPS devices are only supported on Linux.")
+}
+
+/// Creates the operator markers, numbered in the order they are set
+#[derive(Clone)]
+struct MarkSender {
+    tx: UnboundedSender<Marker>,
+    clock: CaptureClock,
+    count: Arc<AtomicU64>,
+}
+
+impl MarkSender {
+    /// Queue a marker for the recorder, an empty label is replaced by the marker number.
+    fn mark(&self, label: String) {
+        let n = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        let label = match label.is_empty() {
+            true => format!("mark {n}"),
+            false => label,
+        };
+        let _ = self.tx.send(Marker {
+            kind: MarkerKind::User,
+            ch: None,
+            label,
+            time: self.clock.now().into(),
+        });
+    }
+
+    /// Record that the capture device went away or came back
+    fn outage(&self, label: String) {
+        let _ = self.tx.send(Marker {
+            kind: MarkerKind::Outage,
+            ch: None,
+            label,
+            time: self.clock.now().into(),
+        });
+    }
+
+    /// Record that an alert rule fired
+    fn alert(&self, alert: &Alert) {
+        let _ = self.tx.send(Marker {
+            kind: MarkerKind::Alert,
+            ch: None,
+            label: alert.to_string(),
+            time: alert.time,
+        });
+    }
+
+    /// Pause or resume the recording, the recorder acts on the marker when it arrives.
+    fn pause(&self, paused: bool) {
+        let (kind, label) = match paused {
+            true => (MarkerKind::Pause, "capture paused"),
+            false => (MarkerKind::Resume, "capture resumed"),
+        };
+        let _ = self.tx.send(Marker {
+            kind,
+            ch: None,
+            label: label.into(),
+            time: self.clock.now().into(),
+        });
+    }
+}
+
+/// Write a marker when the process receives SIGUSR1, and pause or resume
+/// the capture on SIGUSR2.
+#[cfg(unix)]
+async fn handle_user_signals(marks: MarkSender) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut usr1 = signal(SignalKind::user_defined1()).context("Failed to handle SIGUSR1.")?;
+    let mut usr2 = signal(SignalKind::user_defined2()).context("Failed to handle SIGUSR2.")?;
+    let mut paused = false;
+    loop {
+        tokio::select! {
+            Some(()) = usr1.recv() => marks.mark(String::new()),
+            Some(()) = usr2.recv() => {
+                paused = !paused;
+                marks.pause(paused);
+            }
+            else => return Ok(()),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn handle_user_signals(_marks: MarkSender) -> Result<()> {
+    Ok(())
+}
+
+/// Wait for ctrl-c, or SIGTERM from a service manager
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = signal(SignalKind::terminate()).context("Failed to handle SIGTERM.")?;
+    tokio::select! {
+        r = tokio::signal::ctrl_c() => r.context("ctrl-c handler failed."),
+        _ = term.recv() => {
+            info!("Stopping on SIGTERM.");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c()
+        .await
+        .context("ctrl-c handler failed.")
+}
+
+/// Report the capture status to systemd, from the packets passed to a sink of the tee
+#[cfg(unix)]
+fn start_daemon_status(tee: &mut Tee, drops: Arc<DropStats>) -> Result<()> {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        warn!("NOTIFY_SOCKET isn't set, the service manager won't be notified.");
+    }
+    let packets = tee.packets();
+    std::thread::Builder::new()
+        .name("sd-notify".into())
+        .spawn(move || serial_pcap::systemd::run_status(packets, drops, Duration::from_secs(10)))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn start_daemon_status(_tee: &mut Tee, _drops: Arc<DropStats>) -> Result<()> {
+    bail!("Daemon mode is only supported on unix.")
+}
+
+/// Log the drop counters periodically, when data has been lost
+async fn report_drops(drops: Arc<DropStats>) {
+    let mut reported = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let total = drops.total_events();
+        if total != reported {
+            warn!("Data lost during capture, {}", drops.summary());
+            reported = total;
         }
     }
 }
@@ -172,38 +1114,1003 @@ async fn await_task<E: Into<anyhow::Error>>(handle: &mut JoinHandle<Result<(), E
     }
 }
 
+fn convert(args: ConvertOpts) -> Result<()> {
+    let mut reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    let timing = ExportTiming {
+        baud: args.baud,
+        samplerate: args.samplerate,
+        ..Default::default()
+    };
+    match args.output {
+        Some(filename) => {
+            let file = std::fs::File::create(&filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            export(
+                &mut reader,
+                std::io::BufWriter::new(file),
+                args.format,
+                timing,
+            )
+        }
+        None => export(&mut reader, std::io::stdout().lock(), args.format, timing),
+    }
+}
+
+fn hexdump(args: HexdumpOpts) -> Result<()> {
+    let mut reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    let style = HexdumpStyle {
+        width: args.width,
+        ascii: args.ascii,
+        utc_offset: args.utc_offset,
+    };
+    match args.output {
+        Some(filename) => {
+            let file = std::fs::File::create(&filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            write_hexdump(&mut reader, std::io::BufWriter::new(file), style)
+        }
+        None => write_hexdump(&mut reader, std::io::stdout().lock(), style),
+    }
+}
+
+fn extract_channel(args: ExtractOpts) -> Result<()> {
+    let mut reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    let mut index = match &args.index {
+        Some(filename) => Some(std::io::BufWriter::new(
+            std::fs::File::create(filename)
+                .with_context(|| format!("Failed to create {filename}."))?,
+        )),
+        None => None,
+    };
+    let index = index.as_mut().map(|w| w as &mut dyn std::io::Write);
+    let (bus, ch) = (args.bus, args.channel);
+    match args.output {
+        Some(filename) => {
+            let file = std::fs::File::create(&filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            extract(&mut reader, bus, ch, std::io::BufWriter::new(file), index)?;
+        }
+        None => {
+            extract(&mut reader, bus, ch, std::io::stdout().lock(), index)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "wasm")]
+fn decode_plugin(args: DecodePluginOpts) -> Result<()> {
+    use serial_pcap::plugin::{decode_with_plugin, DecoderPlugin};
+    let mut plugin = DecoderPlugin::load(&args.plugin)?;
+    let mut reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    match args.output {
+        Some(filename) => {
+            let file = std::fs::File::create(&filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            decode_with_plugin(&mut reader, &mut plugin, std::io::BufWriter::new(file))?;
+        }
+        None => {
+            decode_with_plugin(&mut reader, &mut plugin, std::io::stdout().lock())?;
+        }
+    }
+    Ok(())
+}
+
+fn load_names(filename: Option<&str>) -> Result<NameMap> {
+    filename.map_or(Ok(NameMap::default()), NameMap::from_file)
+}
+
+fn plot(args: PlotOpts) -> Result<()> {
+    let mut reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    let names = load_names(args.names.as_deref())?;
+    let series = collect_series(&mut reader, &args.params, &names)?;
+    let format = args
+        .format
+        .or_else(|| PlotFormat::from_filename(args.output.as_deref()?))
+        .unwrap_or(PlotFormat::Svg);
+    match (&args.output, format) {
+        (Some(filename), PlotFormat::Png) => {
+            write_plot(&series, format, std::io::sink(), Some(filename))
+        }
+        (Some(filename), _) => {
+            let file = std::fs::File::create(filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            write_plot(&series, format, std::io::BufWriter::new(file), None)
+        }
+        (None, _) => write_plot(&series, format, std::io::stdout().lock(), None),
+    }
+}
+
+fn influx(args: InfluxOpts) -> Result<()> {
+    fn write_all<W: std::io::Write>(
+        reader: SerialPacketReader<CaptureInput>,
+        mut writer: InfluxWriter<W>,
+    ) -> Result<()> {
+        for pkt in reader {
+            writer.feed(&pkt?)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+    let reader = SerialPacketReader::from_file(&args.pcap_file)?;
+    let names = load_names(args.names.as_deref())?;
+    if let Some(url) = args.url {
+        let sink = HttpSink::new(url, args.token);
+        return write_all(reader, InfluxWriter::new(sink, args.measurement, names));
+    }
+    match args.output {
+        Some(filename) => {
+            let file = std::fs::File::create(&filename)
+                .with_context(|| format!("Failed to create {filename}."))?;
+            let out = std::io::BufWriter::new(file);
+            write_all(reader, InfluxWriter::new(out, args.measurement, names))
+        }
+        None => write_all(
+            reader,
+            InfluxWriter::new(std::io::stdout().lock(), args.measurement, names),
+        ),
+    }
+}
+
+fn import(args: ImportOpts) -> Result<()> {
+    let file = std::fs::File::open(&args.input)
+        .with_context(|| format!("Failed to open {}.", args.input))?;
+    let start_time = match args.start_time {
+        Some(t) => t.into(),
+        None => file.metadata()?.modified()?,
+    };
+    let names = ChannelNames {
+        ctrl: args.ctrl_name,
+        node: args.node_name,
+    };
+    let bytes = read_analyzer_export(
+        std::io::BufReader::new(file),
+        args.format,
+        &names,
+        args.samplerate,
+    )?;
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    write_analyzer_bytes(
+        &bytes,
+        &mut writer,
+        start_time,
+        Duration::from_millis(args.gap_ms),
+    )?;
+    writer.flush()
+}
+
+fn import_raw(args: ImportRawOpts) -> Result<()> {
+    let data =
+        std::fs::read(&args.input).with_context(|| format!("Failed to read {}.", args.input))?;
+    let timing = match &args.index {
+        Some(filename) => {
+            let file = std::fs::File::open(filename)
+                .with_context(|| format!("Failed to open {filename}."))?;
+            read_raw_index(std::io::BufReader::new(file))
+                .with_context(|| format!("Failed to read {filename}."))?
+        }
+        None => {
+            if args.baud == 0 {
+                bail!("The baud rate must be above 0.");
+            }
+            let start = match args.start_time {
+                Some(t) => t.into(),
+                None => std::fs::metadata(&args.input)?.modified()?,
+            };
+            RawTiming::Rate {
+                start,
+                byte_duration: Duration::from_secs(10) / args.baud,
+            }
+        }
+    };
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    write_raw_bytes(&data, &timing, args.bus, args.channel, &mut writer)?;
+    writer.flush()
+}
+
+fn import_hexdump(args: ImportHexdumpOpts) -> Result<()> {
+    let file = std::fs::File::open(&args.input)
+        .with_context(|| format!("Failed to open {}.", args.input))?;
+    let channels = HexdumpChannels {
+        ctrl: args.ctrl_name,
+        node: args.node_name,
+    };
+    let log = read_hexdump(
+        std::io::BufReader::new(file),
+        args.format,
+        channels,
+        args.utc_offset,
+    )
+    .with_context(|| format!("Failed to read {}.", args.input))?;
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    write_analyzer_bytes(
+        &log.bytes,
+        &mut writer,
+        log.start,
+        Duration::from_millis(args.gap_ms),
+    )?;
+    writer.flush()
+}
+
+async fn receive(args: ReceiveOpts) -> Result<()> {
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    let received = async {
+        match args.listen {
+            true => {
+                let listener = tokio::net::TcpListener::bind(&args.server)
+                    .await
+                    .with_context(|| format!("Failed to listen on {}.", args.server))?;
+                info!("Waiting for a capture on {}.", listener.local_addr()?);
+                serial_pcap::remote::receive_forwarded(listener, &mut writer).await
+            }
+            false => serial_pcap::remote::receive(&args.server, &mut writer).await,
+        }
+    };
+    tokio::select! {
+        r = received => r?,
+        r = tokio::signal::ctrl_c() => r.context("ctrl-c handler failed.")?,
+    }
+    writer.flush()
+}
+
+fn sd_log(args: SdLogOpts) -> Result<()> {
+    let file = std::fs::File::open(&args.log_file)
+        .with_context(|| format!("Failed to open {}.", args.log_file))?;
+    let start_time = args.start_time.map_or(std::time::UNIX_EPOCH, Into::into);
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    let packets = convert_sd_log(std::io::BufReader::new(file), &mut writer, start_time)?;
+    println!("Converted {packets} packets.");
+    writer.flush()
+}
+
+fn validate(args: ValidateOpts) -> Result<()> {
+    let input = CaptureInput::open(&args.pcap_file)?;
+    let report = serial_pcap::validate::validate(std::io::BufReader::new(input))?;
+    println!("{report}");
+    if !report.is_ok() {
+        bail!("{} is not a valid capture.", args.pcap_file);
+    }
+    Ok(())
+}
+
+fn fixup(args: FixupOpts) -> Result<()> {
+    let input = CaptureInput::open(&args.pcap_file)?;
+    let mut writer = SerialPacketWriter::new_file(&args.output)?;
+    let report = serial_pcap::fixup::fixup(std::io::BufReader::new(input), &mut writer)?;
+    println!("{report}");
+    writer.flush()
+}
+
+fn generate_test_pcap(args: GenerateOpts) -> Result<()> {
+    use serial_pcap::generate::{generate, GenerateOptions};
+
+    let opts = GenerateOptions {
+        transactions: args.transactions,
+        nodes: args.nodes,
+        corrupt_every: args.corrupt_every,
+        no_reply_every: args.no_reply_every,
+        start: args.start,
+    };
+    let mut writer = SerialPacketWriter::new_file(&args.pcap_file)?;
+    generate(&opts, &mut writer)?;
+    writer.flush()
+}
+
+fn diff(args: DiffOpts) -> Result<()> {
+    use serial_pcap::diff::{exchanges, DiffItem};
+
+    let names = load_names(args.names.as_deref())?;
+    let before = exchanges(SerialPacketReader::from_file(&args.before)?)?;
+    let after = exchanges(SerialPacketReader::from_file(&args.after)?)?;
+    let line = |x: &serial_pcap::diff::Exchange| {
+        format!("{} {}", x.time.format("%H:%M:%S%.3f"), x.describe(&names))
+    };
+    println!("--- {} ({} transactions)", args.before, before.len());
+    println!("+++ {} ({} transactions)", args.after, after.len());
+    let (mut removed, mut added, mut changed) = (0, 0, 0);
+    for item in serial_pcap::diff::diff(&before, &after)? {
+        match item {
+            DiffItem::Same(a, _) if args.all => println!("  {}", line(&before[a])),
+            DiffItem::Same(..) => {}
+            DiffItem::Removed(a) => {
+                removed += 1;
+                println!("- {}", line(&before[a]));
+            }
+            DiffItem::Added(b) => {
+                added += 1;
+                println!("+ {}", line(&after[b]));
+            }
+            DiffItem::Changed(a, b) => {
+                changed += 1;
+                println!("! {}", line(&before[a]));
+                println!("! {}", line(&after[b]));
+            }
+        }
+    }
+    println!("{removed} removed, {added} added and {changed} changed transactions.");
+    if removed + added + changed > 0 {
+        bail!("The captures differ.");
+    }
+    Ok(())
+}
+
+fn dissector(args: DissectorOpts) -> Result<()> {
+    let names = NameMap::from_file(&args.names)?;
+    let lua = serial_pcap::dissector::generate(&names);
+    match args.output {
+        Some(filename) => {
+            std::fs::write(&filename, lua).with_context(|| format!("Failed to write {filename}."))
+        }
+        None => {
+            print!("{lua}");
+            Ok(())
+        }
+    }
+}
+
+fn device(args: DeviceOpts) -> Result<()> {
+    match args.command {
+        DeviceCommand::Dfu(opts) => dfu(opts),
+    }
+}
+
+fn dfu(args: DfuOpts) -> Result<()> {
+    let baud = if args.touch { 1200 } else { 9600 };
+    let mut port = tokio_serial::new(&args.port, baud)
+        .open()
+        .with_context(|| format!("Failed to open {}.", args.port))?;
+    if !args.touch {
+        port.write_all(b"bootsel\r\n")?;
+        port.flush()?;
+    }
+    // closing the port drops DTR, which completes the 1200 baud touch
+    drop(port);
+    println!(
+        "Rebooting {} into the bootloader, the device appears as the USB drive RPI-RP2.",
+        args.port
+    );
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = CmdlineOpts::parse();
+    match args.command {
+        Some(Command::Convert(opts)) => convert(opts),
+        Some(Command::Extract(opts)) => extract_channel(opts),
+        Some(Command::Hexdump(opts)) => hexdump(opts),
+        Some(Command::Import(opts)) => import(opts),
+        Some(Command::ImportRaw(opts)) => import_raw(opts),
+        Some(Command::ImportHexdump(opts)) => import_hexdump(opts),
+        Some(Command::Influx(opts)) => influx(opts),
+        Some(Command::Plot(opts)) => plot(opts),
+        #[cfg(feature = "wasm")]
+        Some(Command::DecodePlugin(opts)) => decode_plugin(opts),
+        Some(Command::Receive(opts)) => receive(opts).await,
+        Some(Command::Device(opts)) => device(opts),
+        Some(Command::SdLog(opts)) => sd_log(opts),
+        Some(Command::Validate(opts)) => validate(opts),
+        Some(Command::Diff(opts)) => diff(opts),
+        Some(Command::Dissector(opts)) => dissector(opts),
+        Some(Command::Fixup(opts)) => fixup(opts),
+        Some(Command::GenerateTestPcap(opts)) => generate_test_pcap(opts),
+        Some(Command::Doc(doc)) => {
+            doc.run(CmdlineOpts::command());
+            Ok(())
+        }
+        None => capture(args.capture).await,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PortArg {
+    name: String,
+    path: String,
+    settings: UartSettings,
+}
+
+fn parse_port(arg: &str) -> Result<PortArg> {
+    let (name, port) = arg.split_once('=').context("Expected NAME=SERIAL_PORT.")?;
+    let (path, settings) = match port.split_once('@') {
+        Some((path, settings)) => (path, settings.parse()?),
+        None => (port, UartSettings::default()),
+    };
+    Ok(PortArg {
+        name: name.to_string(),
+        path: path.to_string(),
+        settings,
+    })
+}
+
+/// Parse a size in bytes like "500K", "20M" or "1G", with binary multiples
+fn parse_size(arg: &str) -> Result<u64> {
+    let (num, unit) = match arg.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => arg.split_at(pos),
+        None => (arg, ""),
+    };
+    let scale: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("Unknown size unit '{unit}', expected K, M, G or T."),
+    };
+    let num: u64 = num.trim().parse().context("Invalid size.")?;
+    num.checked_mul(scale).context("Size is too large.")
+}
+
+/// The bus and channel which the `n`th `--port` is recorded as
+fn port_channel(n: usize) -> (u8, UartTxChannel) {
+    let ch = match n % 2 {
+        0 => UartTxChannel::Ctrl,
+        _ => UartTxChannel::Node,
+    };
+    ((n / 2) as u8, ch)
+}
+
+/// Where each channel is captured from, for the pcapng interface descriptions
+fn channel_sources(args: &CaptureOpts) -> Vec<(u8, UartTxChannel, String)> {
+    let both = |bus, source: String| {
+        [UartTxChannel::Ctrl, UartTxChannel::Node].map(|ch| (bus, ch, source.clone()))
+    };
+    let mut sources = vec![];
+    if args.pty {
+        sources.extend(both(0, "virtual serial port".to_string()));
+    } else if !args.port.is_empty() {
+        for (n, port) in args.port.iter().enumerate() {
+            let (bus, ch) = port_channel(n);
+            let PortArg {
+                name,
+                path,
+                settings,
+            } = port;
+            sources.push((bus, ch, format!("{name}: {path} {settings}")));
+        }
+    } else if args.usb {
+        let (vid, pid) = serial_pcap::framed::VID_PID;
+        sources.extend(both(0, format!("USB {vid:04x}:{pid:04x}")));
+        sources.extend(both(1, format!("USB {vid:04x}:{pid:04x} bus 2")));
+    } else if let Some(addr) = args.wifi.as_ref().or(args.wifi_udp.as_ref()) {
+        sources.extend(both(0, format!("Wi-Fi {addr}")));
+        sources.extend(both(1, format!("Wi-Fi {addr} bus 2")));
+    } else if let Some(ctrl) = &args.ctrl {
+        if args.muxed {
+            sources.extend(both(0, format!("{ctrl} muxed")));
+            if let Some(bus2) = &args.bus2 {
+                sources.extend(both(1, format!("{bus2} muxed")));
+            }
+        } else {
+            let settings = args.ctrl_settings;
+            sources.push((0, UartTxChannel::Ctrl, format!("{ctrl} {settings}")));
+            if let Some(node) = &args.node {
+                let settings = args.node_settings.unwrap_or(settings);
+                sources.push((0, UartTxChannel::Node, format!("{node} {settings}")));
+            }
+        }
+    }
+    sources
+}
+
+/// How often the capture device is looked for after it was disconnected
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The open serial ports of a capture device in muxed mode
+struct MuxedPorts {
+    stream: tokio_serial::SerialStream,
+    bus2: Option<tokio_serial::SerialStream>,
+    events: Option<tokio_serial::SerialStream>,
+}
+
+impl MuxedPorts {
+    fn open(stream: &str, bus2: Option<&str>, events: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            stream: open_async_uart(stream)?,
+            bus2: bus2.map(open_async_uart).transpose()?,
+            events: events.map(open_async_uart).transpose()?,
+        })
+    }
+}
+
+/// Read the muxed streams and the event log until one of the ports fails
+async fn read_muxed_ports(
+    args: &CaptureOpts,
+    ports: MuxedPorts,
+    event_log: &mut [Box<dyn std::io::Write + Send>],
+    clock: &CaptureClock,
+    tx: UartSink,
+) -> Result<()> {
+    let read_bus2 = async {
+        match ports.bus2 {
+            Some(uart) => read_muxed_uart(uart, 1, args.muxed_framing, tx.clone()).await,
+            None => std::future::pending().await,
+        }
+    };
+    let read_events = async {
+        match ports.events {
+            Some(uart) => read_event_log(uart, clock.clone(), event_log).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        r = read_muxed_uart(ports.stream, 0, args.muxed_framing, tx.clone()) => r,
+        r = read_bus2 => r,
+        r = read_events => r,
+    }
+}
 
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(Level::TRACE)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+/// Capture from the ports of the capture device. When they fail, e.g. because the device was
+/// reset or replugged, the device is looked for by its USB ID until it's back, and the capture
+/// continues with outage markers around the gap.
+async fn capture_device(
+    args: &CaptureOpts,
+    ports: DevicePorts,
+    clock: &CaptureClock,
+    tx: UartSink,
+    marks: &MarkSender,
+) -> Result<()> {
+    let open = |ports: &DevicePorts| {
+        MuxedPorts::open(&ports.stream, ports.bus2.as_deref(), Some(&ports.events))
+    };
+    let mut event_log = event_log_outputs(args)?;
+    let mut open_ports = open(&ports)?;
+    loop {
+        // each connection gets a clone, the sink is dropped with this function when it returns
+        let res = read_muxed_ports(args, open_ports, &mut event_log, clock, tx.clone()).await;
+        let err = match res {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        warn!("Lost the capture device: {err:#}");
+        let lost = clock.now();
+        marks.outage("capture device disconnected".into());
+        // the ports can be listed before they can be opened, e.g. until udev has set them up
+        open_ports = loop {
+            tokio::time::sleep(DEVICE_POLL_INTERVAL).await;
+            match DevicePorts::find().and_then(|ports| Ok((open(&ports)?, ports))) {
+                Ok((open_ports, ports)) => {
+                    info!(
+                        "Capture device back, muxed stream on {}, event log on {}.",
+                        ports.stream, ports.events
+                    );
+                    break open_ports;
+                }
+                Err(err) => trace!("Capture device not back yet: {err:#}"),
+            }
+        };
+        let outage = clock.now().duration_since(lost).unwrap_or_default();
+        let label = format!(
+            "capture device reconnected after {:.1} s",
+            outage.as_secs_f64()
+        );
+        marks.outage(label);
+    }
+}
+
+/// Where the event log of the capture device is written, the console is left to the terminal UI
+fn event_log_outputs(args: &CaptureOpts) -> Result<Vec<Box<dyn std::io::Write + Send>>> {
+    let mut outputs: Vec<Box<dyn std::io::Write + Send>> = vec![];
+    if !args.tui {
+        outputs.push(Box::new(std::io::stdout()));
+    }
+    if let Some(path) = &args.event_log {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the event log file {path}."))?;
+        outputs.push(Box::new(file));
+    }
+    Ok(outputs)
+}
+
+async fn capture(mut args: CaptureOpts) -> Result<()> {
+    // Log output would garble the terminal UI
+    if !args.tui {
+        let subscriber = tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(Level::TRACE)
+            .finish()
+            .with(serial_pcap::logging::env_filter(Level::TRACE)?);
+        tracing::subscriber::set_global_default(subscriber)?;
+    }
 
     info!("Logging at INFO level.");
     trace!("Logging at TRACE level.");
 
-    let pcap_writer = SerialPacketWriter::new_file(args.pcap_file)?;
-    let ctrl = open_async_uart(&args.ctrl)?;
+    // the device ports are captured like a muxed stream given with --ctrl and --bus2
+    let device = match args.device {
+        true => {
+            let ports = DevicePorts::find()?;
+            info!(
+                "Capture device found, muxed stream on {}, event log on {}.",
+                ports.stream, ports.events
+            );
+            args.ctrl = Some(ports.stream.clone());
+            args.bus2.clone_from(&ports.bus2);
+            args.muxed = true;
+            Some(ports)
+        }
+        false => None,
+    };
+
+    let clock = CaptureClock::with_model(ClockModel {
+        offset: args.clock_offset,
+        drift_ppm: args.clock_drift,
+    });
+    let pcap_file = args.pcap_file.clone().unwrap();
+    let pcapng = pcap_file.ends_with(".pcapng");
+    let descriptions: Vec<_> = channel_sources(&args)
+        .into_iter()
+        .map(|(bus, ch, source)| {
+            let name = serial_pcap::export::channel_name(ch);
+            (bus, ch, format!("{name} ({source})"))
+        })
+        .collect();
+    let ports: Vec<_> = descriptions
+        .iter()
+        .map(|(bus, _, description)| match bus {
+            0 => description.clone(),
+            bus => format!("bus {bus} {description}"),
+        })
+        .collect();
+    if args.port.len() > 2 * (u8::MAX as usize + 1) {
+        bail!("Too many ports, at most 512 can be captured.");
+    }
+    // pcap files have no place for the port names, so they are stored in markers
+    let mut header: Vec<_> = args
+        .port
+        .iter()
+        .enumerate()
+        .map(|(n, port)| {
+            let (bus, ch) = port_channel(n);
+            Marker {
+                kind: MarkerKind::Port,
+                ch: Some(ch),
+                label: format!("bus {bus} {} ({} {})", port.name, port.path, port.settings),
+                time: clock.now().into(),
+            }
+        })
+        .collect();
+    header.push(clock.anchor_marker());
+
+    // the copies start like the capture file, and get the rest from the tee
+    let copies = args
+        .tee
+        .iter()
+        .map(|path| {
+            let mut copy = match path.ends_with(".pcapng") {
+                true => SerialPacketWriter::new_pcapng_file(path)?,
+                false => SerialPacketWriter::new_file(path)?,
+            };
+            for (bus, ch, description) in &descriptions {
+                copy.describe_channel(*bus, *ch, description.clone());
+            }
+            for marker in &header {
+                copy.write_marker(marker)?;
+            }
+            Ok(copy)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let ring_limits = RingLimits {
+        max_size: args.ring_size,
+        max_age: args.ring_time,
+    };
+    let (pcap_writer, ring) = if ring_limits != RingLimits::default() {
+        // each segment is a complete capture, with the descriptions and markers repeated
+        let mut ring = SegmentRing::new(&pcap_file, ring_limits)?;
+        for (bus, ch, description) in descriptions {
+            ring.describe_channel(bus, ch, description);
+        }
+        for marker in header {
+            ring.add_header_marker(marker);
+        }
+        (ring.open_segment()?, Some(ring))
+    } else {
+        let mut pcap_writer = match (args.append, pcapng) {
+            (true, true) => bail!("Appending to pcapng files isn't supported."),
+            (true, false) => SerialPacketWriter::append_file(&pcap_file)?,
+            (false, true) => SerialPacketWriter::new_pcapng_file(&pcap_file)?,
+            (false, false) => SerialPacketWriter::new_file(&pcap_file)?,
+        };
+        for (bus, ch, description) in descriptions {
+            pcap_writer.describe_channel(bus, ch, description);
+        }
+        for marker in &header {
+            pcap_writer.write_marker(marker)?;
+        }
+        (pcap_writer, None)
+    };
+
+    let (tx, rx) = queue::bounded(args.queue_size, args.overflow);
+    let drops = Arc::new(DropStats::new(clock.clone()));
+    let pool = BufferPool::new();
+    let tx = UartSink {
+        tx,
+        drops: drops.clone(),
+        clock: clock.clone(),
+        pool: pool.clone(),
+    };
+    if let Some(path) = &args.pps {
+        start_pps(path, clock.clone())?;
+    }
+    let _drop_reporter: abort_on_drop::ChildTask<_> =
+        tokio::spawn(report_drops(drops.clone())).into();
+    let (mark_tx, mark_rx) = tokio::sync::mpsc::unbounded_channel();
+    let marks = MarkSender {
+        tx: mark_tx,
+        clock: clock.clone(),
+        count: Arc::default(),
+    };
+    let _user_signals: abort_on_drop::ChildTask<_> =
+        tokio::spawn(handle_user_signals(marks.clone())).into();
+    let outage_marks = marks.clone();
+    let alert_marks = marks.clone();
+    let mut tee = Tee::new();
+    let copies = copies
+        .into_iter()
+        .map(|copy| {
+            let records = tee.records();
+            std::thread::Builder::new()
+                .name("tee".into())
+                .spawn(move || {
+                    let res =
+                        serial_pcap::tee::write_copy(copy, records, Duration::from_millis(500));
+                    if let Err(e) = &res {
+                        warn!("Stopped writing a copy of the capture: {e:#}");
+                    }
+                    res
+                })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let tui = match args.tui {
+        true => {
+            let names = load_names(args.names.as_deref())?;
+            let records = tee.records();
+            let on_mark = Box::new(move |label| marks.mark(label));
+            Some(tokio::task::spawn_blocking(|| {
+                serial_pcap::tui::run(records, names, Some(on_mark))
+            }))
+        }
+        false => None,
+    };
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = args.mqtt.clone() {
+        let config = serial_pcap::mqtt::MqttConfig {
+            topic_prefix: args.mqtt_topic.clone(),
+            retain: args.mqtt_retain,
+            ..serial_pcap::mqtt::MqttConfig::new(broker)
+        };
+        let publisher = serial_pcap::mqtt::MqttPublisher::connect(config)?;
+        let packets = tee.packets();
+        std::thread::spawn(move || publisher.run(packets));
+    }
+    if let Some(addr) = &args.websocket {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen on {addr}."))?;
+        info!(
+            "Streaming the decoded traffic on ws://{}/.",
+            listener.local_addr()?
+        );
+        let names = load_names(args.names.as_deref())?;
+        let records = tee.records();
+        std::thread::spawn(move || serial_pcap::websocket::serve(listener, records, names));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = &args.grpc {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {addr}."))?;
+        info!(
+            "Serving the decoded traffic over gRPC on {}.",
+            listener.local_addr()?
+        );
+        let records = tee.records();
+        tokio::spawn(async move {
+            if let Err(e) = serial_pcap::grpc::serve(listener, records).await {
+                warn!("The gRPC server failed: {e:#}");
+            }
+        });
+    }
+    if let Some(filename) = &args.rules {
+        let rules = AlertRules::from_file(filename)?;
+        let packets = tee.packets();
+        let clock = clock.clone();
+        std::thread::Builder::new()
+            .name("alerts".into())
+            .spawn(move || {
+                serial_pcap::alert::monitor(
+                    rules,
+                    packets,
+                    || clock.now().into(),
+                    |alert| {
+                        warn!("Alert: {alert}");
+                        alert_marks.alert(&alert);
+                    },
+                )
+            })?;
+    }
+    if let Some(addr) = &args.serve {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen on {addr}."))?;
+        info!("Serving the capture on {}.", listener.local_addr()?);
+        let packets = tee.packets();
+        std::thread::spawn(move || serial_pcap::remote::serve(listener, packets));
+    }
+    if let Some(dest) = args.udp_mirror {
+        info!("Mirroring the capture as UDP datagrams to {dest}.");
+        let records = tee.records();
+        std::thread::Builder::new()
+            .name("udp-mirror".into())
+            .spawn(move || serial_pcap::mirror::run(dest, records))?;
+    }
+    if let Some(addr) = args.forward.clone() {
+        let packets = tee.packets();
+        std::thread::Builder::new()
+            .name("forward".into())
+            .spawn(move || serial_pcap::remote::forward(&addr, packets))?;
+    }
+    if args.daemon {
+        start_daemon_status(&mut tee, drops.clone())?;
+    }
+    let status_line = !args.tui && !args.status_interval.is_zero();
+    if status_line || args.http.is_some() {
+        // the segment files of a ring come and go, so only a plain capture file is measured
+        let path = ring.is_none().then(|| pcap_file.clone());
+        let file_size = move || Some(std::fs::metadata(path.as_ref()?).ok()?.len());
+        let status = Arc::new(std::sync::Mutex::new(CaptureStatus::new(ports)));
+        let packets = tee.packets();
+        let interval = status_line.then_some(args.status_interval);
+        let (counted, size) = (status.clone(), file_size.clone());
+        std::thread::Builder::new()
+            .name("status".into())
+            .spawn(move || serial_pcap::status::run_status(packets, counted, interval, size))?;
+        if let Some(addr) = &args.http {
+            let listener = std::net::TcpListener::bind(addr)
+                .with_context(|| format!("Failed to listen on {addr}."))?;
+            info!(
+                "Serving the capture status at http://{}/status.",
+                listener.local_addr()?
+            );
+            let drops = drops.clone();
+            std::thread::Builder::new()
+                .name("status-http".into())
+                .spawn(move || {
+                    serial_pcap::status::serve_http(listener, status, drops, file_size)
+                })?;
+        }
+    }
+    let tshark = match args.tshark {
+        true => {
+            let child = serial_pcap::tshark::spawn(serial_pcap::tshark::command(&args.tshark_arg))?;
+            let packets = tee.packets();
+            Some(std::thread::spawn(move || {
+                serial_pcap::tshark::feed(child, packets)
+            }))
+        }
+        false => None,
+    };
+    let limits = CaptureLimits {
+        max_packets: args.max_packets,
+        max_size: args.max_size,
+        ..Default::default()
+    };
+    let limit_reached = limits.reached.clone();
+    let mut recorder = tokio::spawn(record_streams(
+        pcap_writer,
+        ring,
+        RecorderInputs {
+            data: rx,
+            marks: mark_rx,
+            pool,
+            marker: None,
+            read: None,
+        },
+        tee,
+        drops.clone(),
+        limits,
+    ));
 
-    let (tx, rx) = unbounded_channel();
-    let mut recorder = tokio::spawn(record_streams(pcap_writer, rx));
+    // Stop the capture on ctrl-c or SIGTERM, when the user exits the terminal UI, or when a
+    // capture limit is reached
+    let time_limit = async {
+        match args.duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    let stop = async {
+        let signal = async {
+            match tui {
+                Some(tui) => tokio::select! {
+                    r = shutdown_signal() => r,
+                    r = tui => r.context("Terminal UI task failed.")?,
+                },
+                None => shutdown_signal().await,
+            }
+        };
+        tokio::select! {
+            r = signal => r,
+            _ = time_limit => {
+                info!("Capture duration reached, stopping.");
+                Ok(())
+            }
+            _ = limit_reached.notified() => Ok(()),
+        }
+    };
+    tokio::pin!(stop);
 
     let res;
-    if args.muxed {
+    if args.pty {
+        tokio::select! {
+            r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
+            r = read_pty(tx) => {res = r;}
+            r = &mut stop => { res = r }
+        }
+    } else if args.usb {
+        tokio::select! {
+            r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
+            r = read_usb(tx) => {res = r;}
+            r = &mut stop => { res = r }
+        }
+    } else if let Some(addr) = &args.wifi {
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Failed to connect to the capture device at {addr}."))?;
+        info!("Connected to the capture device at {addr}.");
         tokio::select! {
             r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
-            r = read_muxed_uart(ctrl, tx) => {res = r;}
-            _ = tokio::signal::ctrl_c() => { res = Ok(()) }
+            r = read_framed_stream(stream, tx) => {res = r;}
+            r = &mut stop => { res = r }
+        }
+    } else if let Some(addr) = &args.wifi_udp {
+        let socket = tokio::net::UdpSocket::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind {addr}."))?;
+        tokio::select! {
+            r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
+            r = read_framed_datagrams(socket, tx) => {res = r;}
+            r = &mut stop => { res = r }
+        }
+    } else if !args.port.is_empty() {
+        let mut readers = tokio::task::JoinSet::new();
+        for (n, port) in args.port.iter().enumerate() {
+            let (bus, ch) = port_channel(n);
+            let uart = open_async_uart_with(&port.path, &port.settings)?;
+            readers.spawn(read_uart(uart, bus, ch, tx.clone()));
+        }
+        // the recorder stops when the readers have dropped their senders
+        drop(tx);
+        tokio::select! {
+            r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
+            Some(r) = readers.join_next() => { res = r.context("UART reader task failed.")?; }
+            r = &mut stop => { res = r }
+        }
+    } else if args.muxed {
+        // the readers own the sink, so the recorder stops when they have dropped it
+        let read = async {
+            match device {
+                Some(ports) => capture_device(&args, ports, &clock, tx, &outage_marks).await,
+                None => {
+                    let ports =
+                        MuxedPorts::open(args.ctrl.as_ref().unwrap(), args.bus2.as_deref(), None)?;
+                    read_muxed_ports(&args, ports, &mut [], &clock, tx).await
+                }
+            }
+        };
+        tokio::select! {
+            r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
+            r = read => {res = r;}
+            r = &mut stop => { res = r }
         }
     } else {
-        let node = open_async_uart(args.node.as_ref().unwrap())?;
+        let ctrl = open_async_uart_with(args.ctrl.as_ref().unwrap(), &args.ctrl_settings)?;
+        let node_settings = args.node_settings.unwrap_or(args.ctrl_settings);
+        let node = open_async_uart_with(args.node.as_ref().unwrap(), &node_settings)?;
         tokio::select! {
             r = await_task(&mut recorder) => { return r.context("Error in stream recorder task."); }
-            r = read_uart(ctrl, UartTxChannel::Ctrl, tx.clone()) => {res = r;}
-            r = read_uart(node, UartTxChannel::Node, tx) => {res = r;}
-            _ = tokio::signal::ctrl_c() => { res = Ok(()) }
+            r = read_uart(ctrl, 0, UartTxChannel::Ctrl, tx.clone()) => {res = r;}
+            r = read_uart(node, 0, UartTxChannel::Node, tx) => {res = r;}
+            r = &mut stop => { res = r }
         }
     }
 
@@ -211,7 +2118,30 @@ async fn main() -> Result<()> {
 
     // Stop the recorder task by dropping all the channel tx handles
     await_task(&mut recorder).await?;
+    // the copies have got the end of the capture when the recorder has dropped the tee
+    for copy in copies {
+        match tokio::task::block_in_place(|| copy.join()) {
+            Ok(r) => r?,
+            Err(_) => bail!("A capture copy thread panicked."),
+        }
+    }
+    if let Some(tshark) = tshark {
+        // the recorder has dropped the monitor, so tshark gets the end of the stream
+        match tokio::task::block_in_place(|| tshark.join()) {
+            Ok(r) => r?,
+            Err(_) => bail!("The tshark thread panicked."),
+        }
+    }
 
+    info!(
+        "The system clock moved {:+.3} s relative to the capture clock.",
+        clock.drift()
+    );
+    // printed rather than logged, so it's shown after the terminal UI exits too
+    match drops.total_events() {
+        0 => info!("No data was lost during the capture."),
+        _ => eprintln!("Data lost during the capture, {}.", drops.summary()),
+    }
     info!("Shutdown complete.");
     res.context("Error returned from main()")
 }
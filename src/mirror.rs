@@ -0,0 +1,113 @@
+//! Live mirroring of the capture as real UDP datagrams, so Wireshark or tcpdump can capture the
+//! bus from a network interface without a pipe.
+//!
+//! On loopback, the datagrams use the same addresses and ports as the packets in the pcap file,
+//! e.g. 127.0.0.1:422 to 127.0.0.2:1422 for the controller data on bus 0, so the dissector and
+//! the display filters work the same on both. Binding the addresses other than 127.0.0.1 needs
+//! Linux, where all of 127.0.0.0/8 is on the loopback interface.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::Receiver;
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::{channel_addrs, CaptureRecord, Marker, SerialPacket, Trigger, MARKER, TRIGGER};
+
+/// Sends the captured data as UDP datagrams
+#[derive(Debug)]
+pub struct UdpMirror {
+    dest: Ipv4Addr,
+    /// The sockets by their source address in the pcap file
+    sockets: HashMap<SocketAddrV4, UdpSocket>,
+    /// A send error has been logged
+    failed: bool,
+}
+
+impl UdpMirror {
+    /// Mirror to `dest`. A loopback address selects the addresses of the pcap packets. Other
+    /// addresses, e.g. the broadcast address of an interface, get all the datagrams, and only
+    /// the destination port tells the channel.
+    pub fn new(dest: Ipv4Addr) -> Self {
+        Self {
+            dest,
+            sockets: HashMap::new(),
+            failed: false,
+        }
+    }
+
+    pub fn send_packet(&mut self, pkt: &SerialPacket) {
+        let (ip, ports) = channel_addrs(pkt.bus, pkt.ch);
+        self.send(
+            SocketAddrV4::new(ip.0.into(), ports.0),
+            SocketAddrV4::new(ip.1.into(), ports.1),
+            &pkt.data,
+        );
+    }
+
+    pub fn send_marker(&mut self, marker: &Marker) {
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, MARKER);
+        self.send(addr, addr, marker.encode().as_bytes());
+    }
+
+    pub fn send_trigger(&mut self, trigger: &Trigger) {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(127, 0, trigger.bus, 1), TRIGGER);
+        self.send(addr, addr, trigger.encode().as_bytes());
+    }
+
+    fn send(&mut self, src: SocketAddrV4, dst: SocketAddrV4, data: &[u8]) {
+        let loopback = self.dest.is_loopback();
+        let dst = match loopback {
+            true => dst,
+            false => SocketAddrV4::new(self.dest, dst.port()),
+        };
+        let res = match self.sockets.get(&src) {
+            Some(socket) => Ok(socket),
+            None => bind(src, loopback).map(|socket| &*self.sockets.entry(src).or_insert(socket)),
+        }
+        .and_then(|socket| socket.send_to(data, dst));
+        match res {
+            Ok(_) => self.failed = false,
+            // only the first error of a series is logged, the mirror isn't essential
+            Err(e) if !self.failed => {
+                warn!("Failed to mirror a packet to {dst}: {e}");
+                self.failed = true;
+            }
+            Err(_) => (),
+        }
+    }
+}
+
+/// A socket with the source address of the pcap packets, or another port if the port can't be
+/// bound, e.g. the privileged port 422
+fn bind(src: SocketAddrV4, loopback: bool) -> std::io::Result<UdpSocket> {
+    let ip = match loopback {
+        true => *src.ip(),
+        false => Ipv4Addr::UNSPECIFIED,
+    };
+    let socket = UdpSocket::bind((ip, src.port())).or_else(|e| {
+        warn!(
+            "Failed to bind {ip}:{}, mirroring from another port: {e}",
+            src.port()
+        );
+        UdpSocket::bind((ip, 0))
+    })?;
+    if !loopback {
+        socket.set_broadcast(true)?;
+    }
+    Ok(socket)
+}
+
+/// Mirror the `records` passed to the monitors to `dest`, until the capture stops.
+pub fn run(dest: Ipv4Addr, records: Receiver<CaptureRecord>) -> Result<()> {
+    let mut mirror = UdpMirror::new(dest);
+    for record in records {
+        match &record {
+            CaptureRecord::Packet(pkt) => mirror.send_packet(pkt),
+            CaptureRecord::Marker(marker) => mirror.send_marker(marker),
+            CaptureRecord::Trigger(trigger) => mirror.send_trigger(trigger),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Reading a capture file through a memory map, for passes over whole multi-gigabyte captures.
+//!
+//! The pcap reader reads each record header and each record with its own `read` call, which
+//! for a file is a system call and a copy from the page cache per call. A mapped file is read
+//! from the page cache directly, with the kernel reading ahead since the access is sequential.
+//!
+//! The map is a snapshot of the file length when it is opened, so a capture which is still
+//! being written should be read as a plain file. Truncating the file while it is mapped kills
+//! the process with SIGBUS, e.g. when a capture is restarted with `--append` and the partial
+//! record at the end is removed.
+
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+
+/// A memory mapped capture file, read from the start
+pub struct MappedCapture {
+    map: Mmap,
+    pos: usize,
+}
+
+impl MappedCapture {
+    pub fn open(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let file = File::open(filename)
+            .with_context(|| format!("Failed to open {}.", filename.display()))?;
+        // SAFETY: the map is only read, the caveat is a file truncated by another process, see
+        // the module documentation
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to map {}.", filename.display()))?;
+        #[cfg(unix)]
+        {
+            // only a hint to the kernel, the reading works without it
+            let _ = map.advise(memmap2::Advice::Sequential);
+        }
+        Ok(Self { map, pos: 0 })
+    }
+
+    /// The length of the file when it was mapped
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The bytes read so far
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl Read for MappedCapture {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.fill_buf()?.read_exact(buf)?;
+        self.consume(buf.len());
+        Ok(())
+    }
+}
+
+impl BufRead for MappedCapture {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(&self.map[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.map.len());
+    }
+}
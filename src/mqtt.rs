@@ -0,0 +1,125 @@
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rumqttc::{Client, MqttOptions, QoS};
+use tracing::{debug, warn};
+
+use crate::decode::{BusEvent, X328Decoder};
+use crate::SerialPacket;
+
+const DEFAULT_PORT: u16 = 1883;
+
+/// MQTT broker connection settings
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    /// Broker address, "host" or "host:port"
+    pub broker: String,
+    pub client_id: String,
+    /// Values are published to "<prefix>/<addr>/<param>"
+    pub topic_prefix: String,
+    pub retain: bool,
+}
+
+impl MqttConfig {
+    pub fn new(broker: impl Into<String>) -> Self {
+        Self {
+            broker: broker.into(),
+            client_id: "serial-pcap".into(),
+            topic_prefix: "serial-pcap".into(),
+            retain: false,
+        }
+    }
+
+    fn options(&self) -> Result<MqttOptions> {
+        let (host, port) = match self.broker.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("Invalid MQTT broker port.")?),
+            None => (self.broker.as_str(), DEFAULT_PORT),
+        };
+        let mut opts = MqttOptions::new(&self.client_id, host, port);
+        opts.set_keep_alive(Duration::from_secs(30));
+        Ok(opts)
+    }
+}
+
+/// The topic and payload published for a bus event, if any: the value of a successful read
+/// or write to `<prefix>/<addr>/<param>`, and the error of a failed transaction or a timeout
+/// to `<prefix>/<addr>/<param>/error`.
+pub fn message(prefix: &str, event: &BusEvent) -> Option<(String, String)> {
+    let (cmd, suffix, payload) = match event {
+        BusEvent::Transaction(t) => match &t.result {
+            Ok(value) => (t.cmd, "", value.to_string()),
+            Err(e) => (t.cmd, "/error", e.to_string()),
+        },
+        BusEvent::Timeout { cmd: Some(cmd), .. } => (*cmd, "/error", "Timeout".into()),
+        _ => return None,
+    };
+    let topic = format!("{prefix}/{}/{}{suffix}", *cmd.addr(), *cmd.param());
+    Some((topic, payload))
+}
+
+/// Publishes decoded X3.28 parameter values to an MQTT broker.
+///
+/// Successful reads and writes publish the value to `<prefix>/<addr>/<param>`,
+/// failed transactions publish the error to `<prefix>/<addr>/<param>/error`.
+pub struct MqttPublisher {
+    client: Client,
+    config: MqttConfig,
+    decoder: X328Decoder,
+}
+
+impl MqttPublisher {
+    /// Connect to the broker. The connection is driven by a background thread,
+    /// which reconnects if the broker goes away.
+    pub fn connect(config: MqttConfig) -> Result<Self> {
+        let (client, mut connection) = Client::new(config.options()?, 100);
+        std::thread::Builder::new()
+            .name("mqtt".into())
+            .spawn(move || {
+                for event in connection.iter() {
+                    match event {
+                        Ok(event) => debug!("MQTT {event:?}"),
+                        Err(e) => {
+                            warn!("MQTT connection error: {e}");
+                            std::thread::sleep(Duration::from_secs(1));
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            client,
+            config,
+            decoder: X328Decoder::new(),
+        })
+    }
+
+    /// Decode the packet and publish any parameter values found.
+    pub fn feed(&mut self, pkt: &SerialPacket) {
+        let mut messages = vec![];
+        self.decoder.feed(pkt, |e| {
+            messages.extend(message(&self.config.topic_prefix, &e));
+        });
+        for (topic, payload) in messages {
+            self.publish(&topic, payload);
+        }
+    }
+
+    fn publish(&self, topic: &str, payload: String) {
+        // Don't stall the decoding if the broker is unreachable, drop the update instead
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtMostOnce, self.config.retain, payload)
+        {
+            warn!("Failed to publish to {topic}: {e}");
+        }
+    }
+
+    /// Publish the packets received on `rx`, until the sender is dropped.
+    pub fn run(mut self, rx: Receiver<SerialPacket>) -> Result<()> {
+        for pkt in rx {
+            self.feed(&pkt);
+        }
+        let _ = self.client.disconnect();
+        Ok(())
+    }
+}
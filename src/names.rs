@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Name, unit and description of a node parameter
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ParamInfo {
+    pub name: String,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Human readable names for the bus addresses and parameters.
+///
+/// The mapping is read from a TOML file
+/// ```toml
+/// [[node]]
+/// address = 31
+/// name = "stow east"
+///
+/// [[param]]
+/// address = 31
+/// param = 401
+/// name = "stow pressure east"
+/// unit = "bar"
+/// ```
+/// or a CSV file with the columns `address,param,name,unit,description`,
+/// where rows with an empty `param` name the node itself.
+#[derive(Debug, Clone, Default)]
+pub struct NameMap {
+    nodes: HashMap<u8, String>,
+    params: HashMap<(u8, i16), ParamInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlMap {
+    #[serde(default)]
+    node: Vec<TomlNode>,
+    #[serde(default)]
+    param: Vec<TomlParam>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlNode {
+    address: u8,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct TomlParam {
+    address: u8,
+    param: i16,
+    #[serde(flatten)]
+    info: ParamInfo,
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    address: u8,
+    param: Option<i16>,
+    name: String,
+    unit: Option<String>,
+    description: Option<String>,
+}
+
+impl NameMap {
+    /// Load a mapping file, the format is selected by the file extension.
+    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let read = || -> Result<Self> {
+            let text = std::fs::read_to_string(filename)?;
+            match filename.extension().and_then(|e| e.to_str()) {
+                Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::from_csv(&text),
+                _ => Self::from_toml(&text),
+            }
+        };
+        read().with_context(|| format!("Failed to read name mapping {}.", filename.display()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let map: TomlMap = toml::from_str(text)?;
+        Ok(Self {
+            nodes: map.node.into_iter().map(|n| (n.address, n.name)).collect(),
+            params: map
+                .param
+                .into_iter()
+                .map(|p| ((p.address, p.param), p.info))
+                .collect(),
+        })
+    }
+
+    pub fn from_csv(text: &str) -> Result<Self> {
+        let mut map = Self::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(text.as_bytes());
+        for row in reader.deserialize() {
+            let row: CsvRow = row?;
+            match row.param {
+                Some(param) => {
+                    let info = ParamInfo {
+                        name: row.name,
+                        unit: row.unit,
+                        description: row.description,
+                    };
+                    map.params.insert((row.address, param), info);
+                }
+                None => {
+                    map.nodes.insert(row.address, row.name);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.params.is_empty()
+    }
+
+    pub fn node(&self, addr: u8) -> Option<&str> {
+        self.nodes.get(&addr).map(String::as_str)
+    }
+
+    pub fn param(&self, addr: u8, param: i16) -> Option<&ParamInfo> {
+        self.params.get(&(addr, param))
+    }
+
+    /// The named nodes, in no particular order
+    pub fn nodes(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.nodes.iter().map(|(&addr, name)| (addr, name.as_str()))
+    }
+
+    /// The named parameters, as ((address, param), info), in no particular order
+    pub fn params(&self) -> impl Iterator<Item = ((u8, i16), &ParamInfo)> {
+        self.params.iter().map(|(&key, info)| (key, info))
+    }
+
+    /// The parameter name, or "node/param" with the node name if known.
+    pub fn label(&self, addr: u8, param: i16) -> String {
+        match (self.param(addr, param), self.node(addr)) {
+            (Some(info), _) => info.name.clone(),
+            (None, Some(node)) => format!("{node}/{param}"),
+            (None, None) => format!("{addr}/{param}"),
+        }
+    }
+
+    /// The value, followed by the parameter unit if known.
+    pub fn value(&self, addr: u8, param: i16, value: i32) -> String {
+        match self.param(addr, param).and_then(|p| p.unit.as_deref()) {
+            Some(unit) => format!("{value} {unit}"),
+            None => value.to_string(),
+        }
+    }
+}
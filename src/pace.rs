@@ -0,0 +1,88 @@
+//! Pacing of a replay by the recorded timestamps, so a capture can be watched at the speed of
+//! the bus or faster.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+/// How fast to replay a capture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// As fast as the records can be read
+    Max,
+    /// The recorded time scaled by the factor, 1 is the speed of the capture
+    Scaled(f64),
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    /// Parse "max", "realtime", or a factor like "0.5x" or "10", without or with the x
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s {
+            "max" => return Ok(ReplaySpeed::Max),
+            "realtime" => return Ok(ReplaySpeed::Scaled(1.0)),
+            _ => {}
+        }
+        let factor: f64 = s
+            .strip_suffix(['x', 'X'])
+            .unwrap_or(s)
+            .parse()
+            .with_context(|| format!("Invalid replay speed '{s}'."))?;
+        if !(factor.is_finite() && factor > 0.0) {
+            bail!("The replay speed must be above 0, not '{s}'.");
+        }
+        Ok(ReplaySpeed::Scaled(factor))
+    }
+}
+
+impl Display for ReplaySpeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplaySpeed::Max => f.write_str("max"),
+            ReplaySpeed::Scaled(factor) if *factor == 1.0 => f.write_str("realtime"),
+            ReplaySpeed::Scaled(factor) => write!(f, "{factor}x"),
+        }
+    }
+}
+
+/// Holds back the records until their time in the replay. The first record is passed on at
+/// once, and the rest at their recorded distance from it, scaled by the speed. Records which
+/// are earlier than the ones before them, e.g. after the clock was stepped, are passed on at
+/// once.
+#[derive(Debug)]
+pub struct Pacer {
+    speed: ReplaySpeed,
+    /// The time of the first record, and when it was passed on
+    start: Option<(DateTime<Utc>, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(speed: ReplaySpeed) -> Self {
+        Self { speed, start: None }
+    }
+
+    /// How long to wait at `now` before passing on a record from `time`
+    pub fn delay(&mut self, time: DateTime<Utc>, now: Instant) -> Duration {
+        let ReplaySpeed::Scaled(factor) = self.speed else {
+            return Duration::ZERO;
+        };
+        let (first, started) = *self.start.get_or_insert((time, now));
+        let Ok(recorded) = (time - first).to_std() else {
+            return Duration::ZERO;
+        };
+        (started + recorded.div_f64(factor)).saturating_duration_since(now)
+    }
+
+    /// Sleep until the time of the record from `time`
+    pub fn wait(&mut self, time: DateTime<Utc>) {
+        let delay = self.delay(time, Instant::now());
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! A minimal pcapng writer, used when the capture is written as pcapng.
+//!
+//! Each channel gets its own interface, described by an interface block which is written
+//! before its first packet, so Wireshark shows the channel name and the serial port instead
+//! of anonymous UDP flows. The packets are the same IPv4/UDP packets as in the pcap files.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use crate::{UartTxChannel, LINKTYPE_IPV4};
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+
+/// An interface in the pcapng file: a channel of a bus, or the markers and triggers when `ch`
+/// is None
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Interface {
+    pub bus: u8,
+    pub ch: Option<UartTxChannel>,
+}
+
+impl Interface {
+    fn default_name(&self) -> String {
+        let name = match self.ch {
+            Some(ch) => crate::export::channel_name(ch),
+            None => return "markers".to_string(),
+        };
+        match self.bus {
+            0 => name.to_string(),
+            bus => format!("bus {bus} {name}"),
+        }
+    }
+}
+
+pub(crate) struct PcapngWriter<W: Write> {
+    writer: W,
+    snaplen: usize,
+    /// The interface ids, in the order the interface blocks were written
+    ids: HashMap<Interface, u32>,
+    descriptions: HashMap<Interface, String>,
+    /// Bytes written to the file
+    written: u64,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(mut writer: W, snaplen: usize) -> Result<Self> {
+        let mut body = vec![];
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // section length not known
+        let written = write_block(&mut writer, SECTION_HEADER, &body)
+            .context("Failed to write pcapng header.")?;
+        Ok(Self {
+            writer,
+            snaplen,
+            ids: HashMap::new(),
+            descriptions: HashMap::new(),
+            written,
+        })
+    }
+
+    /// Set the description of the interface, before its first packet is written
+    pub fn describe(&mut self, iface: Interface, description: String) {
+        self.descriptions.insert(iface, description);
+    }
+
+    /// Write a packet, with an optional comment which Wireshark shows in the packet list
+    pub fn write(
+        &mut self,
+        iface: Interface,
+        time: SystemTime,
+        data: &[u8],
+        comment: Option<&str>,
+    ) -> Result<()> {
+        let id = match self.ids.get(&iface) {
+            Some(id) => *id,
+            None => self.add_interface(iface)?,
+        };
+        // the default timestamp resolution is microseconds
+        let micros = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("Packet time before the Unix epoch.")?
+            .as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + data.len() + 3);
+        body.extend_from_slice(&id.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+        if let Some(comment) = comment {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            push_option(&mut body, OPT_END, &[]);
+        }
+        self.written += write_block(&mut self.writer, ENHANCED_PACKET, &body)
+            .context("Failed to write packet to pcapng file")?;
+        Ok(())
+    }
+
+    fn add_interface(&mut self, iface: Interface) -> Result<u32> {
+        let mut body = vec![];
+        body.extend_from_slice(&(LINKTYPE_IPV4 as u16).to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+        body.extend_from_slice(&(self.snaplen as u32).to_le_bytes());
+        push_option(&mut body, IF_NAME, iface.default_name().as_bytes());
+        if let Some(description) = self.descriptions.get(&iface) {
+            push_option(&mut body, IF_DESCRIPTION, description.as_bytes());
+        }
+        push_option(&mut body, OPT_END, &[]);
+        self.written += write_block(&mut self.writer, INTERFACE_DESCRIPTION, &body)
+            .context("Failed to write pcapng interface.")?;
+        let id = self.ids.len() as u32;
+        self.ids.insert(iface, id);
+        Ok(id)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn take_writer(self) -> W {
+        self.writer
+    }
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+/// Write a block, `body` is padded to 32 bits. Returns the length of the block.
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> std::io::Result<u64> {
+    let len = 12 + body.len() as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())?;
+    Ok(len as u64)
+}
@@ -0,0 +1,362 @@
+//! Time series of parameter values from a capture, as charts or data for other plotting tools,
+//! e.g. to see the encoder positions or the stow pressures over the capture.
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::decode::{BusCommand, BusEvent, X328Decoder};
+use crate::names::NameMap;
+use crate::SerialPacketReader;
+
+/// Output formats of the plot command
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlotFormat {
+    /// An SVG chart with a panel per series
+    Svg,
+    /// A PNG chart, rendered by gnuplot
+    Png,
+    /// A gnuplot script with the data inline
+    Gnuplot,
+    /// CSV with a row per value, time,addr,param,name,value
+    Csv,
+}
+
+impl PlotFormat {
+    /// The format for the extension of `filename`, if it is a known one
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let (_, ext) = filename.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "svg" => Some(PlotFormat::Svg),
+            "png" => Some(PlotFormat::Png),
+            "gp" | "gnuplot" | "plt" => Some(PlotFormat::Gnuplot),
+            "csv" => Some(PlotFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// A parameter of a node, "ADDR:PARAM" on the command line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParamId {
+    pub addr: u8,
+    pub param: i16,
+}
+
+impl FromStr for ParamId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, param) = s.split_once(':').context("Expected ADDR:PARAM.")?;
+        Ok(Self {
+            addr: addr
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid node address '{addr}'."))?,
+            param: param
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid parameter '{param}'."))?,
+        })
+    }
+}
+
+/// The values of a parameter, in the order they were seen on the bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub id: ParamId,
+    /// The name from the mapping file, with the unit, or "addr/param"
+    pub label: String,
+    pub points: Vec<(DateTime<Utc>, i32)>,
+}
+
+/// Collect the values read from and written to the parameters, at the times of the responses.
+pub fn collect_series<R: Read>(
+    reader: &mut SerialPacketReader<R>,
+    params: &[ParamId],
+    names: &NameMap,
+) -> Result<Vec<Series>> {
+    let mut series: Vec<_> = params
+        .iter()
+        .map(|&id| {
+            let mut label = names.label(id.addr, id.param);
+            if let Some(unit) = names.param(id.addr, id.param).and_then(|p| p.unit.as_ref()) {
+                label += &format!(" ({unit})");
+            }
+            Series {
+                id,
+                label,
+                points: vec![],
+            }
+        })
+        .collect();
+    let mut decoder = X328Decoder::new();
+    for pkt in reader {
+        decoder.feed(&pkt?, |event| {
+            let BusEvent::Transaction(t) = event else {
+                return;
+            };
+            let Ok(value) = t.result else {
+                return;
+            };
+            let (addr, param) = match t.cmd {
+                BusCommand::Read { addr, param } | BusCommand::Write { addr, param, .. } => {
+                    (*addr, *param)
+                }
+            };
+            for s in &mut series {
+                if s.id == (ParamId { addr, param }) {
+                    s.points.push((t.resp_time, *value));
+                }
+            }
+        });
+    }
+    Ok(series)
+}
+
+/// Write the series in `format`. PNG is written to `png_file` by gnuplot, which must be
+/// installed, the other formats to `out`.
+pub fn write_plot(
+    series: &[Series],
+    format: PlotFormat,
+    out: impl Write,
+    png_file: Option<&str>,
+) -> Result<()> {
+    match format {
+        PlotFormat::Svg => write_svg(series, out, SvgSize::default()),
+        PlotFormat::Gnuplot => write_gnuplot(series, out),
+        PlotFormat::Csv => write_csv(series, out),
+        PlotFormat::Png => {
+            let png_file = png_file.context("A PNG chart needs an output file.")?;
+            render_png(series, png_file)
+        }
+    }
+}
+
+/// All the values in a CSV file, sorted by time
+pub fn write_csv(series: &[Series], out: impl Write) -> Result<()> {
+    let mut rows: Vec<_> = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(move |p| (p, s)))
+        .collect();
+    rows.sort_by_key(|((time, _), _)| *time);
+    let mut csv = csv::Writer::from_writer(out);
+    csv.write_record(["time", "addr", "param", "name", "value"])?;
+    for ((time, value), s) in rows {
+        csv.write_record([
+            time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            s.id.addr.to_string(),
+            s.id.param.to_string(),
+            s.label.clone(),
+            value.to_string(),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// A gnuplot script plotting the series as steps in panels above each other, with the data
+/// in the script. It sets no terminal, e.g. `gnuplot -p plot.gp` shows it in a window.
+pub fn write_gnuplot(series: &[Series], mut out: impl Write) -> Result<()> {
+    writeln!(out, "# written by serial-pcap plot")?;
+    // gnuplot fails on an empty data block, so the series without values are left out
+    let (series, empty): (Vec<_>, Vec<_>) = series.iter().partition(|s| !s.points.is_empty());
+    for s in empty {
+        writeln!(out, "# no values of {}", s.label)?;
+    }
+    if series.is_empty() {
+        bail!("None of the parameters were seen in the capture.");
+    }
+    for (n, s) in series.iter().enumerate() {
+        writeln!(out, "$s{n} << EOD")?;
+        for (time, value) in &s.points {
+            let secs = time.timestamp_micros() as f64 / 1e6;
+            writeln!(out, "{secs:.6} {value}")?;
+        }
+        writeln!(out, "EOD")?;
+    }
+    writeln!(out, "set xdata time")?;
+    writeln!(out, "set timefmt \"%s\"")?;
+    writeln!(out, "set format x \"%H:%M:%S\"")?;
+    writeln!(out, "set grid")?;
+    writeln!(out, "set multiplot layout {},1", series.len())?;
+    for (n, s) in series.iter().enumerate() {
+        writeln!(out, "set title \"{}\"", s.label.replace('"', "'"))?;
+        writeln!(out, "plot $s{n} using 1:2 with steps notitle")?;
+    }
+    writeln!(out, "unset multiplot")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn render_png(series: &[Series], filename: &str) -> Result<()> {
+    let mut script = format!(
+        "set terminal png size 900,{}\nset output \"{}\"\n",
+        200 * series.len().max(1),
+        filename.replace('"', "\\\"")
+    )
+    .into_bytes();
+    write_gnuplot(series, &mut script)?;
+    let mut child = Command::new("gnuplot")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to start gnuplot, which renders the PNG charts.")?;
+    let mut stdin = child.stdin.take().context("gnuplot has no stdin pipe.")?;
+    stdin.write_all(&script)?;
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("gnuplot exited with {status}.");
+    }
+    Ok(())
+}
+
+/// The size of an SVG chart
+#[derive(Debug, Copy, Clone)]
+pub struct SvgSize {
+    pub width: u32,
+    /// The height of the panel of each series
+    pub panel_height: u32,
+}
+
+impl Default for SvgSize {
+    fn default() -> Self {
+        Self {
+            width: 900,
+            panel_height: 200,
+        }
+    }
+}
+
+/// Margins around the plot area of a panel
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 30.0;
+const BOTTOM: f64 = 30.0;
+const TICKS: usize = 5;
+
+/// An SVG chart with the series as steps in panels above each other, on a common time axis
+pub fn write_svg(series: &[Series], mut out: impl Write, size: SvgSize) -> Result<()> {
+    let times = series.iter().flat_map(|s| s.points.iter().map(|p| p.0));
+    let (start, end) = match (times.clone().min(), times.max()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => bail!("None of the parameters were seen in the capture."),
+    };
+    let span = ((end - start).num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6).max(1e-3);
+    let (width, panel_height) = (size.width as f64, size.panel_height as f64);
+    let plot_width = width - LEFT - RIGHT;
+    let plot_height = panel_height - TOP - BOTTOM;
+    let x = |time: DateTime<Utc>| {
+        let secs = (time - start).num_microseconds().unwrap_or(0) as f64 / 1e6;
+        LEFT + secs / span * plot_width
+    };
+
+    // the tick labels of a short capture need the fractions of a second to differ
+    let time_format = match span < 10.0 * TICKS as f64 {
+        true => "%H:%M:%S%.3f",
+        false => "%H:%M:%S",
+    };
+    let mut svg = String::new();
+    let height = panel_height * series.len() as f64;
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif" font-size="11">"#
+    )?;
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+    for (n, s) in series.iter().enumerate() {
+        let top = n as f64 * panel_height + TOP;
+        let bottom = top + plot_height;
+        writeln!(
+            svg,
+            r#"<text x="{LEFT}" y="{}" font-size="13">{}</text>"#,
+            top - 10.0,
+            escape(&s.label)
+        )?;
+        writeln!(
+            svg,
+            r##"<rect x="{LEFT}" y="{top}" width="{plot_width}" height="{plot_height}" fill="none" stroke="#888"/>"##
+        )?;
+        // the time axis
+        for i in 0..=TICKS {
+            let tx = LEFT + plot_width * i as f64 / TICKS as f64;
+            let time = start
+                + chrono::Duration::microseconds((span * 1e6) as i64 * i as i64 / TICKS as i64);
+            writeln!(
+                svg,
+                r##"<line x1="{tx:.1}" y1="{top}" x2="{tx:.1}" y2="{bottom}" stroke="#ddd"/>"##
+            )?;
+            writeln!(
+                svg,
+                r#"<text x="{tx:.1}" y="{}" text-anchor="middle">{}</text>"#,
+                bottom + 15.0,
+                time.format(time_format)
+            )?;
+        }
+        let Some(min) = s.points.iter().map(|p| p.1).min() else {
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="middle">no values</text>"#,
+                LEFT + plot_width / 2.0,
+                top + plot_height / 2.0
+            )?;
+            continue;
+        };
+        let max = s.points.iter().map(|p| p.1).max().unwrap_or(min);
+        // a constant value is drawn in the middle of the panel
+        let (lo, hi) = match min == max {
+            true => (min as f64 - 1.0, max as f64 + 1.0),
+            false => (min as f64, max as f64),
+        };
+        let y = |value: f64| bottom - (value - lo) / (hi - lo) * plot_height;
+        for i in 0..=TICKS {
+            let value = lo + (hi - lo) * i as f64 / TICKS as f64;
+            writeln!(
+                svg,
+                r##"<line x1="{LEFT}" y1="{0:.1}" x2="{1}" y2="{0:.1}" stroke="#ddd"/>"##,
+                y(value),
+                LEFT + plot_width
+            )?;
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{:.1}" text-anchor="end">{}</text>"#,
+                LEFT - 5.0,
+                y(value) + 4.0,
+                format_value(value)
+            )?;
+        }
+        // the value holds until the next one is seen
+        let mut path = String::new();
+        for (i, (time, value)) in s.points.iter().enumerate() {
+            let (px, py) = (x(*time), y(*value as f64));
+            match i {
+                0 => write!(path, "M{px:.1},{py:.1}")?,
+                _ => write!(path, " H{px:.1} V{py:.1}")?,
+            }
+        }
+        writeln!(
+            svg,
+            r##"<path d="{path}" fill="none" stroke="#1f77b4" stroke-width="1.5"/>"##
+        )?;
+    }
+    writeln!(svg, "</svg>")?;
+    out.write_all(svg.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+fn format_value(value: f64) -> String {
+    match value.fract() == 0.0 {
+        true => format!("{value:.0}"),
+        false => format!("{value:.1}"),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
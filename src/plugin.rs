@@ -0,0 +1,195 @@
+//! Protocol decoders loaded as WebAssembly modules, for the proprietary bus protocols which
+//! serial-pcap doesn't know, without building serial-pcap with them.
+//!
+//! A plugin is a WASM module, e.g. a Rust crate built for `wasm32-unknown-unknown`, with the
+//! exports
+//!
+//! - `memory`, which the data is passed in
+//! - `alloc(len: i32) -> i32`, a buffer for `len` bytes of packet data. The host writes the
+//!   data to it just before calling `feed`, so the plugin can hand out the same buffer each time.
+//! - `feed(bus: i32, ch: i32, time_us: i64, ptr: i32, len: i32)`, the data of a packet. The
+//!   channel is 0 for ctrl and 1 for node, and the time is in microseconds since the Unix epoch.
+//! - `finish()`, optional, called after the last packet
+//!
+//! The plugin passes back what it decoded with the import `serial_pcap.emit(time_us: i64,
+//! ptr: i32, len: i32)`, a line of UTF-8 text at the time. Each call is limited to
+//! [`FUEL_PER_CALL`] units of fuel, roughly WASM instructions, so a plugin stuck in a loop
+//! fails instead of hanging the decoding.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, TypedFunc,
+};
+
+use crate::{SerialPacket, SerialPacketReader, UartTxChannel};
+
+/// The fuel for each call into the plugin
+pub const FUEL_PER_CALL: u64 = 1_000_000_000;
+
+/// A line of text emitted by a plugin
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginEvent {
+    pub time: DateTime<Utc>,
+    pub text: String,
+}
+
+/// The emitted events, until they are taken after the call
+#[derive(Default)]
+struct PluginState {
+    events: Vec<PluginEvent>,
+}
+
+/// A loaded decoder plugin
+pub struct DecoderPlugin {
+    store: Store<PluginState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    feed: TypedFunc<(i32, i32, i64, i32, i32), ()>,
+    finish: Option<TypedFunc<(), ()>>,
+}
+
+impl DecoderPlugin {
+    /// Load a plugin from a `.wasm` file, or a `.wat` file in the text format
+    pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let wasm = std::fs::read(filename)
+            .with_context(|| format!("Failed to read {}.", filename.display()))?;
+        Self::new(&wasm).with_context(|| format!("Failed to load {}.", filename.display()))
+    }
+
+    /// Load a plugin from the binary or the text format of the module
+    pub fn new(wasm: &[u8]) -> Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, wasm)?;
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("serial_pcap", "emit", emit)?;
+        let mut store = Store::new(&engine, PluginState::default());
+        store.set_fuel(FUEL_PER_CALL)?;
+        let instance: Instance = linker.instantiate(&mut store, &module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("The plugin doesn't export its memory.")?;
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(anyhow::Error::from)
+            .context("The plugin has no alloc function.")?;
+        let feed = instance
+            .get_typed_func(&mut store, "feed")
+            .map_err(anyhow::Error::from)
+            .context("The plugin has no feed function.")?;
+        let finish = match instance.get_func(&mut store, "finish") {
+            Some(f) => Some(
+                f.typed(&store)
+                    .map_err(anyhow::Error::from)
+                    .context("Invalid finish function.")?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            feed,
+            finish,
+        })
+    }
+
+    /// Pass a packet to the plugin, and return the events it emitted.
+    pub fn feed(&mut self, pkt: &SerialPacket) -> Result<Vec<PluginEvent>> {
+        let len = i32::try_from(pkt.data.len()).context("Packet too long.")?;
+        let ch = match pkt.ch {
+            UartTxChannel::Ctrl => 0,
+            UartTxChannel::Node => 1,
+        };
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(anyhow::Error::from)
+            .context("The plugin alloc failed.")?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &pkt.data)
+            .map_err(anyhow::Error::from)
+            .context("The plugin returned an invalid buffer.")?;
+        let time_us = pkt.time.timestamp_micros();
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        self.feed
+            .call(&mut self.store, (pkt.bus.into(), ch, time_us, ptr, len))
+            .map_err(anyhow::Error::from)
+            .context("The plugin feed failed.")?;
+        Ok(std::mem::take(&mut self.store.data_mut().events))
+    }
+
+    /// Tell the plugin that there are no more packets, and return the events it emitted.
+    pub fn finish(&mut self) -> Result<Vec<PluginEvent>> {
+        if let Some(finish) = &self.finish {
+            self.store.set_fuel(FUEL_PER_CALL)?;
+            finish
+                .call(&mut self.store, ())
+                .map_err(anyhow::Error::from)
+                .context("The plugin finish failed.")?;
+        }
+        Ok(std::mem::take(&mut self.store.data_mut().events))
+    }
+}
+
+fn emit(
+    mut caller: Caller<'_, PluginState>,
+    time_us: i64,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<()> {
+    emit_event(&mut caller, time_us, ptr, len).map_err(wasmtime::Error::from_anyhow)
+}
+
+fn emit_event(
+    caller: &mut Caller<'_, PluginState>,
+    time_us: i64,
+    ptr: i32,
+    len: i32,
+) -> Result<()> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        bail!("The plugin doesn't export its memory.");
+    };
+    let (start, len) = (ptr as u32 as usize, len as u32 as usize);
+    let text = memory
+        .data(&*caller)
+        .get(start..start + len)
+        .context("The emitted text is outside of the plugin memory.")?;
+    let text = String::from_utf8_lossy(text).into_owned();
+    let nanos = (time_us.rem_euclid(1_000_000) * 1000) as u32;
+    let time = DateTime::from_timestamp(time_us.div_euclid(1_000_000), nanos)
+        .context("Invalid emitted time.")?;
+    caller.data_mut().events.push(PluginEvent { time, text });
+    Ok(())
+}
+
+/// Decode all packets from `reader` with the plugin, and write the events to `out` with a
+/// line each, the time followed by the text. Returns the number of events.
+pub fn decode_with_plugin<R: Read>(
+    reader: &mut SerialPacketReader<R>,
+    plugin: &mut DecoderPlugin,
+    mut out: impl Write,
+) -> Result<u64> {
+    let mut count = 0;
+    let mut write = |events: Vec<PluginEvent>| -> Result<()> {
+        for event in events {
+            let time = event.time.to_rfc3339_opts(SecondsFormat::Micros, true);
+            writeln!(out, "{time} {}", event.text)?;
+            count += 1;
+        }
+        Ok(())
+    };
+    for pkt in reader {
+        write(plugin.feed(&pkt?)?)?;
+    }
+    write(plugin.finish()?)?;
+    out.flush()?;
+    Ok(count)
+}
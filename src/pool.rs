@@ -0,0 +1,55 @@
+//! A pool of read buffers shared by the capture sources and the recorder, so a capture of
+//! several channels doesn't allocate a buffer for each read.
+//!
+//! A source takes a buffer with [`BufferPool::get`], reads into it and queues it, and the
+//! recorder hands it back with [`BufferPool::put`] once the data is copied into the packet
+//! it is building. Buffers which are lost on the way, e.g. evicted from a full queue, are
+//! just freed, and the pool allocates new ones.
+
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+
+/// The capacity of the buffers, and so the most bytes of one read
+pub const BUFFER_LEN: usize = 1024;
+/// The most idle buffers kept, the rest are freed
+const MAX_IDLE: usize = 64;
+
+/// A shared handle to the pool, cloned for each source
+#[derive(Debug, Clone, Default)]
+pub struct BufferPool {
+    idle: Arc<Mutex<Vec<BytesMut>>>,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty buffer with room for [`BUFFER_LEN`] bytes
+    pub fn get(&self) -> BytesMut {
+        self.idle
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(BUFFER_LEN))
+    }
+
+    /// Return a buffer to the pool. Buffers with less room than [`BUFFER_LEN`], e.g. the
+    /// parts of a split buffer, are freed instead.
+    pub fn put(&self, mut buf: BytesMut) {
+        if buf.capacity() < BUFFER_LEN {
+            return;
+        }
+        buf.clear();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(buf);
+        }
+    }
+
+    /// The number of buffers ready for reuse
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+use crate::clock::CaptureClock;
+
+/// Corrections larger than this are logged, since they mean that the clock was off
+const LOG_CORRECTION: f64 = 1e-3;
+
+// from linux/pps.h
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct PpsKtime {
+    sec: i64,
+    nsec: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct PpsKinfo {
+    assert_sequence: u32,
+    clear_sequence: u32,
+    assert_tu: PpsKtime,
+    clear_tu: PpsKtime,
+    current_mode: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct PpsFdata {
+    info: PpsKinfo,
+    timeout: PpsKtime,
+}
+
+/// _IOWR('p', 0xa4, struct pps_fdata *)
+const PPS_FETCH: u64 = 0xc000_70a4 | ((std::mem::size_of::<usize>() as u64) << 16);
+
+/// A Linux kernel PPS device, e.g. `/dev/pps0` from a GPS receiver
+pub struct PpsSource {
+    dev: File,
+    path: String,
+}
+
+impl PpsSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let dev = File::open(path).with_context(|| format!("Failed to open {path}."))?;
+        Ok(Self {
+            dev,
+            path: path.into(),
+        })
+    }
+
+    /// Wait for the next pulse, and return its system clock time.
+    pub fn fetch(&self, last_sequence: &mut u32) -> Result<SystemTime> {
+        let mut data = PpsFdata {
+            timeout: PpsKtime {
+                sec: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // SAFETY: PPS_FETCH reads and writes a pps_fdata struct, which PpsFdata mirrors
+        let res = unsafe { libc::ioctl(self.dev.as_raw_fd(), PPS_FETCH as _, &mut data) };
+        if res < 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(err).with_context(|| format!("No pulse from {}.", self.path));
+        }
+        let info = data.info;
+        if info.assert_sequence == *last_sequence {
+            bail!("No new pulse from {}.", self.path);
+        }
+        *last_sequence = info.assert_sequence;
+        let t = info.assert_tu;
+        Ok(SystemTime::UNIX_EPOCH + Duration::new(t.sec as u64, t.nsec as u32))
+    }
+
+    /// Discipline `clock` with the pulses, until the device fails.
+    pub fn run(self, clock: CaptureClock) -> Result<()> {
+        let mut sequence = 0;
+        let mut locked = false;
+        loop {
+            let pulse = match self.fetch(&mut sequence) {
+                Ok(pulse) => pulse,
+                Err(e) if locked => {
+                    warn!("Lost the PPS signal: {e:#}");
+                    locked = false;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let correction = clock.apply_pps(pulse);
+            if !locked {
+                info!("Locked to {}, correction {correction:+.6} s.", self.path);
+                locked = true;
+            } else if correction.abs() > LOG_CORRECTION {
+                warn!("PPS correction {correction:+.6} s.");
+            }
+        }
+    }
+}
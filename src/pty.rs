@@ -0,0 +1,86 @@
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::{SerialPort, SerialStream};
+
+use crate::UartTxChannel;
+
+/// Two virtual serial ports connected back to back, like `socat pty pty`.
+///
+/// Everything written to one of the ports can be read from the other one, and a copy of the
+/// traffic is passed to a tap callback. This makes it possible to run the simulator and the
+/// capture chain without any serial hardware.
+pub struct PtyPair {
+    ctrl: Pty,
+    node: Pty,
+}
+
+struct Pty {
+    master: SerialStream,
+    // Reads from the master fail when nobody has the slave side open, so keep it open here
+    _slave: SerialStream,
+    path: String,
+}
+
+impl Pty {
+    fn new() -> Result<Self> {
+        let (master, slave) = SerialStream::pair().context("Failed to create PTY.")?;
+        let path = slave.name().context("PTY slave has no path.")?;
+        Ok(Self {
+            master,
+            _slave: slave,
+            path,
+        })
+    }
+}
+
+impl PtyPair {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            ctrl: Pty::new()?,
+            node: Pty::new()?,
+        })
+    }
+
+    /// The port where the bus controller should be connected.
+    pub fn ctrl_path(&self) -> &str {
+        &self.ctrl.path
+    }
+
+    /// The port where the bus nodes should be connected.
+    pub fn node_path(&self) -> &str {
+        &self.node.path
+    }
+
+    /// Forward data between the two ports until an error occurs, calling `tap`
+    /// with the data sent in each direction.
+    pub async fn run(
+        self,
+        mut tap: impl AsyncFnMut(UartTxChannel, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let (mut ctrl_rx, mut ctrl_tx) = tokio::io::split(self.ctrl.master);
+        let (mut node_rx, mut node_tx) = tokio::io::split(self.node.master);
+        let mut ctrl_buf = BytesMut::with_capacity(64);
+        let mut node_buf = BytesMut::with_capacity(64);
+        loop {
+            tokio::select! {
+                r = ctrl_rx.read_buf(&mut ctrl_buf) => {
+                    if r.context("Read from ctrl PTY failed.")? == 0 {
+                        bail!("The ctrl PTY was closed.");
+                    }
+                    node_tx.write_all(&ctrl_buf).await.context("Write to node PTY failed.")?;
+                    tap(UartTxChannel::Ctrl, &ctrl_buf).await?;
+                    ctrl_buf.clear();
+                }
+                r = node_rx.read_buf(&mut node_buf) => {
+                    if r.context("Read from node PTY failed.")? == 0 {
+                        bail!("The node PTY was closed.");
+                    }
+                    ctrl_tx.write_all(&node_buf).await.context("Write to ctrl PTY failed.")?;
+                    tap(UartTxChannel::Node, &node_buf).await?;
+                    node_buf.clear();
+                }
+            }
+        }
+    }
+}
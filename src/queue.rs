@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use tokio::sync::Notify;
+
+/// What to do when data arrives faster than the queue is drained
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue, which stops reading from the sender
+    #[default]
+    Block,
+    /// Discard the oldest queued item and count it as dropped
+    DropOldest,
+    /// Fail the send, which stops the capture
+    Abort,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    not_empty: Notify,
+    not_full: Notify,
+}
+
+/// Create a bounded multi-producer, single-consumer queue
+/// which handles overflow according to `policy`.
+pub fn bounded<T>(capacity: usize, policy: OverflowPolicy) -> (QueueSender<T>, QueueReceiver<T>) {
+    assert!(capacity > 0, "Queue capacity must be at least one.");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        capacity,
+        policy,
+        dropped: AtomicU64::new(0),
+        not_empty: Notify::new(),
+        not_full: Notify::new(),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    /// Queue `item`, handling a full queue according to the overflow policy.
+    /// Returns the item discarded to make room, if any.
+    ///
+    /// Fails if the receiver is gone, or if the queue is full and the policy is `Abort`.
+    pub async fn send(&self, item: T) -> Result<Option<T>> {
+        let shared = &self.shared;
+        let mut item = Some(item);
+        let mut evicted = None;
+        loop {
+            let notified = shared.not_full.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut state = shared.state.lock().unwrap();
+                if !state.receiver_alive {
+                    bail!("Queue receiver closed.");
+                }
+                if state.items.len() >= shared.capacity {
+                    match shared.policy {
+                        OverflowPolicy::Block => {}
+                        OverflowPolicy::DropOldest => {
+                            evicted = state.items.pop_front();
+                            shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        OverflowPolicy::Abort => {
+                            bail!("Queue overflow, {} items queued.", state.items.len())
+                        }
+                    }
+                }
+                if state.items.len() < shared.capacity {
+                    state.items.push_back(item.take().unwrap());
+                    drop(state);
+                    shared.not_empty.notify_one();
+                    return Ok(evicted);
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Number of items discarded by the `DropOldest` policy
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.not_empty.notify_one();
+        }
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Receive the next item, or `None` when the queue is empty and all senders are gone.
+    pub async fn recv(&mut self) -> Option<T> {
+        let shared = &self.shared;
+        loop {
+            let notified = shared.not_empty.notified();
+            {
+                let mut state = shared.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    drop(state);
+                    shared.not_full.notify_waiters();
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Take the next item if there is one, without waiting
+    pub fn try_recv(&mut self) -> Option<T> {
+        let item = self.shared.state.lock().unwrap().items.pop_front();
+        if item.is_some() {
+            self.shared.not_full.notify_waiters();
+        }
+        item
+    }
+
+    /// Number of items discarded by the `DropOldest` policy
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_alive = false;
+        self.shared.not_full.notify_waiters();
+    }
+}
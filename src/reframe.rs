@@ -0,0 +1,130 @@
+//! Re-framing of the captured byte stream on the idle time between bytes.
+//!
+//! How the bytes are split into packets depends on the recorder, e.g. on its 5 ms read timeout
+//! or on the gaps measured by the capture device. [`FrameReader`] joins and splits the packets
+//! again on a fixed idle gap, so the analysis doesn't depend on how the capture was made.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+
+use crate::{SerialPacket, UartTxChannel};
+
+/// The bytes sent on one channel without an idle gap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub bus: u8,
+    pub ch: UartTxChannel,
+    /// Time of the first byte
+    pub time: DateTime<Utc>,
+    pub data: BytesMut,
+}
+
+struct PendingFrame {
+    frame: Frame,
+    /// Estimated end of the last byte
+    end: DateTime<Utc>,
+}
+
+/// Re-frames the packets from a capture into [`Frame`]s, separately for each channel and bus.
+///
+/// A frame ends when its channel has been idle for longer than the gap. The frames are yielded
+/// in the order of their first byte, once they have ended.
+pub struct FrameReader<I> {
+    packets: I,
+    gap: chrono::Duration,
+    byte_time: chrono::Duration,
+    pending: Vec<PendingFrame>,
+    ready: Vec<Frame>,
+    done: bool,
+}
+
+impl<I: Iterator<Item = Result<SerialPacket>>> FrameReader<I> {
+    /// Split the packets on idle times longer than `gap`
+    pub fn new(packets: I, gap: Duration) -> Self {
+        Self {
+            packets,
+            gap: chrono::Duration::from_std(gap).unwrap_or_else(|_| chrono::Duration::max_value()),
+            byte_time: chrono::Duration::zero(),
+            pending: vec![],
+            ready: vec![],
+            done: false,
+        }
+    }
+
+    /// The time to send one byte. The packet timestamps are for the first byte, so without
+    /// it the idle time is measured from the start of the previous packet.
+    pub fn with_byte_time(mut self, byte_time: Duration) -> Self {
+        self.byte_time =
+            chrono::Duration::from_std(byte_time).unwrap_or_else(|_| chrono::Duration::zero());
+        self
+    }
+
+    fn feed(&mut self, pkt: SerialPacket) {
+        // the frames which have been idle for longer than the gap can't continue
+        let (ended, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|p| pkt.time - p.end > self.gap);
+        self.pending = pending;
+        self.finish(ended);
+
+        let end = pkt.time + self.byte_time * pkt.data.len() as i32;
+        let same_channel = |p: &&mut PendingFrame| p.frame.bus == pkt.bus && p.frame.ch == pkt.ch;
+        match self.pending.iter_mut().find(same_channel) {
+            Some(p) => {
+                p.frame.data.extend_from_slice(&pkt.data);
+                p.end = p.end.max(end);
+            }
+            None => self.pending.push(PendingFrame {
+                frame: Frame {
+                    bus: pkt.bus,
+                    ch: pkt.ch,
+                    time: pkt.time,
+                    data: pkt.data,
+                },
+                end,
+            }),
+        }
+    }
+
+    /// Queue the ended frames
+    fn finish(&mut self, ended: Vec<PendingFrame>) {
+        self.ready.extend(ended.into_iter().map(|p| p.frame));
+        self.ready.sort_by_key(|f| f.time);
+    }
+
+    /// The first ended frame, unless a frame which started before it is still pending
+    fn pop_ready(&mut self) -> Option<Frame> {
+        let first = self.ready.first()?;
+        if self.pending.iter().any(|p| p.frame.time < first.time) {
+            return None;
+        }
+        Some(self.ready.remove(0))
+    }
+}
+
+impl<I: Iterator<Item = Result<SerialPacket>>> Iterator for FrameReader<I> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.pop_ready() {
+                return Some(Ok(frame));
+            }
+            if self.done {
+                return None;
+            }
+            match self.packets.next() {
+                Some(Ok(pkt)) => self.feed(pkt),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    let pending = std::mem::take(&mut self.pending);
+                    self.finish(pending);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tokio::io::AsyncRead;
+use tokio::net::ToSocketAddrs;
+use tracing::{info, warn};
+
+use crate::async_reader::AsyncSerialPacketReader;
+use crate::{RecordRef, SerialPacket, SerialPacketWriter};
+
+/// Clients which don't accept data for this long are disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between the attempts to connect to the forwarding destination
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+type Clients = Arc<Mutex<Vec<(String, SerialPacketWriter<TcpStream>)>>>;
+
+/// Streams the captured packets in pcap format to the TCP clients connected to `listener`.
+///
+/// Each client gets a complete pcap stream, starting with the packets captured
+/// after it connected, so e.g. `nc host port | wireshark -k -i -` works as a client.
+pub fn serve(listener: TcpListener, packets: Receiver<SerialPacket>) -> Result<()> {
+    let clients = Clients::default();
+    let acceptor = clients.clone();
+    std::thread::Builder::new()
+        .name("capture-server".into())
+        .spawn(move || accept_clients(listener, acceptor))?;
+
+    for pkt in packets {
+        let mut clients = clients.lock().unwrap();
+        clients.retain_mut(|(peer, writer)| {
+            let res = writer
+                .write_bus_packet_time(pkt.bus, &pkt.data, pkt.ch, pkt.time.into())
+                .and_then(|_| writer.flush());
+            if let Err(e) = &res {
+                info!("Disconnecting {peer}: {e:#}");
+            }
+            res.is_ok()
+        });
+    }
+    // disconnect the clients, so they see the end of the capture
+    clients.lock().unwrap().clear();
+    Ok(())
+}
+
+fn accept_clients(listener: TcpListener, clients: Clients) {
+    for stream in listener.incoming() {
+        let res = stream.map_err(anyhow::Error::from).and_then(|stream| {
+            let peer = stream.peer_addr()?.to_string();
+            stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            let mut writer = SerialPacketWriter::new(stream)?;
+            // the client is registered once it has received the header
+            let mut clients = clients.lock().unwrap();
+            writer.flush()?;
+            info!("Capture client {peer} connected.");
+            clients.push((peer, writer));
+            Ok(())
+        });
+        if let Err(e) = res {
+            warn!("Failed to accept capture client: {e:#}");
+        }
+    }
+}
+
+/// Streams the captured packets in pcap format to the TCP server at `addr`, e.g. a
+/// `serial-pcap receive --listen` on the analysis machine.
+///
+/// The connection is retried every few seconds when it fails, and each connection gets a complete
+/// pcap stream. The packets captured while disconnected aren't forwarded.
+pub fn forward(addr: &str, packets: Receiver<SerialPacket>) -> Result<()> {
+    let mut writer = None;
+    let mut next_attempt = Instant::now();
+    let mut skipped = 0;
+    for pkt in packets {
+        if writer.is_none() && Instant::now() >= next_attempt {
+            match connect(addr) {
+                Ok(w) => {
+                    info!("Forwarding the capture to {addr}.");
+                    if skipped > 0 {
+                        warn!("{skipped} packets weren't forwarded while disconnected.");
+                        skipped = 0;
+                    }
+                    writer = Some(w);
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {addr}: {e:#}");
+                    next_attempt = Instant::now() + RECONNECT_INTERVAL;
+                }
+            }
+        }
+        let Some(w) = writer.as_mut() else {
+            skipped += 1;
+            continue;
+        };
+        let res = w
+            .write_bus_packet_time(pkt.bus, &pkt.data, pkt.ch, pkt.time.into())
+            .and_then(|_| w.flush());
+        if let Err(e) = res {
+            warn!("Lost the connection to {addr}: {e:#}");
+            writer = None;
+            skipped += 1;
+            next_attempt = Instant::now() + RECONNECT_INTERVAL;
+        }
+    }
+    Ok(())
+}
+
+fn connect(addr: &str) -> Result<SerialPacketWriter<TcpStream>> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let mut writer = SerialPacketWriter::new(stream)?;
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Connect to a capture server and write the received packets and markers to `writer`,
+/// until the server closes the connection.
+pub async fn receive<W: std::io::Write>(
+    addr: impl ToSocketAddrs,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<()> {
+    let stream = tokio::net::TcpStream::connect(addr)
+        .await
+        .context("Failed to connect to the capture server.")?;
+    receive_stream(stream, writer).await
+}
+
+/// Wait for a capture forwarded with `--forward` on `listener`, and write the received packets
+/// to `writer` until the capture closes the connection.
+pub async fn receive_forwarded<W: std::io::Write>(
+    listener: tokio::net::TcpListener,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<()> {
+    let (stream, peer) = listener.accept().await?;
+    info!("Receiving the capture forwarded by {peer}.");
+    receive_stream(stream, writer).await
+}
+
+async fn receive_stream<W: std::io::Write>(
+    stream: impl AsyncRead + Unpin,
+    writer: &mut SerialPacketWriter<W>,
+) -> Result<()> {
+    let mut reader = AsyncSerialPacketReader::new(stream).await?;
+    while let Some(record) = reader.next_record_ref().await? {
+        match record {
+            RecordRef::Packet(pkt) => {
+                writer.write_bus_packet_time(pkt.bus, pkt.data, pkt.ch, pkt.time.into())?
+            }
+            RecordRef::Marker(marker) => writer.write_marker(&marker)?,
+            RecordRef::Trigger(trigger) => writer.write_trigger(&trigger)?,
+        }
+        // the traffic is slow, so keep the file current for the readers
+        writer.flush()?;
+    }
+    Ok(())
+}
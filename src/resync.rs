@@ -0,0 +1,157 @@
+//! Recovery from corrupt records in a capture.
+//!
+//! A capture with a mangled record, e.g. from a disk error or a crash while it was written,
+//! can't be read past that record with the normal reader. [`ResyncReader`] skips a record
+//! which isn't an IPv4/UDP packet of this tool, and if the record headers themselves are
+//! broken, scans forward byte by byte until a record header with a valid packet after it is
+//! found. The skipped data is counted in [`ResyncStats`].
+
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use rpcap::{CapturedPacket, FileOptions};
+
+use crate::{parse_record, PCAP_RECORD_HEADER_LEN};
+
+const HEADER_LEN: usize = PCAP_RECORD_HEADER_LEN as usize;
+const READ_SIZE: usize = 64 * 1024;
+
+/// The data skipped while reading a capture
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResyncStats {
+    /// Bytes which weren't part of a valid record
+    pub skipped_bytes: u64,
+    /// Number of places in the file where data was skipped
+    pub gaps: u64,
+}
+
+/// A pcap record reader which skips the corrupt records, for the data after the file header.
+pub struct ResyncReader<R> {
+    reader: R,
+    /// Bytes read from the file, the unread ones start at `pos`
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    options: FileOptions,
+    /// `pos` is at the start of a record, the one before it was valid
+    in_sync: bool,
+    stats: ResyncStats,
+}
+
+impl<R: Read> ResyncReader<R> {
+    /// `reader` is positioned after the pcap file header, which had the `options`
+    pub fn new(reader: R, options: FileOptions) -> Self {
+        Self {
+            reader,
+            buf: vec![],
+            pos: 0,
+            eof: false,
+            options,
+            in_sync: true,
+            stats: ResyncStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ResyncStats {
+        self.stats
+    }
+
+    /// The next valid record, or None at the end of the file
+    pub fn next_record(&mut self) -> Result<Option<CapturedPacket<'_>>> {
+        let (start, len, time) = loop {
+            if !self.fill(HEADER_LEN)? {
+                self.skip(self.buf.len() - self.pos);
+                return Ok(None);
+            }
+            let Some((time, len)) = self.record_header() else {
+                self.skip(1);
+                continue;
+            };
+            if !self.fill(HEADER_LEN + len)? {
+                // the file ends in the middle of the record, or the header is garbage
+                match self.in_sync {
+                    true => self.skip(self.buf.len() - self.pos),
+                    false => self.skip(1),
+                }
+                continue;
+            }
+            let start = self.pos + HEADER_LEN;
+            if parse_record(&self.buf[start..start + len], time.into()).is_ok() {
+                break (start, len, time);
+            }
+            // a bad packet after a valid record is skipped as a whole, otherwise the header
+            // may be garbage too
+            match self.in_sync {
+                true => self.skip(HEADER_LEN + len),
+                false => self.skip(1),
+            }
+        };
+        self.pos = start + len;
+        self.in_sync = true;
+        Ok(Some(CapturedPacket {
+            time,
+            data: &self.buf[start..start + len],
+            orig_len: len,
+        }))
+    }
+
+    /// The time and the length of the record at `pos`, if the header is plausible
+    fn record_header(&self) -> Option<(SystemTime, usize)> {
+        let field = |i: usize| {
+            let bytes = self.buf[self.pos + 4 * i..self.pos + 4 * i + 4]
+                .try_into()
+                .unwrap();
+            match self.options.non_native_byte_order {
+                true => u32::from_ne_bytes(bytes).swap_bytes(),
+                false => u32::from_ne_bytes(bytes),
+            }
+        };
+        let (secs, frac, incl_len, orig_len) = (field(0), field(1), field(2), field(3));
+        let frac = match self.options.high_res_timestamps {
+            true if frac < 1_000_000_000 => Duration::from_nanos(frac.into()),
+            false if frac < 1_000_000 => Duration::from_micros(frac.into()),
+            _ => return None,
+        };
+        // the packets of this tool are never truncated
+        let len = incl_len as usize;
+        if incl_len != orig_len || len > self.options.snaplen {
+            return None;
+        }
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs.into()) + frac;
+        Some((time, len))
+    }
+
+    fn skip(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        if self.in_sync {
+            self.stats.gaps += 1;
+            self.in_sync = false;
+        }
+        self.pos += len;
+        self.stats.skipped_bytes += len as u64;
+    }
+
+    /// Read until `len` bytes are available at `pos`, returns false if the file ends before
+    fn fill(&mut self, len: usize) -> Result<bool> {
+        if self.pos > READ_SIZE {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        while self.buf.len() - self.pos < len && !self.eof {
+            let end = self.buf.len();
+            self.buf.resize(end + READ_SIZE, 0);
+            let n = loop {
+                match self.reader.read(&mut self.buf[end..]) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    r => break r.context("Pcap read error")?,
+                }
+            };
+            self.buf.truncate(end + n);
+            self.eof = n == 0;
+        }
+        Ok(self.buf.len() - self.pos >= len)
+    }
+}
@@ -0,0 +1,230 @@
+//! Ring buffer capture, which keeps only the last part of a long running capture.
+//!
+//! The capture is written to numbered segment files next to the capture file, e.g.
+//! `bus.00001.pcap`, `bus.00002.pcap` and so on. Each segment is a complete capture file with
+//! the header markers repeated at its start, and the oldest segments are deleted when the
+//! segments together exceed the size or age limit.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tracing::info;
+
+use crate::{Marker, SerialPacketWriter, UartTxChannel};
+
+/// The ring is split into this many segments, so at most a tenth of the limit is deleted at once
+pub const SEGMENTS: u32 = 10;
+
+/// How much of the capture to keep
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RingLimits {
+    /// Total size of the segments in bytes
+    pub max_size: Option<u64>,
+    /// Time since the end of the oldest segment
+    pub max_age: Option<Duration>,
+}
+
+struct Segment {
+    path: PathBuf,
+    size: u64,
+    /// When the last packet was written to it
+    closed: Instant,
+}
+
+/// Creates the segment files and deletes the old ones
+pub struct SegmentRing {
+    /// The capture file name, split into the part before and after the segment number
+    stem: PathBuf,
+    extension: String,
+    limits: RingLimits,
+    segments: VecDeque<Segment>,
+    next_seq: u64,
+    /// When the current segment was opened
+    opened: Instant,
+    header: Vec<Marker>,
+    descriptions: Vec<(u8, UartTxChannel, String)>,
+}
+
+impl SegmentRing {
+    /// A ring of segments named after `path`. Segments left by a previous capture with the
+    /// same name are kept, and count towards the limits.
+    pub fn new(path: impl AsRef<Path>, limits: RingLimits) -> Result<Self> {
+        if limits.max_size.is_none() && limits.max_age.is_none() {
+            bail!("The ring buffer needs a size or age limit.");
+        }
+        let path = path.as_ref();
+        let extension = match path.extension() {
+            Some(ext) => ext.to_string_lossy().into_owned(),
+            None => "pcap".to_string(),
+        };
+        let mut ring = Self {
+            stem: path.with_extension(""),
+            extension,
+            limits,
+            segments: VecDeque::new(),
+            next_seq: 1,
+            opened: Instant::now(),
+            header: vec![],
+            descriptions: vec![],
+        };
+        ring.find_segments()?;
+        Ok(ring)
+    }
+
+    /// Write `marker` at the start of every segment, e.g. the clock anchor
+    pub fn add_header_marker(&mut self, marker: Marker) {
+        self.header.push(marker);
+    }
+
+    /// Describe the channel in every pcapng segment, see
+    /// [`SerialPacketWriter::describe_channel`]
+    pub fn describe_channel(&mut self, bus: u8, ch: UartTxChannel, description: impl Into<String>) {
+        self.descriptions.push((bus, ch, description.into()));
+    }
+
+    /// The file name of segment number `seq`
+    pub fn segment_path(&self, seq: u64) -> PathBuf {
+        let mut name = self.stem.clone().into_os_string();
+        name.push(format!(".{seq:05}.{}", self.extension));
+        name.into()
+    }
+
+    /// The segment files currently in the ring, oldest first
+    pub fn segment_paths(&self) -> impl Iterator<Item = &Path> {
+        self.segments.iter().map(|s| s.path.as_path())
+    }
+
+    /// Start a new segment, with the header markers written
+    pub fn open_segment(&mut self) -> Result<SerialPacketWriter<File>> {
+        self.open_segment_at(Instant::now())
+    }
+
+    fn open_segment_at(&mut self, now: Instant) -> Result<SerialPacketWriter<File>> {
+        let path = self.segment_path(self.next_seq);
+        self.next_seq += 1;
+        let mut writer = match self.extension.as_str() {
+            "pcapng" => SerialPacketWriter::new_pcapng_file(&path)?,
+            _ => SerialPacketWriter::new_file(&path)?,
+        };
+        for (bus, ch, description) in &self.descriptions {
+            writer.describe_channel(*bus, *ch, description.as_str());
+        }
+        for marker in &self.header {
+            writer.write_marker(marker)?;
+        }
+        info!("Writing capture segment {}.", path.display());
+        self.opened = now;
+        self.segments.push_back(Segment {
+            path,
+            size: writer.bytes_written(),
+            closed: self.opened,
+        });
+        Ok(writer)
+    }
+
+    /// Replace `writer` with a new segment if the current one is full, and delete the
+    /// segments which are outside the limits. Returns true if a new segment was started.
+    pub fn rotate_if_due(
+        &mut self,
+        writer: &mut SerialPacketWriter<File>,
+        now: Instant,
+    ) -> Result<bool> {
+        let current = self.segments.back_mut().context("No segment is open.")?;
+        current.size = writer.bytes_written();
+        current.closed = now;
+        let full =
+            self.limits
+                .max_size
+                .is_some_and(|max| current.size >= max / SEGMENTS as u64)
+                || self.limits.max_age.is_some_and(|max| {
+                    now.saturating_duration_since(self.opened) >= max / SEGMENTS
+                });
+        if full {
+            writer.flush()?;
+            *writer = self.open_segment_at(now)?;
+        }
+        self.prune(now)?;
+        Ok(full)
+    }
+
+    /// Delete the oldest segments, but never the one being written
+    fn prune(&mut self, now: Instant) -> Result<()> {
+        while self.segments.len() > 1 {
+            let total: u64 = self.segments.iter().map(|s| s.size).sum();
+            let oldest = &self.segments[0];
+            let too_big = self.limits.max_size.is_some_and(|max| total > max);
+            let too_old = self
+                .limits
+                .max_age
+                .is_some_and(|max| now.saturating_duration_since(oldest.closed) > max);
+            if !too_big && !too_old {
+                break;
+            }
+            let oldest = self.segments.pop_front().unwrap();
+            info!("Deleting capture segment {}.", oldest.path.display());
+            match std::fs::remove_file(&oldest.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Failed to delete capture segment {}.",
+                            oldest.path.display()
+                        )
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the segments from a previous capture, so they are pruned as well
+    fn find_segments(&mut self) -> Result<()> {
+        let dir = match self.stem.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = match self.stem.file_name() {
+            Some(name) => format!("{}.", name.to_string_lossy()),
+            None => bail!("The capture file name is missing."),
+        };
+        let suffix = format!(".{}", self.extension);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to list {}.", dir.display())),
+        };
+        let now = Instant::now();
+        let mut found = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(seq) = name
+                .strip_prefix(&prefix)
+                .and_then(|n| n.strip_suffix(&suffix))
+                .filter(|n| n.len() >= 5 && n.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            let meta = entry.metadata()?;
+            let age = meta.modified()?.elapsed().unwrap_or_default();
+            found.push((
+                seq,
+                Segment {
+                    path: self.segment_path(seq),
+                    size: meta.len(),
+                    closed: now.checked_sub(age).unwrap_or(now),
+                },
+            ));
+        }
+        found.sort_by_key(|(seq, _)| *seq);
+        if let Some((seq, _)) = found.last() {
+            self.next_seq = seq + 1;
+        }
+        self.segments = found.into_iter().map(|(_, s)| s).collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! User scripts called for each decoded transaction, for ad-hoc analyses like custom counters or
+//! conditional printing without changing serial-pcap.
+//!
+//! The scripts are written in [Rhai](https://rhai.rs). The top level of the script is run once
+//! when it is loaded, and the variables declared there keep their values between the calls, e.g.
+//!
+//! ```text
+//! let slow = 0;
+//!
+//! fn on_transaction(t) {
+//!     if t.latency_ms > 50.0 {
+//!         slow += 1;
+//!         print(`${t.resp_time} slow response from ${t.addr}: ${t.latency_ms} ms`);
+//!     }
+//! }
+//!
+//! fn on_end() {
+//!     print(`${slow} slow responses`);
+//! }
+//! ```
+//!
+//! `on_transaction(t)` is called for each transaction, with a map of
+//!
+//! - `addr` and `param`, the node address and parameter number
+//! - `write`, true for write commands
+//! - `value`, the value read or written, or `()` if the command failed
+//! - `ok`, false if the command failed, with the reason in `error`
+//! - `cmd_time` and `resp_time`, RFC 3339 times of the command and the response
+//! - `latency_ms`, the time from the command to the response in milliseconds
+//!
+//! and `on_end()`, if the script defines it, after the last one.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::SecondsFormat;
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::decode::{BusCommand, Transaction};
+
+/// A loaded transaction script
+pub struct TransactionScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    has_end: bool,
+}
+
+impl TransactionScript {
+    /// Load a script from a file
+    pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let source = std::fs::read_to_string(filename)
+            .with_context(|| format!("Failed to read {}.", filename.display()))?;
+        Self::new(&source).with_context(|| format!("Failed to load {}.", filename.display()))
+    }
+
+    /// Compile the script and run its top level. The script prints to stdout.
+    pub fn new(source: &str) -> Result<Self> {
+        Self::with_print(source, |s| println!("{s}"))
+    }
+
+    /// Like [`new`](Self::new), with the lines printed by the script passed to `print`
+    pub fn with_print(source: &str, print: impl Fn(&str) + 'static) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.on_print(print);
+        let ast = engine.compile(source)?;
+        let has_fn = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == params)
+        };
+        if !has_fn("on_transaction", 1) {
+            return Err(anyhow!("The script has no on_transaction(t) function."));
+        }
+        let has_end = has_fn("on_end", 0);
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| anyhow!("{e}"))?;
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            has_end,
+        })
+    }
+
+    /// Call `on_transaction` with the transaction
+    pub fn transaction(&mut self, t: &Transaction) -> Result<()> {
+        self.call("on_transaction", (transaction_map(t),))
+    }
+
+    /// Call `on_end`, if the script has it
+    pub fn end(&mut self) -> Result<()> {
+        match self.has_end {
+            true => self.call("on_end", ()),
+            false => Ok(()),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) -> Result<()> {
+        // keep the variables of the top level, and what the functions do to them
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(false);
+        self.engine
+            .call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args)
+            .map(drop)
+            .map_err(|e| anyhow!("{e}"))
+            .with_context(|| format!("The script {name} failed."))
+    }
+}
+
+fn transaction_map(t: &Transaction) -> Map {
+    let time = |t: chrono::DateTime<chrono::Utc>| t.to_rfc3339_opts(SecondsFormat::Micros, true);
+    let mut map = Map::new();
+    let mut set = |key: &str, value: Dynamic| {
+        map.insert(key.into(), value);
+    };
+    set("addr", (*t.cmd.addr() as i64).into());
+    set("param", (*t.cmd.param() as i64).into());
+    set("write", matches!(t.cmd, BusCommand::Write { .. }).into());
+    set("ok", t.result.is_ok().into());
+    match &t.result {
+        Ok(value) => {
+            set("value", (**value as i64).into());
+            set("error", Dynamic::UNIT);
+        }
+        Err(e) => {
+            set("value", Dynamic::UNIT);
+            set("error", format!("{e:?}").into());
+        }
+    }
+    set("cmd_time", time(t.cmd_time).into());
+    set("resp_time", time(t.resp_time).into());
+    let latency_us = t.latency().num_microseconds().unwrap_or(i64::MAX);
+    set("latency_ms", (latency_us as f64 / 1000.0).into());
+    map
+}
@@ -0,0 +1,86 @@
+//! Conversion of the log files written to an SD card by the capture firmware, when it
+//! runs without a host.
+//!
+//! The file starts with [`SD_LOG_MAGIC`], followed by records of a little endian u64
+//! timestamp in microseconds since the device booted, a length byte, and that many bytes
+//! of the muxed stream, in the same format as sent over USB.
+
+use std::io::{ErrorKind, Read};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use serial_pcap_core::sdlog::{decode_header, HEADER_LEN};
+
+use crate::{
+    Marker, MarkerKind, MuxedStreamDecoder, SerialPacketWriter, Trigger, UartData, TRIG_BYTE,
+};
+
+pub use serial_pcap_core::sdlog::SD_LOG_MAGIC;
+
+/// Write the data in the SD card log `reader` to `writer`, with the timestamps offset from
+/// `start_time`, the wall clock time when the device booted. Returns the number of packets.
+pub fn convert_sd_log<R: Read, W: std::io::Write>(
+    mut reader: R,
+    writer: &mut SerialPacketWriter<W>,
+    start_time: SystemTime,
+) -> Result<usize> {
+    let mut magic = [0u8; 8];
+    reader
+        .read_exact(&mut magic)
+        .context("Failed to read the log file header.")?;
+    if &magic != SD_LOG_MAGIC {
+        bail!("Not a capture device log file.");
+    }
+    let mut decoder = MuxedStreamDecoder::new();
+    let mut packets = 0;
+    let mut header = [0u8; HEADER_LEN];
+    let mut data = [0u8; 255];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            // a partial record at the end is expected if the power was lost
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let (micros, len) = decode_header(&header);
+        let data = &mut data[..len as usize];
+        match reader.read_exact(data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let time = start_time + Duration::from_micros(micros);
+        for UartData {
+            ch,
+            data,
+            drops,
+            line_break,
+            ..
+        } in decoder.feed(data)
+        {
+            if drops > 0 {
+                writer.write_marker(&Marker {
+                    kind: MarkerKind::Drop,
+                    ch: Some(ch),
+                    label: format!("{drops} device overruns"),
+                    time: time.into(),
+                })?;
+            }
+            // the triggers are recorded as trigger packets, between the data around them
+            let mut parts = data[..].split(|&b| b == TRIG_BYTE).peekable();
+            while let Some(part) = parts.next() {
+                if !part.is_empty() {
+                    writer.write_packet_time(part, ch, time)?;
+                    packets += 1;
+                }
+                if parts.peek().is_some() {
+                    writer.write_trigger(&Trigger::new(0, Trigger::DEVICE, time.into()))?;
+                }
+            }
+            if line_break {
+                writer.write_marker(&Marker::line_break(0, ch, time.into()))?;
+            }
+        }
+    }
+    Ok(packets)
+}
@@ -0,0 +1,315 @@
+//! Reading a capture split in several files, e.g. the segments of a ring capture, as one
+//! continuous capture.
+//!
+//! The segments are read in the order they are given, or in the order of their names for a
+//! glob pattern, which is the order of the numbered segments of a ring. The header markers
+//! which the ring repeats at the start of each segment are only returned once. A segment
+//! which starts before the end of the previous one is an error, since the files are then out
+//! of order or overlap, and the gaps between the segments are kept in the
+//! [`boundaries`](SegmentReader::boundaries).
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::lenient::LenientStats;
+use crate::resync::ResyncStats;
+use crate::sequence::SequenceGap;
+use crate::{CaptureInput, CaptureRecord, Marker, SerialPacket, SerialPacketReader};
+
+/// The end of one segment and the start of the next
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boundary {
+    pub prev: PathBuf,
+    pub next: PathBuf,
+    /// The time of the last record in `prev`
+    pub prev_end: DateTime<Utc>,
+    /// The time of the first record in `next`, after the repeated header markers
+    pub next_start: DateTime<Utc>,
+}
+
+impl Boundary {
+    /// The time between the segments, e.g. a pruned segment or an idle bus
+    pub fn gap(&self) -> chrono::Duration {
+        self.next_start - self.prev_end
+    }
+}
+
+/// How the segments are opened
+#[derive(Debug, Default, Clone, Copy)]
+struct SegmentOptions {
+    resync: bool,
+    lenient: bool,
+    mmap: bool,
+    from: Option<DateTime<Utc>>,
+}
+
+/// Reads the records of several capture files in turn
+pub struct SegmentReader {
+    paths: VecDeque<PathBuf>,
+    options: SegmentOptions,
+    current: Option<(PathBuf, SerialPacketReader<CaptureInput>)>,
+    /// The markers at the start of the first segment
+    header: Vec<Marker>,
+    /// The number of segments opened so far
+    opened: usize,
+    /// No data has been read from the current segment yet, only header markers
+    at_start: bool,
+    /// The file and time of the last record
+    last: Option<(PathBuf, DateTime<Utc>)>,
+    boundaries: Vec<Boundary>,
+    /// The counts of the segments which have been read
+    resync_stats: ResyncStats,
+    lenient_stats: LenientStats,
+    sequence_gaps: u64,
+    pending_gaps: Vec<SequenceGap>,
+}
+
+impl SegmentReader {
+    /// Read the files in the given order. A file name with `*`, `?` or `[` is a glob pattern,
+    /// which is replaced by the files matching it, sorted by name. "-" reads stdin.
+    pub fn new<S: AsRef<str>>(files: impl IntoIterator<Item = S>) -> Result<Self> {
+        let mut paths = VecDeque::new();
+        for file in files {
+            let file = file.as_ref();
+            if !file.contains(['*', '?', '[']) {
+                paths.push_back(PathBuf::from(file));
+                continue;
+            }
+            let mut matches = glob::glob(file)
+                .with_context(|| format!("Invalid file pattern '{file}'."))?
+                .collect::<Result<Vec<_>, _>>()?;
+            if matches.is_empty() {
+                bail!("No capture files match '{file}'.");
+            }
+            matches.sort();
+            paths.extend(matches);
+        }
+        if paths.is_empty() {
+            bail!("No capture files to read.");
+        }
+        if paths.len() > 1 && paths.iter().any(|p| p == Path::new("-")) {
+            bail!("Stdin can't be read together with other capture files.");
+        }
+        Ok(Self {
+            paths,
+            options: SegmentOptions::default(),
+            current: None,
+            header: vec![],
+            opened: 0,
+            at_start: true,
+            last: None,
+            boundaries: vec![],
+            resync_stats: ResyncStats::default(),
+            lenient_stats: LenientStats::default(),
+            sequence_gaps: 0,
+            pending_gaps: vec![],
+        })
+    }
+
+    /// Skip the corrupt records, see [`SerialPacketReader::with_resync`]
+    pub fn with_resync(mut self) -> Self {
+        self.options.resync = true;
+        self
+    }
+
+    /// Skip the foreign records, see [`SerialPacketReader::with_lenient`]
+    pub fn with_lenient(mut self) -> Self {
+        self.options.lenient = true;
+        self
+    }
+
+    /// Map the files into memory, see [`SerialPacketReader::from_file_mapped`]
+    pub fn with_mmap(mut self) -> Self {
+        self.options.mmap = true;
+        self
+    }
+
+    /// Start at `time`, see [`SerialPacketReader::skip_to`]. The segments which end before it
+    /// are skipped on their record headers.
+    pub fn skip_to(&mut self, time: DateTime<Utc>) -> Result<()> {
+        self.options.from = Some(time);
+        if let Some((_, reader)) = &mut self.current {
+            reader.skip_to(time)?;
+        }
+        Ok(())
+    }
+
+    /// The file being read
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// The boundaries between the segments read so far
+    pub fn boundaries(&self) -> &[Boundary] {
+        &self.boundaries
+    }
+
+    pub fn resync_stats(&self) -> Option<ResyncStats> {
+        if !self.options.resync {
+            return None;
+        }
+        let mut stats = self.resync_stats;
+        if let Some(current) = self.current_reader().and_then(|r| r.resync_stats()) {
+            stats.skipped_bytes += current.skipped_bytes;
+            stats.gaps += current.gaps;
+        }
+        Some(stats)
+    }
+
+    pub fn lenient_stats(&self) -> Option<LenientStats> {
+        if !self.options.lenient {
+            return None;
+        }
+        let mut stats = self.lenient_stats;
+        if let Some(current) = self.current_reader().and_then(|r| r.lenient_stats()) {
+            stats.foreign += current.foreign;
+            stats.reassembled += current.reassembled;
+            stats.incomplete += current.incomplete;
+        }
+        Some(stats)
+    }
+
+    /// See [`SerialPacketReader::sequence_gaps`]. The sequence numbers start over in each
+    /// segment.
+    pub fn sequence_gaps(&self) -> u64 {
+        self.sequence_gaps + self.current_reader().map_or(0, |r| r.sequence_gaps())
+    }
+
+    /// See [`SerialPacketReader::take_sequence_gaps`]
+    pub fn take_sequence_gaps(&mut self) -> Vec<SequenceGap> {
+        let mut gaps = std::mem::take(&mut self.pending_gaps);
+        if let Some((_, reader)) = &mut self.current {
+            gaps.extend(reader.take_sequence_gaps());
+        }
+        gaps
+    }
+
+    fn current_reader(&self) -> Option<&SerialPacketReader<CaptureInput>> {
+        self.current.as_ref().map(|(_, reader)| reader)
+    }
+
+    /// Read the next UART data packet, skipping any markers and triggers.
+    pub fn next_packet(&mut self) -> Result<Option<SerialPacket>> {
+        loop {
+            match self.next_record()? {
+                Some(CaptureRecord::Packet(pkt)) => return Ok(Some(pkt)),
+                Some(CaptureRecord::Marker(_) | CaptureRecord::Trigger(_)) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Read the next packet, marker or trigger, from the next segment at the end of one.
+    pub fn next_record(&mut self) -> Result<Option<CaptureRecord>> {
+        loop {
+            let Some((path, reader)) = &mut self.current else {
+                if !self.open_next()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            let record = reader
+                .next_record()
+                .with_context(|| format!("Failed to read {}.", path.display()))?;
+            let Some(record) = record else {
+                self.close_current();
+                continue;
+            };
+            if self.at_start {
+                match &record {
+                    // the first segment, its leading markers are the header
+                    CaptureRecord::Marker(marker) if self.opened == 1 => {
+                        self.header.push(marker.clone())
+                    }
+                    CaptureRecord::Marker(marker) if self.header.contains(marker) => continue,
+                    _ => {
+                        self.at_start = false;
+                        if self.opened > 1 {
+                            self.check_boundary(record.time())?;
+                        }
+                    }
+                }
+            }
+            let path = self.current.as_ref().unwrap().0.clone();
+            self.last = Some((path, record.time()));
+            return Ok(Some(record));
+        }
+    }
+
+    /// Check the first record of a segment against the end of the previous one
+    fn check_boundary(&mut self, start: DateTime<Utc>) -> Result<()> {
+        let next = self.current.as_ref().unwrap().0.clone();
+        let Some((prev, prev_end)) = self.last.clone() else {
+            return Ok(());
+        };
+        if start < prev_end {
+            bail!(
+                "{} starts at {start}, before the end of {} at {prev_end}. The capture files \
+                 are out of order or overlap.",
+                next.display(),
+                prev.display()
+            );
+        }
+        self.boundaries.push(Boundary {
+            prev,
+            next,
+            prev_end,
+            next_start: start,
+        });
+        Ok(())
+    }
+
+    fn open_next(&mut self) -> Result<bool> {
+        let Some(path) = self.paths.pop_front() else {
+            return Ok(false);
+        };
+        let mut reader = match self.options.mmap {
+            true => SerialPacketReader::from_file_mapped(&path)?,
+            false => SerialPacketReader::from_file(&path)?,
+        };
+        if self.options.resync {
+            reader = reader.with_resync();
+        }
+        if self.options.lenient {
+            reader = reader.with_lenient();
+        }
+        if let Some(time) = self.options.from {
+            reader
+                .skip_to(time)
+                .with_context(|| format!("Failed to read {}.", path.display()))?;
+        }
+        self.current = Some((path, reader));
+        self.opened += 1;
+        self.at_start = true;
+        Ok(true)
+    }
+
+    /// Add up the counts of the segment which has been read
+    fn close_current(&mut self) {
+        let Some((_, mut reader)) = self.current.take() else {
+            return;
+        };
+        if let Some(stats) = reader.resync_stats() {
+            self.resync_stats.skipped_bytes += stats.skipped_bytes;
+            self.resync_stats.gaps += stats.gaps;
+        }
+        if let Some(stats) = reader.lenient_stats() {
+            self.lenient_stats.foreign += stats.foreign;
+            self.lenient_stats.reassembled += stats.reassembled;
+            self.lenient_stats.incomplete += stats.incomplete;
+        }
+        self.sequence_gaps += reader.sequence_gaps();
+        self.pending_gaps.extend(reader.take_sequence_gaps());
+    }
+}
+
+impl Iterator for SegmentReader {
+    type Item = Result<SerialPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
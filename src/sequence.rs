@@ -0,0 +1,121 @@
+//! Continuity checks of the packet sequence numbers, which the writer stores in the IPv4
+//! identification of each packet, see [`crate::SerialPacketReader::sequence`].
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::export::channel_name;
+use crate::{RecordRef, UartTxChannel};
+
+/// A jump in the sequence numbers of a channel: packets were lost, or reordered, after the
+/// capture was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub bus: u8,
+    /// None for the markers and the triggers
+    pub ch: Option<UartTxChannel>,
+    pub expected: u16,
+    pub found: u16,
+    /// The time of the packet after the gap
+    pub time: DateTime<Utc>,
+}
+
+impl SequenceGap {
+    /// The number of missing packets, or None if the packet is from before the expected one,
+    /// i.e. reordered or duplicated
+    pub fn missing(&self) -> Option<u16> {
+        let distance = distance(self.expected, self.found);
+        (distance < HALF_CYCLE).then_some(distance)
+    }
+}
+
+/// e.g. "3 packets missing on ctrl" or "packet 5 out of order on bus 1 node, expected 8"
+impl std::fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bus = match self.bus {
+            0 => String::new(),
+            bus => format!("bus {bus} "),
+        };
+        let ch = self.ch.map_or("markers", channel_name);
+        match self.missing() {
+            Some(1) => write!(f, "1 packet missing on {bus}{ch}"),
+            Some(n) => write!(f, "{n} packets missing on {bus}{ch}"),
+            None => write!(
+                f,
+                "packet {} out of order on {bus}{ch}, expected {}",
+                self.found, self.expected
+            ),
+        }
+    }
+}
+
+/// The sequence numbers run from 1 to 65535
+const CYCLE: i32 = u16::MAX as i32;
+const HALF_CYCLE: u16 = u16::MAX / 2;
+/// The gaps which haven't been taken are limited to this many, the later ones are only counted
+const MAX_GAPS: usize = 1024;
+
+/// Steps from `from` to `to`, in the sequence number cycle
+fn distance(from: u16, to: u16) -> u16 {
+    (i32::from(to) - i32::from(from)).rem_euclid(CYCLE) as u16
+}
+
+/// The sequence number after `seq`, 0 is skipped
+pub(crate) fn next(seq: u16) -> u16 {
+    seq.checked_add(1).unwrap_or(1)
+}
+
+/// Follows the sequence numbers of each channel
+#[derive(Debug, Default)]
+pub(crate) struct SequenceCheck {
+    /// The next sequence number of each bus and channel
+    expected: HashMap<(u8, Option<UartTxChannel>), u16>,
+    gaps: Vec<SequenceGap>,
+    count: u64,
+}
+
+impl SequenceCheck {
+    pub fn check(&mut self, seq: u16, record: &RecordRef) {
+        let (bus, ch, time) = match record {
+            RecordRef::Packet(pkt) => (pkt.bus, Some(pkt.ch), pkt.time),
+            RecordRef::Marker(marker) => (0, None, marker.time),
+            // numbered with the markers by the writer
+            RecordRef::Trigger(trigger) => (0, None, trigger.time),
+        };
+        let expected = self.expected.entry((bus, ch)).or_insert(seq);
+        // a new writer starts from 1, e.g. when a capture was appended to
+        if seq == *expected || seq == 1 {
+            *expected = next(seq);
+            return;
+        }
+        let gap = SequenceGap {
+            bus,
+            ch,
+            expected: *expected,
+            found: seq,
+            time,
+        };
+        // a late packet doesn't move the expected number back
+        if gap.missing().is_some() {
+            *expected = next(seq);
+        }
+        if self.gaps.len() < MAX_GAPS {
+            self.gaps.push(gap);
+        }
+        self.count += 1;
+    }
+
+    /// Forget the expected numbers, after skipping records
+    pub fn reset(&mut self) {
+        self.expected.clear();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn take_gaps(&mut self) -> Vec<SequenceGap> {
+        std::mem::take(&mut self.gaps)
+    }
+}
@@ -0,0 +1,301 @@
+//! Simulated X3.28 bus, for integration tests of tools which read the captures.
+//!
+//! A [`SimNode`] is an x328-proto node with a value table and error injection, and a
+//! [`Scenario`] runs a list of commands against a set of nodes without any serial ports:
+//!
+//! ```
+//! use serial_pcap::sim::{Scenario, SimNode};
+//!
+//! let mut scenario = Scenario::new()
+//!     .with_node(SimNode::new(21).with_value(23, 33))
+//!     .with_node(SimNode::new(31).with_value(223, 0).with_corrupt_every(4))
+//!     .with_read(21, 23)
+//!     .with_write(31, 223, 442)
+//!     .with_cycles(5);
+//! while let Some(exchange) = scenario.next_exchange() {
+//!     // exchange.cmd and exchange.reply are the bytes on the bus
+//! }
+//! ```
+//!
+//! [`Scenario::write_pcap`] writes the traffic to a capture, with packet times computed from the
+//! byte times at 9600 baud, so the same scenario always gives the same file.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::node::{Node, NodeState};
+use x328_proto::{addr, param, value};
+
+use crate::decode::BusCommand;
+use crate::{x328, SerialPacketWriter, UartTxChannel};
+
+/// One byte at 9600 baud, with start, parity and stop bits
+pub const BYTE_TIME: Duration = Duration::microseconds(1042);
+/// Delay from the end of a command to the reply
+pub const RESPONSE_DELAY: Duration = Duration::milliseconds(2);
+/// The time the controller waits for a reply which doesn't come
+pub const RESPONSE_TIMEOUT: Duration = Duration::milliseconds(50);
+/// Delay from the end of a reply to the next command
+pub const COMMAND_INTERVAL: Duration = Duration::milliseconds(10);
+
+/// A bus node with a table of parameter values.
+///
+/// Reads of a parameter in the table are answered with the value, and writes replace it. The
+/// parameters which aren't in the table are invalid: a read gets an EOT and a write a NAK.
+#[derive(Debug, Clone)]
+pub struct SimNode {
+    address: u8,
+    values: HashMap<i16, i32>,
+    fail: Vec<i16>,
+    no_reply_every: usize,
+    corrupt_every: usize,
+    replies: usize,
+}
+
+impl SimNode {
+    /// A node without any parameters, panics if the address is over 99
+    pub fn new(address: u8) -> Self {
+        assert!(address <= 99, "Invalid X3.28 address {address}.");
+        Self {
+            address,
+            values: HashMap::new(),
+            fail: vec![],
+            no_reply_every: 0,
+            corrupt_every: 0,
+            replies: 0,
+        }
+    }
+
+    /// Add a parameter to the value table
+    pub fn with_value(mut self, param: i16, value: i32) -> Self {
+        self.values.insert(param, value);
+        self
+    }
+
+    /// Add the parameters to the value table
+    pub fn with_values(mut self, values: impl IntoIterator<Item = (i16, i32)>) -> Self {
+        self.values.extend(values);
+        self
+    }
+
+    /// Reads and writes of the parameter fail with a NAK
+    pub fn with_failing(mut self, param: i16) -> Self {
+        self.fail.push(param);
+        self
+    }
+
+    /// Skip every Nth reply, 0 disables
+    pub fn with_no_reply_every(mut self, n: usize) -> Self {
+        self.no_reply_every = n;
+        self
+    }
+
+    /// Corrupt the checksum of every Nth reply, 0 disables
+    pub fn with_corrupt_every(mut self, n: usize) -> Self {
+        self.corrupt_every = n;
+        self
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// The current value of the parameter, after the writes so far
+    pub fn value(&self, param: i16) -> Option<i32> {
+        self.values.get(&param).copied()
+    }
+
+    /// Feed the data of a command to the node, returns the reply to send, if any.
+    pub fn receive(&mut self, recv: &[u8]) -> Option<Vec<u8>> {
+        let reply = self.respond(recv)?;
+        self.inject_errors(reply)
+    }
+
+    fn respond(&mut self, recv: &[u8]) -> Option<Vec<u8>> {
+        // the node is always left in the receive state
+        let mut node = Node::new(addr(self.address));
+        let token = node.reset();
+        let NodeState::ReceiveData(r) = node.state(token) else {
+            unreachable!()
+        };
+        let mut token = r.receive_data(recv);
+        let mut reply = None;
+        loop {
+            token = match node.state(token) {
+                NodeState::ReceiveData(_) => return reply,
+                NodeState::SendData(s) => {
+                    reply = Some(s.send_data().to_vec());
+                    s.data_sent()
+                }
+                NodeState::ReadParameter(read) => {
+                    let p = *read.parameter();
+                    if self.fail.contains(&p) {
+                        read.send_read_failed()
+                    } else if let Some(v) = self.values.get(&p) {
+                        read.send_reply_ok(value(*v))
+                    } else {
+                        read.send_invalid_parameter()
+                    }
+                }
+                NodeState::WriteParameter(write) => {
+                    let p = *write.parameter();
+                    match self.values.get_mut(&p) {
+                        Some(v) if !self.fail.contains(&p) => {
+                            *v = *write.value();
+                            write.write_ok()
+                        }
+                        _ => write.write_error(),
+                    }
+                }
+            };
+        }
+    }
+
+    fn inject_errors(&mut self, mut reply: Vec<u8>) -> Option<Vec<u8>> {
+        self.replies += 1;
+        let nth = |every: usize| every != 0 && self.replies.is_multiple_of(every);
+        if nth(self.no_reply_every) {
+            return None;
+        }
+        if nth(self.corrupt_every) {
+            if let Some(bcc) = reply.last_mut() {
+                *bcc ^= 0x01;
+            }
+        }
+        Some(reply)
+    }
+}
+
+/// A command sent by the controller, and the reply of the nodes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimExchange {
+    pub cmd: Vec<u8>,
+    /// None if no node replied, or the reply was skipped
+    pub reply: Option<Vec<u8>>,
+}
+
+/// A bus controller running a list of commands against a set of nodes
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    nodes: Vec<SimNode>,
+    commands: Vec<BusCommand>,
+    cycles: usize,
+    /// Index of the next command in all cycles
+    next: usize,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scenario {
+    /// A scenario without nodes and commands, which runs the commands once
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![],
+            commands: vec![],
+            cycles: 1,
+            next: 0,
+        }
+    }
+
+    pub fn with_node(mut self, node: SimNode) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Read a parameter, panics if the address or the parameter is out of range
+    pub fn with_read(self, address: u8, parameter: i16) -> Self {
+        self.with_command(BusCommand::Read {
+            addr: addr(address),
+            param: param(parameter),
+        })
+    }
+
+    /// Write a parameter, panics if the address, the parameter or the value is out of range
+    pub fn with_write(self, address: u8, parameter: i16, v: i32) -> Self {
+        self.with_command(BusCommand::Write {
+            addr: addr(address),
+            param: param(parameter),
+            value: value(v),
+        })
+    }
+
+    pub fn with_command(mut self, cmd: BusCommand) -> Self {
+        self.commands.push(cmd);
+        self
+    }
+
+    /// Number of times the command list is run, 0 means forever
+    pub fn with_cycles(mut self, cycles: usize) -> Self {
+        self.cycles = cycles;
+        self
+    }
+
+    pub fn nodes(&self) -> &[SimNode] {
+        &self.nodes
+    }
+
+    /// The node with the address, to check the values written to it
+    pub fn node(&self, address: u8) -> Option<&SimNode> {
+        self.nodes.iter().find(|n| n.address == address)
+    }
+
+    /// Send the next command to the nodes, None when all the cycles are done
+    pub fn next_exchange(&mut self) -> Option<SimExchange> {
+        if self.commands.is_empty()
+            || self.cycles != 0 && self.next >= self.commands.len() * self.cycles
+        {
+            return None;
+        }
+        let cmd = x328::command(&self.commands[self.next % self.commands.len()]);
+        self.next += 1;
+        // every node sees the command, the one with the address replies
+        let mut reply = None;
+        for node in &mut self.nodes {
+            reply = reply.or(node.receive(&cmd));
+        }
+        Some(SimExchange { cmd, reply })
+    }
+
+    /// Write the rest of the exchanges to `writer`, the first command at `start`. Returns the
+    /// time after the last exchange.
+    pub fn write_pcap<W: Write>(
+        &mut self,
+        writer: &mut SerialPacketWriter<W>,
+        start: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        if self.cycles == 0 {
+            bail!("Can't write a scenario which runs forever.");
+        }
+        let mut time = start;
+        while let Some(exchange) = self.next_exchange() {
+            time = write_exchange(writer, &exchange, time)?;
+        }
+        Ok(time)
+    }
+}
+
+/// Write the command at `time`, and the reply after the command and the response delay.
+/// Returns the time of the next command, after the command interval.
+pub fn write_exchange<W: Write>(
+    writer: &mut SerialPacketWriter<W>,
+    exchange: &SimExchange,
+    mut time: DateTime<Utc>,
+) -> Result<DateTime<Utc>> {
+    writer.write_packet_time(&exchange.cmd, UartTxChannel::Ctrl, time.into())?;
+    time += BYTE_TIME * exchange.cmd.len() as i32;
+    match &exchange.reply {
+        Some(reply) => {
+            time += RESPONSE_DELAY;
+            writer.write_packet_time(reply, UartTxChannel::Node, time.into())?;
+            time += BYTE_TIME * reply.len() as i32;
+        }
+        None => time += RESPONSE_TIMEOUT,
+    }
+    Ok(time + COMMAND_INTERVAL)
+}
@@ -0,0 +1,242 @@
+//! Status of a running capture: a periodic status line, so an operator watching the terminal can
+//! see that data is flowing without the TRACE log, and a minimal HTTP endpoint with the same
+//! counters as JSON for monitoring scripts.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use tracing::{info, warn};
+
+use crate::capture::DropStats;
+use crate::export::channel_name;
+use crate::{SerialPacket, UartTxChannel};
+
+/// Clients which don't send their request within this time are disconnected
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The data captured on a channel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelCount {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+/// Counts of the captured packets, on each bus and channel
+#[derive(Debug, Clone)]
+pub struct CaptureStatus {
+    /// Descriptions of the captured ports, e.g. "ctrl (/dev/ttyUSB0 9600,7E1)"
+    pub ports: Vec<String>,
+    pub channels: BTreeMap<(u8, UartTxChannel), ChannelCount>,
+    /// The time of the last packet
+    pub last_packet: Option<DateTime<Utc>>,
+    started: Instant,
+}
+
+impl Default for CaptureStatus {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+/// The status shared by the counting monitor and the HTTP server
+pub type SharedStatus = Arc<Mutex<CaptureStatus>>;
+
+impl CaptureStatus {
+    /// The capture of `ports` starts now
+    pub fn new(ports: Vec<String>) -> Self {
+        Self {
+            ports,
+            channels: BTreeMap::new(),
+            last_packet: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn count(&mut self, pkt: &SerialPacket) {
+        let count = self.channels.entry((pkt.bus, pkt.ch)).or_default();
+        count.bytes += pkt.data.len() as u64;
+        count.packets += 1;
+        self.last_packet = Some(pkt.time);
+    }
+
+    /// e.g. "Capturing for 0:15:02, ctrl 1234 bytes in 56 packets, node 789 bytes in 40 packets,
+    /// file 23456 bytes"
+    pub fn line(&self, elapsed: Duration, file_size: Option<u64>) -> String {
+        let secs = elapsed.as_secs();
+        let mut line = format!(
+            "Capturing for {}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        if self.channels.is_empty() {
+            line.push_str(", no data yet");
+        }
+        for (&(bus, ch), count) in &self.channels {
+            line.push_str(", ");
+            if bus != 0 {
+                write!(line, "bus {bus} ").unwrap();
+            }
+            write!(
+                line,
+                "{} {} bytes in {} packets",
+                channel_name(ch),
+                count.bytes,
+                count.packets
+            )
+            .unwrap();
+        }
+        if let Some(size) = file_size {
+            write!(line, ", file {size} bytes").unwrap();
+        }
+        line
+    }
+
+    /// The status as a JSON object, with the losses counted in `drops`
+    pub fn json(&self, drops: &DropStats, file_size: Option<u64>) -> String {
+        let ports: Vec<_> = self.ports.iter().map(|p| json_string(p)).collect();
+        let channels: Vec<_> = self
+            .channels
+            .iter()
+            .map(|(&(bus, ch), count)| {
+                format!(
+                    r#"{{"bus":{bus},"channel":"{}","bytes":{},"packets":{}}}"#,
+                    channel_name(ch),
+                    count.bytes,
+                    count.packets
+                )
+            })
+            .collect();
+        let losses = |ch| {
+            let (events, bytes) = drops.channel_losses(ch);
+            format!(r#"{{"events":{events},"bytes":{bytes}}}"#)
+        };
+        format!(
+            r#"{{"uptime_s":{:.3},"ports":[{}],"channels":[{}],"last_packet":{},"drops":{{"ctrl":{},"node":{}}},"file_size":{}}}"#,
+            self.uptime().as_secs_f64(),
+            ports.join(","),
+            channels.join(","),
+            self.last_packet.map_or("null".into(), json_time),
+            losses(UartTxChannel::Ctrl),
+            losses(UartTxChannel::Node),
+            file_size.map_or("null".into(), |s| s.to_string()),
+        )
+    }
+}
+
+/// A quoted JSON string
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A quoted RFC 3339 time in UTC, with microseconds
+pub fn json_time(time: DateTime<Utc>) -> String {
+    json_string(&time.to_rfc3339_opts(SecondsFormat::Micros, true))
+}
+
+/// Count the packets passed to a monitor in `status`, and log the status line every `interval`,
+/// if one is given. Runs until the recorder stops.
+pub fn run_status(
+    packets: Receiver<SerialPacket>,
+    status: SharedStatus,
+    interval: Option<Duration>,
+    file_size: impl Fn() -> Option<u64>,
+) {
+    // without the status line, the packets are only counted
+    let mut next_status = interval.map(|interval| Instant::now() + interval);
+    loop {
+        let pkt = match next_status {
+            Some(next) => packets.recv_timeout(next.saturating_duration_since(Instant::now())),
+            None => packets.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match pkt {
+            Ok(pkt) => status.lock().unwrap().count(&pkt),
+            Err(RecvTimeoutError::Timeout) => {
+                let status = status.lock().unwrap();
+                info!("{}", status.line(status.uptime(), file_size()));
+                next_status = next_status
+                    .zip(interval)
+                    .map(|(next, interval)| next + interval);
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Answer `GET /status` requests on `listener` with the JSON status of the capture. Runs until
+/// the listener fails.
+pub fn serve_http(
+    listener: TcpListener,
+    status: SharedStatus,
+    drops: Arc<DropStats>,
+    file_size: impl Fn() -> Option<u64>,
+) {
+    for stream in listener.incoming() {
+        let res = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| respond(stream, &status, &drops, &file_size));
+        if let Err(e) = res {
+            warn!("Failed to answer a status request: {e:#}");
+        }
+    }
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &SharedStatus,
+    drops: &DropStats,
+    file_size: &impl Fn() -> Option<u64>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers don't matter, but are read so the client doesn't see a reset
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut words = request.split_whitespace();
+    let (code, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/status")) => {
+            let json = status.lock().unwrap().json(drops, file_size());
+            ("200 OK", json + "\n")
+        }
+        (Some("GET"), _) => ("404 Not Found", "{\"error\":\"not found\"}\n".into()),
+        _ => (
+            "405 Method Not Allowed",
+            "{\"error\":\"method not allowed\"}\n".into(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
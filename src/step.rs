@@ -0,0 +1,203 @@
+//! Stepping through the decoded transactions of a capture, like a debugger for the bus.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::decode::{BusEvent, X328Decoder};
+use crate::SerialPacket;
+
+/// A decoded bus event, with the packets it was decoded from
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub event: BusEvent,
+    /// The packets fed to the decoder since the previous event. If one packet completes
+    /// several events, they all get that packet.
+    pub packets: Vec<SerialPacket>,
+}
+
+impl Step {
+    pub fn matches(&self, bp: &Breakpoint) -> bool {
+        self.event
+            .command()
+            .is_some_and(|cmd| *cmd.addr() == bp.addr && bp.param.is_none_or(|p| *cmd.param() == p))
+    }
+}
+
+/// Stop at the commands to a node, or to a single parameter of the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u8,
+    pub param: Option<i16>,
+}
+
+impl FromStr for Breakpoint {
+    type Err = anyhow::Error;
+
+    /// Parse "ADDR" or "ADDR:PARAM"
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, param) = match s.split_once(':') {
+            Some((addr, param)) => (addr, Some(param)),
+            None => (s, None),
+        };
+        let addr = addr
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid node address '{addr}'."))?;
+        let param = param
+            .map(|p| p.trim().parse())
+            .transpose()
+            .context("Invalid parameter number.")?;
+        Ok(Self { addr, param })
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.param {
+            Some(param) => write!(f, "{}:{param}", self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// Decode all the packets into steps
+pub fn decode_steps(packets: impl Iterator<Item = Result<SerialPacket>>) -> Result<Vec<Step>> {
+    let mut decoder = X328Decoder::new();
+    let mut steps = vec![];
+    let mut pending: Vec<SerialPacket> = vec![];
+    for pkt in packets {
+        let pkt = pkt?;
+        if pkt.bus != 0 {
+            continue;
+        }
+        let mut events = vec![];
+        decoder.feed(&pkt, |e| events.push(e));
+        pending.push(pkt);
+        for event in events {
+            let packets = match pending.is_empty() {
+                true => steps
+                    .last()
+                    .map(|s: &Step| s.packets[s.packets.len() - 1..].to_vec()),
+                false => Some(std::mem::take(&mut pending)),
+            };
+            steps.push(Step {
+                event,
+                packets: packets.unwrap_or_default(),
+            });
+        }
+    }
+    Ok(steps)
+}
+
+/// The position in a list of steps, and the breakpoints
+pub struct Stepper {
+    steps: Vec<Step>,
+    pos: usize,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Stepper {
+    pub fn new(steps: Vec<Step>) -> Self {
+        Self {
+            steps,
+            pos: 0,
+            breakpoints: vec![],
+        }
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn current(&self) -> Option<&Step> {
+        self.steps.get(self.pos)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Add the breakpoint, or remove it if it is already set. Returns true if it was added.
+    pub fn toggle_breakpoint(&mut self, bp: Breakpoint) -> bool {
+        match self.breakpoints.iter().position(|b| *b == bp) {
+            Some(n) => {
+                self.breakpoints.remove(n);
+                false
+            }
+            None => {
+                self.breakpoints.push(bp);
+                true
+            }
+        }
+    }
+
+    /// The current step is at a breakpoint
+    pub fn at_breakpoint(&self) -> bool {
+        self.current()
+            .is_some_and(|s| self.breakpoints.iter().any(|bp| s.matches(bp)))
+    }
+
+    /// Move to step `pos`, limited to the last step
+    pub fn go_to(&mut self, pos: usize) {
+        self.pos = pos.min(self.steps.len().saturating_sub(1));
+    }
+
+    /// Move forward `n` steps, returns false at the end
+    pub fn forward(&mut self, n: usize) -> bool {
+        let old = self.pos;
+        self.go_to(self.pos.saturating_add(n));
+        self.pos != old
+    }
+
+    /// Move back `n` steps, returns false at the start
+    pub fn back(&mut self, n: usize) -> bool {
+        let old = self.pos;
+        self.pos = self.pos.saturating_sub(n);
+        self.pos != old
+    }
+
+    /// Move to the next error, returns false if there is none
+    pub fn next_error(&mut self) -> bool {
+        self.find_next(|s| s.event.is_error())
+    }
+
+    /// Move to the previous error, returns false if there is none
+    pub fn prev_error(&mut self) -> bool {
+        self.find_prev(|s| s.event.is_error())
+    }
+
+    /// Move to the next step at a breakpoint, returns false if there is none
+    pub fn continue_to_breakpoint(&mut self) -> bool {
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let found = self.find_next(|s| breakpoints.iter().any(|bp| s.matches(bp)));
+        self.breakpoints = breakpoints;
+        found
+    }
+
+    fn find_next(&mut self, f: impl Fn(&Step) -> bool) -> bool {
+        let start = self.pos + 1;
+        match self.steps.iter().skip(start).position(f) {
+            Some(n) => {
+                self.pos = start + n;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find_prev(&mut self, f: impl Fn(&Step) -> bool) -> bool {
+        match self.steps[..self.pos].iter().rposition(f) {
+            Some(n) => {
+                self.pos = n;
+                true
+            }
+            None => false,
+        }
+    }
+}
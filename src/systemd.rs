@@ -0,0 +1,71 @@
+//! Service manager notifications, for running the capture as a systemd `Type=notify` service.
+
+use std::os::unix::net::UnixDatagram;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::capture::DropStats;
+use crate::{SerialPacket, UartTxChannel};
+
+/// Send a notification like "READY=1" to the service manager. Returns false if the process
+/// wasn't started by a service manager which listens for notifications.
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    let sent = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => anyhow::bail!("Abstract notification sockets are only supported on Linux."),
+        None => socket.send_to(state.as_bytes(), path.as_ref()),
+    };
+    sent.with_context(|| format!("Failed to notify the service manager at {path}."))?;
+    Ok(true)
+}
+
+/// Reports the capture to the service manager: ready when the first packet has been written,
+/// and the byte counters in the status every `interval`. Runs until the recorder stops.
+pub fn run_status(packets: Receiver<SerialPacket>, drops: Arc<DropStats>, interval: Duration) {
+    let (mut ctrl, mut node) = (0u64, 0u64);
+    let mut ready = false;
+    let mut next_status = Instant::now() + interval;
+    let status = |ctrl, node| {
+        format!(
+            "STATUS=ctrl {ctrl} bytes, node {node} bytes, {} losses",
+            drops.total_events()
+        )
+    };
+    loop {
+        let timeout = next_status.saturating_duration_since(Instant::now());
+        match packets.recv_timeout(timeout) {
+            Ok(pkt) => {
+                match pkt.ch {
+                    UartTxChannel::Ctrl => ctrl += pkt.data.len() as u64,
+                    UartTxChannel::Node => node += pkt.data.len() as u64,
+                }
+                if !ready {
+                    ready = true;
+                    let _ = notify(&format!("READY=1\n{}", status(ctrl, node)));
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = notify(&status(ctrl, node));
+                next_status = Instant::now() + interval;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = notify(&format!("STOPPING=1\n{}", status(ctrl, node)));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Fan-out of the captured data to the sinks besides the capture file, e.g. the terminal UI,
+//! the TCP forwarder or a copy of the capture in another file.
+//!
+//! Each sink gets its own channel and runs in its own thread, so a slow sink doesn't hold up
+//! the recorder or the other sinks. A sink which has stopped, e.g. the terminal UI after it
+//! exits, is removed at the next send.
+
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::{CaptureRecord, Marker, SerialPacket, SerialPacketWriter, Trigger};
+
+/// Sends the data written to the capture file on to the sinks
+#[derive(Debug, Default)]
+pub struct Tee {
+    /// Take the data packets
+    packets: Vec<Sender<SerialPacket>>,
+    /// Take the data packets, the markers and the triggers, in the order they are written
+    records: Vec<Sender<CaptureRecord>>,
+}
+
+impl Tee {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sink for the data packets
+    pub fn packets(&mut self) -> Receiver<SerialPacket> {
+        let (tx, rx) = channel();
+        self.packets.push(tx);
+        rx
+    }
+
+    /// Add a sink for the data packets, the markers and the triggers
+    pub fn records(&mut self) -> Receiver<CaptureRecord> {
+        let (tx, rx) = channel();
+        self.records.push(tx);
+        rx
+    }
+
+    /// Number of sinks still taking data
+    pub fn len(&self) -> usize {
+        self.packets.len() + self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn send_packet(&mut self, pkt: SerialPacket) {
+        self.send_record(CaptureRecord::Packet(pkt.clone()));
+        self.packets.retain(|sink| sink.send(pkt.clone()).is_ok());
+    }
+
+    pub fn send_marker(&mut self, marker: &Marker) {
+        self.send_record(CaptureRecord::Marker(marker.clone()));
+    }
+
+    pub fn send_trigger(&mut self, trigger: &Trigger) {
+        self.send_record(CaptureRecord::Trigger(trigger.clone()));
+    }
+
+    fn send_record(&mut self, record: CaptureRecord) {
+        self.records
+            .retain(|sink| sink.send(record.clone()).is_ok());
+    }
+}
+
+/// Write the records to another capture file, until the [`Tee`] is dropped. The file is
+/// flushed when no records have arrived for `idle`, so it can be read during the capture.
+pub fn write_copy<W: Write>(
+    mut writer: SerialPacketWriter<W>,
+    records: Receiver<CaptureRecord>,
+    idle: Duration,
+) -> Result<()> {
+    loop {
+        match records.recv_timeout(idle) {
+            Ok(record) => writer
+                .write_record(&record)
+                .context("Failed to write to the capture copy.")?,
+            Err(RecvTimeoutError::Timeout) => writer.flush()?,
+            Err(RecvTimeoutError::Disconnected) => return writer.flush(),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Live view of the capture in tshark, the command line version of Wireshark.
+//!
+//! tshark reads a pcap stream from a pipe and prints the packet list as the packets arrive,
+//! with the X3.28 dissector if it is loaded with e.g. `-Xlua_script:x328-names.lua`.
+
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Receiver;
+
+use anyhow::{bail, Context, Result};
+use tracing::warn;
+
+use crate::{SerialPacket, SerialPacketWriter};
+
+/// The tshark command reading the capture from stdin, with `extra_args` appended
+pub fn command(extra_args: &[String]) -> Command {
+    let mut cmd = Command::new("tshark");
+    // flush the output after each packet, and don't resolve the localhost addresses
+    cmd.args(["-l", "-n", "-r", "-"]).args(extra_args);
+    cmd
+}
+
+/// Start the command with a pipe to its stdin.
+///
+/// On unix it runs in its own process group, so ctrl-c stops the capture but not tshark,
+/// which prints the rest of the packets and exits at the end of the stream.
+pub fn spawn(mut cmd: Command) -> Result<Child> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    cmd.stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start {:?}.", cmd.get_program()))
+}
+
+/// Write the packets to the stdin of `child` in pcap format until the capture ends, then
+/// wait for it to exit.
+pub fn feed(mut child: Child, packets: Receiver<SerialPacket>) -> Result<()> {
+    let stdin = child.stdin.take().context("The child has no stdin pipe.")?;
+    let mut writer = SerialPacketWriter::new(stdin)?;
+    let mut res = writer.flush();
+    if res.is_ok() {
+        for pkt in packets {
+            res = writer
+                .write_bus_packet_time(pkt.bus, &pkt.data, pkt.ch, pkt.time.into())
+                .and_then(|_| writer.flush());
+            if res.is_err() {
+                break;
+            }
+        }
+    }
+    if let Err(e) = res {
+        warn!("tshark stopped reading the capture: {e:#}");
+    }
+    // closing the pipe ends the stream
+    drop(writer);
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("tshark exited with {status}.");
+    }
+    Ok(())
+}
@@ -0,0 +1,598 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use x328_proto::master::Error as X328Error;
+
+use crate::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use crate::fieldbus::FieldBusMirror;
+use crate::names::NameMap;
+use crate::step::Stepper;
+use crate::CaptureRecord;
+
+const MAX_LINES: usize = 10_000;
+
+/// Called with the label the user entered, to write a marker into the capture
+pub type MarkHandler = Box<dyn FnMut(String) + Send>;
+
+/// The bus errors and triggers seen by a [`BusView`]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorCounters {
+    pub timeouts: usize,
+    pub nak: usize,
+    pub invalid_param: usize,
+    pub protocol: usize,
+    pub unexpected: usize,
+    pub triggers: usize,
+    pub collisions: usize,
+}
+
+/// A record for the terminal UI, or the error which ended the stream of records
+pub trait ViewInput {
+    fn into_record(self) -> Result<CaptureRecord>;
+}
+
+impl ViewInput for CaptureRecord {
+    fn into_record(self) -> Result<CaptureRecord> {
+        Ok(self)
+    }
+}
+
+impl ViewInput for Result<CaptureRecord> {
+    fn into_record(self) -> Result<CaptureRecord> {
+        self
+    }
+}
+
+/// Decoded X3.28 bus state, for display in the terminal UI.
+pub struct BusView {
+    decoder: X328Decoder,
+    names: NameMap,
+    lines: VecDeque<(String, bool)>,
+    params: FieldBusMirror,
+    errors: ErrorCounters,
+    scroll: usize,
+    stopped: bool,
+    /// The error which ended the stream
+    error: Option<anyhow::Error>,
+    on_mark: Option<MarkHandler>,
+    /// The marker label being entered
+    mark_input: Option<String>,
+}
+
+impl Default for BusView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BusView {
+    pub fn new() -> Self {
+        Self {
+            decoder: X328Decoder::new(),
+            names: NameMap::default(),
+            lines: VecDeque::new(),
+            params: FieldBusMirror::default(),
+            errors: Default::default(),
+            scroll: 0,
+            stopped: false,
+            error: None,
+            on_mark: None,
+            mark_input: None,
+        }
+    }
+
+    /// Show the names and units from `names` instead of the raw parameter numbers.
+    pub fn with_names(names: NameMap) -> Self {
+        Self {
+            names,
+            ..Self::new()
+        }
+    }
+
+    /// Decode the data in a packet, or take a trigger, and update the bus state.
+    pub fn feed(&mut self, record: &CaptureRecord) {
+        let mut events = vec![];
+        self.decoder.feed_record(record, |e| events.push(e));
+        for event in events {
+            self.bus_event(event);
+        }
+    }
+
+    /// Show the error which ended the stream of records
+    pub fn read_error(&mut self, e: anyhow::Error) {
+        self.log(Utc::now(), format!("Read error: {e:#}"), true);
+        self.stopped = true;
+        self.error = Some(e);
+    }
+
+    /// The error counters
+    pub fn errors(&self) -> &ErrorCounters {
+        &self.errors
+    }
+
+    /// The lines of the transaction log, with whether each one is an error
+    pub fn lines(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.lines.iter().map(|(text, err)| (text.as_str(), *err))
+    }
+
+    fn bus_event(&mut self, event: BusEvent) {
+        self.params.handle_event(&event);
+        match &event {
+            BusEvent::Transaction(t) => {
+                if let Err(e) = &t.result {
+                    self.count_error(e);
+                }
+            }
+            BusEvent::Timeout { .. } => self.errors.timeouts += 1,
+            BusEvent::UnexpectedTransmission { .. } => self.errors.unexpected += 1,
+            BusEvent::Mismatch { .. } => self.errors.protocol += 1,
+            BusEvent::Trigger { .. } => self.errors.triggers += 1,
+            BusEvent::Break { .. } => {}
+            BusEvent::Collision { .. } => self.errors.collisions += 1,
+        }
+        let (msg, err) = describe_event(&self.names, &event);
+        self.log(event.time(), msg, err);
+    }
+
+    fn count_error(&mut self, e: &X328Error) {
+        match e {
+            X328Error::InvalidParameter => self.errors.invalid_param += 1,
+            X328Error::CommandFailed => self.errors.nak += 1,
+            X328Error::ProtocolError => self.errors.protocol += 1,
+        }
+    }
+
+    fn log(&mut self, time: DateTime<Utc>, msg: String, err: bool) {
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines
+            .push_back((format!("{} {msg}", time.format("%H:%M:%S%.3f")), err));
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+        let [log_area, table_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+
+        let height = log_area.height.saturating_sub(2) as usize;
+        let end = self.lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        let lines: Vec<Line> = self
+            .lines
+            .range(start..end)
+            .map(|(text, err)| match err {
+                true => Line::styled(text.as_str(), Style::new().fg(Color::Red)),
+                false => Line::raw(text.as_str()),
+            })
+            .collect();
+        let title = match self.scroll {
+            0 => "Transactions".to_string(),
+            n => format!("Transactions (-{n})"),
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            log_area,
+        );
+
+        let rows = self.params.values().map(|(a, p, v)| {
+            let (a, p) = (*a, *p);
+            let name = self.names.param(a, p).map(|i| i.name.as_str());
+            Row::new([
+                a.to_string(),
+                p.to_string(),
+                name.unwrap_or_default().to_string(),
+                self.names.value(a, p, *v.value),
+                v.time.format("%H:%M:%S").to_string(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Length(12),
+            Constraint::Length(8),
+        ];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(["Addr", "Param", "Name", "Value", "Updated"]).style(Style::new().bold()),
+            )
+            .block(Block::bordered().title("Parameters"));
+        frame.render_widget(table, table_area);
+
+        let e = &self.errors;
+        let mut text = format!(
+            "Timeouts: {}  NAK: {}  Invalid param: {}  Protocol: {}  Unexpected: {}  Collisions: {}  Triggers: {}",
+            e.timeouts, e.nak, e.invalid_param, e.protocol, e.unexpected, e.collisions, e.triggers
+        );
+        if self.error.is_some() {
+            text.push_str("  [read error]");
+        } else if self.stopped {
+            text.push_str("  [stream ended]");
+        }
+        let mut help = "q: quit, ↑/↓/PgUp/PgDn: scroll".to_string();
+        if self.on_mark.is_some() {
+            help.push_str(", m: mark");
+        }
+        if let Some(label) = &self.mark_input {
+            text = format!("Marker label: {label}_");
+            help = "Enter: write marker, Esc: cancel".into();
+        }
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(help)),
+            status,
+        );
+    }
+
+    fn handle_mark_key(&mut self, key: KeyCode) {
+        let Some(label) = &mut self.mark_input else {
+            return;
+        };
+        match key {
+            KeyCode::Char(c) => label.push(c),
+            KeyCode::Backspace => {
+                label.pop();
+            }
+            KeyCode::Enter => {
+                let label = self.mark_input.take().unwrap();
+                self.log(Utc::now(), format!("Marker: {label}"), false);
+                if let Some(on_mark) = &mut self.on_mark {
+                    on_mark(label);
+                }
+            }
+            KeyCode::Esc => self.mark_input = None,
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.mark_input.is_some() {
+            self.handle_mark_key(key);
+            return true;
+        }
+        let max_scroll = self.lines.len().saturating_sub(1);
+        match key {
+            KeyCode::Char('m') if self.on_mark.is_some() => self.mark_input = Some(String::new()),
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up => self.scroll = (self.scroll + 1).min(max_scroll),
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll = (self.scroll + 20).min(max_scroll),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(20),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Home => self.scroll = max_scroll,
+            _ => {}
+        }
+        true
+    }
+
+    fn run_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        rx: &Receiver<impl ViewInput>,
+    ) -> Result<()> {
+        loop {
+            while self.error.is_none() {
+                match rx.try_recv() {
+                    Ok(input) => match input.into_record() {
+                        Ok(record) => self.feed(&record),
+                        Err(e) => self.read_error(e),
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.stopped = true;
+                        break;
+                    }
+                }
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The text shown for a bus event, and whether it is an error
+fn describe_event(names: &NameMap, event: &BusEvent) -> (String, bool) {
+    match event {
+        BusEvent::Transaction(t) => {
+            let latency = t.latency().num_microseconds().unwrap_or(0) as f64 / 1000.0;
+            let (a, p) = (*t.cmd.addr(), *t.cmd.param());
+            let label = names.label(a, p);
+            match (t.cmd, &t.result) {
+                (BusCommand::Read { .. }, Ok(v)) => {
+                    let v = names.value(a, p, **v);
+                    (format!("Read  {label} => {v} ({latency:.1} ms)"), false)
+                }
+                (BusCommand::Write { .. }, Ok(v)) => {
+                    let v = names.value(a, p, **v);
+                    (format!("Write {label} = {v} ({latency:.1} ms)"), false)
+                }
+                (BusCommand::Read { .. }, Err(e)) => (format!("Read  {label} failed: {e}"), true),
+                (BusCommand::Write { value, .. }, Err(e)) => {
+                    let v = names.value(a, p, *value);
+                    (format!("Write {label} = {v} failed: {e}"), true)
+                }
+            }
+        }
+        BusEvent::Timeout { cmd, .. } => {
+            let msg = match cmd {
+                Some(BusCommand::Read { addr, param }) => {
+                    format!("Timeout reading {}", names.label(**addr, **param))
+                }
+                Some(BusCommand::Write { addr, param, value }) => {
+                    let v = names.value(**addr, **param, **value);
+                    format!("Timeout writing {v} to {}", names.label(**addr, **param))
+                }
+                None => "Timeout".into(),
+            };
+            (msg, true)
+        }
+        BusEvent::UnexpectedTransmission { .. } => {
+            ("Unexpected transmission from node".into(), true)
+        }
+        BusEvent::Mismatch { .. } => ("Response doesn't match the command".into(), true),
+        BusEvent::Trigger { .. } => ("Trigger event".into(), false),
+        BusEvent::Break { ch, .. } => (format!("Break on the {ch:?} channel"), false),
+        BusEvent::Collision { kind, .. } => {
+            let msg = match kind {
+                Collision::Echo { ch } => format!("Echo on the {ch:?} channel"),
+                Collision::SecondMaster { cmd: Some(cmd) } => {
+                    let label = names.label(*cmd.addr(), *cmd.param());
+                    format!("Second bus master, interrupted command to {label}")
+                }
+                Collision::SecondMaster { cmd: None } => "Second bus master".into(),
+                Collision::Overlap { ch } => {
+                    format!("Overlapping transmission on the {ch:?} channel")
+                }
+            };
+            (msg, true)
+        }
+    }
+}
+
+/// Show the packets and triggers received on `rx` in the terminal, until the user quits.
+/// An error received on `rx` is shown, and returned when the user quits.
+///
+/// If `on_mark` is set, the user can enter marker labels which are passed to it.
+pub fn run(
+    rx: Receiver<impl ViewInput>,
+    names: NameMap,
+    on_mark: Option<MarkHandler>,
+) -> Result<()> {
+    let mut terminal = ratatui::try_init().context("Failed to initialize the terminal.")?;
+    let mut view = BusView {
+        on_mark,
+        ..BusView::with_names(names)
+    };
+    let res = view.run_loop(&mut terminal, &rx);
+    ratatui::restore();
+    res?;
+    view.error.map_or(Ok(()), Err)
+}
+
+/// Hex and ASCII dump of the data, with the control characters shown as dots
+fn hex_dump(data: &[u8]) -> String {
+    let hex: Vec<String> = data.iter().map(|b| format!("{b:02x}")).collect();
+    let ascii: String = data
+        .iter()
+        .map(|&b| match b {
+            0x20..=0x7e => b as char,
+            _ => '.',
+        })
+        .collect();
+    format!("{}  |{ascii}|", hex.join(" "))
+}
+
+/// Interactive step-through view of the decoded transactions in a capture.
+struct StepView {
+    stepper: Stepper,
+    names: NameMap,
+    /// The breakpoint being entered
+    break_input: Option<String>,
+    /// Result of the last command, e.g. "no more errors"
+    message: String,
+}
+
+impl StepView {
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+        let [list_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(main);
+
+        let steps = self.stepper.steps();
+        let pos = self.stepper.position();
+        let height = list_area.height.saturating_sub(2) as usize;
+        // keep the current step in the middle of the list
+        let start = pos
+            .saturating_sub(height / 2)
+            .min(steps.len().saturating_sub(height));
+        let lines: Vec<Line> = steps
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(height)
+            .map(|(n, step)| {
+                let (msg, err) = describe_event(&self.names, &step.event);
+                let bp = match self.stepper.breakpoints().iter().any(|bp| step.matches(bp)) {
+                    true => '*',
+                    false => ' ',
+                };
+                let text = format!(
+                    "{bp}{n:>6} {} {msg}",
+                    step.event.time().format("%H:%M:%S%.3f")
+                );
+                let mut style = match err {
+                    true => Style::new().fg(Color::Red),
+                    false => Style::new(),
+                };
+                if n == pos {
+                    style = style.reversed();
+                }
+                Line::styled(text, style)
+            })
+            .collect();
+        let title = format!("Step {} of {}", pos + 1, steps.len());
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            list_area,
+        );
+
+        let mut detail = vec![];
+        if let Some(step) = self.stepper.current() {
+            let (msg, _) = describe_event(&self.names, &step.event);
+            detail.push(Line::raw(msg));
+            detail.push(Line::raw(format!("Time: {}", step.event.time())));
+            if let BusEvent::Transaction(t) = &step.event {
+                detail.push(Line::raw(format!("Command: {}", t.cmd_time)));
+            }
+            detail.push(Line::raw(""));
+            for pkt in &step.packets {
+                detail.push(Line::styled(
+                    format!(
+                        "{} {:?}, {} bytes",
+                        pkt.time.format("%H:%M:%S%.6f"),
+                        pkt.ch,
+                        pkt.data.len()
+                    ),
+                    Style::new().bold(),
+                ));
+                for chunk in pkt.data.chunks(8) {
+                    detail.push(Line::raw(format!("  {}", hex_dump(chunk))));
+                }
+            }
+        }
+        if !self.stepper.breakpoints().is_empty() {
+            let bps: Vec<String> = self
+                .stepper
+                .breakpoints()
+                .iter()
+                .map(|bp| bp.to_string())
+                .collect();
+            detail.push(Line::raw(""));
+            detail.push(Line::raw(format!("Breakpoints: {}", bps.join(", "))));
+        }
+        frame.render_widget(
+            Paragraph::new(detail).block(Block::bordered().title("Raw bytes")),
+            detail_area,
+        );
+
+        let (text, help) = match &self.break_input {
+            Some(input) => (
+                format!("Breakpoint ADDR[:PARAM]: {input}_"),
+                "Enter: toggle breakpoint, Esc: cancel",
+            ),
+            None => (
+                self.message.clone(),
+                "q: quit, ↓/↑: step, PgDn/PgUp: 20 steps, e/E: next/prev error, c: continue, b: breakpoint",
+            ),
+        };
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(help)),
+            status,
+        );
+    }
+
+    fn handle_break_key(&mut self, key: KeyCode) {
+        let Some(input) = &mut self.break_input else {
+            return;
+        };
+        match key {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Enter => {
+                let input = self.break_input.take().unwrap();
+                self.message = match input.parse() {
+                    Ok(bp) => match self.stepper.toggle_breakpoint(bp) {
+                        true => format!("Breakpoint set at {bp}"),
+                        false => format!("Breakpoint at {bp} removed"),
+                    },
+                    Err(e) => format!("{e:#}"),
+                };
+            }
+            KeyCode::Esc => self.break_input = None,
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.break_input.is_some() {
+            self.handle_break_key(key);
+            return true;
+        }
+        let stepper = &mut self.stepper;
+        let (moved, failed) = match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('b') => {
+                self.break_input = Some(String::new());
+                return true;
+            }
+            KeyCode::Down | KeyCode::Char('n') | KeyCode::Char(' ') => {
+                (stepper.forward(1), "At the last step")
+            }
+            KeyCode::Up | KeyCode::Char('p') => (stepper.back(1), "At the first step"),
+            KeyCode::PageDown => (stepper.forward(20), "At the last step"),
+            KeyCode::PageUp => (stepper.back(20), "At the first step"),
+            KeyCode::End => (stepper.forward(usize::MAX), "At the last step"),
+            KeyCode::Home => (stepper.back(usize::MAX), "At the first step"),
+            KeyCode::Char('e') => (stepper.next_error(), "No more errors"),
+            KeyCode::Char('E') => (stepper.prev_error(), "No earlier errors"),
+            KeyCode::Char('c') if stepper.breakpoints().is_empty() => (false, "No breakpoints set"),
+            KeyCode::Char('c') => (
+                stepper.continue_to_breakpoint(),
+                "No more steps at a breakpoint",
+            ),
+            _ => return true,
+        };
+        self.message = match (moved, self.stepper.at_breakpoint()) {
+            (false, _) => failed.to_string(),
+            (true, true) => "At a breakpoint".to_string(),
+            (true, false) => String::new(),
+        };
+        true
+    }
+
+    fn run_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Step through the decoded transactions in the terminal, until the user quits.
+pub fn run_stepper(stepper: Stepper, names: NameMap) -> Result<()> {
+    let mut terminal = ratatui::try_init().context("Failed to initialize the terminal.")?;
+    let mut view = StepView {
+        stepper,
+        names,
+        break_input: None,
+        message: String::new(),
+    };
+    let res = view.run_loop(&mut terminal);
+    ratatui::restore();
+    res
+}
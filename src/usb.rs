@@ -0,0 +1,58 @@
+//! Reads the framed capture records from the vendor USB interface of the capture device,
+//! which is faster and more robust than the muxed stream on the CDC serial port.
+
+use anyhow::{anyhow, bail, Context, Result};
+use nusb::transfer::{Queue, RequestBuffer};
+
+use crate::framed::{parse_packet, FramedRecord, VID_PID};
+
+const VENDOR_CLASS: u8 = 0xff;
+/// Number of bulk transfers kept queued, so the device never waits for the host
+const QUEUED_TRANSFERS: usize = 8;
+const PACKET_LEN: usize = 64;
+
+pub struct UsbCapture {
+    queue: Queue<RequestBuffer>,
+}
+
+impl UsbCapture {
+    /// Open the first capture device found
+    pub fn open() -> Result<Self> {
+        let info = nusb::list_devices()
+            .context("Failed to list the USB devices.")?
+            .find(|d| (d.vendor_id(), d.product_id()) == VID_PID)
+            .context("No capture device found.")?;
+        let device = info.open().context("Failed to open the capture device.")?;
+        let config = device
+            .active_configuration()
+            .context("Failed to read the USB configuration.")?;
+        let (iface, ep) = config
+            .interface_alt_settings()
+            .filter(|alt| alt.class() == VENDOR_CLASS)
+            .find_map(|alt| {
+                let ep = alt.endpoints().find(|ep| ep.address() & 0x80 != 0)?;
+                Some((alt.interface_number(), ep.address()))
+            })
+            .context("The capture device has no vendor interface, update the firmware.")?;
+        let interface = device
+            .claim_interface(iface)
+            .context("Failed to claim the capture interface.")?;
+        let mut queue = interface.bulk_in_queue(ep);
+        while queue.pending() < QUEUED_TRANSFERS {
+            queue.submit(RequestBuffer::new(PACKET_LEN));
+        }
+        Ok(Self { queue })
+    }
+
+    /// Wait for the next USB packet, and return the records in it
+    pub async fn next_records(&mut self) -> Result<Vec<FramedRecord>> {
+        let completion = self.queue.next_complete().await;
+        if let Err(e) = completion.status {
+            bail!(anyhow!(e).context("USB transfer failed."));
+        }
+        let records = parse_packet(&completion.data);
+        self.queue
+            .submit(RequestBuffer::reuse(completion.data, PACKET_LEN));
+        records
+    }
+}
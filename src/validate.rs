@@ -0,0 +1,184 @@
+//! Structural checks of capture files, for catching broken captures before they are analysed,
+//! e.g. in CI.
+
+use std::fmt;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use etherparse::{InternetSlice, ReadError, SlicedPacket, TransportSlice};
+use rpcap::read::PcapReader;
+
+use crate::{Marker, Trigger, CTRL, LINKTYPE_IPV4, MARKER, NODE, TRIGGER};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The file doesn't hold raw IPv4 packets
+    Linktype,
+    /// A UDP port which isn't used for UART data or markers
+    Port,
+    /// The timestamp is earlier than the one of the packet before it
+    Timestamp,
+    /// The packet or the file ends before the data in it
+    Truncated,
+    /// The addresses don't match the channel given by the port
+    Channel,
+    /// Not an IPv4 UDP packet, or an unreadable marker
+    Malformed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Index of the packet in the file, starting at 1 like in Wireshark, None for the file header
+    pub packet: Option<usize>,
+    pub detail: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.packet {
+            Some(n) => write!(f, "packet {n}: {:?}: {}", self.kind, self.detail),
+            None => write!(f, "header: {:?}: {}", self.kind, self.detail),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Number of packets in the file
+    pub packets: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn count(&self, kind: ProblemKind) -> usize {
+        self.problems.iter().filter(|p| p.kind == kind).count()
+    }
+
+    fn add(&mut self, kind: ProblemKind, packet: Option<usize>, detail: impl Into<String>) {
+        self.problems.push(Problem {
+            kind,
+            packet,
+            detail: detail.into(),
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{problem}")?;
+        }
+        write!(
+            f,
+            "{} packets, {} problems",
+            self.packets,
+            self.problems.len()
+        )
+    }
+}
+
+/// Check the capture in `reader`. Only a file which can't be read as pcap at all is an error,
+/// the problems found in it are listed in the report.
+pub fn validate<R: Read>(reader: R) -> Result<ValidationReport> {
+    let (options, mut pcap_reader) =
+        PcapReader::new(reader).context("Failed to read the pcap header.")?;
+    let mut report = ValidationReport::default();
+    if options.linktype != LINKTYPE_IPV4 {
+        report.add(
+            ProblemKind::Linktype,
+            None,
+            format!("linktype {}, expected {LINKTYPE_IPV4}", options.linktype),
+        );
+        return Ok(report);
+    }
+    let mut prev_time = None;
+    loop {
+        let pkt = match pcap_reader.next() {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => break,
+            Err(e) => {
+                let n = Some(report.packets + 1);
+                report.add(ProblemKind::Truncated, n, format!("unreadable record: {e}"));
+                break;
+            }
+        };
+        report.packets += 1;
+        let n = Some(report.packets);
+        if prev_time.is_some_and(|t| pkt.time < t) {
+            report.add(
+                ProblemKind::Timestamp,
+                n,
+                "timestamp earlier than the previous packet",
+            );
+        }
+        prev_time = Some(pkt.time);
+        if pkt.data.len() < pkt.orig_len {
+            let detail = format!("{} of {} bytes captured", pkt.data.len(), pkt.orig_len);
+            report.add(ProblemKind::Truncated, n, detail);
+            continue;
+        }
+        check_packet(pkt.data, &mut report);
+    }
+    Ok(report)
+}
+
+fn check_packet(data: &[u8], report: &mut ValidationReport) {
+    let n = Some(report.packets);
+    let pkt = match SlicedPacket::from_ip(data) {
+        Ok(pkt) => pkt,
+        Err(ReadError::UnexpectedEndOfSlice(len)) => {
+            let detail = format!("{} bytes, at least {len} expected", data.len());
+            return report.add(ProblemKind::Truncated, n, detail);
+        }
+        Err(e) => return report.add(ProblemKind::Malformed, n, format!("{e:?}")),
+    };
+    let (Some(InternetSlice::Ipv4(ip, _)), Some(TransportSlice::Udp(udp))) =
+        (&pkt.ip, &pkt.transport)
+    else {
+        return report.add(ProblemKind::Malformed, n, "not an IPv4 UDP packet");
+    };
+    if udp.length() as usize != 8 + pkt.payload.len() {
+        let detail = format!(
+            "UDP length {}, {} bytes in the packet",
+            udp.length(),
+            8 + pkt.payload.len()
+        );
+        return report.add(ProblemKind::Truncated, n, detail);
+    }
+    let ports = (udp.source_port(), udp.destination_port());
+    let (src, dst) = (ip.source(), ip.destination());
+    let (src_host, dst_host) = match ports {
+        (CTRL, NODE) => (1, 2),
+        (NODE, CTRL) => (2, 1),
+        (MARKER, MARKER) => {
+            if let Err(e) = Marker::decode(pkt.payload, chrono::DateTime::UNIX_EPOCH) {
+                report.add(ProblemKind::Malformed, n, format!("bad marker: {e}"));
+            }
+            return;
+        }
+        (TRIGGER, TRIGGER) => {
+            if let Err(e) = Trigger::decode(0, pkt.payload, chrono::DateTime::UNIX_EPOCH) {
+                report.add(ProblemKind::Malformed, n, format!("bad trigger: {e}"));
+            }
+            // both addresses are the bus
+            (1, 1)
+        }
+        _ => {
+            let detail = format!("UDP ports {} -> {}", ports.0, ports.1);
+            return report.add(ProblemKind::Port, n, detail);
+        }
+    };
+    // the bus is the third octet, and both ends must be on the same bus
+    if src[..2] != [127, 0] || dst[..3] != src[..3] || src[3] != src_host || dst[3] != dst_host {
+        let detail = format!(
+            "{}.{}.{}.{} -> {}.{}.{}.{} for UDP port {}",
+            src[0], src[1], src[2], src[3], dst[0], dst[1], dst[2], dst[3], ports.0
+        );
+        report.add(ProblemKind::Channel, n, detail);
+    }
+}
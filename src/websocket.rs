@@ -0,0 +1,277 @@
+//! Live stream of the decoded bus traffic over WebSocket, for browser dashboards.
+//!
+//! Every message is a JSON object with a `type`:
+//!
+//! - `transaction`: a command and its outcome, with `bus`, `time` (of the command), `addr`,
+//!   `param`, `write` (the value written, or null for a read), `outcome` ("ok", "timeout",
+//!   "invalid parameter", "command failed" or "protocol error"), `value` and `text`
+//! - `event`: another decoder event, with `bus`, `time` and `event`, e.g. "mismatch"
+//! - `marker`: a marker written to the capture, with `kind`, `channel`, `label` and `time`
+//!
+//! The server only sends, the messages from the clients are ignored.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+use crate::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use crate::diff::{Exchange, Outcome};
+use crate::export::channel_name;
+use crate::names::NameMap;
+use crate::status::{json_string, json_time};
+use crate::{CaptureRecord, Marker};
+
+/// Clients which don't accept data for this long are disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Appended to the client key for the handshake, from RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+type Clients = Arc<Mutex<Vec<(String, TcpStream)>>>;
+
+/// Streams the events decoded from the packets in `records` and the markers to the WebSocket
+/// clients connected to `listener`, from when they connect until the capture stops.
+pub fn serve(
+    listener: TcpListener,
+    records: Receiver<CaptureRecord>,
+    names: NameMap,
+) -> Result<()> {
+    let clients = Clients::default();
+    let acceptor = clients.clone();
+    std::thread::Builder::new()
+        .name("websocket-server".into())
+        .spawn(move || accept_clients(listener, acceptor))?;
+
+    let mut decoders: BTreeMap<u8, X328Decoder> = BTreeMap::new();
+    for record in records {
+        let bus = match &record {
+            CaptureRecord::Packet(pkt) => pkt.bus,
+            CaptureRecord::Trigger(trigger) => trigger.bus,
+            CaptureRecord::Marker(marker) => {
+                broadcast(&clients, &marker_json(marker));
+                continue;
+            }
+        };
+        // the decoder reports the triggers
+        let decoder = decoders.entry(bus).or_default();
+        decoder.feed_record(&record, |event| {
+            broadcast(&clients, &event_json(bus, &event, &names))
+        });
+    }
+    // disconnect the clients, so they see the end of the capture
+    for (_, stream) in clients.lock().unwrap().drain(..) {
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+    }
+    Ok(())
+}
+
+/// The JSON message for a decoder event
+pub fn event_json(bus: u8, event: &BusEvent, names: &NameMap) -> String {
+    if let Some(exchange) = Exchange::from_event(event) {
+        let write = match exchange.cmd {
+            BusCommand::Write { value, .. } => (*value).to_string(),
+            BusCommand::Read { .. } => "null".into(),
+        };
+        let (outcome, value) = match exchange.outcome {
+            Outcome::Ok(v) => ("ok".to_string(), v.to_string()),
+            other => (other.to_string(), "null".into()),
+        };
+        return format!(
+            r#"{{"type":"transaction","bus":{bus},"time":{},"addr":{},"param":{},"write":{write},"outcome":"{outcome}","value":{value},"text":{}}}"#,
+            json_time(exchange.time),
+            *exchange.cmd.addr(),
+            *exchange.cmd.param(),
+            json_string(&exchange.describe(names)),
+        );
+    }
+    let name = match event {
+        BusEvent::Transaction(_) => unreachable!("transactions are exchanges"),
+        BusEvent::Timeout { .. } => "timeout",
+        BusEvent::UnexpectedTransmission { .. } => "unexpected transmission",
+        BusEvent::Mismatch { .. } => "mismatch",
+        BusEvent::Trigger { .. } => "trigger",
+        BusEvent::Break { .. } => "break",
+        BusEvent::Collision {
+            kind: Collision::Echo { .. },
+            ..
+        } => "echo",
+        BusEvent::Collision {
+            kind: Collision::SecondMaster { .. },
+            ..
+        } => "second master",
+        BusEvent::Collision {
+            kind: Collision::Overlap { .. },
+            ..
+        } => "overlap",
+    };
+    format!(
+        r#"{{"type":"event","bus":{bus},"time":{},"event":"{name}"}}"#,
+        json_time(event.time())
+    )
+}
+
+/// The JSON message for a marker
+pub fn marker_json(marker: &Marker) -> String {
+    let channel = match marker.ch {
+        Some(ch) => json_string(channel_name(ch)),
+        None => "null".into(),
+    };
+    format!(
+        r#"{{"type":"marker","kind":"{}","channel":{channel},"label":{},"time":{}}}"#,
+        marker.kind.as_str(),
+        json_string(&marker.label),
+        json_time(marker.time)
+    )
+}
+
+fn broadcast(clients: &Clients, message: &str) {
+    let frame = text_frame(message);
+    clients.lock().unwrap().retain_mut(|(peer, stream)| {
+        let res = stream.write_all(&frame);
+        if let Err(e) = &res {
+            info!("Disconnecting WebSocket client {peer}: {e}");
+        }
+        res.is_ok()
+    });
+}
+
+fn accept_clients(listener: TcpListener, clients: Clients) {
+    for stream in listener.incoming() {
+        let res = stream.map_err(anyhow::Error::from).and_then(|mut stream| {
+            let peer = stream.peer_addr()?.to_string();
+            stream.set_read_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+            stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            handshake(&mut stream)?;
+            info!("WebSocket client {peer} connected.");
+            clients.lock().unwrap().push((peer, stream));
+            Ok(())
+        });
+        if let Err(e) = res {
+            warn!("Failed to accept WebSocket client: {e:#}");
+        }
+    }
+}
+
+/// Read the upgrade request and accept it
+fn handshake(stream: &mut TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(&*stream);
+    let mut key = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("The connection was closed during the handshake.");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+    let Some(key) = key else {
+        stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n")?;
+        bail!("Not a WebSocket upgrade request.");
+    };
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )
+    .context("Failed to answer the WebSocket handshake.")
+}
+
+/// The Sec-WebSocket-Accept value for the client key
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()))
+}
+
+/// An unfragmented, unmasked text frame
+pub fn text_frame(text: &str) -> Vec<u8> {
+    let len = text.len();
+    let mut frame = vec![0x81];
+    match len {
+        0..=125 => frame.push(len as u8),
+        126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(wi);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (out, h) in digest.chunks_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
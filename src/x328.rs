@@ -0,0 +1,221 @@
+//! Raw X3.28 frames.
+//!
+//! Builds and checks the bytes of the commands and responses on the bus, for test fixtures and
+//! simulators which need byte-exact traffic, e.g. with a corrupt BCC, without running the
+//! x328-proto bus controller and node state machines. The frames are the same as the ones sent
+//! by x328-proto for values in the normal format.
+
+use std::fmt;
+
+use x328_proto::{Address, Parameter, Value};
+
+use crate::decode::BusCommand;
+
+/// End of transmission, starts a command, or the node response to an invalid parameter
+pub const EOT: u8 = 0x04;
+/// Start of text, before the parameter of a write command or a read response
+pub const STX: u8 = 0x02;
+/// End of text, after the value, followed by the BCC
+pub const ETX: u8 = 0x03;
+/// Enquiry, ends a read command
+pub const ENQ: u8 = 0x05;
+/// Positive node response to a write command
+pub const ACK: u8 = 0x06;
+/// Negative node response
+pub const NAK: u8 = 0x15;
+
+/// A frame which isn't a valid X3.28 command or response
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame ends before it's complete, or has data after the end
+    Length,
+    /// A control character is missing or in the wrong place
+    Layout,
+    Address,
+    Parameter,
+    Value,
+    /// The BCC in the frame isn't the one computed from the data
+    Bcc {
+        expected: u8,
+        found: u8,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Length => f.write_str("Wrong X3.28 frame length."),
+            FrameError::Layout => f.write_str("Invalid X3.28 frame layout."),
+            FrameError::Address => f.write_str("Invalid X3.28 address."),
+            FrameError::Parameter => f.write_str("Invalid X3.28 parameter."),
+            FrameError::Value => f.write_str("Invalid X3.28 value."),
+            FrameError::Bcc { expected, found } => {
+                write!(f, "Bad BCC {found:#04x}, expected {expected:#04x}.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// The block check character of the data between STX and the BCC, including the ETX
+pub fn bcc(data: &[u8]) -> u8 {
+    let bcc = data.iter().fold(0, |bcc, b| bcc ^ b);
+    // the BCC is never a control character
+    if bcc < 0x20 {
+        bcc + 0x20
+    } else {
+        bcc
+    }
+}
+
+/// Address 21 is sent as "2211"
+pub fn encode_address(addr: Address) -> [u8; 4] {
+    let (tens, ones) = (b'0' + *addr / 10, b'0' + *addr % 10);
+    [tens, tens, ones, ones]
+}
+
+/// Four digits, with leading zeros
+pub fn encode_parameter(param: Parameter) -> [u8; 4] {
+    let param = *param as u16;
+    [1000, 100, 10, 1].map(|d| b'0' + (param / d % 10) as u8)
+}
+
+/// The sign and as few digits as possible, at most six characters
+pub fn encode_value(value: Value) -> Vec<u8> {
+    let value = *value;
+    let mut bytes = match value.is_negative() {
+        true => b"-".to_vec(),
+        false => b"+".to_vec(),
+    };
+    bytes.extend_from_slice(value.unsigned_abs().to_string().as_bytes());
+    if bytes.len() > 6 {
+        // six digits don't leave room for the sign
+        bytes.remove(0);
+    }
+    bytes
+}
+
+/// EOT, address, parameter, ENQ
+pub fn read_command(addr: Address, param: Parameter) -> Vec<u8> {
+    let mut frame = vec![EOT];
+    frame.extend_from_slice(&encode_address(addr));
+    frame.extend_from_slice(&encode_parameter(param));
+    frame.push(ENQ);
+    frame
+}
+
+/// EOT, address, STX, parameter, value, ETX, BCC
+pub fn write_command(addr: Address, param: Parameter, value: Value) -> Vec<u8> {
+    let mut frame = vec![EOT];
+    frame.extend_from_slice(&encode_address(addr));
+    frame.extend(text(param, value));
+    frame
+}
+
+/// The node response to a read of a valid parameter: STX, parameter, value, ETX, BCC
+pub fn read_response(param: Parameter, value: Value) -> Vec<u8> {
+    text(param, value)
+}
+
+/// The command frame for `cmd`
+pub fn command(cmd: &BusCommand) -> Vec<u8> {
+    match *cmd {
+        BusCommand::Read { addr, param } => read_command(addr, param),
+        BusCommand::Write { addr, param, value } => write_command(addr, param, value),
+    }
+}
+
+fn text(param: Parameter, value: Value) -> Vec<u8> {
+    let mut frame = vec![STX];
+    frame.extend_from_slice(&encode_parameter(param));
+    frame.extend(encode_value(value));
+    frame.push(ETX);
+    frame.push(bcc(&frame[1..]));
+    frame
+}
+
+/// Decode a complete read or write command frame
+pub fn parse_command(frame: &[u8]) -> Result<BusCommand, FrameError> {
+    if frame.len() < 10 {
+        return Err(FrameError::Length);
+    }
+    if frame[0] != EOT {
+        return Err(FrameError::Layout);
+    }
+    let addr = parse_address(&frame[1..5])?;
+    match frame[5] {
+        STX => {
+            let (param, value) = parse_text(&frame[5..])?;
+            Ok(BusCommand::Write { addr, param, value })
+        }
+        _ if frame.len() != 10 => Err(FrameError::Length),
+        _ if frame[9] != ENQ => Err(FrameError::Layout),
+        _ => Ok(BusCommand::Read {
+            addr,
+            param: parse_parameter(&frame[5..9])?,
+        }),
+    }
+}
+
+/// Decode the response to a read of a valid parameter
+pub fn parse_read_response(frame: &[u8]) -> Result<(Parameter, Value), FrameError> {
+    parse_text(frame)
+}
+
+/// STX, parameter, value, ETX, BCC
+fn parse_text(frame: &[u8]) -> Result<(Parameter, Value), FrameError> {
+    // the shortest value is a single digit
+    if frame.len() < 8 {
+        return Err(FrameError::Length);
+    }
+    if frame[0] != STX {
+        return Err(FrameError::Layout);
+    }
+    let etx = frame.len() - 2;
+    if frame[etx] != ETX {
+        return match frame[1..].contains(&ETX) {
+            true => Err(FrameError::Length),
+            false => Err(FrameError::Layout),
+        };
+    }
+    let (expected, found) = (bcc(&frame[1..=etx]), frame[etx + 1]);
+    if expected != found {
+        return Err(FrameError::Bcc { expected, found });
+    }
+    let param = parse_parameter(&frame[1..5])?;
+    Ok((param, parse_value(&frame[5..etx])?))
+}
+
+fn parse_address(bytes: &[u8]) -> Result<Address, FrameError> {
+    if bytes[0] != bytes[1] || bytes[2] != bytes[3] {
+        return Err(FrameError::Address);
+    }
+    let addr = parse_digits(&[bytes[0], bytes[2]]).ok_or(FrameError::Address)?;
+    Address::new(addr).map_err(|_| FrameError::Address)
+}
+
+fn parse_parameter(bytes: &[u8]) -> Result<Parameter, FrameError> {
+    let param = parse_digits(bytes).ok_or(FrameError::Parameter)?;
+    Parameter::new(param).map_err(|_| FrameError::Parameter)
+}
+
+fn parse_value(bytes: &[u8]) -> Result<Value, FrameError> {
+    let (negative, digits) = match bytes.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        Some((b'+', digits)) => (false, digits),
+        _ => (false, bytes),
+    };
+    if bytes.len() > 6 {
+        return Err(FrameError::Value);
+    }
+    let value = parse_digits(digits).ok_or(FrameError::Value)? as i32;
+    Value::new(if negative { -value } else { value }).map_err(|_| FrameError::Value)
+}
+
+fn parse_digits(bytes: &[u8]) -> Option<u32> {
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |n, b| n * 10 + (b - b'0') as u32))
+}
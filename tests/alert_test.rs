@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use x328_proto::master::Error as X328Error;
+use x328_proto::{addr, param, Value};
+
+use serial_pcap::alert::{Alert, AlertMonitor, AlertRules, Comparison, Rule};
+use serial_pcap::decode::{BusCommand, BusEvent, Transaction};
+use serial_pcap::{CaptureRecord, Marker, MarkerKind, SerialPacketReader, SerialPacketWriter};
+
+fn time(ms: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap() + chrono::Duration::milliseconds(ms)
+}
+
+fn read(a: u8, p: i16, result: Result<i32, X328Error>, ms: i64) -> BusEvent {
+    BusEvent::Transaction(Transaction {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(p),
+        },
+        cmd_time: time(ms),
+        resp_time: time(ms),
+        result: result.map(|v| Value::new(v).unwrap()),
+    })
+}
+
+fn run(rules: &str, events: &[BusEvent]) -> Result<Vec<Alert>> {
+    let mut monitor = AlertMonitor::new(rules.parse()?);
+    let mut alerts = vec![];
+    for event in events {
+        monitor.handle_event(event, |a| alerts.push(a));
+    }
+    assert_eq!(monitor.alerts(), alerts.len() as u64);
+    Ok(alerts)
+}
+
+#[test]
+fn test_parse_rules() -> Result<()> {
+    let rules: AlertRules = "\
+        # stow pressure\n\
+        addr 31 param 401 > 180 for 5s\n\
+        \n\
+        addr 31 param 402 <= -10  # at once\n\
+        no response from addr 12 for 30s\n"
+        .parse()?;
+    assert_eq!(
+        rules.rules,
+        [
+            Rule::Value {
+                addr: 31,
+                param: 401,
+                cmp: Comparison::Greater,
+                limit: 180,
+                duration: Duration::from_secs(5),
+            },
+            Rule::Value {
+                addr: 31,
+                param: 402,
+                cmp: Comparison::LessOrEqual,
+                limit: -10,
+                duration: Duration::ZERO,
+            },
+            Rule::NoResponse {
+                addr: 12,
+                duration: Duration::from_secs(30),
+            },
+        ]
+    );
+    assert_eq!(rules.rules[0].to_string(), "addr 31 param 401 > 180 for 5s");
+    assert_eq!(rules.rules[1].to_string(), "addr 31 param 402 <= -10");
+
+    for bad in [
+        "addr 31 param 401 => 180",
+        "addr 100 param 401 > 180",
+        "addr 31 param 401 > 180 for",
+        "no response from addr 12",
+        "addr 31",
+    ] {
+        assert!(bad.parse::<Rule>().is_err(), "{bad}");
+    }
+    let err = "addr 1 param 2 > 3\nfoo".parse::<AlertRules>().unwrap_err();
+    assert!(format!("{err:#}").starts_with("Line 2"), "{err:#}");
+    Ok(())
+}
+
+#[test]
+fn test_value_rule() -> Result<()> {
+    let events = [
+        read(31, 401, Ok(190), 0),
+        read(31, 401, Ok(200), 3000),
+        // other parameters and failed reads don't matter
+        read(31, 402, Ok(0), 4000),
+        read(31, 401, Err(X328Error::CommandFailed), 4500),
+        read(31, 401, Ok(185), 5000),
+        // fired once while the value stays high
+        read(31, 401, Ok(185), 9000),
+        read(31, 401, Ok(180), 10000),
+        read(31, 401, Ok(181), 11000),
+        read(31, 401, Ok(181), 17000),
+    ];
+    let alerts = run("addr 31 param 401 > 180 for 5s", &events)?;
+    assert_eq!(alerts.len(), 2, "{alerts:?}");
+    assert_eq!(alerts[0].time, time(5000));
+    assert_eq!(alerts[0].value, Some(185));
+    assert_eq!(
+        alerts[0].to_string(),
+        "addr 31 param 401 > 180 for 5s (value 185)"
+    );
+    assert_eq!(alerts[1].time, time(17000));
+
+    // the time is checked at the other events too
+    let alerts = run(
+        "addr 31 param 401 > 180 for 5s",
+        &[read(31, 401, Ok(190), 0), read(5, 1, Ok(0), 6000)],
+    )?;
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].time, time(6000));
+    Ok(())
+}
+
+#[test]
+fn test_no_response_rule() -> Result<()> {
+    let events = [
+        read(5, 1, Ok(0), 0),
+        read(12, 1, Ok(0), 10_000),
+        read(5, 1, Ok(0), 39_000),
+        read(5, 1, Ok(0), 41_000),
+        read(5, 1, Ok(0), 50_000),
+        // EOT is a response too
+        read(12, 1, Err(X328Error::CommandFailed), 55_000),
+        BusEvent::Timeout {
+            cmd: None,
+            time: time(84_000),
+        },
+        BusEvent::Timeout {
+            cmd: None,
+            time: time(86_000),
+        },
+    ];
+    let alerts = run("no response from addr 12 for 30s", &events)?;
+    let times: Vec<_> = alerts.iter().map(|a| a.time).collect();
+    assert_eq!(times, [time(41_000), time(86_000)]);
+    assert_eq!(alerts[0].value, None);
+
+    // a node which never responds, checked while the bus is silent
+    let rules = "no response from addr 12 for 30s".parse()?;
+    let mut monitor = AlertMonitor::new(rules);
+    let mut alerts = vec![];
+    monitor.check(time(0), |a| alerts.push(a));
+    monitor.check(time(29_000), |a| alerts.push(a));
+    assert!(alerts.is_empty());
+    monitor.check(time(30_000), |a| alerts.push(a));
+    assert_eq!(alerts.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_alert_marker() -> Result<()> {
+    let marker = Marker {
+        kind: MarkerKind::Alert,
+        ch: None,
+        label: "no response from addr 12 for 30s".into(),
+        time: time(0),
+    };
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_marker(&marker)?;
+    drop(writer);
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    match reader.next_record()? {
+        Some(CaptureRecord::Marker(m)) => assert_eq!(m, marker),
+        r => panic!("Expected the marker, got {r:?}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::export::{export, AnalyzerFormat, ExportTiming};
+use serial_pcap::import::{read_analyzer_export, write_analyzer_bytes, ChannelNames};
+use serial_pcap::{SerialPacket, SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+const TRAFFIC: &[(UartTxChannel, &[u8])] = &[
+    (UartTxChannel::Ctrl, b"\x042211\x02223442\x03\x36"),
+    (UartTxChannel::Node, b"\x06"),
+    (UartTxChannel::Ctrl, b"\x0422110023\x05"),
+    (UartTxChannel::Node, b"\x020023+33\x03\x2c"),
+];
+
+fn write_pcap(filename: &std::path::Path, start: SystemTime) -> Result<()> {
+    let mut writer = SerialPacketWriter::new_file(filename)?;
+    let mut time = start;
+    for (ch, data) in TRAFFIC {
+        writer.write_packet_time(data, *ch, time)?;
+        // leave room for the data to be transmitted at 9600 baud, plus some idle time
+        time += Duration::from_micros(1042 * data.len() as u64 + 20_000);
+    }
+    Ok(())
+}
+
+fn read_pcap(filename: &std::path::Path) -> Result<Vec<SerialPacket>> {
+    SerialPacketReader::from_file(filename)?.collect()
+}
+
+#[test]
+fn test_analyzer_roundtrip() -> Result<()> {
+    let dir = std::env::temp_dir();
+    let orig = dir.join("serial_pcap_analyzer_orig.pcap");
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    write_pcap(&orig, start)?;
+
+    for format in [AnalyzerFormat::Saleae, AnalyzerFormat::Sigrok] {
+        let mut exported = vec![];
+        let timing = ExportTiming::default();
+        export(
+            &mut SerialPacketReader::from_file(&orig)?,
+            &mut exported,
+            format,
+            timing,
+        )?;
+
+        let bytes = read_analyzer_export(
+            exported.as_slice(),
+            format,
+            &ChannelNames::default(),
+            timing.samplerate,
+        )?;
+        let imported = dir.join(format!("serial_pcap_analyzer_{format:?}.pcap"));
+        let mut writer = SerialPacketWriter::new_file(&imported)?;
+        write_analyzer_bytes(&bytes, &mut writer, start, Duration::from_millis(5))?;
+        drop(writer);
+
+        let orig = read_pcap(&orig)?;
+        let imported = read_pcap(&imported)?;
+        assert_eq!(orig.len(), imported.len(), "{format:?}");
+        for (a, b) in orig.iter().zip(&imported) {
+            assert_eq!((a.ch, &a.data, a.time), (b.ch, &b.data, b.time), "{format:?}");
+        }
+    }
+    Ok(())
+}
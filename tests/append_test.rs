@@ -0,0 +1,57 @@
+use std::io::{Cursor, Write};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn data(pcap: &[u8]) -> Result<Vec<Vec<u8>>> {
+    SerialPacketReader::new(pcap)?
+        .map(|p| p.map(|p| p.data.to_vec()))
+        .collect()
+}
+
+#[test]
+fn test_append() -> Result<()> {
+    let now = SystemTime::now();
+    let mut pcap = Cursor::new(vec![]);
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, now)?;
+    drop(writer);
+
+    let mut writer = SerialPacketWriter::append(&mut pcap)?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, now)?;
+    drop(writer);
+    assert_eq!(
+        data(pcap.get_ref())?,
+        [b"\x0400110023\x05".to_vec(), b"\x06".to_vec()]
+    );
+
+    // a file with another linktype is refused
+    let mut other = pcap.get_ref().clone();
+    other[20] = 1;
+    assert!(SerialPacketWriter::append(Cursor::new(other)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_append_file_drops_partial_record() -> Result<()> {
+    let path = std::env::temp_dir().join("serial_pcap_append_test.pcap");
+    let _ = std::fs::remove_file(&path);
+    let now = SystemTime::now();
+    let mut writer = SerialPacketWriter::append_file(&path)?;
+    writer.write_packet_time(b"\x04", UartTxChannel::Ctrl, now)?;
+    drop(writer);
+    // a capture killed in the middle of a record
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+    file.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 100, 0, 0, 0])?;
+    drop(file);
+
+    let mut writer = SerialPacketWriter::append_file(&path)?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, now + Duration::from_secs(1))?;
+    drop(writer);
+    let pcap = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(data(&pcap)?, [b"\x04".to_vec(), b"\x06".to_vec()]);
+    Ok(())
+}
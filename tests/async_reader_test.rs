@@ -0,0 +1,126 @@
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use serial_pcap::async_reader::AsyncSerialPacketReader;
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, SerialPacketReader, SerialPacketWriter, UartTxChannel,
+};
+
+fn test_capture() -> Result<(Vec<u8>, Marker)> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let marker = Marker {
+        kind: MarkerKind::Drop,
+        ch: Some(UartTxChannel::Ctrl),
+        label: "read error".into(),
+        time: (start + Duration::from_millis(500)).into(),
+    };
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        for i in 0..200u32 {
+            let ch = match i % 2 {
+                0 => UartTxChannel::Ctrl,
+                _ => UartTxChannel::Node,
+            };
+            let data = i.to_string().repeat(i as usize % 30 + 1);
+            writer.write_packet_time(
+                data.as_bytes(),
+                ch,
+                start + Duration::from_micros(i as u64 * 4321),
+            )?;
+            if i == 100 {
+                writer.write_marker(&marker)?;
+            }
+        }
+    }
+    Ok((pcap, marker))
+}
+
+#[tokio::test]
+async fn test_async_reader_matches_sync_reader() -> Result<()> {
+    let (pcap, marker) = test_capture()?;
+    let expected: Vec<_> = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<_>>()?;
+
+    // a small pipe, so the records arrive in pieces
+    let (mut tx, rx) = tokio::io::duplex(7);
+    let feeder = tokio::spawn(async move { tx.write_all(&pcap).await });
+
+    let mut reader = AsyncSerialPacketReader::new(rx).await?;
+    let mut packets = vec![];
+    let mut markers = vec![];
+    while let Some(record) = reader.next_record().await? {
+        match record {
+            CaptureRecord::Packet(pkt) => packets.push(pkt),
+            CaptureRecord::Marker(m) => markers.push(m),
+            CaptureRecord::Trigger(t) => panic!("unexpected trigger {t}"),
+        }
+    }
+    feeder.await??;
+
+    assert_eq!(markers, [marker]);
+    assert_eq!(packets.len(), expected.len());
+    for (a, b) in packets.iter().zip(&expected) {
+        assert_eq!((a.ch, &a.data, a.time), (b.ch, &b.data, b.time));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_reader_truncated() -> Result<()> {
+    let (pcap, _) = test_capture()?;
+    let mut reader = AsyncSerialPacketReader::new(&pcap[..pcap.len() - 3]).await?;
+    let result = loop {
+        match reader.next_packet().await {
+            Ok(Some(_)) => {}
+            other => break other,
+        }
+    };
+    assert!(result.is_err());
+
+    assert!(
+        AsyncSerialPacketReader::new(&b"not a pcap file at all.."[..])
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_channel_reader() -> Result<()> {
+    let (pcap, _) = test_capture()?;
+    let mut expected = vec![];
+    SerialPacketReader::new(pcap.as_slice())?
+        .reader(UartTxChannel::Node)
+        .read_to_end(&mut expected)?;
+
+    let mut reader = AsyncSerialPacketReader::new(pcap.as_slice()).await?;
+    let mut data = vec![];
+    reader
+        .reader(UartTxChannel::Node)
+        .read_to_end(&mut data)
+        .await?;
+    assert_eq!(data, expected);
+
+    let mut reader = AsyncSerialPacketReader::new(pcap.as_slice()).await?;
+    // the first packet on the ctrl channel is "0"
+    let bytes = reader.read_bytes(UartTxChannel::Ctrl, 10).await?;
+    assert_eq!(&bytes[..], b"0");
+    // the node channel was buffered meanwhile
+    let bytes = reader.read_bytes(UartTxChannel::Node, 1).await?;
+    assert_eq!(&bytes[..], b"1");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_async_reader_bad_timestamp() -> Result<()> {
+    let (mut pcap, _) = test_capture()?;
+    // the microseconds of the first record
+    pcap[28..32].copy_from_slice(&1_000_000u32.to_le_bytes());
+    let mut reader = AsyncSerialPacketReader::new(pcap.as_slice()).await?;
+    let err = reader.next_record().await.unwrap_err();
+    assert!(err.to_string().contains("timestamp"), "{err}");
+    Ok(())
+}
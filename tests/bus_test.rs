@@ -0,0 +1,58 @@
+use std::io::Read;
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use serial_pcap::decode::{BusEvent, X328Decoder};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn write_two_buses() -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let now = SystemTime::now();
+    writer.write_bus_packet_time(1, b"\x0499880011\x05", UartTxChannel::Ctrl, now)?;
+    writer.write_packet_time(b"\x0422110023\x05", UartTxChannel::Ctrl, now)?;
+    writer.write_bus_packet_time(1, b"\x15", UartTxChannel::Node, now)?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, now)?;
+    drop(writer);
+    Ok(pcap)
+}
+
+#[test]
+fn test_bus_roundtrip() -> Result<()> {
+    let pcap = write_two_buses()?;
+    let packets = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<Vec<_>>>()?;
+    let buses: Vec<_> = packets.iter().map(|p| (p.bus, p.ch)).collect();
+    assert_eq!(
+        buses,
+        [
+            (1, UartTxChannel::Ctrl),
+            (0, UartTxChannel::Ctrl),
+            (1, UartTxChannel::Node),
+            (0, UartTxChannel::Node),
+        ]
+    );
+
+    // only bus 0 is decoded
+    let mut decoder = X328Decoder::new();
+    let mut transactions = vec![];
+    for pkt in &packets {
+        decoder.feed(pkt, |e| {
+            if let BusEvent::Transaction(t) = e {
+                transactions.push(*t.cmd.addr());
+            }
+        });
+    }
+    assert_eq!(transactions, [21]);
+    Ok(())
+}
+
+#[test]
+fn test_byte_reader_skips_other_buses() -> Result<()> {
+    let pcap = write_two_buses()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut ctrl = vec![];
+    reader.reader(UartTxChannel::Ctrl).read_to_end(&mut ctrl)?;
+    assert_eq!(ctrl, b"\x0422110023\x05");
+    Ok(())
+}
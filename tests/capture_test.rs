@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use serial_pcap::capture::{
+    read_framed_datagrams, read_framed_stream, read_muxed_uart, read_uart, DropStats, UartEvent,
+    UartSink,
+};
+use serial_pcap::clock::CaptureClock;
+use serial_pcap::framed::{
+    FramedRecord, FLAG_BUS2, FLAG_CTRL, FLAG_DROP, FLAG_TRIGGER, TRIGGER_SOURCE_BUTTON,
+};
+use serial_pcap::pool::BufferPool;
+use serial_pcap::queue::{bounded, OverflowPolicy};
+use serial_pcap::{MuxFraming, Trigger, UartTxChannel, BREAK_BYTE, DROP_BYTE, TRIG_BYTE};
+use serial_pcap_core::mux::CTRL_BIT;
+use serial_pcap_core::slip::Frame;
+
+fn sink() -> (
+    UartSink,
+    serial_pcap::queue::QueueReceiver<serial_pcap::capture::UartRead>,
+) {
+    let (tx, rx) = bounded(100, OverflowPolicy::Block);
+    let clock = CaptureClock::new();
+    let drops = Arc::new(DropStats::new(clock.clone()));
+    let sink = UartSink {
+        tx,
+        drops,
+        clock,
+        pool: BufferPool::new(),
+    };
+    (sink, rx)
+}
+
+#[tokio::test]
+async fn test_read_uart_from_slice() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let input: &[u8] = b"\x0400110023\x05";
+    // the end of the stream is an error, like a serial port which goes away
+    assert!(read_uart(input, 0, UartTxChannel::Ctrl, tx).await.is_err());
+    let mut data = vec![];
+    while let Some(read) = rx.recv().await {
+        assert_eq!((read.bus, read.ch_name), (0, UartTxChannel::Ctrl));
+        data.extend_from_slice(&read.data);
+    }
+    assert_eq!(data, input);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_muxed_uart_from_slice() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let drops = tx.drops.clone();
+    let mut input: Vec<u8> = b"\x0400110023\x05".iter().map(|b| b | 0x80).collect();
+    input.extend_from_slice(&[DROP_BYTE, b'\x06']);
+    assert!(read_muxed_uart(input.as_slice(), 1, MuxFraming::Bit7, tx)
+        .await
+        .is_err());
+    let (mut ctrl, mut node) = (vec![], vec![]);
+    while let Some(read) = rx.recv().await {
+        assert_eq!(read.bus, 1);
+        match read.ch_name {
+            UartTxChannel::Ctrl => ctrl.extend_from_slice(&read.data),
+            UartTxChannel::Node => node.extend_from_slice(&read.data),
+        }
+    }
+    assert_eq!(ctrl, b"\x0400110023\x05");
+    assert_eq!(node, b"\x06");
+    // the end of the stream isn't a read error, so only the device overrun is counted
+    assert_eq!(drops.total_events(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_muxed_trigger() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let mut input: Vec<u8> = b"\x0400110023\x05".iter().map(|b| b | 0x80).collect();
+    input.insert(3, TRIG_BYTE);
+    assert!(read_muxed_uart(input.as_slice(), 0, MuxFraming::Bit7, tx)
+        .await
+        .is_err());
+    let mut parts = vec![vec![]];
+    while let Some(read) = rx.recv().await {
+        match read.event {
+            Some(event) => {
+                assert_eq!(event, UartEvent::Trigger(Trigger::DEVICE.into()));
+                parts.push(vec![]);
+            }
+            None => parts.last_mut().unwrap().extend_from_slice(&read.data),
+        }
+    }
+    // the trigger is taken out of the data, and recorded between the bytes around it
+    assert_eq!(parts, [&b"\x0400"[..], b"110023\x05"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slip_muxed_8bit_data() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let mut input = vec![];
+    // 8 bit data, with the trigger byte and bit 7 set in the node data
+    Frame::Data(b"\x8a\n\xff").encode(0, |b| input.push(b));
+    Frame::Trigger.encode(0, |b| input.push(b));
+    Frame::Data(b"\x06").encode(CTRL_BIT, |b| input.push(b));
+    assert!(read_muxed_uart(input.as_slice(), 0, MuxFraming::Slip, tx)
+        .await
+        .is_err());
+    let mut reads = vec![];
+    while let Some(read) = rx.recv().await {
+        reads.push((read.ch_name, read.data.to_vec(), read.event));
+    }
+    let trigger = UartEvent::Trigger(Trigger::DEVICE.into());
+    assert_eq!(
+        reads,
+        [
+            (UartTxChannel::Node, b"\x8a\n\xff".to_vec(), None),
+            (UartTxChannel::Node, vec![], Some(trigger)),
+            (UartTxChannel::Ctrl, b"\x06".to_vec(), None),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_muxed_break() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let mut input: Vec<u8> = b"\x040011".iter().map(|b| b | 0x80).collect();
+    input.push(BREAK_BYTE | 0x80);
+    input.extend(b"\x0400110023\x05".iter().map(|b| b | 0x80));
+    assert!(read_muxed_uart(input.as_slice(), 0, MuxFraming::Bit7, tx)
+        .await
+        .is_err());
+    let mut parts = vec![vec![]];
+    while let Some(read) = rx.recv().await {
+        match read.event {
+            Some(event) => {
+                assert_eq!(
+                    (event, read.ch_name),
+                    (UartEvent::Break, UartTxChannel::Ctrl)
+                );
+                parts.push(vec![]);
+            }
+            None => parts.last_mut().unwrap().extend_from_slice(&read.data),
+        }
+    }
+    assert_eq!(parts, [&b"\x040011"[..], b"\x0400110023\x05"]);
+    Ok(())
+}
+
+fn framed(records: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut packet = vec![];
+    for (n, (flags, data)) in records.iter().enumerate() {
+        let record = FramedRecord {
+            flags: *flags,
+            time: 1000 * n as u32,
+            data: (*data).into(),
+        };
+        record.encode(&mut packet);
+    }
+    packet
+}
+
+#[tokio::test]
+async fn test_read_framed_stream() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let drops = tx.drops.clone();
+    let input = framed(&[
+        (FLAG_CTRL, b"\x0400110023\x05"),
+        // older firmware sends a trigger byte instead of the source
+        (FLAG_TRIGGER, b"\n"),
+        (FLAG_TRIGGER, TRIGGER_SOURCE_BUTTON),
+        (FLAG_DROP, b"\x06"),
+        (FLAG_BUS2, b"\x15"),
+    ]);
+    // the records arrive in pieces, split anywhere
+    let (client, mut server) = tokio::io::duplex(7);
+    let writer = tokio::spawn(async move {
+        use tokio::io::AsyncWriteExt;
+        server.write_all(&input).await
+    });
+    // the closed connection ends the capture
+    assert!(read_framed_stream(client, tx).await.is_err());
+    writer.await??;
+    let mut reads = vec![];
+    while let Some(read) = rx.recv().await {
+        reads.push((read.bus, read.ch_name, read.event, read.data.to_vec()));
+    }
+    assert_eq!(
+        reads,
+        [
+            (0, UartTxChannel::Ctrl, None, b"\x0400110023\x05".to_vec()),
+            (
+                0,
+                UartTxChannel::Node,
+                Some(UartEvent::Trigger("device".into())),
+                vec![]
+            ),
+            (
+                0,
+                UartTxChannel::Node,
+                Some(UartEvent::Trigger("button A".into())),
+                vec![]
+            ),
+            (0, UartTxChannel::Node, None, b"\x06".to_vec()),
+            (1, UartTxChannel::Node, None, b"\x15".to_vec()),
+        ]
+    );
+    assert_eq!(drops.total_events(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_framed_datagrams() -> Result<()> {
+    let (tx, mut rx) = sink();
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    let reader = tokio::spawn(read_framed_datagrams(socket, tx));
+    let device = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+    device
+        .send_to(&framed(&[(FLAG_CTRL, b"\x0400110023\x05")]), addr)
+        .await?;
+    // a truncated datagram is ignored
+    device.send_to(&[5, 0, 0, 0, 0, 0, 1], addr).await?;
+    device.send_to(&framed(&[(0, b"\x06")]), addr).await?;
+    let mut data = vec![];
+    for _ in 0..2 {
+        let read = rx.recv().await.unwrap();
+        data.push((read.ch_name, read.data.to_vec()));
+    }
+    reader.abort();
+    assert_eq!(
+        data,
+        [
+            (UartTxChannel::Ctrl, b"\x0400110023\x05".to_vec()),
+            (UartTxChannel::Node, b"\x06".to_vec()),
+        ]
+    );
+    Ok(())
+}
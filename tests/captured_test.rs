@@ -0,0 +1,62 @@
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use serial_pcap::captured::CapturedStream;
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+#[tokio::test]
+async fn test_captured_stream() -> Result<()> {
+    let (app, mut bus) = tokio::io::duplex(64);
+    let mut pcap = vec![];
+    let mut port = CapturedStream::new(app, SerialPacketWriter::new(&mut pcap)?);
+
+    port.write_all(b"\x0422110023\x05").await?;
+    let mut cmd = [0u8; 10];
+    bus.read_exact(&mut cmd).await?;
+    assert_eq!(&cmd, b"\x0422110023\x05");
+
+    bus.write_all(b"\x0223+00033\x03\x02").await?;
+    let mut reply = [0u8; 11];
+    port.read_exact(&mut reply).await?;
+    assert_eq!(&reply, b"\x0223+00033\x03\x02");
+    port.flush().await?;
+    drop(port.into_inner());
+
+    let packets = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<Vec<_>>>()?;
+    let ctrl: Vec<u8> = packets
+        .iter()
+        .filter(|p| p.ch == UartTxChannel::Ctrl)
+        .flat_map(|p| p.data.iter().copied())
+        .collect();
+    let node: Vec<u8> = packets
+        .iter()
+        .filter(|p| p.ch == UartTxChannel::Node)
+        .flat_map(|p| p.data.iter().copied())
+        .collect();
+    assert_eq!(ctrl, b"\x0422110023\x05");
+    assert_eq!(node, b"\x0223+00033\x03\x02");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_captured_node_stream() -> Result<()> {
+    let (app, mut bus) = tokio::io::duplex(64);
+    let mut pcap = vec![];
+    let mut port = CapturedStream::new(app, SerialPacketWriter::new(&mut pcap)?)
+        .with_written_channel(UartTxChannel::Node);
+
+    bus.write_all(b"\x0422110023\x05").await?;
+    let mut cmd = [0u8; 10];
+    port.read_exact(&mut cmd).await?;
+    port.write_all(b"\x15").await?;
+    drop(port.into_inner());
+
+    let packets = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<Vec<_>>>()?;
+    let last = packets.last().unwrap();
+    assert_eq!(packets[0].ch, UartTxChannel::Ctrl);
+    assert_eq!(
+        (last.ch, &last.data[..]),
+        (UartTxChannel::Node, &b"\x15"[..])
+    );
+    Ok(())
+}
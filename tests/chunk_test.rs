@@ -0,0 +1,68 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn t0() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+/// The packet lengths and continuation flags of a capture
+fn packets(pcap: &[u8]) -> Result<Vec<(usize, bool)>> {
+    let mut reader = SerialPacketReader::new(pcap)?;
+    let mut packets = vec![];
+    while let Some(pkt) = reader.next_packet()? {
+        packets.push((pkt.data.len(), reader.continued()));
+    }
+    Ok(packets)
+}
+
+#[test]
+fn test_default_chunking() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(&[b'x'; 400], UartTxChannel::Ctrl, t0())?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, t0())?;
+    drop(writer);
+    assert_eq!(
+        packets(&pcap)?,
+        [(168, true), (168, true), (64, false), (1, false)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_chunk_size() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?.with_chunk_size(Some(10));
+    writer.write_packet_time(&[b'x'; 25], UartTxChannel::Ctrl, t0())?;
+    drop(writer);
+    assert_eq!(packets(&pcap)?, [(10, true), (10, true), (5, false)]);
+
+    // without chunking, the data must fit in the snaplen
+    let mut writer = SerialPacketWriter::new(vec![])?.with_chunk_size(None);
+    writer.write_packet_time(&[b'x'; 150], UartTxChannel::Ctrl, t0())?;
+    assert!(writer
+        .write_packet_time(&[b'x'; 400], UartTxChannel::Ctrl, t0())
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_large_snaplen() -> Result<()> {
+    assert!(SerialPacketWriter::new_with_snaplen(vec![], 100).is_err());
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new_with_snaplen(&mut pcap, 65535)?;
+    writer.write_packet_time(&[b'x'; 4000], UartTxChannel::Ctrl, t0())?;
+    drop(writer);
+    assert_eq!(packets(&pcap)?, [(4000, false)]);
+
+    // appending keeps the snaplen
+    let mut file = std::io::Cursor::new(pcap);
+    let mut writer = SerialPacketWriter::append(&mut file)?;
+    writer.write_packet_time(&[b'y'; 1000], UartTxChannel::Node, t0())?;
+    drop(writer);
+    assert_eq!(packets(file.get_ref())?, [(4000, false), (1000, false)]);
+    Ok(())
+}
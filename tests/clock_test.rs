@@ -0,0 +1,100 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::clock::{CaptureClock, ClockModel, DeviceClock};
+use serial_pcap::{CaptureRecord, MarkerKind, SerialPacketReader, SerialPacketWriter};
+
+#[test]
+fn test_capture_clock() -> Result<()> {
+    let clock = CaptureClock::new();
+    let t0 = clock.now();
+    std::thread::sleep(Duration::from_millis(10));
+    let elapsed = clock.now().duration_since(t0)?;
+    assert!(elapsed >= Duration::from_millis(10));
+    assert!(t0 >= clock.anchor());
+    assert!(clock.drift().abs() < 1.0);
+
+    // the anchor is stored in the capture
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        writer.write_marker(&clock.anchor_marker())?;
+    }
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let Some(CaptureRecord::Marker(marker)) = reader.next_record()? else {
+        panic!("Expected a marker.");
+    };
+    assert_eq!(marker.kind, MarkerKind::Clock);
+    // pcap timestamps have microsecond resolution
+    let anchor = clock.anchor_marker().time;
+    assert!((marker.time - anchor).abs() <= chrono::Duration::microseconds(1));
+    Ok(())
+}
+
+#[test]
+fn test_clock_model() {
+    let secs = |clock: &CaptureClock| {
+        let t = clock.now().duration_since(clock.anchor()).unwrap();
+        t.as_secs_f64()
+    };
+    let clock = CaptureClock::with_model(ClockModel {
+        offset: 2.5,
+        drift_ppm: 0.0,
+    });
+    assert!((secs(&clock) - 2.5).abs() < 0.1);
+
+    let clock = CaptureClock::with_model(ClockModel {
+        offset: -2.5,
+        drift_ppm: 0.0,
+    });
+    assert!(clock.now() < clock.anchor());
+    assert!((clock.drift() - 2.5).abs() < 0.1);
+
+    // a clock running 50 % fast only advances half as much after correction
+    let clock = CaptureClock::with_model(ClockModel {
+        offset: 0.0,
+        drift_ppm: 500_000.0,
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    assert!((secs(&clock) - 0.1).abs() < 0.05);
+}
+
+#[test]
+fn test_pps_correction() {
+    let clock = CaptureClock::new();
+    let now = clock.now();
+    let whole_sec = SystemTime::UNIX_EPOCH
+        + Duration::from_secs(
+            now.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+
+    // a pulse 0.2 s after the whole second means the clock is 0.2 s ahead
+    let correction = clock.apply_pps(whole_sec + Duration::from_millis(200));
+    assert!((correction + 0.2).abs() < 0.01, "{correction}");
+    let after = clock.now();
+    assert!(after < now);
+
+    let correction = clock.apply_pps(whole_sec + Duration::from_millis(900));
+    assert!((correction - 0.1).abs() < 0.01, "{correction}");
+}
+
+#[test]
+fn test_device_clock() {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut clock = DeviceClock::new();
+    // received with 3 ms of USB latency
+    let t = clock.capture_time(1_000_000, start + Duration::from_millis(3));
+    assert_eq!(t, start + Duration::from_millis(3));
+    // less latency improves the estimate, for the following chunks
+    clock.capture_time(2_000_000, start + Duration::from_millis(1001));
+    let t = clock.capture_time(3_000_000, start + Duration::from_millis(2010));
+    assert_eq!(t, start + Duration::from_millis(2001));
+
+    // the device time wraps around after 2^28 µs
+    let wrap = Duration::from_micros(1 << 28);
+    let t = clock.capture_time(500_000, start + wrap - Duration::from_millis(497));
+    assert_eq!(t, start + wrap - Duration::from_millis(499));
+}
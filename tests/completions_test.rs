@@ -0,0 +1,122 @@
+use std::process::Command;
+
+use anyhow::Result;
+use clap::{Arg, ArgAction};
+
+use serial_pcap::completions::{generate, man_page, Shell};
+
+fn test_cmd() -> clap::Command {
+    clap::Command::new("tool")
+        .about("Does things with .pcap files")
+        .arg(Arg::new("input").help("The input file").required(true))
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .short('f')
+                .help("The output format")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .help("Log more")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(
+            clap::Command::new("show")
+                .about("Show one record")
+                .arg(Arg::new("all").long("all").action(ArgAction::SetTrue)),
+        )
+}
+
+fn run(bin: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(bin).args(args).output()?;
+    assert!(output.status.success(), "{output:?}");
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[test]
+fn test_bash() {
+    let script = generate(Shell::Bash, test_cmd());
+    assert!(
+        script.contains("\"tool show\") cmd=\"tool show\" ;;"),
+        "{script}"
+    );
+    assert!(script.contains("-f|--format)"), "{script}");
+    assert!(script.contains("compgen -W \"text json\""), "{script}");
+    assert!(
+        script.contains("opts=\"-f --format --verbose -h --help show\""),
+        "{script}"
+    );
+    assert!(script.ends_with("complete -o default -F _tool tool\n"));
+
+    let zsh = generate(Shell::Zsh, test_cmd());
+    assert!(zsh.starts_with("#compdef tool\n"));
+    assert!(zsh.ends_with(&script));
+}
+
+#[test]
+fn test_fish() {
+    let script = generate(Shell::Fish, test_cmd());
+    let lines: Vec<_> = script.lines().collect();
+    assert!(lines.contains(
+        &"complete -c tool -n \"__fish_use_subcommand\" -f -a show -d 'Show one record'"
+    ));
+    assert!(lines.contains(
+        &"complete -c tool -n \"__fish_use_subcommand\" -s f -l format -x -a 'text json' -d 'The output format'"
+    ));
+    assert!(lines.contains(&"complete -c tool -n \"__fish_seen_subcommand_from show\" -l all"));
+}
+
+#[test]
+fn test_man_page() {
+    let page = man_page(test_cmd());
+    let expected = [
+        ".TH TOOL 1 \"\" \"tool\"",
+        ".SH NAME",
+        "tool \\- Does things with .pcap files",
+        ".SH SYNOPSIS",
+        "tool [OPTIONS] <input> [COMMAND]",
+        ".SH OPTIONS",
+        ".TP",
+        "\\fI<INPUT>\\fR",
+        "The input file",
+        ".TP",
+        "\\fB\\-f\\fR, \\fB\\-\\-format\\fR \\fIFORMAT\\fR",
+        "The output format [possible values: text, json] [default: text]",
+        ".TP",
+        "\\fB\\-\\-verbose\\fR",
+        "Log more",
+    ];
+    let lines: Vec<_> = page.lines().collect();
+    assert_eq!(lines[..expected.len()], expected, "{page}");
+    let show = ".SS tool show\nShow one record\n.PP\ntool <input> show [OPTIONS]\n.TP\n\\fB\\-\\-all\\fR\n.TP\n";
+    assert!(page.contains(show), "{page}");
+}
+
+#[test]
+fn test_binaries() -> Result<()> {
+    for bin in [
+        env!("CARGO_BIN_EXE_serial-pcap"),
+        env!("CARGO_BIN_EXE_replay_x328"),
+        env!("CARGO_BIN_EXE_simulate"),
+    ] {
+        let name = std::path::Path::new(bin)
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let bash = run(bin, &["completions", "bash"])?;
+        assert!(bash.ends_with(&format!(" {name}\n")), "{bash}");
+        let man = run(bin, &["man"])?;
+        assert!(man.starts_with(&format!(
+            ".TH {} 1",
+            name.to_uppercase().replace('-', "\\-")
+        )));
+    }
+    let bash = run(env!("CARGO_BIN_EXE_serial-pcap"), &["completions", "bash"])?;
+    assert!(bash.contains("\"serial-pcap device dfu\")"));
+    assert!(bash.contains("compgen -W \"block drop-oldest abort\""));
+    Ok(())
+}
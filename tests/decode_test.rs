@@ -0,0 +1,311 @@
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::master::Error as X328Error;
+use x328_proto::{addr, param};
+
+use serial_pcap::decode::{BusCommand, BusEvent, Collision, X328Decoder};
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, SerialPacket, SerialPacketReader, SerialPacketWriter,
+    Trigger, UartTxChannel,
+};
+
+/// Read parameter 23 from node 21
+const READ_CMD: &[u8] = b"\x0422110023\x05";
+/// Node response to a read of an invalid parameter
+const INVALID_PARAM: &[u8] = b"\x04";
+
+fn decode(packets: &[(UartTxChannel, &[u8], i64)]) -> Vec<BusEvent> {
+    decode_with(X328Decoder::new(), packets)
+}
+
+fn decode_with(mut decoder: X328Decoder, packets: &[(UartTxChannel, &[u8], i64)]) -> Vec<BusEvent> {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut events = vec![];
+    for &(ch, data, ms) in packets {
+        let pkt = SerialPacket {
+            bus: 0,
+            ch,
+            data: data.into(),
+            time: start + Duration::milliseconds(ms),
+        };
+        decoder.feed(&pkt, |e| events.push(e));
+    }
+    events
+}
+
+fn is_invalid_param_read(e: &BusEvent) -> bool {
+    matches!(e, BusEvent::Transaction(t)
+        if matches!(t.cmd, BusCommand::Read{ addr: a, param: p } if a == addr(21) && p == param(23))
+        && matches!(t.result, Err(X328Error::InvalidParameter)))
+}
+
+fn is_overlap(e: &BusEvent) -> bool {
+    matches!(
+        e,
+        BusEvent::Collision {
+            kind: Collision::Overlap { .. },
+            ..
+        }
+    )
+}
+
+#[test]
+fn test_no_collision() {
+    use UartTxChannel::*;
+    let events = decode(&[(Ctrl, READ_CMD, 0), (Node, INVALID_PARAM, 15)]);
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert!(is_invalid_param_read(&events[0]));
+}
+
+#[test]
+fn test_echo() {
+    use UartTxChannel::*;
+    // the command is echoed on the node channel, split over interleaved packets
+    let events = decode(&[
+        (Ctrl, &READ_CMD[..4], 0),
+        (Node, &READ_CMD[..3], 1),
+        (Ctrl, &READ_CMD[4..], 4),
+        (Node, &READ_CMD[3..], 5),
+        (Node, INVALID_PARAM, 20),
+    ]);
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(matches!(
+        &events[0],
+        BusEvent::Collision {
+            kind: Collision::Echo { ch: Node },
+            ..
+        }
+    ));
+    assert!(is_invalid_param_read(&events[1]));
+
+    // the node response echoed on the ctrl channel
+    let events = decode(&[
+        (Ctrl, READ_CMD, 0),
+        (Node, INVALID_PARAM, 15),
+        (Ctrl, INVALID_PARAM, 16),
+    ]);
+    assert!(is_invalid_param_read(&events[0]));
+    assert!(matches!(
+        &events[1..],
+        [BusEvent::Collision {
+            kind: Collision::Echo { ch: Ctrl },
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn test_second_master() {
+    use UartTxChannel::*;
+    let events = decode(&[(Ctrl, READ_CMD, 0), (Ctrl, READ_CMD, 5)]);
+    assert!(
+        matches!(
+            &events[..],
+            [BusEvent::Collision {
+                kind: Collision::SecondMaster {
+                    cmd: Some(BusCommand::Read { .. })
+                },
+                ..
+            }]
+        ),
+        "{events:?}"
+    );
+
+    // a new command after a plausible timeout is just a timeout
+    let events = decode(&[(Ctrl, READ_CMD, 0), (Ctrl, READ_CMD, 100)]);
+    assert!(matches!(
+        &events[..],
+        [BusEvent::Timeout { cmd: Some(_), .. }]
+    ));
+}
+
+#[test]
+fn test_overlap() {
+    use UartTxChannel::*;
+    // the node starts responding 5 ms into the 11 ms command
+    let overlap = &[(Ctrl, READ_CMD, 0), (Node, INVALID_PARAM, 5)];
+    let events = decode_with(X328Decoder::new().with_baud(9600), overlap);
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    assert!(
+        matches!(
+            &events[..],
+            [BusEvent::Collision {
+                kind: Collision::Overlap { ch: Node },
+                time,
+            }, ..] if *time == start + Duration::milliseconds(5)
+        ),
+        "{events:?}"
+    );
+
+    // reported once for a transmission split over several packets
+    let events = decode_with(
+        X328Decoder::new().with_baud(9600),
+        &[
+            (Ctrl, READ_CMD, 0),
+            (Node, b"12", 2),
+            (Node, b"34", 5),
+            (Node, INVALID_PARAM, 30),
+        ],
+    );
+    let overlaps = events.iter().filter(|e| is_overlap(e)).count();
+    assert_eq!(overlaps, 1, "{events:?}");
+
+    // a response within a byte time of the end of the command is timestamp jitter
+    let events = decode_with(
+        X328Decoder::new().with_baud(9600),
+        &[(Ctrl, READ_CMD, 0), (Node, INVALID_PARAM, 11)],
+    );
+    assert!(is_invalid_param_read(&events[0]), "{events:?}");
+    assert_eq!(events.len(), 1, "{events:?}");
+
+    // at 19200 baud the command is sent in under 6 ms
+    let events = decode_with(
+        X328Decoder::new().with_baud(19200),
+        &[(Ctrl, READ_CMD, 0), (Node, INVALID_PARAM, 7)],
+    );
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert!(is_invalid_param_read(&events[0]));
+
+    // not detected without the baud rate
+    let events = decode(overlap);
+    assert!(!events.iter().any(is_overlap), "{events:?}");
+}
+
+#[test]
+fn test_split_frames() {
+    use UartTxChannel::*;
+    let mut packets: Vec<(UartTxChannel, &[u8], i64)> = READ_CMD
+        .chunks(1)
+        .enumerate()
+        .map(|(n, b)| (Ctrl, b, n as i64))
+        .collect();
+    packets.push((Node, INVALID_PARAM, 30));
+    let events = decode(&packets);
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert!(is_invalid_param_read(&events[0]));
+}
+
+/// A packet of `data`, or a trigger if it's None, at `ms` after the start
+fn record(ch: UartTxChannel, data: Option<&[u8]>, ms: i64) -> CaptureRecord {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let time = start + Duration::milliseconds(ms);
+    match data {
+        Some(data) => CaptureRecord::Packet(SerialPacket {
+            bus: 0,
+            ch,
+            data: data.into(),
+            time,
+        }),
+        None => CaptureRecord::Trigger(Trigger::new(0, Trigger::DEVICE, time)),
+    }
+}
+
+fn decode_records(records: &[CaptureRecord], reset_on_trigger: bool) -> Vec<BusEvent> {
+    let mut decoder = X328Decoder::new().with_reset_on_trigger(reset_on_trigger);
+    let mut events = vec![];
+    for record in records {
+        decoder.feed_record(record, |e| events.push(e));
+    }
+    events
+}
+
+#[test]
+fn test_trigger_order() {
+    use UartTxChannel::*;
+    let records = [
+        record(Ctrl, Some(READ_CMD), 0),
+        record(Node, Some(INVALID_PARAM), 15),
+        record(Node, None, 15),
+    ];
+    let events = decode_records(&records, false);
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(is_invalid_param_read(&events[0]));
+    assert!(matches!(events[1], BusEvent::Trigger { .. }));
+}
+
+#[test]
+fn test_reset_on_trigger() {
+    use UartTxChannel::*;
+    // the command before the trigger is forgotten, so the response after it is unexpected
+    let records = [
+        record(Ctrl, Some(READ_CMD), 0),
+        record(Ctrl, None, 0),
+        record(Node, Some(INVALID_PARAM), 15),
+    ];
+
+    let events = decode_records(&records, true);
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(matches!(events[0], BusEvent::Trigger { .. }));
+    assert!(matches!(events[1], BusEvent::UnexpectedTransmission { .. }));
+
+    let events = decode_records(&records, false);
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(is_invalid_param_read(&events[1]));
+}
+
+#[test]
+fn test_trigger_packet() -> anyhow::Result<()> {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(READ_CMD, UartTxChannel::Ctrl, start.into())?;
+    let trigger_time = start + Duration::microseconds(5_250);
+    writer.write_trigger(&Trigger::new(0, "button A", trigger_time))?;
+    let node_time = start + Duration::milliseconds(15);
+    writer.write_packet_time(INVALID_PARAM, UartTxChannel::Node, node_time.into())?;
+    drop(writer);
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut decoder = X328Decoder::new().with_reset_on_trigger(true);
+    let mut events = vec![];
+    while let Some(record) = reader.next_record()? {
+        if let CaptureRecord::Trigger(t) = &record {
+            assert_eq!(
+                (t.bus, t.source.as_str(), t.label.as_str()),
+                (0, "button A", "measurement trigger")
+            );
+        }
+        decoder.feed_record(&record, |e| events.push(e));
+    }
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(matches!(events[0], BusEvent::Trigger { time } if time == trigger_time));
+    assert!(matches!(events[1], BusEvent::UnexpectedTransmission { .. }));
+    Ok(())
+}
+
+#[test]
+fn test_break_marker() -> anyhow::Result<()> {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    // a break in the middle of the command, the rest would complete it without the break
+    writer.write_packet_time(&READ_CMD[..5], UartTxChannel::Ctrl, start.into())?;
+    let break_time = start + Duration::milliseconds(2);
+    writer.write_marker(&Marker::line_break(0, UartTxChannel::Ctrl, break_time))?;
+    let rest_time = start + Duration::milliseconds(3);
+    writer.write_packet_time(&READ_CMD[5..], UartTxChannel::Ctrl, rest_time.into())?;
+    let node_time = start + Duration::milliseconds(15);
+    writer.write_packet_time(INVALID_PARAM, UartTxChannel::Node, node_time.into())?;
+    drop(writer);
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut decoder = X328Decoder::new();
+    let mut events = vec![];
+    while let Some(record) = reader.next_record()? {
+        if let CaptureRecord::Marker(m) = &record {
+            assert_eq!(
+                (m.kind, m.ch, m.label.as_str()),
+                (MarkerKind::Break, Some(UartTxChannel::Ctrl), "line break")
+            );
+        }
+        decoder.feed_record(&record, |e| events.push(e));
+    }
+    assert!(
+        matches!(events[0], BusEvent::Break { ch: UartTxChannel::Ctrl, time } if time == break_time),
+        "{events:?}"
+    );
+    assert!(
+        !events.iter().any(|e| matches!(e, BusEvent::Transaction(_))),
+        "{events:?}"
+    );
+    Ok(())
+}
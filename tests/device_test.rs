@@ -0,0 +1,49 @@
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+
+use serial_pcap::device::{DevicePorts, EventLineSplitter};
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn test_device_ports() -> Result<()> {
+    let ports = DevicePorts::from_names(names(&["/dev/ttyACM10", "/dev/ttyACM9"]))?;
+    assert_eq!(ports.stream, "/dev/ttyACM9");
+    assert_eq!(ports.events, "/dev/ttyACM10");
+    assert_eq!(ports.bus2, None);
+
+    let ports = DevicePorts::from_names(names(&["COM5", "COM3", "COM4"]))?;
+    assert_eq!(ports.stream, "COM3");
+    assert_eq!(ports.events, "COM4");
+    assert_eq!(ports.bus2.as_deref(), Some("COM5"));
+
+    assert!(DevicePorts::from_names(names(&[])).is_err());
+    assert!(DevicePorts::from_names(names(&["COM3"])).is_err());
+    // two devices, the ports can't be told apart
+    assert!(DevicePorts::from_names(names(&["a0", "a1", "a2", "a3", "a4", "a5"])).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_event_lines() {
+    let t0 = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    let t1 = Utc.timestamp_opt(1_700_000_001, 250_000_000).unwrap();
+    let mut splitter = EventLineSplitter::new();
+    assert!(splitter.feed(b"Node 31 read ok", t0).is_empty());
+    let lines = splitter.feed(b" 401 == 120\r\n\r\nTrigger", t1);
+    assert_eq!(lines.len(), 1);
+    // the time of the first byte of the line
+    assert_eq!(lines[0].time, t0);
+    assert_eq!(lines[0].text, "Node 31 read ok 401 == 120");
+    assert_eq!(
+        lines[0].to_string(),
+        "2023-11-14 22:13:20.000000 Node 31 read ok 401 == 120"
+    );
+
+    let lines = splitter.feed(b" event\n", t0);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].time, t1);
+    assert_eq!(lines[0].text, "Trigger event");
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, value};
+
+use serial_pcap::decode::BusCommand;
+use serial_pcap::diff::{diff, exchanges, DiffItem, Exchange, Outcome};
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+fn read(a: u8, p: i16, outcome: Outcome) -> Exchange {
+    Exchange {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(p),
+        },
+        outcome,
+        time: DateTime::default(),
+    }
+}
+
+fn ok(a: u8, p: i16, v: i32) -> Exchange {
+    read(a, p, Outcome::Ok(v))
+}
+
+#[test]
+fn test_diff() -> Result<()> {
+    use DiffItem::*;
+    let before = [ok(1, 1, 10), ok(1, 2, 20), ok(2, 1, 30), ok(3, 1, 40)];
+    let after = [
+        ok(1, 1, 10),
+        ok(2, 1, 31),
+        read(2, 2, Outcome::Timeout),
+        ok(3, 1, 40),
+    ];
+    assert_eq!(
+        diff(&before, &after)?,
+        [Same(0, 0), Removed(1), Changed(2, 1), Added(2), Same(3, 3)]
+    );
+    assert_eq!(diff(&before, &[])?, [0, 1, 2, 3].map(Removed));
+    assert_eq!(diff(&[], &after)?, [0, 1, 2, 3].map(Added));
+    assert_eq!(diff(&[], &[])?, []);
+
+    // a write of another value is the same command
+    let write = |v| Exchange {
+        cmd: BusCommand::Write {
+            addr: addr(1),
+            param: param(1),
+            value: value(v),
+        },
+        outcome: Outcome::Ok(v),
+        time: DateTime::default(),
+    };
+    assert_eq!(diff(&[write(1)], &[write(2)])?, [Changed(0, 0)]);
+    assert_eq!(diff(&[write(1)], &[ok(1, 1, 1)])?, [Removed(0), Added(0)]);
+    Ok(())
+}
+
+/// Length of the longest common subsequence of commands
+fn lcs(a: &[Exchange], b: &[Exchange]) -> usize {
+    let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..a.len() {
+        for j in 0..b.len() {
+            table[i + 1][j + 1] = match a[i].cmd == b[j].cmd {
+                true => table[i][j] + 1,
+                false => table[i][j + 1].max(table[i + 1][j]),
+            };
+        }
+    }
+    table[a.len()][b.len()]
+}
+
+#[test]
+fn test_diff_is_minimal() -> Result<()> {
+    // xorshift, for repeatable pseudo random sequences
+    let mut state = 0x2545_f491_u32;
+    let mut rand = move |n: u32| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state % n
+    };
+    for _ in 0..200 {
+        let (len_a, len_b) = (rand(12), rand(12));
+        let a: Vec<_> = (0..len_a).map(|_| ok(1, rand(4) as i16, 0)).collect();
+        let b: Vec<_> = (0..len_b).map(|_| ok(1, rand(4) as i16, 0)).collect();
+        let items = diff(&a, &b)?;
+        let matched: Vec<_> = items
+            .iter()
+            .filter_map(|item| match *item {
+                DiffItem::Same(i, j) => Some((i, j)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(matched.len(), lcs(&a, &b), "{a:?} {b:?} {items:?}");
+        assert!(matched.iter().all(|&(i, j)| a[i].cmd == b[j].cmd));
+        let count = |f: fn(&DiffItem) -> bool| items.iter().filter(|i| f(i)).count();
+        assert_eq!(count(|i| !matches!(i, DiffItem::Added(_))), a.len());
+        assert_eq!(count(|i| !matches!(i, DiffItem::Removed(_))), b.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_exchanges() -> Result<()> {
+    use UartTxChannel::*;
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let packets = [
+        (Ctrl, &b"\x0422110023\x05"[..], 0),
+        (Node, b"\x04", 15),
+        (Ctrl, b"\x0422110023\x05", 100),
+        (Ctrl, b"\x0422110023\x05", 300),
+    ]
+    .map(|(ch, data, ms)| {
+        Ok(SerialPacket {
+            bus: 0,
+            ch,
+            data: data.into(),
+            time: start + Duration::milliseconds(ms),
+        })
+    });
+    let found = exchanges(packets.into_iter())?;
+    let outcomes: Vec<_> = found.iter().map(|x| x.outcome).collect();
+    assert_eq!(outcomes, [Outcome::InvalidParameter, Outcome::Timeout]);
+    assert_eq!(found[1].time, start + Duration::milliseconds(300));
+    Ok(())
+}
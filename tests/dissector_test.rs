@@ -0,0 +1,48 @@
+use anyhow::Result;
+
+use serial_pcap::dissector::{generate, lua_string};
+use serial_pcap::names::NameMap;
+
+#[test]
+fn test_lua_string() {
+    assert_eq!(lua_string("stow east"), r#""stow east""#);
+    assert_eq!(lua_string(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+    assert_eq!(lua_string("a\nb\t1"), r#""a\nb\0091""#);
+    assert_eq!(lua_string("°C"), "\"°C\"");
+}
+
+#[test]
+fn test_generate() -> Result<()> {
+    let names = NameMap::from_toml(
+        r#"
+        [[node]]
+        address = 31
+        name = "stow east"
+
+        [[param]]
+        address = 31
+        param = 401
+        name = "stow pressure east"
+        unit = "bar"
+
+        [[param]]
+        address = 31
+        param = 12
+        name = "mode"
+        "#,
+    )?;
+    let lua = generate(&names);
+    assert!(!lua.contains("--@NAMES@"));
+    assert!(lua.contains("    [31] = \"stow east\",\n"));
+    // sorted on the parameter number
+    let mode = lua.find(r#"[12] = { name = "mode" },"#).unwrap();
+    let pressure = lua
+        .find(r#"[401] = { name = "stow pressure east", unit = "bar" },"#)
+        .unwrap();
+    assert!(mode < pressure);
+    assert!(lua.contains(r#"DissectorTable.get("udp.port"):add(422, x328)"#));
+
+    let empty = generate(&NameMap::default());
+    assert!(empty.contains("local nodes = {\n}\n\nlocal params = {\n}"));
+    Ok(())
+}
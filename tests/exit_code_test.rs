@@ -0,0 +1,122 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use anyhow::Result;
+
+use serial_pcap::generate::{generate, GenerateOptions};
+use serial_pcap::SerialPacketWriter;
+
+fn replay(data: &[u8]) -> Result<Output> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_replay_x328"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(data)?;
+    Ok(child.wait_with_output()?)
+}
+
+fn generated(opts: GenerateOptions) -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    generate(&opts, &mut writer)?;
+    drop(writer);
+    Ok(pcap)
+}
+
+/// The last line of the output
+fn summary(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().last().unwrap_or_default().to_string()
+}
+
+#[test]
+fn test_exit_ok() -> Result<()> {
+    let output = replay(&generated(GenerateOptions {
+        transactions: 10,
+        ..Default::default()
+    })?)?;
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(
+        summary(&output),
+        "summary transactions=10 failed=0 protocol_errors=0 timeouts=0 violations=0 \
+         decode_errors=0 exit_code=0"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_exit_protocol_error() -> Result<()> {
+    let output = replay(&generated(GenerateOptions {
+        transactions: 12,
+        corrupt_every: 4,
+        ..Default::default()
+    })?)?;
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(
+        summary(&output).contains(" protocol_errors=3 "),
+        "{output:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_exit_violation() -> Result<()> {
+    let output = replay(&generated(GenerateOptions {
+        transactions: 12,
+        no_reply_every: 5,
+        ..Default::default()
+    })?)?;
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+    let summary = summary(&output);
+    assert!(summary.contains(" timeouts=2 violations=2 "), "{summary}");
+    Ok(())
+}
+
+#[test]
+fn test_exit_decode() -> Result<()> {
+    let mut data = generated(GenerateOptions {
+        transactions: 12,
+        corrupt_every: 4,
+        ..Default::default()
+    })?;
+    // cut the last record short
+    data.truncate(data.len() - 3);
+    let output = replay(&data)?;
+    // the decode errors take precedence over the protocol errors
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    assert!(summary(&output).contains(" decode_errors=1 "), "{output:?}");
+    Ok(())
+}
+
+#[test]
+fn test_log_filter() -> Result<()> {
+    let data = generated(GenerateOptions {
+        transactions: 12,
+        corrupt_every: 4,
+        no_reply_every: 5,
+        ..Default::default()
+    })?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_replay_x328"))
+        .arg("-")
+        .env("RUST_LOG", "error")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(&data)?;
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout.clone())?;
+    let lines: Vec<_> = stdout.lines().collect();
+    // only the bad checksums, in the span of their transaction, and the summary
+    assert_eq!(lines.len(), 4, "{stdout}");
+    for line in &lines[..3] {
+        assert!(
+            line.starts_with("ERROR transaction{cmd=write of "),
+            "{line}"
+        );
+        assert!(line.contains("Failed => ProtocolError"), "{line}");
+    }
+    assert_eq!(summary(&output).split(' ').next(), Some("summary"));
+    Ok(())
+}
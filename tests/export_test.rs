@@ -0,0 +1,52 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::export::{export, AnalyzerFormat, ExportTiming};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+/// "AB" from the controller, and an ACK from the node 5 ms later
+fn capture() -> Result<Vec<u8>> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(b"AB", UartTxChannel::Ctrl, start)?;
+    writer.write_packet_time(
+        b"\x06",
+        UartTxChannel::Node,
+        start + Duration::from_millis(5),
+    )?;
+    drop(writer);
+    Ok(pcap)
+}
+
+fn exported(format: AnalyzerFormat) -> Result<String> {
+    let pcap = capture()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut out = vec![];
+    export(&mut reader, &mut out, format, ExportTiming::default())?;
+    Ok(String::from_utf8(out)?)
+}
+
+#[test]
+fn test_sigrok_export() -> Result<()> {
+    assert_eq!(
+        exported(AnalyzerFormat::Sigrok)?,
+        "0-1042 uart-ctrl: 41\n\
+         1042-2083 uart-ctrl: 42\n\
+         5000-6042 uart-node: 06\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_saleae_export() -> Result<()> {
+    assert_eq!(
+        exported(AnalyzerFormat::Saleae)?,
+        "name,type,start_time,duration,data\n\
+         \"ctrl\",\"data\",0.000000000,0.001041667,0x41\n\
+         \"ctrl\",\"data\",0.001041667,0.001041667,0x42\n\
+         \"node\",\"data\",0.005000000,0.001041667,0x06\n"
+    );
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::extract::{extract, ExtractStats};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn write_two_buses() -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_644_900);
+    let ms = |n| t0 + Duration::from_millis(n);
+    writer.write_packet_time(b"\x0422110023\x05", UartTxChannel::Ctrl, ms(0))?;
+    writer.write_bus_packet_time(1, b"\x0499880011\x05", UartTxChannel::Ctrl, ms(1))?;
+    writer.write_packet_time(b"\x0223+00033\x03", UartTxChannel::Node, ms(12))?;
+    writer.write_packet_time(b"\x02", UartTxChannel::Node, ms(13))?;
+    drop(writer);
+    Ok(pcap)
+}
+
+#[test]
+fn test_extract_with_index() -> Result<()> {
+    let pcap = write_two_buses()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let (mut data, mut index) = (vec![], vec![]);
+    let stats = extract(
+        &mut reader,
+        0,
+        UartTxChannel::Node,
+        &mut data,
+        Some(&mut index),
+    )?;
+    assert_eq!(
+        stats,
+        ExtractStats {
+            packets: 2,
+            bytes: 11
+        }
+    );
+    assert_eq!(data, b"\x0223+00033\x03\x02");
+    assert_eq!(
+        String::from_utf8(index)?,
+        "offset,length,time\n\
+         0,10,2024-05-02T10:15:00.012000Z\n\
+         10,1,2024-05-02T10:15:00.013000Z\n"
+    );
+
+    // only the selected bus
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut data = vec![];
+    extract(&mut reader, 1, UartTxChannel::Ctrl, &mut data, None)?;
+    assert_eq!(data, b"\x0499880011\x05");
+    Ok(())
+}
+
+#[test]
+fn test_extract_command() -> Result<()> {
+    let dir = std::env::temp_dir();
+    let pcap = dir.join(format!("serial_pcap_extract_{}.pcap", std::process::id()));
+    let index = pcap.with_extension("csv");
+    std::fs::write(&pcap, write_two_buses()?)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .arg("extract")
+        .arg(&pcap)
+        .args(["--channel", "ctrl", "--index"])
+        .arg(&index)
+        .output()?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"\x0422110023\x05");
+    let index_lines = std::fs::read_to_string(&index)?.lines().count();
+    assert_eq!(index_lines, 2);
+    std::fs::remove_file(pcap)?;
+    std::fs::remove_file(index)?;
+    Ok(())
+}
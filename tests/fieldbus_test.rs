@@ -0,0 +1,67 @@
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::master::Error as X328Error;
+use x328_proto::{addr, param, Value};
+
+use serial_pcap::decode::{BusCommand, BusEvent, Transaction};
+use serial_pcap::fieldbus::FieldBusMirror;
+use serial_pcap_core::nodes::NodeAddresses;
+use serial_pcap_core::x328_bus::UpdateEvent;
+
+fn read(a: u8, p: i16, result: Result<i32, X328Error>, s: i64) -> BusEvent {
+    let time = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap() + Duration::seconds(s);
+    BusEvent::Transaction(Transaction {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(p),
+        },
+        cmd_time: time,
+        resp_time: time,
+        result: result.map(|v| Value::new(v).unwrap()),
+    })
+}
+
+#[test]
+fn test_changes() {
+    let mut mirror = FieldBusMirror::default();
+    let all = mirror.subscribe();
+    let iobox = mirror.subscribe_node(addr(31));
+
+    mirror.handle_event(&read(31, 401, Ok(120), 0));
+    // the same value again isn't a change
+    mirror.handle_event(&read(31, 401, Ok(120), 1));
+    mirror.handle_event(&read(5, 1, Ok(7), 2));
+    mirror.handle_event(&read(5, 1, Err(X328Error::CommandFailed), 3));
+    mirror.handle_event(&read(5, 1, Ok(-8), 4));
+
+    let changes: Vec<_> = all.try_iter().collect();
+    assert_eq!(changes.len(), 3, "{changes:?}");
+    assert_eq!(changes[0].update, Some(UpdateEvent::StowPress(120, 0)));
+    assert!(changes[0].prev.is_none());
+    assert_eq!(changes[1].addr, addr(5));
+    assert_eq!(changes[1].update, None);
+    assert_eq!(changes[2].prev.map(|v| *v), Some(7));
+    assert_eq!(*changes[2].value, -8);
+
+    let changes: Vec<_> = iobox.try_iter().collect();
+    assert_eq!(changes.len(), 1, "{changes:?}");
+
+    // the time is updated by the repeated value
+    let value = mirror.value(addr(31), param(401)).unwrap();
+    assert_eq!(value.time.timestamp(), 1_700_000_001);
+    assert_eq!(mirror.nodes().iobox.stow_press_east, 120);
+    let params: Vec<_> = mirror.node_values(addr(5)).map(|(p, _)| *p).collect();
+    assert_eq!(params, [1]);
+    assert_eq!(mirror.values().count(), 2);
+}
+
+#[test]
+fn test_addresses_and_dropped_subscriber() {
+    let addrs: NodeAddresses = "iobox=30".parse().unwrap();
+    let mut mirror = FieldBusMirror::new(&addrs);
+    drop(mirror.subscribe());
+    let rx = mirror.subscribe();
+    mirror.handle_event(&read(31, 401, Ok(1), 0));
+    mirror.handle_event(&read(30, 402, Ok(2), 0));
+    let updates: Vec<_> = rx.try_iter().map(|c| c.update).collect();
+    assert_eq!(updates, [None, Some(UpdateEvent::StowPress(0, 2))]);
+}
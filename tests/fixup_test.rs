@@ -0,0 +1,95 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use etherparse::PacketBuilder;
+use rpcap::write::{PcapWriter, WriteOptions};
+use rpcap::CapturedPacket;
+
+use serial_pcap::fixup::fixup;
+use serial_pcap::validate::validate;
+use serial_pcap::{CaptureRecord, SerialPacketReader, SerialPacketWriter, TRIG_BYTE};
+
+fn udp(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+    PacketBuilder::ipv4(src, dst, 254)
+        .udp(ports.0, ports.1)
+        .write(&mut buf, payload)
+        .unwrap();
+    buf
+}
+
+/// A capture like the ones from older versions, with a large snaplen
+fn legacy_capture(packets: &[(SystemTime, Vec<u8>)]) -> Vec<u8> {
+    let mut pcap = vec![];
+    let options = WriteOptions {
+        snaplen: 65535,
+        linktype: 228,
+        high_res_timestamps: false,
+        non_native_byte_order: false,
+    };
+    let mut writer = PcapWriter::new(&mut pcap, options).unwrap();
+    for (time, data) in packets {
+        writer
+            .write(&CapturedPacket {
+                time: *time,
+                data,
+                orig_len: data.len(),
+            })
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    pcap
+}
+
+#[test]
+fn test_fixup_legacy_capture() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let (ctrl, node) = ([127, 0, 0, 1], [127, 0, 0, 2]);
+    let mut cmd = b"\x0400110023\x05".to_vec();
+    cmd.push(TRIG_BYTE);
+    let long = vec![b'0'; 300];
+    let legacy = legacy_capture(&[
+        (t, udp(ctrl, node, (422, 1422), &cmd)),
+        (t, udp(node, ctrl, (1442, 422), b"\x06")),
+        (t, udp(ctrl, node, (422, 1422), &long)),
+    ]);
+    // the old port is a problem for the structural checks
+    assert!(!validate(legacy.as_slice())?.is_ok());
+
+    let mut fixed = vec![];
+    let mut writer = SerialPacketWriter::new(&mut fixed)?;
+    let report = fixup(legacy.as_slice(), &mut writer)?;
+    drop(writer);
+    assert_eq!(
+        (
+            report.legacy_ports,
+            report.triggers,
+            report.split,
+            report.snaplen
+        ),
+        (1, 1, 1, 65535)
+    );
+    let validation = validate(fixed.as_slice())?;
+    assert!(validation.is_ok(), "{validation}");
+
+    // the data is the same, with the trigger moved out of it
+    let mut data = vec![];
+    let mut triggers = vec![];
+    let mut reader = SerialPacketReader::new(fixed.as_slice())?;
+    while let Some(record) = reader.next_record()? {
+        match record {
+            CaptureRecord::Packet(pkt) => {
+                assert_eq!(SystemTime::from(pkt.time), t);
+                data.extend_from_slice(&pkt.data);
+            }
+            CaptureRecord::Trigger(trigger) => triggers.push((trigger.bus, trigger.source)),
+            CaptureRecord::Marker(m) => panic!("Unexpected marker {m}"),
+        }
+    }
+    let mut expected = cmd[..cmd.len() - 1].to_vec();
+    expected.push(b'\x06');
+    expected.extend_from_slice(&long);
+    assert_eq!(data, expected);
+    assert_eq!(triggers, [(0, "device".into())]);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use bytes::BytesMut;
+
+use serial_pcap::framed::{
+    parse_packet, FramedRecord, FLAG_BUS2, FLAG_CHUNK, FLAG_CTRL, FLAG_DROP,
+};
+use serial_pcap::UartTxChannel;
+
+fn record(flags: u8, time: u32, data: &[u8]) -> FramedRecord {
+    FramedRecord {
+        flags,
+        time,
+        data: BytesMut::from(data),
+    }
+}
+
+#[test]
+fn test_parse_packet() -> Result<()> {
+    let records = [
+        record(FLAG_CTRL | FLAG_CHUNK, 0x12345678, b"\x0412340011\x05"),
+        record(FLAG_BUS2 | FLAG_DROP, 7, b""),
+        record(0, u32::MAX, b"\x06"),
+    ];
+    let mut packet = vec![];
+    records.iter().for_each(|r| r.encode(&mut packet));
+    let parsed = parse_packet(&packet)?;
+    assert_eq!(parsed, records);
+
+    assert_eq!((parsed[0].bus(), parsed[0].ch()), (0, UartTxChannel::Ctrl));
+    assert!(parsed[0].chunk_start() && !parsed[0].dropped());
+    assert_eq!((parsed[1].bus(), parsed[1].ch()), (1, UartTxChannel::Node));
+    assert!(parsed[1].dropped() && !parsed[1].is_trigger());
+
+    assert!(parse_packet(&packet[..packet.len() - 1]).is_err());
+    assert!(parse_packet(&packet[..3]).is_err());
+    assert!(parse_packet(&[]).unwrap().is_empty());
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+
+use serial_pcap::diff::Outcome;
+use serial_pcap::generate::{generate, GenerateOptions, READ_VALUE};
+use serial_pcap::harness::Replay;
+use serial_pcap::SerialPacketWriter;
+
+fn pcap(opts: &GenerateOptions) -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    generate(opts, &mut writer)?;
+    drop(writer);
+    Ok(pcap)
+}
+
+#[test]
+fn test_generate() -> Result<()> {
+    let opts = GenerateOptions {
+        transactions: 12,
+        nodes: vec![3, 45, 67],
+        corrupt_every: 4,
+        no_reply_every: 5,
+        ..Default::default()
+    };
+    let data = pcap(&opts)?;
+    // the same options give the same file
+    assert_eq!(data, pcap(&opts)?);
+
+    let exchanges = Replay::new(data.as_slice())?.exchanges();
+    assert_eq!(exchanges.len(), 12);
+    assert_eq!(exchanges[0].time, opts.start);
+    let outcomes: Vec<_> = exchanges.iter().map(|x| x.outcome).collect();
+    assert_eq!(outcomes[0], Outcome::Ok(READ_VALUE));
+    assert_eq!(outcomes[1], Outcome::Ok(1));
+    assert_eq!(outcomes[3], Outcome::ProtocolError);
+    assert_eq!(outcomes[4], Outcome::Timeout);
+    assert_eq!(outcomes[7], Outcome::ProtocolError);
+    assert_eq!(outcomes[9], Outcome::Timeout);
+    for (n, x) in exchanges.iter().enumerate() {
+        assert_eq!(*x.cmd.addr(), opts.nodes[n % 3]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_generate_invalid_address() {
+    let opts = GenerateOptions {
+        nodes: vec![21, 100],
+        ..Default::default()
+    };
+    assert!(pcap(&opts).is_err());
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "grpc")]
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, value};
+
+use serial_pcap::grpc::proto::bus_events_client::BusEventsClient;
+use serial_pcap::grpc::proto::{event, transaction, Channel, Command, SubscribeRequest};
+use serial_pcap::x328::{read_command, read_response};
+use serial_pcap::{CaptureRecord, SerialPacket, UartTxChannel};
+
+fn packet(ch: UartTxChannel, data: Vec<u8>, ms: i64) -> CaptureRecord {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    CaptureRecord::Packet(SerialPacket {
+        bus: 0,
+        ch,
+        data: data.as_slice().into(),
+        time: start + Duration::milliseconds(ms),
+    })
+}
+
+#[tokio::test]
+async fn test_subscribe() -> Result<()> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    let (tx, records) = std::sync::mpsc::channel();
+    let server = tokio::spawn(serial_pcap::grpc::serve(listener, records));
+
+    let mut client = BusEventsClient::connect(url).await?;
+    let mut raw = client
+        .subscribe(SubscribeRequest {
+            raw_frames: true,
+            buses: vec![],
+        })
+        .await?
+        .into_inner();
+    let mut decoded = client
+        .subscribe(SubscribeRequest {
+            raw_frames: false,
+            buses: vec![0],
+        })
+        .await?
+        .into_inner();
+    let mut other_bus = client
+        .subscribe(SubscribeRequest {
+            raw_frames: true,
+            buses: vec![1],
+        })
+        .await?
+        .into_inner();
+
+    tx.send(packet(
+        UartTxChannel::Ctrl,
+        read_command(addr(31), param(401)),
+        0,
+    ))?;
+    tx.send(packet(
+        UartTxChannel::Node,
+        read_response(param(401), value(-120)),
+        15,
+    ))?;
+    // the capture stops
+    drop(tx);
+
+    let mut raw_events = vec![];
+    while let Some(event) = raw.message().await? {
+        raw_events.push(event.event.unwrap());
+    }
+    assert_eq!(raw_events.len(), 3);
+    let event::Event::Raw(frame) = &raw_events[0] else {
+        panic!("not a raw frame: {:?}", raw_events[0]);
+    };
+    assert_eq!(frame.channel(), Channel::Ctrl);
+    assert_eq!(frame.data, read_command(addr(31), param(401)));
+    assert!(matches!(&raw_events[1], event::Event::Raw(f) if f.channel() == Channel::Node));
+
+    let event = decoded.message().await?.unwrap();
+    assert_eq!(event.bus, 0);
+    assert_eq!(event.time.unwrap().nanos, 15_000_000);
+    let Some(event::Event::Transaction(t)) = event.event else {
+        panic!("not a transaction: {event:?}");
+    };
+    assert_eq!(
+        t.command,
+        Some(Command {
+            address: 31,
+            parameter: 401,
+            value: None
+        })
+    );
+    assert_eq!(t.result, Some(transaction::Result::Value(-120)));
+    assert_eq!(raw_events[2], event::Event::Transaction(t));
+    assert!(decoded.message().await?.is_none());
+
+    assert!(other_bus.message().await?.is_none());
+    server.await??;
+    Ok(())
+}
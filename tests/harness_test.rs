@@ -0,0 +1,44 @@
+use anyhow::Result;
+
+use serial_pcap::harness::{check_transcript, Replay};
+
+/// Recorded from the simulator over a pair of ptys, with failing parameters, a corrupted
+/// checksum and nodes which don't reply
+const FIXTURE: &str = "tests/fixtures/pty_bus.pcap";
+
+#[test]
+fn test_fixture_transcript() -> Result<()> {
+    check_transcript(FIXTURE)
+}
+
+#[test]
+fn test_fixture_simulation() -> Result<()> {
+    let replay = Replay::from_file(FIXTURE)?;
+    assert_eq!(replay.exchanges().len(), 17);
+    replay.check_simulation()
+}
+
+#[test]
+fn test_transcript_mismatch() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("serial_pcap_harness_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let pcap = dir.join("bus.pcap");
+    std::fs::copy(FIXTURE, &pcap)?;
+    let transcript = Replay::from_file(FIXTURE)?.transcript();
+
+    std::fs::write(
+        dir.join("bus.events"),
+        transcript.replacen("=> 33", "=> 34", 1),
+    )?;
+    let err = check_transcript(&pcap).unwrap_err().to_string();
+    assert!(err.contains("bus.events:1:"), "{err}");
+
+    let mut lines: Vec<_> = transcript.lines().collect();
+    lines.pop();
+    std::fs::write(dir.join("bus.events"), lines.join("\n"))?;
+    let err = check_transcript(&pcap).unwrap_err().to_string();
+    assert!(err.contains("expected 16 events, decoded 17"), "{err}");
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
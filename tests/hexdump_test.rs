@@ -0,0 +1,214 @@
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use chrono::FixedOffset;
+
+use serial_pcap::hexdump::{
+    read_hexdump, write_hexdump, HexdumpChannels, HexdumpFormat, HexdumpStyle,
+};
+use serial_pcap::import::write_analyzer_bytes;
+use serial_pcap::{SerialPacket, SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+const JPNEVULATOR: &str = "\
+2024-05-02 12:15:00.000000: /dev/ttyUSB0
+04 32 32 31 31 30 30 32 33 05
+2024-05-02 12:15:00.012000: /dev/ttyUSB1
+02 32 33 2B 30 30 30 33 33  .23+00033
+03 44                       .D
+";
+
+const INTERCEPTTY: &str = "\
+2024-05-02 12:15:00.000 > 0x04 (.)
+2024-05-02 12:15:00.001 > 0x31 (1)
+2024-05-02 12:15:00.012 < 0x06 (.)
+";
+
+fn utc() -> Option<FixedOffset> {
+    FixedOffset::east_opt(0)
+}
+
+fn to_packets(input: &str, format: HexdumpFormat) -> Result<Vec<SerialPacket>> {
+    let log = read_hexdump(input.as_bytes(), format, HexdumpChannels::default(), utc())?;
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    write_analyzer_bytes(&log.bytes, &mut writer, log.start, Duration::from_millis(5))?;
+    drop(writer);
+    SerialPacketReader::new(pcap.as_slice())?.collect()
+}
+
+#[test]
+fn test_jpnevulator() -> Result<()> {
+    let packets = to_packets(JPNEVULATOR, HexdumpFormat::Jpnevulator)?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].ch, UartTxChannel::Ctrl);
+    assert_eq!(&packets[0].data[..], b"\x0422110023\x05");
+    assert_eq!(packets[1].ch, UartTxChannel::Node);
+    assert_eq!(&packets[1].data[..], b"\x0223+00033\x03D");
+    assert_eq!(packets[0].time.to_rfc3339(), "2024-05-02T12:15:00+00:00");
+    assert_eq!(
+        packets[1].time.to_rfc3339(),
+        "2024-05-02T12:15:00.012+00:00"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_jpnevulator_channels() -> Result<()> {
+    let channels = HexdumpChannels {
+        ctrl: Some("/dev/ttyUSB1".into()),
+        node: None,
+    };
+    let offset = FixedOffset::east_opt(2 * 3600);
+    let log = read_hexdump(
+        JPNEVULATOR.as_bytes(),
+        HexdumpFormat::Jpnevulator,
+        channels,
+        offset,
+    )?;
+    assert_eq!(log.bytes[0].ch, UartTxChannel::Node);
+    assert_eq!(log.bytes.last().unwrap().ch, UartTxChannel::Ctrl);
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_644_900);
+    assert_eq!(log.start, start);
+
+    let three = format!("{JPNEVULATOR}2024-05-02 12:15:01.000000: /dev/ttyUSB2\n04\n");
+    let err = read_hexdump(
+        three.as_bytes(),
+        HexdumpFormat::Jpnevulator,
+        HexdumpChannels::default(),
+        utc(),
+    )
+    .unwrap_err();
+    assert!(format!("{err:#}").contains("line 6"), "{err:#}");
+    Ok(())
+}
+
+#[test]
+fn test_interceptty() -> Result<()> {
+    let packets = to_packets(INTERCEPTTY, HexdumpFormat::Interceptty)?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].ch, UartTxChannel::Ctrl);
+    assert_eq!(&packets[0].data[..], b"\x041");
+    assert_eq!(packets[1].ch, UartTxChannel::Node);
+    assert_eq!(&packets[1].data[..], b"\x06");
+
+    // the lines without a time have the time of the line before
+    let untimed = "2024-05-02 12:15:00.000 > 0x04\n> 0x31\n";
+    let log = read_hexdump(
+        untimed.as_bytes(),
+        HexdumpFormat::Interceptty,
+        HexdumpChannels::default(),
+        utc(),
+    )?;
+    assert_eq!(log.bytes.len(), 2);
+    assert_eq!(log.bytes[1].time, 0.0);
+    let err = read_hexdump(
+        "> 0x04\n".as_bytes(),
+        HexdumpFormat::Interceptty,
+        HexdumpChannels::default(),
+        utc(),
+    )
+    .unwrap_err();
+    assert!(format!("{err:#}").contains("Missing time"), "{err:#}");
+    Ok(())
+}
+
+#[test]
+fn test_import_hexdump_command() -> Result<()> {
+    let dir = std::env::temp_dir();
+    let log = dir.join(format!("serial_pcap_hexdump_{}.log", std::process::id()));
+    let pcap = log.with_extension("pcap");
+    std::fs::write(&log, INTERCEPTTY)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .arg("import-hexdump")
+        .arg(&log)
+        .arg(&pcap)
+        .args(["--format", "interceptty", "--utc-offset", "-01:00"])
+        .output()?;
+    assert!(output.status.success(), "{output:?}");
+    let packets: Vec<_> = SerialPacketReader::from_file(&pcap)?.collect::<Result<_>>()?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].time.to_rfc3339(), "2024-05-02T13:15:00+00:00");
+    std::fs::remove_file(log)?;
+    std::fs::remove_file(pcap)?;
+    Ok(())
+}
+
+fn write_capture() -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_652_100);
+    let ms = |n| t0 + Duration::from_millis(n);
+    writer.write_packet_time(b"\x0422110023\x05", UartTxChannel::Ctrl, ms(0))?;
+    writer.write_packet_time(b"\x0223+00033\x03D", UartTxChannel::Node, ms(12))?;
+    writer.write_bus_packet_time(1, b"\x06", UartTxChannel::Node, ms(123))?;
+    drop(writer);
+    Ok(pcap)
+}
+
+#[test]
+fn test_write_hexdump() -> Result<()> {
+    let pcap = write_capture()?;
+    let mut out = vec![];
+    let style = HexdumpStyle {
+        width: 8,
+        utc_offset: utc(),
+        ..Default::default()
+    };
+    write_hexdump(
+        &mut SerialPacketReader::new(pcap.as_slice())?,
+        &mut out,
+        style,
+    )?;
+    assert_eq!(
+        String::from_utf8(out)?,
+        "2024-05-02T12:15:00.000 CTRL | 04 32 32 31 31 30 30 32\n\
+         \x20                            | 33 05\n\
+         2024-05-02T12:15:00.012 NODE | 02 32 33 2B 30 30 30 33\n\
+         \x20                            | 33 03 44\n\
+         2024-05-02T12:15:00.123 NODE1 | 06\n"
+    );
+
+    let mut out = vec![];
+    let style = HexdumpStyle {
+        ascii: true,
+        utc_offset: FixedOffset::east_opt(2 * 3600),
+        ..Default::default()
+    };
+    write_hexdump(
+        &mut SerialPacketReader::new(pcap.as_slice())?,
+        &mut out,
+        style,
+    )?;
+    let out = String::from_utf8(out)?;
+    let first = out.lines().next().unwrap();
+    assert_eq!(
+        first,
+        format!(
+            "2024-05-02T14:15:00.000 CTRL | 04 32 32 31 31 30 30 32 33 05{:20}.22110023.",
+            ""
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn test_hexdump_command() -> Result<()> {
+    let dir = std::env::temp_dir();
+    let pcap = dir.join(format!("serial_pcap_hexdump_{}.pcap", std::process::id()));
+    std::fs::write(&pcap, write_capture()?)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .arg("hexdump")
+        .arg(&pcap)
+        .args(["--utc-offset", "+00:00"])
+        .output()?;
+    assert!(output.status.success(), "{output:?}");
+    let out = String::from_utf8(output.stdout)?;
+    assert_eq!(out.lines().count(), 3);
+    assert!(
+        out.starts_with("2024-05-02T12:15:00.000 CTRL | 04 32"),
+        "{out}"
+    );
+    std::fs::remove_file(pcap)?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::import::{read_raw_index, write_raw_bytes, RawTiming, RAW_PACKET_LEN};
+use serial_pcap::{SerialPacket, SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn import(data: &[u8], timing: &RawTiming, bus: u8) -> Result<Vec<SerialPacket>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    write_raw_bytes(data, timing, bus, UartTxChannel::Node, &mut writer)?;
+    drop(writer);
+    SerialPacketReader::new(pcap.as_slice())?.collect()
+}
+
+#[test]
+fn test_import_raw_index() -> Result<()> {
+    let index = "offset,length,time\n\
+                 0,10,2024-05-02T10:15:00.012000Z\n\
+                 10,1,2024-05-02T10:15:00.013000Z\n";
+    let timing = read_raw_index(index.as_bytes())?;
+    let packets = import(b"\x0223+00033\x03\x02", &timing, 1)?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(&packets[0].data[..], b"\x0223+00033\x03");
+    assert_eq!(&packets[1].data[..], b"\x02");
+    assert!(packets
+        .iter()
+        .all(|p| p.bus == 1 && p.ch == UartTxChannel::Node));
+    assert_eq!(
+        packets[0].time.to_rfc3339(),
+        "2024-05-02T10:15:00.012+00:00"
+    );
+    assert_eq!(
+        packets[1].time.to_rfc3339(),
+        "2024-05-02T10:15:00.013+00:00"
+    );
+
+    // the length column is optional
+    let timing = read_raw_index("time,offset\n2024-05-02T10:15:00Z,0\n".as_bytes())?;
+    assert_eq!(import(b"\x02", &timing, 0)?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_import_raw_bad_index() -> Result<()> {
+    let bad = |index: &str| -> String {
+        let result = read_raw_index(index.as_bytes()).and_then(|t| import(b"abcdef", &t, 0));
+        format!("{:#}", result.unwrap_err())
+    };
+    assert!(bad("offset\n0\n").contains("no 'time' column"));
+    assert!(bad("offset,time\n0,yesterday\n").contains("line 2"));
+    assert!(bad("offset,time\n2,2024-05-02T10:15:00Z\n").contains("offset 0"));
+    let past_end = "offset,time\n0,2024-05-02T10:15:00Z\n9,2024-05-02T10:15:01Z\n";
+    assert!(bad(past_end).contains("entry 1"));
+    Ok(())
+}
+
+#[test]
+fn test_import_raw_rate() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_644_900);
+    let timing = RawTiming::Rate {
+        start,
+        byte_duration: Duration::from_millis(1),
+    };
+    let mut data = b"\x0422110023\x05\x0431".to_vec();
+    data.extend([b'0'; RAW_PACKET_LEN + 1]);
+    let packets = import(&data, &timing, 0)?;
+    let lens: Vec<_> = packets.iter().map(|p| p.data.len()).collect();
+    assert_eq!(lens, [10, RAW_PACKET_LEN, 4]);
+    let offsets: Vec<_> = packets
+        .iter()
+        .map(|p| (p.time - packets[0].time).num_milliseconds())
+        .collect();
+    assert_eq!(offsets, [0, 10, 10 + RAW_PACKET_LEN as i64]);
+    Ok(())
+}
+
+#[test]
+fn test_import_raw_command() -> Result<()> {
+    let dir = std::env::temp_dir();
+    let raw = dir.join(format!("serial_pcap_import_raw_{}.bin", std::process::id()));
+    let pcap = raw.with_extension("pcap");
+    std::fs::write(&raw, b"\x0422110023\x05")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .arg("import-raw")
+        .arg(&raw)
+        .arg(&pcap)
+        .args(["--channel", "ctrl", "--baud", "1200"])
+        .args(["--start-time", "2024-05-02T10:15:00Z"])
+        .output()?;
+    assert!(output.status.success(), "{output:?}");
+    let packets: Vec<_> = SerialPacketReader::from_file(&pcap)?.collect::<Result<_>>()?;
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].ch, UartTxChannel::Ctrl);
+    assert_eq!(&packets[0].data[..], b"\x0422110023\x05");
+    std::fs::remove_file(raw)?;
+    std::fs::remove_file(pcap)?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, value, Value};
+
+use serial_pcap::decode::{BusCommand, Transaction};
+use serial_pcap::influx::{line, HttpSink, InfluxWriter};
+use serial_pcap::names::NameMap;
+use serial_pcap::x328::{write_command, ACK};
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+fn time() -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap()
+}
+
+fn read(a: u8, p: i16, v: i32) -> Transaction {
+    Transaction {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(p),
+        },
+        cmd_time: time(),
+        resp_time: time() + Duration::microseconds(12_345),
+        result: Ok(Value::new(v).unwrap()),
+    }
+}
+
+#[test]
+fn test_line() -> Result<()> {
+    let names = NameMap::from_toml(
+        "[[node]]\naddress = 31\nname = \"stow east\"\n\
+         [[param]]\naddress = 31\nparam = 401\nname = \"pressure,pin=1\"\nunit = \"bar a\"\n",
+    )?;
+    assert_eq!(
+        line("x328", &read(31, 401, 120), &names).as_deref(),
+        Some(
+            "x328,addr=31,param=401,node=stow\\ east,name=pressure\\,pin\\=1,unit=bar\\ a \
+             value=120i 1700000000012345000\n"
+        )
+    );
+    // no names
+    assert_eq!(
+        line("bus", &read(21, 23, -5), &NameMap::default()).as_deref(),
+        Some("bus,addr=21,param=23 value=-5i 1700000000012345000\n")
+    );
+    // no value to write for a failed command
+    let failed = Transaction {
+        result: Err(x328_proto::master::Error::CommandFailed),
+        ..read(21, 23, 0)
+    };
+    assert_eq!(line("x328", &failed, &names), None);
+    Ok(())
+}
+
+#[test]
+fn test_writer() -> Result<()> {
+    let mut influx = InfluxWriter::new(vec![], "x328", NameMap::default());
+    for (ch, data, ms) in [
+        (
+            UartTxChannel::Ctrl,
+            write_command(addr(21), param(23), value(5)),
+            0,
+        ),
+        (UartTxChannel::Node, vec![ACK], 15),
+    ] {
+        influx.feed(&SerialPacket {
+            bus: 0,
+            ch,
+            data: data.as_slice().into(),
+            time: time() + Duration::milliseconds(ms),
+        })?;
+    }
+    assert_eq!(
+        String::from_utf8(influx.finish()?)?,
+        "x328,addr=21,param=23 value=5i 1700000000015000000\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_http_sink() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/api/v2/write", listener.local_addr()?);
+    // a minimal InfluxDB, which returns the request head and body
+    let server = std::thread::spawn(move || -> Result<(String, String)> {
+        let (stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head)?;
+        }
+        let len: usize = head
+            .lines()
+            .find_map(|l| {
+                l.to_ascii_lowercase()
+                    .strip_prefix("content-length:")
+                    .map(str::to_owned)
+            })
+            .unwrap_or_default()
+            .trim()
+            .parse()?;
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        (&stream).write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")?;
+        Ok((head, String::from_utf8(body)?))
+    });
+
+    let mut sink = HttpSink::new(url, Some("secret".into()));
+    sink.write_all(b"x328,addr=21,param=23 value=5i 1\n")?;
+    sink.flush()?;
+    let (head, body) = server.join().unwrap()?;
+    assert!(head.starts_with("POST /api/v2/write "), "{head}");
+    assert!(head.contains("Token secret"), "{head}");
+    assert_eq!(body, "x328,addr=21,param=23 value=5i 1\n");
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, Value};
+
+use serial_pcap::decode::{BusCommand, Transaction};
+use serial_pcap::latency::{LatencySamples, LatencyStats, NodeLatency};
+
+fn read(a: u8, latency_us: i64) -> Transaction {
+    let time = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    Transaction {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(23),
+        },
+        cmd_time: time,
+        resp_time: time + Duration::microseconds(latency_us),
+        result: Ok(Value::new(1).unwrap()),
+    }
+}
+
+#[test]
+fn test_percentiles() {
+    let mut node = NodeLatency::default();
+    assert_eq!(node.percentile(50.0), None);
+    for ms in (1..=100).rev() {
+        node.add(ms as f64);
+    }
+    assert_eq!(node.count(), 100);
+    assert_eq!(node.min(), Some(1.0));
+    assert_eq!(node.percentile(50.0), Some(50.0));
+    assert_eq!(node.percentile(90.0), Some(90.0));
+    assert_eq!(node.percentile(99.0), Some(99.0));
+    assert_eq!(node.percentile(100.0), Some(100.0));
+    assert_eq!(node.percentile(0.0), Some(1.0));
+    assert_eq!(node.max(), Some(100.0));
+    // the buckets are < 1, 1-2, 2-5, 5-10, 10-20, 20-50, 50-100, 100-200, 200-500 and >= 500 ms
+    assert_eq!(node.histogram(), [0, 1, 3, 5, 10, 30, 50, 1, 0, 0]);
+    node.add(0.5);
+    node.add(1000.0);
+    assert_eq!(node.histogram(), [1, 1, 3, 5, 10, 30, 50, 1, 0, 1]);
+    assert_eq!(node.min(), Some(0.5));
+}
+
+#[test]
+fn test_report() -> Result<()> {
+    let mut stats = LatencyStats::default();
+    for us in [3_000, 4_000, 4_500, 12_000] {
+        stats.add(&read(21, us));
+    }
+    stats.add(&read(5, 800));
+    assert_eq!(stats.node(21).map(|n| n.count()), Some(4));
+    assert!(stats.node(6).is_none());
+    let mut out = vec![];
+    stats.write_report(&mut out)?;
+    assert_eq!(
+        String::from_utf8(out)?,
+        "latency addr=5 count=1 min=0.8ms p50=0.8ms p90=0.8ms p99=0.8ms max=0.8ms\n\
+         \x20     < 1 ms       1 ########################################\n\
+         latency addr=21 count=4 min=3.0ms p50=4.0ms p90=12.0ms p99=12.0ms max=12.0ms\n\
+         \x20     2-5 ms       3 ########################################\n\
+         \x20    5-10 ms       0 \n\
+         \x20   10-20 ms       1 ##############\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_samples() -> Result<()> {
+    let mut samples = LatencySamples::new(vec![])?;
+    samples.add(&read(21, 3_250))?;
+    let out = samples.finish()?;
+    assert_eq!(
+        String::from_utf8(out)?,
+        "time,addr,param,latency_ms\n2023-11-14T22:13:20.000000Z,21,23,3.250\n"
+    );
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use etherparse::PacketBuilder;
+use rpcap::write::{PcapWriter, WriteOptions};
+use rpcap::CapturedPacket;
+
+use serial_pcap::lenient::LenientStats;
+use serial_pcap::{SerialPacketReader, UartTxChannel};
+
+fn udp(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![];
+    PacketBuilder::ipv4(src, dst, 254)
+        .udp(ports.0, ports.1)
+        .write(&mut data, payload)
+        .unwrap();
+    data
+}
+
+/// Split an IPv4 packet with a 20 byte header in two fragments, at `at` bytes of the payload
+fn fragment(packet: &[u8], id: u16, at: usize) -> [Vec<u8>; 2] {
+    let (header, payload) = packet.split_at(20);
+    let part = |data: &[u8], offset: usize, more: bool| {
+        let mut frag = header.to_vec();
+        frag[2..4].copy_from_slice(&(20 + data.len() as u16).to_be_bytes());
+        frag[4..6].copy_from_slice(&id.to_be_bytes());
+        let flags = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+        frag[6..8].copy_from_slice(&flags.to_be_bytes());
+        frag.extend_from_slice(data);
+        frag
+    };
+    [
+        part(&payload[..at], 0, true),
+        part(&payload[at..], at, false),
+    ]
+}
+
+fn pcap(records: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut pcap = vec![];
+    let options = WriteOptions {
+        snaplen: 65535,
+        linktype: 228,
+        high_res_timestamps: false,
+        non_native_byte_order: false,
+    };
+    let mut writer = PcapWriter::new(&mut pcap, options)?;
+    for (i, data) in records.iter().enumerate() {
+        writer.write(&CapturedPacket {
+            time: t + Duration::from_millis(i as u64),
+            data,
+            orig_len: data.len(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(pcap)
+}
+
+#[test]
+fn test_merged_capture() -> Result<()> {
+    let ctrl = |payload: &[u8]| udp([127, 0, 0, 1], [127, 0, 0, 2], (422, 1422), payload);
+    let long_cmd = [b'x'; 40];
+    let mut tcp = vec![];
+    PacketBuilder::ipv4([10, 0, 0, 1], [10, 0, 0, 2], 64)
+        .tcp(40000, 80, 1, 1000)
+        .write(&mut tcp, b"GET / HTTP/1.1\r\n")?;
+    let [first, second] = fragment(&ctrl(&long_cmd), 7, 16);
+    let [lone, _] = fragment(&ctrl(b"lost"), 8, 8);
+    let records = [
+        ctrl(b"\x0400110023\x05"),
+        tcp,
+        udp([10, 0, 0, 1], [10, 0, 0, 53], (5353, 53), b"dns"),
+        b"\x60\x00 not IPv4".to_vec(),
+        first,
+        lone,
+        second,
+        udp([127, 0, 0, 2], [127, 0, 0, 1], (1422, 422), b"\x06"),
+    ];
+    let pcap = pcap(&records)?;
+
+    // the default reader stops at the first foreign record
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    assert!(reader.next_packet()?.is_some());
+    assert!(reader.next_packet().is_err());
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?.with_lenient();
+    let mut packets = vec![];
+    while let Some(pkt) = reader.next_packet()? {
+        packets.push(pkt);
+    }
+    let data: Vec<_> = packets.iter().map(|p| p.data.as_ref()).collect();
+    assert_eq!(data, [&b"\x0400110023\x05"[..], &long_cmd, b"\x06"]);
+    assert_eq!(packets[1].ch, UartTxChannel::Ctrl);
+    assert_eq!(packets[2].ch, UartTxChannel::Node);
+    assert_eq!(
+        reader.lenient_stats(),
+        Some(LenientStats {
+            foreign: 3,
+            reassembled: 1,
+            incomplete: 1,
+        })
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lenient_skip_to() -> Result<()> {
+    let packet = udp([127, 0, 0, 1], [127, 0, 0, 2], (422, 1422), b"\x06");
+    let foreign = udp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), b"other");
+    let pcap = pcap(&[packet.clone(), foreign, packet])?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?.with_lenient();
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    // the peeked record is the foreign one
+    reader.skip_to((start + Duration::from_millis(1)).into())?;
+    let pkt = reader.next_packet()?.unwrap();
+    assert_eq!(SystemTime::from(pkt.time), start + Duration::from_millis(2));
+    assert!(reader.next_packet()?.is_none());
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, RecordRef, SerialPacketReader, SerialPacketWriter,
+    UartTxChannel,
+};
+
+#[test]
+fn test_marker_roundtrip() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let marker = Marker {
+        kind: MarkerKind::Drop,
+        ch: Some(UartTxChannel::Node),
+        label: "queue overflow (2x, 17 bytes)".into(),
+        time: (start + Duration::from_millis(1)).into(),
+    };
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        writer.write_packet_time(b"\x0422110023\x05", UartTxChannel::Ctrl, start)?;
+        writer.write_marker(&marker)?;
+        writer.write_packet_time(
+            b"\x06",
+            UartTxChannel::Node,
+            start + Duration::from_millis(2),
+        )?;
+    }
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    assert!(
+        matches!(reader.next_record()?, Some(CaptureRecord::Packet(p)) if p.ch == UartTxChannel::Ctrl)
+    );
+    assert!(matches!(reader.next_record()?, Some(CaptureRecord::Marker(m)) if m == marker));
+    assert!(
+        matches!(reader.next_record()?, Some(CaptureRecord::Packet(p)) if p.ch == UartTxChannel::Node)
+    );
+    assert!(reader.next_record()?.is_none());
+
+    // the packet iterator skips the markers
+    let packets: Vec<_> = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<_>>()?;
+    assert_eq!(packets.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_record_ref() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        for i in 0..1000u32 {
+            let ch = match i % 2 {
+                0 => UartTxChannel::Ctrl,
+                _ => UartTxChannel::Node,
+            };
+            let data = i.to_string().repeat(i as usize % 50 + 1);
+            writer.write_packet_time(
+                data.as_bytes(),
+                ch,
+                start + Duration::from_millis(i.into()),
+            )?;
+        }
+    }
+
+    let owned: Vec<_> = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<_>>()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    for pkt in &owned {
+        let Some(RecordRef::Packet(r)) = reader.next_record_ref()? else {
+            panic!("Expected a packet.");
+        };
+        assert_eq!(
+            (r.ch, r.data, r.time),
+            (pkt.ch, pkt.data.as_ref(), pkt.time)
+        );
+    }
+    assert!(reader.next_record_ref()?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_user_marker() -> Result<()> {
+    let marker = Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "brake fault on az drive".into(),
+        time: SystemTime::UNIX_EPOCH.into(),
+    };
+    assert_eq!(marker.to_string(), "user: brake fault on az drive");
+
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        writer.write_marker(&marker)?;
+    }
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    assert!(matches!(reader.next_record()?, Some(CaptureRecord::Marker(m)) if m == marker));
+    Ok(())
+}
+
+#[test]
+fn test_port_marker_roundtrip() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let marker = Marker {
+        kind: MarkerKind::Port,
+        ch: Some(UartTxChannel::Node),
+        label: "bus 1 meter (/dev/ttyUSB3)".into(),
+        time: chrono::DateTime::UNIX_EPOCH,
+    };
+    writer.write_marker(&marker)?;
+    drop(writer);
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    match reader.next_record()? {
+        Some(CaptureRecord::Marker(m)) => assert_eq!(m, marker),
+        r => panic!("expected a marker, got {r:?}"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_outage_markers() -> Result<()> {
+    let outage = |label: &str, secs| Marker {
+        kind: MarkerKind::Outage,
+        ch: None,
+        label: label.into(),
+        time: (SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs)).into(),
+    };
+    let markers = [
+        outage("capture device disconnected", 10),
+        outage("capture device reconnected after 2.5 s", 12),
+    ];
+    assert_eq!(
+        markers[0].to_string(),
+        "outage: capture device disconnected"
+    );
+
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        for marker in &markers {
+            writer.write_marker(marker)?;
+        }
+    }
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    for marker in markers {
+        assert!(matches!(reader.next_record()?, Some(CaptureRecord::Marker(m)) if m == marker));
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use serial_pcap::mirror::UdpMirror;
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+fn packet(bus: u8, ch: UartTxChannel, data: &[u8]) -> SerialPacket {
+    SerialPacket {
+        bus,
+        ch,
+        data: data.into(),
+        time: Default::default(),
+    }
+}
+
+#[test]
+fn test_loopback_addresses() -> Result<()> {
+    // the destinations of the pcap packets on bus 7
+    let node = UdpSocket::bind("127.0.7.2:1422")?;
+    let ctrl = UdpSocket::bind("127.0.7.1:422").or_else(|_| {
+        // ports below 1024 need privileges, skip the node to ctrl direction
+        UdpSocket::bind("127.0.7.1:0")
+    })?;
+    node.set_read_timeout(Some(Duration::from_secs(5)))?;
+    ctrl.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut mirror = UdpMirror::new(Ipv4Addr::LOCALHOST);
+    mirror.send_packet(&packet(7, UartTxChannel::Ctrl, b"\x0422110023\x05"));
+    let mut buf = [0; 64];
+    let (len, src) = node.recv_from(&mut buf)?;
+    assert_eq!(&buf[..len], b"\x0422110023\x05");
+    assert_eq!(src.ip(), Ipv4Addr::new(127, 0, 7, 1));
+
+    if ctrl.local_addr()?.port() == 422 {
+        mirror.send_packet(&packet(7, UartTxChannel::Node, b"\x06"));
+        let (len, src) = ctrl.recv_from(&mut buf)?;
+        assert_eq!(&buf[..len], b"\x06");
+        // the receiving socket above has the source port, so another port is used
+        assert_eq!(src.ip(), Ipv4Addr::new(127, 0, 7, 2));
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::io::{BufRead, Read};
+
+use anyhow::Result;
+
+use serial_pcap::generate::{generate, GenerateOptions};
+use serial_pcap::mmap::MappedCapture;
+use serial_pcap::{CaptureRecord, SerialPacketReader, SerialPacketWriter};
+
+fn write_capture(name: &str) -> Result<std::path::PathBuf> {
+    let path = std::env::temp_dir().join(name);
+    let mut writer = SerialPacketWriter::new_file(&path)?;
+    generate(&GenerateOptions::default(), &mut writer)?;
+    writer.flush()?;
+    Ok(path)
+}
+
+/// The records as text, to compare the readers
+fn records(mut reader: SerialPacketReader<impl Read>) -> Result<Vec<String>> {
+    let mut records = vec![];
+    while let Some(record) = reader.next_record()? {
+        records.push(match record {
+            CaptureRecord::Packet(p) => format!("{:?} {:?} {:?}", p.time, p.ch, &p.data[..]),
+            CaptureRecord::Marker(m) => format!("{:?} {m}", m.time),
+            CaptureRecord::Trigger(t) => format!("{:?} {t:?}", t.time),
+        });
+    }
+    Ok(records)
+}
+
+#[test]
+fn test_mapped_reader_matches_file_reader() -> Result<()> {
+    let path = write_capture("serial_pcap_mmap_test.pcap")?;
+    let mapped = records(SerialPacketReader::from_file_mapped(&path)?)?;
+    let plain = records(SerialPacketReader::from_file(&path)?)?;
+    assert!(mapped.len() > 100);
+    assert_eq!(mapped, plain);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[test]
+fn test_mapped_capture_read() -> Result<()> {
+    let path = std::env::temp_dir().join("serial_pcap_mmap_read_test.bin");
+    std::fs::write(&path, b"0123456789")?;
+    let mut capture = MappedCapture::open(&path)?;
+    assert_eq!(capture.len(), 10);
+
+    let mut buf = [0u8; 4];
+    capture.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"0123");
+    assert_eq!(capture.fill_buf()?, b"456789");
+    capture.consume(2);
+    assert_eq!(capture.position(), 6);
+    // a short read at the end fails without consuming anything
+    let mut buf = [0u8; 5];
+    assert!(capture.read_exact(&mut buf).is_err());
+    assert_eq!(capture.read(&mut buf)?, 4);
+    assert_eq!(&buf[..4], b"6789");
+    assert_eq!(capture.read(&mut buf)?, 0);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "mqtt")]
+
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, value};
+
+use serial_pcap::decode::{BusEvent, X328Decoder};
+use serial_pcap::mqtt::message;
+use serial_pcap::x328::{read_command, read_response, write_command, ACK, EOT};
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+/// The messages published for a command and the node response
+fn messages(cmd: Vec<u8>, resp: Vec<u8>) -> Vec<(String, String)> {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut decoder = X328Decoder::new();
+    let mut events: Vec<BusEvent> = vec![];
+    for (ch, data, ms) in [
+        (UartTxChannel::Ctrl, cmd, 0),
+        (UartTxChannel::Node, resp, 15),
+    ] {
+        let pkt = SerialPacket {
+            bus: 0,
+            ch,
+            data: data.as_slice().into(),
+            time: start + Duration::milliseconds(ms),
+        };
+        decoder.feed(&pkt, |e| events.push(e));
+    }
+    events
+        .iter()
+        .filter_map(|e| message("serial-pcap", e))
+        .collect()
+}
+
+#[test]
+fn test_read_message() {
+    assert_eq!(
+        messages(
+            read_command(addr(31), param(401)),
+            read_response(param(401), value(-120))
+        ),
+        [("serial-pcap/31/401".to_string(), "-120".to_string())]
+    );
+}
+
+#[test]
+fn test_write_message() {
+    assert_eq!(
+        messages(write_command(addr(21), param(23), value(5)), vec![ACK]),
+        [("serial-pcap/21/23".to_string(), "5".to_string())]
+    );
+    let failed = messages(read_command(addr(21), param(23)), vec![EOT]);
+    assert_eq!(failed.len(), 1, "{failed:?}");
+    assert_eq!(failed[0].0, "serial-pcap/21/23/error");
+}
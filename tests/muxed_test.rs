@@ -0,0 +1,187 @@
+use serial_pcap::{
+    ChunkTimes, MuxFraming, MuxedStreamDecoder, UartData, UartTxChannel, BREAK_BYTE,
+    DEVICE_TIME_MASK, TIME_BYTE, TRIG_BYTE,
+};
+use serial_pcap_core::mux::CTRL_BIT;
+use serial_pcap_core::slip::Frame;
+
+fn data(ch: UartTxChannel, bytes: &[u8], drops: usize) -> UartData {
+    UartData {
+        ch,
+        data: bytes.into(),
+        drops,
+        chunk: None,
+        line_break: false,
+        trigger: false,
+    }
+}
+
+/// Set bit 7, like the capture device does for the ctrl bytes
+fn ctrl(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().map(|b| b | 0x80).collect()
+}
+
+#[test]
+fn test_demux() {
+    let mut stream = ctrl(b"\x0422110023\x05");
+    stream.extend_from_slice(b"\x06");
+    stream.extend(ctrl(b"\x04"));
+
+    let mut decoder = MuxedStreamDecoder::new();
+    assert_eq!(
+        decoder.feed(&stream),
+        [
+            data(UartTxChannel::Ctrl, b"\x0422110023\x05", 0),
+            data(UartTxChannel::Node, b"\x06", 0),
+            data(UartTxChannel::Ctrl, b"\x04", 0),
+        ]
+    );
+    assert_eq!(decoder.feed(&[]), []);
+}
+
+#[test]
+fn test_demux_trigger() {
+    let mut decoder = MuxedStreamDecoder::new();
+    // a leading trigger is held until the channel is known
+    assert_eq!(decoder.feed(&[TRIG_BYTE]), []);
+    let mut stream = ctrl(b"\x04");
+    stream.push(TRIG_BYTE);
+    stream.extend_from_slice(b"\x06");
+    assert_eq!(
+        decoder.feed(&stream),
+        [
+            data(UartTxChannel::Ctrl, b"\n\x04\n", 0),
+            data(UartTxChannel::Node, b"\x06", 0),
+        ]
+    );
+}
+
+#[test]
+fn test_demux_break() {
+    let mut decoder = MuxedStreamDecoder::new();
+    let mut stream = ctrl(b"\x0422");
+    stream.push(BREAK_BYTE | 0x80);
+    stream.extend(ctrl(b"\x04"));
+    stream.push(BREAK_BYTE);
+    let mut broken = data(UartTxChannel::Ctrl, b"\x0422", 0);
+    broken.line_break = true;
+    let mut node = data(UartTxChannel::Node, b"", 0);
+    node.line_break = true;
+    assert_eq!(
+        decoder.feed(&stream),
+        [broken, data(UartTxChannel::Ctrl, b"\x04", 0), node]
+    );
+}
+
+#[test]
+fn test_demux_drops() {
+    let mut decoder = MuxedStreamDecoder::new();
+    let mut stream = b"ab\x1a\x1ac".to_vec();
+    stream.extend(ctrl(b"\x1a"));
+    assert_eq!(
+        decoder.feed(&stream),
+        [
+            data(UartTxChannel::Node, b"abc", 2),
+            data(UartTxChannel::Ctrl, b"", 1),
+        ]
+    );
+}
+
+/// A chunk timing record, as sent by the capture device
+fn time_record(ch_bit: u8, first: u32, prev_last: u32) -> Vec<u8> {
+    let mut record = vec![TIME_BYTE | ch_bit];
+    for t in [first, prev_last] {
+        record.extend((0..4).map(|i| (t >> (7 * i)) as u8 & 0x7f));
+    }
+    record
+}
+
+#[test]
+fn test_demux_chunk_times() {
+    let mut decoder = MuxedStreamDecoder::new();
+    let mut stream = time_record(0x80, 150_000_000, 149_990_000);
+    stream.extend(ctrl(b"\x04"));
+    stream.extend(time_record(0, 150_001_200, 0));
+    // the record is split between two reads
+    let (first_read, second_read) = stream.split_at(13);
+    let out = decoder.feed(first_read);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].ch, UartTxChannel::Ctrl);
+    assert_eq!(out[0].data.as_ref(), b"\x04");
+    let chunk = out[0].chunk.unwrap();
+    assert_eq!(
+        chunk,
+        ChunkTimes {
+            first: 150_000_000,
+            prev_last: 149_990_000
+        }
+    );
+    assert_eq!(chunk.gap_micros(), 10_000);
+
+    let mut rest = second_read.to_vec();
+    rest.extend_from_slice(b"\x06");
+    let out = decoder.feed(&rest);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].data.as_ref(), b"\x06");
+    assert_eq!(out[0].chunk.map(|c| c.first), Some(150_001_200));
+    // the following data continues the chunk
+    assert_eq!(
+        decoder.feed(b"\x07"),
+        [data(UartTxChannel::Node, b"\x07", 0)]
+    );
+}
+
+#[test]
+fn test_shared_time_record_encoding() {
+    // the firmware encodes the records with the core crate
+    let times = ChunkTimes {
+        first: DEVICE_TIME_MASK,
+        prev_last: 0x0123_4567,
+    };
+    let record = times.encode(CTRL_BIT);
+    assert_eq!(
+        record.to_vec(),
+        time_record(0x80, times.first, times.prev_last)
+    );
+    assert_eq!(ChunkTimes::decode(&record), times);
+
+    let mut decoder = MuxedStreamDecoder::new();
+    let mut stream = record.to_vec();
+    stream.extend(ctrl(b"\x06"));
+    let out = decoder.feed(&stream);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].chunk, Some(times));
+}
+
+#[test]
+fn test_demux_slip() {
+    let chunk = ChunkTimes {
+        first: 1_000_000,
+        prev_last: 990_000,
+    };
+    let mut stream = vec![0x55]; // noise before the first frame
+    Frame::Time(chunk).encode(CTRL_BIT, |b| stream.push(b));
+    Frame::Data(&[0x84, 0xc0, 0x0a]).encode(CTRL_BIT, |b| stream.push(b));
+    Frame::Drop.encode(0, |b| stream.push(b));
+    Frame::Break.encode(CTRL_BIT, |b| stream.push(b));
+
+    // the frames are split between the reads
+    let mut decoder = MuxedStreamDecoder::with_framing(MuxFraming::Slip);
+    let (first, rest) = stream.split_at(14);
+    let mut out = decoder.feed(first);
+    out.extend(decoder.feed(rest));
+    assert_eq!(
+        out,
+        [
+            UartData {
+                chunk: Some(chunk),
+                ..data(UartTxChannel::Ctrl, &[0x84, 0xc0, 0x0a], 0)
+            },
+            data(UartTxChannel::Node, b"", 1),
+            UartData {
+                line_break: true,
+                ..data(UartTxChannel::Ctrl, b"", 0)
+            },
+        ]
+    );
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+
+use serial_pcap::names::NameMap;
+
+#[test]
+fn test_toml_names() -> Result<()> {
+    let names = NameMap::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/names.toml"))?;
+    assert_eq!(names.node(31), Some("stow east"));
+    assert_eq!(names.label(31, 401), "stow pressure east");
+    assert_eq!(names.label(31, 223), "stow east/223");
+    assert_eq!(names.label(5, 10), "5/10");
+    assert_eq!(names.value(31, 401, 120), "120 bar");
+    assert_eq!(names.value(31, 223, 442), "442");
+    Ok(())
+}
+
+#[test]
+fn test_csv_names() -> Result<()> {
+    let names = NameMap::from_csv(
+        "address,param,name,unit,description\n\
+         31,,stow east,,\n\
+         31,401,stow pressure east,bar,East stow pin\n\
+         21,23,encoder position,,\n",
+    )?;
+    assert_eq!(names.node(31), Some("stow east"));
+    let info = names.param(31, 401).unwrap();
+    assert_eq!(info.unit.as_deref(), Some("bar"));
+    assert_eq!(info.description.as_deref(), Some("East stow pin"));
+    assert_eq!(names.value(21, 23, 33), "33");
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use serial_pcap::generate::{generate, GenerateOptions};
+use serial_pcap::pace::{Pacer, ReplaySpeed};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter};
+
+#[test]
+fn test_parse_speed() -> Result<()> {
+    assert_eq!("max".parse::<ReplaySpeed>()?, ReplaySpeed::Max);
+    assert_eq!("realtime".parse::<ReplaySpeed>()?, ReplaySpeed::Scaled(1.0));
+    assert_eq!("0.5x".parse::<ReplaySpeed>()?, ReplaySpeed::Scaled(0.5));
+    assert_eq!("10".parse::<ReplaySpeed>()?, ReplaySpeed::Scaled(10.0));
+    for bad in ["0x", "-2x", "fast", "nanx"] {
+        assert!(bad.parse::<ReplaySpeed>().is_err(), "{bad}");
+    }
+    assert_eq!(ReplaySpeed::Scaled(1.0).to_string(), "realtime");
+    assert_eq!(ReplaySpeed::Scaled(0.5).to_string(), "0.5x");
+    Ok(())
+}
+
+#[test]
+fn test_pacer_delay() {
+    let t0: DateTime<Utc> = "2024-05-02T10:15:00Z".parse().unwrap();
+    let secs = |s: i64| t0 + chrono::Duration::seconds(s);
+    let now = Instant::now();
+
+    let mut pacer = Pacer::new(ReplaySpeed::Scaled(2.0));
+    assert_eq!(pacer.delay(t0, now), Duration::ZERO);
+    assert_eq!(pacer.delay(secs(4), now), Duration::from_secs(2));
+    assert_eq!(
+        pacer.delay(secs(4), now + Duration::from_millis(500)),
+        Duration::from_millis(1500)
+    );
+    // late or out of order records aren't held back
+    assert_eq!(
+        pacer.delay(secs(4), now + Duration::from_secs(3)),
+        Duration::ZERO
+    );
+    assert_eq!(pacer.delay(secs(-10), now), Duration::ZERO);
+
+    let mut pacer = Pacer::new(ReplaySpeed::Max);
+    pacer.delay(t0, now);
+    assert_eq!(pacer.delay(secs(3600), now), Duration::ZERO);
+}
+
+#[test]
+fn test_replay_speed() -> Result<()> {
+    let opts = GenerateOptions {
+        transactions: 10,
+        ..Default::default()
+    };
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    generate(&opts, &mut writer)?;
+    drop(writer);
+    let packets = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<Vec<_>>>()?;
+    let recorded = (packets.last().unwrap().time - opts.start).to_std()?;
+
+    let replay = |speed: &str| -> Result<Duration> {
+        let start = Instant::now();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_replay_x328"))
+            .args(["-", "--speed", speed])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(&pcap)?;
+        let output = child.wait_with_output()?;
+        assert!(output.status.success(), "{output:?}");
+        Ok(start.elapsed())
+    };
+    // scaled to take 400 ms
+    let factor = recorded.as_secs_f64() / 0.4;
+    assert!(replay(&format!("{factor}x"))? >= Duration::from_millis(390));
+    assert!(replay("max")? < Duration::from_millis(390));
+    Ok(())
+}
@@ -0,0 +1,60 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::Result;
+
+use serial_pcap::{CaptureRecord, MarkerKind, SerialPacketReader, UartTxChannel};
+
+fn signal(pid: u32, sig: libc::c_int) {
+    assert_eq!(unsafe { libc::kill(pid as libc::pid_t, sig) }, 0);
+}
+
+#[test]
+fn test_pause_on_sigusr2() -> Result<()> {
+    let pcap = std::env::temp_dir().join(format!("serial_pcap_pause_{}.pcap", std::process::id()));
+    let mut capture = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .arg("--pty")
+        .arg(&pcap)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut lines = BufReader::new(capture.stdout.take().unwrap()).lines();
+    // the port paths are printed among the log lines
+    let ctrl = lines
+        .find_map(|l| Some(l.ok()?.strip_prefix("ctrl: ")?.to_owned()))
+        .unwrap();
+    let mut port = std::fs::OpenOptions::new().write(true).open(&ctrl)?;
+    // let the capture install its signal handlers
+    std::thread::sleep(Duration::from_millis(300));
+
+    port.write_all(b"before")?;
+    std::thread::sleep(Duration::from_millis(200));
+    signal(capture.id(), libc::SIGUSR2);
+    std::thread::sleep(Duration::from_millis(200));
+    port.write_all(b"after")?;
+    std::thread::sleep(Duration::from_millis(200));
+    signal(capture.id(), libc::SIGTERM);
+    assert!(capture.wait()?.success());
+
+    let mut data = vec![];
+    let mut markers = vec![];
+    let mut reader = SerialPacketReader::from_file(&pcap)?;
+    while let Some(record) = reader.next_record()? {
+        match record {
+            CaptureRecord::Packet(pkt) if pkt.ch == UartTxChannel::Ctrl => {
+                assert!(markers.is_empty(), "data after the pause marker");
+                data.extend_from_slice(&pkt.data);
+            }
+            // the clock marker at the start doesn't matter here
+            CaptureRecord::Marker(m) if m.kind != MarkerKind::Clock => markers.push(m.kind),
+            _ => {}
+        }
+    }
+    std::fs::remove_file(&pcap)?;
+    assert_eq!(String::from_utf8(data)?, "before");
+    assert_eq!(markers, [MarkerKind::Pause]);
+    Ok(())
+}
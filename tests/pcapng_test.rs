@@ -0,0 +1,77 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::{Marker, MarkerKind, SerialPacketWriter, Trigger, UartTxChannel};
+
+/// The blocks in a pcapng file, as (type, body)
+fn blocks(mut file: &[u8]) -> Vec<(u32, &[u8])> {
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+    let mut blocks = vec![];
+    while !file.is_empty() {
+        let len = u32_at(file, 4) as usize;
+        assert_eq!(len % 4, 0);
+        assert_eq!(u32_at(file, len - 4) as usize, len);
+        blocks.push((u32_at(file, 0), &file[8..len - 4]));
+        file = &file[len..];
+    }
+    blocks
+}
+
+#[test]
+fn test_pcapng_interfaces() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_micros(0x1_2345_6789);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new_pcapng(&mut pcap)?;
+    writer.describe_channel(0, UartTxChannel::Ctrl, "ctrl (/dev/ttyUSB0 9600 7E1)");
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, t)?;
+    writer.write_marker(&Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "fault".into(),
+        time: t.into(),
+    })?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, t)?;
+    writer.write_packet_time(b"\x04", UartTxChannel::Ctrl, t)?;
+    drop(writer);
+
+    let blocks = blocks(&pcap);
+    let types: Vec<_> = blocks.iter().map(|b| b.0).collect();
+    assert_eq!(types, [0x0a0d0d0a, 1, 6, 1, 6, 1, 6, 6]);
+
+    // the first interface is the ctrl channel, with its name and description
+    let idb = blocks[1].1;
+    assert_eq!(&idb[..2], &228u16.to_le_bytes());
+    let text = String::from_utf8_lossy(idb);
+    assert!(text.contains("ctrl") && text.contains("ctrl (/dev/ttyUSB0 9600 7E1)"));
+    assert!(String::from_utf8_lossy(blocks[3].1).contains("markers"));
+    assert!(String::from_utf8_lossy(blocks[5].1).contains("node"));
+
+    // the packets refer to their interfaces, with microsecond timestamps
+    let interface_ids: Vec<_> = [2, 4, 6, 7].map(|i| blocks[i].1[0]).to_vec();
+    assert_eq!(interface_ids, [0, 1, 2, 0]);
+    let epb = blocks[2].1;
+    assert_eq!(epb[4..8], 1u32.to_le_bytes());
+    assert_eq!(epb[8..12], 0x2345_6789u32.to_le_bytes());
+    Ok(())
+}
+
+#[test]
+fn test_pcapng_trigger_comment() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new_pcapng(&mut pcap)?;
+    writer.write_trigger(&Trigger::new(1, Trigger::DEVICE, t.into()))?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, t)?;
+    drop(writer);
+
+    // the trigger text is also a packet comment, option 1 after the packet data
+    let blocks = blocks(&pcap);
+    let comment = b"device: measurement trigger";
+    let mut option = [1u16.to_le_bytes(), (comment.len() as u16).to_le_bytes()].concat();
+    option.extend_from_slice(comment);
+    let trigger = blocks[2].1;
+    assert!(trigger.windows(option.len()).any(|w| w == option));
+    assert!(!blocks[4].1.windows(4).any(|w| w == b"trig"));
+    Ok(())
+}
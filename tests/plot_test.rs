@@ -0,0 +1,134 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use x328_proto::{addr, param, value};
+
+use serial_pcap::names::NameMap;
+use serial_pcap::plot::{
+    collect_series, write_csv, write_gnuplot, write_svg, ParamId, PlotFormat, Series, SvgSize,
+};
+use serial_pcap::x328::{read_command, read_response, write_command, ACK};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+/// Reads of 31:401 with 100, 120 and 90, and a write of 5 to 21:23, a second apart
+fn capture() -> Result<Vec<u8>> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_652_100);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let mut time = start;
+    let mut exchange = |cmd: Vec<u8>, resp: Vec<u8>| -> Result<()> {
+        writer.write_packet_time(&cmd, UartTxChannel::Ctrl, time)?;
+        let resp_time = time + Duration::from_millis(10);
+        writer.write_packet_time(&resp, UartTxChannel::Node, resp_time)?;
+        time += Duration::from_secs(1);
+        Ok(())
+    };
+    for v in [100, 120, 90] {
+        exchange(
+            read_command(addr(31), param(401)),
+            read_response(param(401), value(v)),
+        )?;
+        if v == 120 {
+            exchange(write_command(addr(21), param(23), value(5)), vec![ACK])?;
+        }
+    }
+    drop(writer);
+    Ok(pcap)
+}
+
+fn series(names: &NameMap) -> Result<Vec<Series>> {
+    let pcap = capture()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let params = ["31:401".parse()?, "21:23".parse()?, "5:1".parse()?];
+    collect_series(&mut reader, &params, names)
+}
+
+#[test]
+fn test_collect_series() -> Result<()> {
+    let names = NameMap::from_toml(
+        "[[param]]\naddress = 31\nparam = 401\nname = \"stow pressure\"\nunit = \"bar\"\n",
+    )?;
+    let series = series(&names)?;
+    assert_eq!(series.len(), 3);
+    assert_eq!(
+        series[0].id,
+        ParamId {
+            addr: 31,
+            param: 401
+        }
+    );
+    assert_eq!(series[0].label, "stow pressure (bar)");
+    let values: Vec<_> = series[0].points.iter().map(|p| p.1).collect();
+    assert_eq!(values, [100, 120, 90]);
+    assert_eq!(
+        series[0].points[1].0.to_rfc3339(),
+        "2024-05-02T12:15:01.010+00:00"
+    );
+    assert_eq!(series[1].label, "21/23");
+    assert_eq!(series[1].points.len(), 1);
+    assert_eq!(series[1].points[0].1, 5);
+    assert!(series[2].points.is_empty());
+
+    assert!("31".parse::<ParamId>().is_err());
+    assert!("31:x".parse::<ParamId>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_csv() -> Result<()> {
+    let mut out = vec![];
+    write_csv(&series(&NameMap::default())?, &mut out)?;
+    assert_eq!(
+        String::from_utf8(out)?,
+        "time,addr,param,name,value\n\
+         2024-05-02T12:15:00.010000Z,31,401,31/401,100\n\
+         2024-05-02T12:15:01.010000Z,31,401,31/401,120\n\
+         2024-05-02T12:15:02.010000Z,21,23,21/23,5\n\
+         2024-05-02T12:15:03.010000Z,31,401,31/401,90\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_gnuplot() -> Result<()> {
+    let mut out = vec![];
+    write_gnuplot(&series(&NameMap::default())?, &mut out)?;
+    let script = String::from_utf8(out)?;
+    assert!(script.contains("# no values of 5/1\n"), "{script}");
+    assert!(
+        script.contains("$s0 << EOD\n1714652100.010000 100\n"),
+        "{script}"
+    );
+    assert!(script.contains("set multiplot layout 2,1\n"), "{script}");
+    assert!(script.contains("plot $s1 using 1:2 with steps notitle\n"));
+    Ok(())
+}
+
+#[test]
+fn test_svg() -> Result<()> {
+    let mut out = vec![];
+    let size = SvgSize {
+        width: 600,
+        panel_height: 100,
+    };
+    write_svg(&series(&NameMap::default())?, &mut out, size)?;
+    let svg = String::from_utf8(out)?;
+    assert!(svg.starts_with("<svg "), "{svg}");
+    assert!(svg.contains(r#"width="600" height="300""#), "{svg}");
+    assert_eq!(svg.matches("<path ").count(), 2, "{svg}");
+    assert!(svg.contains(">no values</text>"), "{svg}");
+    assert!(svg.trim_end().ends_with("</svg>"));
+
+    // nothing to plot
+    assert!(write_svg(&[], &mut vec![], size).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_format_from_filename() {
+    assert_eq!(PlotFormat::from_filename("a.SVG"), Some(PlotFormat::Svg));
+    assert_eq!(PlotFormat::from_filename("a.png"), Some(PlotFormat::Png));
+    assert_eq!(PlotFormat::from_filename("a.gp"), Some(PlotFormat::Gnuplot));
+    assert_eq!(PlotFormat::from_filename("a.csv"), Some(PlotFormat::Csv));
+    assert_eq!(PlotFormat::from_filename("plot"), None);
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "wasm")]
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::plugin::{decode_with_plugin, DecoderPlugin};
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+/// Emits "ctrl N" or "node N" with the length of each packet, and "done" at the end
+const COUNTER: &str = r#"
+(module
+  (import "serial_pcap" "emit" (func $emit (param i64 i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "ctrl node done")
+  (global $last (mut i64) (i64.const 0))
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "feed") (param $bus i32) (param $ch i32) (param $time i64) (param $ptr i32) (param $len i32)
+    (i32.store8 (i32.const 100) (i32.const 32))
+    (i32.store8 (i32.const 101) (i32.add (i32.const 48) (local.get $len)))
+    (memory.copy (i32.const 96) (i32.mul (local.get $ch) (i32.const 5)) (i32.const 4))
+    (global.set $last (local.get $time))
+    (call $emit (local.get $time) (i32.const 96) (i32.const 6)))
+  (func (export "finish")
+    (call $emit (global.get $last) (i32.const 10) (i32.const 4))))
+"#;
+
+const SPIN: &str = r#"
+(module
+  (import "serial_pcap" "emit" (func $emit (param i64 i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "feed") (param i32 i32 i64 i32 i32)
+    (loop $l (br $l))))
+"#;
+
+fn capture() -> Result<Vec<u8>> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_652_100);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(b"\x04123\x05", UartTxChannel::Ctrl, start)?;
+    writer.write_packet_time(
+        b"\x06",
+        UartTxChannel::Node,
+        start + Duration::from_millis(12),
+    )?;
+    drop(writer);
+    Ok(pcap)
+}
+
+#[test]
+fn test_decode_with_plugin() -> Result<()> {
+    let pcap = capture()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut plugin = DecoderPlugin::new(COUNTER.as_bytes())?;
+    let mut out = vec![];
+    let count = decode_with_plugin(&mut reader, &mut plugin, &mut out)?;
+    assert_eq!(count, 3);
+    assert_eq!(
+        String::from_utf8(out)?,
+        "2024-05-02T12:15:00.000000Z ctrl 5\n\
+         2024-05-02T12:15:00.012000Z node 1\n\
+         2024-05-02T12:15:00.012000Z done\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_missing_export() {
+    let err = DecoderPlugin::new(b"(module (memory (export \"memory\") 1))").err();
+    assert!(err.is_some());
+}
+
+#[test]
+fn test_runaway_plugin() -> Result<()> {
+    let pcap = capture()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let mut plugin = DecoderPlugin::new(SPIN.as_bytes())?;
+    let pkt = reader.next().unwrap()?;
+    assert!(plugin.feed(&pkt).is_err());
+    Ok(())
+}
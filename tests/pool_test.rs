@@ -0,0 +1,57 @@
+use anyhow::Result;
+
+use serial_pcap::capture::{read_uart, DropStats, UartSink};
+use serial_pcap::clock::CaptureClock;
+use serial_pcap::pool::{BufferPool, BUFFER_LEN};
+use serial_pcap::queue::{bounded, OverflowPolicy};
+use serial_pcap::UartTxChannel;
+
+#[test]
+fn test_reuse() {
+    let pool = BufferPool::new();
+    let mut buf = pool.get();
+    assert!(buf.is_empty() && buf.capacity() >= BUFFER_LEN);
+    buf.extend_from_slice(b"\x0400110023\x05");
+    let ptr = buf.as_ptr();
+    pool.put(buf);
+    assert_eq!(pool.idle(), 1);
+
+    // the same memory comes back, emptied
+    let buf = pool.get();
+    assert_eq!((buf.as_ptr(), buf.len()), (ptr, 0));
+    assert_eq!(pool.idle(), 0);
+
+    // the parts of a split buffer are too small to keep
+    let mut buf = buf;
+    buf.resize(BUFFER_LEN, 0);
+    let head = buf.split_to(10);
+    pool.put(head);
+    pool.put(buf);
+    assert_eq!(pool.idle(), 0);
+}
+
+#[tokio::test]
+async fn test_read_uart_uses_pool() -> Result<()> {
+    let (tx, mut rx) = bounded(100, OverflowPolicy::Block);
+    let clock = CaptureClock::new();
+    let pool = BufferPool::new();
+    let sink = UartSink {
+        tx,
+        drops: DropStats::new(clock.clone()).into(),
+        clock,
+        pool: pool.clone(),
+    };
+    let bufs: Vec<_> = (0..4).map(|_| pool.get()).collect();
+    bufs.into_iter().for_each(|buf| pool.put(buf));
+    let idle = pool.idle();
+    let input: &[u8] = b"\x0400110023\x05";
+    assert!(read_uart(input, 0, UartTxChannel::Ctrl, sink)
+        .await
+        .is_err());
+    let read = rx.recv().await.unwrap();
+    assert_eq!(&read.data[..], input);
+    assert!(read.data.capacity() >= BUFFER_LEN);
+    // the read buffer and the next one were taken from the pool
+    assert_eq!(pool.idle(), idle - 2);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+#![cfg(unix)]
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use serial_pcap::pty::PtyPair;
+use serial_pcap::UartTxChannel;
+
+const SCENARIO: &str = r#"
+commands = [{ read = [21, 23] }, { write = [31, 223, 442] }]
+
+[bus]
+cycles = 3
+
+[[node]]
+address = 21
+parameters = { 23 = 33 }
+
+[[node]]
+address = 31
+parameters = { 223 = 0 }
+"#;
+
+#[tokio::test]
+async fn test_simulate_over_pty() -> Result<()> {
+    let scenario = std::env::temp_dir().join("serial_pcap_pty_test.toml");
+    std::fs::write(&scenario, SCENARIO)?;
+
+    let pty = PtyPair::new()?;
+    let sim = tokio::process::Command::new(env!("CARGO_BIN_EXE_simulate"))
+        .arg(&scenario)
+        .args(["--ctrl", pty.ctrl_path(), "--node", pty.node_path()])
+        .output();
+
+    let ctrl = Arc::new(Mutex::new(Vec::new()));
+    let tap = ctrl.clone();
+    let bus = pty.run(async move |ch, data| {
+        if ch == UartTxChannel::Ctrl {
+            tap.lock().unwrap().extend_from_slice(data);
+        }
+        Ok(())
+    });
+
+    let output = tokio::select! {
+        r = bus => { r?; unreachable!() }
+        output = sim => output?,
+    };
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(stdout.matches("Read 23@21 => 33").count(), 3, "{stdout}");
+    assert_eq!(stdout.matches("Write 442 to 223@31 ok").count(), 3);
+
+    let ctrl = ctrl.lock().unwrap();
+    assert_eq!(ctrl.iter().filter(|&&b| b == 0x04).count(), 6); // one EOT per command
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use serial_pcap::queue::{bounded, OverflowPolicy};
+
+#[tokio::test]
+async fn test_drop_oldest() -> Result<()> {
+    let (tx, mut rx) = bounded(2, OverflowPolicy::DropOldest);
+    let mut evicted = vec![];
+    for i in 0..5 {
+        evicted.extend(tx.send(i).await?);
+    }
+    assert_eq!(evicted, [0, 1, 2]);
+    assert_eq!(tx.dropped(), 3);
+    drop(tx);
+    assert_eq!(rx.recv().await, Some(3));
+    assert_eq!(rx.recv().await, Some(4));
+    assert_eq!(rx.recv().await, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_abort() -> Result<()> {
+    let (tx, _rx) = bounded(2, OverflowPolicy::Abort);
+    tx.send(1).await?;
+    tx.send(2).await?;
+    assert!(tx.send(3).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_block() -> Result<()> {
+    let (tx, mut rx) = bounded(1, OverflowPolicy::Block);
+    tx.send(1).await?;
+    let full = tokio::time::timeout(Duration::from_millis(50), tx.send(2)).await;
+    assert!(full.is_err(), "send should block on a full queue");
+
+    let sender = tokio::spawn(async move {
+        for i in 2..100 {
+            tx.send(i).await?;
+        }
+        anyhow::Ok(tx.dropped())
+    });
+    let mut received = vec![];
+    while let Some(i) = rx.recv().await {
+        received.push(i);
+    }
+    assert_eq!(received, (1..100).collect::<Vec<_>>());
+    assert_eq!(sender.await??, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receiver_closed() {
+    let (tx, rx) = bounded(1, OverflowPolicy::Block);
+    tx.send(1).await.unwrap();
+    drop(rx);
+    assert!(tx.send(2).await.is_err());
+}
+
+#[test]
+fn test_zero_queue_size() -> Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .args(["--queue-size", "0", "unused.pcap"])
+        .output()?;
+    // rejected as a usage error, instead of a panic in the queue
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--queue-size"));
+    Ok(())
+}
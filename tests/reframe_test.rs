@@ -0,0 +1,52 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+#[test]
+fn test_frames() -> Result<()> {
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let ms = |n| t0 + Duration::from_millis(n);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    // one command split in three packets
+    writer.write_packet_time(b"\x0400", UartTxChannel::Ctrl, ms(0))?;
+    writer.write_packet_time(b"11", UartTxChannel::Ctrl, ms(3))?;
+    writer.write_packet_time(b"0023\x05", UartTxChannel::Ctrl, ms(6))?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, ms(9))?;
+    writer.write_packet_time(b"\x04", UartTxChannel::Ctrl, ms(40))?;
+    writer.write_bus_packet_time(1, b"\x15", UartTxChannel::Ctrl, ms(42))?;
+    drop(writer);
+
+    let frames = SerialPacketReader::new(pcap.as_slice())?
+        .frames(Duration::from_millis(5))
+        .collect::<Result<Vec<_>>>()?;
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|f| (f.bus, f.ch, f.time, f.data.as_ref()))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (
+                0,
+                UartTxChannel::Ctrl,
+                ms(0).into(),
+                &b"\x0400110023\x05"[..]
+            ),
+            (0, UartTxChannel::Node, ms(9).into(), b"\x06"),
+            (0, UartTxChannel::Ctrl, ms(40).into(), b"\x04"),
+            (1, UartTxChannel::Ctrl, ms(42).into(), b"\x15"),
+        ]
+    );
+
+    // with the byte time, a gap is measured from the end of the packet
+    let frames = SerialPacketReader::new(pcap.as_slice())?
+        .frames(Duration::from_millis(2))
+        .with_byte_time(Duration::from_millis(1))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(frames[0].data.as_ref(), b"\x0400110023\x05");
+    assert_eq!(frames.len(), 4);
+    Ok(())
+}
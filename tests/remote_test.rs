@@ -0,0 +1,136 @@
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use tokio::io::AsyncWriteExt;
+
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, SerialPacket, SerialPacketReader, SerialPacketWriter,
+    UartTxChannel,
+};
+
+#[test]
+fn test_serve() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let server = std::thread::spawn(move || serial_pcap::remote::serve(listener, rx));
+
+    let mut client = TcpStream::connect(addr)?;
+    let mut pcap = vec![0u8; 24];
+    client.read_exact(&mut pcap)?;
+
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    for (i, ch) in [UartTxChannel::Ctrl, UartTxChannel::Node]
+        .into_iter()
+        .enumerate()
+    {
+        tx.send(SerialPacket {
+            bus: 0,
+            ch,
+            data: format!("packet {i}").as_bytes().into(),
+            time: (start + Duration::from_millis(i as u64)).into(),
+        })?;
+    }
+    drop(tx);
+    server.join().unwrap()?;
+    client.read_to_end(&mut pcap)?;
+
+    let packets: Vec<_> = SerialPacketReader::new(pcap.as_slice())?.collect::<Result<_>>()?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[1].ch, UartTxChannel::Node);
+    assert_eq!(packets[1].data.as_ref(), b"packet 1");
+    assert_eq!(
+        SystemTime::from(packets[1].time),
+        start + Duration::from_millis(1)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receive() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let marker = Marker {
+        kind: MarkerKind::Drop,
+        ch: Some(UartTxChannel::Node),
+        label: "device overrun".into(),
+        time: start.into(),
+    };
+    let mut sent = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut sent)?;
+        writer.write_marker(&marker)?;
+        writer.write_packet_time(b"\x0422110023\x05", UartTxChannel::Ctrl, start)?;
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.write_all(&sent).await
+    });
+
+    let mut writer = SerialPacketWriter::new(vec![])?;
+    serial_pcap::remote::receive(addr, &mut writer).await?;
+    server.await??;
+
+    let received = writer.into_inner()?;
+    let mut reader = SerialPacketReader::new(received.as_slice())?;
+    assert!(matches!(reader.next_record()?, Some(CaptureRecord::Marker(m)) if m == marker));
+    assert!(
+        matches!(reader.next_record()?, Some(CaptureRecord::Packet(p)) if p.ch == UartTxChannel::Ctrl)
+    );
+    assert!(reader.next_record()?.is_none());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_forward() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?.to_string();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let forwarder = std::thread::spawn(move || serial_pcap::remote::forward(&addr, rx));
+    for (i, ch) in [UartTxChannel::Ctrl, UartTxChannel::Node]
+        .into_iter()
+        .enumerate()
+    {
+        tx.send(SerialPacket {
+            bus: 1,
+            ch,
+            data: format!("packet {i}").as_bytes().into(),
+            time: (start + Duration::from_millis(i as u64)).into(),
+        })?;
+    }
+    drop(tx);
+
+    let mut writer = SerialPacketWriter::new(vec![])?;
+    serial_pcap::remote::receive_forwarded(listener, &mut writer).await?;
+    forwarder.join().unwrap()?;
+
+    let received = writer.into_inner()?;
+    let packets: Vec<_> = SerialPacketReader::new(received.as_slice())?.collect::<Result<_>>()?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].bus, 1);
+    assert_eq!(packets[1].ch, UartTxChannel::Node);
+    assert_eq!(packets[1].data.as_ref(), b"packet 1");
+    Ok(())
+}
+
+#[test]
+fn test_forward_unreachable() -> Result<()> {
+    // nothing listens on the port, so the packets are skipped
+    let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?.to_string();
+    let (tx, rx) = std::sync::mpsc::channel();
+    for i in 0..3 {
+        tx.send(SerialPacket {
+            bus: 0,
+            ch: UartTxChannel::Ctrl,
+            data: [i][..].into(),
+            time: Default::default(),
+        })?;
+    }
+    drop(tx);
+    serial_pcap::remote::forward(&addr, rx)
+}
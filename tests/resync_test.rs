@@ -0,0 +1,103 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::resync::ResyncStats;
+use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+const PAYLOADS: [&[u8]; 3] = [b"\x0400110023\x05", b"\x06", b"\x0400110024\x05"];
+/// File header, then 16 bytes of record header, 20 bytes of IP header and 8 of UDP header
+const FIRST_RECORD: usize = 24;
+const RECORD_OVERHEAD: usize = 16 + 28;
+
+fn capture() -> Result<Vec<u8>> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    for (n, data) in PAYLOADS.iter().enumerate() {
+        writer.write_packet_time(
+            data,
+            UartTxChannel::Ctrl,
+            t + Duration::from_millis(n as u64),
+        )?;
+    }
+    drop(writer);
+    Ok(pcap)
+}
+
+/// The start of the second record
+fn second_record() -> usize {
+    FIRST_RECORD + RECORD_OVERHEAD + PAYLOADS[0].len()
+}
+
+fn read_all(pcap: &[u8]) -> Result<(Vec<Vec<u8>>, ResyncStats)> {
+    let mut reader = SerialPacketReader::new(pcap)?.with_resync();
+    let mut data = vec![];
+    while let Some(pkt) = reader.next_packet()? {
+        data.push(pkt.data.to_vec());
+    }
+    Ok((data, reader.resync_stats().unwrap()))
+}
+
+#[test]
+fn test_skip_bad_packet() -> Result<()> {
+    let mut pcap = capture()?;
+    // not an IPv4 packet
+    pcap[second_record() + 16] = 0x60;
+    assert!(SerialPacketReader::new(pcap.as_slice())?
+        .find(|r| r.is_err())
+        .is_some());
+
+    let (data, stats) = read_all(&pcap)?;
+    assert_eq!(data, [PAYLOADS[0], PAYLOADS[2]]);
+    let skipped = (RECORD_OVERHEAD + PAYLOADS[1].len()) as u64;
+    assert_eq!(
+        stats,
+        ResyncStats {
+            skipped_bytes: skipped,
+            gaps: 1
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_scan_for_record_header() -> Result<()> {
+    let mut pcap = capture()?;
+    // a broken record length, followed by garbage
+    let at = second_record();
+    pcap[at + 8..at + 12].copy_from_slice(&0xffff_u32.to_le_bytes());
+    pcap.splice(at + 16..at + 16, [0x45; 7]);
+
+    let (data, stats) = read_all(&pcap)?;
+    assert_eq!(data, [PAYLOADS[0], PAYLOADS[2]]);
+    let skipped = (RECORD_OVERHEAD + PAYLOADS[1].len() + 7) as u64;
+    assert_eq!(
+        stats,
+        ResyncStats {
+            skipped_bytes: skipped,
+            gaps: 1
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_truncated_end() -> Result<()> {
+    let mut pcap = capture()?;
+    pcap.truncate(pcap.len() - 3);
+    let (data, stats) = read_all(&pcap)?;
+    assert_eq!(data, &PAYLOADS[..2]);
+    let skipped = (RECORD_OVERHEAD + PAYLOADS[2].len() - 3) as u64;
+    assert_eq!(
+        stats,
+        ResyncStats {
+            skipped_bytes: skipped,
+            gaps: 1
+        }
+    );
+
+    let (data, stats) = read_all(&capture()?)?;
+    assert_eq!((data.len(), stats), (3, ResyncStats::default()));
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use etherparse::PacketBuilder;
+use rpcap::write::{PcapWriter, WriteOptions};
+use rpcap::CapturedPacket;
+
+use serial_pcap::{Marker, MarkerKind, SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn rewrite(pcap: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = SerialPacketReader::new(pcap)?;
+    let mut out = vec![];
+    let mut writer = SerialPacketWriter::new_like(&mut out, &reader)?;
+    while let Some(record) = reader.next_record()? {
+        writer.write_record(&record)?;
+    }
+    drop(writer);
+    Ok(out)
+}
+
+#[test]
+fn test_rewrite_is_exact() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_micros(1_234_567_890_123_457);
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_marker(&Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "start".into(),
+        time: t.into(),
+    })?;
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, t)?;
+    writer.write_bus_packet_time(
+        1,
+        b"\x06",
+        UartTxChannel::Node,
+        t + Duration::from_micros(3),
+    )?;
+    drop(writer);
+    assert_eq!(rewrite(&pcap)?, pcap);
+    Ok(())
+}
+
+#[test]
+fn test_rewrite_keeps_nanoseconds_and_long_packets() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_nanos(1_234_567_890_123_456_789);
+    let mut data = vec![];
+    PacketBuilder::ipv4([127, 0, 0, 1], [127, 0, 0, 2], 254)
+        .udp(422, 1422)
+        .write(&mut data, &[b'x'; 300])?;
+    let mut pcap = vec![];
+    let options = WriteOptions {
+        snaplen: 1000,
+        linktype: 228,
+        high_res_timestamps: true,
+        non_native_byte_order: false,
+    };
+    let mut writer = PcapWriter::new(&mut pcap, options)?;
+    writer.write(&CapturedPacket {
+        time: t,
+        data: &data,
+        orig_len: data.len(),
+    })?;
+    writer.flush()?;
+
+    let out = rewrite(&pcap)?;
+    let packets = SerialPacketReader::new(out.as_slice())?.collect::<Result<Vec<_>>>()?;
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].data.len(), 300);
+    assert_eq!(SystemTime::from(packets[0].time), t);
+
+    // the normal writer can't hold the packet without splitting it
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let record = reader.next_record()?.unwrap();
+    let mut writer = SerialPacketWriter::new(vec![])?;
+    assert!(writer.write_record(&record).is_err());
+    Ok(())
+}
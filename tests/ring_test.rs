@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::ring::{RingLimits, SegmentRing};
+use serial_pcap::{CaptureRecord, Marker, MarkerKind, SerialPacketReader, UartTxChannel};
+
+/// An empty directory for the segments of one test
+fn test_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("serial-pcap-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn file_names(ring: &SegmentRing) -> Vec<String> {
+    ring.segment_paths()
+        .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn test_ring_prunes_by_size() -> Result<()> {
+    let dir = test_dir("ring-size")?;
+    let limits = RingLimits {
+        max_size: Some(10_000),
+        max_age: None,
+    };
+    let mut ring = SegmentRing::new(dir.join("bus.pcap"), limits)?;
+    ring.add_header_marker(Marker {
+        kind: MarkerKind::Clock,
+        ch: None,
+        label: "anchor".into(),
+        time: SystemTime::now().into(),
+    });
+    let mut writer = ring.open_segment()?;
+    let now = Instant::now();
+    for _ in 0..200 {
+        writer.write_packet_time(&[b'x'; 100], UartTxChannel::Ctrl, SystemTime::now())?;
+        ring.rotate_if_due(&mut writer, now)?;
+    }
+    writer.flush()?;
+
+    let names = file_names(&ring);
+    assert!(names.len() <= 11, "{names:?}");
+    let last = names.last().unwrap().clone();
+    assert!(!dir.join("bus.00001.pcap").exists());
+    let total: u64 = ring
+        .segment_paths()
+        .map(|p| std::fs::metadata(p).unwrap().len())
+        .sum();
+    assert!(total <= 11_000, "{total}");
+
+    // every segment starts with the header markers
+    for path in ring.segment_paths() {
+        let first = SerialPacketReader::new(std::fs::File::open(path)?)?.next_record()?;
+        assert!(matches!(first, Some(CaptureRecord::Marker(m)) if m.label == "anchor"));
+    }
+
+    // a new capture with the same name continues the numbering, and prunes the old segments
+    drop(writer);
+    let mut ring = SegmentRing::new(dir.join("bus.pcap"), limits)?;
+    let mut writer = ring.open_segment()?;
+    let next = ring.segment_paths().last().unwrap().to_path_buf();
+    assert!(next.file_name().unwrap().to_string_lossy().as_ref() > last.as_str());
+    for _ in 0..200 {
+        writer.write_packet_time(&[b'x'; 100], UartTxChannel::Ctrl, SystemTime::now())?;
+        ring.rotate_if_due(&mut writer, now)?;
+    }
+    assert!(!dir.join(last).exists());
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_ring_prunes_by_age() -> Result<()> {
+    let dir = test_dir("ring-age")?;
+    let limits = RingLimits {
+        max_size: None,
+        max_age: Some(Duration::from_secs(600)),
+    };
+    let mut ring = SegmentRing::new(dir.join("bus.pcapng"), limits)?;
+    let mut writer = ring.open_segment()?;
+    let start = Instant::now();
+    // a packet every 10 s for an hour
+    for n in 1..=360 {
+        let now = start + Duration::from_secs(10 * n);
+        writer.write_packet_time(b"\x06", UartTxChannel::Node, SystemTime::now())?;
+        ring.rotate_if_due(&mut writer, now)?;
+    }
+    let names = file_names(&ring);
+    // a new segment every minute, and the ones which ended more than 10 minutes ago deleted,
+    // which leaves 10 minutes and the segment which was just started
+    assert_eq!(names.len(), 12, "{names:?}");
+    assert!(names.iter().all(|n| n.ends_with(".pcapng")));
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_ring_needs_a_limit() {
+    assert!(SegmentRing::new("bus.pcap", RingLimits::default()).is_err());
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "script")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::master::Error as X328Error;
+use x328_proto::{addr, param, Value};
+
+use serial_pcap::decode::{BusCommand, Transaction};
+use serial_pcap::script::TransactionScript;
+
+fn read(a: u8, p: i16, result: Result<i32, X328Error>, latency_ms: i64) -> Transaction {
+    let time = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    Transaction {
+        cmd: BusCommand::Read {
+            addr: addr(a),
+            param: param(p),
+        },
+        cmd_time: time,
+        resp_time: time + Duration::milliseconds(latency_ms),
+        result: result.map(|v| Value::new(v).unwrap()),
+    }
+}
+
+fn load(source: &str) -> Result<(TransactionScript, Rc<RefCell<Vec<String>>>)> {
+    let lines = Rc::new(RefCell::new(vec![]));
+    let printed = lines.clone();
+    let script =
+        TransactionScript::with_print(source, move |s| printed.borrow_mut().push(s.into()))?;
+    Ok((script, lines))
+}
+
+#[test]
+fn test_counters() -> Result<()> {
+    let (mut script, lines) = load(
+        r#"
+        let reads = 0;
+        let failed = 0;
+        fn on_transaction(t) {
+            reads += 1;
+            if !t.ok { failed += 1; }
+            if t.addr == 31 && t.value > 100 {
+                print(`${t.resp_time} ${t.param} ${t.value} ${t.latency_ms}`);
+            }
+        }
+        fn on_end() { print(`${reads} ${failed}`); }
+        "#,
+    )?;
+    script.transaction(&read(31, 401, Ok(120), 12))?;
+    script.transaction(&read(31, 401, Ok(80), 12))?;
+    script.transaction(&read(5, 1, Err(X328Error::CommandFailed), 3))?;
+    script.end()?;
+    assert_eq!(
+        *lines.borrow(),
+        ["2023-11-14T22:13:20.012000Z 401 120 12.0", "3 1"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_errors() -> Result<()> {
+    assert!(load("fn other(t) {}").is_err());
+    assert!(load("fn on_transaction(t) {").is_err());
+    let (mut script, _) = load("fn on_transaction(t) { t.value / 0 }")?;
+    assert!(script.transaction(&read(5, 1, Ok(7), 3)).is_err());
+    // without on_end
+    script.end()
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::sdlog::{convert_sd_log, SD_LOG_MAGIC};
+use serial_pcap::{
+    CaptureRecord, MarkerKind, SerialPacketReader, SerialPacketWriter, UartTxChannel,
+};
+
+fn record(log: &mut Vec<u8>, micros: u64, data: &[u8]) {
+    log.extend_from_slice(&micros.to_le_bytes());
+    log.push(data.len() as u8);
+    log.extend_from_slice(data);
+}
+
+#[test]
+fn test_convert_sd_log() -> Result<()> {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut log = SD_LOG_MAGIC.to_vec();
+    let cmd: Vec<u8> = b"\x0422110023\x05".iter().map(|b| b | 0x80).collect();
+    record(&mut log, 1_000, &cmd);
+    // a drop byte, followed by the node response
+    record(&mut log, 20_000, b"\x1a\x06");
+    // truncated record at the end, from a power loss
+    log.extend_from_slice(&30_000u64.to_le_bytes());
+
+    let mut pcap = vec![];
+    {
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        assert_eq!(convert_sd_log(log.as_slice(), &mut writer, start)?, 2);
+    }
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    let Some(CaptureRecord::Packet(p)) = reader.next_record()? else {
+        panic!("expected the ctrl packet");
+    };
+    assert_eq!(p.ch, UartTxChannel::Ctrl);
+    assert_eq!(p.data.as_ref(), b"\x0422110023\x05");
+    assert_eq!(SystemTime::from(p.time), start + Duration::from_millis(1));
+    let Some(CaptureRecord::Marker(m)) = reader.next_record()? else {
+        panic!("expected a drop marker");
+    };
+    assert_eq!(
+        (m.kind, m.ch),
+        (MarkerKind::Drop, Some(UartTxChannel::Node))
+    );
+    let Some(CaptureRecord::Packet(p)) = reader.next_record()? else {
+        panic!("expected the node packet");
+    };
+    assert_eq!(p.data.as_ref(), b"\x06");
+    assert!(reader.next_record()?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_not_a_log() -> Result<()> {
+    let mut writer = SerialPacketWriter::new(vec![])?;
+    assert!(convert_sd_log(&b"not a log file"[..], &mut writer, SystemTime::UNIX_EPOCH).is_err());
+    Ok(())
+}
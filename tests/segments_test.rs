@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use serial_pcap::generate::{generate, GenerateOptions};
+use serial_pcap::ring::{RingLimits, SegmentRing};
+use serial_pcap::segments::SegmentReader;
+use serial_pcap::{CaptureRecord, Marker, MarkerKind};
+
+/// An empty directory for the segments of one test
+fn test_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("serial-pcap-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// A ring capture of three segments with 10 transactions each
+fn write_ring(dir: &std::path::Path) -> Result<DateTime<Utc>> {
+    let limits = RingLimits {
+        max_size: Some(1 << 30),
+        max_age: None,
+    };
+    let mut ring = SegmentRing::new(dir.join("bus.pcap"), limits)?;
+    let mut start: DateTime<Utc> = "2024-05-02T10:15:00Z".parse()?;
+    ring.add_header_marker(Marker {
+        kind: MarkerKind::Clock,
+        ch: None,
+        label: "anchor".into(),
+        time: start,
+    });
+    for _ in 0..3 {
+        let mut writer = ring.open_segment()?;
+        let opts = GenerateOptions {
+            transactions: 10,
+            start,
+            ..Default::default()
+        };
+        start = generate(&opts, &mut writer)? + chrono::Duration::seconds(1);
+        writer.flush()?;
+    }
+    Ok(start)
+}
+
+#[test]
+fn test_read_ring_segments() -> Result<()> {
+    let dir = test_dir("segments")?;
+    write_ring(&dir)?;
+    let pattern = dir.join("bus.*.pcap");
+    let mut reader = SegmentReader::new([pattern.to_str().unwrap()])?;
+    let mut markers = 0;
+    let mut packets = 0;
+    let mut last = None;
+    while let Some(record) = reader.next_record()? {
+        match &record {
+            CaptureRecord::Marker(m) => {
+                assert_eq!(m.label, "anchor");
+                markers += 1;
+            }
+            _ => packets += 1,
+        }
+        assert!(last <= Some(record.time()));
+        last = Some(record.time());
+    }
+    // the header marker is repeated in each segment, but only returned once
+    assert_eq!(markers, 1);
+    assert!(packets >= 60);
+    let boundaries = reader.boundaries();
+    assert_eq!(boundaries.len(), 2);
+    assert!(boundaries[0].prev.ends_with("bus.00001.pcap"));
+    assert!(boundaries[0].next.ends_with("bus.00002.pcap"));
+    assert!(boundaries[0].gap() >= chrono::Duration::seconds(1));
+    assert_eq!(reader.sequence_gaps(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_segments_out_of_order() -> Result<()> {
+    let dir = test_dir("segments-order")?;
+    write_ring(&dir)?;
+    let files = ["bus.00002.pcap", "bus.00001.pcap"].map(|f| dir.join(f));
+    let files = files.iter().map(|f| f.to_str().unwrap());
+    let mut reader = SegmentReader::new(files)?;
+    let err = loop {
+        match reader.next_record() {
+            Ok(Some(_)) => {}
+            Ok(None) => panic!("the segments were accepted"),
+            Err(e) => break e,
+        }
+    };
+    assert!(err.to_string().contains("out of order"), "{err}");
+
+    assert!(SegmentReader::new([dir.join("none.*.pcap").to_str().unwrap()]).is_err());
+    assert!(SegmentReader::new(["-", "bus.pcap"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_replay_segments() -> Result<()> {
+    let dir = test_dir("segments-replay")?;
+    write_ring(&dir)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_replay_x328"))
+        .arg(dir.join("bus.*.pcap"))
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert_eq!(stdout.matches("Continued in").count(), 2, "{stdout}");
+    assert!(stdout.contains("summary transactions=30 "), "{stdout}");
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use etherparse::PacketBuilder;
+use rpcap::read::PcapReader;
+use rpcap::write::{PcapWriter, WriteOptions};
+use rpcap::CapturedPacket;
+
+use serial_pcap::sequence::SequenceGap;
+use serial_pcap::{Marker, MarkerKind, SerialPacketReader, SerialPacketWriter, UartTxChannel};
+
+fn t0() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+/// The sequence numbers of the records in a capture
+fn sequences(pcap: &[u8]) -> Result<Vec<Option<u16>>> {
+    let mut reader = SerialPacketReader::new(pcap)?;
+    let mut seqs = vec![];
+    while reader.next_record()?.is_some() {
+        seqs.push(reader.sequence());
+    }
+    Ok(seqs)
+}
+
+#[test]
+fn test_sequence_per_channel() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, t0())?;
+    writer.write_marker(&Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "start".into(),
+        time: t0().into(),
+    })?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, t0())?;
+    writer.write_bus_packet_time(1, b"\x04", UartTxChannel::Ctrl, t0())?;
+    writer.write_packet_time(b"\x0400110024\x05", UartTxChannel::Ctrl, t0())?;
+    // the split packets are numbered too
+    writer.write_packet_time(&[b'x'; 300], UartTxChannel::Node, t0())?;
+    drop(writer);
+    assert_eq!(sequences(&pcap)?, [1, 1, 1, 1, 2, 2, 3].map(Some).to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn test_sequence_wraps() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    for _ in 0..=u16::MAX {
+        writer.write_packet_time(b"\x06", UartTxChannel::Node, t0())?;
+    }
+    drop(writer);
+    let seqs = sequences(&pcap)?;
+    // 0 is skipped
+    assert_eq!(seqs[..2], [Some(1), Some(2)]);
+    assert_eq!(seqs[seqs.len() - 2..], [Some(u16::MAX), Some(1)]);
+    Ok(())
+}
+
+#[test]
+fn test_no_sequence() -> Result<()> {
+    // packets from older versions have identification 0
+    let mut data = vec![];
+    PacketBuilder::ipv4([127, 0, 0, 1], [127, 0, 0, 2], 254)
+        .udp(422, 1422)
+        .write(&mut data, b"\x04")?;
+    let mut pcap = vec![];
+    let options = WriteOptions {
+        snaplen: 200,
+        linktype: 228,
+        high_res_timestamps: false,
+        non_native_byte_order: false,
+    };
+    let mut writer = PcapWriter::new(&mut pcap, options)?;
+    writer.write(&CapturedPacket {
+        time: t0(),
+        data: &data,
+        orig_len: data.len(),
+    })?;
+    writer.flush()?;
+    assert_eq!(sequences(&pcap)?, [None]);
+    Ok(())
+}
+
+/// The records of `pcap` in the `order` of their indices, e.g. with some left out
+fn reorder(pcap: &[u8], order: &[usize]) -> Result<Vec<u8>> {
+    let (options, mut reader) = PcapReader::new(pcap)?;
+    let mut records = vec![];
+    while let Some(pkt) = reader.next()? {
+        records.push((pkt.time, pkt.data.to_vec()));
+    }
+    let mut out = vec![];
+    let mut writer = PcapWriter::new(&mut out, options)?;
+    for &i in order {
+        let (time, data) = &records[i];
+        writer.write(&CapturedPacket {
+            time: *time,
+            data,
+            orig_len: data.len(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(out)
+}
+
+#[test]
+fn test_sequence_gaps() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    for i in 0..6 {
+        let t = t0() + Duration::from_millis(i);
+        writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, t)?;
+        writer.write_packet_time(b"\x06", UartTxChannel::Node, t)?;
+    }
+    drop(writer);
+    let complete = |pcap: &[u8]| -> Result<_> {
+        let mut reader = SerialPacketReader::new(pcap)?;
+        while reader.next_record()?.is_some() {}
+        Ok((reader.sequence_gaps(), reader.take_sequence_gaps()))
+    };
+    assert_eq!(complete(&pcap)?, (0, vec![]));
+
+    // ctrl 2 and 3 are lost, node 5 is after node 6
+    let pcap = reorder(&pcap, &[0, 1, 3, 5, 6, 7, 8, 11, 10, 9])?;
+    let (count, gaps) = complete(&pcap)?;
+    assert_eq!(count, 3);
+    assert_eq!(
+        gaps,
+        [
+            SequenceGap {
+                bus: 0,
+                ch: Some(UartTxChannel::Ctrl),
+                expected: 2,
+                found: 4,
+                time: (t0() + Duration::from_millis(3)).into(),
+            },
+            SequenceGap {
+                bus: 0,
+                ch: Some(UartTxChannel::Node),
+                expected: 5,
+                found: 6,
+                time: (t0() + Duration::from_millis(5)).into(),
+            },
+            SequenceGap {
+                bus: 0,
+                ch: Some(UartTxChannel::Node),
+                expected: 7,
+                found: 5,
+                time: (t0() + Duration::from_millis(4)).into(),
+            },
+        ]
+    );
+    assert_eq!(gaps[0].to_string(), "2 packets missing on ctrl");
+    assert_eq!(gaps[1].to_string(), "1 packet missing on node");
+    assert_eq!(
+        gaps[2].to_string(),
+        "packet 5 out of order on node, expected 7"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_appended_capture() -> Result<()> {
+    // a new writer starts from 1 again, which isn't a gap
+    let mut file = std::io::Cursor::new(vec![]);
+    drop(SerialPacketWriter::new(&mut file)?);
+    for _ in 0..2 {
+        let mut writer = SerialPacketWriter::append(&mut file)?;
+        writer.write_packet_time(b"\x06", UartTxChannel::Node, t0())?;
+        writer.write_packet_time(b"\x06", UartTxChannel::Node, t0())?;
+    }
+    let mut reader = SerialPacketReader::new(file.get_ref().as_slice())?;
+    while reader.next_record()?.is_some() {}
+    assert_eq!(reader.sequence_gaps(), 0);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+#![cfg(target_os = "linux")]
+
+use std::io::{BufRead, BufReader};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+
+/// A pseudo terminal, the capture opens the returned path. The master side is kept open, so
+/// the port doesn't hang up.
+fn pty() -> Result<(OwnedFd, String)> {
+    let (mut master, mut slave) = (0, 0);
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(res, 0);
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    let path = std::fs::read_link(format!("/proc/self/fd/{}", slave.as_raw_fd()))?;
+    drop(slave);
+    Ok((
+        unsafe { OwnedFd::from_raw_fd(master) },
+        path.to_string_lossy().into(),
+    ))
+}
+
+/// Start a capture with `args`, stop it with SIGINT and return the exit status
+fn interrupt_capture(args: &[&str]) -> Result<ExitStatus> {
+    let pcap = std::env::temp_dir().join(format!(
+        "serial_pcap_shutdown_{}_{}.pcap",
+        std::process::id(),
+        args.len()
+    ));
+    let mut capture = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .args(args)
+        .arg(&pcap)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut lines = BufReader::new(capture.stdout.take().unwrap()).lines();
+    assert!(lines.any(|l| l.is_ok_and(|l| l.contains("Stream recorder running"))));
+    // let the capture install its signal handlers
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        unsafe { libc::kill(capture.id() as libc::pid_t, libc::SIGINT) },
+        0
+    );
+    // keep reading, so the log output doesn't block the capture
+    std::thread::spawn(move || lines.for_each(drop));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = capture.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            capture.kill()?;
+            bail!("The capture didn't stop after SIGINT.");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    std::fs::remove_file(&pcap)?;
+    Ok(status)
+}
+
+#[test]
+fn test_muxed_stream_shutdown() -> Result<()> {
+    let (_master, ctrl) = pty()?;
+    assert!(interrupt_capture(&["--ctrl", &ctrl, "--muxed-stream"])?.success());
+    Ok(())
+}
+
+#[test]
+fn test_ctrl_node_shutdown() -> Result<()> {
+    let (_ctrl_master, ctrl) = pty()?;
+    let (_node_master, node) = pty()?;
+    assert!(interrupt_capture(&["--ctrl", &ctrl, "--node", &node])?.success());
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use chrono::DateTime;
+use x328_proto::{addr, param, value};
+
+use serial_pcap::diff::Outcome;
+use serial_pcap::harness::Replay;
+use serial_pcap::sim::{Scenario, SimNode};
+use serial_pcap::x328::{read_command, read_response, write_command, ACK, EOT, NAK};
+use serial_pcap::SerialPacketWriter;
+
+#[test]
+fn test_node_value_table() {
+    let mut node = SimNode::new(21)
+        .with_values([(23, 33), (24, -5)])
+        .with_failing(24);
+    let read = |p| read_command(addr(21), param(p));
+    assert_eq!(
+        node.receive(&read(23)),
+        Some(read_response(param(23), value(33)))
+    );
+    assert_eq!(node.receive(&read(24)), Some(vec![NAK]));
+    assert_eq!(node.receive(&read(25)), Some(vec![EOT]));
+    // another node's command
+    assert_eq!(node.receive(&read_command(addr(22), param(23))), None);
+
+    let write = |p, v| write_command(addr(21), param(p), value(v));
+    assert_eq!(node.receive(&write(23, 442)), Some(vec![ACK]));
+    assert_eq!(node.value(23), Some(442));
+    assert_eq!(node.receive(&write(25, 1)), Some(vec![NAK]));
+    assert_eq!(node.value(25), None);
+}
+
+#[test]
+fn test_node_errors() {
+    let mut node = SimNode::new(21)
+        .with_value(23, 33)
+        .with_no_reply_every(3)
+        .with_corrupt_every(2);
+    let cmd = read_command(addr(21), param(23));
+    let ok = read_response(param(23), value(33));
+    let replies: Vec<_> = (0..4).map(|_| node.receive(&cmd)).collect();
+    assert_eq!(replies[0].as_ref(), Some(&ok));
+    assert_ne!(replies[1].as_ref(), Some(&ok));
+    assert_eq!(replies[2], None);
+    assert_ne!(replies[3].as_ref(), Some(&ok));
+}
+
+#[test]
+fn test_scenario_pcap() -> Result<()> {
+    let scenario = Scenario::new()
+        .with_node(SimNode::new(21).with_value(23, 33))
+        .with_node(SimNode::new(31).with_value(223, 0))
+        .with_read(21, 23)
+        // no node, the timeout of the last command isn't in the capture
+        .with_read(45, 1)
+        .with_write(31, 223, 442)
+        .with_read(31, 223)
+        .with_cycles(2);
+    let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let pcap = |mut scenario: Scenario| -> Result<_> {
+        let mut pcap = vec![];
+        let mut writer = SerialPacketWriter::new(&mut pcap)?;
+        scenario.write_pcap(&mut writer, start)?;
+        drop(writer);
+        Ok((pcap, scenario))
+    };
+    let (data, done) = pcap(scenario.clone())?;
+    assert_eq!(data, pcap(scenario)?.0);
+    assert_eq!(done.node(31).unwrap().value(223), Some(442));
+    assert!(done.clone().next_exchange().is_none());
+
+    let exchanges = Replay::new(data.as_slice())?.exchanges();
+    let outcomes: Vec<_> = exchanges.iter().map(|x| x.outcome).collect();
+    let cycle = [
+        Outcome::Ok(33),
+        Outcome::Timeout,
+        Outcome::Ok(442),
+        Outcome::Ok(442),
+    ];
+    assert_eq!(outcomes, [cycle, cycle].concat());
+    assert_eq!(exchanges[0].time, start);
+    Ok(())
+}
+
+#[test]
+fn test_endless_scenario() -> Result<()> {
+    let mut scenario = Scenario::new()
+        .with_node(SimNode::new(21).with_value(23, 33))
+        .with_read(21, 23)
+        .with_cycles(0);
+    assert_eq!(
+        std::iter::from_fn(|| scenario.next_exchange())
+            .take(50)
+            .count(),
+        50
+    );
+    let mut writer = SerialPacketWriter::new(vec![])?;
+    assert!(scenario
+        .write_pcap(&mut writer, Default::default())
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, SerialPacketReader, SerialPacketWriter, UartTxChannel,
+};
+
+fn start() -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap()
+}
+
+/// A packet every 10 ms, with a marker at 25 ms
+fn capture() -> Result<Vec<u8>> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    for n in 0..10u8 {
+        let time = start() + Duration::milliseconds(10 * n as i64);
+        writer.write_packet_time(&[b'0' + n], UartTxChannel::Ctrl, time.into())?;
+        if n == 2 {
+            writer.write_marker(&Marker {
+                kind: MarkerKind::User,
+                ch: None,
+                label: "fault".into(),
+                time: time + Duration::milliseconds(5),
+            })?;
+        }
+    }
+    drop(writer);
+    Ok(pcap)
+}
+
+fn packet_data(reader: &mut SerialPacketReader<&[u8]>) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    while let Some(pkt) = reader.next_packet()? {
+        data.extend_from_slice(&pkt.data);
+    }
+    Ok(data)
+}
+
+#[test]
+fn test_skip_to() -> Result<()> {
+    let pcap = capture()?;
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    reader.skip_to(start() + Duration::milliseconds(25))?;
+    // the record at the time is kept
+    match reader.next_record()? {
+        Some(CaptureRecord::Marker(m)) => assert_eq!(m.label, "fault"),
+        r => panic!("expected the marker, got {r:?}"),
+    }
+    assert_eq!(packet_data(&mut reader)?, b"3456789");
+
+    // skipping between the packets, and to a time which was already passed
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    reader.skip_to(start() + Duration::milliseconds(61))?;
+    reader.skip_to(start())?;
+    assert_eq!(packet_data(&mut reader)?, b"789");
+
+    let mut reader = SerialPacketReader::new(pcap.as_slice())?;
+    reader.skip_to(start() + Duration::seconds(1))?;
+    assert!(reader.next_record()?.is_none());
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use serial_pcap::capture::DropStats;
+use serial_pcap::clock::CaptureClock;
+use serial_pcap::status::{json_string, serve_http, CaptureStatus, ChannelCount};
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+fn packet(bus: u8, ch: UartTxChannel, data: &[u8], time: DateTime<Utc>) -> SerialPacket {
+    SerialPacket {
+        bus,
+        ch,
+        data: data.into(),
+        time,
+    }
+}
+
+#[test]
+fn test_status_line() {
+    let t = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut status = CaptureStatus::default();
+    assert_eq!(
+        status.line(Duration::from_secs(5), None),
+        "Capturing for 0:00:05, no data yet"
+    );
+    status.count(&packet(1, UartTxChannel::Node, b"\x06", t));
+    status.count(&packet(0, UartTxChannel::Node, b"\x04", t));
+    status.count(&packet(0, UartTxChannel::Ctrl, b"\x0422110023\x05", t));
+    let last = t + chrono::Duration::seconds(1);
+    status.count(&packet(0, UartTxChannel::Ctrl, b"\x0422110023\x05", last));
+    assert_eq!(
+        status.channels[&(0, UartTxChannel::Ctrl)],
+        ChannelCount {
+            bytes: 20,
+            packets: 2
+        }
+    );
+    assert_eq!(status.last_packet, Some(last));
+    assert_eq!(
+        status.line(Duration::from_secs(3 * 3600 + 62), Some(1234)),
+        "Capturing for 3:01:02, ctrl 20 bytes in 2 packets, node 1 bytes in 1 packets, \
+         bus 1 node 1 bytes in 1 packets, file 1234 bytes"
+    );
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(
+        json_string("ctrl (/dev/ttyUSB0)"),
+        r#""ctrl (/dev/ttyUSB0)""#
+    );
+    assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+}
+
+#[test]
+fn test_http_status() -> Result<()> {
+    let t = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let mut status = CaptureStatus::new(vec!["ctrl (/dev/ttyUSB0 9600,7E1)".into()]);
+    status.count(&packet(0, UartTxChannel::Ctrl, b"\x0422110023\x05", t));
+    let drops = Arc::new(DropStats::new(CaptureClock::new()));
+    drops.record(UartTxChannel::Node, 12, "queue overflow");
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let status = Arc::new(Mutex::new(status));
+    std::thread::spawn(move || serve_http(listener, status, drops, || Some(1234)));
+    let get = |path: &str| -> Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+
+    let response = get("/status")?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    assert!(body.starts_with(r#"{"uptime_s":"#), "{body}");
+    assert!(
+        body.ends_with(concat!(
+            r#""ports":["ctrl (/dev/ttyUSB0 9600,7E1)"],"#,
+            r#""channels":[{"bus":0,"channel":"ctrl","bytes":10,"packets":1}],"#,
+            r#""last_packet":"2023-11-14T22:13:20.000000Z","#,
+            r#""drops":{"ctrl":{"events":0,"bytes":0},"node":{"events":1,"bytes":12}},"#,
+            r#""file_size":1234}"#,
+            "\n"
+        )),
+        "{body}"
+    );
+    assert!(get("/")?.starts_with("HTTP/1.1 404 "));
+    Ok(())
+}
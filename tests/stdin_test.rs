@@ -0,0 +1,33 @@
+use std::process::{Command, Stdio};
+
+use anyhow::Result;
+
+use serial_pcap::harness::Replay;
+
+const FIXTURE: &str = "tests/fixtures/pty_bus.pcap";
+
+#[test]
+fn test_replay_from_stdin() -> Result<()> {
+    let output = Command::new(env!("CARGO_BIN_EXE_replay_x328"))
+        .arg("-")
+        .stdin(Stdio::from(std::fs::File::open(FIXTURE)?))
+        .output()?;
+    // the fixture has a corrupt response
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let transactions = stdout
+        .lines()
+        .filter(|l| l.contains(" transaction{") && !l.contains("Timeout"))
+        .count();
+    let replay = Replay::from_file(FIXTURE)?;
+    assert_eq!(transactions, replay.exchanges().len() - 3, "{stdout}");
+    Ok(())
+}
+
+#[test]
+fn test_missing_file() {
+    let err = serial_pcap::CaptureInput::open("no/such/capture.pcap")
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), "Failed to open no/such/capture.pcap.");
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+
+use serial_pcap::decode::BusEvent;
+use serial_pcap::step::{decode_steps, Breakpoint, Stepper};
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+/// Read parameter 23 from node 21
+const READ_21: &[u8] = b"\x0422110023\x05";
+/// Read parameter 401 from node 31
+const READ_31: &[u8] = b"\x0433110401\x05";
+/// Node response to a read of an invalid parameter
+const INVALID_PARAM: &[u8] = b"\x04";
+
+fn steps(packets: &[(UartTxChannel, &[u8], i64)]) -> Result<Stepper> {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    let packets = packets.iter().map(|&(ch, data, ms)| {
+        Ok(SerialPacket {
+            bus: 0,
+            ch,
+            data: data.into(),
+            time: start + Duration::milliseconds(ms),
+        })
+    });
+    Ok(Stepper::new(decode_steps(packets)?))
+}
+
+#[test]
+fn test_breakpoint_parse() -> Result<()> {
+    let bp: Breakpoint = "31:401".parse()?;
+    assert_eq!(
+        bp,
+        Breakpoint {
+            addr: 31,
+            param: Some(401)
+        }
+    );
+    assert_eq!(bp.to_string(), "31:401");
+    assert_eq!("31".parse::<Breakpoint>()?.param, None);
+    assert!("x:1".parse::<Breakpoint>().is_err());
+    assert!("31:".parse::<Breakpoint>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_stepping() -> Result<()> {
+    use UartTxChannel::*;
+    let mut stepper = steps(&[
+        (Ctrl, READ_21, 0),
+        (Node, INVALID_PARAM, 15),
+        (Ctrl, READ_31, 100),
+        (Node, INVALID_PARAM, 115),
+        (Ctrl, READ_21, 200),
+        // the next command times out the previous one
+        (Ctrl, READ_31, 400),
+        (Node, INVALID_PARAM, 415),
+    ])?;
+    let steps = stepper.steps();
+    assert_eq!(steps.len(), 4, "{:?}", steps);
+    // the raw bytes of the transaction are the command and the response
+    assert_eq!(steps[0].packets.len(), 2);
+    assert_eq!(&steps[0].packets[0].data[..], READ_21);
+    assert!(matches!(steps[2].event, BusEvent::Timeout { .. }));
+
+    assert_eq!(stepper.position(), 0);
+    assert!(stepper.forward(1));
+    assert!(stepper.next_error());
+    assert_eq!(stepper.position(), 2);
+    // the invalid parameter reads are errors too
+    assert!(stepper.prev_error());
+    assert_eq!(stepper.position(), 1);
+    assert!(stepper.prev_error());
+    assert_eq!(stepper.position(), 0);
+    assert!(!stepper.prev_error());
+
+    assert!(!stepper.continue_to_breakpoint());
+    stepper.toggle_breakpoint("31:401".parse()?);
+    assert!(stepper.continue_to_breakpoint());
+    assert_eq!(stepper.position(), 1);
+    assert!(stepper.at_breakpoint());
+    assert!(stepper.continue_to_breakpoint());
+    assert_eq!(stepper.position(), 3);
+    assert!(!stepper.continue_to_breakpoint());
+    assert!(!stepper.forward(1));
+
+    // a node breakpoint stops at the timeout of its command
+    assert!(!stepper.toggle_breakpoint("31:401".parse()?));
+    stepper.toggle_breakpoint("21".parse()?);
+    stepper.go_to(0);
+    assert!(stepper.continue_to_breakpoint());
+    assert_eq!(stepper.position(), 2);
+    Ok(())
+}
@@ -0,0 +1,51 @@
+#![cfg(unix)]
+
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use bytes::BytesMut;
+
+use serial_pcap::capture::DropStats;
+use serial_pcap::systemd::run_status;
+use serial_pcap::{SerialPacket, UartTxChannel};
+
+fn recv(socket: &UnixDatagram) -> Result<String> {
+    let mut buf = [0u8; 256];
+    let len = socket.recv(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+#[test]
+fn test_status_notifications() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("serial_pcap_notify_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    std::env::set_var("NOTIFY_SOCKET", &path);
+
+    let (monitor, packets) = std::sync::mpsc::channel();
+    let drops = Arc::new(DropStats::default());
+    let status = std::thread::spawn(move || run_status(packets, drops, Duration::from_secs(60)));
+    let packet = |ch, data: &[u8]| SerialPacket {
+        bus: 0,
+        ch,
+        data: BytesMut::from(data),
+        time: SystemTime::now().into(),
+    };
+    monitor.send(packet(UartTxChannel::Ctrl, b"\x0400110023\x05"))?;
+    assert_eq!(
+        recv(&socket)?,
+        "READY=1\nSTATUS=ctrl 10 bytes, node 0 bytes, 0 losses"
+    );
+    monitor.send(packet(UartTxChannel::Node, b"\x06"))?;
+    drop(monitor);
+    status.join().unwrap();
+    assert_eq!(
+        recv(&socket)?,
+        "STOPPING=1\nSTATUS=ctrl 10 bytes, node 1 bytes, 0 losses"
+    );
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use serial_pcap::tee::{write_copy, Tee};
+use serial_pcap::{
+    CaptureRecord, Marker, MarkerKind, SerialPacket, SerialPacketReader, SerialPacketWriter,
+    Trigger, UartTxChannel,
+};
+
+fn packet(ch: UartTxChannel, data: &[u8], secs: u64) -> SerialPacket {
+    SerialPacket {
+        bus: 0,
+        ch,
+        data: data.into(),
+        time: (SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).into(),
+    }
+}
+
+fn user_marker(label: &str, secs: u64) -> Marker {
+    Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: label.into(),
+        time: (SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).into(),
+    }
+}
+
+#[test]
+fn test_fan_out() {
+    let mut tee = Tee::new();
+    let packets = tee.packets();
+    let records = tee.records();
+    let stopped = tee.records();
+    drop(stopped);
+    assert_eq!(tee.len(), 3);
+
+    let marker = user_marker("fault", 1);
+    let trigger = Trigger::new(0, Trigger::DEVICE, marker.time);
+    tee.send_packet(packet(UartTxChannel::Ctrl, b"\x0400110023\x05", 1));
+    tee.send_marker(&marker);
+    tee.send_trigger(&trigger);
+    tee.send_packet(packet(UartTxChannel::Node, b"\x06", 2));
+    // the sink which went away is removed, the others keep getting the data
+    assert_eq!(tee.len(), 2);
+    drop(tee);
+
+    let packets: Vec<_> = packets.iter().map(|p| p.data).collect();
+    assert_eq!(packets, [&b"\x0400110023\x05"[..], b"\x06"]);
+    let records: Vec<_> = records.iter().collect();
+    assert_eq!(records.len(), 4);
+    assert!(matches!(&records[1], CaptureRecord::Marker(m) if *m == marker));
+    assert!(matches!(&records[2], CaptureRecord::Trigger(t) if *t == trigger));
+}
+
+#[test]
+fn test_write_copy() -> Result<()> {
+    let mut tee = Tee::new();
+    let records = tee.records();
+    let mut file = vec![];
+    let copy = SerialPacketWriter::new(&mut file)?;
+    let marker = user_marker("copied", 3);
+    let writer = std::thread::scope(|s| {
+        let writer = s.spawn(|| write_copy(copy, records, Duration::from_millis(10)));
+        tee.send_packet(packet(UartTxChannel::Ctrl, b"\x0400110023\x05", 1));
+        tee.send_packet(packet(UartTxChannel::Node, b"\x06", 2));
+        tee.send_marker(&marker);
+        drop(tee);
+        writer.join().unwrap()
+    });
+    writer?;
+
+    let mut reader = SerialPacketReader::new(file.as_slice())?;
+    let mut copied = vec![];
+    while let Some(record) = reader.next_record()? {
+        copied.push(record);
+    }
+    assert_eq!(copied.len(), 3);
+    assert!(
+        matches!(&copied[1], CaptureRecord::Packet(p) if p.data[..] == b"\x06"[..] && p.time.timestamp() == 2)
+    );
+    assert!(matches!(&copied[2], CaptureRecord::Marker(m) if *m == marker));
+    Ok(())
+}
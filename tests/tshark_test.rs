@@ -0,0 +1,65 @@
+use std::process::Command;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use serial_pcap::tshark::{command, feed, spawn};
+use serial_pcap::{SerialPacket, SerialPacketReader, UartTxChannel};
+
+#[test]
+fn test_command() {
+    let cmd = command(&["-Y".into(), "x328".into()]);
+    assert_eq!(cmd.get_program(), "tshark");
+    let args: Vec<_> = cmd.get_args().collect();
+    assert_eq!(args, ["-l", "-n", "-r", "-", "-Y", "x328"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_feed() -> Result<()> {
+    let out = std::env::temp_dir().join(format!("serial_pcap_tshark_{}.pcap", std::process::id()));
+    // a stand-in for tshark, which stores the stream
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(format!("cat > '{}'", out.display()));
+    let child = spawn(cmd)?;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let time = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    for (ch, data) in [
+        (UartTxChannel::Ctrl, "\x0422110023\x05"),
+        (UartTxChannel::Node, "\x04"),
+    ] {
+        tx.send(SerialPacket {
+            bus: 0,
+            ch,
+            data: data.as_bytes().into(),
+            time,
+        })?;
+    }
+    drop(tx);
+    feed(child, rx)?;
+
+    let packets: Vec<_> = SerialPacketReader::from_file(&out)?.collect::<Result<_>>()?;
+    std::fs::remove_file(&out)?;
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[1].ch, UartTxChannel::Node);
+    assert_eq!(packets[1].time, time);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_child_fails() -> Result<()> {
+    let child = spawn(Command::new("false"))?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    drop(tx);
+    let err = feed(child, rx).unwrap_err();
+    assert!(err.to_string().starts_with("tshark exited with"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn test_missing_program() {
+    let err = spawn(Command::new("no-such-tshark")).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to start \"no-such-tshark\".");
+}
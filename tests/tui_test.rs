@@ -0,0 +1,73 @@
+use chrono::{DateTime, Duration, Utc};
+use x328_proto::{addr, param, value};
+
+use serial_pcap::names::NameMap;
+use serial_pcap::tui::{BusView, ErrorCounters};
+use serial_pcap::x328::{read_command, read_response, write_command, NAK};
+use serial_pcap::{CaptureRecord, SerialPacket, Trigger, UartTxChannel};
+
+fn packet(ch: UartTxChannel, data: &[u8], ms: i64) -> CaptureRecord {
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    CaptureRecord::Packet(SerialPacket {
+        bus: 0,
+        ch,
+        data: data.into(),
+        time: start + Duration::milliseconds(ms),
+    })
+}
+
+#[test]
+fn test_bus_view() -> anyhow::Result<()> {
+    use UartTxChannel::*;
+    let names = NameMap::from_toml(
+        "[[param]]\naddress = 31\nparam = 401\nname = \"stow pressure\"\nunit = \"bar\"\n",
+    )?;
+    let mut view = BusView::with_names(names);
+    let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+    for record in [
+        packet(Ctrl, &read_command(addr(31), param(401)), 0),
+        packet(Node, &read_response(param(401), value(120)), 15),
+        packet(Ctrl, &write_command(addr(21), param(23), value(5)), 100),
+        packet(Node, &[NAK], 115),
+        // unanswered
+        packet(Ctrl, &read_command(addr(21), param(23)), 200),
+        packet(Ctrl, &read_command(addr(21), param(23)), 400),
+        CaptureRecord::Trigger(Trigger::new(0, Trigger::DEVICE, start)),
+    ] {
+        view.feed(&record);
+    }
+    assert_eq!(
+        view.errors(),
+        &ErrorCounters {
+            timeouts: 1,
+            nak: 1,
+            triggers: 1,
+            ..Default::default()
+        }
+    );
+    let lines: Vec<_> = view.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            (
+                "22:13:20.015 Read  stow pressure => 120 bar (15.0 ms)",
+                false
+            ),
+            (
+                "22:13:20.115 Write 21/23 = 5 failed: Command failed, NAK received.",
+                true
+            ),
+            ("22:13:20.400 Timeout reading 21/23", true),
+            ("22:13:20.000 Trigger event", false),
+        ]
+    );
+
+    view.read_error(anyhow::anyhow!("Truncated pcap record."));
+    let (last, err) = view.lines().last().unwrap();
+    assert!(
+        last.ends_with("Read error: Truncated pcap record."),
+        "{last}"
+    );
+    assert!(err);
+    Ok(())
+}
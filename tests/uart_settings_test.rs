@@ -0,0 +1,26 @@
+use anyhow::Result;
+use tokio_serial::{DataBits, Parity, StopBits};
+
+use serial_pcap::UartSettings;
+
+#[test]
+fn test_parse_uart_settings() -> Result<()> {
+    let settings: UartSettings = "19200,8N2".parse()?;
+    assert_eq!(
+        settings,
+        UartSettings {
+            baud: 19200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::Two,
+        }
+    );
+    assert_eq!(settings.to_string(), "19200 8N2");
+    // the format defaults to the X3.28 7E1
+    assert_eq!("9600".parse::<UartSettings>()?, UartSettings::default());
+    assert_eq!("4800,7o1".parse::<UartSettings>()?.parity, Parity::Odd);
+    for bad in ["fast", "9600,9N1", "9600,8X1", "9600,8N3", "9600,8N"] {
+        assert!(bad.parse::<UartSettings>().is_err(), "{bad}");
+    }
+    Ok(())
+}
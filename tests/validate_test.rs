@@ -0,0 +1,95 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use etherparse::PacketBuilder;
+use rpcap::write::{PcapWriter, WriteOptions};
+use rpcap::CapturedPacket;
+
+use serial_pcap::validate::{validate, ProblemKind};
+use serial_pcap::{SerialPacketWriter, UartTxChannel};
+
+fn udp(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+    PacketBuilder::ipv4(src, dst, 254)
+        .udp(ports.0, ports.1)
+        .write(&mut buf, payload)
+        .unwrap();
+    buf
+}
+
+fn write_raw(linktype: u32, packets: &[(SystemTime, Vec<u8>, usize)]) -> Vec<u8> {
+    let mut pcap = vec![];
+    let options = WriteOptions {
+        snaplen: 200,
+        linktype,
+        high_res_timestamps: false,
+        non_native_byte_order: false,
+    };
+    let mut writer = PcapWriter::new(&mut pcap, options).unwrap();
+    for (time, data, orig_len) in packets {
+        writer
+            .write(&CapturedPacket {
+                time: *time,
+                data,
+                orig_len: *orig_len,
+            })
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    pcap
+}
+
+#[test]
+fn test_valid_capture() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    let now = SystemTime::now();
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, now)?;
+    writer.write_bus_packet_time(1, b"\x06", UartTxChannel::Node, now)?;
+    writer.write_marker(&serial_pcap::Marker {
+        kind: serial_pcap::MarkerKind::User,
+        ch: None,
+        label: "start".into(),
+        time: now.into(),
+    })?;
+    drop(writer);
+    let report = validate(pcap.as_slice())?;
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(report.packets, 3);
+    Ok(())
+}
+
+#[test]
+fn test_problems() -> Result<()> {
+    let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    let ctrl = udp([127, 0, 0, 1], [127, 0, 0, 2], (422, 1422), b"\x04");
+    let bad_port = udp([127, 0, 0, 1], [127, 0, 0, 2], (422, 53), b"\x04");
+    let bad_addr = udp([127, 0, 0, 2], [127, 0, 0, 1], (422, 1422), b"\x04");
+    let short = ctrl[..ctrl.len() - 1].to_vec();
+    let packets = [
+        (t, ctrl.clone(), ctrl.len()),
+        (t - Duration::from_millis(1), ctrl.clone(), ctrl.len()),
+        (t, bad_port.clone(), bad_port.len()),
+        (t, bad_addr.clone(), bad_addr.len()),
+        (t, short.clone(), ctrl.len()),
+        (t, short.clone(), short.len()),
+        (t, vec![0x45, 0, 0], 3),
+    ];
+    let report = validate(write_raw(228, &packets).as_slice())?;
+    assert_eq!(report.packets, 7);
+    assert_eq!(report.count(ProblemKind::Timestamp), 1);
+    assert_eq!(report.count(ProblemKind::Port), 1);
+    assert_eq!(report.count(ProblemKind::Channel), 1);
+    assert_eq!(report.count(ProblemKind::Truncated), 3);
+    assert_eq!(report.problems.len(), 6, "{report}");
+    assert_eq!(report.problems[0].packet, Some(2));
+
+    let report = validate(write_raw(1, &packets[..1]).as_slice())?;
+    assert_eq!(report.count(ProblemKind::Linktype), 1);
+
+    // a record cut off at the end of the file
+    let pcap = write_raw(228, &packets[..1]);
+    let report = validate(&pcap[..pcap.len() - 2])?;
+    assert_eq!(report.count(ProblemKind::Truncated), 1);
+    Ok(())
+}
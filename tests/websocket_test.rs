@@ -0,0 +1,205 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use x328_proto::{addr, param, value};
+
+use serial_pcap::names::NameMap;
+use serial_pcap::websocket::{accept_key, marker_json, serve, text_frame};
+use serial_pcap::x328::{read_command, read_response};
+use serial_pcap::{CaptureRecord, Marker, MarkerKind, SerialPacket, Trigger, UartTxChannel};
+
+fn time(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap() + chrono::Duration::milliseconds(ms)
+}
+
+/// Read an unmasked text frame sent by the server, None at the end of the stream
+fn read_frame(stream: &mut impl Read) -> Result<Option<String>> {
+    let mut head = [0; 2];
+    if stream.read(&mut head[..1])? == 0 {
+        return Ok(None);
+    }
+    stream.read_exact(&mut head[1..])?;
+    assert_eq!(head[0], 0x81);
+    let len = match head[1] {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut text = vec![0; len];
+    stream.read_exact(&mut text)?;
+    Ok(Some(String::from_utf8(text)?))
+}
+
+#[test]
+fn test_accept_key() {
+    // the example in RFC 6455
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_text_frame() {
+    assert_eq!(text_frame("hi"), b"\x81\x02hi");
+    let frame = text_frame(&"x".repeat(300));
+    assert_eq!(frame[..4], [0x81, 126, 0x01, 0x2c]);
+    assert_eq!(frame.len(), 304);
+    let frame = text_frame(&"x".repeat(70_000));
+    assert_eq!(frame[..2], [0x81, 127]);
+    assert_eq!(frame[2..10], 70_000u64.to_be_bytes());
+}
+
+#[test]
+fn test_marker_json() {
+    let marker = Marker::line_break(0, UartTxChannel::Node, time(0));
+    assert_eq!(
+        marker_json(&marker),
+        r#"{"type":"marker","kind":"break","channel":"node","label":"line break","time":"2023-11-14T22:13:20.000000Z"}"#
+    );
+}
+
+#[test]
+fn test_stream() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let server_addr = listener.local_addr()?;
+    let (record_tx, records) = channel();
+    let names = NameMap::from_csv("address,param,name,unit\n21,23,Speed,rpm\n")?;
+    let server = std::thread::spawn(move || serve(listener, records, names));
+
+    let mut stream = TcpStream::connect(server_addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(
+        b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+          Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    )?;
+    let mut reader = BufReader::new(stream);
+    let mut response = vec![];
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        response.push(line.trim_end().to_string());
+        line.clear();
+    }
+    assert_eq!(response[0], "HTTP/1.1 101 Switching Protocols");
+    assert!(response.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".into()));
+    // the client is added after the handshake
+    std::thread::sleep(Duration::from_millis(200));
+
+    let packet = |ch, data: Vec<u8>, ms| {
+        CaptureRecord::Packet(SerialPacket {
+            bus: 0,
+            ch,
+            data: data.as_slice().into(),
+            time: time(ms),
+        })
+    };
+    record_tx.send(packet(
+        UartTxChannel::Ctrl,
+        read_command(addr(21), param(23)),
+        0,
+    ))?;
+    record_tx.send(packet(
+        UartTxChannel::Node,
+        read_response(param(23), value(1500)),
+        12,
+    ))?;
+    record_tx.send(CaptureRecord::Trigger(Trigger::new(
+        0,
+        "button A",
+        time(20),
+    )))?;
+    record_tx.send(CaptureRecord::Marker(Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "start".into(),
+        time: time(30),
+    }))?;
+    drop(record_tx);
+    server.join().unwrap()?;
+
+    let mut messages = vec![];
+    while let Some(text) = read_frame(&mut reader)? {
+        messages.push(text);
+    }
+    assert_eq!(
+        messages,
+        [
+            r#"{"type":"transaction","bus":0,"time":"2023-11-14T22:13:20.000000Z","addr":21,"param":23,"write":null,"outcome":"ok","value":1500,"text":"Read  Speed => 1500 rpm"}"#,
+            r#"{"type":"event","bus":0,"time":"2023-11-14T22:13:20.020000Z","event":"trigger"}"#,
+            r#"{"type":"marker","kind":"user","channel":null,"label":"start","time":"2023-11-14T22:13:20.030000Z"}"#,
+        ]
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_capture_with_names() -> Result<()> {
+    use std::process::{Command, Stdio};
+
+    let dir = std::env::temp_dir();
+    let names = dir.join(format!("serial_pcap_ws_names_{}.csv", std::process::id()));
+    let pcap = dir.join(format!("serial_pcap_ws_{}.pcap", std::process::id()));
+    std::fs::write(&names, "address,param,name,unit\n21,23,Speed,rpm\n")?;
+    // the names are used without the terminal UI
+    let mut capture = Command::new(env!("CARGO_BIN_EXE_serial-pcap"))
+        .args(["--pty", "--websocket", "127.0.0.1:0", "--names"])
+        .arg(&names)
+        .arg(&pcap)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut lines = BufReader::new(capture.stdout.take().unwrap()).lines();
+    let (mut ctrl, mut node, mut server) = (None, None, None);
+    while ctrl.is_none() || node.is_none() || server.is_none() {
+        let line = lines.next().unwrap()?;
+        if let Some(path) = line.strip_prefix("ctrl: ") {
+            ctrl = Some(path.to_owned());
+        } else if let Some(path) = line.strip_prefix("node: ") {
+            node = Some(path.to_owned());
+        } else if let Some((_, addr)) = line.split_once("ws://") {
+            server = Some(addr.trim_end_matches("/.").to_owned());
+        }
+    }
+    std::thread::spawn(move || lines.for_each(drop));
+
+    let mut stream = TcpStream::connect(server.unwrap())?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(
+        b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+          Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    )?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    // the client is added after the handshake
+    std::thread::sleep(Duration::from_millis(200));
+
+    let open = |path: Option<String>| std::fs::OpenOptions::new().write(true).open(path.unwrap());
+    open(ctrl)?.write_all(&read_command(addr(21), param(23)))?;
+    std::thread::sleep(Duration::from_millis(50));
+    open(node)?.write_all(&read_response(param(23), value(1500)))?;
+    let message = read_frame(&mut reader)?.unwrap();
+
+    assert_eq!(
+        unsafe { libc::kill(capture.id() as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+    assert!(capture.wait()?.success());
+    std::fs::remove_file(&names)?;
+    std::fs::remove_file(&pcap)?;
+    assert!(
+        message.contains(r#""text":"Read  Speed => 1500 rpm""#),
+        "{message}"
+    );
+    Ok(())
+}
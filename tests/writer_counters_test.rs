@@ -0,0 +1,45 @@
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use serial_pcap::{Marker, MarkerKind, SerialPacketWriter, UartTxChannel};
+
+/// Write a few packets and a marker, returns the counters before the writer is dropped
+fn write_some<W: std::io::Write>(writer: &mut SerialPacketWriter<W>) -> Result<(u64, u64)> {
+    let t = SystemTime::now();
+    writer.write_packet_time(b"\x0400110023\x05", UartTxChannel::Ctrl, t)?;
+    writer.write_packet_time(b"\x06", UartTxChannel::Node, t)?;
+    writer.write_marker(&Marker {
+        kind: MarkerKind::User,
+        ch: None,
+        label: "fault".into(),
+        time: t.into(),
+    })?;
+    writer.write_bus_packet_time(1, &[b'x'; 300], UartTxChannel::Ctrl, t)?;
+    Ok((writer.packets_written(), writer.bytes_written()))
+}
+
+#[test]
+fn test_pcap_counters_match_file() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new(&mut pcap)?;
+    assert_eq!(writer.packets_written(), 0);
+    assert_eq!(writer.bytes_written(), 24);
+    let (packets, bytes) = write_some(&mut writer)?;
+    drop(writer);
+    // the long packet is split in two
+    assert_eq!(packets, 5);
+    assert_eq!(bytes, pcap.len() as u64);
+    Ok(())
+}
+
+#[test]
+fn test_pcapng_counters_match_file() -> Result<()> {
+    let mut pcap = vec![];
+    let mut writer = SerialPacketWriter::new_pcapng(&mut pcap)?;
+    let (packets, bytes) = write_some(&mut writer)?;
+    drop(writer);
+    assert_eq!(packets, 5);
+    assert_eq!(bytes, pcap.len() as u64);
+    Ok(())
+}
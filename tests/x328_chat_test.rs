@@ -1,74 +1,18 @@
-use std::io::{Read, Write};
+use std::io::Read;
 
 use anyhow::Result;
-use x328_proto::{addr, node, param, value, Master, NodeState};
 
+use serial_pcap::sim::{Scenario, SimNode};
 use serial_pcap::{SerialPacketReader, SerialPacketWriter, UartTxChannel};
 
-pub struct Chat {
-    master: Master,
-    nodes: Vec<Node>,
-    read: bool,
-}
-
-fn new_node(address: usize) -> Node {
-    Node::new(address)
-}
-
-impl Chat {
-    pub fn new() -> Self {
-        Chat {
-            master: Master::new(),
-            nodes: vec![new_node(21), new_node(31)],
-            read: true,
-        }
-    }
-
-    pub fn next<T: Write>(&mut self, mut master_tx: T, mut client_tx: T) -> Result<()> {
-        if self.read {
-            let send = self.master.read_parameter(addr(21), param(23));
-            master_tx.write_all(send.get_data())?;
-            for node in &mut self.nodes {
-                node.next(send.get_data(), &mut client_tx)
-            }
-        } else {
-            let send = self
-                .master
-                .write_parameter(addr(31), param(223), value(442));
-            master_tx.write_all(send.get_data())?;
-            for node in &mut self.nodes {
-                node.next(send.get_data(), &mut client_tx)
-            }
-        }
-        self.read = !self.read;
-        Ok(())
-    }
-}
-
-struct Node(Option<node::ReceiveData>);
-
-impl Node {
-    fn new(address: usize) -> Self {
-        Self(node::ReceiveData::new(address).ok())
-    }
-
-    fn next(&mut self, recv: &[u8], mut send: impl Write) {
-        let mut state = self.0.take().unwrap().receive_data(recv);
-        loop {
-            state = match state {
-                NodeState::ReceiveData(r) => {
-                    self.0 = r.into();
-                    return;
-                }
-                NodeState::SendData(mut s) => {
-                    send.write_all(s.get_data()).expect("Write failed");
-                    s.data_sent()
-                }
-                NodeState::ReadParameter(read) => read.send_reply_ok(value(33)),
-                NodeState::WriteParameter(write) => write.write_ok(),
-            };
-        }
-    }
+/// Node 21 is read and node 31 written, alternately
+fn chat() -> Scenario {
+    Scenario::new()
+        .with_node(SimNode::new(21).with_value(23, 33))
+        .with_node(SimNode::new(31).with_value(223, 0))
+        .with_read(21, 23)
+        .with_write(31, 223, 442)
+        .with_cycles(0)
 }
 
 #[test]
@@ -83,27 +27,15 @@ fn test_chatter() -> Result<()> {
 
 fn test_chatter_write(writer: impl std::io::Write) -> Result<()> {
     let mut pcap = SerialPacketWriter::new(writer)?;
-    let mut chat = Chat::new();
-
-    let mut buf_a = Vec::new();
-    let mut buf_b = Vec::new();
-
-    let mut cnt = 0;
+    let mut chat = chat();
 
-    while chat.next(&mut buf_a, &mut buf_b).is_ok() {
-        cnt += 1;
-        if !buf_a.is_empty() {
-            pcap.write_packet(buf_a.as_slice(), UartTxChannel::Ctrl)?;
-        }
-        if !buf_b.is_empty() {
-            pcap.write_packet(buf_b.as_slice(), UartTxChannel::Node)?;
-        }
-        buf_a.clear();
-        buf_b.clear();
-        if cnt > 10 {
-            break;
+    for exchange in std::iter::from_fn(|| chat.next_exchange()).take(11) {
+        pcap.write_packet(&exchange.cmd, UartTxChannel::Ctrl)?;
+        if let Some(reply) = &exchange.reply {
+            pcap.write_packet(reply, UartTxChannel::Node)?;
         }
     }
+    assert_eq!(chat.node(31).and_then(|n| n.value(223)), Some(442));
     Ok(())
 }
 
@@ -111,9 +43,9 @@ fn test_chatter_read(reader: impl std::io::Read) -> Result<()> {
     let mut pcap = SerialPacketReader::new(reader)?;
     let mut buf = vec![];
     pcap.reader(UartTxChannel::Ctrl).read_to_end(&mut buf)?;
-    assert!(buf.len() > 0);
+    assert!(!buf.is_empty());
     buf.clear();
     pcap.reader(UartTxChannel::Node).read_to_end(&mut buf)?;
-    assert!(buf.len() > 0);
+    assert!(!buf.is_empty());
     Ok(())
 }
@@ -0,0 +1,100 @@
+use x328_proto::master::SendData;
+use x328_proto::node::{Node, NodeState};
+use x328_proto::{addr, param, value, Master};
+
+use serial_pcap::decode::BusCommand;
+use serial_pcap::x328::{
+    bcc, parse_command, parse_read_response, read_command, read_response, write_command, FrameError,
+};
+
+/// The read response of an x328-proto node
+fn node_read_response(cmd: &[u8], v: i32) -> Vec<u8> {
+    let mut node = Node::new(addr(21));
+    let token = node.reset();
+    let NodeState::ReceiveData(recv) = node.state(token) else {
+        unreachable!()
+    };
+    let token = recv.receive_data(cmd);
+    let NodeState::ReadParameter(read) = node.state(token) else {
+        panic!("not a read command")
+    };
+    let token = read.send_reply_ok(value(v));
+    let NodeState::SendData(send) = node.state(token) else {
+        unreachable!()
+    };
+    send.send_data().to_vec()
+}
+
+#[test]
+fn test_same_as_x328_proto() {
+    let mut master = Master::new();
+    for p in [0, 23, 9999] {
+        let cmd = read_command(addr(21), param(p));
+        assert_eq!(cmd, master.read_parameter(addr(21), param(p)).get_data());
+        for v in [0, 33, -1, -9999, -99_999, 99_999, 999_999] {
+            assert_eq!(
+                write_command(addr(21), param(p), value(v)),
+                master
+                    .write_parameter(addr(21), param(p), value(v))
+                    .get_data(),
+                "{p} {v}"
+            );
+            assert_eq!(
+                read_response(param(p), value(v)),
+                node_read_response(&cmd, v),
+                "{p} {v}"
+            );
+        }
+    }
+    assert_eq!(read_command(addr(0), param(1)), b"\x0400000001\x05");
+}
+
+#[test]
+fn test_parse_frames() {
+    let cmd = write_command(addr(31), param(223), value(-442));
+    assert_eq!(
+        parse_command(&cmd),
+        Ok(BusCommand::Write {
+            addr: addr(31),
+            param: param(223),
+            value: value(-442)
+        })
+    );
+    assert_eq!(
+        parse_command(b"\x0422110023\x05"),
+        Ok(BusCommand::Read {
+            addr: addr(21),
+            param: param(23)
+        })
+    );
+    let response = read_response(param(23), value(33));
+    assert_eq!(parse_read_response(&response), Ok((param(23), value(33))));
+
+    assert_eq!(parse_command(b"\x0421110023\x05"), Err(FrameError::Address));
+    assert_eq!(parse_command(b"\x042211002\x05"), Err(FrameError::Length));
+    assert_eq!(
+        parse_command(&cmd[..cmd.len() - 1]),
+        Err(FrameError::Length)
+    );
+    assert_eq!(parse_read_response(b"\x0623"), Err(FrameError::Length));
+
+    let mut corrupt = response.clone();
+    *corrupt.last_mut().unwrap() ^= 0x01;
+    assert_eq!(
+        parse_read_response(&corrupt),
+        Err(FrameError::Bcc {
+            expected: *response.last().unwrap(),
+            found: *corrupt.last().unwrap()
+        })
+    );
+}
+
+#[test]
+fn test_bcc() {
+    // the BCC of a small XOR is moved out of the control characters
+    assert_eq!(bcc(b"\x03"), 0x23);
+    assert_eq!(
+        bcc(b"0023+33\x03"),
+        b"0023+33\x03".iter().fold(0, |a, b| a ^ b)
+    );
+}
@@ -0,0 +1,264 @@
+-- X3.28 field bus dissector, generated by `serial-pcap dissector` with the node and
+-- parameter names from a name mapping file. Load it with -Xlua_script:FILE, or copy it to
+-- the Wireshark plugin directory.
+
+local x328 = Proto("x328", "X3.28 field bus")
+
+local f = x328.fields
+f.command = ProtoField.string("x328.command", "Command")
+f.address = ProtoField.uint8("x328.address", "Address")
+f.node = ProtoField.string("x328.node", "Node")
+f.parameter = ProtoField.uint16("x328.parameter", "Parameter")
+f.name = ProtoField.string("x328.name", "Parameter name")
+f.unit = ProtoField.string("x328.unit", "Unit")
+f.description = ProtoField.string("x328.description", "Description")
+f.value = ProtoField.int32("x328.value", "Value")
+f.bcc = ProtoField.uint8("x328.bcc", "BCC", base.HEX)
+f.bcc_status = ProtoField.string("x328.bcc.status", "BCC status")
+f.response = ProtoField.string("x328.response", "Response")
+
+local ef_bcc = ProtoExpert.new("x328.bcc.bad", "Bad BCC checksum",
+    expert.group.CHECKSUM, expert.severity.ERROR)
+local ef_malformed = ProtoExpert.new("x328.malformed", "Malformed or partial frame",
+    expert.group.MALFORMED, expert.severity.WARN)
+x328.experts = { ef_bcc, ef_malformed }
+
+--@NAMES@
+
+local EOT, STX, ETX, ENQ, ACK, NAK, BS = 4, 2, 3, 5, 6, 21, 8
+
+-- the responses don't repeat the address, so it's taken from the last command on the bus
+local last_addr = {}
+local resp_addr = {}
+
+-- plain Lua, the bit library isn't available in all Wireshark versions
+local function bxor(a, b)
+    local r, m = 0, 1
+    while a > 0 or b > 0 do
+        if a % 2 ~= b % 2 then
+            r = r + m
+        end
+        a, b, m = math.floor(a / 2), math.floor(b / 2), m * 2
+    end
+    return r
+end
+
+local function bus_of(pinfo)
+    return tostring(pinfo.src):match("^127%.0%.(%d+)%.") or "0"
+end
+
+local function digits(tvb, offset, len)
+    if offset + len > tvb:len() then
+        return nil
+    end
+    local s = tvb(offset, len):string()
+    if not s:match("^%d+$") then
+        return nil
+    end
+    return tonumber(s)
+end
+
+local function label(addr, param)
+    local info = addr and params[addr] and params[addr][param]
+    if info then
+        return info.name
+    end
+    if addr and nodes[addr] then
+        return nodes[addr] .. "/" .. param
+    end
+    return (addr or "?") .. "/" .. param
+end
+
+local function value_text(addr, param, value)
+    local info = addr and params[addr] and params[addr][param]
+    if info and info.unit then
+        return value .. " " .. info.unit
+    end
+    return tostring(value)
+end
+
+local function add_address(tree, range, addr)
+    tree:add(f.address, range, addr)
+    if nodes[addr] then
+        tree:add(f.node, range, nodes[addr])
+    end
+end
+
+local function add_param(tree, range, addr, param)
+    tree:add(f.parameter, range, param)
+    local info = addr and params[addr] and params[addr][param]
+    if info then
+        tree:add(f.name, range, info.name)
+        if info.unit then
+            tree:add(f.unit, range, info.unit)
+        end
+        if info.description then
+            tree:add(f.description, range, info.description)
+        end
+    end
+end
+
+-- The STX, parameter, value, ETX and BCC block of a write command or read response.
+-- Returns the offset after it, the parameter and the value, or nil if it is incomplete.
+local function dissect_block(tvb, tree, addr, offset)
+    local param = digits(tvb, offset + 1, 4)
+    if not param then
+        return nil
+    end
+    local etx = nil
+    for i = offset + 6, math.min(offset + 12, tvb:len() - 2) do
+        if tvb(i, 1):uint() == ETX then
+            etx = i
+            break
+        end
+    end
+    if not etx then
+        return nil
+    end
+    local value = tonumber(tvb(offset + 5, etx - offset - 5):string())
+    if not value then
+        return nil
+    end
+    add_param(tree, tvb(offset + 1, 4), addr, param)
+    tree:add(f.value, tvb(offset + 5, etx - offset - 5), value)
+
+    local bcc = 0
+    for i = offset + 1, etx do
+        bcc = bxor(bcc, tvb(i, 1):uint())
+    end
+    if bcc < 0x20 then
+        bcc = bcc + 0x20
+    end
+    local bcc_range = tvb(etx + 1, 1)
+    tree:add(f.bcc, bcc_range)
+    if bcc_range:uint() == bcc then
+        tree:add(f.bcc_status, bcc_range, "Good")
+    else
+        local item = tree:add(f.bcc_status, bcc_range, string.format("Bad, expected 0x%02x", bcc))
+        item:add_proto_expert_info(ef_bcc)
+    end
+    return etx + 2, param, value
+end
+
+-- A command from the bus controller at offset, returns the offset after it and the info text
+local function dissect_ctrl(tvb, pinfo, tree, offset)
+    local b = tvb(offset, 1):uint()
+    if b == ACK then
+        tree:add(f.command, tvb(offset, 1), "Read next")
+        return offset + 1, "Read next"
+    elseif b == NAK then
+        tree:add(f.command, tvb(offset, 1), "Read again")
+        return offset + 1, "Read again"
+    elseif b == BS then
+        tree:add(f.command, tvb(offset, 1), "Read previous")
+        return offset + 1, "Read previous"
+    elseif b ~= EOT or offset + 5 >= tvb:len() then
+        return nil
+    end
+    -- the address digits are sent twice, "2211" is address 21
+    local a = tvb(offset + 1, 4):string()
+    if not a:match("^%d%d%d%d$") or a:sub(1, 1) ~= a:sub(2, 2) or a:sub(3, 3) ~= a:sub(4, 4) then
+        return nil
+    end
+    local addr = tonumber(a:sub(1, 1) .. a:sub(3, 3))
+    if not pinfo.visited then
+        last_addr[bus_of(pinfo)] = addr
+    end
+
+    if tvb(offset + 5, 1):uint() == STX then
+        local cmd = tree:add(f.command, tvb(offset), "Write")
+        add_address(cmd, tvb(offset + 1, 4), addr)
+        local after, param, value = dissect_block(tvb, cmd, addr, offset + 5)
+        if not after then
+            return nil
+        end
+        cmd:set_len(after - offset)
+        return after, "Write " .. label(addr, param) .. " = " .. value_text(addr, param, value)
+    end
+    local param = digits(tvb, offset + 5, 4)
+    if not param or offset + 9 >= tvb:len() or tvb(offset + 9, 1):uint() ~= ENQ then
+        return nil
+    end
+    local cmd = tree:add(f.command, tvb(offset, 10), "Read")
+    add_address(cmd, tvb(offset + 1, 4), addr)
+    add_param(cmd, tvb(offset + 5, 4), addr, param)
+    return offset + 10, "Read " .. label(addr, param)
+end
+
+-- A response from a bus node at offset, returns the offset after it and the info text
+local function dissect_node(tvb, pinfo, tree, offset)
+    if not pinfo.visited then
+        resp_addr[pinfo.number] = last_addr[bus_of(pinfo)]
+    end
+    local addr = resp_addr[pinfo.number]
+    local b = tvb(offset, 1):uint()
+    if b == ACK then
+        tree:add(f.response, tvb(offset, 1), "Write ok")
+        return offset + 1, "Write ok"
+    elseif b == NAK then
+        tree:add(f.response, tvb(offset, 1), "Write failed")
+        return offset + 1, "Write failed"
+    elseif b == EOT then
+        tree:add(f.response, tvb(offset, 1), "Invalid parameter")
+        return offset + 1, "Invalid parameter"
+    elseif b ~= STX then
+        return nil
+    end
+    local resp = tree:add(f.response, tvb(offset), "Read value")
+    if addr then
+        add_address(resp, tvb(offset, 0), addr)
+    end
+    local after, param, value = dissect_block(tvb, resp, addr, offset)
+    if not after then
+        return nil
+    end
+    resp:set_len(after - offset)
+    return after, label(addr, param) .. " => " .. value_text(addr, param, value)
+end
+
+function x328.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "X3.28"
+    local subtree = tree:add(x328, tvb(), "X3.28 field bus")
+    local from_ctrl = pinfo.src_port == 422
+    local infos = {}
+    local offset = 0
+    while offset < tvb:len() do
+        local after, info
+        if from_ctrl then
+            after, info = dissect_ctrl(tvb, pinfo, subtree, offset)
+        else
+            after, info = dissect_node(tvb, pinfo, subtree, offset)
+        end
+        if not after then
+            subtree:add_tvb_expert_info(ef_malformed, tvb(offset))
+            infos[#infos + 1] = "[partial frame]"
+            break
+        end
+        infos[#infos + 1] = info
+        offset = after
+    end
+    if from_ctrl then
+        pinfo.cols.info = table.concat(infos, ", ")
+    else
+        pinfo.cols.info = "Response: " .. table.concat(infos, ", ")
+    end
+end
+
+DissectorTable.get("udp.port"):add(422, x328)
+
+-- The measurement triggers, with the payload "<source>: <label>"
+local trigger = Proto("x328trigger", "Measurement trigger")
+trigger.fields.source = ProtoField.string("x328trigger.source", "Source")
+trigger.fields.label = ProtoField.string("x328trigger.label", "Label")
+
+function trigger.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "Trigger"
+    local text = tvb():string()
+    local source, label = text:match("^(.-): (.*)$")
+    local subtree = tree:add(trigger, tvb())
+    subtree:add(trigger.fields.source, source or "")
+    subtree:add(trigger.fields.label, label or text)
+    pinfo.cols.info = "Trigger from " .. (source or "?") .. ": " .. (label or text)
+end
+
+DissectorTable.get("udp.port"):add(3422, trigger)